@@ -18,19 +18,31 @@
 use std::sync::Arc;
 
 use axum::routing::{delete, get, patch, post};
-use axum::Router;
+use axum::{Extension, Router};
 use common::ApplicationState;
 
 use super::routes::account::{handle_account_login, handle_account_register};
 use super::routes::admin;
 use super::routes::album_access;
+use super::routes::copyright;
 use super::routes::download;
+use super::routes::email;
 use super::routes::customer::{
     get_customer_album_media, get_customer_albums, get_customer_media_file,
     handle_customer_login, handle_customer_register,
 };
 use super::routes::get_user_id_profile::get_user_id_profile;
+use super::routes::mail_template;
+use super::routes::metadata_policy;
+use super::routes::mfa;
+use super::routes::passkey;
+use super::routes::password;
+use super::routes::selection;
+use super::routes::session;
+use super::routes::share;
+use super::routes::signed_media;
 use super::routes::stats;
+use super::routes::watermark;
 
 pub struct AccountsApi {}
 
@@ -48,9 +60,54 @@ impl AccountsApi {
             .route("/auth/customer/albums", get(get_customer_albums))
             .route("/auth/customer/albums/:album_id/media", get(get_customer_album_media))
             .route("/auth/customer/media/:media_id/file", get(get_customer_media_file))
+            .route("/auth/customer/media/:media_id/url", get(signed_media::get_media_signed_url))
+            // Signed media URLs, authorized by the query string instead of a header
+            // 403 Forbidden - Signature is invalid or expired
+            .route("/media/:media_id/signed", get(signed_media::get_signed_media_file))
             // Account authentication (email + password based)
             .route("/auth/account/register", post(handle_account_register))
             .route("/auth/account/login", post(handle_account_login))
+            .route("/auth/account/login/mfa", post(mfa::complete_login))
+            .route("/auth/account/mfa", get(mfa::get_status))
+            .route(
+                "/auth/account/mfa/totp",
+                post(mfa::start_totp_enrollment).delete(mfa::disable_totp),
+            )
+            .route("/auth/account/mfa/totp/confirm", post(mfa::confirm_totp_enrollment))
+            .route("/auth/account/mfa/recovery-codes", post(mfa::regenerate_recovery_codes))
+            .route("/auth/account/login/passkey/options", post(passkey::login_options))
+            .route("/auth/account/login/passkey", post(passkey::login))
+            .route("/auth/account/passkeys", get(passkey::list_passkeys))
+            .route("/auth/account/passkeys/register/options", post(passkey::registration_options))
+            .route("/auth/account/passkeys/register", post(passkey::register_passkey))
+            .route("/auth/account/passkeys/:credential_id", delete(passkey::remove_passkey))
+            // Mailed links verifying the address and resetting a forgotten password
+            // 202 Accepted - Answered alike for unknown addresses
+            .route("/auth/account/email/verify", get(email::verify_email_link).post(email::verify_email))
+            .route("/auth/account/email/verify/resend", post(email::resend_verification))
+            .route("/auth/account/password/forgot", post(password::forgot_password))
+            .route("/auth/account/password/reset", post(password::reset_password))
+            // Signs out all other sessions of the caller
+            .route("/auth/account/password", post(password::change_password))
+            // Wording of the mails to accounts of the caller's studio
+            .route("/auth/account/mail-templates", get(mail_template::list_mail_templates))
+            .route(
+                "/auth/account/mail-templates/:kind",
+                get(mail_template::get_mail_template)
+                    .put(mail_template::put_mail_template)
+                    .delete(mail_template::delete_mail_template),
+            )
+            // Signed in devices of the caller, signing one of them out
+            // 404 Not Found - Unknown session, or one of another user
+            .route("/auth/sessions", get(session::list_sessions))
+            .route("/auth/sessions/:session_id", delete(session::revoke_session))
+            // Default IPTC/XMP credit of the caller's albums
+            .route(
+                "/auth/account/copyright",
+                get(copyright::get_account_copyright)
+                    .put(copyright::put_account_copyright)
+                    .delete(copyright::delete_account_copyright),
+            )
             // User profile management
             // Returns information about a single account by ID
             // 200 OK
@@ -81,6 +138,14 @@ impl AccountsApi {
             .route("/admin/users/detailed", get(admin::list_users_detailed))
             .route("/admin/albums", get(admin::list_albums))
             .route("/admin/customers", get(admin::list_customers).post(admin::create_customer_code))
+            // Lift or restore watermarks of a customer
+            .route("/admin/customers/:customer_id/paid", patch(watermark::set_customer_paid))
+            // Queued, running and failed background jobs
+            .route("/admin/jobs", get(admin::list_jobs))
+            .route("/admin/jobs/:job_id/retry", post(admin::retry_job))
+            // Disk usage of cached album ZIPs, clearing it
+            .route("/admin/zip-cache", get(admin::get_zip_cache).delete(admin::clear_zip_cache))
+            .route("/admin/zip-cache/:album_id", delete(admin::clear_album_zip_cache))
             // Album access management
             .route(
                 "/albums/:album_id/access",
@@ -107,9 +172,54 @@ impl AccountsApi {
                 "/albums/:album_id/download",
                 get(download::download_album_zip),
             )
+            // ZIPs of selections across albums
+            // 403 Forbidden - The caller has no access to some of the media items
+            // 410 Gone - The download has expired
+            .route("/downloads", post(selection::create_download))
+            .route("/downloads/:download_id", get(selection::get_download))
+            // Copyright template overriding the owner's default for licensed shoots
+            .route(
+                "/albums/:album_id/copyright",
+                get(copyright::get_album_copyright)
+                    .put(copyright::put_album_copyright)
+                    .delete(copyright::delete_album_copyright),
+            )
+            // Metadata delivered with originals to customers and share visitors
+            .route(
+                "/albums/:album_id/metadata-policy",
+                get(metadata_policy::get_album_metadata_policy)
+                    .put(metadata_policy::put_album_metadata_policy),
+            )
+            // Watermark settings of proof galleries
+            // 402 Payment Required - Returned for clean downloads of unpaid galleries
+            .route(
+                "/albums/:album_id/watermark",
+                get(watermark::get_album_watermark)
+                    .put(watermark::put_album_watermark)
+                    .delete(watermark::delete_album_watermark),
+            )
+            // Public share links (anonymous visitors)
+            // 401 Unauthorized - The link is password protected and no share token was sent
+            // 404 Not Found - Unknown share token
+            // 410 Gone - The link has expired
+            .route("/share/:token", get(share::get_share))
+            .route("/share/:token/unlock", post(share::unlock_share))
+            .route("/share/:token/media/:media_id/file", get(share::get_share_media_file))
+            .route("/share/:token/download", get(share::download_share_zip))
             .route("/albums/:album_id/stats", get(stats::get_album_stats))
             .route("/albums/stats", get(stats::get_owned_album_stats))
             .with_state(db)
+            // Configured defaults, e.g. the delivery of album ZIPs
+            .layer(Extension(Arc::clone(&state.config)))
+            // Keys that sign and verify access tokens and media URLs
+            .layer(Extension(Arc::clone(&state.keys)))
+            // Domain and origins of passkey ceremonies
+            .layer(Extension(Arc::clone(&state.relying_party)))
+            // Mails to account holders and their links
+            .layer(Extension(Arc::clone(&state.mailer)))
+            .layer(Extension(Arc::clone(&state.account_mail)))
+            // Proxies whose forwarded client address sessions record
+            .layer(Extension(Arc::clone(&state.trusted_proxies)))
             .layer(tower_http::trace::TraceLayer::new_for_http())
     }
 }
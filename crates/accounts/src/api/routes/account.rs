@@ -15,14 +15,23 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use std::net::SocketAddr;
 use std::sync::Arc;
 
-use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use axum::extract::{ConnectInfo, Extension, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::{response::IntoResponse, Json};
 use serde::{Deserialize, Serialize};
 use tracing::{error, info};
 
+use common::auth::account_token::AccountMailSettings;
 use common::auth::auth_manager::AuthManager;
+use common::auth::keys::KeyRing;
+use common::auth::mfa::{AccountLogin, MfaChallenge};
+use common::auth::session::{SessionClient, TrustedProxies};
+use common::config::configuration::Configuration;
 use common::database::ArcDynDatabase;
+use common::mail::Mailer;
 
 #[derive(Debug, Deserialize)]
 pub struct AccountRegisterRequest {
@@ -44,10 +53,28 @@ pub struct AccountLoginResponse {
     pub email: String,
     pub display_name: Option<String>,
     pub jwt_token: Option<String>,
+    pub refresh_token: Option<String>,
+    pub expires_in: Option<u64>,
+}
+
+impl From<common::auth::auth_manager::AccountLoginResponse> for AccountLoginResponse {
+    fn from(response: common::auth::auth_manager::AccountLoginResponse) -> Self {
+        AccountLoginResponse {
+            account_id: response.account_id,
+            email: response.email,
+            display_name: response.display_name,
+            jwt_token: response.jwt_token,
+            refresh_token: response.refresh_token,
+            expires_in: response.expires_in,
+        }
+    }
 }
 
 pub async fn handle_account_register(
     State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    Extension(mailer): Extension<Arc<dyn Mailer>>,
+    Extension(account_mail): Extension<Arc<AccountMailSettings>>,
     Json(request): Json<AccountRegisterRequest>,
 ) -> impl IntoResponse {
     info!("Account registration attempt for email: {}", request.email);
@@ -60,7 +87,7 @@ pub async fn handle_account_register(
             .into_response();
     }
 
-    let auth_manager = AuthManager::new(Arc::clone(&db));
+    let auth_manager = AuthManager::new(Arc::clone(&db), Arc::clone(&keys));
     let display_name = request.display_name.unwrap_or_default();
 
     match auth_manager
@@ -74,12 +101,14 @@ pub async fn handle_account_register(
     {
         Ok(account_id) => {
             info!("Account successfully registered for email: {}", request.email);
+            send_email_verification(&auth_manager, &mailer, &account_mail, &account_id).await;
             (
                 StatusCode::CREATED,
                 Json(serde_json::json!({
                     "account_id": account_id,
                     "email": request.email,
                     "display_name": display_name,
+                    "email_verified": false,
                     "message": "Account registered successfully"
                 })),
             )
@@ -97,33 +126,38 @@ pub async fn handle_account_register(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn handle_account_login(
     State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    Extension(account_mail): Extension<Arc<AccountMailSettings>>,
+    Extension(proxies): Extension<Arc<TrustedProxies>>,
+    Extension(config): Extension<Arc<Configuration>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
     Json(request): Json<AccountLoginRequest>,
 ) -> impl IntoResponse {
     info!("Account login attempt for email: {}", request.email);
 
-    let auth_manager = AuthManager::new(Arc::clone(&db));
+    let auth_manager = AuthManager::new(Arc::clone(&db), Arc::clone(&keys));
+    let client =
+        SessionClient::from_request(&headers, peer.map(|ConnectInfo(addr)| addr), &proxies);
 
     match auth_manager
-        .verify_account_credentials(request.email.clone(), request.password.clone())
+        .verify_account_credentials(
+            &account_mail,
+            config.auth.require_admin_mfa,
+            request.email.clone(),
+            request.password.clone(),
+            &client,
+        )
         .await
     {
-        Ok(response) => {
+        Ok(AccountLogin::Complete(response)) => {
             info!("Account login successful for email: {}", request.email);
-            let _ = auth_manager
-                .update_last_login_account(response.account_id.clone())
-                .await;
-
-            let response_json = AccountLoginResponse {
-                account_id: response.account_id,
-                email: response.email,
-                display_name: response.display_name,
-                jwt_token: response.jwt_token,
-            };
-
-            (StatusCode::OK, Json(response_json)).into_response()
+            login_response(&auth_manager, response).await
         }
+        Ok(AccountLogin::MfaRequired(challenge)) => mfa_required_response(challenge),
         Err(e) => {
             error!("Account login failed: {}", e);
             let msg = e.to_string();
@@ -135,3 +169,52 @@ pub async fn handle_account_login(
         }
     }
 }
+
+/// Mails the new account a link to verify its address. Failures leave the account as it is,
+/// the mail can be sent again from `/auth/account/email/verify/resend`.
+pub(crate) async fn send_email_verification(
+    auth_manager: &AuthManager,
+    mailer: &Arc<dyn Mailer>,
+    account_mail: &AccountMailSettings,
+    account_id: &str,
+) {
+    let result = match auth_manager.db.get_account_by_id(account_id).await {
+        Ok(account) => {
+            auth_manager
+                .send_email_verification(mailer, account_mail, &account)
+                .await
+        }
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        error!("Could not send verification mail to account {}: {}", account_id, e);
+    }
+}
+
+/// Records the login and answers with the tokens of the new session.
+pub(crate) async fn login_response(
+    auth_manager: &AuthManager,
+    response: common::auth::auth_manager::AccountLoginResponse,
+) -> axum::response::Response {
+    let _ = auth_manager
+        .update_last_login_account(response.account_id.clone())
+        .await;
+
+    (StatusCode::OK, Json(AccountLoginResponse::from(response))).into_response()
+}
+
+/// The password was right, but the login continues at `/auth/account/login/mfa` or
+/// `/auth/account/login/passkey`, or with enrolling a second factor.
+fn mfa_required_response(challenge: MfaChallenge) -> axum::response::Response {
+    (
+        StatusCode::FORBIDDEN,
+        Json(serde_json::json!({
+            "error": "mfa_required",
+            "mfa_token": challenge.mfa_token,
+            "enrollment_required": challenge.enrollment_required,
+            "expires_in": challenge.expires_in,
+            "methods": challenge.methods,
+        })),
+    )
+        .into_response()
+}
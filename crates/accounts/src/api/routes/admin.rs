@@ -1,16 +1,24 @@
 use std::sync::Arc;
 
-use axum::{extract::State, http::{HeaderMap, StatusCode}, response::IntoResponse, Json};
+use axum::{extract::{Extension, Path, Query, State}, http::{HeaderMap, StatusCode}, response::IntoResponse, Json};
+use common::auth::account_token::AccountMailSettings;
 use common::auth::auth_manager::AuthManager;
+use common::auth::keys::KeyRing;
+use common::database::job::{JobRecord, JobStatus};
 use common::database::ArcDynDatabase;
+use common::mail::Mailer;
+use common::zip_cache::ZipCacheManager;
+use chrono::Utc;
 use serde::Deserialize;
+use super::account::send_email_verification;
 use super::customer::extract_session;
 
 pub async fn list_users(
     State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
     headers: HeaderMap,
 ) -> impl IntoResponse {
-    let (account_id, role) = match extract_session(&headers) {
+    let (account_id, role) = match extract_session(&keys, &headers) {
         Ok(pair) => pair,
         Err(e) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": e.to_string()}))).into_response(),
     };
@@ -25,9 +33,10 @@ pub async fn list_users(
 
 pub async fn list_albums(
     State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
     headers: HeaderMap,
 ) -> impl IntoResponse {
-    let (account_id, role) = match extract_session(&headers) {
+    let (account_id, role) = match extract_session(&keys, &headers) {
         Ok(pair) => pair,
         Err(e) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": e.to_string()}))).into_response(),
     };
@@ -42,9 +51,10 @@ pub async fn list_albums(
 
 pub async fn list_users_detailed(
     State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
     headers: HeaderMap,
 ) -> impl IntoResponse {
-    let (account_id, role) = match extract_session(&headers) {
+    let (account_id, role) = match extract_session(&keys, &headers) {
         Ok(pair) => pair,
         Err(e) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": e.to_string()}))).into_response(),
     };
@@ -66,29 +76,36 @@ pub struct CreateUserRequest {
 
 pub async fn create_user(
     State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    Extension(mailer): Extension<Arc<dyn Mailer>>,
+    Extension(account_mail): Extension<Arc<AccountMailSettings>>,
     headers: HeaderMap,
     Json(req): Json<CreateUserRequest>,
 ) -> impl IntoResponse {
-    let (account_id, role) = match extract_session(&headers) {
+    let (account_id, role) = match extract_session(&keys, &headers) {
         Ok(pair) => pair,
         Err(e) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": e.to_string()}))).into_response(),
     };
     if role != "account" || !db.is_account_admin(&account_id).await.unwrap_or(false) {
         return (StatusCode::FORBIDDEN, Json(serde_json::json!({"error": "Admin access required"}))).into_response();
     }
-    let auth_manager = AuthManager::new(Arc::clone(&db));
+    let auth_manager = AuthManager::new(Arc::clone(&db), Arc::clone(&keys));
     let display_name = req.display_name.unwrap_or_default();
     match auth_manager.create_account(req.email, req.password, display_name, None).await {
-        Ok(new_account_id) => (StatusCode::CREATED, Json(serde_json::json!({"account_id": new_account_id}))).into_response(),
+        Ok(new_account_id) => {
+            send_email_verification(&auth_manager, &mailer, &account_mail, &new_account_id).await;
+            (StatusCode::CREATED, Json(serde_json::json!({"account_id": new_account_id}))).into_response()
+        }
         Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": e.to_string()}))).into_response(),
     }
 }
 
 pub async fn list_customers(
     State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
     headers: HeaderMap,
 ) -> impl IntoResponse {
-    let (account_id, role) = match extract_session(&headers) {
+    let (account_id, role) = match extract_session(&keys, &headers) {
         Ok(pair) => pair,
         Err(e) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": e.to_string()}))).into_response(),
     };
@@ -108,10 +125,11 @@ pub struct CreateCustomerCodeRequest {
 
 pub async fn create_customer_code(
     State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
     headers: HeaderMap,
     Json(req): Json<CreateCustomerCodeRequest>,
 ) -> impl IntoResponse {
-    let (account_id, role) = match extract_session(&headers) {
+    let (account_id, role) = match extract_session(&keys, &headers) {
         Ok(pair) => pair,
         Err(e) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": e.to_string()}))).into_response(),
     };
@@ -123,3 +141,105 @@ pub async fn create_customer_code(
         Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": e.to_string()}))).into_response(),
     }
 }
+
+#[derive(Debug, Deserialize)]
+pub struct ListJobsQuery {
+    pub status: Option<JobStatus>,
+}
+
+/// A background job with its payload as JSON instead of a string.
+fn job_response(job: JobRecord) -> serde_json::Value {
+    let payload = serde_json::from_str::<serde_json::Value>(&job.payload)
+        .unwrap_or_else(|_| serde_json::Value::String(job.payload.clone()));
+    let mut value = serde_json::to_value(job).unwrap_or_default();
+    value["payload"] = payload;
+    value
+}
+
+pub async fn list_jobs(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    headers: HeaderMap,
+    Query(query): Query<ListJobsQuery>,
+) -> impl IntoResponse {
+    let (account_id, role) = match extract_session(&keys, &headers) {
+        Ok(pair) => pair,
+        Err(e) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": e.to_string()}))).into_response(),
+    };
+    if role != "account" || !db.is_account_admin(&account_id).await.unwrap_or(false) {
+        return (StatusCode::FORBIDDEN, Json(serde_json::json!({"error": "Admin access required"}))).into_response();
+    }
+    match db.list_jobs(query.status).await {
+        Ok(jobs) => (StatusCode::OK, Json(jobs.into_iter().map(job_response).collect::<Vec<_>>())).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": e.to_string()}))).into_response(),
+    }
+}
+
+pub async fn retry_job(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    headers: HeaderMap,
+    Path(job_id): Path<String>,
+) -> impl IntoResponse {
+    let (account_id, role) = match extract_session(&keys, &headers) {
+        Ok(pair) => pair,
+        Err(e) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": e.to_string()}))).into_response(),
+    };
+    if role != "account" || !db.is_account_admin(&account_id).await.unwrap_or(false) {
+        return (StatusCode::FORBIDDEN, Json(serde_json::json!({"error": "Admin access required"}))).into_response();
+    }
+    match db.retry_failed_job(&job_id, Utc::now()).await {
+        Ok(true) => (StatusCode::ACCEPTED, Json(serde_json::json!({"job_id": job_id}))).into_response(),
+        Ok(false) => (StatusCode::NOT_FOUND, Json(serde_json::json!({"error": "No failed job with this id"}))).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": e.to_string()}))).into_response(),
+    }
+}
+
+pub async fn get_zip_cache(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    Extension(zip_cache): Extension<Arc<ZipCacheManager>>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let (account_id, role) = match extract_session(&keys, &headers) {
+        Ok(pair) => pair,
+        Err(e) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": e.to_string()}))).into_response(),
+    };
+    if role != "account" || !db.is_account_admin(&account_id).await.unwrap_or(false) {
+        return (StatusCode::FORBIDDEN, Json(serde_json::json!({"error": "Admin access required"}))).into_response();
+    }
+    (StatusCode::OK, Json(zip_cache.usage().await)).into_response()
+}
+
+pub async fn clear_zip_cache(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    Extension(zip_cache): Extension<Arc<ZipCacheManager>>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let (account_id, role) = match extract_session(&keys, &headers) {
+        Ok(pair) => pair,
+        Err(e) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": e.to_string()}))).into_response(),
+    };
+    if role != "account" || !db.is_account_admin(&account_id).await.unwrap_or(false) {
+        return (StatusCode::FORBIDDEN, Json(serde_json::json!({"error": "Admin access required"}))).into_response();
+    }
+    (StatusCode::OK, Json(zip_cache.clear(None).await)).into_response()
+}
+
+pub async fn clear_album_zip_cache(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    Extension(zip_cache): Extension<Arc<ZipCacheManager>>,
+    headers: HeaderMap,
+    Path(album_id): Path<String>,
+) -> impl IntoResponse {
+    let (account_id, role) = match extract_session(&keys, &headers) {
+        Ok(pair) => pair,
+        Err(e) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": e.to_string()}))).into_response(),
+    };
+    if role != "account" || !db.is_account_admin(&account_id).await.unwrap_or(false) {
+        return (StatusCode::FORBIDDEN, Json(serde_json::json!({"error": "Admin access required"}))).into_response();
+    }
+    (StatusCode::OK, Json(zip_cache.clear(Some(&album_id)).await)).into_response()
+}
@@ -1,9 +1,12 @@
+use std::sync::Arc;
+
 use axum::{
-    extract::{Path, State},
+    extract::{Extension, Path, State},
     http::{HeaderMap, StatusCode},
     response::IntoResponse,
     Json,
 };
+use common::auth::keys::KeyRing;
 use common::{
     auth::permissions::{has_album_permission, AlbumPermission},
     database::ArcDynDatabase,
@@ -25,10 +28,11 @@ pub struct GenerateCodeRequest {
 
 pub async fn list_album_codes(
     State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
     headers: HeaderMap,
     Path(album_id): Path<String>,
 ) -> impl IntoResponse {
-    let (caller_id, role) = match extract_session(&headers) {
+    let (caller_id, role) = match extract_session(&keys, &headers) {
         Ok(p) => p,
         Err(e) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": e.to_string()}))).into_response(),
     };
@@ -46,11 +50,12 @@ pub async fn list_album_codes(
 
 pub async fn add_album_code(
     State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
     headers: HeaderMap,
     Path(album_id): Path<String>,
     Json(req): Json<AddAccessCodeRequest>,
 ) -> impl IntoResponse {
-    let (caller_id, role) = match extract_session(&headers) {
+    let (caller_id, role) = match extract_session(&keys, &headers) {
         Ok(p) => p,
         Err(e) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": e.to_string()}))).into_response(),
     };
@@ -72,10 +77,11 @@ pub async fn add_album_code(
 
 pub async fn remove_album_code(
     State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
     headers: HeaderMap,
     Path((album_id, access_code)): Path<(String, String)>,
 ) -> impl IntoResponse {
-    let (caller_id, role) = match extract_session(&headers) {
+    let (caller_id, role) = match extract_session(&keys, &headers) {
         Ok(p) => p,
         Err(e) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": e.to_string()}))).into_response(),
     };
@@ -103,11 +109,12 @@ pub struct GrantAccessRequest {
 
 pub async fn grant_album_access(
     State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
     headers: HeaderMap,
     Path(album_id): Path<String>,
     Json(req): Json<GrantAccessRequest>,
 ) -> impl IntoResponse {
-    let (caller_id, role) = match extract_session(&headers) {
+    let (caller_id, role) = match extract_session(&keys, &headers) {
         Ok(p) => p,
         Err(e) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": e.to_string()}))).into_response(),
     };
@@ -131,10 +138,11 @@ pub async fn grant_album_access(
 
 pub async fn revoke_album_access(
     State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
     headers: HeaderMap,
     Path((album_id, target_account_id)): Path<(String, String)>,
 ) -> impl IntoResponse {
-    let (caller_id, role) = match extract_session(&headers) {
+    let (caller_id, role) = match extract_session(&keys, &headers) {
         Ok(p) => p,
         Err(e) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": e.to_string()}))).into_response(),
     };
@@ -155,10 +163,11 @@ pub async fn revoke_album_access(
 
 pub async fn list_album_access(
     State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
     headers: HeaderMap,
     Path(album_id): Path<String>,
 ) -> impl IntoResponse {
-    let (caller_id, role) = match extract_session(&headers) {
+    let (caller_id, role) = match extract_session(&keys, &headers) {
         Ok(p) => p,
         Err(e) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": e.to_string()}))).into_response(),
     };
@@ -179,11 +188,12 @@ pub async fn list_album_access(
 
 pub async fn generate_album_code(
     State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
     headers: HeaderMap,
     Path(album_id): Path<String>,
     Json(req): Json<GenerateCodeRequest>,
 ) -> impl IntoResponse {
-    let (caller_id, role) = match extract_session(&headers) {
+    let (caller_id, role) = match extract_session(&keys, &headers) {
         Ok(p) => p,
         Err(e) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": e.to_string()}))).into_response(),
     };
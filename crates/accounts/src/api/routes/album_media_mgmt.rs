@@ -1,27 +1,34 @@
 use axum::{
-    extract::{Multipart, Path, State},
+    extract::{Extension, Multipart, Path, State},
     http::{HeaderMap, StatusCode},
     response::IntoResponse,
     Json,
 };
 use chrono::Utc;
+use common::auth::keys::KeyRing;
 use common::{
     auth::permissions::{has_album_permission, AlbumPermission},
-    database::{reference::Reference, ArcDynDatabase},
+    database::{
+        reference::{content_hash, Reference, ReferenceRole},
+        ArcDynDatabase,
+    },
+    media_type::detect_mime_type,
 };
 use std::fs;
 use std::path::Path as FsPath;
+use std::sync::Arc;
 use uuid::Uuid;
 
 use super::customer::extract_session;
 
 pub async fn upload_album_media(
     State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
     headers: HeaderMap,
     Path(album_id): Path<String>,
     mut multipart: Multipart,
 ) -> impl IntoResponse {
-    let (caller_id, role) = match extract_session(&headers) {
+    let (caller_id, role) = match extract_session(&keys, &headers) {
         Ok(p) => p,
         Err(e) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": e.to_string()}))).into_response(),
     };
@@ -74,6 +81,9 @@ pub async fn upload_album_media(
         description: String::new(),
         last_modified: Utc::now(),
         is_missing: false,
+        mime_type: detect_mime_type(&bytes).map(str::to_string),
+        role: ReferenceRole::Original,
+        content_hash: Some(content_hash(&bytes)),
     };
     if let Err(e) = db.add_reference(&caller_id, &created_id, &reference).await {
         return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": format!("DB reference failed: {}", e)}))).into_response();
@@ -88,10 +98,11 @@ pub async fn upload_album_media(
 
 pub async fn delete_album_media(
     State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
     headers: HeaderMap,
     Path((album_id, media_id)): Path<(String, String)>,
 ) -> impl IntoResponse {
-    let (caller_id, role) = match extract_session(&headers) {
+    let (caller_id, role) = match extract_session(&keys, &headers) {
         Ok(p) => p,
         Err(e) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": e.to_string()}))).into_response(),
     };
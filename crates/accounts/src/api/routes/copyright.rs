@@ -0,0 +1,313 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Copyright templates embedded into JPEGs delivered to customers and share visitors.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Extension, Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use tracing::error;
+
+use common::auth::keys::KeyRing;
+use common::auth::permissions::{has_album_permission, AlbumPermission};
+use common::database::copyright_template::{CopyrightScope, CopyrightTemplate};
+use common::database::ArcDynDatabase;
+use common::metadata::credit::template_for_album;
+
+use super::customer::extract_session;
+use super::metadata_policy::{require_owner, schedule_album_delivery};
+
+/// Trims the fields and rejects templates that would embed nothing or malformed values.
+fn normalize(template: CopyrightTemplate) -> Result<CopyrightTemplate, &'static str> {
+    let clean = |value: Option<String>| {
+        value
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+    };
+    let template = CopyrightTemplate {
+        creator: clean(template.creator),
+        copyright_notice: clean(template.copyright_notice),
+        credit_line: clean(template.credit_line),
+        rights_url: clean(template.rights_url),
+        contact_email: clean(template.contact_email),
+        usage_terms: clean(template.usage_terms),
+    };
+
+    if template.is_empty() {
+        return Err("At least one field is required");
+    }
+    if template
+        .rights_url
+        .as_deref()
+        .is_some_and(|url| !url.starts_with("https://") && !url.starts_with("http://"))
+    {
+        return Err("rights_url must be an http(s) URL");
+    }
+    if template
+        .contact_email
+        .as_deref()
+        .is_some_and(|email| !email.contains('@'))
+    {
+        return Err("contact_email must be an email address");
+    }
+    Ok(template)
+}
+
+/// Returns the caller's account id, or the status and message for non-account sessions.
+pub(crate) fn require_account(
+    keys: &KeyRing,
+    headers: &HeaderMap,
+) -> Result<String, (StatusCode, String)> {
+    match extract_session(keys, headers) {
+        Ok((caller_id, role)) if role == "account" => Ok(caller_id),
+        Ok(_) => Err((StatusCode::FORBIDDEN, "Account token required".to_string())),
+        Err(e) => Err((StatusCode::UNAUTHORIZED, e.to_string())),
+    }
+}
+
+async fn get_template(db: &ArcDynDatabase, scope: CopyrightScope, owner_id: &str) -> Response {
+    match db.get_copyright_template(scope, owner_id).await {
+        Ok(Some(template)) => (StatusCode::OK, Json(template)).into_response(),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error": "No copyright template"})),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
+}
+
+async fn put_template(
+    db: &ArcDynDatabase,
+    scope: CopyrightScope,
+    owner_id: &str,
+    req: CopyrightTemplate,
+) -> Response {
+    let template = match normalize(req) {
+        Ok(template) => template,
+        Err(error) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({"error": error})),
+            )
+                .into_response()
+        }
+    };
+    // Credited copies and ZIPs are keyed by the template, changes need no invalidation.
+    match db.set_copyright_template(scope, owner_id, &template).await {
+        Ok(_) => (StatusCode::OK, Json(template)).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
+}
+
+async fn delete_template(db: &ArcDynDatabase, scope: CopyrightScope, owner_id: &str) -> Response {
+    match db.delete_copyright_template(scope, owner_id).await {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn get_account_copyright(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    match require_account(&keys, &headers) {
+        Ok(account_id) => get_template(&db, CopyrightScope::Account, &account_id).await,
+        Err((status, error)) => (status, Json(serde_json::json!({"error": error}))).into_response(),
+    }
+}
+
+pub async fn put_account_copyright(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    headers: HeaderMap,
+    Json(req): Json<CopyrightTemplate>,
+) -> impl IntoResponse {
+    match require_account(&keys, &headers) {
+        Ok(account_id) => put_template(&db, CopyrightScope::Account, &account_id, req).await,
+        Err((status, error)) => (status, Json(serde_json::json!({"error": error}))).into_response(),
+    }
+}
+
+pub async fn delete_account_copyright(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    match require_account(&keys, &headers) {
+        Ok(account_id) => delete_template(&db, CopyrightScope::Account, &account_id).await,
+        Err((status, error)) => (status, Json(serde_json::json!({"error": error}))).into_response(),
+    }
+}
+
+pub async fn get_album_copyright(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    headers: HeaderMap,
+    Path(album_id): Path<String>,
+) -> impl IntoResponse {
+    if let Some(resp) = require_owner(&db, &keys, &headers, &album_id).await {
+        return resp;
+    }
+    get_template(&db, CopyrightScope::Album, &album_id).await
+}
+
+pub async fn put_album_copyright(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    headers: HeaderMap,
+    Path(album_id): Path<String>,
+    Json(req): Json<CopyrightTemplate>,
+) -> impl IntoResponse {
+    if let Some(resp) = require_owner(&db, &keys, &headers, &album_id).await {
+        return resp;
+    }
+    let resp = put_template(&db, CopyrightScope::Album, &album_id, req).await;
+    if resp.status().is_success() {
+        schedule_album_delivery(&db, &album_id).await;
+    }
+    resp
+}
+
+pub async fn delete_album_copyright(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    headers: HeaderMap,
+    Path(album_id): Path<String>,
+) -> impl IntoResponse {
+    if let Some(resp) = require_owner(&db, &keys, &headers, &album_id).await {
+        return resp;
+    }
+    let resp = delete_template(&db, CopyrightScope::Album, &album_id).await;
+    if resp.status().is_success() {
+        schedule_album_delivery(&db, &album_id).await;
+    }
+    resp
+}
+
+/// The copyright template embedded into files this viewer gets from `album_id`. Album
+/// owners always get their files as uploaded.
+pub(crate) async fn copyright_for_viewer(
+    db: &ArcDynDatabase,
+    album_id: &str,
+    viewer_id: &str,
+    viewer_role: &str,
+) -> Result<Option<CopyrightTemplate>, StatusCode> {
+    let template = match template_for_album(db, album_id).await {
+        Ok(template) => template,
+        Err(e) => {
+            error!(
+                "Failed to load copyright template for album {}: {:?}",
+                album_id, e
+            );
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    if template.is_none() || viewer_role != "account" {
+        return Ok(template);
+    }
+
+    if has_album_permission(db, viewer_id, album_id, AlbumPermission::Owner)
+        .await
+        .unwrap_or(false)
+    {
+        return Ok(None);
+    }
+    Ok(template)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use database::memory::MemoryDatabase;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_normalize_trims_and_validates() {
+        // given
+        let template = CopyrightTemplate {
+            creator: Some("  Jane Doe ".into()),
+            credit_line: Some("   ".into()),
+            ..Default::default()
+        };
+
+        // when
+        let normalized = normalize(template).unwrap();
+
+        // then
+        assert_eq!(normalized.creator.as_deref(), Some("Jane Doe"));
+        assert_eq!(normalized.credit_line, None);
+        assert!(normalize(CopyrightTemplate::default()).is_err());
+        assert!(normalize(CopyrightTemplate {
+            rights_url: Some("javascript:alert(1)".into()),
+            ..Default::default()
+        })
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_copyright_of_media_in_two_albums_should_follow_viewed_album() {
+        // given
+        let db: ArcDynDatabase = Arc::new(MemoryDatabase::new());
+        let first = db.create_album("owner", "Wedding", None).await.unwrap();
+        let second = db.create_album("owner", "Prints", None).await.unwrap();
+        let media_id = db
+            .create_media_item("owner", "DSC_0001", Utc::now())
+            .await
+            .unwrap();
+        for (album_id, creator) in [(&first, "Wedding Studio"), (&second, "Print Studio")] {
+            db.add_media_to_album(album_id, &media_id).await.unwrap();
+            let template = CopyrightTemplate {
+                creator: Some(creator.into()),
+                ..Default::default()
+            };
+            db.set_copyright_template(CopyrightScope::Album, album_id, &template)
+                .await
+                .unwrap();
+        }
+
+        // when
+        let copyright = copyright_for_viewer(&db, &second, "customer", "customer")
+            .await
+            .unwrap();
+
+        // then
+        assert_eq!(
+            copyright.and_then(|template| template.creator).as_deref(),
+            Some("Print Studio")
+        );
+    }
+}
@@ -15,19 +15,39 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use axum::{
-    extract::{Path, State},
+    extract::{ConnectInfo, Extension, Path, Query, State},
     http::{HeaderMap, StatusCode},
     response::IntoResponse,
     Json,
 };
+use chrono::Duration;
 use serde::{Deserialize, Serialize};
-use tracing::{error, info};
-
-use common::auth::auth_manager::AuthManager;
+use tokio::io::AsyncReadExt;
+use tracing::{error, info, warn};
+
+use common::auth::auth_manager::{AccessToken, AuthManager};
+use common::auth::keys::KeyRing;
+use common::auth::permissions::{has_album_permission, AlbumPermission};
+use common::auth::session::{SessionClient, TrustedProxies};
+use common::auth::signed_url::{sign_media_url, DEFAULT_TTL_SECONDS};
+use common::database::reference::{ReferenceFile, ReferenceRole};
 use common::database::ArcDynDatabase;
+use common::metadata::MetadataPolicy;
+use common::media_type::{detect_mime_type, SNIFF_LEN};
+use common::rendition::{get_or_create_rendition, Rendition};
+use common::heif::{self, is_heif};
+use common::jobs::{self, Job};
+use common::video::{extract_poster, is_video};
+
+use super::file_response::{counts_as_download, serve_file, CacheScope};
+use super::copyright::copyright_for_viewer;
+use super::metadata_policy::{apply_policy, policy_for_viewer};
+use super::watermark::{payment_required, resolve_watermarked_rendition, watermark_for_viewer};
 
 #[derive(Debug, Deserialize)]
 pub struct CustomerLoginRequest {
@@ -40,6 +60,8 @@ pub struct CustomerLoginResponse {
     pub customer_id: String,
     pub display_name: Option<String>,
     pub jwt_token: Option<String>,
+    pub refresh_token: Option<String>,
+    pub expires_in: Option<u64>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -50,14 +72,20 @@ pub struct CustomerRegisterRequest {
 
 pub async fn handle_customer_login(
     State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    Extension(proxies): Extension<Arc<TrustedProxies>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
     Json(request): Json<CustomerLoginRequest>,
 ) -> impl IntoResponse {
     info!("Customer login attempt with access_code: {}", request.access_code);
 
-    let auth_manager = AuthManager::new(Arc::clone(&db));
+    let auth_manager = AuthManager::new(Arc::clone(&db), Arc::clone(&keys));
+    let client =
+        SessionClient::from_request(&headers, peer.map(|ConnectInfo(addr)| addr), &proxies);
 
     match auth_manager
-        .verify_access_code(request.access_code.clone())
+        .verify_access_code(request.access_code.clone(), &client)
         .await
     {
         Ok(response) => {
@@ -69,6 +97,8 @@ pub async fn handle_customer_login(
                 customer_id: response.customer_id,
                 display_name: response.display_name,
                 jwt_token: response.jwt_token,
+                refresh_token: response.refresh_token,
+                expires_in: response.expires_in,
             };
 
             (StatusCode::OK, Json(response_json)).into_response()
@@ -87,9 +117,10 @@ pub async fn handle_customer_login(
 
 pub async fn get_customer_albums(
     State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
     headers: HeaderMap,
 ) -> impl IntoResponse {
-    let (id, role) = match extract_session(&headers) {
+    let (id, role) = match extract_session(&keys, &headers) {
         Ok(pair) => pair,
         Err(e) => {
             return (
@@ -121,11 +152,12 @@ pub async fn get_customer_albums(
 
 pub async fn get_customer_album_media(
     State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
     headers: HeaderMap,
     Path(album_id): Path<String>,
 ) -> impl IntoResponse {
-    let (id, role) = match extract_session(&headers) {
-        Ok(pair) => pair,
+    let (id, role, session_id) = match extract_access_token(&keys, &headers) {
+        Ok(token) => (token.sub, token.role, token.session_id),
         Err(e) => {
             return (
                 StatusCode::UNAUTHORIZED,
@@ -135,6 +167,12 @@ pub async fn get_customer_album_media(
         }
     };
 
+    match may_view_album(&db, &id, &role, &album_id).await {
+        Ok(true) => {}
+        Ok(false) => return StatusCode::FORBIDDEN.into_response(),
+        Err(status) => return status.into_response(),
+    }
+
     // For customer role: respect manual item selection; for account role: show all items
     let selected_ids = if role == "customer" {
         db.get_customer_album_items(&id, &album_id)
@@ -150,14 +188,37 @@ pub async fn get_customer_album_media(
                 items.retain(|m| selected_ids.contains(&m.uuid));
             }
 
-            // Record album view (non-blocking — don't fail request on error)
-            let db_clone = db.clone();
-            let album_id_clone = album_id.clone();
-            let id_clone = id.clone();
-            let role_clone = role.clone();
-            tokio::spawn(async move {
-                let _ = db_clone.record_album_view(&album_id_clone, &id_clone, &role_clone).await;
-            });
+            // Record album view (queued — don't fail request on error)
+            let view = Job::RecordAlbumView {
+                album_id: album_id.clone(),
+                viewer_id: id.clone(),
+                viewer_role: role.clone(),
+            };
+            if let Err(e) = jobs::enqueue(&db, view).await {
+                warn!("Could not queue view of album {}: {:?}", album_id, e);
+            }
+
+            // Attach signed URLs so clients can load images via plain `<img src>` tags.
+            let items: Vec<serde_json::Value> = items
+                .into_iter()
+                .map(|item| {
+                    let (url, _) = sign_media_url(
+                        &keys,
+                        &item.uuid,
+                        Rendition::default(),
+                        &id,
+                        &role,
+                        session_id.as_deref(),
+                        Some(&album_id),
+                        Duration::seconds(DEFAULT_TTL_SECONDS),
+                    );
+                    let mut value = serde_json::to_value(&item).unwrap_or_default();
+                    if let Some(obj) = value.as_object_mut() {
+                        obj.insert("signed_url".to_string(), serde_json::Value::String(url));
+                    }
+                    value
+                })
+                .collect();
 
             (StatusCode::OK, Json(items)).into_response()
         }
@@ -172,44 +233,292 @@ pub async fn get_customer_album_media(
     }
 }
 
+#[derive(Debug, Deserialize)]
+pub struct MediaFileQuery {
+    /// Serves a specific file of the media item, e.g. the RAW of a RAW+JPEG pair.
+    pub role: Option<ReferenceRole>,
+    /// The album the media is viewed in, which decides e.g. the embedded copyright.
+    pub album_id: Option<String>,
+}
+
 pub async fn get_customer_media_file(
     State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
     headers: HeaderMap,
     Path(media_id): Path<String>,
+    Query(query): Query<MediaFileQuery>,
 ) -> impl IntoResponse {
-    use axum::http::header;
-
-    let (id, role) = match extract_session(&headers) {
+    let (id, role) = match extract_session(&keys, &headers) {
         Ok(pair) => pair,
         Err(_) => return StatusCode::UNAUTHORIZED.into_response(),
     };
+    let album_id =
+        match viewing_album(&db, &id, &role, &media_id, query.album_id.as_deref()).await {
+            Ok(Some(album_id)) => album_id,
+            Ok(None) => return StatusCode::FORBIDDEN.into_response(),
+            Err(status) => return status.into_response(),
+        };
+
+    let watermark = match watermark_for_viewer(&db, &album_id, &id, &role).await {
+        Ok(watermark) => watermark,
+        Err(status) => return status.into_response(),
+    };
+    let copyright = match copyright_for_viewer(&db, &album_id, &id, &role).await {
+        Ok(copyright) => copyright,
+        Err(status) => return status.into_response(),
+    };
+    let (path, content_type) = match (query.role, watermark) {
+        (Some(_), Some(_)) => return payment_required(),
+        (None, Some(watermark)) => {
+            let (path, content_type) =
+                match resolve_watermarked_rendition(&db, &media_id, Rendition::Original, &watermark).await {
+                    Ok(file) => file,
+                    Err(status) => return status.into_response(),
+                };
+            match apply_policy(&media_id, path, content_type, MetadataPolicy::Keep, copyright.as_ref()).await {
+                Ok(file) => file,
+                Err(resp) => return resp,
+            }
+        }
+        (reference_role, None) => {
+            let resolved = match reference_role {
+                Some(reference_role) => resolve_media_reference(&db, &media_id, reference_role).await,
+                None => resolve_media_rendition(&db, &media_id, Rendition::Original).await,
+            };
+            let (path, content_type) = match resolved {
+                Ok(file) => file,
+                Err(status) => return status.into_response(),
+            };
+            let policy = match policy_for_viewer(&db, &media_id, &id, &role).await {
+                Ok(policy) => policy,
+                Err(status) => return status.into_response(),
+            };
+            match apply_policy(&media_id, path, content_type, policy, copyright.as_ref()).await {
+                Ok(file) => file,
+                Err(resp) => return resp,
+            }
+        }
+    };
 
-    let (filepath, filename) = match db.get_media_file_path(&media_id).await {
-        Ok(Some(info)) => info,
-        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
-        Err(e) => {
-            error!("Failed to get file path for media {}: {}", media_id, e);
-            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    let content_hash = stored_content_hash(&db, &media_id, &path).await;
+    let response = serve_file(
+        &headers,
+        &path,
+        &content_type,
+        CacheScope::Private,
+        content_hash.as_deref(),
+    )
+    .await;
+    if !counts_as_download(&response) {
+        return response;
+    }
+
+    // Record media download (queued — don't fail request on error)
+    let download = Job::RecordMediaDownload {
+        media_id: media_id.clone(),
+        album_id: Some(album_id),
+        downloader_id: id,
+        downloader_role: role,
+    };
+    if let Err(e) = jobs::enqueue(&db, download).await {
+        warn!("Could not queue download of media {}: {:?}", media_id, e);
+    }
+
+    response
+}
+
+/// Whether the caller may open `album_id`: accounts need read permission, customers an
+/// assignment through their access code.
+pub(crate) async fn may_view_album(
+    db: &ArcDynDatabase,
+    caller_id: &str,
+    role: &str,
+    album_id: &str,
+) -> Result<bool, StatusCode> {
+    match role {
+        "account" => Ok(
+            has_album_permission(db, caller_id, album_id, AlbumPermission::Read)
+                .await
+                .unwrap_or(false),
+        ),
+        "customer" => db
+            .get_albums_for_customer(caller_id)
+            .await
+            .map(|albums| albums.iter().any(|a| a.album_id == album_id))
+            .map_err(|e| {
+                error!("Failed to load albums of customer {}: {}", caller_id, e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }),
+        _ => Ok(false),
+    }
+}
+
+/// Whether the caller may see `media_id` as part of `album_id`.
+/// Customers with a manual selection only get the selected items.
+pub(crate) async fn may_view_media_in(
+    db: &ArcDynDatabase,
+    caller_id: &str,
+    role: &str,
+    album_id: &str,
+    media_id: &str,
+) -> Result<bool, StatusCode> {
+    if !may_view_album(db, caller_id, role, album_id).await? {
+        return Ok(false);
+    }
+    if role != "customer" {
+        return Ok(true);
+    }
+
+    let selected = db
+        .get_customer_album_items(caller_id, album_id)
+        .await
+        .unwrap_or_default();
+    Ok(selected.is_empty() || selected.iter().any(|id| id == media_id))
+}
+
+/// The album the caller sees `media_id` in: `requested` if given, otherwise the first of
+/// its albums open to the caller. `None` when the caller may not see it there.
+pub(crate) async fn viewing_album(
+    db: &ArcDynDatabase,
+    caller_id: &str,
+    role: &str,
+    media_id: &str,
+    requested: Option<&str>,
+) -> Result<Option<String>, StatusCode> {
+    let album_ids = db.get_album_ids_for_media(media_id).await.map_err(|e| {
+        error!("Failed to load albums of media {}: {:?}", media_id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    for album_id in album_ids {
+        if requested.is_some_and(|requested| requested != album_id) {
+            continue;
+        }
+        if may_view_media_in(db, caller_id, role, &album_id, media_id).await? {
+            return Ok(Some(album_id));
         }
+    }
+    Ok(None)
+}
+
+/// Picks a stored file of a media item: the one with `role`, or the primary file.
+async fn find_reference_file(
+    db: &ArcDynDatabase,
+    media_id: &str,
+    role: Option<ReferenceRole>,
+) -> Result<ReferenceFile, StatusCode> {
+    let files = db.get_reference_files(media_id).await.map_err(|e| {
+        error!("Failed to get files for media {}: {}", media_id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let file = match role {
+        Some(role) => files.into_iter().find(|f| f.role == role),
+        None => files.into_iter().next(),
     };
 
-    let full_path = format!("{}/{}", filepath, filename);
+    file.ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Locates the stored file with the given role, unmodified.
+pub(crate) async fn resolve_media_reference(
+    db: &ArcDynDatabase,
+    media_id: &str,
+    role: ReferenceRole,
+) -> Result<(PathBuf, String), StatusCode> {
+    let file = find_reference_file(db, media_id, Some(role)).await?;
+    let path = std::path::Path::new(&file.filepath).join(&file.filename);
+    let content_type = original_content_type(file.mime_type, &path, &file.filename).await;
+
+    Ok((path, content_type))
+}
+
+/// Locates the requested rendition of a media item, generating it on first use.
+/// Renditions are always derived from the primary file. Returns the file path together
+/// with its content type.
+pub(crate) async fn resolve_media_rendition(
+    db: &ArcDynDatabase,
+    media_id: &str,
+    rendition: Rendition,
+) -> Result<(PathBuf, String), StatusCode> {
+    let file = find_reference_file(db, media_id, None).await?;
+
+    let source = std::path::Path::new(&file.filepath).join(&file.filename);
+    let original_type = original_content_type(file.mime_type, &source, &file.filename).await;
+    if rendition == Rendition::Original {
+        return Ok((source, original_type));
+    }
+
+    // Videos are scaled down from their poster frame, HEIF images from their decoded image.
+    let image_source = if is_video(&original_type) {
+        let known = db
+            .get_video_details(media_id)
+            .await
+            .ok()
+            .flatten()
+            .and_then(|d| d.duration_ms);
+        extract_poster(&source, media_id, known)
+            .await
+            .ok_or(StatusCode::UNSUPPORTED_MEDIA_TYPE)?
+    } else if is_heif(&original_type) {
+        heif::decode(&source, media_id)
+            .await
+            .ok_or(StatusCode::UNSUPPORTED_MEDIA_TYPE)?
+    } else {
+        source
+    };
 
-    let bytes = match tokio::fs::read(&full_path).await {
-        Ok(b) => b,
-        Err(_) => return StatusCode::NOT_FOUND.into_response(),
+    let path = get_or_create_rendition(&image_source, media_id, rendition)
+        .await
+        .map_err(|e| {
+            error!("Failed to render {} for media {}: {:?}", rendition, media_id, e);
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        })?;
+
+    Ok((path, "image/jpeg".to_string()))
+}
+
+/// Content hash taken at upload when `path` is a stored file of the media item, `None` for
+/// generated files such as renditions.
+pub(crate) async fn stored_content_hash(
+    db: &ArcDynDatabase,
+    media_id: &str,
+    path: &std::path::Path,
+) -> Option<String> {
+    db.get_reference_files(media_id)
+        .await
+        .ok()?
+        .into_iter()
+        .find(|file| std::path::Path::new(&file.filepath).join(&file.filename) == path)
+        .and_then(|file| file.content_hash)
+}
+
+/// Content type of a stored file: the type detected at upload, otherwise sniffed from the
+/// file itself for files uploaded before detection existed.
+async fn original_content_type(
+    stored: Option<String>,
+    path: &std::path::Path,
+    filename: &str,
+) -> String {
+    if let Some(mime_type) = stored {
+        return mime_type;
+    }
+
+    let mut head = vec![0u8; SNIFF_LEN];
+    let sniffed = match tokio::fs::File::open(path).await {
+        Ok(mut file) => {
+            let n = file.read(&mut head).await.unwrap_or(0);
+            detect_mime_type(&head[..n])
+        }
+        Err(_) => None,
     };
 
-    // Record media download (non-blocking — don't fail request on error)
-    let db_clone = db.clone();
-    let media_id_clone = media_id.clone();
-    let id_clone = id.clone();
-    let role_clone = role.clone();
-    tokio::spawn(async move {
-        let _ = db_clone.record_media_download(&media_id_clone, None, &id_clone, &role_clone).await;
-    });
+    sniffed.unwrap_or_else(|| content_type_for(filename)).to_string()
+}
 
-    let content_type = if filename.ends_with(".jpg") || filename.ends_with(".jpeg") {
+/// Guesses the content type of a stored file from its extension.
+fn content_type_for(filename: &str) -> &'static str {
+    let filename = filename.to_ascii_lowercase();
+    if filename.ends_with(".jpg") || filename.ends_with(".jpeg") {
         "image/jpeg"
     } else if filename.ends_with(".png") {
         "image/png"
@@ -217,27 +526,48 @@ pub async fn get_customer_media_file(
         "image/gif"
     } else if filename.ends_with(".webp") {
         "image/webp"
+    } else if filename.ends_with(".heic") {
+        "image/heic"
+    } else if filename.ends_with(".heif") {
+        "image/heif"
+    } else if filename.ends_with(".avif") {
+        "image/avif"
     } else {
         "application/octet-stream"
-    };
-
-    (StatusCode::OK, [(header::CONTENT_TYPE, content_type)], bytes).into_response()
+    }
 }
 
 /// Extracts the session identity from the Authorization header.
-/// Returns `(id, role)` where role is either `"customer"` or `"account"`.
-pub fn extract_session(headers: &HeaderMap) -> Result<(String, String), anyhow::Error> {
+/// Returns `(id, role)` where role is either `"customer"` or `"account"`; share tokens are refused.
+pub fn extract_session(
+    keys: &KeyRing,
+    headers: &HeaderMap,
+) -> Result<(String, String), anyhow::Error> {
+    let token = extract_access_token(keys, headers)?;
+    Ok((token.sub, token.role))
+}
+
+/// Like `extract_session`, but returns all claims of the access token.
+pub(crate) fn extract_access_token(
+    keys: &KeyRing,
+    headers: &HeaderMap,
+) -> Result<AccessToken, anyhow::Error> {
     let token = headers
         .get("Authorization")
         .and_then(|v| v.to_str().ok())
         .and_then(|v| v.strip_prefix("Bearer "))
         .ok_or_else(|| anyhow::anyhow!("Missing or invalid Authorization header"))?;
 
-    AuthManager::validate_jwt_token(token)
+    let token = AuthManager::decode_access_token(keys, token)?;
+    if token.role == "share" {
+        return Err(anyhow::anyhow!("Share tokens are only valid for their share link"));
+    }
+    Ok(token)
 }
 
 pub async fn handle_customer_register(
     State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
     Json(request): Json<CustomerRegisterRequest>,
 ) -> impl IntoResponse {
     info!("Customer registration attempt with access_code: {}", request.access_code);
@@ -252,7 +582,7 @@ pub async fn handle_customer_register(
             .into_response();
     }
 
-    let auth_manager = AuthManager::new(Arc::clone(&db));
+    let auth_manager = AuthManager::new(Arc::clone(&db), Arc::clone(&keys));
 
     match auth_manager
         .create_customer(
@@ -286,3 +616,78 @@ pub async fn handle_customer_register(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use common::auth::keys::JwtKey;
+    use database::memory::MemoryDatabase;
+
+    fn keys() -> Arc<KeyRing> {
+        Arc::new(KeyRing::new(vec![JwtKey::new("test", b"test-secret")], None).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_get_media_of_unassigned_album_should_fail() {
+        // given
+        let db: ArcDynDatabase = Arc::new(MemoryDatabase::new());
+        let assigned = db.create_album("owner", "Wedding", None).await.unwrap();
+        let unassigned = db.create_album("owner", "Private", None).await.unwrap();
+        let media_id = db
+            .create_media_item("owner", "DSC_0001", Utc::now())
+            .await
+            .unwrap();
+        db.add_media_to_album(&unassigned, &media_id).await.unwrap();
+        db.create_customer("customer".into(), "ABC123".into(), "Guest".into())
+            .await
+            .unwrap();
+        db.assign_album_to_customer(&assigned, "customer")
+            .await
+            .unwrap();
+        let token =
+            AuthManager::generate_access_token(&keys(), "customer", "customer", false, "session")
+                .unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "Authorization",
+            format!("Bearer {}", token).parse().unwrap(),
+        );
+
+        // when
+        let own = get_customer_album_media(
+            State(db.clone()),
+            Extension(keys()),
+            headers.clone(),
+            Path(assigned),
+        )
+        .await
+        .into_response();
+        let album = get_customer_album_media(
+            State(db.clone()),
+            Extension(keys()),
+            headers.clone(),
+            Path(unassigned),
+        )
+        .await
+        .into_response();
+        let query = Query(MediaFileQuery {
+            role: None,
+            album_id: None,
+        });
+        let file = get_customer_media_file(
+            State(db.clone()),
+            Extension(keys()),
+            headers,
+            Path(media_id),
+            query,
+        )
+        .await
+        .into_response();
+
+        // then
+        assert_eq!(own.status(), StatusCode::OK);
+        assert_eq!(album.status(), StatusCode::FORBIDDEN);
+        assert_eq!(file.status(), StatusCode::FORBIDDEN);
+    }
+}
@@ -1,18 +1,30 @@
 use axum::{
     body::StreamBody,
-    extract::{Path, State},
+    extract::{Extension, Path, Query, State},
     http::{HeaderMap, HeaderValue, StatusCode},
-    response::IntoResponse,
+    response::{IntoResponse, Response},
 };
+use common::auth::keys::KeyRing;
 use common::{
     auth::permissions::{has_album_permission, AlbumPermission},
-    database::ArcDynDatabase,
-    zip_cache::{build_zip_to_file, zip_tmp_path, ZipCacheManager},
+    config::configuration::Configuration,
+    database::{reference::ReferenceRole, ArcDynDatabase},
+    metadata::{credit::template_for_album, MetadataPolicy},
+    zip_cache::{
+        build_zip_to_file, touch, zip_entries, zip_tmp_path, ZipCacheManager, ZipDelivery, ZipFormat, ZipOptions,
+        DEFAULT_ZIP_ROLES,
+    },
+    zip_stream::StreamedZip,
 };
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::sync::Arc;
 use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tokio_util::io::ReaderStream;
 
 use super::customer::extract_session;
+use super::file_response::parse_byte_range;
+use super::watermark::{is_clean_viewer, payment_required};
 
 const BUILDING_HTML: &str = r#"<!DOCTYPE html>
 <html lang="en">
@@ -44,39 +56,53 @@ const BUILDING_HTML: &str = r#"<!DOCTYPE html>
 </body>
 </html>"#;
 
-/// Parses a single `bytes=start-end` range. Returns `Ok(Some(start, end))` for a valid range,
-/// `Ok(None)` to ignore (serve full file), `Err(())` for a satisfiable-but-out-of-bounds range (→ 416).
-fn parse_byte_range(range_str: &str, file_size: u64) -> Result<Option<(u64, u64)>, ()> {
-    let s = match range_str.strip_prefix("bytes=") {
-        Some(s) if !s.contains(',') => s, // skip multi-range
-        _ => return Ok(None),
-    };
-    let (start_s, end_s) = match s.split_once('-') {
-        Some(p) => p,
-        None => return Ok(None),
-    };
-    // Suffix ranges (bytes=-N) not needed for resumption — ignore.
-    if start_s.trim().is_empty() {
-        return Ok(None);
+#[derive(Debug, Deserialize)]
+pub struct DownloadQuery {
+    /// Comma separated file roles to pack, e.g. `original,raw`.
+    pub roles: Option<String>,
+    /// `jpeg` converts HEIC and AVIF images, `original` (default) packs them as uploaded.
+    #[serde(default)]
+    pub format: ZipFormat,
+    /// `stream` or `cached`, overrides the configured `media.zip_delivery`.
+    pub delivery: Option<ZipDelivery>,
+}
+
+impl DownloadQuery {
+    fn roles(&self) -> Vec<ReferenceRole> {
+        let mut roles: Vec<ReferenceRole> = self
+            .roles
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|r| r.trim().parse().ok())
+            .collect();
+        if roles.is_empty() {
+            return DEFAULT_ZIP_ROLES.to_vec();
+        }
+        // Stable order and no duplicates, so equal selections share a cache file.
+        roles.sort_by_key(|r| r.as_str());
+        roles.dedup();
+        roles
     }
-    let start: u64 = start_s.trim().parse().map_err(|_| ())?;
-    let end: u64 = if end_s.trim().is_empty() {
-        file_size.saturating_sub(1)
-    } else {
-        end_s.trim().parse().map_err(|_| ())?
-    };
-    if start >= file_size || start > end {
-        return Err(()); // 416
+
+    fn options(&self) -> ZipOptions {
+        ZipOptions {
+            roles: self.roles(),
+            format: self.format,
+            ..Default::default()
+        }
     }
-    Ok(Some((start, end.min(file_size - 1))))
 }
 
 pub async fn download_album_zip(
     State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    Extension(config): Extension<Arc<Configuration>>,
     headers: HeaderMap,
     Path(album_id): Path<String>,
+    Query(query): Query<DownloadQuery>,
 ) -> impl IntoResponse {
-    let (caller_id, role) = match extract_session(&headers) {
+    let (caller_id, role) = match extract_session(&keys, &headers) {
         Ok(p) => p,
         Err(e) => return (StatusCode::UNAUTHORIZED, e.to_string()).into_response(),
     };
@@ -97,6 +123,14 @@ pub async fn download_album_zip(
         return StatusCode::FORBIDDEN.into_response();
     }
 
+    match db.get_album_watermark(&album_id).await {
+        Ok(Some(_)) if !is_clean_viewer(&db, &album_id, &caller_id, &role).await => {
+            return payment_required();
+        }
+        Ok(_) => {}
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+
     let album_name = db
         .get_album(&album_id)
         .await
@@ -111,11 +145,73 @@ pub async fn download_album_zip(
         vec![]
     };
 
+    // Owners download their files as uploaded, everyone else under the album's policy and
+    // with its copyright template.
+    let is_owner = role == "account"
+        && has_album_permission(&db, &caller_id, &album_id, AlbumPermission::Owner)
+            .await
+            .unwrap_or(false);
+    let metadata_policy = if is_owner {
+        MetadataPolicy::Keep
+    } else {
+        match db.get_album_metadata_policy(&album_id).await {
+            Ok(policy) => policy,
+            Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        }
+    };
+    let copyright = if is_owner {
+        None
+    } else {
+        match template_for_album(&db, &album_id).await {
+            Ok(template) => template,
+            Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        }
+    };
+    let options = ZipOptions {
+        metadata_policy,
+        copyright,
+        ..query.options()
+    };
     let cache_path = if selected.is_empty() {
         ZipCacheManager::all_zip_path(&album_id)
     } else {
         ZipCacheManager::customer_zip_path(&album_id, &caller_id)
     };
+    let cache_path = ZipCacheManager::variant_path(cache_path, &options);
+
+    let delivery = query.delivery.unwrap_or(config.media.zip_delivery);
+
+    serve_album_zip(&db, &headers, album_id, &album_name, selected, &options, cache_path, delivery).await
+}
+
+/// Serves an album ZIP once access has been checked. Cached delivery starts a background
+/// build and returns a self-refreshing page when `cache_path` does not exist yet, streamed
+/// delivery writes the ZIP while reading the files.
+/// `selected` restricts the build to the given media ids; empty means the whole album.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn serve_album_zip(
+    db: &ArcDynDatabase,
+    headers: &HeaderMap,
+    album_id: String,
+    album_name: &str,
+    selected: Vec<String>,
+    options: &ZipOptions,
+    cache_path: PathBuf,
+    delivery: ZipDelivery,
+) -> Response {
+    // Streamed downloads never switch to the cached file, it has different bytes and
+    // would corrupt a resumed download.
+    if delivery == ZipDelivery::Stream {
+        let mut media_items = match db.get_media_for_album(&album_id).await {
+            Ok(items) => items,
+            Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        };
+        if !selected.is_empty() {
+            media_items.retain(|m| selected.contains(&m.uuid));
+        }
+        let zip = StreamedZip::plan(zip_entries(&media_items, db, options).await).await;
+        return serve_streamed_zip(headers, format!("album {}", album_id), album_name, zip);
+    }
 
     // Cache miss: kick off background build and return a self-refreshing page.
     // This avoids blocking the proxy connection for the duration of the zip build.
@@ -132,27 +228,33 @@ pub async fn download_album_zip(
             }
             let path_clone = cache_path.clone();
             let db_clone = db.clone();
+            let options = options.clone();
             tokio::spawn(async move {
-                if let Err(e) = build_zip_to_file(&media_items, &path_clone, &db_clone).await {
+                if let Err(e) = build_zip_to_file(&media_items, &path_clone, &db_clone, &options).await {
                     tracing::warn!("ZIP build failed for album {}: {:?}", album_id, e);
                 }
             });
         }
-        return (
-            StatusCode::OK,
-            [(axum::http::header::CONTENT_TYPE, "text/html; charset=utf-8")],
-            BUILDING_HTML,
-        )
-            .into_response();
+        return building_page();
     }
 
-    let safe_name: String = album_name
-        .chars()
-        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
-        .collect();
-    let disposition = format!("attachment; filename=\"{}.zip\"", safe_name);
+    serve_cached_zip(headers, album_name, &cache_path).await
+}
 
-    let mut file = match tokio::fs::File::open(&cache_path).await {
+/// The self-refreshing page shown while a ZIP is built.
+pub(crate) fn building_page() -> Response {
+    (
+        StatusCode::OK,
+        [(axum::http::header::CONTENT_TYPE, "text/html; charset=utf-8")],
+        BUILDING_HTML,
+    )
+        .into_response()
+}
+
+/// Serves a ZIP from the cache, honouring `Range` requests.
+pub(crate) async fn serve_cached_zip(headers: &HeaderMap, download_name: &str, cache_path: &PathBuf) -> Response {
+    touch(cache_path);
+    let mut file = match tokio::fs::File::open(cache_path).await {
         Ok(f) => f,
         Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
     };
@@ -162,43 +264,21 @@ pub async fn download_album_zip(
         Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
     };
 
-    let mut resp_headers = HeaderMap::new();
-    resp_headers.insert(axum::http::header::CONTENT_TYPE, HeaderValue::from_static("application/zip"));
-    resp_headers.insert(axum::http::header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
-    if let Ok(val) = HeaderValue::from_str(&disposition) {
-        resp_headers.insert(axum::http::header::CONTENT_DISPOSITION, val);
-    }
+    let mut resp_headers = zip_headers(download_name);
 
     // Handle Range header for partial / resumable downloads.
-    if let Some(range_val) = headers.get(axum::http::header::RANGE) {
-        if let Ok(range_str) = range_val.to_str() {
-            match parse_byte_range(range_str, file_size) {
-                Ok(Some((start, end))) => {
-                    let length = end - start + 1;
-                    if file.seek(std::io::SeekFrom::Start(start)).await.is_err() {
-                        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
-                    }
-                    let stream = ReaderStream::with_capacity(file.take(length), 256 * 1024);
-                    if let Ok(cr) = HeaderValue::from_str(&format!("bytes {}-{}/{}", start, end, file_size)) {
-                        resp_headers.insert(axum::http::header::CONTENT_RANGE, cr);
-                    }
-                    if let Ok(cl) = HeaderValue::from_str(&length.to_string()) {
-                        resp_headers.insert(axum::http::header::CONTENT_LENGTH, cl);
-                    }
-                    return (StatusCode::PARTIAL_CONTENT, resp_headers, StreamBody::new(stream)).into_response();
-                }
-                Err(()) => {
-                    // Valid syntax but out of bounds → 416.
-                    let cr = format!("bytes */{}", file_size);
-                    return (
-                        StatusCode::RANGE_NOT_SATISFIABLE,
-                        [(axum::http::header::CONTENT_RANGE, cr.as_str())],
-                        "",
-                    ).into_response();
-                }
-                Ok(None) => {} // unrecognised range unit — fall through to full response
+    match requested_range(headers, file_size) {
+        Ok(Some((start, end))) => {
+            let length = end - start + 1;
+            if file.seek(std::io::SeekFrom::Start(start)).await.is_err() {
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
             }
+            let stream = ReaderStream::with_capacity(file.take(length), 256 * 1024);
+            insert_range_headers(&mut resp_headers, start, end, file_size);
+            return (StatusCode::PARTIAL_CONTENT, resp_headers, StreamBody::new(stream)).into_response();
         }
+        Err(()) => return range_not_satisfiable(file_size),
+        Ok(None) => {}
     }
 
     if let Ok(val) = HeaderValue::from_str(&file_size.to_string()) {
@@ -207,3 +287,77 @@ pub async fn download_album_zip(
     let stream = ReaderStream::with_capacity(file, 256 * 1024);
     (StatusCode::OK, resp_headers, StreamBody::new(stream)).into_response()
 }
+
+/// Streams the requested part of a ZIP that is written on the fly. `label` names the
+/// download in logs.
+pub(crate) fn serve_streamed_zip(headers: &HeaderMap, label: String, download_name: &str, zip: StreamedZip) -> Response {
+    let total = zip.len();
+    let mut resp_headers = zip_headers(download_name);
+    let (status, start, end) = match requested_range(headers, total) {
+        Ok(Some((start, end))) => {
+            insert_range_headers(&mut resp_headers, start, end, total);
+            (StatusCode::PARTIAL_CONTENT, start, end)
+        }
+        Ok(None) => {
+            if let Ok(val) = HeaderValue::from_str(&total.to_string()) {
+                resp_headers.insert(axum::http::header::CONTENT_LENGTH, val);
+            }
+            (StatusCode::OK, 0, total - 1)
+        }
+        Err(()) => return range_not_satisfiable(total),
+    };
+
+    // The writer runs ahead of the client by at most the pipe's buffer.
+    let (mut writer, reader) = tokio::io::duplex(256 * 1024);
+    tokio::spawn(async move {
+        if let Err(e) = zip.write_range(start, end, &mut writer).await {
+            tracing::warn!("Streamed ZIP of {} aborted: {:?}", label, e);
+        }
+    });
+    (status, resp_headers, StreamBody::new(ReaderStream::with_capacity(reader, 256 * 1024))).into_response()
+}
+
+fn zip_headers(download_name: &str) -> HeaderMap {
+    let safe_name: String = download_name
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    let disposition = format!("attachment; filename=\"{}.zip\"", safe_name);
+
+    let mut resp_headers = HeaderMap::new();
+    resp_headers.insert(axum::http::header::CONTENT_TYPE, HeaderValue::from_static("application/zip"));
+    resp_headers.insert(axum::http::header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    if let Ok(val) = HeaderValue::from_str(&disposition) {
+        resp_headers.insert(axum::http::header::CONTENT_DISPOSITION, val);
+    }
+    resp_headers
+}
+
+/// The byte range asked for by the `Range` header, unknown units are ignored.
+/// Fails for out of bounds ranges, see [`range_not_satisfiable`].
+fn requested_range(headers: &HeaderMap, size: u64) -> Result<Option<(u64, u64)>, ()> {
+    match headers.get(axum::http::header::RANGE).and_then(|v| v.to_str().ok()) {
+        Some(range_str) => parse_byte_range(range_str, size),
+        None => Ok(None),
+    }
+}
+
+fn range_not_satisfiable(size: u64) -> Response {
+    // Valid syntax but out of bounds → 416.
+    let cr = format!("bytes */{}", size);
+    (
+        StatusCode::RANGE_NOT_SATISFIABLE,
+        [(axum::http::header::CONTENT_RANGE, cr.as_str())],
+        "",
+    )
+        .into_response()
+}
+
+fn insert_range_headers(resp_headers: &mut HeaderMap, start: u64, end: u64, size: u64) {
+    if let Ok(cr) = HeaderValue::from_str(&format!("bytes {}-{}/{}", start, end, size)) {
+        resp_headers.insert(axum::http::header::CONTENT_RANGE, cr);
+    }
+    if let Ok(cl) = HeaderValue::from_str(&(end - start + 1).to_string()) {
+        resp_headers.insert(axum::http::header::CONTENT_LENGTH, cl);
+    }
+}
@@ -0,0 +1,103 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Verifying the email address of accounts with the token of the mailed link.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Extension, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use tracing::error;
+
+use common::auth::account_token::AccountMailSettings;
+use common::auth::auth_manager::AuthManager;
+use common::auth::keys::KeyRing;
+use common::database::ArcDynDatabase;
+use common::mail::Mailer;
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyEmailRequest {
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResendVerificationRequest {
+    pub email: String,
+}
+
+/// Opened from the link in the verification mail.
+pub async fn verify_email_link(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    Query(request): Query<VerifyEmailRequest>,
+) -> impl IntoResponse {
+    verify(db, keys, &request.token).await
+}
+
+pub async fn verify_email(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    Json(request): Json<VerifyEmailRequest>,
+) -> impl IntoResponse {
+    verify(db, keys, &request.token).await
+}
+
+async fn verify(db: ArcDynDatabase, keys: Arc<KeyRing>, token: &str) -> Response {
+    match AuthManager::new(db, keys).verify_email(token).await {
+        Ok(account) => (
+            StatusCode::OK,
+            Json(serde_json::json!({
+                "account_id": account.account_id,
+                "email": account.email,
+                "email_verified": true,
+            })),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
+}
+
+/// Answers the same for unknown, verified and unverified addresses.
+pub async fn resend_verification(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    Extension(mailer): Extension<Arc<dyn Mailer>>,
+    Extension(account_mail): Extension<Arc<AccountMailSettings>>,
+    Json(request): Json<ResendVerificationRequest>,
+) -> impl IntoResponse {
+    if let Err(e) = AuthManager::new(Arc::clone(&db), Arc::clone(&keys))
+        .resend_email_verification(&mailer, &account_mail, request.email.trim())
+        .await
+    {
+        error!("Could not resend verification mail: {}", e);
+    }
+
+    (
+        StatusCode::ACCEPTED,
+        Json(serde_json::json!({"message": "A verification mail is on its way if the address needs one"})),
+    )
+        .into_response()
+}
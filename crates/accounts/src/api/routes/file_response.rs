@@ -0,0 +1,368 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Streams single files from disk with validators, conditional GET and byte ranges.
+
+use std::path::Path;
+use std::time::SystemTime;
+
+use axum::{
+    body::StreamBody,
+    headers::{ETag, HeaderMapExt, IfModifiedSince, IfNoneMatch, IfRange, LastModified},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+use tracing::error;
+
+/// Who may keep a copy of the response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CacheScope {
+    /// Only the requesting browser, e.g. content behind a login.
+    Private,
+    /// Shared caches as well, e.g. unprotected share links.
+    Public,
+}
+
+impl CacheScope {
+    fn cache_control(&self) -> HeaderValue {
+        match self {
+            CacheScope::Private => {
+                HeaderValue::from_static("private, max-age=3600, must-revalidate")
+            }
+            CacheScope::Public => HeaderValue::from_static("public, max-age=3600, must-revalidate"),
+        }
+    }
+}
+
+/// Returns a strong ETag from the content hash taken at upload. Generated files and uploads
+/// from before hashing get a weak one derived from size and modification time, so no request
+/// has to read the file. Weak validators never satisfy `If-Range`, which then falls back to
+/// the date.
+fn file_etag(content_hash: Option<&str>, len: u64, modified: SystemTime) -> anyhow::Result<ETag> {
+    if let Some(content_hash) = content_hash {
+        return format!("\"{}\"", content_hash)
+            .parse::<ETag>()
+            .map_err(|_| anyhow::anyhow!("Invalid ETag"));
+    }
+    let modified = modified
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    format!(
+        "W/\"{:x}-{:x}.{:x}\"",
+        len,
+        modified.as_secs(),
+        modified.subsec_nanos()
+    )
+    .parse::<ETag>()
+    .map_err(|_| anyhow::anyhow!("Invalid ETag"))
+}
+
+/// Parses a single `bytes=start-end` or `bytes=-suffix` range. Returns `Ok(Some(start, end))`
+/// for a valid range, `Ok(None)` to ignore it and serve the full file, and `Err(())` for a
+/// range outside the file (→ 416). Malformed ranges are ignored as RFC 9110 §14.2 asks.
+pub(crate) fn parse_byte_range(range_str: &str, file_size: u64) -> Result<Option<(u64, u64)>, ()> {
+    let s = match range_str.strip_prefix("bytes=") {
+        Some(s) if !s.contains(',') => s, // skip multi-range
+        _ => return Ok(None),
+    };
+    let (start_s, end_s) = match s.split_once('-') {
+        Some(p) => p,
+        None => return Ok(None),
+    };
+    // Suffix range: the last N bytes.
+    if start_s.trim().is_empty() {
+        let Ok(suffix) = end_s.trim().parse::<u64>() else {
+            return Ok(None);
+        };
+        if suffix == 0 || file_size == 0 {
+            return Err(());
+        }
+        return Ok(Some((file_size.saturating_sub(suffix), file_size - 1)));
+    }
+    let Ok(start) = start_s.trim().parse::<u64>() else {
+        return Ok(None);
+    };
+    let end = if end_s.trim().is_empty() {
+        file_size.saturating_sub(1)
+    } else {
+        match end_s.trim().parse::<u64>() {
+            Ok(end) if end >= start => end,
+            _ => return Ok(None),
+        }
+    };
+    if start >= file_size {
+        return Err(()); // 416
+    }
+    Ok(Some((start, end.min(file_size - 1))))
+}
+
+/// Streams `path` honouring `If-None-Match`, `If-Modified-Since`, `Range` and `If-Range`.
+/// `content_hash` is the hash stored with the reference when `path` is the upload itself.
+pub(crate) async fn serve_file(
+    request_headers: &HeaderMap,
+    path: &Path,
+    content_type: &str,
+    scope: CacheScope,
+    content_hash: Option<&str>,
+) -> Response {
+    let mut file = match tokio::fs::File::open(path).await {
+        Ok(f) => f,
+        Err(_) => return StatusCode::NOT_FOUND.into_response(),
+    };
+    let (file_size, modified) = match file.metadata().await {
+        Ok(m) => (m.len(), m.modified().unwrap_or(SystemTime::UNIX_EPOCH)),
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    let etag = match file_etag(content_hash, file_size, modified) {
+        Ok(etag) => etag,
+        Err(e) => {
+            error!("Failed to build ETag for {}: {:?}", path.display(), e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let last_modified = LastModified::from(modified);
+
+    let mut resp_headers = HeaderMap::new();
+    resp_headers.typed_insert(etag.clone());
+    resp_headers.typed_insert(last_modified);
+    resp_headers.insert(header::CACHE_CONTROL, scope.cache_control());
+    resp_headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+
+    // If-None-Match takes precedence over If-Modified-Since (RFC 9110 §13.2.2).
+    let not_modified = match request_headers.typed_get::<IfNoneMatch>() {
+        Some(if_none_match) => !if_none_match.precondition_passes(&etag),
+        None => request_headers
+            .typed_get::<IfModifiedSince>()
+            .is_some_and(|since| !since.is_modified(modified)),
+    };
+    if not_modified {
+        return (StatusCode::NOT_MODIFIED, resp_headers).into_response();
+    }
+
+    if let Ok(val) = HeaderValue::from_str(content_type) {
+        resp_headers.insert(header::CONTENT_TYPE, val);
+    }
+
+    // A stale If-Range turns the request into a plain GET for the full, current file.
+    let range_applies = request_headers
+        .typed_get::<IfRange>()
+        .map(|if_range| !if_range.is_modified(Some(&etag), Some(&last_modified)))
+        .unwrap_or(true);
+
+    if let Some(range_str) = request_headers
+        .get(header::RANGE)
+        .and_then(|v| v.to_str().ok())
+        .filter(|_| range_applies)
+    {
+        match parse_byte_range(range_str, file_size) {
+            Ok(Some((start, end))) => {
+                let length = end - start + 1;
+                if file.seek(std::io::SeekFrom::Start(start)).await.is_err() {
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+                let stream = ReaderStream::with_capacity(file.take(length), 256 * 1024);
+                if let Ok(cr) =
+                    HeaderValue::from_str(&format!("bytes {}-{}/{}", start, end, file_size))
+                {
+                    resp_headers.insert(header::CONTENT_RANGE, cr);
+                }
+                resp_headers.insert(header::CONTENT_LENGTH, HeaderValue::from(length));
+                return (
+                    StatusCode::PARTIAL_CONTENT,
+                    resp_headers,
+                    StreamBody::new(stream),
+                )
+                    .into_response();
+            }
+            Err(()) => {
+                let cr = format!("bytes */{}", file_size);
+                return (
+                    StatusCode::RANGE_NOT_SATISFIABLE,
+                    [(header::CONTENT_RANGE, cr.as_str())],
+                    "",
+                )
+                    .into_response();
+            }
+            Ok(None) => {} // unrecognised range unit — fall through to full response
+        }
+    }
+
+    resp_headers.insert(header::CONTENT_LENGTH, HeaderValue::from(file_size));
+    let stream = ReaderStream::with_capacity(file, 256 * 1024);
+    (StatusCode::OK, resp_headers, StreamBody::new(stream)).into_response()
+}
+
+/// Whether a response delivered the file from its beginning. Conditional hits and follow-up
+/// range requests (e.g. video scrubbing) are not counted as separate downloads.
+pub(crate) fn counts_as_download(response: &Response) -> bool {
+    match response.status() {
+        StatusCode::OK => true,
+        StatusCode::PARTIAL_CONTENT => response
+            .headers()
+            .get(header::CONTENT_RANGE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("bytes 0-")),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::database::reference::content_hash;
+
+    #[test]
+    fn test_parse_byte_range() {
+        // given
+        let size = 1000;
+
+        // when / then
+        assert_eq!(parse_byte_range("bytes=0-99", size), Ok(Some((0, 99))));
+        assert_eq!(parse_byte_range("bytes=500-", size), Ok(Some((500, 999))));
+        assert_eq!(parse_byte_range("bytes=-100", size), Ok(Some((900, 999))));
+        assert_eq!(
+            parse_byte_range("bytes=900-5000", size),
+            Ok(Some((900, 999)))
+        );
+        assert_eq!(parse_byte_range("bytes=1000-", size), Err(()));
+        assert_eq!(parse_byte_range("bytes=0-1,5-9", size), Ok(None));
+        assert_eq!(parse_byte_range("items=0-1", size), Ok(None));
+        assert_eq!(parse_byte_range("bytes=abc-", size), Ok(None));
+        assert_eq!(parse_byte_range("bytes=5-3", size), Ok(None));
+        assert_eq!(parse_byte_range("bytes=-x", size), Ok(None));
+        assert_eq!(parse_byte_range("bytes=-0", size), Err(()));
+    }
+
+    #[test]
+    fn test_etag_of_changed_file_should_differ() {
+        // given
+        let modified = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+
+        // when
+        let etag = file_etag(None, 10, modified).unwrap();
+        let resized = file_etag(None, 11, modified).unwrap();
+        let touched = file_etag(None, 10, modified + std::time::Duration::from_millis(1)).unwrap();
+        let hashed = file_etag(Some("abc123"), 10, modified).unwrap();
+        let rehashed = file_etag(Some("def456"), 10, modified).unwrap();
+
+        // then
+        assert_eq!(etag, file_etag(None, 10, modified).unwrap());
+        assert_ne!(etag, resized);
+        assert_ne!(etag, touched);
+        assert_eq!(hashed, file_etag(Some("abc123"), 11, modified).unwrap());
+        assert_ne!(hashed, rehashed);
+    }
+
+    #[tokio::test]
+    async fn test_serve_file_conditional_and_range() {
+        // given
+        let dir = std::env::temp_dir().join(format!("file_response_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("photo.jpg");
+        std::fs::write(&path, b"0123456789").unwrap();
+
+        // when
+        let full = serve_file(
+            &HeaderMap::new(),
+            &path,
+            "image/jpeg",
+            CacheScope::Private,
+            None,
+        )
+        .await;
+        let etag = full.headers().get(header::ETAG).unwrap().clone();
+
+        let mut conditional = HeaderMap::new();
+        conditional.insert(header::IF_NONE_MATCH, etag);
+        let cached = serve_file(&conditional, &path, "image/jpeg", CacheScope::Private, None).await;
+
+        let mut ranged = HeaderMap::new();
+        ranged.insert(header::RANGE, HeaderValue::from_static("bytes=2-4"));
+        let partial = serve_file(&ranged, &path, "image/jpeg", CacheScope::Public, None).await;
+
+        // then
+        assert_eq!(full.status(), StatusCode::OK);
+        assert_eq!(cached.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(partial.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            partial.headers().get(header::CONTENT_RANGE).unwrap(),
+            "bytes 2-4/10"
+        );
+        assert!(partial
+            .headers()
+            .get(header::CACHE_CONTROL)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .starts_with("public"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    #[tokio::test]
+    async fn test_serve_file_if_range_with_content_hash_should_serve_range() {
+        // given
+        let dir = std::env::temp_dir().join(format!("file_response_hash_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("clip.mov");
+        std::fs::write(&path, b"0123456789").unwrap();
+        let hash = content_hash(b"0123456789");
+
+        let mut matching = HeaderMap::new();
+        matching.insert(header::RANGE, HeaderValue::from_static("bytes=2-4"));
+        matching.insert(
+            header::IF_RANGE,
+            HeaderValue::from_str(&format!("\"{}\"", hash)).unwrap(),
+        );
+        let mut stale = matching.clone();
+        stale.insert(header::IF_RANGE, HeaderValue::from_static("\"replaced\""));
+
+        // when
+        let partial = serve_file(
+            &matching,
+            &path,
+            "video/quicktime",
+            CacheScope::Private,
+            Some(&hash),
+        )
+        .await;
+        let full = serve_file(
+            &stale,
+            &path,
+            "video/quicktime",
+            CacheScope::Private,
+            Some(&hash),
+        )
+        .await;
+
+        // then
+        assert_eq!(partial.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            partial
+                .headers()
+                .get(header::ETAG)
+                .unwrap()
+                .to_str()
+                .unwrap(),
+            format!("\"{}\"", hash)
+        );
+        assert_eq!(full.status(), StatusCode::OK);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
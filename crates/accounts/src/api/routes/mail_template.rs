@@ -0,0 +1,321 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Wording of the mails sent to accounts of the caller's studio, those registered with one of
+//! its access codes. Only studios, the accounts owning albums, and admins may word mails.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Extension, Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+
+use common::auth::keys::KeyRing;
+use common::database::mail_template::{MailKind, MailTemplate};
+use common::database::ArcDynDatabase;
+
+use super::copyright::require_account;
+
+/// Longest accepted body, in bytes.
+const MAX_BODY_LENGTH: usize = 16 * 1024;
+
+#[derive(Serialize)]
+pub struct MailTemplateEntry {
+    pub kind: &'static str,
+    #[serde(flatten)]
+    pub template: MailTemplate,
+    /// False while the built-in text is used.
+    pub custom: bool,
+}
+
+/// Trims the template and rejects ones that are empty or bring links of their own.
+fn normalize(kind: MailKind, template: MailTemplate) -> Result<MailTemplate, String> {
+    let template = MailTemplate {
+        subject: template.subject.trim().to_string(),
+        body: template.body.trim_end().to_string() + "\n",
+    };
+
+    if template.subject.is_empty() || template.body.trim().is_empty() {
+        return Err("subject and body are required".into());
+    }
+    if template.subject.contains(['\r', '\n']) {
+        return Err("subject must be a single line".into());
+    }
+    if template.body.len() > MAX_BODY_LENGTH {
+        return Err(format!(
+            "body must not be longer than {} bytes",
+            MAX_BODY_LENGTH
+        ));
+    }
+    template.check_links(kind)?;
+    Ok(template)
+}
+
+/// Returns the caller's account id if it may word mails, or the status and message otherwise.
+async fn require_studio(
+    db: &ArcDynDatabase,
+    keys: &KeyRing,
+    headers: &HeaderMap,
+) -> Result<String, (StatusCode, String)> {
+    let account_id = require_account(keys, headers)?;
+    if db.is_account_admin(&account_id).await.unwrap_or(false) {
+        return Ok(account_id);
+    }
+
+    match db.get_albums_owned_by_account(&account_id).await {
+        Ok(albums) if !albums.is_empty() => Ok(account_id),
+        Ok(_) => Err((
+            StatusCode::FORBIDDEN,
+            "Only studios and admins can word mails".to_string(),
+        )),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
+
+fn unknown_kind() -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(serde_json::json!({"error": "Unknown mail kind"})),
+    )
+        .into_response()
+}
+
+async fn entry(
+    db: &ArcDynDatabase,
+    owner_id: &str,
+    kind: MailKind,
+) -> anyhow::Result<MailTemplateEntry> {
+    let custom = db.get_mail_template(owner_id, kind).await?;
+    Ok(MailTemplateEntry {
+        kind: kind.as_str(),
+        custom: custom.is_some(),
+        template: custom.unwrap_or_else(|| MailTemplate::builtin(kind)),
+    })
+}
+
+pub async fn list_mail_templates(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let account_id = match require_account(&keys, &headers) {
+        Ok(account_id) => account_id,
+        Err((status, error)) => {
+            return (status, Json(serde_json::json!({"error": error}))).into_response()
+        }
+    };
+
+    let mut entries = vec![];
+    for kind in MailKind::ALL {
+        match entry(&db, &account_id, kind).await {
+            Ok(entry) => entries.push(entry),
+            Err(e) => {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(serde_json::json!({"error": e.to_string()})),
+                )
+                    .into_response()
+            }
+        }
+    }
+    (StatusCode::OK, Json(entries)).into_response()
+}
+
+pub async fn get_mail_template(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    headers: HeaderMap,
+    Path(kind): Path<String>,
+) -> impl IntoResponse {
+    let account_id = match require_account(&keys, &headers) {
+        Ok(account_id) => account_id,
+        Err((status, error)) => {
+            return (status, Json(serde_json::json!({"error": error}))).into_response()
+        }
+    };
+    let Some(kind) = MailKind::parse(&kind) else {
+        return unknown_kind();
+    };
+
+    match entry(&db, &account_id, kind).await {
+        Ok(entry) => (StatusCode::OK, Json(entry)).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn put_mail_template(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    headers: HeaderMap,
+    Path(kind): Path<String>,
+    Json(req): Json<MailTemplate>,
+) -> impl IntoResponse {
+    let account_id = match require_studio(&db, &keys, &headers).await {
+        Ok(account_id) => account_id,
+        Err((status, error)) => {
+            return (status, Json(serde_json::json!({"error": error}))).into_response()
+        }
+    };
+    let Some(kind) = MailKind::parse(&kind) else {
+        return unknown_kind();
+    };
+    let template = match normalize(kind, req) {
+        Ok(template) => template,
+        Err(error) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({"error": error})),
+            )
+                .into_response()
+        }
+    };
+
+    match db.set_mail_template(&account_id, kind, &template).await {
+        Ok(_) => (
+            StatusCode::OK,
+            Json(MailTemplateEntry {
+                kind: kind.as_str(),
+                template,
+                custom: true,
+            }),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn delete_mail_template(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    headers: HeaderMap,
+    Path(kind): Path<String>,
+) -> impl IntoResponse {
+    let account_id = match require_studio(&db, &keys, &headers).await {
+        Ok(account_id) => account_id,
+        Err((status, error)) => {
+            return (status, Json(serde_json::json!({"error": error}))).into_response()
+        }
+    };
+    let Some(kind) = MailKind::parse(&kind) else {
+        return unknown_kind();
+    };
+
+    match db.delete_mail_template(&account_id, kind).await {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_with_link_placeholder_should_fail_for_mails_with_links() {
+        // given
+        let template = MailTemplate {
+            subject: "Reset your password".into(),
+            body: "Hello {{name}}, choose a new password at {{link}}.".into(),
+        };
+
+        // when
+        let result = normalize(MailKind::PasswordReset, template);
+
+        // then
+        assert_eq!(
+            result.unwrap_err(),
+            "password_reset mails must not contain {{link}}, the link is added below the body"
+        );
+    }
+
+    #[test]
+    fn test_normalize_with_redirected_link_should_fail() {
+        // given
+        let template = MailTemplate {
+            subject: "Please verify your email address".into(),
+            body: "Hello {{name}}, verify at HTTPS://evil.example/?next={{link}}".into(),
+        };
+
+        // when
+        let result = normalize(MailKind::VerifyEmail, template);
+
+        // then
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_normalize_without_link_should_succeed() {
+        // given
+        let template = MailTemplate {
+            subject: " Reset your password ".into(),
+            body: "Hello {{name}}, choose a new password with the link below.  \n\n".into(),
+        };
+
+        // when
+        let reset = normalize(MailKind::PasswordReset, template.clone()).unwrap();
+        let changed = normalize(MailKind::PasswordChanged, template).unwrap();
+
+        // then
+        assert_eq!(reset.subject, "Reset your password");
+        assert_eq!(
+            reset.body,
+            "Hello {{name}}, choose a new password with the link below.\n"
+        );
+        assert_eq!(changed, reset);
+    }
+
+    #[test]
+    fn test_rendered_mail_should_keep_the_link_below_the_body() {
+        // given
+        let template = normalize(
+            MailKind::PasswordReset,
+            MailTemplate {
+                subject: "Reset your password".into(),
+                body: "Hello {{name}}, choose a new password with the link below.".into(),
+            },
+        )
+        .unwrap();
+
+        // when
+        let mail = template
+            .render(&[("name", "Jane"), ("link", "https://evil.example/")])
+            .with_link("https://photos.example.com/reset-password?token=abc");
+
+        // then
+        assert_eq!(
+            mail.body,
+            "Hello Jane, choose a new password with the link below.\n\n\
+             https://photos.example.com/reset-password?token=abc\n"
+        );
+    }
+}
@@ -0,0 +1,216 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Metadata policy of albums and its application, together with copyright templates, to
+//! delivered files.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    extract::{Extension, Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use common::auth::keys::KeyRing;
+use common::auth::permissions::{has_album_permission, AlbumPermission};
+use common::database::copyright_template::CopyrightTemplate;
+use common::database::ArcDynDatabase;
+use common::jobs::{self, Job};
+use common::metadata::{prepare_delivery, MetadataPolicy, UnsupportedFormat};
+
+use super::customer::extract_session;
+
+/// Owners often try a few settings in a row, only the last one is prepared.
+const DELIVERY_DEBOUNCE_SECS: u64 = 60;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MetadataPolicyBody {
+    pub metadata_policy: MetadataPolicy,
+}
+
+/// Checks that the caller owns the album, returning the error response otherwise.
+pub(crate) async fn require_owner(
+    db: &ArcDynDatabase,
+    keys: &KeyRing,
+    headers: &HeaderMap,
+    album_id: &str,
+) -> Option<Response> {
+    let (caller_id, role) = match extract_session(keys, headers) {
+        Ok(p) => p,
+        Err(e) => {
+            return Some(
+                (
+                    StatusCode::UNAUTHORIZED,
+                    Json(serde_json::json!({"error": e.to_string()})),
+                )
+                    .into_response(),
+            )
+        }
+    };
+    if role != "account" {
+        return Some(
+            (
+                StatusCode::FORBIDDEN,
+                Json(serde_json::json!({"error": "Account token required"})),
+            )
+                .into_response(),
+        );
+    }
+    if !has_album_permission(db, &caller_id, album_id, AlbumPermission::Owner)
+        .await
+        .unwrap_or(false)
+    {
+        return Some(
+            (
+                StatusCode::FORBIDDEN,
+                Json(serde_json::json!({"error": "Owner or admin access required"})),
+            )
+                .into_response(),
+        );
+    }
+    None
+}
+
+pub async fn get_album_metadata_policy(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    headers: HeaderMap,
+    Path(album_id): Path<String>,
+) -> impl IntoResponse {
+    if let Some(resp) = require_owner(&db, &keys, &headers, &album_id).await {
+        return resp;
+    }
+    match db.get_album_metadata_policy(&album_id).await {
+        Ok(metadata_policy) => {
+            (StatusCode::OK, Json(MetadataPolicyBody { metadata_policy })).into_response()
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn put_album_metadata_policy(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    headers: HeaderMap,
+    Path(album_id): Path<String>,
+    Json(req): Json<MetadataPolicyBody>,
+) -> impl IntoResponse {
+    if let Some(resp) = require_owner(&db, &keys, &headers, &album_id).await {
+        return resp;
+    }
+    match db
+        .set_album_metadata_policy(&album_id, req.metadata_policy)
+        .await
+    {
+        Ok(_) => {
+            schedule_album_delivery(&db, &album_id).await;
+            (StatusCode::OK, Json(req)).into_response()
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
+}
+
+/// Prepares stripped copies and the album ZIP in the background after the album's policy or
+/// copyright template changed, so the next download does not have to wait for them.
+pub(crate) async fn schedule_album_delivery(db: &ArcDynDatabase, album_id: &str) {
+    let delay = Duration::from_secs(DELIVERY_DEBOUNCE_SECS);
+    let scrub = Job::ScrubAlbum {
+        album_id: album_id.to_string(),
+    };
+    let zip = Job::BuildAlbumZip {
+        album_id: album_id.to_string(),
+    };
+    for (job, key) in [
+        (scrub, format!("scrub:{}", album_id)),
+        (zip, format!("zip:{}", album_id)),
+    ] {
+        if let Err(e) = jobs::enqueue_debounced(db, job, &key, delay).await {
+            error!("Could not schedule {}: {:?}", key, e);
+        }
+    }
+}
+
+/// The metadata policy for originals delivered to this viewer. Album owners always get
+/// their files as uploaded.
+pub(crate) async fn policy_for_viewer(
+    db: &ArcDynDatabase,
+    media_id: &str,
+    viewer_id: &str,
+    viewer_role: &str,
+) -> Result<MetadataPolicy, StatusCode> {
+    let (album_id, policy) = match db.get_metadata_policy_for_media(media_id).await {
+        Ok(Some(found)) => found,
+        Ok(None) => return Ok(MetadataPolicy::Keep),
+        Err(e) => {
+            error!(
+                "Failed to load metadata policy for media {}: {:?}",
+                media_id, e
+            );
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    if viewer_role == "account"
+        && has_album_permission(db, viewer_id, &album_id, AlbumPermission::Owner)
+            .await
+            .unwrap_or(false)
+    {
+        return Ok(MetadataPolicy::Keep);
+    }
+    Ok(policy)
+}
+
+/// Applies `policy` and embeds `copyright` into a stored file, returning the file to serve
+/// and its content type.
+pub(crate) async fn apply_policy(
+    media_id: &str,
+    path: PathBuf,
+    content_type: String,
+    policy: MetadataPolicy,
+    copyright: Option<&CopyrightTemplate>,
+) -> Result<(PathBuf, String), Response> {
+    if policy == MetadataPolicy::Keep && copyright.is_none() {
+        return Ok((path, content_type));
+    }
+
+    match prepare_delivery(&path, media_id, policy, copyright).await {
+        Ok(delivery) => Ok((delivery.path, delivery.mime_type)),
+        Err(e) if e.downcast_ref::<UnsupportedFormat>().is_some() => Err((
+            StatusCode::FORBIDDEN,
+            Json(serde_json::json!({"error": format!("{}, the album's metadata policy withholds it", e)})),
+        )
+            .into_response()),
+        Err(e) => {
+            error!("Failed to prepare delivery of media {}: {:?}", media_id, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
+}
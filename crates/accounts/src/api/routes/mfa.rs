@@ -0,0 +1,413 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Two-factor authentication of accounts: finishing a password login with a code,
+//! and enrolling or removing the authenticator app.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::{
+    extract::{ConnectInfo, Extension, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use tracing::{error, warn};
+
+use common::auth::auth_manager::{AccessToken, AuthManager};
+use common::auth::keys::KeyRing;
+use common::auth::mfa::{MfaPurpose, SecondFactor};
+use common::auth::session::{SessionClient, TrustedProxies};
+use common::config::configuration::Configuration;
+use common::database::ArcDynDatabase;
+
+use super::account::{login_response, AccountLoginResponse};
+use super::session::access_token;
+
+#[derive(Debug, Deserialize)]
+pub struct MfaLoginRequest {
+    pub mfa_token: String,
+    /// Code of the authenticator app or a recovery code.
+    pub code: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct TotpEnrollmentRequest {
+    /// Identifies the account instead of a bearer token while the policy
+    /// makes an admin enroll during login.
+    pub mfa_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TotpConfirmRequest {
+    pub code: String,
+    pub mfa_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MfaCodeRequest {
+    pub code: String,
+}
+
+fn error_response(status: StatusCode, message: impl ToString) -> Response {
+    (
+        status,
+        Json(serde_json::json!({"error": message.to_string()})),
+    )
+        .into_response()
+}
+
+fn account_token(keys: &KeyRing, headers: &HeaderMap) -> Result<AccessToken, (StatusCode, String)> {
+    match access_token(keys, headers) {
+        Ok(token) if token.role == "account" => Ok(token),
+        Ok(_) => Err((
+            StatusCode::FORBIDDEN,
+            "Only accounts have a second factor".to_string(),
+        )),
+        Err(e) => Err((StatusCode::UNAUTHORIZED, e.to_string())),
+    }
+}
+
+/// POST /auth/account/login/mfa
+pub async fn complete_login(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    Extension(proxies): Extension<Arc<TrustedProxies>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    Json(request): Json<MfaLoginRequest>,
+) -> impl IntoResponse {
+    let auth_manager = AuthManager::new(Arc::clone(&db), Arc::clone(&keys));
+    let client =
+        SessionClient::from_request(&headers, peer.map(|ConnectInfo(addr)| addr), &proxies);
+
+    match auth_manager
+        .complete_mfa_login(&request.mfa_token, &request.code, &client)
+        .await
+    {
+        Ok(response) => login_response(&auth_manager, response).await,
+        Err(e) => {
+            warn!("Second factor login failed: {}", e);
+            error_response(StatusCode::UNAUTHORIZED, e)
+        }
+    }
+}
+
+/// GET /auth/account/mfa
+pub async fn get_status(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    Extension(config): Extension<Arc<Configuration>>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let caller = match account_token(&keys, &headers) {
+        Ok(token) => token,
+        Err((status, message)) => return error_response(status, message),
+    };
+
+    match AuthManager::new(Arc::clone(&db), Arc::clone(&keys))
+        .mfa_status(&caller.sub, caller.is_admin, config.auth.require_admin_mfa)
+        .await
+    {
+        Ok(status) => (StatusCode::OK, Json(status)).into_response(),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e),
+    }
+}
+
+/// POST /auth/account/mfa/totp
+///
+/// Answers with the secret and an `otpauth://` URI to show as QR code.
+pub async fn start_totp_enrollment(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    headers: HeaderMap,
+    request: Option<Json<TotpEnrollmentRequest>>,
+) -> impl IntoResponse {
+    let request = request.map(|Json(request)| request).unwrap_or_default();
+    let account_id = match request.mfa_token {
+        Some(mfa_token) => match AuthManager::mfa_challenge_account(&mfa_token, MfaPurpose::Enroll)
+        {
+            Some(account_id) => account_id,
+            None => {
+                return error_response(StatusCode::UNAUTHORIZED, "Unknown or expired mfa_token")
+            }
+        },
+        None => match account_token(&keys, &headers) {
+            Ok(token) => token.sub,
+            Err((status, message)) => return error_response(status, message),
+        },
+    };
+
+    let auth_manager = AuthManager::new(Arc::clone(&db), Arc::clone(&keys));
+    match db.get_account_mfa(&account_id).await {
+        Ok(Some(mfa)) if mfa.is_enabled() => {
+            return error_response(
+                StatusCode::CONFLICT,
+                "Two-factor authentication is already enabled",
+            )
+        }
+        Ok(_) => {}
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, e),
+    }
+
+    match auth_manager.start_totp_enrollment(&account_id).await {
+        Ok(enrollment) => (StatusCode::OK, Json(enrollment)).into_response(),
+        Err(e) => {
+            error!("Could not start TOTP enrollment: {}", e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, e)
+        }
+    }
+}
+
+/// POST /auth/account/mfa/totp/confirm
+///
+/// Enables the second factor and answers with the recovery codes. Enrollments made during
+/// login answer with the tokens of the new session as well.
+pub async fn confirm_totp_enrollment(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    Extension(proxies): Extension<Arc<TrustedProxies>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    Json(request): Json<TotpConfirmRequest>,
+) -> impl IntoResponse {
+    let auth_manager = AuthManager::new(Arc::clone(&db), Arc::clone(&keys));
+
+    if let Some(mfa_token) = request.mfa_token {
+        let client =
+            SessionClient::from_request(&headers, peer.map(|ConnectInfo(addr)| addr), &proxies);
+        return match auth_manager
+            .complete_mfa_enrollment(&mfa_token, &request.code, &client)
+            .await
+        {
+            Ok((recovery_codes, response)) => {
+                let _ = auth_manager
+                    .update_last_login_account(response.account_id.clone())
+                    .await;
+                (
+                    StatusCode::OK,
+                    Json(serde_json::json!({
+                        "recovery_codes": recovery_codes,
+                        "login": AccountLoginResponse::from(response),
+                    })),
+                )
+                    .into_response()
+            }
+            Err(e) => error_response(StatusCode::BAD_REQUEST, e),
+        };
+    }
+
+    let caller = match account_token(&keys, &headers) {
+        Ok(token) => token,
+        Err((status, message)) => return error_response(status, message),
+    };
+
+    match auth_manager
+        .confirm_totp_enrollment(&caller.sub, &request.code)
+        .await
+    {
+        Ok(recovery_codes) => (
+            StatusCode::OK,
+            Json(serde_json::json!({"recovery_codes": recovery_codes})),
+        )
+            .into_response(),
+        Err(e) => error_response(StatusCode::BAD_REQUEST, e),
+    }
+}
+
+/// DELETE /auth/account/mfa/totp
+pub async fn disable_totp(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    Extension(config): Extension<Arc<Configuration>>,
+    headers: HeaderMap,
+    Json(request): Json<MfaCodeRequest>,
+) -> impl IntoResponse {
+    let caller = match account_token(&keys, &headers) {
+        Ok(token) => token,
+        Err((status, message)) => return error_response(status, message),
+    };
+
+    let auth_manager = AuthManager::new(Arc::clone(&db), Arc::clone(&keys));
+    match auth_manager
+        .second_factor_removable(
+            &caller.sub,
+            caller.is_admin,
+            config.auth.require_admin_mfa,
+            SecondFactor::Totp,
+        )
+        .await
+    {
+        Ok(true) => {}
+        Ok(false) => {
+            return error_response(
+                StatusCode::FORBIDDEN,
+                "Two-factor authentication is required for admin accounts",
+            )
+        }
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, e),
+    }
+
+    match auth_manager
+        .disable_totp(
+            &caller.sub,
+            caller.is_admin,
+            config.auth.require_admin_mfa,
+            &request.code,
+        )
+        .await
+    {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => error_response(StatusCode::BAD_REQUEST, e),
+    }
+}
+
+/// POST /auth/account/mfa/recovery-codes
+///
+/// Replaces the recovery codes, invalidating the old ones.
+pub async fn regenerate_recovery_codes(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    headers: HeaderMap,
+    Json(request): Json<MfaCodeRequest>,
+) -> impl IntoResponse {
+    let caller = match account_token(&keys, &headers) {
+        Ok(token) => token,
+        Err((status, message)) => return error_response(status, message),
+    };
+
+    match AuthManager::new(Arc::clone(&db), Arc::clone(&keys))
+        .regenerate_recovery_codes(&caller.sub, &request.code)
+        .await
+    {
+        Ok(recovery_codes) => (
+            StatusCode::OK,
+            Json(serde_json::json!({"recovery_codes": recovery_codes})),
+        )
+            .into_response(),
+        Err(e) => error_response(StatusCode::BAD_REQUEST, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use common::auth::keys::JwtKey;
+    use common::auth::mfa::{SecondFactorLocked, MAX_SECOND_FACTOR_FAILURES};
+    use common::auth::totp;
+    use database::memory::MemoryDatabase;
+
+    fn keys() -> Arc<KeyRing> {
+        Arc::new(KeyRing::new(vec![JwtKey::new("test", b"test-secret")], None).unwrap())
+    }
+
+    /// RFC 6238 test secret, base32 encoded.
+    const SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    async fn account_with_totp(account_id: &str) -> ArcDynDatabase {
+        let db: ArcDynDatabase = Arc::new(MemoryDatabase::new());
+        db.set_pending_totp_secret(account_id, SECRET, Utc::now())
+            .await
+            .unwrap();
+        db.enable_totp(account_id, 0, &[], Utc::now())
+            .await
+            .unwrap();
+        db
+    }
+
+    fn current_code() -> String {
+        totp::code_at(b"12345678901234567890", totp::time_step(Utc::now()), 6)
+    }
+
+    #[tokio::test]
+    async fn test_second_factor_after_too_many_wrong_codes_should_fail() {
+        // given
+        let db = account_with_totp("account").await;
+        let auth_manager = AuthManager::new(Arc::clone(&db), keys());
+        let login = |code: String| {
+            let db = Arc::clone(&db);
+            let auth_manager = AuthManager::new(Arc::clone(&db), keys());
+            async move {
+                // Every password login hands out a fresh mfa_token.
+                let challenge = auth_manager
+                    .mfa_challenge_for("account", false, false)
+                    .await
+                    .unwrap()
+                    .unwrap();
+                let request = MfaLoginRequest {
+                    mfa_token: challenge.mfa_token,
+                    code,
+                };
+                complete_login(
+                    State(db),
+                    Extension(keys()),
+                    Extension(Default::default()),
+                    None,
+                    HeaderMap::new(),
+                    Json(request),
+                )
+                .await
+                .into_response()
+            }
+        };
+
+        // when
+        for _ in 0..MAX_SECOND_FACTOR_FAILURES {
+            assert_eq!(
+                login("12345".into()).await.status(),
+                StatusCode::UNAUTHORIZED
+            );
+        }
+        let right_code = login(current_code()).await;
+
+        // then
+        assert_eq!(right_code.status(), StatusCode::UNAUTHORIZED);
+        assert!(auth_manager
+            .verify_second_factor("account", &current_code())
+            .await
+            .unwrap_err()
+            .is::<SecondFactorLocked>());
+    }
+
+    #[tokio::test]
+    async fn test_right_second_factor_should_reset_failures() {
+        // given
+        let db = account_with_totp("account").await;
+        let auth_manager = AuthManager::new(Arc::clone(&db), keys());
+        for _ in 1..MAX_SECOND_FACTOR_FAILURES {
+            assert!(!auth_manager
+                .verify_second_factor("account", "12345")
+                .await
+                .unwrap());
+        }
+
+        // when
+        let verified = auth_manager
+            .verify_second_factor("account", &current_code())
+            .await
+            .unwrap();
+
+        // then
+        assert!(verified);
+        let mfa = db.get_account_mfa("account").await.unwrap().unwrap();
+        assert_eq!(mfa.failed_attempts, 0);
+        assert_eq!(mfa.locked_until, None);
+    }
+}
@@ -1,7 +1,20 @@
 pub(crate) mod account;
 pub(crate) mod admin;
 pub(crate) mod album_access;
+pub(crate) mod copyright;
 pub(crate) mod customer;
 pub(crate) mod download;
+pub(crate) mod email;
+pub(crate) mod file_response;
 pub(crate) mod get_user_id_profile;
+pub(crate) mod mail_template;
+pub(crate) mod metadata_policy;
+pub(crate) mod mfa;
+pub(crate) mod passkey;
+pub(crate) mod password;
+pub(crate) mod selection;
+pub(crate) mod session;
+pub(crate) mod share;
+pub(crate) mod signed_media;
 pub(crate) mod stats;
+pub(crate) mod watermark;
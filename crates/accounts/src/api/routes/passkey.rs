@@ -0,0 +1,257 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Passkeys of accounts: registering and removing them, and signing in with one,
+//! either passwordless or as second factor of a password login.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::{
+    extract::{ConnectInfo, Extension, Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use tracing::{error, warn};
+
+use common::auth::auth_manager::{AccessToken, AuthManager};
+use common::auth::keys::KeyRing;
+use common::auth::mfa::SecondFactor;
+use common::auth::passkey::{AssertionCredential, RegistrationCredential};
+use common::auth::session::{SessionClient, TrustedProxies};
+use common::auth::webauthn::RelyingParty;
+use common::config::configuration::Configuration;
+use common::database::ArcDynDatabase;
+
+use super::account::login_response;
+use super::session::access_token;
+
+#[derive(Debug, Deserialize)]
+pub struct PasskeyRegistrationRequest {
+    /// Shown in the list of passkeys, e.g. "Phone".
+    pub name: Option<String>,
+    pub credential: RegistrationCredential,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct PasskeyLoginOptionsRequest {
+    /// Asks for a passkey as second factor of this password login.
+    pub mfa_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasskeyLoginRequest {
+    pub credential: AssertionCredential,
+    pub mfa_token: Option<String>,
+}
+
+fn error_response(status: StatusCode, message: impl ToString) -> Response {
+    (
+        status,
+        Json(serde_json::json!({"error": message.to_string()})),
+    )
+        .into_response()
+}
+
+fn account_token(keys: &KeyRing, headers: &HeaderMap) -> Result<AccessToken, (StatusCode, String)> {
+    match access_token(keys, headers) {
+        Ok(token) if token.role == "account" => Ok(token),
+        Ok(_) => Err((
+            StatusCode::FORBIDDEN,
+            "Only accounts have passkeys".to_string(),
+        )),
+        Err(e) => Err((StatusCode::UNAUTHORIZED, e.to_string())),
+    }
+}
+
+/// GET /auth/account/passkeys
+pub async fn list_passkeys(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let caller = match account_token(&keys, &headers) {
+        Ok(token) => token,
+        Err((status, message)) => return error_response(status, message),
+    };
+
+    match db.list_passkeys(&caller.sub).await {
+        Ok(passkeys) => (StatusCode::OK, Json(passkeys)).into_response(),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e),
+    }
+}
+
+/// POST /auth/account/passkeys/register/options
+///
+/// Answers with the options for `navigator.credentials.create()`.
+pub async fn registration_options(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    Extension(relying_party): Extension<Arc<RelyingParty>>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let caller = match account_token(&keys, &headers) {
+        Ok(token) => token,
+        Err((status, message)) => return error_response(status, message),
+    };
+
+    match AuthManager::new(Arc::clone(&db), Arc::clone(&keys))
+        .passkey_registration_options(&relying_party, &caller.sub)
+        .await
+    {
+        Ok(options) => (StatusCode::OK, Json(options)).into_response(),
+        Err(e) => {
+            error!("Could not start passkey registration: {}", e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, e)
+        }
+    }
+}
+
+/// POST /auth/account/passkeys/register
+pub async fn register_passkey(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    Extension(relying_party): Extension<Arc<RelyingParty>>,
+    headers: HeaderMap,
+    Json(request): Json<PasskeyRegistrationRequest>,
+) -> impl IntoResponse {
+    let caller = match account_token(&keys, &headers) {
+        Ok(token) => token,
+        Err((status, message)) => return error_response(status, message),
+    };
+
+    match AuthManager::new(Arc::clone(&db), Arc::clone(&keys))
+        .register_passkey(
+            &relying_party,
+            &caller.sub,
+            request.name,
+            &request.credential,
+        )
+        .await
+    {
+        Ok(passkey) => (StatusCode::CREATED, Json(passkey)).into_response(),
+        Err(e) => {
+            warn!("Passkey registration failed: {}", e);
+            error_response(StatusCode::BAD_REQUEST, e)
+        }
+    }
+}
+
+/// DELETE /auth/account/passkeys/:credential_id
+pub async fn remove_passkey(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    Extension(config): Extension<Arc<Configuration>>,
+    headers: HeaderMap,
+    Path(credential_id): Path<String>,
+) -> impl IntoResponse {
+    let caller = match account_token(&keys, &headers) {
+        Ok(token) => token,
+        Err((status, message)) => return error_response(status, message),
+    };
+
+    let auth_manager = AuthManager::new(Arc::clone(&db), Arc::clone(&keys));
+    match auth_manager
+        .second_factor_removable(
+            &caller.sub,
+            caller.is_admin,
+            config.auth.require_admin_mfa,
+            SecondFactor::Passkey,
+        )
+        .await
+    {
+        Ok(true) => {}
+        Ok(false) => {
+            return error_response(
+                StatusCode::FORBIDDEN,
+                "Two-factor authentication is required for admin accounts",
+            )
+        }
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, e),
+    }
+
+    match auth_manager
+        .remove_passkey(
+            &caller.sub,
+            caller.is_admin,
+            config.auth.require_admin_mfa,
+            &credential_id,
+        )
+        .await
+    {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => error_response(StatusCode::NOT_FOUND, "Passkey not found"),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e),
+    }
+}
+
+/// POST /auth/account/login/passkey/options
+///
+/// Answers with the options for `navigator.credentials.get()`.
+pub async fn login_options(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    Extension(relying_party): Extension<Arc<RelyingParty>>,
+    request: Option<Json<PasskeyLoginOptionsRequest>>,
+) -> impl IntoResponse {
+    let request = request.map(|Json(request)| request).unwrap_or_default();
+
+    match AuthManager::new(Arc::clone(&db), Arc::clone(&keys))
+        .passkey_login_options(&relying_party, request.mfa_token.as_deref())
+        .await
+    {
+        Ok(options) => (StatusCode::OK, Json(options)).into_response(),
+        Err(e) => error_response(StatusCode::BAD_REQUEST, e),
+    }
+}
+
+/// POST /auth/account/login/passkey
+pub async fn login(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    Extension(relying_party): Extension<Arc<RelyingParty>>,
+    Extension(proxies): Extension<Arc<TrustedProxies>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    Json(request): Json<PasskeyLoginRequest>,
+) -> impl IntoResponse {
+    let auth_manager = AuthManager::new(Arc::clone(&db), Arc::clone(&keys));
+    let client =
+        SessionClient::from_request(&headers, peer.map(|ConnectInfo(addr)| addr), &proxies);
+
+    let account = match auth_manager
+        .authenticate_with_passkey(
+            &relying_party,
+            &request.credential,
+            request.mfa_token.as_deref(),
+        )
+        .await
+    {
+        Ok(account) => account,
+        Err(e) => {
+            warn!("Passkey login failed: {}", e);
+            return error_response(StatusCode::UNAUTHORIZED, e);
+        }
+    };
+
+    match auth_manager.finish_account_login(account, &client).await {
+        Ok(response) => login_response(&auth_manager, response).await,
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e),
+    }
+}
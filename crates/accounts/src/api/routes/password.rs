@@ -0,0 +1,159 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Changing the password of the caller, and resetting a forgotten one with the token of the
+//! mailed link. Both sign out the other devices.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Extension, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use serde::Deserialize;
+use tracing::{error, info};
+
+use common::auth::account_token::AccountMailSettings;
+use common::auth::auth_manager::AuthManager;
+use common::auth::keys::KeyRing;
+use common::database::ArcDynDatabase;
+use common::mail::Mailer;
+
+use super::session::access_token;
+
+#[derive(Debug, Deserialize)]
+pub struct ForgotPasswordRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+/// Answers the same whether the address belongs to an account or not.
+pub async fn forgot_password(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    Extension(mailer): Extension<Arc<dyn Mailer>>,
+    Extension(account_mail): Extension<Arc<AccountMailSettings>>,
+    Json(request): Json<ForgotPasswordRequest>,
+) -> impl IntoResponse {
+    if let Err(e) = AuthManager::new(Arc::clone(&db), Arc::clone(&keys))
+        .request_password_reset(&mailer, &account_mail, request.email.trim())
+        .await
+    {
+        error!("Could not send password reset mail: {}", e);
+    }
+
+    (
+        StatusCode::ACCEPTED,
+        Json(serde_json::json!({"message": "A mail with a link to choose a new password is on its way if the address belongs to an account"})),
+    )
+        .into_response()
+}
+
+pub async fn reset_password(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    Extension(mailer): Extension<Arc<dyn Mailer>>,
+    Json(request): Json<ResetPasswordRequest>,
+) -> impl IntoResponse {
+    if request.password.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"error": "Password is required."})),
+        )
+            .into_response();
+    }
+
+    match AuthManager::new(Arc::clone(&db), Arc::clone(&keys))
+        .reset_password(&mailer, &request.token, &request.password)
+        .await
+    {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
+}
+
+/// Keeps the session of the calling token, all others are signed out.
+pub async fn change_password(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    Extension(mailer): Extension<Arc<dyn Mailer>>,
+    headers: HeaderMap,
+    Json(request): Json<ChangePasswordRequest>,
+) -> impl IntoResponse {
+    let caller = match access_token(&keys, &headers) {
+        Ok(token) => token,
+        Err(e) => {
+            return (
+                StatusCode::UNAUTHORIZED,
+                Json(serde_json::json!({"error": e.to_string()})),
+            )
+                .into_response()
+        }
+    };
+    if caller.role != "account" {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(serde_json::json!({"error": "Account token required"})),
+        )
+            .into_response();
+    }
+    if request.new_password.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"error": "Password is required."})),
+        )
+            .into_response();
+    }
+
+    match AuthManager::new(Arc::clone(&db), Arc::clone(&keys))
+        .change_password(
+            &mailer,
+            &caller.sub,
+            &request.current_password,
+            &request.new_password,
+            caller.session_id.as_deref(),
+        )
+        .await
+    {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => {
+            info!("Password change of account {} failed: {}", caller.sub, e);
+            (
+                StatusCode::FORBIDDEN,
+                Json(serde_json::json!({"error": e.to_string()})),
+            )
+                .into_response()
+        }
+    }
+}
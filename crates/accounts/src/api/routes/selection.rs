@@ -0,0 +1,478 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Downloads of arbitrary media selections, possibly across several albums.
+//!
+//! `POST /downloads` checks every item and stores the selection under a random id. The
+//! returned URL works without an `Authorization` header, so download managers can fetch
+//! it, and access is checked again for the requester on every request.
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use axum::{
+    extract::{Extension, Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+use tracing::error;
+use uuid::Uuid;
+
+use common::auth::keys::KeyRing;
+use common::auth::permissions::{has_album_permission, AlbumPermission};
+use common::config::configuration::Configuration;
+use common::database::download::{Download, DownloadContents, DownloadItem};
+use common::database::reference::ReferenceRole;
+use common::database::ArcDynDatabase;
+use common::jobs::{self, Job};
+use common::metadata::credit::template_for_album;
+use common::metadata::MetadataPolicy;
+use common::zip_cache::{
+    build_entries_to_file, zip_entries, zip_tmp_path, ZipCacheManager, ZipDelivery, ZipFormat,
+    ZipOptions, DEFAULT_ZIP_ROLES,
+};
+use common::zip_stream::StreamedZip;
+
+use super::customer::{extract_session, may_view_media_in};
+use super::download::{building_page, serve_cached_zip, serve_streamed_zip};
+use super::file_response::counts_as_download;
+use super::watermark::is_clean_viewer;
+
+/// Upper bound for the items of a single download.
+const MAX_ITEMS: usize = 5000;
+const DOWNLOAD_TTL_HOURS: i64 = 24;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateDownloadRequest {
+    pub media_ids: Vec<String>,
+    /// File roles to pack, the originals by default.
+    #[serde(default)]
+    pub roles: Vec<ReferenceRole>,
+    #[serde(default)]
+    pub format: ZipFormat,
+    /// `stream` or `cached`, overrides the configured `media.zip_delivery`.
+    pub delivery: Option<ZipDelivery>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DownloadQuery {
+    /// `stream` or `cached`, overrides the delivery chosen when the download was created.
+    pub delivery: Option<ZipDelivery>,
+}
+
+pub async fn create_download(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    Extension(config): Extension<Arc<Configuration>>,
+    headers: HeaderMap,
+    Json(req): Json<CreateDownloadRequest>,
+) -> impl IntoResponse {
+    let (caller_id, role) = match extract_session(&keys, &headers) {
+        Ok(p) => p,
+        Err(e) => {
+            return (
+                StatusCode::UNAUTHORIZED,
+                Json(serde_json::json!({"error": e.to_string()})),
+            )
+                .into_response()
+        }
+    };
+    if role != "account" && role != "customer" {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(serde_json::json!({"error": "Account or customer token required"})),
+        )
+            .into_response();
+    }
+
+    // Keep the caller's order, but pack every item only once.
+    let mut seen = HashSet::new();
+    let media_ids: Vec<String> = req
+        .media_ids
+        .into_iter()
+        .filter(|id| seen.insert(id.clone()))
+        .collect();
+    if media_ids.is_empty() || media_ids.len() > MAX_ITEMS {
+        let error = format!("Between 1 and {} media ids required", MAX_ITEMS);
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"error": error})),
+        )
+            .into_response();
+    }
+
+    let mut items = Vec::with_capacity(media_ids.len());
+    let mut denied = Vec::new();
+    for media_id in media_ids {
+        match authorized_album(&db, &caller_id, &role, &media_id).await {
+            Ok(Some(album_id)) => items.push(DownloadItem { media_id, album_id }),
+            Ok(None) => denied.push(media_id),
+            Err(status) => return status.into_response(),
+        }
+    }
+    if !denied.is_empty() {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(
+                serde_json::json!({"error": "No access to some media items", "media_ids": denied}),
+            ),
+        )
+            .into_response();
+    }
+
+    let mut roles = req.roles;
+    if roles.is_empty() {
+        roles = DEFAULT_ZIP_ROLES.to_vec();
+    }
+    roles.sort_by_key(|r| r.as_str());
+    roles.dedup();
+
+    let now = Utc::now();
+    let download = Download {
+        download_id: Uuid::new_v4().hyphenated().to_string(),
+        requester_id: caller_id,
+        requester_role: role,
+        contents: DownloadContents {
+            items,
+            roles,
+            format: req.format,
+            delivery: req.delivery,
+        },
+        created_at: now,
+        expires_at: now + Duration::hours(DOWNLOAD_TTL_HOURS),
+    };
+    let _ = db.delete_expired_downloads(now).await;
+    if let Err(e) = db.create_download(&download).await {
+        error!("Failed to store download: {:?}", e);
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": e.to_string()})),
+        )
+            .into_response();
+    }
+
+    // Cached ZIPs are built right away, so they are likely ready when the URL is opened.
+    if download
+        .contents
+        .delivery
+        .unwrap_or(config.media.zip_delivery)
+        == ZipDelivery::Cached
+    {
+        let db = db.clone();
+        let download = download.clone();
+        tokio::spawn(async move {
+            let Ok(entries) = selection_entries(&db, &download).await else {
+                return;
+            };
+            let path = ZipCacheManager::selection_zip_path(&entries).await;
+            if !path.exists() && !zip_tmp_path(&path).exists() {
+                if let Err(e) = build_entries_to_file(entries, &path).await {
+                    tracing::warn!(
+                        "ZIP build failed for download {}: {:?}",
+                        download.download_id,
+                        e
+                    );
+                }
+            }
+        });
+    }
+
+    (
+        StatusCode::CREATED,
+        Json(serde_json::json!({
+            "download_id": download.download_id,
+            "url": format!("/downloads/{}", download.download_id),
+            "expires_at": download.expires_at,
+            "media_count": download.contents.items.len(),
+        })),
+    )
+        .into_response()
+}
+
+pub async fn get_download(
+    State(db): State<ArcDynDatabase>,
+    Extension(config): Extension<Arc<Configuration>>,
+    headers: HeaderMap,
+    Path(download_id): Path<String>,
+    Query(query): Query<DownloadQuery>,
+) -> impl IntoResponse {
+    let download = match db.get_download(&download_id).await {
+        Ok(Some(download)) if download.is_expired() => {
+            return (
+                StatusCode::GONE,
+                Json(serde_json::json!({"error": "Download has expired"})),
+            )
+                .into_response()
+        }
+        Ok(Some(download)) => download,
+        Ok(None) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(serde_json::json!({"error": "Download not found"})),
+            )
+                .into_response()
+        }
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    // Access may have been revoked since the download was created.
+    for item in &download.contents.items {
+        match may_download_from(
+            &db,
+            &download.requester_id,
+            &download.requester_role,
+            &item.album_id,
+            &item.media_id,
+        )
+        .await
+        {
+            Ok(true) => {}
+            Ok(false) => return StatusCode::FORBIDDEN.into_response(),
+            Err(status) => return status.into_response(),
+        }
+    }
+
+    let entries = match selection_entries(&db, &download).await {
+        Ok(entries) => entries,
+        Err(status) => return status.into_response(),
+    };
+    let download_name = format!("download-{}", &download.download_id[..8]);
+    let delivery = query
+        .delivery
+        .or(download.contents.delivery)
+        .unwrap_or(config.media.zip_delivery);
+
+    let response = match delivery {
+        ZipDelivery::Stream => {
+            let zip = StreamedZip::plan(entries).await;
+            serve_streamed_zip(
+                &headers,
+                format!("download {}", download.download_id),
+                &download_name,
+                zip,
+            )
+        }
+        ZipDelivery::Cached => {
+            let path = ZipCacheManager::selection_zip_path(&entries).await;
+            if path.exists() {
+                serve_cached_zip(&headers, &download_name, &path).await
+            } else {
+                if !zip_tmp_path(&path).exists() {
+                    let download_id = download.download_id.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = build_entries_to_file(entries, &path).await {
+                            tracing::warn!(
+                                "ZIP build failed for download {}: {:?}",
+                                download_id,
+                                e
+                            );
+                        }
+                    });
+                }
+                building_page()
+            }
+        }
+    };
+    // The page shown while the ZIP is built is no download either.
+    if !counts_as_download(&response)
+        || response
+            .headers()
+            .get(axum::http::header::CONTENT_DISPOSITION)
+            .is_none()
+    {
+        return response;
+    }
+
+    // Record media downloads (queued — don't fail request on error)
+    for item in download.contents.items {
+        let job = Job::RecordMediaDownload {
+            media_id: item.media_id.clone(),
+            album_id: Some(item.album_id),
+            downloader_id: download.requester_id.clone(),
+            downloader_role: download.requester_role.clone(),
+        };
+        if let Err(e) = jobs::enqueue(&db, job).await {
+            tracing::warn!(
+                "Could not queue download of media {}: {:?}",
+                item.media_id,
+                e
+            );
+        }
+    }
+
+    response
+}
+
+/// The first album through which the caller may download `media_id`.
+async fn authorized_album(
+    db: &ArcDynDatabase,
+    caller_id: &str,
+    role: &str,
+    media_id: &str,
+) -> Result<Option<String>, StatusCode> {
+    let album_ids = db.get_album_ids_for_media(media_id).await.map_err(|e| {
+        error!("Failed to load albums of media {}: {:?}", media_id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    for album_id in album_ids {
+        if may_download_from(db, caller_id, role, &album_id, media_id).await? {
+            return Ok(Some(album_id));
+        }
+    }
+    Ok(None)
+}
+
+/// Whether the caller may download the original of `media_id` as part of `album_id`.
+/// Unpaid proofs are only delivered watermarked, never in a ZIP.
+async fn may_download_from(
+    db: &ArcDynDatabase,
+    caller_id: &str,
+    role: &str,
+    album_id: &str,
+    media_id: &str,
+) -> Result<bool, StatusCode> {
+    let has_access = may_view_media_in(db, caller_id, role, album_id, media_id).await?;
+    if !has_access {
+        return Ok(false);
+    }
+
+    match db.get_album_watermark(album_id).await {
+        Ok(Some(_)) => Ok(is_clean_viewer(db, album_id, caller_id, role).await),
+        Ok(None) => Ok(true),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+/// Entries of the ZIP, one folder per album. Each album's files are packed under its own
+/// metadata policy and copyright template unless the requester owns it.
+async fn selection_entries(
+    db: &ArcDynDatabase,
+    download: &Download,
+) -> Result<Vec<(String, PathBuf)>, StatusCode> {
+    let mut album_ids: Vec<&str> = Vec::new();
+    for item in &download.contents.items {
+        if !album_ids.contains(&item.album_id.as_str()) {
+            album_ids.push(&item.album_id);
+        }
+    }
+
+    let mut entries = Vec::new();
+    let mut folders = HashSet::new();
+    for album_id in album_ids {
+        let media = db
+            .get_media_for_album(album_id)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let items: Vec<_> = download
+            .contents
+            .items
+            .iter()
+            .filter(|item| item.album_id == album_id)
+            .filter_map(|item| media.iter().find(|m| m.uuid == item.media_id).cloned())
+            .collect();
+        let options = album_zip_options(db, album_id, download).await?;
+
+        let album_name = db
+            .get_album(album_id)
+            .await
+            .map(|a| a.name)
+            .unwrap_or_else(|_| album_id.to_string());
+        let mut folder = folder_name(&album_name);
+        let mut n = 2;
+        while !folders.insert(folder.clone()) {
+            folder = format!("{} ({})", folder_name(&album_name), n);
+            n += 1;
+        }
+
+        for (name, path) in zip_entries(&items, db, &options).await {
+            entries.push((format!("{}/{}", folder, name), path));
+        }
+    }
+    Ok(entries)
+}
+
+async fn album_zip_options(
+    db: &ArcDynDatabase,
+    album_id: &str,
+    download: &Download,
+) -> Result<ZipOptions, StatusCode> {
+    let is_owner = download.requester_role == "account"
+        && has_album_permission(db, &download.requester_id, album_id, AlbumPermission::Owner)
+            .await
+            .unwrap_or(false);
+    let (metadata_policy, copyright) = if is_owner {
+        (MetadataPolicy::Keep, None)
+    } else {
+        let policy = db
+            .get_album_metadata_policy(album_id)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let template = template_for_album(db, album_id)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        (policy, template)
+    };
+    Ok(ZipOptions {
+        roles: download.contents.roles.clone(),
+        format: download.contents.format,
+        metadata_policy,
+        copyright,
+    })
+}
+
+/// An album name usable as a folder inside a ZIP.
+fn folder_name(album_name: &str) -> String {
+    let name: String = album_name
+        .trim()
+        .chars()
+        .map(|c| {
+            if c == '/' || c == '\\' || c.is_control() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    match name.trim_matches('.') {
+        "" => "album".to_string(),
+        _ => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_folder_name() {
+        // given
+        let names = ["Wedding / Party", "  ", "..", "Summer\n2024"];
+
+        // when
+        let folders: Vec<String> = names.iter().map(|n| folder_name(n)).collect();
+
+        // then
+        assert_eq!(
+            folders,
+            vec!["Wedding _ Party", "album", "album", "Summer_2024"]
+        );
+    }
+}
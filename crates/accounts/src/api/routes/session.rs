@@ -0,0 +1,195 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Signed in devices of the caller, and signing them out one by one.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Extension, Path, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use chrono::Utc;
+use serde::Serialize;
+
+use common::auth::auth_manager::{AccessToken, AuthManager};
+use common::auth::keys::KeyRing;
+use common::auth::session::Session;
+use common::database::ArcDynDatabase;
+
+#[derive(Serialize)]
+pub struct SessionEntry {
+    #[serde(flatten)]
+    pub session: Session,
+    /// The session of the token that made this request.
+    pub current: bool,
+}
+
+pub(crate) fn access_token(
+    keys: &KeyRing,
+    headers: &HeaderMap,
+) -> Result<AccessToken, anyhow::Error> {
+    let token = headers
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .ok_or_else(|| anyhow::anyhow!("Missing or invalid Authorization header"))?;
+
+    AuthManager::decode_access_token(keys, token)
+}
+
+pub async fn list_sessions(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let caller = match access_token(&keys, &headers) {
+        Ok(token) => token,
+        Err(e) => {
+            return (
+                StatusCode::UNAUTHORIZED,
+                Json(serde_json::json!({"error": e.to_string()})),
+            )
+                .into_response()
+        }
+    };
+    if caller.role == "share" {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(serde_json::json!({"error": "Share tokens have no sessions"})),
+        )
+            .into_response();
+    }
+
+    match db
+        .list_sessions(&caller.sub, &caller.role, Utc::now())
+        .await
+    {
+        Ok(sessions) => {
+            let entries: Vec<SessionEntry> = sessions
+                .into_iter()
+                .map(|session| SessionEntry {
+                    current: caller.session_id.as_deref() == Some(session.session_id.as_str()),
+                    session,
+                })
+                .collect();
+            (StatusCode::OK, Json(entries)).into_response()
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn revoke_session(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    headers: HeaderMap,
+    Path(session_id): Path<String>,
+) -> impl IntoResponse {
+    let caller = match access_token(&keys, &headers) {
+        Ok(token) => token,
+        Err(e) => {
+            return (
+                StatusCode::UNAUTHORIZED,
+                Json(serde_json::json!({"error": e.to_string()})),
+            )
+                .into_response()
+        }
+    };
+
+    // Sessions of other users look the same as unknown ones.
+    match db.get_session(&session_id).await {
+        Ok(Some(session)) if session.subject_id == caller.sub && session.role == caller.role => {}
+        Ok(_) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(serde_json::json!({"error": "Session not found"})),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({"error": e.to_string()})),
+            )
+                .into_response()
+        }
+    }
+
+    match AuthManager::new(Arc::clone(&db), Arc::clone(&keys))
+        .revoke_session(&session_id)
+        .await
+    {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::auth::keys::JwtKey;
+    use common::auth::session::SessionClient;
+    use database::memory::MemoryDatabase;
+
+    fn keys() -> Arc<KeyRing> {
+        Arc::new(KeyRing::new(vec![JwtKey::new("test", b"test-secret")], None).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_refresh_session_of_deleted_account_should_fail() {
+        // given
+        let db: ArcDynDatabase = Arc::new(MemoryDatabase::new());
+        db.create_account(
+            "kept".into(),
+            "kept@example.org".into(),
+            "hash".into(),
+            None,
+        )
+        .await
+        .unwrap();
+        let auth = AuthManager::new(Arc::clone(&db), keys());
+        let client = SessionClient::default();
+        let kept = auth
+            .start_session("kept", "account", false, &client)
+            .await
+            .unwrap();
+        let gone = auth
+            .start_session("gone", "account", false, &client)
+            .await
+            .unwrap();
+
+        // when
+        let refreshed = auth.refresh_session(&kept.refresh_token, &client).await;
+        let refused = auth.refresh_session(&gone.refresh_token, &client).await;
+
+        // then
+        assert!(refreshed.is_ok());
+        assert!(refused.is_err());
+        let session = db.get_session(&gone.session_id).await.unwrap().unwrap();
+        assert!(session.revoked_at.is_some());
+    }
+}
@@ -0,0 +1,553 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Anonymous access to albums through public share links.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Extension, Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use tracing::{error, warn};
+
+use common::auth::auth_manager::AuthManager;
+use common::auth::keys::KeyRing;
+use common::config::configuration::Configuration;
+use common::database::share_link::ShareLink;
+use common::database::ArcDynDatabase;
+use common::jobs::{self, Job};
+use common::metadata::MetadataPolicy;
+use common::rendition::Rendition;
+use common::video::is_video;
+use common::zip_cache::{all_zip_options, ZipCacheManager, ZipDelivery};
+
+use super::copyright::copyright_for_viewer;
+use super::customer::{resolve_media_rendition, stored_content_hash};
+use super::download::serve_album_zip;
+use super::file_response::{counts_as_download, serve_file, CacheScope};
+use super::metadata_policy::{apply_policy, policy_for_viewer};
+use super::watermark::{payment_required, resolve_watermarked_rendition};
+
+#[derive(Debug, Deserialize)]
+pub struct UnlockShareRequest {
+    pub password: String,
+}
+
+/// Loads a share link and rejects unknown (404) or expired (410) tokens.
+async fn load_share_link(db: &ArcDynDatabase, token: &str) -> Result<ShareLink, Response> {
+    match db.get_share_link(token).await {
+        Ok(Some(link)) if link.is_expired() => Err((
+            StatusCode::GONE,
+            Json(serde_json::json!({ "error": "Share link has expired" })),
+        )
+            .into_response()),
+        Ok(Some(link)) => Ok(link),
+        Ok(None) => Err((
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({ "error": "Share link not found" })),
+        )
+            .into_response()),
+        Err(e) => {
+            error!("Failed to load share link: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
+}
+
+/// Password protected links require a share token obtained from `/share/:token/unlock`.
+/// Returns the rejection to send when the visitor is not allowed in.
+fn reject_visitor(keys: &KeyRing, link: &ShareLink, headers: &HeaderMap) -> Option<Response> {
+    if !link.is_password_protected() {
+        return None;
+    }
+
+    let share_token = headers
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .and_then(|token| AuthManager::validate_share_token(keys, token).ok());
+
+    match share_token {
+        Some(sub) if sub == link.token => None,
+        _ => Some(
+            (
+                StatusCode::UNAUTHORIZED,
+                Json(serde_json::json!({ "error": "password_required" })),
+            )
+                .into_response(),
+        ),
+    }
+}
+
+pub async fn get_share(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    headers: HeaderMap,
+    Path(token): Path<String>,
+) -> impl IntoResponse {
+    let link = match load_share_link(&db, &token).await {
+        Ok(link) => link,
+        Err(resp) => return resp,
+    };
+    if let Some(resp) = reject_visitor(&keys, &link, &headers) {
+        return resp;
+    }
+
+    let album = match db.get_album(&link.album_id).await {
+        Ok(album) => album,
+        Err(_) => return StatusCode::NOT_FOUND.into_response(),
+    };
+
+    match db.get_media_for_album(&link.album_id).await {
+        Ok(items) => {
+            // Record album view (queued — don't fail request on error)
+            let view = Job::RecordAlbumView {
+                album_id: link.album_id.clone(),
+                viewer_id: link.token.clone(),
+                viewer_role: "share".to_string(),
+            };
+            if let Err(e) = jobs::enqueue(&db, view).await {
+                warn!("Could not queue view of album {}: {:?}", link.album_id, e);
+            }
+
+            (
+                StatusCode::OK,
+                Json(serde_json::json!({
+                    "album_id": album.album_id,
+                    "name": album.name,
+                    "description": album.description,
+                    "allow_download": link.allow_download,
+                    "rendition": link.rendition,
+                    "expires_at": link.expires_at,
+                    "items": items,
+                })),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            error!(
+                "Failed to fetch media for shared album {}: {}",
+                link.album_id, e
+            );
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": "Failed to fetch media" })),
+            )
+                .into_response()
+        }
+    }
+}
+
+pub async fn unlock_share(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    Path(token): Path<String>,
+    Json(request): Json<UnlockShareRequest>,
+) -> impl IntoResponse {
+    let link = match load_share_link(&db, &token).await {
+        Ok(link) => link,
+        Err(resp) => return resp,
+    };
+
+    if let Some(hash) = &link.password_hash {
+        if !bcrypt::verify(&request.password, hash).unwrap_or(false) {
+            return (
+                StatusCode::UNAUTHORIZED,
+                Json(serde_json::json!({ "error": "Invalid password" })),
+            )
+                .into_response();
+        }
+    }
+
+    match AuthManager::generate_share_jwt(&keys, &link.token, link.expires_at) {
+        Ok((jwt_token, expires_in)) => (
+            StatusCode::OK,
+            Json(serde_json::json!({ "jwt_token": jwt_token, "expires_in": expires_in })),
+        )
+            .into_response(),
+        Err(e) => {
+            error!("Failed to generate share JWT: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub async fn get_share_media_file(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    headers: HeaderMap,
+    Path((token, media_id)): Path<(String, String)>,
+) -> impl IntoResponse {
+    let link = match load_share_link(&db, &token).await {
+        Ok(link) => link,
+        Err(resp) => return resp,
+    };
+    if let Some(resp) = reject_visitor(&keys, &link, &headers) {
+        return resp;
+    }
+
+    let in_album = db
+        .get_media_for_album(&link.album_id)
+        .await
+        .map(|items| items.iter().any(|m| m.uuid == media_id))
+        .unwrap_or(false);
+    if !in_album {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    // The link's rendition limits image sizes; videos are streamed as uploaded.
+    let is_video_item = db
+        .get_media_mime_type(&media_id)
+        .await
+        .ok()
+        .flatten()
+        .is_some_and(|mime_type| is_video(&mime_type));
+    let rendition = if is_video_item {
+        Rendition::Original
+    } else {
+        link.rendition.parse().unwrap_or_default()
+    };
+    // Share visitors never count as paid, proofs stay watermarked.
+    let watermark = match db.get_album_watermark(&link.album_id).await {
+        Ok(watermark) => watermark,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    let resolved = match &watermark {
+        Some(watermark) => {
+            resolve_watermarked_rendition(&db, &media_id, rendition, watermark).await
+        }
+        None => resolve_media_rendition(&db, &media_id, rendition).await,
+    };
+    let (path, content_type) = match resolved {
+        Ok(file) => file,
+        Err(status) => return status.into_response(),
+    };
+
+    let policy = if watermark.is_none() && rendition == Rendition::Original {
+        match policy_for_viewer(&db, &media_id, &link.token, "share").await {
+            Ok(policy) => policy,
+            Err(status) => return status.into_response(),
+        }
+    } else {
+        MetadataPolicy::Keep
+    };
+    let copyright = match copyright_for_viewer(&db, &link.album_id, &link.token, "share").await {
+        Ok(copyright) => copyright,
+        Err(status) => return status.into_response(),
+    };
+    let (path, content_type) =
+        match apply_policy(&media_id, path, content_type, policy, copyright.as_ref()).await {
+            Ok(file) => file,
+            Err(resp) => return resp,
+        };
+
+    // Unprotected links are public anyway, so shared caches may keep a copy.
+    let scope = if link.is_password_protected() {
+        CacheScope::Private
+    } else {
+        CacheScope::Public
+    };
+    let content_hash = stored_content_hash(&db, &media_id, &path).await;
+    let response = serve_file(
+        &headers,
+        &path,
+        &content_type,
+        scope,
+        content_hash.as_deref(),
+    )
+    .await;
+    if !counts_as_download(&response) {
+        return response;
+    }
+
+    // Record media download (queued — don't fail request on error)
+    let download = Job::RecordMediaDownload {
+        media_id: media_id.clone(),
+        album_id: Some(link.album_id),
+        downloader_id: link.token,
+        downloader_role: "share".to_string(),
+    };
+    if let Err(e) = jobs::enqueue(&db, download).await {
+        warn!("Could not queue download of media {}: {:?}", media_id, e);
+    }
+
+    response
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ShareDownloadQuery {
+    /// `stream` or `cached`, overrides the configured `media.zip_delivery`.
+    pub delivery: Option<ZipDelivery>,
+}
+
+pub async fn download_share_zip(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    Extension(config): Extension<Arc<Configuration>>,
+    headers: HeaderMap,
+    Path(token): Path<String>,
+    Query(query): Query<ShareDownloadQuery>,
+) -> impl IntoResponse {
+    let link = match load_share_link(&db, &token).await {
+        Ok(link) => link,
+        Err(resp) => return resp,
+    };
+    if let Some(resp) = reject_visitor(&keys, &link, &headers) {
+        return resp;
+    }
+
+    if !link.allow_download {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(serde_json::json!({ "error": "Downloads are disabled for this link" })),
+        )
+            .into_response();
+    }
+
+    match db.get_album_watermark(&link.album_id).await {
+        Ok(None) => {}
+        Ok(Some(_)) => return payment_required(),
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+
+    let album_name = db
+        .get_album(&link.album_id)
+        .await
+        .map(|a| a.name)
+        .unwrap_or_else(|_| link.album_id.clone());
+    let options = match all_zip_options(&link.album_id, &db).await {
+        Ok(options) => options,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    let cache_path =
+        ZipCacheManager::variant_path(ZipCacheManager::all_zip_path(&link.album_id), &options);
+
+    let delivery = query.delivery.unwrap_or(config.media.zip_delivery);
+
+    serve_album_zip(
+        &db,
+        &headers,
+        link.album_id,
+        &album_name,
+        vec![],
+        &options,
+        cache_path,
+        delivery,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, Utc};
+    use common::auth::keys::JwtKey;
+    use database::memory::MemoryDatabase;
+
+    use super::super::customer::get_customer_album_media;
+
+    fn keys() -> Arc<KeyRing> {
+        Arc::new(KeyRing::new(vec![JwtKey::new("test", b"test-secret")], None).unwrap())
+    }
+
+    /// An album with one item, shared through a link with the given password and expiry.
+    async fn shared_album(
+        password: Option<&str>,
+        expires_at: Option<chrono::DateTime<Utc>>,
+    ) -> (ArcDynDatabase, ShareLink) {
+        let db: ArcDynDatabase = Arc::new(MemoryDatabase::new());
+        let album_id = db.create_album("owner", "Wedding", None).await.unwrap();
+        let media_id = db
+            .create_media_item("owner", "DSC_0001", Utc::now())
+            .await
+            .unwrap();
+        db.add_media_to_album(&album_id, &media_id).await.unwrap();
+
+        let link = ShareLink {
+            token: ShareLink::generate_token(),
+            album_id,
+            created_by: "owner".into(),
+            password_hash: password.map(|p| bcrypt::hash(p, 4).unwrap()),
+            allow_download: false,
+            rendition: "large".into(),
+            expires_at,
+            created_at: Utc::now(),
+        };
+        db.create_share_link(&link).await.unwrap();
+
+        (db, link)
+    }
+
+    fn bearer(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "Authorization",
+            format!("Bearer {}", token).parse().unwrap(),
+        );
+        headers
+    }
+
+    async fn unlock(db: &ArcDynDatabase, token: &str, password: &str) -> Response {
+        let request = UnlockShareRequest {
+            password: password.into(),
+        };
+        unlock_share(
+            State(db.clone()),
+            Extension(keys()),
+            Path(token.to_string()),
+            Json(request),
+        )
+        .await
+        .into_response()
+    }
+
+    async fn json_body(response: Response) -> serde_json::Value {
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_unlock_share_with_password_should_succeed() {
+        // given
+        let (db, link) = shared_album(Some("secret"), None).await;
+
+        // when
+        let locked = get_share(
+            State(db.clone()),
+            Extension(keys()),
+            HeaderMap::new(),
+            Path(link.token.clone()),
+        )
+        .await
+        .into_response();
+        let wrong = unlock(&db, &link.token, "guess").await;
+        let unlocked = unlock(&db, &link.token, "secret").await;
+        let jwt = json_body(unlocked).await["jwt_token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let opened = get_share(
+            State(db.clone()),
+            Extension(keys()),
+            bearer(&jwt),
+            Path(link.token.clone()),
+        )
+        .await
+        .into_response();
+
+        // then
+        assert_eq!(locked.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(wrong.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(opened.status(), StatusCode::OK);
+        assert_eq!(
+            json_body(opened).await["items"].as_array().unwrap().len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_expired_share_should_fail() {
+        // given
+        let (db, link) =
+            shared_album(Some("secret"), Some(Utc::now() - Duration::minutes(1))).await;
+
+        // when
+        let opened = get_share(
+            State(db.clone()),
+            Extension(keys()),
+            HeaderMap::new(),
+            Path(link.token.clone()),
+        )
+        .await
+        .into_response();
+        let unlocked = unlock(&db, &link.token, "secret").await;
+
+        // then
+        assert_eq!(opened.status(), StatusCode::GONE);
+        assert_eq!(unlocked.status(), StatusCode::GONE);
+    }
+
+    #[tokio::test]
+    async fn test_get_revoked_share_should_fail() {
+        // given
+        let (db, link) = shared_album(Some("secret"), None).await;
+        let jwt = json_body(unlock(&db, &link.token, "secret").await).await["jwt_token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        // when
+        db.revoke_share_links(&link.album_id, Some(&link.token))
+            .await
+            .unwrap();
+        let opened = get_share(
+            State(db.clone()),
+            Extension(keys()),
+            bearer(&jwt),
+            Path(link.token.clone()),
+        )
+        .await
+        .into_response();
+
+        // then
+        assert_eq!(opened.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_share_token_for_other_album_should_fail() {
+        // given
+        let (db, link) = shared_album(Some("secret"), None).await;
+        let other_album = db.create_album("owner", "Private", None).await.unwrap();
+        let other_media = db
+            .create_media_item("owner", "DSC_0002", Utc::now())
+            .await
+            .unwrap();
+        db.add_media_to_album(&other_album, &other_media)
+            .await
+            .unwrap();
+        let jwt = json_body(unlock(&db, &link.token, "secret").await).await["jwt_token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        // when
+        let media = get_share_media_file(
+            State(db.clone()),
+            Extension(keys()),
+            bearer(&jwt),
+            Path((link.token.clone(), other_media.clone())),
+        )
+        .await
+        .into_response();
+        let album = get_customer_album_media(
+            State(db.clone()),
+            Extension(keys()),
+            bearer(&jwt),
+            Path(other_album.clone()),
+        )
+        .await
+        .into_response();
+
+        // then
+        assert_eq!(media.status(), StatusCode::NOT_FOUND);
+        assert_eq!(album.status(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,309 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Extension, Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use chrono::Duration;
+use serde::Deserialize;
+
+use common::auth::auth_manager::AuthManager;
+use common::auth::keys::KeyRing;
+use common::auth::signed_url::{
+    sign_media_url, verify_media_url, SignedMediaQuery, DEFAULT_TTL_SECONDS,
+};
+use common::database::ArcDynDatabase;
+use common::jobs::{self, Job};
+use common::metadata::MetadataPolicy;
+use common::rendition::Rendition;
+
+use super::copyright::copyright_for_viewer;
+use super::customer::{
+    extract_access_token, resolve_media_rendition, stored_content_hash, viewing_album,
+};
+use super::file_response::{counts_as_download, serve_file, CacheScope};
+use super::metadata_policy::{apply_policy, policy_for_viewer};
+use super::watermark::{resolve_watermarked_rendition, watermark_for_viewer};
+
+#[derive(Debug, Deserialize)]
+pub struct SignedUrlRequest {
+    #[serde(default)]
+    pub rendition: Rendition,
+    /// The album the media is viewed in, which decides e.g. the embedded copyright.
+    pub album_id: Option<String>,
+}
+
+/// Issues a signed URL for a media item that can be used without an `Authorization` header.
+/// The URL stops working when the caller's session ends or the caller loses access.
+pub async fn get_media_signed_url(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    headers: HeaderMap,
+    Path(media_id): Path<String>,
+    Query(request): Query<SignedUrlRequest>,
+) -> impl IntoResponse {
+    // Share tokens are refused here, share visitors load media through their link.
+    let token = match extract_access_token(&keys, &headers) {
+        Ok(token) => token,
+        Err(e) => {
+            return (
+                StatusCode::UNAUTHORIZED,
+                Json(serde_json::json!({ "error": e.to_string() })),
+            )
+                .into_response()
+        }
+    };
+    let album_id = match viewing_album(
+        &db,
+        &token.sub,
+        &token.role,
+        &media_id,
+        request.album_id.as_deref(),
+    )
+    .await
+    {
+        Ok(Some(album_id)) => album_id,
+        Ok(None) => return StatusCode::FORBIDDEN.into_response(),
+        Err(status) => return status.into_response(),
+    };
+
+    let (url, expires_at) = sign_media_url(
+        &keys,
+        &media_id,
+        request.rendition,
+        &token.sub,
+        &token.role,
+        token.session_id.as_deref(),
+        Some(&album_id),
+        Duration::seconds(DEFAULT_TTL_SECONDS),
+    );
+
+    (
+        StatusCode::OK,
+        Json(serde_json::json!({ "url": url, "expires_at": expires_at })),
+    )
+        .into_response()
+}
+
+/// Serves a media file addressed by a signed URL.
+pub async fn get_signed_media_file(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    headers: HeaderMap,
+    Path(media_id): Path<String>,
+    Query(query): Query<SignedMediaQuery>,
+) -> impl IntoResponse {
+    let access = match verify_media_url(&keys, &media_id, &query) {
+        Ok(access) => access,
+        Err(e) => {
+            return (
+                StatusCode::FORBIDDEN,
+                Json(serde_json::json!({ "error": e.to_string() })),
+            )
+                .into_response()
+        }
+    };
+    if let Some(session_id) = &access.session_id {
+        match AuthManager::new(db.clone(), Arc::clone(&keys))
+            .active_session(session_id)
+            .await
+        {
+            Ok(Some(_)) => {}
+            Ok(None) => return StatusCode::FORBIDDEN.into_response(),
+            Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        }
+    }
+    let album_id = match viewing_album(
+        &db,
+        &access.sub,
+        &access.role,
+        &media_id,
+        access.album_id.as_deref(),
+    )
+    .await
+    {
+        Ok(Some(album_id)) => album_id,
+        Ok(None) => return StatusCode::FORBIDDEN.into_response(),
+        Err(status) => return status.into_response(),
+    };
+
+    let resolved = match watermark_for_viewer(&db, &album_id, &access.sub, &access.role).await {
+        Ok(Some(watermark)) => {
+            resolve_watermarked_rendition(&db, &media_id, access.rendition, &watermark).await
+        }
+        Ok(None) => resolve_media_rendition(&db, &media_id, access.rendition).await,
+        Err(status) => Err(status),
+    };
+    let (path, content_type) = match resolved {
+        Ok(file) => file,
+        Err(status) => return status.into_response(),
+    };
+
+    // Renditions are re-encoded without metadata, only originals need stripping.
+    let policy = if access.rendition == Rendition::Original {
+        match policy_for_viewer(&db, &media_id, &access.sub, &access.role).await {
+            Ok(policy) => policy,
+            Err(status) => return status.into_response(),
+        }
+    } else {
+        MetadataPolicy::Keep
+    };
+    let copyright = match copyright_for_viewer(&db, &album_id, &access.sub, &access.role).await {
+        Ok(copyright) => copyright,
+        Err(status) => return status.into_response(),
+    };
+    let (path, content_type) =
+        match apply_policy(&media_id, path, content_type, policy, copyright.as_ref()).await {
+            Ok(file) => file,
+            Err(resp) => return resp,
+        };
+
+    let content_hash = stored_content_hash(&db, &media_id, &path).await;
+    let response = serve_file(
+        &headers,
+        &path,
+        &content_type,
+        CacheScope::Private,
+        content_hash.as_deref(),
+    )
+    .await;
+    if !counts_as_download(&response) {
+        return response;
+    }
+
+    // Record media download (queued — don't fail request on error)
+    let download = Job::RecordMediaDownload {
+        media_id: media_id.clone(),
+        album_id: Some(album_id),
+        downloader_id: access.sub,
+        downloader_role: access.role,
+    };
+    if let Err(e) = jobs::enqueue(&db, download).await {
+        tracing::warn!("Could not queue download of media {}: {:?}", media_id, e);
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use common::auth::keys::JwtKey;
+    use common::auth::session::Session;
+    use database::memory::MemoryDatabase;
+    use std::sync::Arc;
+
+    fn keys() -> Arc<KeyRing> {
+        Arc::new(KeyRing::new(vec![JwtKey::new("test", b"test-secret")], None).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_signed_url_of_revoked_session_should_fail() {
+        // given
+        let db: ArcDynDatabase = Arc::new(MemoryDatabase::new());
+        let album_id = db.create_album("owner", "Wedding", None).await.unwrap();
+        let media_id = db
+            .create_media_item("owner", "DSC_0001", Utc::now())
+            .await
+            .unwrap();
+        let foreign_album = db
+            .create_album("someone-else", "Private", None)
+            .await
+            .unwrap();
+        let foreign_media = db
+            .create_media_item("someone-else", "DSC_0002", Utc::now())
+            .await
+            .unwrap();
+        db.add_media_to_album(&album_id, &media_id).await.unwrap();
+        db.add_media_to_album(&foreign_album, &foreign_media)
+            .await
+            .unwrap();
+        let now = Utc::now();
+        db.create_session(&Session {
+            session_id: "session".into(),
+            subject_id: "owner".into(),
+            role: "account".into(),
+            refresh_token_hash: "hash".into(),
+            previous_token_hash: None,
+            user_agent: None,
+            ip_address: None,
+            created_at: now,
+            last_used_at: now,
+            expires_at: now + Duration::days(1),
+            revoked_at: None,
+        })
+        .await
+        .unwrap();
+        let token =
+            AuthManager::generate_access_token(&keys(), "owner", "account", false, "session")
+                .unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "Authorization",
+            format!("Bearer {}", token).parse().unwrap(),
+        );
+        let rendition = || {
+            Query(SignedUrlRequest {
+                rendition: Rendition::Small,
+                album_id: None,
+            })
+        };
+
+        // when
+        let foreign = get_media_signed_url(
+            State(db.clone()),
+            Extension(keys()),
+            headers.clone(),
+            Path(foreign_media),
+            rendition(),
+        )
+        .await
+        .into_response();
+        let signed = get_media_signed_url(
+            State(db.clone()),
+            Extension(keys()),
+            headers,
+            Path(media_id.clone()),
+            rendition(),
+        )
+        .await
+        .into_response();
+        let body = hyper::body::to_bytes(signed.into_body()).await.unwrap();
+        let url: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let (_, query) = url["url"].as_str().unwrap().split_once('?').unwrap();
+        db.revoke_session("session", Utc::now()).await.unwrap();
+        let file = get_signed_media_file(
+            State(db.clone()),
+            Extension(keys()),
+            HeaderMap::new(),
+            Path(media_id),
+            Query(serde_urlencoded::from_str(query).unwrap()),
+        )
+        .await
+        .into_response();
+
+        // then
+        assert_eq!(foreign.status(), StatusCode::FORBIDDEN);
+        assert_eq!(file.status(), StatusCode::FORBIDDEN);
+    }
+}
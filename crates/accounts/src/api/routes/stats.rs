@@ -15,12 +15,15 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use std::sync::Arc;
+
 use axum::{
-    extract::{Path, State},
+    extract::{Extension, Path, State},
     http::{HeaderMap, StatusCode},
     response::IntoResponse,
     Json,
 };
+use common::auth::keys::KeyRing;
 use common::{
     auth::permissions::{has_album_permission, AlbumPermission},
     database::ArcDynDatabase,
@@ -30,10 +33,11 @@ use super::customer::extract_session;
 
 pub async fn get_album_stats(
     State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
     headers: HeaderMap,
     Path(album_id): Path<String>,
 ) -> impl IntoResponse {
-    let (caller_id, role) = match extract_session(&headers) {
+    let (caller_id, role) = match extract_session(&keys, &headers) {
         Ok(p) => p,
         Err(e) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": e.to_string()}))).into_response(),
     };
@@ -51,9 +55,10 @@ pub async fn get_album_stats(
 
 pub async fn get_owned_album_stats(
     State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
     headers: HeaderMap,
 ) -> impl IntoResponse {
-    let (caller_id, role) = match extract_session(&headers) {
+    let (caller_id, role) = match extract_session(&keys, &headers) {
         Ok(p) => p,
         Err(e) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": e.to_string()}))).into_response(),
     };
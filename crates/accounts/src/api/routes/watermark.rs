@@ -0,0 +1,450 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Watermark settings of proof galleries and who gets to see clean images.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use axum::{
+    extract::{Extension, Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64::Engine;
+use chrono::Utc;
+use serde::Deserialize;
+use tracing::error;
+
+use common::auth::keys::KeyRing;
+use common::auth::permissions::{has_album_permission, AlbumPermission};
+use common::database::watermark::{AlbumWatermark, WatermarkKind, WatermarkPosition};
+use common::database::ArcDynDatabase;
+use common::media_type::detect_mime_type;
+use common::rendition::Rendition;
+use common::watermark::{get_or_create_watermarked, image_path};
+
+use super::customer::{extract_session, resolve_media_rendition};
+use super::metadata_policy::require_owner;
+
+#[derive(Debug, Deserialize)]
+pub struct WatermarkRequest {
+    #[serde(default)]
+    pub kind: WatermarkKind,
+    pub text: Option<String>,
+    /// Base64 encoded PNG, required for image watermarks unless one was uploaded before.
+    pub image: Option<String>,
+    #[serde(default)]
+    pub position: WatermarkPosition,
+    #[serde(default = "default_opacity")]
+    pub opacity: f64,
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+}
+
+fn default_opacity() -> f64 {
+    0.5
+}
+
+fn default_scale() -> f64 {
+    0.25
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CustomerPaidRequest {
+    pub paid: bool,
+}
+
+pub async fn get_album_watermark(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    headers: HeaderMap,
+    Path(album_id): Path<String>,
+) -> impl IntoResponse {
+    if let Some(resp) = require_owner(&db, &keys, &headers, &album_id).await {
+        return resp;
+    }
+    match db.get_album_watermark(&album_id).await {
+        Ok(Some(watermark)) => (StatusCode::OK, Json(watermark)).into_response(),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error": "Album has no watermark"})),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn put_album_watermark(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    headers: HeaderMap,
+    Path(album_id): Path<String>,
+    Json(req): Json<WatermarkRequest>,
+) -> impl IntoResponse {
+    if let Some(resp) = require_owner(&db, &keys, &headers, &album_id).await {
+        return resp;
+    }
+    if !(0.0..=1.0).contains(&req.opacity) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"error": "opacity must be between 0 and 1"})),
+        )
+            .into_response();
+    }
+    if !(req.scale > 0.0 && req.scale <= 1.0) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"error": "scale must be greater than 0 and at most 1"})),
+        )
+            .into_response();
+    }
+
+    let image = match (req.kind, req.image.as_deref()) {
+        (WatermarkKind::Text, _) => {
+            if req.text.as_deref().is_none_or(|t| t.trim().is_empty()) {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(serde_json::json!({"error": "text is required for text watermarks"})),
+                )
+                    .into_response();
+            }
+            None
+        }
+        (WatermarkKind::Image, Some(encoded)) => {
+            let bytes = match base64::engine::general_purpose::STANDARD.decode(encoded) {
+                Ok(bytes) => bytes,
+                Err(_) => {
+                    return (
+                        StatusCode::BAD_REQUEST,
+                        Json(serde_json::json!({"error": "image must be base64 encoded"})),
+                    )
+                        .into_response()
+                }
+            };
+            if detect_mime_type(&bytes) != Some("image/png") {
+                return (
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    Json(serde_json::json!({"error": "image must be a PNG"})),
+                )
+                    .into_response();
+            }
+            Some(bytes)
+        }
+        (WatermarkKind::Image, None) => {
+            if !image_path(&album_id).exists() {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(serde_json::json!({"error": "image is required for image watermarks"})),
+                )
+                    .into_response();
+            }
+            None
+        }
+    };
+
+    let path = image_path(&album_id);
+    if let Some(bytes) = image {
+        if let Err(e) = store_image(&path, &bytes).await {
+            error!(
+                "Failed to store watermark image for album {}: {:?}",
+                album_id, e
+            );
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({"error": e.to_string()})),
+            )
+                .into_response();
+        }
+    }
+
+    let watermark = AlbumWatermark {
+        album_id: album_id.clone(),
+        kind: req.kind,
+        text: req.text,
+        image_path: (req.kind == WatermarkKind::Image).then(|| path.to_string_lossy().to_string()),
+        position: req.position,
+        opacity: req.opacity,
+        scale: req.scale,
+        updated_at: Utc::now(),
+    };
+    match db.set_album_watermark(&watermark).await {
+        Ok(_) => (StatusCode::OK, Json(watermark)).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn delete_album_watermark(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    headers: HeaderMap,
+    Path(album_id): Path<String>,
+) -> impl IntoResponse {
+    if let Some(resp) = require_owner(&db, &keys, &headers, &album_id).await {
+        return resp;
+    }
+    match db.delete_album_watermark(&album_id).await {
+        Ok(_) => {
+            let _ = tokio::fs::remove_file(image_path(&album_id)).await;
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
+}
+
+/// Lifts watermarks for a customer once the invoice is paid, or puts them back.
+pub async fn set_customer_paid(
+    State(db): State<ArcDynDatabase>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    headers: HeaderMap,
+    Path(customer_id): Path<String>,
+    Json(req): Json<CustomerPaidRequest>,
+) -> impl IntoResponse {
+    let (account_id, role) = match extract_session(&keys, &headers) {
+        Ok(pair) => pair,
+        Err(e) => {
+            return (
+                StatusCode::UNAUTHORIZED,
+                Json(serde_json::json!({"error": e.to_string()})),
+            )
+                .into_response()
+        }
+    };
+    if role != "account" || !db.is_account_admin(&account_id).await.unwrap_or(false) {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(serde_json::json!({"error": "Admin access required"})),
+        )
+            .into_response();
+    }
+    if db.get_customer(&customer_id).await.is_err() {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error": "Customer not found"})),
+        )
+            .into_response();
+    }
+    match db.set_customer_paid(&customer_id, req.paid).await {
+        Ok(_) => (
+            StatusCode::OK,
+            Json(serde_json::json!({"customer_id": customer_id, "paid": req.paid})),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
+}
+
+async fn store_image(path: &std::path::Path, bytes: &[u8]) -> std::io::Result<()> {
+    let parent = path.parent().unwrap_or_else(|| std::path::Path::new("."));
+    tokio::fs::create_dir_all(parent).await?;
+    let tmp = tempfile::NamedTempFile::new_in(parent)?.into_temp_path();
+    tokio::fs::write(&tmp, bytes).await?;
+    tmp.persist(path).map_err(|e| e.error)
+}
+
+/// Whether the customer paid and is assigned the album.
+async fn paid_for_album(db: &ArcDynDatabase, customer_id: &str, album_id: &str) -> bool {
+    db.get_customer(customer_id).await.is_ok_and(|c| c.paid)
+        && db
+            .get_albums_for_customer(customer_id)
+            .await
+            .is_ok_and(|albums| albums.iter().any(|a| a.album_id == album_id))
+}
+
+/// Whether the viewer may see the album without watermark: album owners, and customers or
+/// accounts linked to a customer who paid for this album. Share visitors never are.
+pub(crate) async fn is_clean_viewer(
+    db: &ArcDynDatabase,
+    album_id: &str,
+    viewer_id: &str,
+    viewer_role: &str,
+) -> bool {
+    match viewer_role {
+        "account" => {
+            if has_album_permission(db, viewer_id, album_id, AlbumPermission::Owner)
+                .await
+                .unwrap_or(false)
+            {
+                return true;
+            }
+            for customer_id in db
+                .get_customer_ids_for_account(viewer_id)
+                .await
+                .unwrap_or_default()
+            {
+                if paid_for_album(db, &customer_id, album_id).await {
+                    return true;
+                }
+            }
+            false
+        }
+        "customer" => paid_for_album(db, viewer_id, album_id).await,
+        _ => false,
+    }
+}
+
+/// The watermark of the album being viewed to stamp on its media for this viewer, `None` when
+/// they see it clean. Fails closed: if the settings cannot be loaded the request fails instead
+/// of leaking originals.
+pub(crate) async fn watermark_for_viewer(
+    db: &ArcDynDatabase,
+    album_id: &str,
+    viewer_id: &str,
+    viewer_role: &str,
+) -> Result<Option<AlbumWatermark>, StatusCode> {
+    let watermark = match db.get_album_watermark(album_id).await {
+        Ok(Some(watermark)) => watermark,
+        Ok(None) => return Ok(None),
+        Err(e) => {
+            error!("Failed to load watermark for album {}: {:?}", album_id, e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    if is_clean_viewer(db, album_id, viewer_id, viewer_role).await {
+        Ok(None)
+    } else {
+        Ok(Some(watermark))
+    }
+}
+
+/// Locates the watermarked rendition of a media item. Originals are never handed out with a
+/// watermark, the large preview is served instead.
+pub(crate) async fn resolve_watermarked_rendition(
+    db: &ArcDynDatabase,
+    media_id: &str,
+    rendition: Rendition,
+    watermark: &AlbumWatermark,
+) -> Result<(PathBuf, String), StatusCode> {
+    let rendition = match rendition {
+        Rendition::Original | Rendition::Full => Rendition::Large,
+        other => other,
+    };
+    let (source, _) = resolve_media_rendition(db, media_id, rendition).await?;
+
+    let path = get_or_create_watermarked(&source, media_id, rendition, watermark)
+        .await
+        .map_err(|e| {
+            error!(
+                "Failed to watermark {} of media {}: {:?}",
+                rendition, media_id, e
+            );
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok((path, "image/jpeg".to_string()))
+}
+
+/// Response for clean downloads that are locked until the customer paid.
+pub(crate) fn payment_required() -> Response {
+    (
+        StatusCode::PAYMENT_REQUIRED,
+        Json(serde_json::json!({"error": "Clean downloads are unlocked once the gallery is paid"})),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use database::memory::MemoryDatabase;
+
+    #[tokio::test]
+    async fn test_account_of_customer_paid_for_one_album_should_see_only_that_album_clean() {
+        // given
+        let db: ArcDynDatabase = Arc::new(MemoryDatabase::new());
+        let paid_album = db.create_album("owner", "Wedding", None).await.unwrap();
+        let unpaid_album = db.create_album("owner", "Engagement", None).await.unwrap();
+        for (customer_id, album_id, paid) in [
+            ("paid-customer", &paid_album, true),
+            ("unpaid-customer", &unpaid_album, false),
+        ] {
+            db.create_customer(customer_id.into(), customer_id.into(), "Jane".into())
+                .await
+                .unwrap();
+            db.assign_album_to_customer(album_id, customer_id)
+                .await
+                .unwrap();
+            db.set_customer_paid(customer_id, paid).await.unwrap();
+            db.link_access_code_to_account("jane", customer_id)
+                .await
+                .unwrap();
+        }
+
+        // when
+        let paid_clean = is_clean_viewer(&db, &paid_album, "jane", "account").await;
+        let unpaid_clean = is_clean_viewer(&db, &unpaid_album, "jane", "account").await;
+        let customer_clean = is_clean_viewer(&db, &unpaid_album, "paid-customer", "customer").await;
+
+        // then
+        assert!(paid_clean);
+        assert!(!unpaid_clean);
+        assert!(!customer_clean);
+    }
+    #[tokio::test]
+    async fn test_media_in_two_albums_should_get_watermark_of_viewed_album() {
+        // given
+        let db: ArcDynDatabase = Arc::new(MemoryDatabase::new());
+        let media_id = db
+            .create_media_item("owner", "DSC_0001", Utc::now())
+            .await
+            .unwrap();
+        let mut album_ids = Vec::new();
+        for (name, age) in [("Wedding", 1), ("Engagement", 0)] {
+            let album_id = db.create_album("owner", name, None).await.unwrap();
+            album_ids.push(album_id.clone());
+            db.add_media_to_album(&album_id, &media_id).await.unwrap();
+            db.set_album_watermark(&AlbumWatermark {
+                album_id,
+                kind: WatermarkKind::Text,
+                text: Some(name.to_string()),
+                image_path: None,
+                position: Default::default(),
+                opacity: 0.5,
+                scale: 0.3,
+                updated_at: Utc::now() - chrono::Duration::hours(age),
+            })
+            .await
+            .unwrap();
+        }
+
+        // when
+        let watermark = watermark_for_viewer(&db, &album_ids[0], "visitor", "share")
+            .await
+            .unwrap();
+
+        // then
+        assert_eq!(watermark.unwrap().text.as_deref(), Some("Wedding"));
+    }
+}
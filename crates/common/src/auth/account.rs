@@ -12,4 +12,7 @@ pub struct Account {
     pub updated_at: Option<DateTime<Utc>>,
     pub last_login_at: Option<DateTime<Utc>>,
     pub is_admin: bool,
+    /// Missing until the owner followed the link of a verification or password reset mail.
+    #[sqlx(default)]
+    pub email_verified_at: Option<DateTime<Utc>>,
 }
@@ -0,0 +1,479 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Tokens mailed to account holders: verifying the address of new accounts and resetting
+//! forgotten passwords. Password changes sign out the other devices and are announced by mail.
+//!
+//! Mails are worded by the studio of the account, the owner of the albums its access codes
+//! open, falling back to the built-in templates.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, LazyLock, RwLock};
+
+use anyhow::anyhow;
+use chrono::{DateTime, Duration, Utc};
+use sha2::{Digest, Sha256};
+use tracing::{error, info, warn};
+
+use crate::auth::account::Account;
+use crate::auth::auth_manager::AuthManager;
+use crate::database::mail_template::{MailKind, MailTemplate};
+use crate::mail::{Mail, Mailer};
+
+/// Lifetime of the link in verification mails.
+pub const EMAIL_VERIFICATION_TTL_HOURS: i64 = 48;
+
+/// Lifetime of the link in password reset mails.
+pub const PASSWORD_RESET_TTL_MINUTES: i64 = 60;
+
+/// Mails of one kind are sent to an account at most once within this many seconds.
+const MAIL_INTERVAL_SECONDS: i64 = 60;
+
+/// Signs mails of accounts without a studio.
+const DEFAULT_STUDIO_NAME: &str = "Photos.network";
+
+/// When each account was last mailed, by kind and account id.
+static RECENT_MAILS: LazyLock<RwLock<HashMap<String, DateTime<Utc>>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// Links and policy of the mails to account holders, from the `mail` section.
+#[derive(Debug, Clone)]
+pub struct AccountMailSettings {
+    /// Link of verification mails, `{token}` is replaced by the token.
+    pub verify_email_url: String,
+    /// Link of password reset mails, `{token}` is replaced by the token.
+    pub password_reset_url: String,
+    pub require_verified_email: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenPurpose {
+    VerifyEmail,
+    PasswordReset,
+}
+
+impl TokenPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenPurpose::VerifyEmail => "verify_email",
+            TokenPurpose::PasswordReset => "password_reset",
+        }
+    }
+}
+
+/// A mailed token, stored as hash. It is used up by the first request presenting it.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct AccountToken {
+    pub token_hash: String,
+    pub account_id: String,
+    pub purpose: String,
+    /// The address the token was sent to, it only works while the account still has it.
+    pub email: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+}
+
+/// Refuses password logins while `require_verified_email` is set and the address was not verified,
+/// and sign-ins through an external provider into accounts with an unverified address.
+#[derive(Debug)]
+pub struct EmailNotVerified;
+
+impl fmt::Display for EmailNotVerified {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Email address is not verified")
+    }
+}
+
+impl std::error::Error for EmailNotVerified {}
+
+impl AuthManager {
+    /// Mails a link verifying the address of the account, unless it is verified already.
+    pub async fn send_email_verification(
+        &self,
+        mailer: &Arc<dyn Mailer>,
+        settings: &AccountMailSettings,
+        account: &Account,
+    ) -> Result<(), anyhow::Error> {
+        if account.email_verified_at.is_some()
+            || !Self::may_mail(MailKind::VerifyEmail, &account.account_id)
+        {
+            return Ok(());
+        }
+
+        let token = self
+            .issue_account_token(
+                account,
+                TokenPurpose::VerifyEmail,
+                Duration::hours(EMAIL_VERIFICATION_TTL_HOURS),
+            )
+            .await?;
+        let link = settings.verify_email_url.replace("{token}", &token);
+        self.mail_account(
+            mailer,
+            account,
+            MailKind::VerifyEmail,
+            &link,
+            &format!("{} hours", EMAIL_VERIFICATION_TTL_HOURS),
+        )
+        .await
+    }
+
+    /// Sends another verification mail. Unknown addresses are ignored, not to reveal which
+    /// accounts exist.
+    pub async fn resend_email_verification(
+        &self,
+        mailer: &Arc<dyn Mailer>,
+        settings: &AccountMailSettings,
+        email: &str,
+    ) -> Result<(), anyhow::Error> {
+        match self.db.get_account_by_email(email).await {
+            Ok(account) => {
+                self.send_email_verification(mailer, settings, &account)
+                    .await
+            }
+            Err(_) => Ok(()),
+        }
+    }
+
+    pub async fn verify_email(&self, token: &str) -> Result<Account, anyhow::Error> {
+        let (account, _) = self
+            .redeem_account_token(token, TokenPurpose::VerifyEmail)
+            .await?;
+        self.db
+            .set_email_verified(&account.account_id, Utc::now())
+            .await?;
+
+        info!("Verified email address of account {}", account.account_id);
+        Ok(account)
+    }
+
+    /// Mails a link to choose a new password. Unknown addresses are ignored, not to reveal
+    /// which accounts exist.
+    pub async fn request_password_reset(
+        &self,
+        mailer: &Arc<dyn Mailer>,
+        settings: &AccountMailSettings,
+        email: &str,
+    ) -> Result<(), anyhow::Error> {
+        let account = match self.db.get_account_by_email(email).await {
+            Ok(account) => account,
+            Err(_) => {
+                info!("Password reset requested for unknown address");
+                return Ok(());
+            }
+        };
+        if !Self::may_mail(MailKind::PasswordReset, &account.account_id) {
+            return Ok(());
+        }
+
+        let token = self
+            .issue_account_token(
+                &account,
+                TokenPurpose::PasswordReset,
+                Duration::minutes(PASSWORD_RESET_TTL_MINUTES),
+            )
+            .await?;
+        let link = settings.password_reset_url.replace("{token}", &token);
+        self.mail_account(
+            mailer,
+            &account,
+            MailKind::PasswordReset,
+            &link,
+            &format!("{} minutes", PASSWORD_RESET_TTL_MINUTES),
+        )
+        .await
+    }
+
+    /// Sets a new password with the token of a reset mail and signs out all devices. Since the
+    /// mail arrived, the address counts as verified. A second factor is still asked on login.
+    pub async fn reset_password(
+        &self,
+        mailer: &Arc<dyn Mailer>,
+        token: &str,
+        new_password: &str,
+    ) -> Result<(), anyhow::Error> {
+        Self::check_new_password(new_password)?;
+        let (account, now) = self
+            .redeem_account_token(token, TokenPurpose::PasswordReset)
+            .await?;
+
+        self.store_password(&account, new_password, now).await?;
+        if account.email_verified_at.is_none() {
+            self.db.set_email_verified(&account.account_id, now).await?;
+        }
+        let revoked = self
+            .revoke_account_sessions(&account.account_id, None)
+            .await?;
+
+        info!(
+            "Reset password of account {}, signed out {} sessions",
+            account.account_id, revoked
+        );
+        self.mail_account(mailer, &account, MailKind::PasswordChanged, "", "")
+            .await
+    }
+
+    /// Changes the password after checking the current one. All sessions but `keep_session`,
+    /// usually the one asking, are signed out.
+    pub async fn change_password(
+        &self,
+        mailer: &Arc<dyn Mailer>,
+        account_id: &str,
+        current_password: &str,
+        new_password: &str,
+        keep_session: Option<&str>,
+    ) -> Result<(), anyhow::Error> {
+        Self::check_new_password(new_password)?;
+        let account = self.db.get_account_by_id(account_id).await?;
+        let valid = bcrypt::verify(current_password, &account.password_hash)
+            .map_err(|e| anyhow!("bcrypt error: {}", e))?;
+        if !valid {
+            return Err(anyhow!("Invalid credentials"));
+        }
+
+        self.store_password(&account, new_password, Utc::now())
+            .await?;
+        let revoked = self
+            .revoke_account_sessions(account_id, keep_session)
+            .await?;
+
+        info!(
+            "Changed password of account {}, signed out {} other sessions",
+            account_id, revoked
+        );
+        self.mail_account(mailer, &account, MailKind::PasswordChanged, "", "")
+            .await
+    }
+
+    /// Revokes the active sessions of an account except `keep_session` and returns their number.
+    pub async fn revoke_account_sessions(
+        &self,
+        account_id: &str,
+        keep_session: Option<&str>,
+    ) -> Result<usize, anyhow::Error> {
+        let sessions = self
+            .db
+            .list_sessions(account_id, "account", Utc::now())
+            .await?;
+        let mut revoked = 0;
+        for session in sessions
+            .iter()
+            .filter(|s| Some(s.session_id.as_str()) != keep_session)
+        {
+            self.revoke_session(&session.session_id).await?;
+            revoked += 1;
+        }
+        Ok(revoked)
+    }
+
+    /// The template of this kind the studio of the account wrote, or the built-in one, and the
+    /// name of the studio. Templates with links of their own, stored before they were refused,
+    /// are not used.
+    pub async fn mail_template_for(
+        &self,
+        account_id: &str,
+        kind: MailKind,
+    ) -> Result<(MailTemplate, String), anyhow::Error> {
+        let Some(studio_id) = self.db.get_studio_of_account(account_id).await? else {
+            return Ok((MailTemplate::builtin(kind), DEFAULT_STUDIO_NAME.to_string()));
+        };
+
+        let template = self
+            .db
+            .get_mail_template(&studio_id, kind)
+            .await?
+            .filter(|template| match template.check_links(kind) {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!("Ignoring {} template of studio {}: {}", kind, studio_id, e);
+                    false
+                }
+            })
+            .unwrap_or_else(|| MailTemplate::builtin(kind));
+        let studio_name = self
+            .db
+            .get_account_by_id(&studio_id)
+            .await
+            .ok()
+            .and_then(|studio| studio.display_name)
+            .filter(|name| !name.trim().is_empty())
+            .unwrap_or_else(|| DEFAULT_STUDIO_NAME.to_string());
+        Ok((template, studio_name))
+    }
+
+    /// Renders the mail, with the link below the body, and sends it in the background: a slow
+    /// mail server neither delays the response nor tells known addresses apart from unknown ones.
+    async fn mail_account(
+        &self,
+        mailer: &Arc<dyn Mailer>,
+        account: &Account,
+        kind: MailKind,
+        link: &str,
+        expires_in: &str,
+    ) -> Result<(), anyhow::Error> {
+        let (template, studio_name) = self.mail_template_for(&account.account_id, kind).await?;
+        let name = account
+            .display_name
+            .clone()
+            .filter(|name| !name.trim().is_empty())
+            .unwrap_or_else(|| account.email.clone());
+        let mut rendered = template.render(&[
+            ("name", &name),
+            ("email", &account.email),
+            ("studio", &studio_name),
+            ("expires_in", expires_in),
+        ]);
+        if kind.needs_link() {
+            rendered = rendered.with_link(link);
+        }
+        let mail = Mail {
+            to: account.email.clone(),
+            subject: rendered.subject,
+            body: rendered.body,
+        };
+
+        let mailer = Arc::clone(mailer);
+        tokio::spawn(async move {
+            if let Err(e) = mailer.send(&mail).await {
+                error!("Could not send {} mail to {}: {:#}", kind, mail.to, e);
+            }
+        });
+        Ok(())
+    }
+
+    /// Replaces unused tokens of this purpose with a new one, so only the latest link works.
+    async fn issue_account_token(
+        &self,
+        account: &Account,
+        purpose: TokenPurpose,
+        lifetime: Duration,
+    ) -> Result<String, anyhow::Error> {
+        use rand::RngCore;
+
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let token = hex::encode(bytes);
+
+        let now = Utc::now();
+        self.db
+            .delete_account_tokens(&account.account_id, purpose)
+            .await?;
+        self.db
+            .create_account_token(&AccountToken {
+                token_hash: Self::hash_account_token(&token),
+                account_id: account.account_id.clone(),
+                purpose: purpose.as_str().to_string(),
+                email: account.email.clone(),
+                created_at: now,
+                expires_at: now + lifetime,
+                used_at: None,
+            })
+            .await?;
+        Ok(token)
+    }
+
+    /// Uses up the token and returns its account, if the account still has the address it was sent to.
+    async fn redeem_account_token(
+        &self,
+        token: &str,
+        purpose: TokenPurpose,
+    ) -> Result<(Account, DateTime<Utc>), anyhow::Error> {
+        let now = Utc::now();
+        let invalid = || anyhow!("Invalid or expired token");
+
+        let token = self
+            .db
+            .use_account_token(&Self::hash_account_token(token.trim()), purpose, now)
+            .await?
+            .ok_or_else(invalid)?;
+        let account = self
+            .db
+            .get_account_by_id(&token.account_id)
+            .await
+            .map_err(|_| invalid())?;
+        if account.email != token.email {
+            return Err(invalid());
+        }
+        Ok((account, now))
+    }
+
+    async fn store_password(
+        &self,
+        account: &Account,
+        password: &str,
+        now: DateTime<Utc>,
+    ) -> Result<(), anyhow::Error> {
+        let password_hash = bcrypt::hash(password, bcrypt::DEFAULT_COST)
+            .map_err(|e| anyhow!("Failed to hash password: {}", e))?;
+        self.db
+            .set_account_password(&account.account_id, &password_hash, now)
+            .await?;
+        // Links of earlier reset mails must not undo the new password.
+        self.db
+            .delete_account_tokens(&account.account_id, TokenPurpose::PasswordReset)
+            .await?;
+        Ok(())
+    }
+
+    fn check_new_password(password: &str) -> Result<(), anyhow::Error> {
+        if password.is_empty() {
+            return Err(anyhow!("Password must not be empty"));
+        }
+        Ok(())
+    }
+
+    /// Throttles mails of one kind to an account, so repeated requests cannot flood its inbox.
+    fn may_mail(kind: MailKind, account_id: &str) -> bool {
+        let now = Utc::now();
+        let mut recent = RECENT_MAILS.write().unwrap();
+        recent.retain(|_, at| *at > now - Duration::seconds(MAIL_INTERVAL_SECONDS));
+
+        let key = format!("{}:{}", kind, account_id);
+        if recent.contains_key(&key) {
+            return false;
+        }
+        recent.insert(key, now);
+        true
+    }
+
+    fn hash_account_token(token: &str) -> String {
+        hex::encode(Sha256::digest(token.as_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mails_of_same_kind_should_be_throttled() {
+        // given
+        let account_id = uuid::Uuid::new_v4().to_string();
+
+        // when
+        let first = AuthManager::may_mail(MailKind::PasswordReset, &account_id);
+        let second = AuthManager::may_mail(MailKind::PasswordReset, &account_id);
+        let other_kind = AuthManager::may_mail(MailKind::VerifyEmail, &account_id);
+
+        // then
+        assert!(first);
+        assert!(!second);
+        assert!(other_kind);
+    }
+}
@@ -1,9 +1,30 @@
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, RwLock};
+
+use crate::auth::account::Account;
+use crate::auth::account_token::{AccountMailSettings, EmailNotVerified};
 use crate::auth::customer::Customer;
+use crate::auth::keys::KeyRing;
+use crate::auth::mfa::AccountLogin;
+use crate::auth::session::{Session, SessionClient};
+use crate::database::share_link::ShareLink;
 use crate::database::ArcDynDatabase;
-use chrono::Utc;
+use chrono::{DateTime, Duration, Utc};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use tracing::{error, info};
+use sha2::{Digest, Sha256};
+use tracing::{error, info, warn};
+
+/// Lifetime of access tokens in seconds. Clients renew them with their refresh token.
+pub const ACCESS_TOKEN_TTL: i64 = 15 * 60;
+
+/// Refresh tokens expire after this many days without use.
+pub const REFRESH_TOKEN_TTL_DAYS: i64 = 30;
+
+/// Sessions revoked within the last `ACCESS_TOKEN_TTL`, whose access tokens are refused
+/// although they did not expire yet.
+static REVOKED_SESSIONS: LazyLock<RwLock<HashMap<String, DateTime<Utc>>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CustomerLoginRequest {
@@ -16,6 +37,8 @@ pub struct CustomerLoginResponse {
     pub access_code: String,
     pub display_name: Option<String>,
     pub jwt_token: Option<String>,
+    pub refresh_token: Option<String>,
+    pub expires_in: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -24,6 +47,8 @@ pub struct AccountLoginResponse {
     pub email: String,
     pub display_name: Option<String>,
     pub jwt_token: Option<String>,
+    pub refresh_token: Option<String>,
+    pub expires_in: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -34,15 +59,41 @@ struct JwtClaims {
     iss: String,
     role: String,
     is_admin: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sid: Option<String>,
+}
+
+/// Tokens handed out when a session starts or is refreshed.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TokenPair {
+    pub session_id: String,
+    pub access_token: String,
+    pub refresh_token: String,
+    pub expires_in: u64,
+}
+
+/// The validated claims of an access token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessToken {
+    pub sub: String,
+    pub role: String,
+    pub is_admin: bool,
+    pub issuer: String,
+    /// Unix timestamps of `iat` and `exp`.
+    pub issued_at: i64,
+    pub expires_at: i64,
+    /// Missing for share tokens, which are not bound to a session.
+    pub session_id: Option<String>,
 }
 
 pub struct AuthManager {
     pub db: ArcDynDatabase,
+    pub keys: Arc<KeyRing>,
 }
 
 impl AuthManager {
-    pub fn new(db: ArcDynDatabase) -> Self {
-        Self { db }
+    pub fn new(db: ArcDynDatabase, keys: Arc<KeyRing>) -> Self {
+        Self { db, keys }
     }
 
     pub async fn create_customer(
@@ -63,11 +114,13 @@ impl AuthManager {
     pub async fn verify_access_code(
         &self,
         access_code: String,
+        client: &SessionClient,
     ) -> Result<CustomerLoginResponse, anyhow::Error> {
         let customer = self.db.get_customer_by_access_code(&access_code).await?;
 
-        let jwt_token = Self::generate_jwt_token(&customer.customer_id)
-            .map_err(|e| anyhow::anyhow!("Failed to generate JWT: {}", e))?;
+        let tokens = self
+            .start_session(&customer.customer_id, "customer", false, client)
+            .await?;
 
         info!("Customer verified successfully: {}", customer.access_code);
 
@@ -75,7 +128,9 @@ impl AuthManager {
             customer_id: customer.customer_id,
             access_code: customer.access_code,
             display_name: customer.display_name,
-            jwt_token: Some(jwt_token),
+            jwt_token: Some(tokens.access_token),
+            refresh_token: Some(tokens.refresh_token),
+            expires_in: Some(tokens.expires_in),
         })
     }
 
@@ -120,82 +175,309 @@ impl AuthManager {
         Ok(account_id)
     }
 
-    pub async fn verify_account_credentials(
+    /// Checks email and password without starting a session. Accounts that still have to
+    /// verify their address are refused with `EmailNotVerified`.
+    pub async fn check_account_credentials(
         &self,
-        email: String,
-        password: String,
-    ) -> Result<AccountLoginResponse, anyhow::Error> {
-        let account = self.db.get_account_by_email(&email).await?;
+        settings: &AccountMailSettings,
+        email: &str,
+        password: &str,
+    ) -> Result<Account, anyhow::Error> {
+        let account = self.db.get_account_by_email(email).await?;
 
-        let valid = bcrypt::verify(&password, &account.password_hash)
+        let valid = bcrypt::verify(password, &account.password_hash)
             .map_err(|e| anyhow::anyhow!("bcrypt error: {}", e))?;
 
         if !valid {
             return Err(anyhow::anyhow!("Invalid credentials"));
         }
+        if settings.require_verified_email && account.email_verified_at.is_none() {
+            return Err(EmailNotVerified.into());
+        }
+
+        Ok(account)
+    }
+
+    /// Checks email and password and starts a session, unless the account needs a second factor.
+    /// `require_admin_mfa` is the admin policy of the `auth` configuration.
+    pub async fn verify_account_credentials(
+        &self,
+        settings: &AccountMailSettings,
+        require_admin_mfa: bool,
+        email: String,
+        password: String,
+        client: &SessionClient,
+    ) -> Result<AccountLogin, anyhow::Error> {
+        let account = self.check_account_credentials(settings, &email, &password).await?;
 
         let is_admin = self.db.is_account_admin(&account.account_id).await.unwrap_or(false);
-        let jwt_token = Self::generate_account_jwt(&account.account_id, is_admin)
-            .map_err(|e| anyhow::anyhow!("Failed to generate JWT: {}", e))?;
+        if let Some(challenge) = self
+            .mfa_challenge_for(&account.account_id, is_admin, require_admin_mfa)
+            .await?
+        {
+            info!("Account {} needs a second factor", account.email);
+            return Ok(AccountLogin::MfaRequired(challenge));
+        }
+
+        Ok(AccountLogin::Complete(self.finish_account_login(account, client).await?))
+    }
+
+    /// Opens a session for a verified customer or account and issues its first token pair.
+    pub async fn start_session(
+        &self,
+        subject_id: &str,
+        role: &str,
+        is_admin: bool,
+        client: &SessionClient,
+    ) -> Result<TokenPair, anyhow::Error> {
+        let now = Utc::now();
+        let _ = self.db.delete_expired_sessions(now).await;
+
+        let refresh_token = Self::new_refresh_token();
+        let session = Session {
+            session_id: uuid::Uuid::new_v4().hyphenated().to_string(),
+            subject_id: subject_id.to_string(),
+            role: role.to_string(),
+            refresh_token_hash: Self::hash_refresh_token(&refresh_token),
+            previous_token_hash: None,
+            user_agent: client.user_agent.clone(),
+            ip_address: client.ip_address.clone(),
+            created_at: now,
+            last_used_at: now,
+            expires_at: now + Duration::days(REFRESH_TOKEN_TTL_DAYS),
+            revoked_at: None,
+        };
+        self.db.create_session(&session).await?;
 
-        info!("Account verified successfully: {}", account.email);
+        let access_token = Self::generate_access_token(
+            &self.keys,
+            subject_id,
+            role,
+            is_admin,
+            &session.session_id,
+        )
+        .map_err(|e| anyhow::anyhow!("Failed to generate JWT: {}", e))?;
 
-        Ok(AccountLoginResponse {
-            account_id: account.account_id,
-            email: account.email,
-            display_name: account.display_name,
-            jwt_token: Some(jwt_token),
+        Ok(TokenPair {
+            session_id: session.session_id,
+            access_token,
+            refresh_token,
+            expires_in: ACCESS_TOKEN_TTL as u64,
         })
     }
 
-    pub async fn get_customer_by_id(&self, customer_id: String) -> Option<Customer> {
-        self.db.get_customer(&customer_id).await.ok()
+    /// Trades a refresh token for a new token pair. The presented token is used up; presenting
+    /// it again revokes the whole session, since then either the client or a thief holds a copy.
+    pub async fn refresh_session(
+        &self,
+        refresh_token: &str,
+        client: &SessionClient,
+    ) -> Result<TokenPair, anyhow::Error> {
+        let now = Utc::now();
+        let token_hash = Self::hash_refresh_token(refresh_token);
+        let session = self
+            .db
+            .get_session_by_refresh_token(&token_hash)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Unknown refresh token"))?;
+
+        if !session.is_active(now) {
+            return Err(anyhow::anyhow!("Session expired or revoked"));
+        }
+
+        if session.refresh_token_hash != token_hash {
+            warn!("Refresh token of session {} was reused, revoking it", session.session_id);
+            self.revoke_session(&session.session_id).await?;
+            return Err(anyhow::anyhow!("Refresh token was already used"));
+        }
+
+        let is_admin = match session.role.as_str() {
+            "account" => match self.db.get_account_by_id(&session.subject_id).await {
+                Ok(account) => account.is_admin,
+                Err(_) => {
+                    self.revoke_session(&session.session_id).await?;
+                    return Err(anyhow::anyhow!("Account no longer exists"));
+                }
+            },
+            _ => {
+                if self.db.get_customer(&session.subject_id).await.is_err() {
+                    self.revoke_session(&session.session_id).await?;
+                    return Err(anyhow::anyhow!("Customer no longer exists"));
+                }
+                false
+            }
+        };
+
+        let next_token = Self::new_refresh_token();
+        let rotated = self
+            .db
+            .rotate_session_token(
+                &session.session_id,
+                &token_hash,
+                &Self::hash_refresh_token(&next_token),
+                client,
+                now,
+                now + Duration::days(REFRESH_TOKEN_TTL_DAYS),
+            )
+            .await?;
+        if !rotated {
+            return Err(anyhow::anyhow!("Refresh token was already used"));
+        }
+
+        let access_token = Self::generate_access_token(
+            &self.keys,
+            &session.subject_id,
+            &session.role,
+            is_admin,
+            &session.session_id,
+        )
+        .map_err(|e| anyhow::anyhow!("Failed to generate JWT: {}", e))?;
+
+        Ok(TokenPair {
+            session_id: session.session_id,
+            access_token,
+            refresh_token: next_token,
+            expires_in: ACCESS_TOKEN_TTL as u64,
+        })
+    }
+
+    /// Ends a session. Its refresh token stops working at once, its access tokens are refused
+    /// by this process right away and expire everywhere else within `ACCESS_TOKEN_TTL`.
+    pub async fn revoke_session(&self, session_id: &str) -> Result<bool, anyhow::Error> {
+        let now = Utc::now();
+        let revoked = self.db.revoke_session(session_id, now).await?;
+        Self::deny_session(session_id, now);
+        Ok(revoked)
     }
 
-    pub fn generate_jwt_token(customer_id: &str) -> Result<String, jsonwebtoken::errors::Error> {
-        use jsonwebtoken::{encode, EncodingKey, Header};
+    /// Revokes the session behind a refresh or access token (RFC 7009).
+    /// Unknown and invalid tokens are ignored.
+    pub async fn revoke_token(&self, token: &str) -> Result<(), anyhow::Error> {
+        let session_id = match self
+            .db
+            .get_session_by_refresh_token(&Self::hash_refresh_token(token))
+            .await?
+        {
+            Some(session) => Some(session.session_id),
+            None => Self::decode_access_token(&self.keys, token).ok().and_then(|t| t.session_id),
+        };
 
-        let secret = std::env::var("JWT_SECRET")
-            .unwrap_or_else(|_| "default-secret-key-change-in-production".to_string());
+        if let Some(session_id) = session_id {
+            self.revoke_session(&session_id).await?;
+        }
+        Ok(())
+    }
 
+    /// The session a refresh token currently belongs to. Rotated tokens, and tokens of
+    /// expired or revoked sessions, yield `None`.
+    pub async fn session_of_refresh_token(&self, token: &str) -> Result<Option<Session>, anyhow::Error> {
+        let token_hash = Self::hash_refresh_token(token);
+        let session = self.db.get_session_by_refresh_token(&token_hash).await?;
+
+        Ok(session.filter(|s| s.refresh_token_hash == token_hash && s.is_active(Utc::now())))
+    }
+
+    /// Looks up a session that is neither expired nor revoked.
+    pub async fn active_session(&self, session_id: &str) -> Result<Option<Session>, anyhow::Error> {
+        let session = self.db.get_session(session_id).await?;
+
+        Ok(session.filter(|s| s.is_active(Utc::now())))
+    }
+
+    /// Looks up a share link that is neither expired nor revoked.
+    pub async fn active_share_link(&self, token: &str) -> Result<Option<ShareLink>, anyhow::Error> {
+        let link = self.db.get_share_link(token).await?;
+
+        Ok(link.filter(|l| !l.is_expired()))
+    }
+
+    /// Refuses access tokens of sessions revoked shortly before the process started.
+    pub async fn load_revoked_sessions(&self) -> Result<(), anyhow::Error> {
+        let now = Utc::now();
+        let ids = self
+            .db
+            .list_revoked_session_ids(now - Duration::seconds(ACCESS_TOKEN_TTL))
+            .await?;
+        for id in ids {
+            Self::deny_session(&id, now);
+        }
+        Ok(())
+    }
+
+    fn deny_session(session_id: &str, now: DateTime<Utc>) {
+        let mut revoked = REVOKED_SESSIONS.write().unwrap();
+        revoked.retain(|_, at| *at > now - Duration::seconds(ACCESS_TOKEN_TTL));
+        revoked.insert(session_id.to_string(), now);
+    }
+
+    fn is_session_denied(session_id: &str) -> bool {
+        REVOKED_SESSIONS.read().unwrap().contains_key(session_id)
+    }
+
+    fn new_refresh_token() -> String {
+        use rand::RngCore;
+
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        hex::encode(bytes)
+    }
+
+    fn hash_refresh_token(token: &str) -> String {
+        hex::encode(Sha256::digest(token.as_bytes()))
+    }
+
+    pub async fn get_customer_by_id(&self, customer_id: String) -> Option<Customer> {
+        self.db.get_customer(&customer_id).await.ok()
+    }
+
+    /// Issues a short-lived access token bound to a session.
+    pub fn generate_access_token(
+        keys: &KeyRing,
+        sub: &str,
+        role: &str,
+        is_admin: bool,
+        session_id: &str,
+    ) -> Result<String, jsonwebtoken::errors::Error> {
         let now = Utc::now().timestamp() as usize;
         let claims = JwtClaims {
-            sub: customer_id.to_string(),
-            exp: now + 86400,
+            sub: sub.to_string(),
+            exp: now + ACCESS_TOKEN_TTL as usize,
             iat: now,
             iss: "photos.network".to_string(),
-            role: "customer".to_string(),
-            is_admin: false,
+            role: role.to_string(),
+            is_admin,
+            sid: Some(session_id.to_string()),
         };
 
-        encode(
-            &Header::default(),
-            &claims,
-            &EncodingKey::from_secret(secret.as_ref()),
-        )
+        keys.encode(&claims)
     }
 
-    pub fn generate_account_jwt(account_id: &str, is_admin: bool) -> Result<String, jsonwebtoken::errors::Error> {
-        use jsonwebtoken::{encode, EncodingKey, Header};
-
-        let secret = std::env::var("JWT_SECRET")
-            .unwrap_or_else(|_| "default-secret-key-change-in-production".to_string());
-
+    /// Issues a token for a visitor who unlocked a password protected share link.
+    /// The token never outlives the link itself.
+    pub fn generate_share_jwt(
+        keys: &KeyRing,
+        share_token: &str,
+        link_expires_at: Option<DateTime<Utc>>,
+    ) -> Result<(String, usize), jsonwebtoken::errors::Error> {
         let now = Utc::now().timestamp() as usize;
+        let mut exp = now + 86400;
+        if let Some(link_exp) = link_expires_at {
+            exp = exp.min(link_exp.timestamp().max(0) as usize);
+        }
+
         let claims = JwtClaims {
-            sub: account_id.to_string(),
-            exp: now + 86400,
+            sub: share_token.to_string(),
+            exp,
             iat: now,
             iss: "photos.network".to_string(),
-            role: "account".to_string(),
-            is_admin,
+            role: "share".to_string(),
+            is_admin: false,
+            sid: None,
         };
 
-        encode(
-            &Header::default(),
-            &claims,
-            &EncodingKey::from_secret(secret.as_ref()),
-        )
+        let token = keys.encode(&claims)?;
+
+        Ok((token, exp.saturating_sub(now)))
     }
 
     fn access_code_regex() -> Regex {
@@ -216,19 +498,94 @@ impl AuthManager {
     }
 
     /// Validates a JWT token and returns `(sub, role)`.
-    pub fn validate_jwt_token(token: &str) -> Result<(String, String), anyhow::Error> {
-        use jsonwebtoken::{decode, DecodingKey, Validation};
+    /// Share tokens are refused, they only open their share link (see `validate_share_token`).
+    pub fn validate_jwt_token(
+        keys: &KeyRing,
+        token: &str,
+    ) -> Result<(String, String), anyhow::Error> {
+        let token = Self::decode_access_token(keys, token)?;
+        if token.role == "share" {
+            return Err(anyhow::anyhow!("Share tokens are only valid for their share link"));
+        }
+        Ok((token.sub, token.role))
+    }
+
+    /// Validates a token issued by `generate_share_jwt` and returns the share link token.
+    pub fn validate_share_token(keys: &KeyRing, token: &str) -> Result<String, anyhow::Error> {
+        let token = Self::decode_access_token(keys, token)?;
+        if token.role != "share" {
+            return Err(anyhow::anyhow!("Not a share token"));
+        }
+        Ok(token.sub)
+    }
 
-        let secret = std::env::var("JWT_SECRET")
-            .unwrap_or_else(|_| "default-secret-key-change-in-production".to_string());
+    /// Validates a JWT token, refusing tokens of revoked sessions.
+    pub fn decode_access_token(keys: &KeyRing, token: &str) -> Result<AccessToken, anyhow::Error> {
+        let token_data = keys.decode::<JwtClaims>(token, &jsonwebtoken::Validation::default())?;
 
-        let token_data = decode::<JwtClaims>(
-            token,
-            &DecodingKey::from_secret(secret.as_ref()),
-            &Validation::default(),
-        )
-        .map_err(|e| anyhow::anyhow!("Invalid JWT: {}", e))?;
+        let claims = token_data.claims;
+        if claims.sid.as_deref().is_some_and(Self::is_session_denied) {
+            return Err(anyhow::anyhow!("Session was revoked"));
+        }
+
+        Ok(AccessToken {
+            sub: claims.sub,
+            role: claims.role,
+            is_admin: claims.is_admin,
+            issuer: claims.iss,
+            issued_at: claims.iat as i64,
+            expires_at: claims.exp as i64,
+            session_id: claims.sid,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::keys::JwtKey;
+
+    fn keys() -> KeyRing {
+        KeyRing::new(vec![JwtKey::new("test", b"test-secret")], None).unwrap()
+    }
+
+    #[test]
+    fn test_access_token_of_revoked_session_should_fail() {
+        // given
+        let keys = keys();
+        let token =
+            AuthManager::generate_access_token(&keys, "account-1", "account", false, "session-1")
+                .unwrap();
+        let other =
+            AuthManager::generate_access_token(&keys, "account-1", "account", false, "session-2")
+                .unwrap();
+
+        // when
+        AuthManager::deny_session("session-1", Utc::now());
+
+        // then
+        assert!(AuthManager::decode_access_token(&keys, &token).is_err());
+        let valid = AuthManager::decode_access_token(&keys, &other).unwrap();
+        assert_eq!(valid.sub, "account-1");
+        assert_eq!(valid.session_id.as_deref(), Some("session-2"));
+    }
 
-        Ok((token_data.claims.sub, token_data.claims.role))
+    #[test]
+    fn test_share_tokens_only_validate_as_share_tokens() {
+        // given
+        let keys = keys();
+        let (share, _) = AuthManager::generate_share_jwt(&keys, "share-1", None).unwrap();
+        let access =
+            AuthManager::generate_access_token(&keys, "account-1", "account", false, "session-1")
+                .unwrap();
+
+        // when
+        let as_session = AuthManager::validate_jwt_token(&keys, &share);
+        let as_share = AuthManager::validate_share_token(&keys, &share);
+
+        // then
+        assert!(as_session.is_err());
+        assert_eq!(as_share.unwrap(), "share-1");
+        assert!(AuthManager::validate_share_token(&keys, &access).is_err());
     }
 }
@@ -9,4 +9,6 @@ pub struct Customer {
     pub created_at: DateTime<Utc>,
     pub updated_at: Option<DateTime<Utc>>,
     pub last_login_at: Option<DateTime<Utc>>,
+    /// Paid customers see their galleries without watermarks.
+    pub paid: bool,
 }
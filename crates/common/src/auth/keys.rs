@@ -0,0 +1,496 @@
+//! Keys that sign and verify issued tokens.
+//!
+//! Every token names its key in the `kid` header, so several keys can be valid at once:
+//! a rotation adds the new key as signing key and keeps the old one listed until the
+//! tokens it signed have expired.
+//!
+//! Realms own RSA and P-256 keys whose public halves are published at `/jwk`, so other
+//! services can verify tokens. The shared HMAC keys verify older tokens and sign media URLs.
+//!
+//! The key ring is loaded once at startup and shared through the `ApplicationState`.
+
+use std::fmt;
+use std::fs;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation};
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use p256::pkcs8::EncodePrivateKey;
+use rsa::pkcs1::{EncodeRsaPrivateKey, EncodeRsaPublicKey};
+use rsa::traits::PublicKeyParts;
+use rsa::RsaPrivateKey;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use crate::config::auth_config::{AuthConfig, SigningAlgorithm};
+
+/// Well-known secret only accepted in development mode.
+const DEVELOPMENT_SECRET: &str = "default-secret-key-change-in-production";
+
+/// `kid` of the key taken from the legacy `JWT_SECRET` environment variable.
+const ENV_KID: &str = "env";
+
+/// Realm whose keys sign tokens issued outside of a realm specific flow.
+pub const DEFAULT_REALM: &str = "master";
+
+#[derive(Clone)]
+pub struct JwtKey {
+    pub kid: String,
+    pub algorithm: Algorithm,
+    /// Realm owning this key, `None` for the shared HMAC keys.
+    pub realm: Option<String>,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    secret: Option<Vec<u8>>,
+    jwk: Option<serde_json::Value>,
+}
+
+impl JwtKey {
+    pub fn new(kid: &str, secret: &[u8]) -> Self {
+        Self {
+            kid: kid.to_string(),
+            algorithm: Algorithm::HS256,
+            realm: None,
+            encoding: EncodingKey::from_secret(secret),
+            decoding: DecodingKey::from_secret(secret),
+            secret: Some(secret.to_vec()),
+            jwk: None,
+        }
+    }
+
+    /// RS256 key of a realm. The `kid` is derived from the public key.
+    pub fn rsa(realm: &str, key: &RsaPrivateKey) -> Result<Self, anyhow::Error> {
+        let public = key.to_public_key();
+        let n = URL_SAFE_NO_PAD.encode(public.n().to_bytes_be());
+        let e = URL_SAFE_NO_PAD.encode(public.e().to_bytes_be());
+        let kid = Self::realm_kid(realm, "rs256", public.to_pkcs1_der()?.as_bytes());
+
+        Ok(Self {
+            encoding: EncodingKey::from_rsa_der(key.to_pkcs1_der()?.as_bytes()),
+            decoding: DecodingKey::from_rsa_components(&n, &e)?,
+            jwk: Some(serde_json::json!({
+                "kty": "RSA",
+                "use": "sig",
+                "alg": "RS256",
+                "kid": kid,
+                "n": n,
+                "e": e,
+            })),
+            kid,
+            algorithm: Algorithm::RS256,
+            realm: Some(realm.to_string()),
+            secret: None,
+        })
+    }
+
+    /// ES256 key of a realm. The `kid` is derived from the public key.
+    pub fn ec(realm: &str, key: &p256::SecretKey) -> Result<Self, anyhow::Error> {
+        let point = key.public_key().to_encoded_point(false);
+        let (Some(x), Some(y)) = (point.x(), point.y()) else {
+            return Err(anyhow::anyhow!(
+                "P-256 public key of realm {} is the identity",
+                realm
+            ));
+        };
+        let x = URL_SAFE_NO_PAD.encode(x);
+        let y = URL_SAFE_NO_PAD.encode(y);
+        let kid = Self::realm_kid(realm, "es256", point.as_bytes());
+        let pem = key
+            .to_pkcs8_pem(p256::pkcs8::LineEnding::LF)
+            .map_err(|e| anyhow::anyhow!("Could not encode P-256 key of realm {}: {}", realm, e))?;
+
+        Ok(Self {
+            encoding: EncodingKey::from_ec_pem(pem.as_bytes())?,
+            decoding: DecodingKey::from_ec_components(&x, &y)?,
+            jwk: Some(serde_json::json!({
+                "kty": "EC",
+                "use": "sig",
+                "alg": "ES256",
+                "kid": kid,
+                "crv": "P-256",
+                "x": x,
+                "y": y,
+            })),
+            kid,
+            algorithm: Algorithm::ES256,
+            realm: Some(realm.to_string()),
+            secret: None,
+        })
+    }
+
+    /// HMAC secret, `None` for asymmetric keys.
+    pub fn secret(&self) -> Option<&[u8]> {
+        self.secret.as_deref()
+    }
+
+    /// Public key in JWK format, `None` for HMAC keys which must never be published.
+    pub fn jwk(&self) -> Option<&serde_json::Value> {
+        self.jwk.as_ref()
+    }
+
+    fn realm_kid(realm: &str, algorithm: &str, public_key: &[u8]) -> String {
+        let digest = hex::encode(Sha256::digest(public_key));
+        format!("{}-{}-{}", realm, algorithm, &digest[..16])
+    }
+}
+
+impl fmt::Debug for JwtKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JwtKey")
+            .field("kid", &self.kid)
+            .field("algorithm", &self.algorithm)
+            .field("realm", &self.realm)
+            .finish_non_exhaustive()
+    }
+}
+
+pub struct KeyRing {
+    keys: Vec<JwtKey>,
+    signing: usize,
+}
+
+impl KeyRing {
+    pub fn new(keys: Vec<JwtKey>, signing_kid: Option<&str>) -> Result<Self, anyhow::Error> {
+        if !keys.iter().any(|k| k.secret.is_some()) {
+            return Err(anyhow::anyhow!(
+                "At least one HMAC key is required to sign media URLs"
+            ));
+        }
+        for (i, key) in keys.iter().enumerate() {
+            if keys[..i].iter().any(|k| k.kid == key.kid) {
+                return Err(anyhow::anyhow!("JWT key id '{}' is listed twice", key.kid));
+            }
+        }
+
+        let signing = match signing_kid {
+            Some(kid) => keys.iter().position(|k| k.kid == kid).ok_or_else(|| {
+                anyhow::anyhow!("Signing key '{}' is not among the JWT keys", kid)
+            })?,
+            None => 0,
+        };
+
+        Ok(Self { keys, signing })
+    }
+
+    /// Loads the configured HMAC keys, falling back to the `JWT_SECRET` environment variable,
+    /// and adds the keys of all realms. Refuses the well-known development secret unless
+    /// `dev_mode` is on.
+    pub fn from_config(
+        config: &AuthConfig,
+        realm_keys: Vec<JwtKey>,
+    ) -> Result<Self, anyhow::Error> {
+        let mut keys = Self::hmac_keys_from_config(config)?;
+
+        let signing_kid = match config.signing_algorithm {
+            SigningAlgorithm::HS256 => config.signing_kid.clone(),
+            algorithm => {
+                let algorithm = Algorithm::from(algorithm);
+                let key = realm_keys
+                    .iter()
+                    .find(|k| k.realm.as_deref() == Some(DEFAULT_REALM) && k.algorithm == algorithm)
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("Realm {} has no {:?} key", DEFAULT_REALM, algorithm)
+                    })?;
+                Some(key.kid.clone())
+            }
+        };
+
+        keys.extend(realm_keys);
+        Self::new(keys, signing_kid.as_deref())
+    }
+
+    fn hmac_keys_from_config(config: &AuthConfig) -> Result<Vec<JwtKey>, anyhow::Error> {
+        let mut keys = Vec::with_capacity(config.jwt_keys.len());
+        for key in &config.jwt_keys {
+            let secret = match (&key.secret, &key.secret_file) {
+                (Some(secret), _) => secret.clone(),
+                (None, Some(path)) => fs::read_to_string(path)
+                    .map_err(|e| {
+                        anyhow::anyhow!("Could not read JWT key file {}: {}", path.display(), e)
+                    })?
+                    .trim()
+                    .to_string(),
+                (None, None) => {
+                    return Err(anyhow::anyhow!(
+                        "JWT key '{}' has neither a secret nor a secret_file",
+                        key.kid
+                    ))
+                }
+            };
+            keys.push(JwtKey::new(&key.kid, secret.as_bytes()));
+        }
+
+        if keys.is_empty() {
+            if let Ok(secret) = std::env::var("JWT_SECRET") {
+                warn!("JWT_SECRET is deprecated, configure `auth.jwt_keys` instead");
+                keys.push(JwtKey::new(ENV_KID, secret.as_bytes()));
+            } else if config.dev_mode {
+                warn!("No JWT key configured, falling back to the development secret");
+                return Ok(vec![JwtKey::new("dev", DEVELOPMENT_SECRET.as_bytes())]);
+            } else {
+                return Err(anyhow::anyhow!(
+                    "No JWT signing key configured. Add `auth.jwt_keys` to the configuration or enable `auth.dev_mode`."
+                ));
+            }
+        }
+
+        for key in &keys {
+            let secret = key.secret().unwrap_or_default();
+            if secret.is_empty() {
+                return Err(anyhow::anyhow!("JWT key '{}' is empty", key.kid));
+            }
+            if secret == DEVELOPMENT_SECRET.as_bytes() && !config.dev_mode {
+                return Err(anyhow::anyhow!(
+                    "JWT key '{}' uses the development secret, which is only allowed with `auth.dev_mode`",
+                    key.kid
+                ));
+            }
+            if secret.len() < 32 {
+                warn!("JWT key '{}' is shorter than 32 bytes", key.kid);
+            }
+        }
+
+        Ok(keys)
+    }
+
+    /// The key new tokens are signed with.
+    pub fn signing_key(&self) -> &JwtKey {
+        &self.keys[self.signing]
+    }
+
+    /// All keys, the signing key first.
+    pub fn keys(&self) -> impl Iterator<Item = &JwtKey> {
+        std::iter::once(self.signing_key()).chain(
+            self.keys
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| *i != self.signing)
+                .map(|(_, k)| k),
+        )
+    }
+
+    /// HMAC secrets, the one that signs new media URLs first.
+    pub fn hmac_secrets(&self) -> impl Iterator<Item = &[u8]> {
+        self.keys().filter_map(JwtKey::secret)
+    }
+
+    pub fn encode<T: Serialize>(&self, claims: &T) -> Result<String, jsonwebtoken::errors::Error> {
+        let key = self.signing_key();
+        let header = Header {
+            kid: Some(key.kid.clone()),
+            ..Header::new(key.algorithm)
+        };
+
+        jsonwebtoken::encode(&header, claims, &key.encoding)
+    }
+
+    /// Signs with the realm's key of the signing algorithm, e.g. ID tokens that clients of the
+    /// realm verify from its `/jwk`. Falls back to the realm's RS256 key while tokens are signed
+    /// with HMAC, whose secret clients do not know.
+    pub fn encode_for_realm<T: Serialize>(
+        &self,
+        realm: &str,
+        claims: &T,
+    ) -> Result<String, jsonwebtoken::errors::Error> {
+        let realm_keys = || {
+            self.keys
+                .iter()
+                .filter(|k| k.realm.as_deref() == Some(realm))
+        };
+        let key = realm_keys()
+            .find(|k| k.algorithm == self.signing_key().algorithm)
+            .or_else(|| realm_keys().find(|k| k.algorithm == Algorithm::RS256))
+            .unwrap_or(self.signing_key());
+        let header = Header {
+            kid: Some(key.kid.clone()),
+            ..Header::new(key.algorithm)
+        };
+
+        jsonwebtoken::encode(&header, claims, &key.encoding)
+    }
+
+    /// Verifies a token with the key named in its `kid` header, only accepting the algorithm
+    /// of that key. Tokens from before key ids existed are checked against the first HMAC key.
+    pub fn decode<T: DeserializeOwned>(
+        &self,
+        token: &str,
+        validation: &Validation,
+    ) -> Result<TokenData<T>, anyhow::Error> {
+        let header = jsonwebtoken::decode_header(token)
+            .map_err(|e| anyhow::anyhow!("Invalid JWT: {}", e))?;
+        let key = match header.kid.as_deref() {
+            Some(kid) => self
+                .keys
+                .iter()
+                .find(|k| k.kid == kid)
+                .ok_or_else(|| anyhow::anyhow!("Invalid JWT: unknown key id '{}'", kid))?,
+            None => self
+                .keys()
+                .find(|k| k.secret.is_some())
+                .ok_or_else(|| anyhow::anyhow!("Invalid JWT: no key id"))?,
+        };
+
+        let mut validation = validation.clone();
+        validation.algorithms = vec![key.algorithm];
+
+        jsonwebtoken::decode::<T>(token, &key.decoding, &validation)
+            .map_err(|e| anyhow::anyhow!("Invalid JWT: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::auth_config::JwtKeyConfig;
+
+    #[derive(Debug, Serialize, serde::Deserialize)]
+    struct Claims {
+        sub: String,
+        exp: usize,
+    }
+
+    fn claims() -> Claims {
+        Claims {
+            sub: "account-1".into(),
+            exp: chrono::Utc::now().timestamp() as usize + 60,
+        }
+    }
+
+    #[test]
+    fn test_token_of_previous_key_after_rotation_should_succeed() {
+        // given
+        let before = KeyRing::new(vec![JwtKey::new("old", b"old-secret")], None).unwrap();
+        let token = before.encode(&claims()).unwrap();
+
+        // when
+        let after = KeyRing::new(
+            vec![
+                JwtKey::new("old", b"old-secret"),
+                JwtKey::new("new", b"new-secret"),
+            ],
+            Some("new"),
+        )
+        .unwrap();
+        let retired = KeyRing::new(vec![JwtKey::new("new", b"new-secret")], None).unwrap();
+
+        // then
+        assert_eq!(
+            jsonwebtoken::decode_header(&after.encode(&claims()).unwrap())
+                .unwrap()
+                .kid
+                .as_deref(),
+            Some("new")
+        );
+        assert_eq!(
+            after
+                .decode::<Claims>(&token, &Validation::default())
+                .unwrap()
+                .claims
+                .sub,
+            "account-1"
+        );
+        assert!(retired
+            .decode::<Claims>(&token, &Validation::default())
+            .is_err());
+    }
+
+    #[test]
+    fn test_development_secret_without_dev_mode_should_fail() {
+        // given
+        let mut config = AuthConfig {
+            dev_mode: false,
+            jwt_keys: vec![JwtKeyConfig {
+                kid: "k1".into(),
+                secret: Some(DEVELOPMENT_SECRET.into()),
+                secret_file: None,
+            }],
+            signing_kid: None,
+            signing_algorithm: SigningAlgorithm::HS256,
+            require_admin_mfa: false,
+            passkey_rp_id: None,
+            passkey_origins: vec![],
+            trusted_proxies: vec![],
+        };
+
+        // when
+        let production = KeyRing::from_config(&config, vec![]);
+        config.dev_mode = true;
+        let development = KeyRing::from_config(&config, vec![]);
+
+        // then
+        assert!(production.is_err());
+        assert!(development.is_ok());
+    }
+
+    #[test]
+    fn test_verify_realm_token_with_published_jwks_should_succeed() {
+        // given
+        let secret = p256::SecretKey::random(&mut rand::rngs::OsRng);
+        let realm_key = JwtKey::ec(DEFAULT_REALM, &secret).unwrap();
+        let config = AuthConfig {
+            dev_mode: false,
+            jwt_keys: vec![JwtKeyConfig {
+                kid: "hmac".into(),
+                secret: Some("a-secret-of-at-least-thirty-two-bytes".into()),
+                secret_file: None,
+            }],
+            signing_kid: None,
+            signing_algorithm: SigningAlgorithm::ES256,
+            require_admin_mfa: false,
+            passkey_rp_id: None,
+            passkey_origins: vec![],
+            trusted_proxies: vec![],
+        };
+        let keys = KeyRing::from_config(&config, vec![realm_key]).unwrap();
+
+        // when
+        let token = keys.encode(&claims()).unwrap();
+        let header = jsonwebtoken::decode_header(&token).unwrap();
+        let published = keys.keys().filter_map(JwtKey::jwk).collect::<Vec<_>>();
+        let jwk: jsonwebtoken::jwk::Jwk = serde_json::from_value(published[0].clone()).unwrap();
+
+        // then
+        assert_eq!(header.alg, Algorithm::ES256);
+        assert_eq!(published.len(), 1);
+        assert_eq!(jwk.common.key_id, header.kid);
+        let verified = jsonwebtoken::decode::<Claims>(
+            &token,
+            &DecodingKey::from_jwk(&jwk).unwrap(),
+            &Validation::new(Algorithm::ES256),
+        )
+        .unwrap();
+        assert_eq!(verified.claims.sub, "account-1");
+        assert_eq!(keys.hmac_secrets().count(), 1);
+    }
+
+    #[test]
+    fn test_token_with_foreign_algorithm_should_fail() {
+        // given
+        let secret = p256::SecretKey::random(&mut rand::rngs::OsRng);
+        let realm_key = JwtKey::ec(DEFAULT_REALM, &secret).unwrap();
+        let keys = KeyRing::new(
+            vec![JwtKey::new("hmac", b"shared"), realm_key.clone()],
+            None,
+        )
+        .unwrap();
+
+        // when
+        let forged = jsonwebtoken::encode(
+            &Header {
+                kid: Some(realm_key.kid.clone()),
+                ..Header::new(Algorithm::HS256)
+            },
+            &claims(),
+            &EncodingKey::from_secret(b"shared"),
+        )
+        .unwrap();
+
+        // then
+        assert!(keys
+            .decode::<Claims>(&forged, &Validation::default())
+            .is_err());
+    }
+}
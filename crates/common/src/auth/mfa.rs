@@ -0,0 +1,688 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Second factor of accounts: a TOTP authenticator app and one-time recovery codes.
+//!
+//! A password login of an account with a second factor does not start a session. It yields an
+//! `mfa_token` instead, which is traded for tokens together with a code from the app.
+//! Admin accounts without a second factor get an `mfa_token` as well while
+//! `require_admin_mfa` is set, which only allows enrolling one. Passkeys count as second
+//! factor as well, see `passkey`.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{LazyLock, RwLock};
+
+use chrono::{DateTime, Duration, Utc};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+use crate::auth::account::Account;
+use crate::auth::auth_manager::{AccountLoginResponse, AuthManager};
+use crate::auth::session::SessionClient;
+use crate::auth::totp;
+
+/// Lifetime of an `mfa_token` in seconds.
+pub const MFA_TOKEN_TTL: i64 = 5 * 60;
+
+/// Wrong codes accepted per `mfa_token` before it is dropped.
+const MAX_MFA_ATTEMPTS: u32 = 5;
+
+/// Wrong second factors in a row, over all logins, before the second factor is locked.
+pub const MAX_SECOND_FACTOR_FAILURES: i64 = 10;
+
+/// How long a locked second factor refuses codes, in seconds. Every further wrong code
+/// locks it again until a right one resets the count.
+pub const SECOND_FACTOR_LOCKOUT: i64 = 15 * 60;
+
+/// Recovery codes handed out on enrollment and regeneration.
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Shown as issuer in authenticator apps.
+pub const TOTP_ISSUER: &str = "Photos.network";
+
+/// Lowercase letters and digits without the easily confused `0`, `o`, `1`, `l` and `i`.
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+const RECOVERY_CODE_LENGTH: usize = 8;
+
+/// Password logins waiting for their second factor, by hash of their `mfa_token`.
+static MFA_CHALLENGES: LazyLock<RwLock<HashMap<String, MfaChallengeState>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// TOTP secret of an account. It only counts as second factor once a code confirmed it.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct AccountMfa {
+    pub account_id: String,
+    pub totp_secret: String,
+    pub enabled_at: Option<DateTime<Utc>>,
+    pub last_used_step: Option<i64>,
+    pub created_at: DateTime<Utc>,
+    /// Wrong codes in a row, see `MAX_SECOND_FACTOR_FAILURES`.
+    pub failed_attempts: i64,
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+impl AccountMfa {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled_at.is_some()
+    }
+
+    pub fn is_locked(&self, now: DateTime<Utc>) -> bool {
+        self.locked_until.is_some_and(|until| until > now)
+    }
+}
+
+/// Refuses second factors of an account after too many wrong ones, whatever login they came with.
+#[derive(Debug)]
+pub struct SecondFactorLocked;
+
+impl fmt::Display for SecondFactorLocked {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Too many wrong codes, try again later")
+    }
+}
+
+impl std::error::Error for SecondFactorLocked {}
+
+/// What an `mfa_token` may be used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MfaPurpose {
+    /// Completing a login with a code.
+    Verify,
+    /// Enrolling a second factor the policy requires, then completing the login.
+    Enroll,
+}
+
+#[derive(Debug, Clone)]
+struct MfaChallengeState {
+    account_id: String,
+    purpose: MfaPurpose,
+    expires_at: DateTime<Utc>,
+    attempts: u32,
+}
+
+/// Handed out instead of tokens when a password login needs a second factor.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MfaChallenge {
+    pub mfa_token: String,
+    /// The account has to enroll a second factor before it can sign in.
+    pub enrollment_required: bool,
+    pub expires_in: u64,
+    /// Second factors the account can answer with: `totp` (which includes recovery codes) and `passkey`.
+    pub methods: Vec<String>,
+}
+
+/// Outcome of a password login.
+#[derive(Debug)]
+pub enum AccountLogin {
+    Complete(AccountLoginResponse),
+    MfaRequired(MfaChallenge),
+}
+
+/// A started TOTP enrollment, to be shown as QR code and confirmed with a first code.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TotpEnrollment {
+    pub secret: String,
+    pub provisioning_uri: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MfaStatus {
+    pub totp_enabled: bool,
+    pub enabled_at: Option<DateTime<Utc>>,
+    pub recovery_codes_left: i64,
+    pub passkeys: usize,
+    /// The policy does not allow removing the last second factor.
+    pub required: bool,
+}
+
+/// A kind of second factor an account can have.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecondFactor {
+    Totp,
+    Passkey,
+}
+
+impl AuthManager {
+    /// Password logins of accounts with a second factor, and of admins while the policy
+    /// requires one, need an `mfa_token` step before a session starts.
+    /// `require_admin_mfa` is the policy of the `auth` configuration.
+    pub async fn mfa_challenge_for(
+        &self,
+        account_id: &str,
+        is_admin: bool,
+        require_admin_mfa: bool,
+    ) -> Result<Option<MfaChallenge>, anyhow::Error> {
+        let methods = self.second_factors(account_id).await?;
+
+        let purpose = if !methods.is_empty() {
+            MfaPurpose::Verify
+        } else if is_admin && require_admin_mfa {
+            MfaPurpose::Enroll
+        } else {
+            return Ok(None);
+        };
+
+        Ok(Some(Self::issue_mfa_challenge(
+            account_id, purpose, methods,
+        )))
+    }
+
+    /// Second factors enrolled by an account.
+    pub async fn second_factors(
+        &self,
+        account_id: &str,
+    ) -> Result<Vec<SecondFactor>, anyhow::Error> {
+        let mut methods = vec![];
+        if self
+            .db
+            .get_account_mfa(account_id)
+            .await?
+            .is_some_and(|mfa| mfa.is_enabled())
+        {
+            methods.push(SecondFactor::Totp);
+        }
+        if !self.db.list_passkeys(account_id).await?.is_empty() {
+            methods.push(SecondFactor::Passkey);
+        }
+        Ok(methods)
+    }
+
+    /// Whether removing the last second factor of a kind leaves the account within the policy.
+    pub async fn second_factor_removable(
+        &self,
+        account_id: &str,
+        is_admin: bool,
+        require_admin_mfa: bool,
+        removing: SecondFactor,
+    ) -> Result<bool, anyhow::Error> {
+        if !(is_admin && require_admin_mfa) {
+            return Ok(true);
+        }
+
+        let remaining = match removing {
+            SecondFactor::Totp => self
+                .second_factors(account_id)
+                .await?
+                .contains(&SecondFactor::Passkey),
+            SecondFactor::Passkey => {
+                self.second_factors(account_id)
+                    .await?
+                    .contains(&SecondFactor::Totp)
+                    || self.db.list_passkeys(account_id).await?.len() > 1
+            }
+        };
+        Ok(remaining)
+    }
+
+    fn issue_mfa_challenge(
+        account_id: &str,
+        purpose: MfaPurpose,
+        methods: Vec<SecondFactor>,
+    ) -> MfaChallenge {
+        use rand::RngCore;
+
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let mfa_token = hex::encode(bytes);
+
+        let now = Utc::now();
+        let mut challenges = MFA_CHALLENGES.write().unwrap();
+        challenges.retain(|_, challenge| challenge.expires_at > now);
+        challenges.insert(
+            hash_secret(&mfa_token),
+            MfaChallengeState {
+                account_id: account_id.to_string(),
+                purpose,
+                expires_at: now + Duration::seconds(MFA_TOKEN_TTL),
+                attempts: 0,
+            },
+        );
+
+        MfaChallenge {
+            mfa_token,
+            enrollment_required: purpose == MfaPurpose::Enroll,
+            expires_in: MFA_TOKEN_TTL as u64,
+            methods: methods
+                .into_iter()
+                .map(|method| match method {
+                    SecondFactor::Totp => "totp".to_string(),
+                    SecondFactor::Passkey => "passkey".to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    /// The account an unexpired `mfa_token` of the given purpose was issued for.
+    pub fn mfa_challenge_account(mfa_token: &str, purpose: MfaPurpose) -> Option<String> {
+        MFA_CHALLENGES
+            .read()
+            .unwrap()
+            .get(&hash_secret(mfa_token))
+            .filter(|challenge| challenge.purpose == purpose && challenge.expires_at > Utc::now())
+            .map(|challenge| challenge.account_id.clone())
+    }
+
+    /// Counts an attempt to use an `mfa_token`, dropping it once it is used up.
+    pub(crate) fn attempt_mfa_challenge(
+        mfa_token: &str,
+        purpose: MfaPurpose,
+    ) -> Result<String, anyhow::Error> {
+        let key = hash_secret(mfa_token);
+        let mut challenges = MFA_CHALLENGES.write().unwrap();
+
+        let challenge = challenges
+            .get_mut(&key)
+            .filter(|challenge| challenge.purpose == purpose && challenge.expires_at > Utc::now())
+            .ok_or_else(|| anyhow::anyhow!("Unknown or expired mfa_token"))?;
+
+        challenge.attempts += 1;
+        let account_id = challenge.account_id.clone();
+        if challenge.attempts >= MAX_MFA_ATTEMPTS {
+            challenges.remove(&key);
+        }
+
+        Ok(account_id)
+    }
+
+    pub(crate) fn finish_mfa_challenge(mfa_token: &str) {
+        MFA_CHALLENGES
+            .write()
+            .unwrap()
+            .remove(&hash_secret(mfa_token));
+    }
+
+    /// Trades an `mfa_token` and a code from the authenticator app, or a recovery code,
+    /// for a session.
+    pub async fn complete_mfa_login(
+        &self,
+        mfa_token: &str,
+        code: &str,
+        client: &SessionClient,
+    ) -> Result<AccountLoginResponse, anyhow::Error> {
+        let account_id = self.verify_mfa_challenge(mfa_token, code).await?;
+
+        let account = self.db.get_account_by_id(&account_id).await?;
+        self.finish_account_login(account, client).await
+    }
+
+    /// Checks the code answering an `mfa_token` and returns the account it was issued for,
+    /// for sign-ins that do not start a session themselves.
+    pub async fn verify_mfa_challenge(
+        &self,
+        mfa_token: &str,
+        code: &str,
+    ) -> Result<String, anyhow::Error> {
+        let account_id = Self::attempt_mfa_challenge(mfa_token, MfaPurpose::Verify)?;
+
+        if !self.verify_second_factor(&account_id, code).await? {
+            warn!("Wrong second factor for account {}", account_id);
+            return Err(anyhow::anyhow!("Invalid code"));
+        }
+        Self::finish_mfa_challenge(mfa_token);
+
+        Ok(account_id)
+    }
+
+    /// Checks a code of the authenticator app or an unused recovery code. Either is used up.
+    /// Wrong codes are counted per account, too many lock the second factor with
+    /// `SecondFactorLocked`.
+    pub async fn verify_second_factor(
+        &self,
+        account_id: &str,
+        code: &str,
+    ) -> Result<bool, anyhow::Error> {
+        let Some(mfa) = self
+            .db
+            .get_account_mfa(account_id)
+            .await?
+            .filter(|mfa| mfa.is_enabled())
+        else {
+            return Ok(false);
+        };
+        let now = Utc::now();
+        if mfa.is_locked(now) {
+            return Err(SecondFactorLocked.into());
+        }
+
+        let valid = self.check_second_factor(&mfa, code, now).await?;
+        if valid {
+            if mfa.failed_attempts > 0 {
+                self.db.reset_mfa_failures(account_id).await?;
+            }
+        } else {
+            let locked_until = now + Duration::seconds(SECOND_FACTOR_LOCKOUT);
+            self.db
+                .record_mfa_failure(account_id, MAX_SECOND_FACTOR_FAILURES, locked_until)
+                .await?;
+            if mfa.failed_attempts + 1 >= MAX_SECOND_FACTOR_FAILURES {
+                warn!("Locked the second factor of account {}", account_id);
+            }
+        }
+        Ok(valid)
+    }
+
+    async fn check_second_factor(
+        &self,
+        mfa: &AccountMfa,
+        code: &str,
+        now: DateTime<Utc>,
+    ) -> Result<bool, anyhow::Error> {
+        if let Some(step) = totp::verify(&mfa.totp_secret, code, now) {
+            return self.db.use_totp_step(&mfa.account_id, step).await;
+        }
+
+        let recovery_code = normalize_recovery_code(code);
+        if recovery_code.len() == RECOVERY_CODE_LENGTH {
+            let used = self
+                .db
+                .use_recovery_code(&mfa.account_id, &hash_secret(&recovery_code), now)
+                .await?;
+            if used {
+                info!("Account {} signed in with a recovery code", mfa.account_id);
+            }
+            return Ok(used);
+        }
+
+        Ok(false)
+    }
+
+    /// Checks a code of the authenticator app only.
+    async fn verify_totp(&self, mfa: &AccountMfa, code: &str) -> Result<bool, anyhow::Error> {
+        match totp::verify(&mfa.totp_secret, code, Utc::now()) {
+            Some(step) => self.db.use_totp_step(&mfa.account_id, step).await,
+            None => Ok(false),
+        }
+    }
+
+    pub async fn mfa_status(
+        &self,
+        account_id: &str,
+        is_admin: bool,
+        require_admin_mfa: bool,
+    ) -> Result<MfaStatus, anyhow::Error> {
+        let mfa = self
+            .db
+            .get_account_mfa(account_id)
+            .await?
+            .filter(|mfa| mfa.is_enabled());
+        let recovery_codes_left = match mfa {
+            Some(_) => self.db.count_unused_recovery_codes(account_id).await?,
+            None => 0,
+        };
+
+        Ok(MfaStatus {
+            totp_enabled: mfa.is_some(),
+            enabled_at: mfa.and_then(|mfa| mfa.enabled_at),
+            recovery_codes_left,
+            passkeys: self.db.list_passkeys(account_id).await?.len(),
+            required: is_admin && require_admin_mfa,
+        })
+    }
+
+    /// Creates a new TOTP secret awaiting confirmation, replacing an unconfirmed one.
+    pub async fn start_totp_enrollment(
+        &self,
+        account_id: &str,
+    ) -> Result<TotpEnrollment, anyhow::Error> {
+        if self
+            .db
+            .get_account_mfa(account_id)
+            .await?
+            .is_some_and(|mfa| mfa.is_enabled())
+        {
+            return Err(anyhow::anyhow!(
+                "Two-factor authentication is already enabled"
+            ));
+        }
+        let account = self.db.get_account_by_id(account_id).await?;
+
+        let secret = totp::generate_secret();
+        self.db
+            .set_pending_totp_secret(account_id, &secret, Utc::now())
+            .await?;
+
+        Ok(TotpEnrollment {
+            provisioning_uri: totp::provisioning_uri(TOTP_ISSUER, &account.email, &secret),
+            secret,
+        })
+    }
+
+    /// Enables the pending TOTP secret once the app shows a matching code.
+    /// Returns the new recovery codes, which are not shown again.
+    pub async fn confirm_totp_enrollment(
+        &self,
+        account_id: &str,
+        code: &str,
+    ) -> Result<Vec<String>, anyhow::Error> {
+        let mfa = self
+            .db
+            .get_account_mfa(account_id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("No enrollment was started"))?;
+        if mfa.is_enabled() {
+            return Err(anyhow::anyhow!(
+                "Two-factor authentication is already enabled"
+            ));
+        }
+
+        let step = totp::verify(&mfa.totp_secret, code, Utc::now())
+            .ok_or_else(|| anyhow::anyhow!("Invalid code"))?;
+
+        let codes = generate_recovery_codes();
+        let hashes: Vec<String> = codes
+            .iter()
+            .map(|code| hash_secret(&normalize_recovery_code(code)))
+            .collect();
+        self.db
+            .enable_totp(account_id, step, &hashes, Utc::now())
+            .await?;
+
+        info!(
+            "Enabled two-factor authentication for account {}",
+            account_id
+        );
+        Ok(codes)
+    }
+
+    /// Confirms the enrollment an admin was asked for during login and starts the session.
+    pub async fn complete_mfa_enrollment(
+        &self,
+        mfa_token: &str,
+        code: &str,
+        client: &SessionClient,
+    ) -> Result<(Vec<String>, AccountLoginResponse), anyhow::Error> {
+        let account_id = Self::attempt_mfa_challenge(mfa_token, MfaPurpose::Enroll)?;
+
+        let codes = self.confirm_totp_enrollment(&account_id, code).await?;
+        Self::finish_mfa_challenge(mfa_token);
+
+        let account = self.db.get_account_by_id(&account_id).await?;
+        let response = self.finish_account_login(account, client).await?;
+        Ok((codes, response))
+    }
+
+    /// Removes the second factor after checking a code or a recovery code.
+    pub async fn disable_totp(
+        &self,
+        account_id: &str,
+        is_admin: bool,
+        require_admin_mfa: bool,
+        code: &str,
+    ) -> Result<(), anyhow::Error> {
+        if !self
+            .second_factor_removable(account_id, is_admin, require_admin_mfa, SecondFactor::Totp)
+            .await?
+        {
+            return Err(anyhow::anyhow!(
+                "Two-factor authentication is required for admin accounts"
+            ));
+        }
+        if !self.verify_second_factor(account_id, code).await? {
+            return Err(anyhow::anyhow!("Invalid code"));
+        }
+
+        self.db.delete_account_mfa(account_id).await?;
+        info!(
+            "Disabled two-factor authentication for account {}",
+            account_id
+        );
+        Ok(())
+    }
+
+    /// Replaces all recovery codes after checking a code of the authenticator app.
+    pub async fn regenerate_recovery_codes(
+        &self,
+        account_id: &str,
+        code: &str,
+    ) -> Result<Vec<String>, anyhow::Error> {
+        let mfa = self
+            .db
+            .get_account_mfa(account_id)
+            .await?
+            .filter(|mfa| mfa.is_enabled())
+            .ok_or_else(|| anyhow::anyhow!("Two-factor authentication is not enabled"))?;
+
+        if !self.verify_totp(&mfa, code).await? {
+            return Err(anyhow::anyhow!("Invalid code"));
+        }
+
+        let codes = generate_recovery_codes();
+        let hashes: Vec<String> = codes
+            .iter()
+            .map(|code| hash_secret(&normalize_recovery_code(code)))
+            .collect();
+        self.db.replace_recovery_codes(account_id, &hashes).await?;
+
+        Ok(codes)
+    }
+
+    /// Starts the session of an account whose credentials, and second factor, were checked.
+    pub async fn finish_account_login(
+        &self,
+        account: Account,
+        client: &SessionClient,
+    ) -> Result<AccountLoginResponse, anyhow::Error> {
+        let is_admin = self
+            .db
+            .is_account_admin(&account.account_id)
+            .await
+            .unwrap_or(false);
+        let tokens = self
+            .start_session(&account.account_id, "account", is_admin, client)
+            .await?;
+
+        info!("Account verified successfully: {}", account.email);
+
+        Ok(AccountLoginResponse {
+            account_id: account.account_id,
+            email: account.email,
+            display_name: account.display_name,
+            jwt_token: Some(tokens.access_token),
+            refresh_token: Some(tokens.refresh_token),
+            expires_in: Some(tokens.expires_in),
+        })
+    }
+}
+
+/// Codes like `k7mq-3xtp`, shown once and stored as hashes.
+fn generate_recovery_codes() -> Vec<String> {
+    let mut rng = rand::thread_rng();
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code: String = (0..RECOVERY_CODE_LENGTH)
+                .map(|_| {
+                    RECOVERY_CODE_ALPHABET[rng.gen_range(0..RECOVERY_CODE_ALPHABET.len())] as char
+                })
+                .collect();
+            format!("{}-{}", &code[..4], &code[4..])
+        })
+        .collect()
+}
+
+/// Ignores case, dashes and whitespace, as users type codes off paper.
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_ascii_lowercase()
+}
+
+fn hash_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recovery_codes_are_unique_and_normalized() {
+        // when
+        let codes = generate_recovery_codes();
+
+        // then
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        assert!(codes
+            .iter()
+            .all(|code| code.len() == RECOVERY_CODE_LENGTH + 1));
+        let normalized = normalize_recovery_code(&format!(" {} ", codes[0].to_uppercase()));
+        assert_eq!(normalized, codes[0].replace('-', ""));
+    }
+
+    #[test]
+    fn test_mfa_token_after_too_many_attempts_should_fail() {
+        // given
+        let challenge = AuthManager::issue_mfa_challenge(
+            "account-1",
+            MfaPurpose::Verify,
+            vec![SecondFactor::Totp],
+        );
+
+        // when
+        for _ in 0..MAX_MFA_ATTEMPTS {
+            assert!(
+                AuthManager::attempt_mfa_challenge(&challenge.mfa_token, MfaPurpose::Verify)
+                    .is_ok()
+            );
+        }
+
+        // then
+        assert!(
+            AuthManager::attempt_mfa_challenge(&challenge.mfa_token, MfaPurpose::Verify).is_err()
+        );
+    }
+
+    #[test]
+    fn test_mfa_token_for_other_purpose_should_fail() {
+        // given
+        let challenge = AuthManager::issue_mfa_challenge("account-2", MfaPurpose::Enroll, vec![]);
+
+        // then
+        assert!(challenge.enrollment_required);
+        assert_eq!(
+            AuthManager::mfa_challenge_account(&challenge.mfa_token, MfaPurpose::Verify),
+            None
+        );
+        assert_eq!(
+            AuthManager::mfa_challenge_account(&challenge.mfa_token, MfaPurpose::Enroll).as_deref(),
+            Some("account-2")
+        );
+    }
+}
@@ -1,8 +1,16 @@
 pub mod account;
+pub mod account_token;
 pub mod account_with_albums;
 pub mod album_account;
 pub mod auth_manager;
 pub mod customer;
+pub mod keys;
 pub mod login;
+pub mod mfa;
+pub mod passkey;
 pub mod permissions;
+pub mod session;
+pub mod signed_url;
+pub mod totp;
 pub mod user;
+pub mod webauthn;
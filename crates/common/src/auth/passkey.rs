@@ -0,0 +1,475 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Passkeys of accounts: WebAuthn credentials used for passwordless sign-in, or as second
+//! factor after a password.
+//!
+//! Ceremony options and responses use the JSON shapes of `PublicKeyCredential.toJSON()`,
+//! binary values encoded as base64url. Challenges are kept in memory until answered.
+
+use std::collections::HashMap;
+use std::sync::{LazyLock, RwLock};
+
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::auth::account::Account;
+use crate::auth::auth_manager::AuthManager;
+use crate::auth::mfa::{MfaPurpose, SecondFactor};
+use crate::auth::webauthn::{
+    self, ClientData, RelyingParty, BASE64URL, COSE_ALG_ES256, COSE_ALG_RS256,
+};
+
+/// Milliseconds an authenticator prompt stays open, and lifetime of its challenge.
+pub const CEREMONY_TIMEOUT_MS: u64 = 5 * 60 * 1000;
+
+/// Ceremonies waiting for the authenticator, by challenge.
+static PASSKEY_CEREMONIES: LazyLock<RwLock<HashMap<String, PasskeyCeremony>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CeremonyKind {
+    Registration,
+    Authentication,
+}
+
+#[derive(Debug, Clone)]
+struct PasskeyCeremony {
+    kind: CeremonyKind,
+    /// Registering account, or the account a second factor is asked from.
+    /// Passwordless sign-ins learn the account from the credential.
+    account_id: Option<String>,
+    expires_at: DateTime<Utc>,
+}
+
+/// A registered credential.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct Passkey {
+    /// base64url, as in the `id` of the credential.
+    pub credential_id: String,
+    #[serde(skip_serializing)]
+    pub account_id: String,
+    pub name: String,
+    #[serde(skip_serializing)]
+    pub public_key: String,
+    pub algorithm: i64,
+    #[serde(skip_serializing)]
+    pub sign_count: i64,
+    pub transports: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RelyingPartyEntity {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserEntity {
+    /// base64url of the account ID, returned as `userHandle` by passwordless sign-ins.
+    pub id: String,
+    pub name: String,
+    pub display_name: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CredentialParameters {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub alg: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CredentialDescriptor {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub id: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub transports: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticatorSelection {
+    pub resident_key: &'static str,
+    pub user_verification: &'static str,
+}
+
+/// `PublicKeyCredentialCreationOptions` for `navigator.credentials.create()`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreationOptions {
+    pub rp: RelyingPartyEntity,
+    pub user: UserEntity,
+    pub challenge: String,
+    pub pub_key_cred_params: Vec<CredentialParameters>,
+    pub timeout: u64,
+    pub exclude_credentials: Vec<CredentialDescriptor>,
+    pub authenticator_selection: AuthenticatorSelection,
+    pub attestation: &'static str,
+}
+
+/// `PublicKeyCredentialRequestOptions` for `navigator.credentials.get()`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestOptions {
+    pub challenge: String,
+    pub timeout: u64,
+    pub rp_id: String,
+    pub allow_credentials: Vec<CredentialDescriptor>,
+    pub user_verification: &'static str,
+}
+
+/// Response of `navigator.credentials.create()`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RegistrationCredential {
+    pub id: String,
+    #[serde(default, rename = "rawId")]
+    pub raw_id: Option<String>,
+    pub response: AttestationResponse,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub attestation_object: String,
+    #[serde(default)]
+    pub transports: Vec<String>,
+}
+
+/// Response of `navigator.credentials.get()`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AssertionCredential {
+    pub id: String,
+    #[serde(default, rename = "rawId")]
+    pub raw_id: Option<String>,
+    pub response: AssertionResponse,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+    #[serde(default)]
+    pub user_handle: Option<String>,
+}
+
+impl AuthManager {
+    fn start_ceremony(kind: CeremonyKind, account_id: Option<String>) -> String {
+        use rand::RngCore;
+
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let challenge = BASE64URL.encode(bytes);
+
+        let now = Utc::now();
+        let mut ceremonies = PASSKEY_CEREMONIES.write().unwrap();
+        ceremonies.retain(|_, ceremony| ceremony.expires_at > now);
+        ceremonies.insert(
+            challenge.clone(),
+            PasskeyCeremony {
+                kind,
+                account_id,
+                expires_at: now + Duration::milliseconds(CEREMONY_TIMEOUT_MS as i64),
+            },
+        );
+
+        challenge
+    }
+
+    /// Takes the ceremony a `clientDataJSON` answers. Each challenge is accepted once.
+    fn take_ceremony(
+        kind: CeremonyKind,
+        client_data_json: &[u8],
+    ) -> Result<(String, PasskeyCeremony), anyhow::Error> {
+        let challenge = ClientData::parse(client_data_json)?.challenge;
+        let ceremony = PASSKEY_CEREMONIES
+            .write()
+            .unwrap()
+            .remove(&challenge)
+            .filter(|ceremony| ceremony.kind == kind && ceremony.expires_at > Utc::now())
+            .ok_or_else(|| anyhow::anyhow!("Unknown or expired challenge"))?;
+
+        Ok((challenge, ceremony))
+    }
+
+    /// Options to create a passkey for a signed in account.
+    pub async fn passkey_registration_options(
+        &self,
+        rp: &RelyingParty,
+        account_id: &str,
+    ) -> Result<CreationOptions, anyhow::Error> {
+        let account = self.db.get_account_by_id(account_id).await?;
+        let existing = self.db.list_passkeys(account_id).await?;
+
+        Ok(CreationOptions {
+            rp: RelyingPartyEntity {
+                id: rp.id.clone(),
+                name: rp.name.clone(),
+            },
+            user: UserEntity {
+                id: BASE64URL.encode(account.account_id.as_bytes()),
+                display_name: account
+                    .display_name
+                    .clone()
+                    .unwrap_or_else(|| account.email.clone()),
+                name: account.email,
+            },
+            challenge: Self::start_ceremony(CeremonyKind::Registration, Some(account.account_id)),
+            pub_key_cred_params: [COSE_ALG_ES256, COSE_ALG_RS256]
+                .into_iter()
+                .map(|alg| CredentialParameters {
+                    kind: "public-key",
+                    alg,
+                })
+                .collect(),
+            timeout: CEREMONY_TIMEOUT_MS,
+            exclude_credentials: existing.iter().map(Passkey::descriptor).collect(),
+            authenticator_selection: AuthenticatorSelection {
+                resident_key: "preferred",
+                user_verification: "preferred",
+            },
+            attestation: "none",
+        })
+    }
+
+    /// Stores the passkey created with options of `passkey_registration_options`.
+    pub async fn register_passkey(
+        &self,
+        rp: &RelyingParty,
+        account_id: &str,
+        name: Option<String>,
+        credential: &RegistrationCredential,
+    ) -> Result<Passkey, anyhow::Error> {
+        let client_data_json = BASE64URL.decode(&credential.response.client_data_json)?;
+        let (challenge, ceremony) =
+            Self::take_ceremony(CeremonyKind::Registration, &client_data_json)?;
+        if ceremony.account_id.as_deref() != Some(account_id) {
+            return Err(anyhow::anyhow!("Challenge was issued to another account"));
+        }
+
+        let registration = webauthn::verify_registration(
+            rp,
+            &challenge,
+            &decode_credential_id(&credential.id, credential.raw_id.as_deref())?,
+            &client_data_json,
+            &BASE64URL.decode(&credential.response.attestation_object)?,
+        )?;
+
+        let credential_id = BASE64URL.encode(&registration.credential_id);
+        if self.db.get_passkey(&credential_id).await?.is_some() {
+            return Err(anyhow::anyhow!("Passkey is already registered"));
+        }
+
+        let now = Utc::now();
+        let passkey = Passkey {
+            credential_id,
+            account_id: account_id.to_string(),
+            name: name
+                .map(|name| name.trim().to_string())
+                .filter(|name| !name.is_empty())
+                .unwrap_or_else(|| "Passkey".to_string()),
+            public_key: BASE64URL.encode(&registration.public_key),
+            algorithm: registration.algorithm,
+            sign_count: registration.sign_count as i64,
+            transports: Some(credential.response.transports.join(",")).filter(|t| !t.is_empty()),
+            created_at: now,
+            last_used_at: None,
+        };
+        self.db.create_passkey(&passkey).await?;
+
+        info!(
+            "Registered passkey '{}' for account {}",
+            passkey.name, account_id
+        );
+        Ok(passkey)
+    }
+
+    /// Options to sign in with a passkey. Without an `mfa_token` any discoverable passkey of
+    /// this server is offered; with one, only passkeys of its account are, as second factor.
+    pub async fn passkey_login_options(
+        &self,
+        rp: &RelyingParty,
+        mfa_token: Option<&str>,
+    ) -> Result<RequestOptions, anyhow::Error> {
+        let (account_id, allow_credentials, user_verification) = match mfa_token {
+            Some(mfa_token) => {
+                let account_id = Self::mfa_challenge_account(mfa_token, MfaPurpose::Verify)
+                    .ok_or_else(|| anyhow::anyhow!("Unknown or expired mfa_token"))?;
+                let passkeys = self.db.list_passkeys(&account_id).await?;
+                if passkeys.is_empty() {
+                    return Err(anyhow::anyhow!("Account has no passkey"));
+                }
+                let allowed = passkeys.iter().map(Passkey::descriptor).collect();
+                (Some(account_id), allowed, "preferred")
+            }
+            None => (None, vec![], "required"),
+        };
+
+        Ok(RequestOptions {
+            challenge: Self::start_ceremony(CeremonyKind::Authentication, account_id),
+            timeout: CEREMONY_TIMEOUT_MS,
+            rp_id: rp.id.clone(),
+            allow_credentials,
+            user_verification,
+        })
+    }
+
+    /// Checks the answer to options of `passkey_login_options` and returns the account
+    /// signing in. Passwordless sign-ins need a passkey that verified the user, e.g. by
+    /// fingerprint or PIN; as second factor the `mfa_token` is used up.
+    pub async fn authenticate_with_passkey(
+        &self,
+        rp: &RelyingParty,
+        credential: &AssertionCredential,
+        mfa_token: Option<&str>,
+    ) -> Result<Account, anyhow::Error> {
+        let response = &credential.response;
+        let client_data_json = BASE64URL.decode(&response.client_data_json)?;
+        let (challenge, ceremony) =
+            Self::take_ceremony(CeremonyKind::Authentication, &client_data_json)?;
+
+        let passkey = self
+            .db
+            .get_passkey(&BASE64URL.encode(decode_credential_id(
+                &credential.id,
+                credential.raw_id.as_deref(),
+            )?))
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Unknown passkey"))?;
+        if ceremony
+            .account_id
+            .as_ref()
+            .is_some_and(|id| *id != passkey.account_id)
+        {
+            return Err(anyhow::anyhow!("Passkey belongs to another account"));
+        }
+        if let Some(user_handle) = response.user_handle.as_deref().filter(|h| !h.is_empty()) {
+            if BASE64URL.decode(user_handle)? != passkey.account_id.as_bytes() {
+                return Err(anyhow::anyhow!("Passkey belongs to another account"));
+            }
+        }
+
+        let assertion = webauthn::verify_assertion(
+            rp,
+            &challenge,
+            &BASE64URL.decode(&passkey.public_key)?,
+            &client_data_json,
+            &BASE64URL.decode(&response.authenticator_data)?,
+            &BASE64URL.decode(&response.signature)?,
+        )?;
+
+        // Authenticators count their signatures, a counter that does not grow points to a clone.
+        let sign_count = assertion.sign_count as i64;
+        if (sign_count != 0 || passkey.sign_count != 0) && sign_count <= passkey.sign_count {
+            warn!(
+                "Signature counter of passkey {} went backwards",
+                passkey.credential_id
+            );
+            return Err(anyhow::anyhow!("Passkey may have been cloned"));
+        }
+
+        match (mfa_token, &ceremony.account_id) {
+            (Some(mfa_token), Some(account_id)) => {
+                if Self::attempt_mfa_challenge(mfa_token, MfaPurpose::Verify)? != *account_id {
+                    return Err(anyhow::anyhow!("mfa_token belongs to another account"));
+                }
+                Self::finish_mfa_challenge(mfa_token);
+            }
+            (None, None) if assertion.user_verified => {}
+            (None, None) => return Err(anyhow::anyhow!("Passkey did not verify the user")),
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Challenge was issued for another kind of sign-in"
+                ))
+            }
+        }
+
+        self.db
+            .update_passkey_usage(&passkey.credential_id, sign_count, Utc::now())
+            .await?;
+        self.db.get_account_by_id(&passkey.account_id).await
+    }
+
+    /// Removes a passkey unless it is the last second factor the admin policy requires.
+    pub async fn remove_passkey(
+        &self,
+        account_id: &str,
+        is_admin: bool,
+        require_admin_mfa: bool,
+        credential_id: &str,
+    ) -> Result<bool, anyhow::Error> {
+        if !self
+            .second_factor_removable(
+                account_id,
+                is_admin,
+                require_admin_mfa,
+                SecondFactor::Passkey,
+            )
+            .await?
+        {
+            return Err(anyhow::anyhow!(
+                "Two-factor authentication is required for admin accounts"
+            ));
+        }
+
+        let removed = self.db.delete_passkey(account_id, credential_id).await?;
+        if removed {
+            info!(
+                "Removed passkey {} of account {}",
+                credential_id, account_id
+            );
+        }
+        Ok(removed)
+    }
+}
+
+impl Passkey {
+    fn descriptor(&self) -> CredentialDescriptor {
+        CredentialDescriptor {
+            kind: "public-key",
+            id: self.credential_id.clone(),
+            transports: self
+                .transports
+                .iter()
+                .flat_map(|t| t.split(','))
+                .map(String::from)
+                .collect(),
+        }
+    }
+}
+
+/// Decodes the `id` of a credential, which `rawId` has to repeat when the client sends both.
+fn decode_credential_id(id: &str, raw_id: Option<&str>) -> Result<Vec<u8>, anyhow::Error> {
+    let decoded = BASE64URL.decode(id)?;
+    if let Some(raw_id) = raw_id {
+        if BASE64URL.decode(raw_id)? != decoded {
+            return Err(anyhow::anyhow!("id and rawId of the credential differ"));
+        }
+    }
+    Ok(decoded)
+}
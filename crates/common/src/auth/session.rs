@@ -0,0 +1,179 @@
+use std::net::{IpAddr, SocketAddr};
+
+use chrono::{DateTime, Utc};
+use http::HeaderMap;
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+
+/// A signed in device. Its refresh token rotates on every use, only hashes are stored.
+#[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow)]
+pub struct Session {
+    pub session_id: String,
+    pub subject_id: String,
+    pub role: String,
+    #[serde(skip)]
+    pub refresh_token_hash: String,
+    #[serde(skip)]
+    pub previous_token_hash: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl Session {
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.revoked_at.is_none() && self.expires_at > now
+    }
+}
+
+/// Proxies whose `X-Forwarded-For` header is believed, read from the `auth` configuration.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies(Vec<IpNet>);
+
+impl TrustedProxies {
+    /// Parses the configured proxies, addresses or networks like `10.0.0.0/8`.
+    pub fn from_config(proxies: &[String]) -> Result<Self, anyhow::Error> {
+        proxies
+            .iter()
+            .map(|proxy| parse_trusted_proxy(proxy))
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+
+    fn contains(&self, ip: &IpAddr) -> bool {
+        self.0.iter().any(|net| net.contains(ip))
+    }
+}
+
+/// Device and address a session was started or last refreshed from, shown in session listings.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SessionClient {
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+impl SessionClient {
+    /// Takes the address from `X-Forwarded-For` when the peer is a trusted proxy, the peer otherwise.
+    pub fn from_request(
+        headers: &HeaderMap,
+        peer: Option<SocketAddr>,
+        proxies: &TrustedProxies,
+    ) -> Self {
+        let user_agent = headers
+            .get(http::header::USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.chars().take(512).collect());
+
+        Self {
+            user_agent,
+            ip_address: peer.map(|p| client_address(headers, p.ip(), proxies).to_string()),
+        }
+    }
+}
+
+/// Follows `X-Forwarded-For` from the peer backwards while the hops are trusted proxies.
+/// Each proxy appends the address it received the request from, so the first untrusted hop
+/// is the client. Addresses further left were sent by the client and could be made up, so
+/// they are never parsed: the walk stops at the last trusted hop if one is not an address.
+fn client_address(headers: &HeaderMap, peer: IpAddr, proxies: &TrustedProxies) -> IpAddr {
+    if !proxies.contains(&peer) {
+        return peer;
+    }
+
+    let forwarded: Vec<&str> = headers
+        .get_all("X-Forwarded-For")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .collect();
+
+    let mut client = peer;
+    for hop in forwarded.into_iter().rev() {
+        let Ok(hop) = hop.trim().parse::<IpAddr>() else {
+            break;
+        };
+        client = hop;
+        if !proxies.contains(&hop) {
+            break;
+        }
+    }
+    client
+}
+
+/// Parses a trusted proxy, either an address or a network like `10.0.0.0/8`.
+pub fn parse_trusted_proxy(proxy: &str) -> Result<IpNet, anyhow::Error> {
+    proxy
+        .parse::<IpNet>()
+        .or_else(|_| proxy.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| anyhow::anyhow!("Invalid trusted proxy {}", proxy))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_client_behind_trusted_proxy() {
+        // given
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "X-Forwarded-For",
+            "198.51.100.1, 203.0.113.7, 10.0.0.3".parse().unwrap(),
+        );
+        headers.insert(
+            http::header::USER_AGENT,
+            "Photos/1.0 (iPhone)".parse().unwrap(),
+        );
+        let peer: SocketAddr = "10.0.0.2:51234".parse().unwrap();
+        let proxies = TrustedProxies::from_config(&["10.0.0.0/8".into()]).unwrap();
+
+        // when
+        let client = SessionClient::from_request(&headers, Some(peer), &proxies);
+
+        // then
+        assert_eq!(client.ip_address.as_deref(), Some("203.0.113.7"));
+        assert_eq!(client.user_agent.as_deref(), Some("Photos/1.0 (iPhone)"));
+        assert_eq!(
+            SessionClient::from_request(&HeaderMap::new(), Some(peer), &proxies)
+                .ip_address
+                .as_deref(),
+            Some("10.0.0.2")
+        );
+    }
+
+    #[test]
+    fn test_session_client_ignores_untrusted_forwarded_for() {
+        // given
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Forwarded-For", "203.0.113.7".parse().unwrap());
+        let peer: SocketAddr = "198.51.100.9:51234".parse().unwrap();
+        let proxies = TrustedProxies::from_config(&["127.0.0.1".into()]).unwrap();
+
+        // when
+        let client = SessionClient::from_request(&headers, Some(peer), &proxies);
+
+        // then
+        assert_eq!(client.ip_address.as_deref(), Some("198.51.100.9"));
+        assert!(parse_trusted_proxy("proxy.local").is_err());
+    }
+    #[test]
+    fn test_session_client_skips_malformed_forwarded_for_hops() {
+        // given
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "X-Forwarded-For",
+            "not-an-address, 203.0.113.7, 10.0.0.3".parse().unwrap(),
+        );
+        let peer: SocketAddr = "10.0.0.2:51234".parse().unwrap();
+        let proxies = TrustedProxies::from_config(&["10.0.0.0/8".into()]).unwrap();
+
+        // when
+        let client = SessionClient::from_request(&headers, Some(peer), &proxies);
+
+        // then
+        assert_eq!(client.ip_address.as_deref(), Some("203.0.113.7"));
+    }
+}
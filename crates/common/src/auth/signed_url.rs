@@ -0,0 +1,301 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! HMAC-signed, time-limited media URLs.
+//!
+//! Browsers cannot attach an `Authorization` header to `<img src>` requests, so the API hands
+//! out URLs whose query string carries the caller identity, the rendition and an expiry,
+//! all covered by an HMAC-SHA256 signature. URLs issued to a session name it, so they stop
+//! working when the session ends.
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+
+use crate::auth::keys::KeyRing;
+use crate::rendition::Rendition;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Lifetime of a signed URL handed out to clients.
+pub const DEFAULT_TTL_SECONDS: i64 = 3600;
+
+/// Query parameters of a signed media URL.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SignedMediaQuery {
+    #[serde(default)]
+    pub rendition: Rendition,
+    pub sub: String,
+    pub role: String,
+    #[serde(default)]
+    pub sid: Option<String>,
+    /// The album the media is viewed in, which decides e.g. the embedded copyright.
+    #[serde(default)]
+    pub album: Option<String>,
+    pub exp: i64,
+    pub sig: String,
+}
+
+/// Identity a verified URL was issued to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedMediaAccess {
+    pub rendition: Rendition,
+    pub sub: String,
+    pub role: String,
+    pub session_id: Option<String>,
+    pub album_id: Option<String>,
+    pub expires_at: DateTime<Utc>,
+}
+
+fn signature(secret: &[u8], media_id: &str, query: &SignedMediaQuery) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    // Newline separated so no field can bleed into its neighbour.
+    mac.update(
+        format!(
+            "{}\n{}\n{}\n{}\n{}\n{}\n{}",
+            media_id,
+            query.rendition,
+            query.sub,
+            query.role,
+            query.sid.as_deref().unwrap_or_default(),
+            query.album.as_deref().unwrap_or_default(),
+            query.exp
+        )
+        .as_bytes(),
+    );
+    mac
+}
+
+/// Builds a signed URL for `media_id` seen in `album_id`, valid for `ttl`, or until
+/// `session_id` ends.
+#[allow(clippy::too_many_arguments)]
+pub fn sign_media_url(
+    keys: &KeyRing,
+    media_id: &str,
+    rendition: Rendition,
+    sub: &str,
+    role: &str,
+    session_id: Option<&str>,
+    album_id: Option<&str>,
+    ttl: Duration,
+) -> (String, DateTime<Utc>) {
+    let expires_at = Utc::now() + ttl;
+    let mut query = SignedMediaQuery {
+        rendition,
+        sub: sub.to_string(),
+        role: role.to_string(),
+        sid: session_id.map(str::to_string),
+        album: album_id.map(str::to_string),
+        exp: expires_at.timestamp(),
+        sig: String::new(),
+    };
+    let secret = keys.hmac_secrets().next().unwrap_or_default();
+    query.sig = hex::encode(signature(secret, media_id, &query).finalize().into_bytes());
+
+    let exp = query.exp.to_string();
+    let mut params = vec![
+        ("rendition", rendition.as_str()),
+        ("sub", sub),
+        ("role", role),
+    ];
+    if let Some(sid) = session_id {
+        params.push(("sid", sid));
+    }
+    if let Some(album) = album_id {
+        params.push(("album", album));
+    }
+    params.extend([("exp", exp.as_str()), ("sig", query.sig.as_str())]);
+    let query = serde_urlencoded::to_string(params).unwrap_or_default();
+
+    (format!("/media/{}/signed?{}", media_id, query), expires_at)
+}
+
+/// Checks signature and expiry of a signed URL for `media_id`.
+pub fn verify_media_url(
+    keys: &KeyRing,
+    media_id: &str,
+    query: &SignedMediaQuery,
+) -> Result<SignedMediaAccess, anyhow::Error> {
+    let sig = hex::decode(&query.sig).map_err(|_| anyhow::anyhow!("Malformed signature"))?;
+
+    // URLs signed before a key rotation stay valid until they expire.
+    let valid = keys.hmac_secrets().any(|secret| {
+        signature(secret, media_id, query)
+            .verify_slice(&sig)
+            .is_ok()
+    });
+    if !valid {
+        return Err(anyhow::anyhow!("Invalid signature"));
+    }
+
+    let expires_at = Utc
+        .timestamp_opt(query.exp, 0)
+        .single()
+        .ok_or_else(|| anyhow::anyhow!("Invalid expiry"))?;
+    if expires_at <= Utc::now() {
+        return Err(anyhow::anyhow!("Signed URL has expired"));
+    }
+
+    Ok(SignedMediaAccess {
+        rendition: query.rendition,
+        sub: query.sub.clone(),
+        role: query.role.clone(),
+        session_id: query.sid.clone(),
+        album_id: query.album.clone(),
+        expires_at,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::keys::JwtKey;
+
+    fn keys() -> KeyRing {
+        KeyRing::new(vec![JwtKey::new("test", b"test-secret")], None).unwrap()
+    }
+
+    fn query_of(url: &str) -> SignedMediaQuery {
+        let (_, query) = url.split_once('?').unwrap();
+        serde_urlencoded::from_str(query).unwrap()
+    }
+
+    #[test]
+    fn test_signed_url_roundtrip() {
+        // given
+        let keys = keys();
+        let (url, _) = sign_media_url(
+            &keys,
+            "m1",
+            Rendition::Small,
+            "c1",
+            "customer",
+            None,
+            None,
+            Duration::minutes(5),
+        );
+
+        // when
+        let access = verify_media_url(&keys, "m1", &query_of(&url)).unwrap();
+
+        // then
+        assert_eq!(access.rendition, Rendition::Small);
+        assert_eq!(access.sub, "c1");
+        assert_eq!(access.role, "customer");
+    }
+
+    #[test]
+    fn test_signed_url_rejects_tampering() {
+        // given
+        let keys = keys();
+        let (url, _) = sign_media_url(
+            &keys,
+            "m1",
+            Rendition::Small,
+            "c1",
+            "customer",
+            None,
+            None,
+            Duration::minutes(5),
+        );
+        let mut query = query_of(&url);
+        query.rendition = Rendition::Original;
+
+        // when
+        let tampered = verify_media_url(&keys, "m1", &query);
+        let other_media = verify_media_url(&keys, "m2", &query_of(&url));
+
+        // then
+        assert!(tampered.is_err());
+        assert!(other_media.is_err());
+    }
+
+    #[test]
+    fn test_signed_url_binds_session() {
+        // given
+        let keys = keys();
+        let (url, _) = sign_media_url(
+            &keys,
+            "m1",
+            Rendition::Small,
+            "a1",
+            "account",
+            Some("s1"),
+            None,
+            Duration::minutes(5),
+        );
+        let mut query = query_of(&url);
+
+        // when
+        let access = verify_media_url(&keys, "m1", &query).unwrap();
+        query.sid = None;
+        let unbound = verify_media_url(&keys, "m1", &query);
+
+        // then
+        assert_eq!(access.session_id.as_deref(), Some("s1"));
+        assert!(unbound.is_err());
+    }
+
+    #[test]
+    fn test_signed_url_binds_album() {
+        // given
+        let keys = keys();
+        let (url, _) = sign_media_url(
+            &keys,
+            "m1",
+            Rendition::Small,
+            "c1",
+            "customer",
+            None,
+            Some("album1"),
+            Duration::minutes(5),
+        );
+        let mut query = query_of(&url);
+
+        // when
+        let access = verify_media_url(&keys, "m1", &query).unwrap();
+        query.album = Some("album2".into());
+        let moved = verify_media_url(&keys, "m1", &query);
+
+        // then
+        assert_eq!(access.album_id.as_deref(), Some("album1"));
+        assert!(moved.is_err());
+    }
+
+    #[test]
+    fn test_signed_url_rejects_expired() {
+        // given
+        let keys = keys();
+        let (url, _) = sign_media_url(
+            &keys,
+            "m1",
+            Rendition::Large,
+            "c1",
+            "customer",
+            None,
+            None,
+            Duration::seconds(-1),
+        );
+
+        // when
+        let result = verify_media_url(&keys, "m1", &query_of(&url));
+
+        // then
+        assert!(result.is_err());
+    }
+}
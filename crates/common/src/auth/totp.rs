@@ -0,0 +1,158 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Time-based one-time passwords (RFC 6238).
+//!
+//! Uses the parameters every authenticator app understands: HMAC-SHA1, six digits and
+//! a 30 second period. Codes of the previous and the next period are accepted as well,
+//! to cope with clocks drifting apart.
+
+use chrono::{DateTime, Utc};
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha1::Sha1;
+use subtle::ConstantTimeEq;
+use url::Url;
+
+type HmacSha1 = Hmac<Sha1>;
+
+pub const DIGITS: u32 = 6;
+pub const PERIOD_SECONDS: i64 = 30;
+
+/// Periods before and after the current one whose codes are still accepted.
+const ALLOWED_DRIFT: i64 = 1;
+
+/// A new random secret, base32 encoded as authenticator apps expect it.
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; 20];
+    rand::thread_rng().fill_bytes(&mut secret);
+    BASE32_NOPAD.encode(&secret)
+}
+
+/// The time step `now` falls into.
+pub fn time_step(now: DateTime<Utc>) -> i64 {
+    now.timestamp().div_euclid(PERIOD_SECONDS)
+}
+
+/// The code of a time step (RFC 4226 section 5.3).
+pub fn code_at(secret: &[u8], step: i64, digits: u32) -> String {
+    let mut mac = HmacSha1::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset],
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]) & 0x7fff_ffff;
+
+    format!(
+        "{:0width$}",
+        binary % 10u32.pow(digits),
+        width = digits as usize
+    )
+}
+
+/// Checks a code against the periods around `now` and returns the time step it belongs to.
+/// Callers refuse steps that were used before, so a code cannot be replayed.
+pub fn verify(secret: &str, code: &str, now: DateTime<Utc>) -> Option<i64> {
+    let secret = decode_secret(secret)?;
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let current = time_step(now);
+    (current - ALLOWED_DRIFT..=current + ALLOWED_DRIFT).find(|step| {
+        bool::from(
+            code_at(&secret, *step, DIGITS)
+                .as_bytes()
+                .ct_eq(code.as_bytes()),
+        )
+    })
+}
+
+/// `otpauth://` URI to show as QR code, see
+/// <https://github.com/google/google-authenticator/wiki/Key-Uri-Format>.
+pub fn provisioning_uri(issuer: &str, account_name: &str, secret: &str) -> String {
+    let mut uri = Url::parse("otpauth://totp/").expect("valid base URI");
+    uri.set_path(&format!("{}:{}", issuer, account_name));
+    uri.query_pairs_mut()
+        .append_pair("secret", secret)
+        .append_pair("issuer", issuer)
+        .append_pair("algorithm", "SHA1")
+        .append_pair("digits", &DIGITS.to_string())
+        .append_pair("period", &PERIOD_SECONDS.to_string());
+    uri.to_string()
+}
+
+fn decode_secret(secret: &str) -> Option<Vec<u8>> {
+    let normalized: String = secret
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '=')
+        .collect::<String>()
+        .to_ascii_uppercase();
+    BASE32_NOPAD.decode(normalized.as_bytes()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_totp_rfc_6238_test_vectors() {
+        // given
+        let secret = b"12345678901234567890";
+
+        // then
+        assert_eq!(code_at(secret, 59 / PERIOD_SECONDS, 8), "94287082");
+        assert_eq!(code_at(secret, 1111111109 / PERIOD_SECONDS, 8), "07081804");
+        assert_eq!(code_at(secret, 1234567890 / PERIOD_SECONDS, 6), "005924");
+    }
+
+    #[test]
+    fn test_verify_code_of_neighbouring_period_should_succeed() {
+        // given
+        let secret = BASE32_NOPAD.encode(b"12345678901234567890");
+        let now = Utc.timestamp_opt(1234567890, 0).unwrap();
+        let step = time_step(now);
+        let previous = code_at(b"12345678901234567890", step - 1, DIGITS);
+        let outdated = code_at(b"12345678901234567890", step - 2, DIGITS);
+
+        // then
+        assert_eq!(verify(&secret, "005 924", now), Some(step));
+        assert_eq!(verify(&secret, &previous, now), Some(step - 1));
+        assert_eq!(verify(&secret, &outdated, now), None);
+        assert_eq!(verify(&secret, "00592", now), None);
+    }
+
+    #[test]
+    fn test_provisioning_uri() {
+        // when
+        let uri = provisioning_uri("Photos.network", "jane@example.com", "JBSWY3DPEHPK3PXP");
+
+        // then
+        assert_eq!(
+            uri,
+            "otpauth://totp/Photos.network:jane@example.com?secret=JBSWY3DPEHPK3PXP&issuer=Photos.network&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+}
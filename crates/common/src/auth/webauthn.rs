@@ -0,0 +1,708 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Relying party side of WebAuthn (Level 2) registration and assertion ceremonies.
+//!
+//! Only what passkeys need is implemented: EC2 P-256 (ES256) and RSA (RS256) COSE keys, and
+//! `none` attestation. Attestation statements of other formats are not evaluated, as the
+//! server asks for none and trusts no authenticator vendor. CBOR is decoded with `ciborium`.
+//!
+//! `webauthn-rs` is not used because its stable releases verify signatures with OpenSSL,
+//! while everything else here, TLS included, is pure Rust on `ring` and RustCrypto.
+
+use base64::alphabet;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use ciborium::Value;
+use p256::ecdsa::signature::Verifier;
+use rsa::traits::PublicKeyParts;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+/// base64url as used by WebAuthn, accepting padded input from lenient clients.
+pub const BASE64URL: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::URL_SAFE,
+    GeneralPurposeConfig::new()
+        .with_encode_padding(false)
+        .with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+pub const COSE_ALG_ES256: i64 = -7;
+pub const COSE_ALG_RS256: i64 = -257;
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+
+/// Smallest RSA modulus accepted for credentials.
+const MIN_RSA_BITS: usize = 2048;
+
+/// Nesting allowed in CBOR input, deeper structures are refused.
+const MAX_CBOR_DEPTH: usize = 8;
+
+/// The server as WebAuthn sees it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelyingParty {
+    /// Domain credentials are scoped to, the host of the web app or a parent domain of it.
+    pub id: String,
+    pub name: String,
+    /// Origins ceremonies may run on, like `https://photos.example.com`.
+    pub origins: Vec<String>,
+}
+
+/// `clientDataJSON` as collected by the browser.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClientData {
+    #[serde(rename = "type")]
+    pub ceremony: String,
+    pub challenge: String,
+    pub origin: String,
+}
+
+impl ClientData {
+    pub fn parse(json: &[u8]) -> Result<Self, anyhow::Error> {
+        serde_json::from_slice(json).map_err(|e| anyhow::anyhow!("Invalid clientDataJSON: {}", e))
+    }
+}
+
+/// Parsed `authenticatorData`.
+#[derive(Debug, Clone)]
+pub struct AuthenticatorData {
+    pub rp_id_hash: [u8; 32],
+    pub flags: u8,
+    pub sign_count: u32,
+    pub attested_credential: Option<AttestedCredential>,
+}
+
+/// Credential created during registration.
+#[derive(Debug, Clone)]
+pub struct AttestedCredential {
+    pub credential_id: Vec<u8>,
+    /// The COSE key as sent, stored to verify later assertions.
+    pub public_key: Vec<u8>,
+}
+
+impl AuthenticatorData {
+    pub fn parse(data: &[u8]) -> Result<Self, anyhow::Error> {
+        if data.len() < 37 {
+            return Err(anyhow::anyhow!("authenticatorData is too short"));
+        }
+        let rp_id_hash: [u8; 32] = data[..32].try_into()?;
+        let flags = data[32];
+        let sign_count = u32::from_be_bytes(data[33..37].try_into()?);
+
+        let attested_credential = if flags & FLAG_ATTESTED_CREDENTIAL != 0 {
+            // AAGUID (16 bytes), credential ID length (2 bytes), credential ID, COSE key
+            let rest = &data[37..];
+            if rest.len() < 18 {
+                return Err(anyhow::anyhow!("Attested credential data is truncated"));
+            }
+            let id_length = u16::from_be_bytes([rest[16], rest[17]]) as usize;
+            let credential_id = rest
+                .get(18..18 + id_length)
+                .ok_or_else(|| anyhow::anyhow!("Credential ID is truncated"))?
+                .to_vec();
+
+            // Extensions may follow the key, so its length is what decoding it consumed.
+            let key = &rest[18 + id_length..];
+            let mut reader = key;
+            decode_cbor(&mut reader)?;
+            let public_key = key[..key.len() - reader.len()].to_vec();
+
+            Some(AttestedCredential {
+                credential_id,
+                public_key,
+            })
+        } else {
+            None
+        };
+
+        Ok(Self {
+            rp_id_hash,
+            flags,
+            sign_count,
+            attested_credential,
+        })
+    }
+
+    pub fn user_present(&self) -> bool {
+        self.flags & FLAG_USER_PRESENT != 0
+    }
+
+    /// The authenticator checked a PIN or biometric, so the credential counts as two factors.
+    pub fn user_verified(&self) -> bool {
+        self.flags & FLAG_USER_VERIFIED != 0
+    }
+}
+
+/// Public key of a credential.
+pub enum CoseKey {
+    Es256(p256::ecdsa::VerifyingKey),
+    Rs256(rsa::RsaPublicKey),
+}
+
+impl CoseKey {
+    pub fn parse(data: &[u8]) -> Result<Self, anyhow::Error> {
+        let Value::Map(entries) = decode_cbor(&mut &data[..])? else {
+            return Err(anyhow::anyhow!("COSE key is not a map"));
+        };
+        let field = |label: i128| {
+            entries
+                .iter()
+                .find(|(k, _)| k.as_integer().map(i128::from) == Some(label))
+                .map(|(_, v)| v)
+        };
+        let integer = |label: i128| field(label).and_then(Value::as_integer).map(i128::from);
+        let bytes = |label: i128| match field(label) {
+            Some(Value::Bytes(bytes)) => Ok(bytes.as_slice()),
+            _ => Err(anyhow::anyhow!("COSE key misses parameter {}", label)),
+        };
+
+        match (integer(1), integer(3)) {
+            // kty EC2, crv P-256
+            (Some(2), Some(alg)) if alg == COSE_ALG_ES256 as i128 => {
+                if integer(-1) != Some(1) {
+                    return Err(anyhow::anyhow!("Only the P-256 curve is supported"));
+                }
+                let (x, y) = (bytes(-2)?, bytes(-3)?);
+                if x.len() != 32 || y.len() != 32 {
+                    return Err(anyhow::anyhow!("Invalid P-256 coordinates"));
+                }
+                let point = p256::EncodedPoint::from_affine_coordinates(x.into(), y.into(), false);
+                let key = p256::ecdsa::VerifyingKey::from_encoded_point(&point)?;
+                Ok(CoseKey::Es256(key))
+            }
+            // kty RSA
+            (Some(3), Some(alg)) if alg == COSE_ALG_RS256 as i128 => {
+                let key = rsa::RsaPublicKey::new(
+                    rsa::BigUint::from_bytes_be(bytes(-1)?),
+                    rsa::BigUint::from_bytes_be(bytes(-2)?),
+                )?;
+                if key.n().bits() < MIN_RSA_BITS {
+                    return Err(anyhow::anyhow!(
+                        "RSA keys need at least {} bits",
+                        MIN_RSA_BITS
+                    ));
+                }
+                Ok(CoseKey::Rs256(key))
+            }
+            _ => Err(anyhow::anyhow!("Unsupported COSE key type or algorithm")),
+        }
+    }
+
+    pub fn algorithm(&self) -> i64 {
+        match self {
+            CoseKey::Es256(_) => COSE_ALG_ES256,
+            CoseKey::Rs256(_) => COSE_ALG_RS256,
+        }
+    }
+
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> Result<(), anyhow::Error> {
+        match self {
+            CoseKey::Es256(key) => {
+                let signature = p256::ecdsa::Signature::from_der(signature)?;
+                key.verify(message, &signature)?;
+            }
+            CoseKey::Rs256(key) => {
+                key.verify(
+                    rsa::Pkcs1v15Sign::new::<Sha256>(),
+                    &Sha256::digest(message),
+                    signature,
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A credential that passed the registration checks.
+#[derive(Debug, Clone)]
+pub struct VerifiedRegistration {
+    pub credential_id: Vec<u8>,
+    pub public_key: Vec<u8>,
+    pub algorithm: i64,
+    pub sign_count: u32,
+    pub user_verified: bool,
+}
+
+/// An assertion that passed the checks.
+#[derive(Debug, Clone, Copy)]
+pub struct VerifiedAssertion {
+    pub sign_count: u32,
+    pub user_verified: bool,
+}
+
+/// Checks the response to a registration ceremony (WebAuthn section 7.1). `credential_id`
+/// is the `id` the client reported, which must name the attested credential.
+pub fn verify_registration(
+    rp: &RelyingParty,
+    expected_challenge: &str,
+    credential_id: &[u8],
+    client_data_json: &[u8],
+    attestation_object: &[u8],
+) -> Result<VerifiedRegistration, anyhow::Error> {
+    check_client_data(rp, "webauthn.create", expected_challenge, client_data_json)?;
+
+    let Value::Map(entries) = decode_cbor(&mut &attestation_object[..])? else {
+        return Err(anyhow::anyhow!("attestationObject is not a map"));
+    };
+    let auth_data = entries
+        .iter()
+        .find_map(|(k, v)| match (k, v) {
+            (Value::Text(key), Value::Bytes(bytes)) if key == "authData" => Some(bytes.as_slice()),
+            _ => None,
+        })
+        .ok_or_else(|| anyhow::anyhow!("attestationObject misses authData"))?;
+
+    let auth_data = AuthenticatorData::parse(auth_data)?;
+    check_authenticator_data(rp, &auth_data)?;
+    let credential = auth_data
+        .attested_credential
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("No credential was created"))?;
+    if credential.credential_id != credential_id {
+        return Err(anyhow::anyhow!(
+            "Credential ID does not match the attested credential"
+        ));
+    }
+    let key = CoseKey::parse(&credential.public_key)?;
+
+    Ok(VerifiedRegistration {
+        credential_id: credential.credential_id.clone(),
+        public_key: credential.public_key.clone(),
+        algorithm: key.algorithm(),
+        sign_count: auth_data.sign_count,
+        user_verified: auth_data.user_verified(),
+    })
+}
+
+/// Checks the response to an authentication ceremony against the stored COSE key
+/// (WebAuthn section 7.2).
+pub fn verify_assertion(
+    rp: &RelyingParty,
+    expected_challenge: &str,
+    public_key: &[u8],
+    client_data_json: &[u8],
+    authenticator_data: &[u8],
+    signature: &[u8],
+) -> Result<VerifiedAssertion, anyhow::Error> {
+    check_client_data(rp, "webauthn.get", expected_challenge, client_data_json)?;
+
+    let auth_data = AuthenticatorData::parse(authenticator_data)?;
+    check_authenticator_data(rp, &auth_data)?;
+
+    let mut signed = authenticator_data.to_vec();
+    signed.extend_from_slice(&Sha256::digest(client_data_json));
+    CoseKey::parse(public_key)?
+        .verify(&signed, signature)
+        .map_err(|_| anyhow::anyhow!("Invalid signature"))?;
+
+    Ok(VerifiedAssertion {
+        sign_count: auth_data.sign_count,
+        user_verified: auth_data.user_verified(),
+    })
+}
+
+fn check_client_data(
+    rp: &RelyingParty,
+    ceremony: &str,
+    expected_challenge: &str,
+    client_data_json: &[u8],
+) -> Result<(), anyhow::Error> {
+    let client_data = ClientData::parse(client_data_json)?;
+    if client_data.ceremony != ceremony {
+        return Err(anyhow::anyhow!("Expected a {} ceremony", ceremony));
+    }
+    if client_data.challenge != expected_challenge {
+        return Err(anyhow::anyhow!("Challenge does not match"));
+    }
+    if !rp.origins.contains(&client_data.origin) {
+        return Err(anyhow::anyhow!(
+            "Origin {} is not allowed",
+            client_data.origin
+        ));
+    }
+    Ok(())
+}
+
+fn check_authenticator_data(
+    rp: &RelyingParty,
+    auth_data: &AuthenticatorData,
+) -> Result<(), anyhow::Error> {
+    if auth_data.rp_id_hash[..] != Sha256::digest(rp.id.as_bytes())[..] {
+        return Err(anyhow::anyhow!(
+            "Credential belongs to another relying party"
+        ));
+    }
+    if !auth_data.user_present() {
+        return Err(anyhow::anyhow!("User was not present"));
+    }
+    Ok(())
+}
+
+/// Decodes one CBOR data item from the front of `reader`, refusing deeper nesting than CTAP2 uses.
+fn decode_cbor(reader: &mut &[u8]) -> Result<Value, anyhow::Error> {
+    ciborium::de::from_reader_with_recursion_limit(reader, MAX_CBOR_DEPTH)
+        .map_err(|e| anyhow::anyhow!("Invalid CBOR: {}", e))
+}
+
+/// A software authenticator, standing in for a security key in tests.
+#[cfg(test)]
+pub(crate) mod software_authenticator {
+    use super::*;
+    use p256::ecdsa::signature::Signer;
+    use p256::ecdsa::{DerSignature, SigningKey};
+
+    pub(crate) struct SoftwareAuthenticator {
+        pub(crate) credential_id: Vec<u8>,
+        key: SigningKey,
+        pub(crate) sign_count: u32,
+        pub(crate) user_verification: bool,
+    }
+
+    fn cbor_head(major: u8, value: u64, out: &mut Vec<u8>) {
+        match value {
+            0..=23 => out.push(major << 5 | value as u8),
+            24..=0xff => out.extend([major << 5 | 24, value as u8]),
+            0x100..=0xffff => {
+                out.push(major << 5 | 25);
+                out.extend((value as u16).to_be_bytes());
+            }
+            _ => {
+                out.push(major << 5 | 26);
+                out.extend((value as u32).to_be_bytes());
+            }
+        }
+    }
+
+    fn cbor_int(value: i64, out: &mut Vec<u8>) {
+        if value >= 0 {
+            cbor_head(0, value as u64, out)
+        } else {
+            cbor_head(1, (-1 - value) as u64, out)
+        }
+    }
+
+    fn cbor_bytes(bytes: &[u8], out: &mut Vec<u8>) {
+        cbor_head(2, bytes.len() as u64, out);
+        out.extend_from_slice(bytes);
+    }
+
+    fn cbor_text(text: &str, out: &mut Vec<u8>) {
+        cbor_head(3, text.len() as u64, out);
+        out.extend_from_slice(text.as_bytes());
+    }
+
+    impl SoftwareAuthenticator {
+        pub(crate) fn new() -> Self {
+            use rand::RngCore;
+
+            let mut credential_id = vec![0u8; 16];
+            rand::thread_rng().fill_bytes(&mut credential_id);
+            Self {
+                credential_id,
+                key: SigningKey::random(&mut rand::rngs::OsRng),
+                sign_count: 0,
+                user_verification: true,
+            }
+        }
+
+        fn cose_key(&self) -> Vec<u8> {
+            let point = self.key.verifying_key().to_encoded_point(false);
+            let mut out = Vec::new();
+            cbor_head(5, 5, &mut out);
+            cbor_int(1, &mut out);
+            cbor_int(2, &mut out);
+            cbor_int(3, &mut out);
+            cbor_int(COSE_ALG_ES256, &mut out);
+            cbor_int(-1, &mut out);
+            cbor_int(1, &mut out);
+            cbor_int(-2, &mut out);
+            cbor_bytes(point.x().unwrap(), &mut out);
+            cbor_int(-3, &mut out);
+            cbor_bytes(point.y().unwrap(), &mut out);
+            out
+        }
+
+        fn authenticator_data(&self, rp_id: &str, attested: bool) -> Vec<u8> {
+            let mut flags = FLAG_USER_PRESENT;
+            if self.user_verification {
+                flags |= FLAG_USER_VERIFIED;
+            }
+            if attested {
+                flags |= FLAG_ATTESTED_CREDENTIAL;
+            }
+
+            let mut data = Sha256::digest(rp_id.as_bytes()).to_vec();
+            data.push(flags);
+            data.extend(self.sign_count.to_be_bytes());
+            if attested {
+                data.extend([0u8; 16]);
+                data.extend((self.credential_id.len() as u16).to_be_bytes());
+                data.extend(&self.credential_id);
+                data.extend(self.cose_key());
+            }
+            data
+        }
+
+        fn client_data(ceremony: &str, challenge: &str, origin: &str) -> Vec<u8> {
+            serde_json::json!({ "type": ceremony, "challenge": challenge, "origin": origin, "crossOrigin": false })
+                .to_string()
+                .into_bytes()
+        }
+
+        /// `navigator.credentials.create()`: returns `clientDataJSON` and `attestationObject`.
+        pub(crate) fn register(
+            &self,
+            rp_id: &str,
+            origin: &str,
+            challenge: &str,
+        ) -> (Vec<u8>, Vec<u8>) {
+            let mut attestation_object = Vec::new();
+            cbor_head(5, 3, &mut attestation_object);
+            cbor_text("fmt", &mut attestation_object);
+            cbor_text("none", &mut attestation_object);
+            cbor_text("attStmt", &mut attestation_object);
+            cbor_head(5, 0, &mut attestation_object);
+            cbor_text("authData", &mut attestation_object);
+            cbor_bytes(
+                &self.authenticator_data(rp_id, true),
+                &mut attestation_object,
+            );
+
+            (
+                Self::client_data("webauthn.create", challenge, origin),
+                attestation_object,
+            )
+        }
+
+        /// `navigator.credentials.get()`: returns `clientDataJSON`, `authenticatorData` and `signature`.
+        pub(crate) fn assert(
+            &mut self,
+            rp_id: &str,
+            origin: &str,
+            challenge: &str,
+        ) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+            self.sign_count += 1;
+            let client_data = Self::client_data("webauthn.get", challenge, origin);
+            let authenticator_data = self.authenticator_data(rp_id, false);
+
+            let mut signed = authenticator_data.clone();
+            signed.extend_from_slice(&Sha256::digest(&client_data));
+            let signature: DerSignature = self.key.sign(&signed);
+
+            (
+                client_data,
+                authenticator_data,
+                signature.as_bytes().to_vec(),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::software_authenticator::SoftwareAuthenticator;
+    use super::*;
+
+    fn relying_party() -> RelyingParty {
+        RelyingParty {
+            id: "photos.example.com".into(),
+            name: "Photos.network".into(),
+            origins: vec!["https://photos.example.com".into()],
+        }
+    }
+
+    #[test]
+    fn test_register_and_sign_in_with_software_authenticator_should_succeed() {
+        // given
+        let rp = relying_party();
+        let mut authenticator = SoftwareAuthenticator::new();
+        let (client_data, attestation_object) =
+            authenticator.register(&rp.id, &rp.origins[0], "register-challenge");
+
+        // when
+        let registration = verify_registration(
+            &rp,
+            "register-challenge",
+            &authenticator.credential_id,
+            &client_data,
+            &attestation_object,
+        )
+        .unwrap();
+        let (client_data, authenticator_data, signature) =
+            authenticator.assert(&rp.id, &rp.origins[0], "login-challenge");
+        let assertion = verify_assertion(
+            &rp,
+            "login-challenge",
+            &registration.public_key,
+            &client_data,
+            &authenticator_data,
+            &signature,
+        )
+        .unwrap();
+
+        // then
+        assert_eq!(registration.credential_id, authenticator.credential_id);
+        assert_eq!(registration.algorithm, COSE_ALG_ES256);
+        assert!(registration.user_verified);
+        assert_eq!(assertion.sign_count, 1);
+        assert!(assertion.user_verified);
+    }
+
+    #[test]
+    fn test_assertion_for_other_challenge_origin_or_key_should_fail() {
+        // given
+        let rp = relying_party();
+        let mut authenticator = SoftwareAuthenticator::new();
+        let (client_data, attestation_object) =
+            authenticator.register(&rp.id, &rp.origins[0], "c1");
+        let public_key = verify_registration(
+            &rp,
+            "c1",
+            &authenticator.credential_id,
+            &client_data,
+            &attestation_object,
+        )
+        .unwrap()
+        .public_key;
+        let (phished, phished_data, phished_signature) =
+            authenticator.assert(&rp.id, "https://evil.example", "c2");
+        let (client_data, authenticator_data, signature) =
+            authenticator.assert(&rp.id, &rp.origins[0], "c3");
+        let other_key = {
+            let other = SoftwareAuthenticator::new();
+            let (client_data, attestation_object) = other.register(&rp.id, &rp.origins[0], "c4");
+            verify_registration(
+                &rp,
+                "c4",
+                &other.credential_id,
+                &client_data,
+                &attestation_object,
+            )
+            .unwrap()
+            .public_key
+        };
+
+        // then
+        assert!(verify_assertion(
+            &rp,
+            "c2",
+            &public_key,
+            &phished,
+            &phished_data,
+            &phished_signature
+        )
+        .is_err());
+        assert!(verify_assertion(
+            &rp,
+            "c2",
+            &public_key,
+            &client_data,
+            &authenticator_data,
+            &signature
+        )
+        .is_err());
+        assert!(verify_assertion(
+            &rp,
+            "c3",
+            &other_key,
+            &client_data,
+            &authenticator_data,
+            &signature
+        )
+        .is_err());
+        assert!(verify_assertion(
+            &rp,
+            "c3",
+            &public_key,
+            &client_data,
+            &authenticator_data,
+            &signature
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_registration_with_other_credential_id_should_fail() {
+        // given
+        let rp = relying_party();
+        let authenticator = SoftwareAuthenticator::new();
+        let (client_data, attestation_object) =
+            authenticator.register(&rp.id, &rp.origins[0], "c1");
+
+        // when
+        let result = verify_registration(&rp, "c1", b"other", &client_data, &attestation_object);
+
+        // then
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_rsa_key_below_2048_bits_should_fail() {
+        // given
+        let rsa_key = |modulus_len: u16| {
+            // {1: 3 (RSA), 3: -257 (RS256), -1: n, -2: 65537}
+            let mut key = vec![0xa4, 0x01, 0x03, 0x03, 0x39, 0x01, 0x00, 0x20, 0x59];
+            key.extend(modulus_len.to_be_bytes());
+            key.extend(vec![0xff; modulus_len as usize]);
+            key.extend([0x21, 0x43, 0x01, 0x00, 0x01]);
+            key
+        };
+
+        // when
+        let weak = CoseKey::parse(&rsa_key(128));
+        let strong = CoseKey::parse(&rsa_key(256));
+
+        // then
+        assert!(weak.is_err());
+        assert!(strong.is_ok());
+    }
+
+    #[test]
+    fn test_truncated_or_nested_cbor_should_fail() {
+        // given
+        let truncated = [0x5a, 0xff, 0xff, 0xff, 0xff, 0x00];
+        let nested = [0x81; 32];
+
+        // then
+        assert!(decode_cbor(&mut &truncated[..]).is_err());
+        assert!(decode_cbor(&mut &nested[..]).is_err());
+        assert_eq!(
+            decode_cbor(&mut &[0x38, 0x18][..]).unwrap(),
+            Value::from(-25)
+        );
+    }
+
+    #[test]
+    fn test_attested_key_ends_before_extensions() {
+        // given
+        let mut data = vec![0u8; 32];
+        data.extend([FLAG_ATTESTED_CREDENTIAL | 0x80, 0, 0, 0, 1]);
+        data.extend([0u8; 16]);
+        data.extend([0, 1, 7]);
+        data.extend([0xa1, 0x01, 0x02]);
+        data.extend([0xa1, 0x61, b'x', 0xf5]);
+
+        // when
+        let parsed = AuthenticatorData::parse(&data).unwrap();
+
+        // then
+        let credential = parsed.attested_credential.unwrap();
+        assert_eq!(credential.credential_id, vec![7]);
+        assert_eq!(credential.public_key, vec![0xa1, 0x01, 0x02]);
+    }
+}
@@ -0,0 +1,149 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! This represents the configuration of issued tokens
+use std::path::PathBuf;
+
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+#[derive(PartialEq, Debug, Deserialize, Serialize, Clone, Default)]
+pub struct AuthConfig {
+    /// Allows running without a configured signing key, falling back to a well-known secret.
+    /// Only meant for local development.
+    #[serde(default)]
+    pub dev_mode: bool,
+    /// Keys accepted for token signatures. Old keys stay listed during a rotation
+    /// so tokens they signed keep working until they expire.
+    #[serde(default)]
+    pub jwt_keys: Vec<JwtKeyConfig>,
+    /// `kid` of the HMAC key new tokens are signed with while `signing_algorithm` is `HS256`,
+    /// the first listed key otherwise.
+    #[serde(default)]
+    pub signing_kid: Option<String>,
+    /// Tokens are signed with the master realm key of this algorithm. The HMAC keys keep
+    /// verifying tokens signed before and sign media URLs.
+    #[serde(default)]
+    pub signing_algorithm: SigningAlgorithm,
+    /// Admin accounts have to enroll a TOTP second factor before they can sign in.
+    #[serde(default)]
+    pub require_admin_mfa: bool,
+    /// Domain passkeys are registered for, the host of `external_url` if not set.
+    /// A parent domain lets web apps on sibling hosts use the same passkeys.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub passkey_rp_id: Option<String>,
+    /// Origins besides `external_url` whose pages may use passkeys, e.g. `https://app.example.com`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub passkey_origins: Vec<String>,
+    /// Reverse proxies whose `X-Forwarded-For` header names the client address shown in
+    /// session listings, as addresses or networks like `172.16.0.0/12`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trusted_proxies: Vec<String>,
+}
+
+#[derive(PartialEq, Eq, Debug, Deserialize, Serialize, Clone, Copy, Default)]
+pub enum SigningAlgorithm {
+    HS256,
+    #[default]
+    RS256,
+    ES256,
+}
+
+impl From<SigningAlgorithm> for jsonwebtoken::Algorithm {
+    fn from(algorithm: SigningAlgorithm) -> Self {
+        match algorithm {
+            SigningAlgorithm::HS256 => jsonwebtoken::Algorithm::HS256,
+            SigningAlgorithm::RS256 => jsonwebtoken::Algorithm::RS256,
+            SigningAlgorithm::ES256 => jsonwebtoken::Algorithm::ES256,
+        }
+    }
+}
+
+impl AuthConfig {
+    /// Configuration with a fresh random key, written on the first start.
+    pub fn generated() -> Self {
+        let mut secret = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut secret);
+
+        AuthConfig {
+            dev_mode: false,
+            jwt_keys: vec![JwtKeyConfig {
+                kid: uuid::Uuid::new_v4().simple().to_string(),
+                secret: Some(hex::encode(secret)),
+                secret_file: None,
+            }],
+            signing_kid: None,
+            signing_algorithm: SigningAlgorithm::default(),
+            require_admin_mfa: false,
+            passkey_rp_id: None,
+            passkey_origins: vec![],
+            trusted_proxies: vec![],
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Deserialize, Serialize, Clone)]
+pub struct JwtKeyConfig {
+    /// Sent in the `kid` header of tokens signed with this key.
+    pub kid: String,
+    /// The HMAC secret itself.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    /// File holding the HMAC secret, used when `secret` is not set. Surrounding whitespace is ignored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret_file: Option<PathBuf>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_deserialization() {
+        // given
+        let json = r#"{}"#;
+
+        // then
+        assert_eq!(AuthConfig::default(), serde_json::from_str(json).unwrap());
+    }
+
+    #[test]
+    fn test_key_deserialization() {
+        // given
+        let json = r#"{
+            "signing_kid": "2024-06",
+            "jwt_keys": [
+                { "kid": "2024-06", "secret_file": "config/jwt-2024-06.key" },
+                { "kid": "2024-01", "secret": "previous-secret" }
+            ]
+        }"#;
+
+        // when
+        let config: AuthConfig = serde_json::from_str(json).unwrap();
+
+        // then
+        assert_eq!(config.signing_kid.as_deref(), Some("2024-06"));
+        assert_eq!(
+            config.jwt_keys[0].secret_file,
+            Some(PathBuf::from("config/jwt-2024-06.key"))
+        );
+        assert_eq!(
+            config.jwt_keys[1].secret.as_deref(),
+            Some("previous-secret")
+        );
+    }
+}
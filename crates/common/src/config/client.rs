@@ -20,12 +20,61 @@ use std::fmt;
 
 use serde::{Deserialize, Serialize};
 
-#[derive(PartialEq, Debug, Deserialize, Serialize, Clone)]
+#[derive(PartialEq, Debug, Deserialize, Serialize, Clone, Default)]
 pub struct OAuthClientConfig {
     pub name: String,
     pub client_id: String,
     pub client_secret: String,
     pub redirect_uris: Vec<String>,
+    /// Issuer of an external OpenID Connect provider accounts can sign in with, as `name`.
+    /// Clients without one are confidential clients of this server, e.g. resource servers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issuer: Option<String>,
+    /// Email addresses and domains whose users get an account on their first sign-in.
+    /// Everyone else needs an existing account with the same verified email.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_signups: Vec<String>,
+    /// ID token claim listing the groups of a user, `groups` if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub groups_claim: Option<String>,
+    /// Members of one of these groups are admins, everyone else is not.
+    /// Without any, the admin flag of accounts is left alone.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub admin_groups: Vec<String>,
+}
+
+impl OAuthClientConfig {
+    pub fn groups_claim(&self) -> &str {
+        self.groups_claim.as_deref().unwrap_or("groups")
+    }
+
+    /// Whether a new account may be created for this verified email address.
+    pub fn allows_signup(&self, email: &str) -> bool {
+        let email = email.to_lowercase();
+        let Some((_, domain)) = email.rsplit_once('@') else {
+            return false;
+        };
+
+        self.allowed_signups
+            .iter()
+            .map(|entry| entry.to_lowercase())
+            .any(|entry| {
+                if entry.contains('@') {
+                    entry == email
+                } else {
+                    entry == domain
+                }
+            })
+    }
+
+    /// The admin flag for a member of these groups, `None` to keep the current one.
+    pub fn admin_flag(&self, groups: &[String]) -> Option<bool> {
+        if self.admin_groups.is_empty() {
+            return None;
+        }
+
+        Some(groups.iter().any(|group| self.admin_groups.contains(group)))
+    }
 }
 
 impl fmt::Display for OAuthClientConfig {
@@ -61,6 +110,7 @@ mod tests {
                 "http://127.0.0.1:7777/callback".into(),
                 "photosapp://authenticate".into(),
             ],
+            ..Default::default()
         };
 
         assert_eq!(data, serde_json::from_str(json).unwrap());
@@ -81,8 +131,42 @@ mod tests {
             client_id: "clientId".into(),
             client_secret: "clientSecret".into(),
             redirect_uris: vec![],
+            ..Default::default()
         };
 
         assert_eq!(data, serde_json::from_str(json).unwrap());
     }
+
+    #[test]
+    fn test_allows_signup_of_allowed_addresses_and_domains() {
+        // given
+        let client = OAuthClientConfig {
+            allowed_signups: vec!["example.com".into(), "Guest@Friends.org".into()],
+            ..Default::default()
+        };
+
+        // then
+        assert!(client.allows_signup("alice@EXAMPLE.com"));
+        assert!(client.allows_signup("guest@friends.org"));
+        assert!(!client.allows_signup("other@friends.org"));
+        assert!(!client.allows_signup("mallory@example.com.evil.org"));
+        assert!(!client.allows_signup("example.com"));
+    }
+
+    #[test]
+    fn test_admin_flag_follows_admin_groups() {
+        // given
+        let mut client = OAuthClientConfig::default();
+        let groups = vec!["photographers".to_string(), "admins".to_string()];
+
+        // then
+        assert_eq!(client.admin_flag(&groups), None);
+
+        // when
+        client.admin_groups = vec!["admins".into()];
+
+        // then
+        assert_eq!(client.admin_flag(&groups), Some(true));
+        assert_eq!(client.admin_flag(&groups[..1]), Some(false));
+    }
 }
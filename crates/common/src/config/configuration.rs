@@ -22,8 +22,11 @@ use serde::{Deserialize, Serialize};
 use tracing::info;
 
 use super::{
+    auth_config::AuthConfig,
     client::OAuthClientConfig,
     database_config::{DatabaseConfig, DatabaseDriver},
+    mail_config::MailConfig,
+    media_config::MediaConfig,
     plugin::Plugin,
 };
 
@@ -35,6 +38,12 @@ pub struct Configuration {
     // pub auth_provider: Vec<AuthProvider>,
     pub clients: Vec<OAuthClientConfig>,
     pub plugins: Vec<Plugin>,
+    #[serde(default)]
+    pub media: MediaConfig,
+    #[serde(default)]
+    pub auth: AuthConfig,
+    #[serde(default)]
+    pub mail: MailConfig,
 }
 
 impl Configuration {
@@ -47,7 +56,10 @@ impl Configuration {
             Ok(data) => serde_json::from_str(&data)
                 .expect("Configuration file could not be parsed as JSON!"),
             Err(_) => {
-                let default_config = Configuration::empty();
+                let default_config = Configuration {
+                    auth: AuthConfig::generated(),
+                    ..Configuration::empty()
+                };
 
                 fs::write(path, serde_json::to_string_pretty(&default_config).unwrap())
                     .expect("Could not write default Configuration to file!");
@@ -69,6 +81,9 @@ impl Configuration {
             }),
             clients: vec![],
             plugins: vec![],
+            media: MediaConfig::default(),
+            auth: AuthConfig::default(),
+            mail: MailConfig::default(),
         }
     }
 }
@@ -129,6 +144,9 @@ mod tests {
             database: None,
             clients: vec![],
             plugins: vec![],
+            media: MediaConfig::default(),
+            auth: AuthConfig::default(),
+            mail: MailConfig::default(),
         };
 
         assert_eq!(data, serde_json::from_str(json).unwrap());
@@ -182,11 +200,15 @@ mod tests {
                 client_id: "clientId".into(),
                 client_secret: "clientSecret".into(),
                 redirect_uris: vec![],
+                ..Default::default()
             }],
             plugins: vec![Plugin {
                 name: "Plugin".into(),
                 config: Some(config),
             }],
+            media: MediaConfig::default(),
+            auth: AuthConfig::default(),
+            mail: MailConfig::default(),
         };
 
         assert_eq!(data, serde_json::from_str(json).unwrap());
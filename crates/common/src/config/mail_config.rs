@@ -0,0 +1,132 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! This represents the configuration of outgoing mails
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(PartialEq, Debug, Deserialize, Serialize, Clone)]
+pub struct MailConfig {
+    /// Sender of all mails, e.g. `Photos.network <noreply@example.com>`.
+    #[serde(default = "default_from")]
+    pub from: String,
+    /// Server mails are sent through. Without one they are written to `outbox_dir` and logged.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub smtp: Option<SmtpConfig>,
+    /// Directory of the `.eml` files written while no SMTP server is configured.
+    #[serde(default = "default_outbox_dir")]
+    pub outbox_dir: PathBuf,
+    /// Link in verification mails, `{token}` is replaced by the token.
+    /// Defaults to `/auth/account/email/verify` of `external_url`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verify_email_url: Option<String>,
+    /// Page of the web app that sets a new password, `{token}` is replaced by the token.
+    /// Defaults to `/reset-password` of `external_url`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_reset_url: Option<String>,
+    /// Accounts can only sign in with their password after verifying their email address.
+    #[serde(default)]
+    pub require_verified_email: bool,
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        MailConfig {
+            from: default_from(),
+            smtp: None,
+            outbox_dir: default_outbox_dir(),
+            verify_email_url: None,
+            password_reset_url: None,
+            require_verified_email: false,
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Deserialize, Serialize, Clone)]
+pub struct SmtpConfig {
+    pub host: String,
+    #[serde(default = "default_smtp_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub tls: SmtpTls,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    /// File holding the password, used when `password` is not set. Surrounding whitespace is ignored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_file: Option<PathBuf>,
+}
+
+/// How the connection to the SMTP server is encrypted.
+#[derive(PartialEq, Eq, Debug, Deserialize, Serialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// Plain connection, upgraded with `STARTTLS` (usually port 587).
+    #[default]
+    StartTls,
+    /// TLS from the first byte on (usually port 465).
+    Tls,
+    /// Unencrypted, only meant for local mail sinks.
+    None,
+}
+
+fn default_from() -> String {
+    "Photos.network <noreply@localhost>".into()
+}
+
+fn default_outbox_dir() -> PathBuf {
+    PathBuf::from("data/outbox")
+}
+
+fn default_smtp_port() -> u16 {
+    587
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_deserialization() {
+        // given
+        let json = r#"{}"#;
+
+        // then
+        assert_eq!(MailConfig::default(), serde_json::from_str(json).unwrap());
+    }
+
+    #[test]
+    fn test_smtp_deserialization() {
+        // given
+        let json = r#"{
+            "from": "Studio <mail@studio.example>",
+            "smtp": { "host": "localhost", "port": 1025, "tls": "none" }
+        }"#;
+
+        // when
+        let config: MailConfig = serde_json::from_str(json).unwrap();
+
+        // then
+        let smtp = config.smtp.unwrap();
+        assert_eq!(smtp.port, 1025);
+        assert_eq!(smtp.tls, SmtpTls::None);
+        assert_eq!(smtp.username, None);
+        assert_eq!(config.outbox_dir, default_outbox_dir());
+    }
+}
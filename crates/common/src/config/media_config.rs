@@ -0,0 +1,159 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! This represents the media handling configuration
+use serde::{Deserialize, Serialize};
+
+use crate::zip_cache::ZipDelivery;
+
+#[derive(PartialEq, Debug, Deserialize, Serialize, Clone)]
+pub struct MediaConfig {
+    /// MIME types accepted on upload, detected from the file content.
+    /// Entries like `image/*` allow a whole family.
+    #[serde(default = "default_allowed_mime_types")]
+    pub allowed_mime_types: Vec<String>,
+    /// Delivery of album ZIPs unless a download asks for another one.
+    #[serde(default)]
+    pub zip_delivery: ZipDelivery,
+    /// Limits of the album ZIP cache.
+    #[serde(default)]
+    pub zip_cache: ZipCacheConfig,
+}
+
+impl Default for MediaConfig {
+    fn default() -> Self {
+        MediaConfig {
+            allowed_mime_types: default_allowed_mime_types(),
+            zip_delivery: ZipDelivery::default(),
+            zip_cache: ZipCacheConfig::default(),
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Deserialize, Serialize, Clone)]
+pub struct ZipCacheConfig {
+    /// Upper bound for all cached ZIPs together, least recently downloaded ones are evicted first.
+    #[serde(default = "default_max_size_mb")]
+    pub max_size_mb: u64,
+    /// ZIPs built longer ago than this are evicted and rebuilt on the next download.
+    #[serde(default = "default_max_age_days")]
+    pub max_age_days: u64,
+    /// ZIPs of customer selections nobody downloaded for this long are evicted.
+    #[serde(default = "default_customer_idle_days")]
+    pub customer_idle_days: u64,
+}
+
+impl Default for ZipCacheConfig {
+    fn default() -> Self {
+        ZipCacheConfig {
+            max_size_mb: default_max_size_mb(),
+            max_age_days: default_max_age_days(),
+            customer_idle_days: default_customer_idle_days(),
+        }
+    }
+}
+
+fn default_max_size_mb() -> u64 {
+    20 * 1024
+}
+
+fn default_max_age_days() -> u64 {
+    90
+}
+
+fn default_customer_idle_days() -> u64 {
+    14
+}
+
+fn default_allowed_mime_types() -> Vec<String> {
+    [
+        "image/jpeg",
+        "image/png",
+        "image/gif",
+        "image/webp",
+        "image/heic",
+        "image/heif",
+        "image/avif",
+        "image/tiff",
+        "image/x-adobe-dng",
+        "image/x-canon-cr2",
+        "image/x-canon-cr3",
+        "video/mp4",
+        "video/quicktime",
+        "application/rdf+xml",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_deserialization() {
+        // given
+        let json = r#"{}"#;
+
+        // then
+        assert_eq!(MediaConfig::default(), serde_json::from_str(json).unwrap());
+    }
+
+    #[test]
+    fn test_custom_deserialization() {
+        // given
+        let json = r#"{ "allowed_mime_types": ["image/*"] }"#;
+
+        let data = MediaConfig {
+            allowed_mime_types: vec!["image/*".into()],
+            zip_delivery: ZipDelivery::Cached,
+            zip_cache: ZipCacheConfig::default(),
+        };
+
+        assert_eq!(data, serde_json::from_str(json).unwrap());
+    }
+
+    #[test]
+    fn test_zip_delivery_deserialization() {
+        // given
+        let json = r#"{ "zip_delivery": "stream" }"#;
+
+        // when
+        let config: MediaConfig = serde_json::from_str(json).unwrap();
+
+        // then
+        assert_eq!(config.zip_delivery, ZipDelivery::Stream);
+        assert_eq!(config.allowed_mime_types, default_allowed_mime_types());
+    }
+
+    #[test]
+    fn test_partial_zip_cache_deserialization() {
+        // given
+        let json = r#"{ "zip_cache": { "max_size_mb": 512 } }"#;
+
+        // when
+        let config: MediaConfig = serde_json::from_str(json).unwrap();
+
+        // then
+        assert_eq!(config.zip_cache.max_size_mb, 512);
+        assert_eq!(
+            config.zip_cache.customer_idle_days,
+            default_customer_idle_days()
+        );
+    }
+}
@@ -18,7 +18,10 @@
 //! The Configuration to customize the behaviour of the Photos.network core
 //!
 //!
+pub mod auth_config;
 pub mod client;
 pub mod configuration;
 pub mod database_config;
+pub mod mail_config;
+pub mod media_config;
 pub mod plugin;
@@ -0,0 +1,108 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// Credit and rights information embedded into delivered JPEGs as IPTC and XMP.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::FromRow)]
+pub struct CopyrightTemplate {
+    /// Byline of the photographer.
+    pub creator: Option<String>,
+    pub copyright_notice: Option<String>,
+    pub credit_line: Option<String>,
+    /// Web page with licensing information.
+    pub rights_url: Option<String>,
+    pub contact_email: Option<String>,
+    pub usage_terms: Option<String>,
+}
+
+impl CopyrightTemplate {
+    pub fn is_empty(&self) -> bool {
+        *self == CopyrightTemplate::default()
+    }
+
+    /// Fills the fields this template leaves open from `fallback`.
+    pub fn or(self, fallback: &CopyrightTemplate) -> CopyrightTemplate {
+        CopyrightTemplate {
+            creator: self.creator.or_else(|| fallback.creator.clone()),
+            copyright_notice: self
+                .copyright_notice
+                .or_else(|| fallback.copyright_notice.clone()),
+            credit_line: self.credit_line.or_else(|| fallback.credit_line.clone()),
+            rights_url: self.rights_url.or_else(|| fallback.rights_url.clone()),
+            contact_email: self
+                .contact_email
+                .or_else(|| fallback.contact_email.clone()),
+            usage_terms: self.usage_terms.or_else(|| fallback.usage_terms.clone()),
+        }
+    }
+}
+
+/// Whom a template belongs to: an account's default, or an album overriding it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CopyrightScope {
+    Account,
+    Album,
+}
+
+impl CopyrightScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CopyrightScope::Account => "account",
+            CopyrightScope::Album => "album",
+        }
+    }
+}
+
+impl fmt::Display for CopyrightScope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_album_override_falls_back_to_account() {
+        // given
+        let account = CopyrightTemplate {
+            creator: Some("Jane Doe".into()),
+            copyright_notice: Some("© Jane Doe".into()),
+            ..Default::default()
+        };
+        let album = CopyrightTemplate {
+            copyright_notice: Some("© ACME Corp, licensed".into()),
+            ..Default::default()
+        };
+
+        // when
+        let merged = album.or(&account);
+
+        // then
+        assert_eq!(merged.creator.as_deref(), Some("Jane Doe"));
+        assert_eq!(
+            merged.copyright_notice.as_deref(),
+            Some("© ACME Corp, licensed")
+        );
+        assert!(!merged.is_empty());
+        assert!(CopyrightTemplate::default().is_empty());
+    }
+}
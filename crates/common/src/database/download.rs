@@ -0,0 +1,73 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::{DateTime, Utc};
+
+use crate::database::reference::ReferenceRole;
+use crate::zip_cache::{ZipDelivery, ZipFormat};
+
+/// A media item of a download and the album it was authorized through.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DownloadItem {
+    pub media_id: String,
+    pub album_id: String,
+}
+
+/// What a download packs, stored as JSON.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DownloadContents {
+    pub items: Vec<DownloadItem>,
+    pub roles: Vec<ReferenceRole>,
+    #[serde(default)]
+    pub format: ZipFormat,
+    /// Delivery asked for when the download was created, the configured one otherwise.
+    #[serde(default)]
+    pub delivery: Option<ZipDelivery>,
+}
+
+impl DownloadContents {
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+}
+
+impl TryFrom<String> for DownloadContents {
+    type Error = serde_json::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        serde_json::from_str(&s)
+    }
+}
+
+/// A selection of media items from one or more albums, downloadable as one ZIP until it expires.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, sqlx::FromRow)]
+pub struct Download {
+    pub download_id: String,
+    pub requester_id: String,
+    pub requester_role: String,
+    #[sqlx(try_from = "String")]
+    pub contents: DownloadContents,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Download {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= Utc::now()
+    }
+}
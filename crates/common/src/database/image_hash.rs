@@ -0,0 +1,39 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::{DateTime, Utc};
+
+/// Perceptual hash of the original image of a media item.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, sqlx::FromRow)]
+pub struct ImageHash {
+    pub reference_id: String,
+    pub media_id: String,
+    /// 64 bit dHash. Stored signed, databases lack an unsigned BIGINT.
+    pub hash: i64,
+    pub taken_at: Option<DateTime<Utc>>,
+}
+
+/// Image reference stored before perceptual hashes or placeholders existed.
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct UnindexedReference {
+    pub reference_id: String,
+    pub media_id: String,
+    pub filepath: String,
+    pub filename: String,
+    pub mime_type: String,
+}
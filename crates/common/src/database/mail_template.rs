@@ -0,0 +1,187 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// Text of a mail sent to accounts. `{{name}}`, `{{email}}`, `{{studio}}` and `{{expires_in}}`
+/// are replaced when it is sent. The link of verification and reset mails is not part of the
+/// text, it is added below the body.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, sqlx::FromRow)]
+pub struct MailTemplate {
+    pub subject: String,
+    pub body: String,
+}
+
+/// The mails a studio can word itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MailKind {
+    VerifyEmail,
+    PasswordReset,
+    PasswordChanged,
+}
+
+impl MailKind {
+    pub const ALL: [MailKind; 3] = [
+        MailKind::VerifyEmail,
+        MailKind::PasswordReset,
+        MailKind::PasswordChanged,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MailKind::VerifyEmail => "verify_email",
+            MailKind::PasswordReset => "password_reset",
+            MailKind::PasswordChanged => "password_changed",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<MailKind> {
+        MailKind::ALL
+            .into_iter()
+            .find(|kind| kind.as_str() == value)
+    }
+
+    /// Whether the mail carries a link below its body.
+    pub fn needs_link(&self) -> bool {
+        !matches!(self, MailKind::PasswordChanged)
+    }
+}
+
+impl fmt::Display for MailKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl MailTemplate {
+    /// The text used unless the studio of the recipient wrote its own.
+    pub fn builtin(kind: MailKind) -> MailTemplate {
+        let (subject, body) = match kind {
+            MailKind::VerifyEmail => (
+                "Please verify your email address",
+                "Hello {{name}},\n\n\
+                 please confirm that {{email}} belongs to your {{studio}} account by opening the link below.\n\n\
+                 The link is valid for {{expires_in}}. If you did not create an account, just ignore this mail.\n",
+            ),
+            MailKind::PasswordReset => (
+                "Reset your password",
+                "Hello {{name}},\n\n\
+                 someone asked to reset the password of your {{studio}} account. Choose a new one with the link below.\n\n\
+                 The link is valid for {{expires_in}}. If you did not ask for it, just ignore this mail,\n\
+                 your password stays unchanged.\n",
+            ),
+            MailKind::PasswordChanged => (
+                "Your password was changed",
+                "Hello {{name}},\n\n\
+                 the password of your {{studio}} account {{email}} was just changed and all other devices were signed out.\n\n\
+                 If this was not you, reset your password right away.\n",
+            ),
+        };
+
+        MailTemplate {
+            subject: subject.into(),
+            body: body.into(),
+        }
+    }
+
+    /// Replaces the placeholders, unknown ones are kept as they are. Subjects stay on one line.
+    pub fn render(&self, values: &[(&str, &str)]) -> MailTemplate {
+        let fill = |text: &str| {
+            values.iter().fold(text.to_string(), |text, (key, value)| {
+                text.replace(&format!("{{{{{}}}}}", key), value)
+            })
+        };
+
+        MailTemplate {
+            subject: fill(&self.subject)
+                .replace(['\r', '\n'], " ")
+                .trim()
+                .to_string(),
+            body: fill(&self.body),
+        }
+    }
+
+    /// Adds the link below the rendered body, where no template can change or hide it.
+    pub fn with_link(self, link: &str) -> MailTemplate {
+        MailTemplate {
+            subject: self.subject,
+            body: format!("{}\n\n{}\n", self.body.trim_end(), link),
+        }
+    }
+
+    /// Refuses templates of mails with links that could lead the recipient anywhere else: they
+    /// must neither contain addresses nor the `{{link}}` placeholder of earlier versions.
+    pub fn check_links(&self, kind: MailKind) -> Result<(), String> {
+        if !kind.needs_link() {
+            return Ok(());
+        }
+
+        let text = format!("{}\n{}", self.subject, self.body).to_lowercase();
+        if text.contains("{{link}}") {
+            return Err(format!(
+                "{} mails must not contain {{{{link}}}}, the link is added below the body",
+                kind
+            ));
+        }
+        if ["://", "www.", "mailto:", "href"]
+            .iter()
+            .any(|marker| text.contains(marker))
+        {
+            return Err(format!("{} mails must not contain links", kind));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_replaces_placeholders() {
+        // given
+        let template = MailTemplate {
+            subject: "Welcome to {{studio}}\r\n".into(),
+            body: "Hi {{name}}, open the link below within {{expires_in}}. {{unknown}}".into(),
+        };
+
+        // when
+        let mail = template
+            .render(&[
+                ("name", "Jane"),
+                ("studio", "Lightbox"),
+                ("expires_in", "48 hours"),
+            ])
+            .with_link("https://example.com/?token=abc");
+
+        // then
+        assert_eq!(mail.subject, "Welcome to Lightbox");
+        assert_eq!(
+            mail.body,
+            "Hi Jane, open the link below within 48 hours. {{unknown}}\n\nhttps://example.com/?token=abc\n"
+        );
+        assert_eq!(
+            MailKind::parse("password_reset"),
+            Some(MailKind::PasswordReset)
+        );
+        for kind in MailKind::ALL {
+            assert!(MailTemplate::builtin(kind).check_links(kind).is_ok());
+        }
+    }
+}
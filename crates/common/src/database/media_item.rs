@@ -18,7 +18,10 @@
 use serde::{Deserialize, Serialize};
 use sqlx::types::chrono::{DateTime, Utc};
 
-use super::{details::Details, location::Location, reference::Reference, tag::Tag};
+use super::{
+    details::Details, location::Location, placeholder::Placeholder, reference::Reference, tag::Tag,
+    video_details::VideoDetails,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct MediaItem {
@@ -34,4 +37,10 @@ pub struct MediaItem {
     pub location: Option<Location>,
     #[sqlx(skip)]
     pub references: Option<Vec<Reference>>,
+    /// Present for videos only.
+    #[sqlx(skip)]
+    pub video: Option<VideoDetails>,
+    /// Missing until the image or poster frame has been indexed.
+    #[sqlx(skip)]
+    pub placeholder: Option<Placeholder>,
 }
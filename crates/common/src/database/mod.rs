@@ -24,20 +24,47 @@ use sqlx::types::chrono::DateTime;
 use sqlx::types::chrono::Utc;
 
 use crate::auth::account::Account;
+use crate::auth::account_token::{AccountToken, TokenPurpose};
 use crate::auth::account_with_albums::AccountWithAlbums;
 use crate::auth::album_account::AlbumAccountEntry;
 use crate::auth::customer::Customer;
-
-use self::{album::Album, media_item::MediaItem, reference::Reference};
+use crate::auth::mfa::AccountMfa;
+use crate::auth::passkey::Passkey;
+use crate::auth::session::{Session, SessionClient};
+
+use self::{
+    album::Album,
+    media_item::MediaItem,
+    reference::{Reference, ReferenceFile, ReferenceRole},
+};
 use crate::database::album_stats::AlbumStats;
+use crate::database::copyright_template::{CopyrightScope, CopyrightTemplate};
+use crate::database::download::Download;
+use crate::database::image_hash::{ImageHash, UnindexedReference};
+use crate::database::job::{JobRecord, JobStatus};
+use crate::database::mail_template::{MailKind, MailTemplate};
+use crate::database::placeholder::Placeholder;
+use crate::database::share_link::ShareLink;
+use crate::database::video_details::VideoDetails;
+use crate::database::watermark::AlbumWatermark;
+use crate::metadata::MetadataPolicy;
 
 pub mod album;
 pub mod album_stats;
+pub mod copyright_template;
 pub mod details;
+pub mod download;
+pub mod image_hash;
+pub mod job;
 pub mod location;
+pub mod mail_template;
 pub mod media_item;
+pub mod placeholder;
 pub mod reference;
+pub mod share_link;
 pub mod tag;
+pub mod video_details;
+pub mod watermark;
 
 pub type ArcDynDatabase = Arc<dyn Database + Send + Sync>;
 
@@ -84,6 +111,9 @@ pub trait Database {
 
     async fn delete_customer(&self, customer_id: &str) -> Result<()>;
 
+    /// Marks a customer as paid, which lifts watermarks and unlocks clean downloads.
+    async fn set_customer_paid(&self, customer_id: &str, paid: bool) -> Result<()>;
+
     ///// Account operations /////
 
     async fn create_account(
@@ -139,6 +169,17 @@ pub trait Database {
 
     async fn delete_album(&self, album_id: &str) -> Result<()>;
 
+    async fn get_album_metadata_policy(&self, album_id: &str) -> Result<MetadataPolicy>;
+
+    async fn set_album_metadata_policy(&self, album_id: &str, policy: MetadataPolicy) -> Result<()>;
+
+    /// Returns the strictest metadata policy among the albums containing the media item,
+    /// together with the album it comes from. `None` when all of them keep metadata.
+    async fn get_metadata_policy_for_media(&self, media_id: &str) -> Result<Option<(String, MetadataPolicy)>>;
+
+    /// Albums containing the media item, oldest membership first.
+    async fn get_album_ids_for_media(&self, media_id: &str) -> Result<Vec<String>>;
+
     ///// Album-media junction /////
 
     async fn add_media_to_album(&self, album_id: &str, media_id: &str) -> Result<()>;
@@ -183,7 +224,171 @@ pub trait Database {
     ) -> Result<Vec<String>>;
 
     /// Returns the (filepath, filename) of the first reference for a media item.
+    /// Path of the primary file of a media item, preferring the original over other roles.
     async fn get_media_file_path(&self, media_id: &str) -> Result<Option<(String, String)>>;
+    /// All files of a media item, primary file first.
+    async fn get_reference_files(&self, media_id: &str) -> Result<Vec<ReferenceFile>>;
+    /// Finds a media item in the album holding a file with the given basename.
+    async fn find_album_media_by_basename(&self, album_id: &str, basename: &str) -> Result<Option<String>>;
+    /// Finds a media item of the user holding a file with the given basename.
+    async fn find_owned_media_by_basename(&self, owner_id: &str, basename: &str) -> Result<Option<String>>;
+    /// Changes the role of a file, e.g. a video that turned out to be part of a Live Photo.
+    async fn set_reference_role(&self, media_id: &str, filename: &str, role: ReferenceRole) -> Result<()>;
+    async fn get_media_mime_type(&self, media_id: &str) -> Result<Option<String>>;
+
+    ///// Video metadata /////
+
+    async fn upsert_video_details(&self, details: &VideoDetails) -> Result<()>;
+    async fn get_video_details(&self, media_id: &str) -> Result<Option<VideoDetails>>;
+    async fn delete_video_details(&self, media_id: &str) -> Result<()>;
+
+    ///// Perceptual hashes /////
+
+    async fn set_perceptual_hash(&self, reference_id: &str, hash: i64) -> Result<()>;
+
+    /// Hashes of the original images of all media items owned by the account.
+    async fn get_image_hashes_for_owner(&self, owner_id: &str) -> Result<Vec<ImageHash>>;
+
+    async fn get_image_hashes_for_album(&self, album_id: &str) -> Result<Vec<ImageHash>>;
+
+    /// Original images without a perceptual hash or placeholder, e.g. uploaded before they existed.
+    async fn get_unindexed_image_references(&self) -> Result<Vec<UnindexedReference>>;
+
+    ///// Placeholders /////
+
+    async fn upsert_placeholder(&self, placeholder: &Placeholder) -> Result<()>;
+
+    ///// Background jobs /////
+
+    async fn insert_job(&self, job: &JobRecord) -> Result<()>;
+
+    /// The queued or running job with this dedupe key.
+    async fn find_active_job(&self, dedupe_key: &str) -> Result<Option<JobRecord>>;
+
+    /// Moves a queued job to another time, running jobs are left alone.
+    async fn reschedule_job(&self, job_id: &str, run_at: DateTime<Utc>) -> Result<()>;
+
+    /// Queued jobs of a kind that are due at `now`, oldest first.
+    async fn get_due_jobs(&self, kind: &str, now: DateTime<Utc>, limit: i64) -> Result<Vec<JobRecord>>;
+
+    /// Marks a queued job as running and counts the attempt. Returns false when another
+    /// worker was faster.
+    async fn claim_job(&self, job_id: &str, now: DateTime<Utc>) -> Result<bool>;
+
+    /// Removes a job that succeeded.
+    async fn complete_job(&self, job_id: &str) -> Result<()>;
+
+    /// Queues a failed job again at `retry_at`, or marks it failed for good without one.
+    async fn fail_job(&self, job_id: &str, error: &str, retry_at: Option<DateTime<Utc>>, now: DateTime<Utc>) -> Result<()>;
+
+    /// Queues jobs left running by a previous process. Returns how many there were.
+    async fn requeue_running_jobs(&self, now: DateTime<Utc>) -> Result<u64>;
+
+    /// Queues a failed job again with fresh attempts. Returns false for unknown or not failed jobs.
+    async fn retry_failed_job(&self, job_id: &str, now: DateTime<Utc>) -> Result<bool>;
+
+    async fn list_jobs(&self, status: Option<JobStatus>) -> Result<Vec<JobRecord>>;
+
+    ///// Downloads /////
+
+    async fn create_download(&self, download: &Download) -> Result<()>;
+    async fn get_download(&self, download_id: &str) -> Result<Option<Download>>;
+    /// Removes downloads that expired before `now`. Returns how many there were.
+    async fn delete_expired_downloads(&self, now: DateTime<Utc>) -> Result<u64>;
+
+    ///// Sessions /////
+
+    async fn create_session(&self, session: &Session) -> Result<()>;
+    async fn get_session(&self, session_id: &str) -> Result<Option<Session>>;
+    /// Finds the session whose current or previous refresh token has this hash.
+    async fn get_session_by_refresh_token(&self, token_hash: &str) -> Result<Option<Session>>;
+    /// Swaps the refresh token of an active session, unless `current_hash` was already rotated.
+    /// Returns false when another request rotated it first.
+    async fn rotate_session_token(
+        &self,
+        session_id: &str,
+        current_hash: &str,
+        new_hash: &str,
+        client: &SessionClient,
+        now: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool>;
+    /// Returns false for unknown or already revoked sessions.
+    async fn revoke_session(&self, session_id: &str, now: DateTime<Utc>) -> Result<bool>;
+    /// Active sessions of an account or customer, most recently used first.
+    async fn list_sessions(&self, subject_id: &str, role: &str, now: DateTime<Utc>) -> Result<Vec<Session>>;
+    /// Ids of sessions revoked after `since`, whose access tokens may still circulate.
+    async fn list_revoked_session_ids(&self, since: DateTime<Utc>) -> Result<Vec<String>>;
+    /// Removes sessions that expired before `now`. Returns how many there were.
+    async fn delete_expired_sessions(&self, now: DateTime<Utc>) -> Result<u64>;
+
+    ///// External identities /////
+
+    /// The account a subject of an external identity provider is linked to.
+    async fn get_linked_account(&self, provider: &str, subject: &str) -> Result<Option<String>>;
+    /// Links a subject of an external identity provider to an account, or records its
+    /// latest sign-in when already linked.
+    async fn link_external_identity(
+        &self,
+        provider: &str,
+        subject: &str,
+        account_id: &str,
+        email: Option<&str>,
+        now: DateTime<Utc>,
+    ) -> Result<()>;
+
+    ///// Two-factor authentication /////
+
+    async fn get_account_mfa(&self, account_id: &str) -> Result<Option<AccountMfa>>;
+    /// Stores a TOTP secret awaiting its first code, replacing an unconfirmed one.
+    /// Confirmed secrets are left alone.
+    async fn set_pending_totp_secret(&self, account_id: &str, secret: &str, now: DateTime<Utc>) -> Result<()>;
+    /// Confirms the TOTP secret with the step of its first code and replaces the recovery codes.
+    async fn enable_totp(
+        &self,
+        account_id: &str,
+        step: i64,
+        recovery_code_hashes: &[String],
+        now: DateTime<Utc>,
+    ) -> Result<()>;
+    /// Removes the TOTP secret and the recovery codes.
+    async fn delete_account_mfa(&self, account_id: &str) -> Result<()>;
+    /// Records the time step of an accepted code. Returns false if it or a later one was used before.
+    async fn use_totp_step(&self, account_id: &str, step: i64) -> Result<bool>;
+    async fn replace_recovery_codes(&self, account_id: &str, code_hashes: &[String]) -> Result<()>;
+    /// Marks a recovery code as used. Returns false for unknown and used codes.
+    async fn use_recovery_code(&self, account_id: &str, code_hash: &str, now: DateTime<Utc>) -> Result<bool>;
+    async fn count_unused_recovery_codes(&self, account_id: &str) -> Result<i64>;
+    /// Counts a wrong second factor. Reaching `max_failures` in a row locks the second factor
+    /// until `locked_until`.
+    async fn record_mfa_failure(&self, account_id: &str, max_failures: i64, locked_until: DateTime<Utc>) -> Result<()>;
+    /// Forgets the wrong second factors after a right one.
+    async fn reset_mfa_failures(&self, account_id: &str) -> Result<()>;
+
+    ///// Passkeys /////
+
+    async fn create_passkey(&self, passkey: &Passkey) -> Result<()>;
+    async fn get_passkey(&self, credential_id: &str) -> Result<Option<Passkey>>;
+    async fn list_passkeys(&self, account_id: &str) -> Result<Vec<Passkey>>;
+    async fn update_passkey_usage(&self, credential_id: &str, sign_count: i64, now: DateTime<Utc>) -> Result<()>;
+    /// Returns false if the account has no such passkey.
+    async fn delete_passkey(&self, account_id: &str, credential_id: &str) -> Result<bool>;
+
+    ///// Email verification and password reset /////
+
+    async fn set_account_password(&self, account_id: &str, password_hash: &str, now: DateTime<Utc>) -> Result<()>;
+    async fn set_email_verified(&self, account_id: &str, now: DateTime<Utc>) -> Result<()>;
+    async fn create_account_token(&self, token: &AccountToken) -> Result<()>;
+    /// Marks an unused and unexpired token of this purpose as used and returns it.
+    async fn use_account_token(
+        &self,
+        token_hash: &str,
+        purpose: TokenPurpose,
+        now: DateTime<Utc>,
+    ) -> Result<Option<AccountToken>>;
+    async fn delete_account_tokens(&self, account_id: &str, purpose: TokenPurpose) -> Result<()>;
+    /// The owner of the albums the account's access codes open, the one linked first.
+    async fn get_studio_of_account(&self, account_id: &str) -> Result<Option<String>>;
 
     ///// Stats /////
 
@@ -202,6 +407,46 @@ pub trait Database {
     /// Creates a customer with a generated access code, NOT assigned to any album.
     /// Returns the generated access_code string.
     async fn generate_code(&self, display_name: &str) -> Result<String>;
+
+    ///// Share links /////
+
+    async fn create_share_link(&self, link: &ShareLink) -> Result<()>;
+
+    /// Returns the share link for the given token, including expired ones.
+    async fn get_share_link(&self, token: &str) -> Result<Option<ShareLink>>;
+
+    async fn list_share_links_for_album(&self, album_id: &str) -> Result<Vec<ShareLink>>;
+
+    /// Revokes a single share link of an album, or all of them when `token` is `None`.
+    /// Returns the number of revoked links.
+    async fn revoke_share_links(&self, album_id: &str, token: Option<&str>) -> Result<u64>;
+
+    ///// Watermarks /////
+
+    async fn get_album_watermark(&self, album_id: &str) -> Result<Option<AlbumWatermark>>;
+
+    async fn set_album_watermark(&self, watermark: &AlbumWatermark) -> Result<()>;
+
+    async fn delete_album_watermark(&self, album_id: &str) -> Result<()>;
+
+    ///// Copyright templates /////
+
+    async fn get_copyright_template(&self, scope: CopyrightScope, owner_id: &str) -> Result<Option<CopyrightTemplate>>;
+
+    async fn set_copyright_template(
+        &self,
+        scope: CopyrightScope,
+        owner_id: &str,
+        template: &CopyrightTemplate,
+    ) -> Result<()>;
+
+    async fn delete_copyright_template(&self, scope: CopyrightScope, owner_id: &str) -> Result<()>;
+
+    ///// Mail templates /////
+
+    async fn get_mail_template(&self, owner_id: &str, kind: MailKind) -> Result<Option<MailTemplate>>;
+    async fn set_mail_template(&self, owner_id: &str, kind: MailKind, template: &MailTemplate) -> Result<()>;
+    async fn delete_mail_template(&self, owner_id: &str, kind: MailKind) -> Result<()>;
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
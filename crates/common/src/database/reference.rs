@@ -15,7 +15,11 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use std::fmt;
+use std::str::FromStr;
+
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use sqlx::types::chrono::{DateTime, Utc};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -27,4 +31,160 @@ pub struct Reference {
     pub description: String,
     pub last_modified: DateTime<Utc>,
     pub is_missing: bool,
+    /// Detected from the file content; `None` for files uploaded before detection existed.
+    pub mime_type: Option<String>,
+    pub role: ReferenceRole,
+    /// Hex SHA-256 of the file taken at upload; `None` for files uploaded before hashing existed.
+    #[serde(default)]
+    pub content_hash: Option<String>,
+}
+
+/// Purpose of a file within a media item, e.g. the RAW next to a JPEG.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReferenceRole {
+    #[default]
+    Original,
+    Raw,
+    Sidecar,
+    Rendition,
+    LiveVideo,
+}
+
+/// File extensions of camera raw formats, including TIFF based ones that cannot be told
+/// apart from plain TIFF by their magic bytes.
+const RAW_EXTENSIONS: &[&str] = &[
+    "dng", "cr2", "cr3", "crw", "nef", "nrw", "arw", "srf", "sr2", "raf", "orf", "rw2", "pef",
+    "srw", "x3f", "3fr", "iiq", "erf", "kdc", "mrw",
+];
+
+impl ReferenceRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReferenceRole::Original => "original",
+            ReferenceRole::Raw => "raw",
+            ReferenceRole::Sidecar => "sidecar",
+            ReferenceRole::Rendition => "rendition",
+            ReferenceRole::LiveVideo => "live-video",
+        }
+    }
+
+    /// Classifies an uploaded file. A video uploaded next to an existing photo of the same
+    /// name is the motion part of a Live Photo; one uploaded first is turned into it once
+    /// the photo arrives.
+    pub fn classify(mime_type: &str, filename: &str, has_photo_sibling: bool) -> Self {
+        let extension = filename
+            .rsplit_once('.')
+            .map(|(_, ext)| ext.to_ascii_lowercase())
+            .unwrap_or_default();
+
+        if mime_type == "application/rdf+xml" || extension == "xmp" {
+            ReferenceRole::Sidecar
+        } else if mime_type.starts_with("image/x-") || RAW_EXTENSIONS.contains(&extension.as_str())
+        {
+            ReferenceRole::Raw
+        } else if mime_type.starts_with("video/") && has_photo_sibling {
+            ReferenceRole::LiveVideo
+        } else {
+            ReferenceRole::Original
+        }
+    }
+}
+
+impl fmt::Display for ReferenceRole {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for ReferenceRole {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "original" => Ok(ReferenceRole::Original),
+            "raw" => Ok(ReferenceRole::Raw),
+            "sidecar" => Ok(ReferenceRole::Sidecar),
+            "rendition" => Ok(ReferenceRole::Rendition),
+            "live-video" => Ok(ReferenceRole::LiveVideo),
+            _ => Err(anyhow::anyhow!("Unknown reference role: {}", s)),
+        }
+    }
+}
+
+/// Location of one stored file of a media item.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReferenceFile {
+    pub filepath: String,
+    pub filename: String,
+    pub mime_type: Option<String>,
+    pub role: ReferenceRole,
+    pub content_hash: Option<String>,
+}
+
+/// Hex SHA-256 of an uploaded file, stored with its reference.
+pub fn content_hash(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+/// Name of a file without its extension, used to group related files.
+pub fn basename(filename: &str) -> &str {
+    match filename.rsplit_once('.') {
+        Some((stem, _)) if !stem.is_empty() => stem,
+        _ => filename,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_reference_roles() {
+        // when / then
+        assert_eq!(
+            ReferenceRole::classify("image/jpeg", "IMG_0001.JPG", false),
+            ReferenceRole::Original
+        );
+        assert_eq!(
+            ReferenceRole::classify("image/x-canon-cr3", "IMG_0001.CR3", true),
+            ReferenceRole::Raw
+        );
+        assert_eq!(
+            ReferenceRole::classify("image/tiff", "DSC_0001.NEF", true),
+            ReferenceRole::Raw
+        );
+        assert_eq!(
+            ReferenceRole::classify("application/rdf+xml", "IMG_0001.xmp", true),
+            ReferenceRole::Sidecar
+        );
+        assert_eq!(
+            ReferenceRole::classify("video/quicktime", "IMG_0001.MOV", true),
+            ReferenceRole::LiveVideo
+        );
+        assert_eq!(
+            ReferenceRole::classify("video/quicktime", "IMG_0001.MOV", false),
+            ReferenceRole::Original
+        );
+    }
+
+    #[test]
+    fn test_basename() {
+        assert_eq!(basename("IMG_0001.CR3"), "IMG_0001");
+        assert_eq!(basename("holiday.2023.jpg"), "holiday.2023");
+        assert_eq!(basename(".hidden"), ".hidden");
+        assert_eq!(basename("README"), "README");
+    }
+
+    #[test]
+    fn test_role_serialization() {
+        assert_eq!(
+            serde_json::to_string(&ReferenceRole::LiveVideo).unwrap(),
+            r#""live-video""#
+        );
+        assert_eq!(
+            "live-video".parse::<ReferenceRole>().unwrap(),
+            ReferenceRole::LiveVideo
+        );
+    }
 }
@@ -0,0 +1,94 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::{DateTime, Utc};
+
+/// Length of a generated share-link token.
+const TOKEN_LENGTH: usize = 32;
+
+/// A public link granting anonymous visitors access to an album.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct ShareLink {
+    pub token: String,
+    pub album_id: String,
+    pub created_by: String,
+    #[serde(skip_serializing)]
+    pub password_hash: Option<String>,
+    pub allow_download: bool,
+    pub rendition: String,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl ShareLink {
+    /// Generates an unguessable, URL-safe token.
+    pub fn generate_token() -> String {
+        use rand::Rng;
+        const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+        let mut rng = rand::thread_rng();
+        (0..TOKEN_LENGTH)
+            .map(|_| CHARSET[rng.gen_range(0..CHARSET.len())] as char)
+            .collect()
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.map(|at| at <= Utc::now()).unwrap_or(false)
+    }
+
+    pub fn is_password_protected(&self) -> bool {
+        self.password_hash.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn share_link(expires_at: Option<DateTime<Utc>>) -> ShareLink {
+        ShareLink {
+            token: ShareLink::generate_token(),
+            album_id: "album".into(),
+            created_by: "account".into(),
+            password_hash: None,
+            allow_download: false,
+            rendition: "large".into(),
+            expires_at,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_generated_tokens_are_unique() {
+        // given
+        let first = ShareLink::generate_token();
+        let second = ShareLink::generate_token();
+
+        // then
+        assert_eq!(first.len(), TOKEN_LENGTH);
+        assert!(first.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn test_expiry() {
+        assert!(!share_link(None).is_expired());
+        assert!(!share_link(Some(Utc::now() + Duration::hours(1))).is_expired());
+        assert!(share_link(Some(Utc::now() - Duration::hours(1))).is_expired());
+    }
+}
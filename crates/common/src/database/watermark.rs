@@ -0,0 +1,159 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::{DateTime, Utc};
+
+/// What is stamped onto an album's proofs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WatermarkKind {
+    #[default]
+    Text,
+    Image,
+}
+
+impl WatermarkKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WatermarkKind::Text => "text",
+            WatermarkKind::Image => "image",
+        }
+    }
+}
+
+impl FromStr for WatermarkKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(WatermarkKind::Text),
+            "image" => Ok(WatermarkKind::Image),
+            _ => Err(anyhow::anyhow!("Unknown watermark kind: {}", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for WatermarkKind {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Where the watermark is placed; `tile` repeats it across the whole image.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WatermarkPosition {
+    TopLeft,
+    TopRight,
+    Center,
+    BottomLeft,
+    #[default]
+    BottomRight,
+    Tile,
+}
+
+impl WatermarkPosition {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WatermarkPosition::TopLeft => "top-left",
+            WatermarkPosition::TopRight => "top-right",
+            WatermarkPosition::Center => "center",
+            WatermarkPosition::BottomLeft => "bottom-left",
+            WatermarkPosition::BottomRight => "bottom-right",
+            WatermarkPosition::Tile => "tile",
+        }
+    }
+}
+
+impl fmt::Display for WatermarkPosition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for WatermarkPosition {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "top-left" => Ok(WatermarkPosition::TopLeft),
+            "top-right" => Ok(WatermarkPosition::TopRight),
+            "center" => Ok(WatermarkPosition::Center),
+            "bottom-left" => Ok(WatermarkPosition::BottomLeft),
+            "bottom-right" => Ok(WatermarkPosition::BottomRight),
+            "tile" => Ok(WatermarkPosition::Tile),
+            _ => Err(anyhow::anyhow!("Unknown watermark position: {}", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for WatermarkPosition {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Watermark settings of an album, applied to everything unpaid customers and share
+/// visitors get to see.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, sqlx::FromRow)]
+pub struct AlbumWatermark {
+    pub album_id: String,
+    #[sqlx(try_from = "String")]
+    pub kind: WatermarkKind,
+    pub text: Option<String>,
+    /// Location of the uploaded watermark image for [`WatermarkKind::Image`].
+    #[serde(skip_serializing)]
+    pub image_path: Option<String>,
+    #[sqlx(try_from = "String")]
+    pub position: WatermarkPosition,
+    /// 0.0 (invisible) to 1.0 (opaque).
+    pub opacity: f64,
+    /// Watermark width relative to the image width, 0.0 to 1.0.
+    pub scale: f64,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watermark_position_round_trip() {
+        for position in [
+            WatermarkPosition::TopLeft,
+            WatermarkPosition::TopRight,
+            WatermarkPosition::Center,
+            WatermarkPosition::BottomLeft,
+            WatermarkPosition::BottomRight,
+            WatermarkPosition::Tile,
+        ] {
+            let json = serde_json::to_string(&position).unwrap();
+
+            assert_eq!(json, format!("\"{}\"", position.as_str()));
+            assert_eq!(position, position.as_str().parse().unwrap());
+        }
+        assert!("middle".parse::<WatermarkPosition>().is_err());
+    }
+}
@@ -0,0 +1,99 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! HEIC/HEIF and AVIF decoding.
+//!
+//! Browsers cannot display HEIC files and the `image` crate cannot decode them, so originals
+//! are decoded once with the libheif command line tools (`heif-dec`, or `heif-convert` on older
+//! installations) into a lossless PNG that renditions are generated from. The decoder applies
+//! the container's rotation and mirroring and keeps the embedded color profile.
+
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use tokio::process::Command;
+use tracing::{debug, warn};
+
+use crate::rendition::CACHE_BASE;
+
+const DECODERS: &[&str] = &["heif-dec", "heif-convert"];
+
+/// Whether the content type is a HEIF container the `image` crate cannot decode.
+pub fn is_heif(mime_type: &str) -> bool {
+    matches!(mime_type, "image/heic" | "image/heif" | "image/avif")
+}
+
+/// Location of the cached decoded image of a HEIF original.
+pub fn decoded_path(media_id: &str) -> PathBuf {
+    PathBuf::from(CACHE_BASE).join(media_id).join("decoded.png")
+}
+
+/// Returns the decoded primary image of a HEIF original, decoding it when not cached yet.
+pub async fn decode(source: &Path, media_id: &str) -> Option<PathBuf> {
+    let target = decoded_path(media_id);
+    if target.exists() {
+        return Some(target);
+    }
+    let dir = target.parent()?;
+    tokio::fs::create_dir_all(dir).await.ok()?;
+
+    // The tools pick the output format from the extension. A unique temp file keeps concurrent
+    // decodes of the same original from writing over each other.
+    let tmp = tempfile::Builder::new()
+        .suffix(".png")
+        .tempfile_in(dir)
+        .ok()?;
+
+    for decoder in DECODERS {
+        let status = Command::new(decoder)
+            .arg(source)
+            .arg(tmp.path())
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .await;
+
+        match status {
+            Ok(s) if s.success() => {
+                tmp.persist(&target).ok()?;
+                return Some(target);
+            }
+            Ok(_) => {
+                warn!("{} could not decode {}", decoder, source.display());
+                return None;
+            }
+            Err(e) => debug!("{} not available: {}", decoder, e),
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_heif() {
+        assert!(is_heif("image/heic"));
+        assert!(is_heif("image/heif"));
+        assert!(is_heif("image/avif"));
+        assert!(!is_heif("image/jpeg"));
+        assert!(!is_heif("video/quicktime"));
+    }
+}
@@ -22,12 +22,15 @@
 //! Responds with `401 Unauthorized` if the header is missing, the token is
 //! invalid/expired, or the account cannot be found.
 //!
+use std::sync::Arc;
+
 use async_trait::async_trait;
 use axum::extract::{Extension, FromRequestParts};
 use axum::http::StatusCode;
 use http::request::Parts;
 
 use crate::auth::auth_manager::AuthManager;
+use crate::auth::keys::KeyRing;
 use crate::auth::user::User;
 use crate::database::ArcDynDatabase;
 
@@ -47,9 +50,15 @@ where
 
         let token = raw
             .strip_prefix("Bearer ")
-            .ok_or((StatusCode::UNAUTHORIZED, "Unauthorized"))?;
+            .ok_or((StatusCode::UNAUTHORIZED, "Unauthorized"))?
+            .to_owned();
+
+        let Extension(keys): Extension<Arc<KeyRing>> =
+            Extension::from_request_parts(parts, state)
+                .await
+                .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Keys unavailable"))?;
 
-        let (sub, _role) = AuthManager::validate_jwt_token(token)
+        let (sub, _role) = AuthManager::validate_jwt_token(&keys, &token)
             .map_err(|_| (StatusCode::UNAUTHORIZED, "Unauthorized"))?;
 
         let Extension(db): Extension<ArcDynDatabase> =
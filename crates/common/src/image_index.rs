@@ -0,0 +1,135 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Indexing of uploaded images: the perceptual hash for near-duplicate detection and the
+//! placeholder for gallery grids, both computed from a single decode.
+
+use std::path::{Path, PathBuf};
+
+use tracing::{debug, info, warn};
+
+use crate::database::placeholder::Placeholder;
+use crate::database::ArcDynDatabase;
+use crate::heif;
+use crate::jobs::{self, Job};
+use crate::placeholder;
+use crate::rendition::decode_oriented;
+use crate::similarity::dhash;
+
+/// Indexes a freshly uploaded original image. Images that cannot be decoded are skipped,
+/// only storing the results can fail.
+pub async fn index_image(
+    db: ArcDynDatabase,
+    reference_id: String,
+    media_id: String,
+    source: PathBuf,
+    mime_type: String,
+) -> anyhow::Result<()> {
+    let Some((hash, placeholder)) = analyze(&source, &media_id, &mime_type).await else {
+        return Ok(());
+    };
+    db.set_perceptual_hash(&reference_id, hash as i64).await?;
+    if let Some(placeholder) = placeholder {
+        db.upsert_placeholder(&placeholder).await?;
+    }
+    debug!("Indexed image {} of media {}", reference_id, media_id);
+    Ok(())
+}
+
+/// Stores the placeholder of a video, computed from its poster frame.
+pub async fn index_poster(db: &ArcDynDatabase, media_id: &str, poster: &Path) {
+    let poster = poster.to_path_buf();
+    let id = media_id.to_string();
+    let result = tokio::task::spawn_blocking(move || {
+        decode_oriented(&poster).and_then(|(img, _)| placeholder::compute(&id, &img))
+    })
+    .await;
+    match result {
+        Ok(Ok(placeholder)) => {
+            if let Err(e) = db.upsert_placeholder(&placeholder).await {
+                warn!("Could not store placeholder of {}: {:?}", media_id, e);
+            }
+        }
+        Ok(Err(e)) => debug!("Cannot compute placeholder of {}: {:?}", media_id, e),
+        Err(e) => warn!("Placeholder of {} panicked: {:?}", media_id, e),
+    }
+}
+
+/// Queues indexing of images stored before perceptual hashes and placeholders existed.
+pub async fn backfill_images(db: ArcDynDatabase) {
+    let references = match db.get_unindexed_image_references().await {
+        Ok(references) => references,
+        Err(e) => {
+            warn!("Image index backfill: could not list references: {:?}", e);
+            return;
+        }
+    };
+    if references.is_empty() {
+        return;
+    }
+
+    info!(
+        "Image index backfill: {} image(s) to queue",
+        references.len()
+    );
+    for reference in references {
+        let key = format!("index-image:{}", reference.reference_id);
+        let job = Job::IndexImage {
+            source: Path::new(&reference.filepath).join(&reference.filename),
+            reference_id: reference.reference_id,
+            media_id: reference.media_id,
+            mime_type: reference.mime_type,
+        };
+        if let Err(e) = jobs::enqueue_unique(&db, job, &key).await {
+            warn!("Image index backfill: could not queue {}: {:?}", key, e);
+        }
+    }
+}
+
+async fn analyze(
+    source: &Path,
+    media_id: &str,
+    mime_type: &str,
+) -> Option<(u64, Option<Placeholder>)> {
+    let decodable = if heif::is_heif(mime_type) {
+        heif::decode(source, media_id).await?
+    } else {
+        source.to_path_buf()
+    };
+
+    let media_id = media_id.to_string();
+    let result = tokio::task::spawn_blocking(move || {
+        decode_oriented(&decodable).map(|(img, _)| {
+            let placeholder = placeholder::compute(&media_id, &img)
+                .inspect_err(|e| warn!("Could not compute placeholder of {}: {:?}", media_id, e))
+                .ok();
+            (dhash(&img), placeholder)
+        })
+    })
+    .await;
+    match result {
+        Ok(Ok(analysis)) => Some(analysis),
+        Ok(Err(e)) => {
+            debug!("Cannot index {}: {:?}", source.display(), e);
+            None
+        }
+        Err(e) => {
+            warn!("Indexing {} panicked: {:?}", source.display(), e);
+            None
+        }
+    }
+}
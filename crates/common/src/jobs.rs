@@ -0,0 +1,543 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Persistent background jobs.
+//!
+//! Media processing, ZIP builds and metadata scrubbing are stored in the `jobs` table and
+//! executed by a [`JobWorker`]. Failed jobs are retried with exponential backoff, each kind
+//! runs with its own concurrency limit, and jobs interrupted by a restart are picked up again.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::{DateTime, Utc};
+use tokio::sync::{Notify, Semaphore};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::database::job::{JobRecord, JobStatus};
+use crate::database::reference::ReferenceRole;
+use crate::database::ArcDynDatabase;
+use crate::heif;
+use crate::image_index::index_image;
+use crate::metadata::credit::template_for_album;
+use crate::metadata::{prepare_delivery, MetadataPolicy, UnsupportedFormat};
+use crate::rendition::{get_or_create_rendition, Rendition};
+use crate::video::{extract_poster, index_video, is_video};
+use crate::zip_cache::generate_and_write_all_zip;
+
+/// How often the worker looks for due jobs when nobody wakes it up.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const BACKOFF_BASE_SECS: i64 = 30;
+const BACKOFF_MAX_SECS: i64 = 3600;
+
+/// Renditions generated ahead of the first gallery request.
+const PREGENERATED_RENDITIONS: &[Rendition] =
+    &[Rendition::Small, Rendition::Medium, Rendition::Large];
+
+/// Wakes the worker up when a job is queued for immediate execution.
+static WAKE_UP: LazyLock<Notify> = LazyLock::new(Notify::new);
+
+/// A unit of background work, stored as JSON in the job's payload.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Job {
+    /// Perceptual hash and placeholder of an uploaded original image.
+    IndexImage {
+        reference_id: String,
+        media_id: String,
+        source: PathBuf,
+        mime_type: String,
+    },
+    /// Probe and poster frame of an uploaded video.
+    IndexVideo { media_id: String, source: PathBuf },
+    /// Thumbnails of an uploaded image or video.
+    GenerateRenditions {
+        media_id: String,
+        source: PathBuf,
+        mime_type: String,
+    },
+    /// The eagerly cached "all items" ZIP of an album.
+    BuildAlbumZip { album_id: String },
+    /// Stripped and credited copies of all files of an album under its current policy.
+    ScrubAlbum { album_id: String },
+    /// A visit of an album, counted in its statistics.
+    RecordAlbumView {
+        album_id: String,
+        viewer_id: String,
+        viewer_role: String,
+    },
+    /// A download of a media item, counted in its statistics.
+    RecordMediaDownload {
+        media_id: String,
+        album_id: Option<String>,
+        downloader_id: String,
+        downloader_role: String,
+    },
+}
+
+/// Job kinds, each with its own concurrency limit and retry budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobKind {
+    IndexImage,
+    IndexVideo,
+    GenerateRenditions,
+    BuildAlbumZip,
+    ScrubAlbum,
+    RecordAlbumView,
+    RecordMediaDownload,
+}
+
+impl JobKind {
+    pub const ALL: [JobKind; 7] = [
+        JobKind::IndexImage,
+        JobKind::IndexVideo,
+        JobKind::GenerateRenditions,
+        JobKind::BuildAlbumZip,
+        JobKind::ScrubAlbum,
+        JobKind::RecordAlbumView,
+        JobKind::RecordMediaDownload,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobKind::IndexImage => "index-image",
+            JobKind::IndexVideo => "index-video",
+            JobKind::GenerateRenditions => "generate-renditions",
+            JobKind::BuildAlbumZip => "build-album-zip",
+            JobKind::ScrubAlbum => "scrub-album",
+            JobKind::RecordAlbumView => "record-album-view",
+            JobKind::RecordMediaDownload => "record-media-download",
+        }
+    }
+
+    /// Jobs of this kind running at the same time.
+    pub fn concurrency(&self) -> usize {
+        match self {
+            JobKind::IndexImage
+            | JobKind::GenerateRenditions
+            | JobKind::RecordAlbumView
+            | JobKind::RecordMediaDownload => 4,
+            JobKind::IndexVideo | JobKind::ScrubAlbum => 2,
+            // ZIPs of large albums read every file, one at a time keeps the disk usable.
+            JobKind::BuildAlbumZip => 1,
+        }
+    }
+
+    /// Attempts before a job is marked as failed.
+    pub fn max_attempts(&self) -> i32 {
+        match self {
+            JobKind::BuildAlbumZip => 3,
+            _ => 5,
+        }
+    }
+}
+
+impl Job {
+    pub fn kind(&self) -> JobKind {
+        match self {
+            Job::IndexImage { .. } => JobKind::IndexImage,
+            Job::IndexVideo { .. } => JobKind::IndexVideo,
+            Job::GenerateRenditions { .. } => JobKind::GenerateRenditions,
+            Job::BuildAlbumZip { .. } => JobKind::BuildAlbumZip,
+            Job::ScrubAlbum { .. } => JobKind::ScrubAlbum,
+            Job::RecordAlbumView { .. } => JobKind::RecordAlbumView,
+            Job::RecordMediaDownload { .. } => JobKind::RecordMediaDownload,
+        }
+    }
+
+    async fn execute(self, db: &ArcDynDatabase) -> anyhow::Result<()> {
+        match self {
+            Job::IndexImage {
+                reference_id,
+                media_id,
+                source,
+                mime_type,
+            } => index_image(db.clone(), reference_id, media_id, source, mime_type).await,
+            Job::IndexVideo { media_id, source } => {
+                if !is_current_original(db, &media_id, &source).await? {
+                    debug!("Skipping superseded video {}", source.display());
+                    return Ok(());
+                }
+                index_video(db.clone(), media_id, source).await
+            }
+            Job::GenerateRenditions {
+                media_id,
+                source,
+                mime_type,
+            } => {
+                if !is_current_original(db, &media_id, &source).await? {
+                    debug!("Skipping renditions of superseded {}", source.display());
+                    return Ok(());
+                }
+                generate_renditions(db, &media_id, &source, &mime_type).await
+            }
+            Job::BuildAlbumZip { album_id } => {
+                generate_and_write_all_zip(&album_id, db).await?;
+                info!("Eager ZIP cached for album {}", album_id);
+                Ok(())
+            }
+            Job::ScrubAlbum { album_id } => scrub_album(db, &album_id).await,
+            Job::RecordAlbumView {
+                album_id,
+                viewer_id,
+                viewer_role,
+            } => {
+                db.record_album_view(&album_id, &viewer_id, &viewer_role)
+                    .await
+            }
+            Job::RecordMediaDownload {
+                media_id,
+                album_id,
+                downloader_id,
+                downloader_role,
+            } => {
+                db.record_media_download(
+                    &media_id,
+                    album_id.as_deref(),
+                    &downloader_id,
+                    &downloader_role,
+                )
+                .await
+            }
+        }
+    }
+}
+
+/// Queues a job for immediate execution.
+pub async fn enqueue(db: &ArcDynDatabase, job: Job) -> anyhow::Result<String> {
+    insert(db, job, None, Utc::now()).await
+}
+
+/// Queues a job unless one with the same key is already queued or running.
+pub async fn enqueue_unique(db: &ArcDynDatabase, job: Job, key: &str) -> anyhow::Result<String> {
+    if let Some(active) = db.find_active_job(key).await? {
+        return Ok(active.job_id);
+    }
+    insert(db, job, Some(key), Utc::now()).await
+}
+
+/// Queues a job to run after `delay`. A job with the same key that is still queued is
+/// pushed back instead, so a burst of changes results in a single run.
+pub async fn enqueue_debounced(
+    db: &ArcDynDatabase,
+    job: Job,
+    key: &str,
+    delay: Duration,
+) -> anyhow::Result<String> {
+    let run_at = Utc::now() + chrono::Duration::from_std(delay)?;
+    match db.find_active_job(key).await? {
+        Some(active) if active.status == JobStatus::Queued => {
+            db.reschedule_job(&active.job_id, run_at).await?;
+            Ok(active.job_id)
+        }
+        // A running job may already have read the old state, so queue another one.
+        _ => insert(db, job, Some(key), run_at).await,
+    }
+}
+
+async fn insert(
+    db: &ArcDynDatabase,
+    job: Job,
+    key: Option<&str>,
+    run_at: DateTime<Utc>,
+) -> anyhow::Result<String> {
+    let now = Utc::now();
+    let kind = job.kind();
+    let record = JobRecord {
+        job_id: Uuid::new_v4().hyphenated().to_string(),
+        kind: kind.as_str().to_string(),
+        payload: serde_json::to_string(&job)?,
+        status: JobStatus::Queued,
+        attempts: 0,
+        max_attempts: kind.max_attempts(),
+        run_at,
+        last_error: None,
+        dedupe_key: key.map(str::to_string),
+        created_at: now,
+        updated_at: now,
+    };
+    db.insert_job(&record).await?;
+    debug!("Queued {} job {}", record.kind, record.job_id);
+    if run_at <= now {
+        WAKE_UP.notify_one();
+    }
+    Ok(record.job_id)
+}
+
+/// Delay before the next attempt after `attempts` failed ones: 30s, 1m, 2m, ... capped at 1h.
+pub fn backoff(attempts: i32) -> chrono::Duration {
+    let exponent = attempts.saturating_sub(1).clamp(0, 16) as u32;
+    let secs = BACKOFF_BASE_SECS
+        .saturating_mul(1 << exponent)
+        .min(BACKOFF_MAX_SECS);
+    chrono::Duration::seconds(secs)
+}
+
+/// Executes queued jobs in the background.
+pub struct JobWorker {
+    db: ArcDynDatabase,
+    limits: Vec<(JobKind, Arc<Semaphore>)>,
+}
+
+impl JobWorker {
+    pub fn new(db: ArcDynDatabase) -> Self {
+        let limits = JobKind::ALL
+            .iter()
+            .map(|kind| (*kind, Arc::new(Semaphore::new(kind.concurrency()))))
+            .collect();
+        Self { db, limits }
+    }
+
+    /// Requeues jobs interrupted by the last shutdown and starts polling.
+    pub fn spawn(self) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            match self.db.requeue_running_jobs(Utc::now()).await {
+                Ok(0) => {}
+                Ok(n) => info!("Job queue: requeued {} interrupted job(s)", n),
+                Err(e) => warn!("Job queue: could not requeue interrupted jobs: {:?}", e),
+            }
+            loop {
+                self.run_due_jobs().await;
+                let _ = tokio::time::timeout(POLL_INTERVAL, WAKE_UP.notified()).await;
+            }
+        })
+    }
+
+    async fn run_due_jobs(&self) {
+        for (kind, limit) in &self.limits {
+            let free = limit.available_permits();
+            if free == 0 {
+                continue;
+            }
+            let due = match self
+                .db
+                .get_due_jobs(kind.as_str(), Utc::now(), free as i64)
+                .await
+            {
+                Ok(due) => due,
+                Err(e) => {
+                    warn!("Job queue: could not fetch {} jobs: {:?}", kind.as_str(), e);
+                    continue;
+                }
+            };
+            for record in due {
+                let Ok(permit) = Arc::clone(limit).try_acquire_owned() else {
+                    break;
+                };
+                match self.db.claim_job(&record.job_id, Utc::now()).await {
+                    Ok(true) => {}
+                    Ok(false) => continue,
+                    Err(e) => {
+                        warn!("Job queue: could not claim job {}: {:?}", record.job_id, e);
+                        continue;
+                    }
+                }
+                let db = self.db.clone();
+                tokio::spawn(async move {
+                    run(&db, record).await;
+                    drop(permit);
+                    // A slot is free again, there may be more due jobs of this kind.
+                    WAKE_UP.notify_one();
+                });
+            }
+        }
+    }
+}
+
+async fn run(db: &ArcDynDatabase, record: JobRecord) {
+    // Claiming counted this attempt, the stored record is from before.
+    let attempts = record.attempts + 1;
+    let result = match serde_json::from_str::<Job>(&record.payload) {
+        Ok(job) => job.execute(db).await,
+        Err(e) => Err(anyhow::anyhow!("Invalid job payload: {}", e)),
+    };
+
+    let stored = match result {
+        Ok(()) => {
+            debug!("Job {} ({}) done", record.job_id, record.kind);
+            db.complete_job(&record.job_id).await
+        }
+        Err(e) => {
+            let error = format!("{:#}", e);
+            let retry_at = (attempts < record.max_attempts).then(|| Utc::now() + backoff(attempts));
+            match retry_at {
+                Some(at) => warn!(
+                    "Job {} ({}) failed, retrying at {}: {}",
+                    record.job_id, record.kind, at, error
+                ),
+                None => warn!(
+                    "Job {} ({}) failed for good: {}",
+                    record.job_id, record.kind, error
+                ),
+            }
+            db.fail_job(&record.job_id, &error, retry_at, Utc::now())
+                .await
+        }
+    };
+    if let Err(e) = stored {
+        warn!("Job queue: could not update job {}: {:?}", record.job_id, e);
+    }
+}
+
+/// Whether `source` is still the original of the media item. A video that became the motion
+/// part of a Live Photo after its jobs were queued is not.
+async fn is_current_original(
+    db: &ArcDynDatabase,
+    media_id: &str,
+    source: &Path,
+) -> anyhow::Result<bool> {
+    let files = db.get_reference_files(media_id).await?;
+    Ok(files.first().is_some_and(|f| {
+        f.role == ReferenceRole::Original && Path::new(&f.filepath).join(&f.filename) == source
+    }))
+}
+
+async fn generate_renditions(
+    db: &ArcDynDatabase,
+    media_id: &str,
+    source: &Path,
+    mime_type: &str,
+) -> anyhow::Result<()> {
+    // Videos are scaled down from their poster frame, HEIF images from their decoded image.
+    let image_source = if is_video(mime_type) {
+        let known = db
+            .get_video_details(media_id)
+            .await?
+            .and_then(|d| d.duration_ms);
+        match extract_poster(source, media_id, known).await {
+            Some(poster) => poster,
+            None => return Ok(()),
+        }
+    } else if heif::is_heif(mime_type) {
+        heif::decode(source, media_id)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("Could not decode {}", source.display()))?
+    } else {
+        source.to_path_buf()
+    };
+
+    for rendition in PREGENERATED_RENDITIONS {
+        get_or_create_rendition(&image_source, media_id, *rendition).await?;
+    }
+    debug!("Generated renditions of media {}", media_id);
+    Ok(())
+}
+
+async fn scrub_album(db: &ArcDynDatabase, album_id: &str) -> anyhow::Result<()> {
+    let policy = db.get_album_metadata_policy(album_id).await?;
+    let template = template_for_album(db, album_id).await?;
+    if policy == MetadataPolicy::Keep && template.is_none() {
+        return Ok(());
+    }
+
+    for item in db.get_media_for_album(album_id).await? {
+        let files = db.get_reference_files(&item.uuid).await?;
+        // Sidecars, raws and live videos are delivered as uploaded.
+        let Some(file) = files.iter().find(|f| f.role == ReferenceRole::Original) else {
+            continue;
+        };
+        let source = PathBuf::from(&file.filepath).join(&file.filename);
+        match prepare_delivery(&source, &item.uuid, policy, template.as_ref()).await {
+            Ok(_) => {}
+            // Such files are never delivered, there is nothing to prepare.
+            Err(e) if e.is::<UnsupportedFormat>() => {}
+            Err(e) => return Err(e),
+        }
+    }
+    info!("Scrubbed album {} for policy {}", album_id, policy.as_str());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_job_payload_round_trip() {
+        // given
+        let job = Job::IndexImage {
+            reference_id: "ref".into(),
+            media_id: "media".into(),
+            source: PathBuf::from("data/files/a.jpg"),
+            mime_type: "image/jpeg".into(),
+        };
+
+        // when
+        let payload = serde_json::to_string(&job).unwrap();
+        let parsed: Job = serde_json::from_str(&payload).unwrap();
+
+        // then
+        assert!(payload.contains("\"kind\":\"index-image\""));
+        assert_eq!(parsed, job);
+    }
+
+    #[test]
+    fn test_job_kind_matches_payload_tag() {
+        // given
+        let jobs = vec![
+            Job::IndexVideo {
+                media_id: "m".into(),
+                source: PathBuf::from("v.mp4"),
+            },
+            Job::GenerateRenditions {
+                media_id: "m".into(),
+                source: PathBuf::from("a.jpg"),
+                mime_type: "image/jpeg".into(),
+            },
+            Job::BuildAlbumZip {
+                album_id: "a".into(),
+            },
+            Job::ScrubAlbum {
+                album_id: "a".into(),
+            },
+            Job::RecordAlbumView {
+                album_id: "a".into(),
+                viewer_id: "v".into(),
+                viewer_role: "customer".into(),
+            },
+            Job::RecordMediaDownload {
+                media_id: "m".into(),
+                album_id: Some("a".into()),
+                downloader_id: "v".into(),
+                downloader_role: "customer".into(),
+            },
+        ];
+
+        for job in jobs {
+            // when
+            let value = serde_json::to_value(&job).unwrap();
+
+            // then
+            assert_eq!(value["kind"], job.kind().as_str());
+        }
+    }
+
+    #[test]
+    fn test_backoff_doubles_and_caps() {
+        // given
+        let attempts = [1, 2, 3, 10, i32::MAX];
+
+        // when
+        let delays: Vec<i64> = attempts.iter().map(|a| backoff(*a).num_seconds()).collect();
+
+        // then
+        assert_eq!(delays, vec![30, 60, 120, 3600, 3600]);
+    }
+}
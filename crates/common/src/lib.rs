@@ -20,19 +20,36 @@
 
 use std::{collections::HashMap, sync::Arc};
 
+use auth::account_token::AccountMailSettings;
+use auth::keys::KeyRing;
+use auth::session::TrustedProxies;
+use auth::webauthn::RelyingParty;
 use axum::Router;
 use config::configuration::Configuration;
 use database::ArcDynDatabase;
+use mail::Mailer;
 use photos_network_plugin::{PluginFactoryRef, PluginId};
 
 pub mod auth;
 pub mod config;
 pub mod database;
+pub mod heif;
 pub mod http;
+pub mod image_index;
+pub mod jobs;
+pub mod mail;
 pub mod model {
     pub mod sensitive;
 }
+pub mod media_type;
+pub mod metadata;
+pub mod placeholder;
+pub mod rendition;
+pub mod similarity;
+pub mod video;
+pub mod watermark;
 pub mod zip_cache;
+pub mod zip_stream;
 
 /// Aggregates the applications configuration, its loaded plugins and the router for all REST APIs
 #[derive(Clone)]
@@ -41,15 +58,38 @@ pub struct ApplicationState {
     pub plugins: HashMap<PluginId, PluginFactoryRef>,
     pub router: Option<Router>,
     pub database: ArcDynDatabase,
+    /// Keys that sign and verify tokens and signed media URLs.
+    pub keys: Arc<KeyRing>,
+    /// Domain passkeys are scoped to and the origins their ceremonies may run on.
+    pub relying_party: Arc<RelyingParty>,
+    /// Sends the mails to account holders.
+    pub mailer: Arc<dyn Mailer>,
+    /// Links and policy of the mails to account holders.
+    pub account_mail: Arc<AccountMailSettings>,
+    /// Reverse proxies whose `X-Forwarded-For` header names the client address.
+    pub trusted_proxies: Arc<TrustedProxies>,
 }
 
 impl ApplicationState {
-    pub fn new(config: Arc<Configuration>, database: ArcDynDatabase) -> Self {
+    pub fn new(
+        config: Arc<Configuration>,
+        database: ArcDynDatabase,
+        keys: Arc<KeyRing>,
+        relying_party: Arc<RelyingParty>,
+        mailer: Arc<dyn Mailer>,
+        account_mail: Arc<AccountMailSettings>,
+        trusted_proxies: Arc<TrustedProxies>,
+    ) -> Self {
         Self {
             config,
             plugins: HashMap::new(),
             router: None,
             database,
+            keys,
+            relying_party,
+            mailer,
+            account_mail,
+            trusted_proxies,
         }
     }
 }
@@ -0,0 +1,173 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Mails to account holders, sent through SMTP or written to an outbox directory.
+
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+
+use crate::config::mail_config::MailConfig;
+
+use self::outbox::OutboxMailer;
+use self::smtp::SmtpMailer;
+
+pub mod outbox;
+pub mod smtp;
+
+/// A plain text mail to a single recipient.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, mail: &Mail) -> Result<()>;
+}
+
+/// The mailer of the `mail` section: the SMTP server if one is configured, the outbox directory otherwise.
+pub fn from_config(config: &MailConfig) -> Result<Arc<dyn Mailer>> {
+    Ok(match &config.smtp {
+        Some(smtp) => Arc::new(SmtpMailer::new(smtp, &config.from)?),
+        None => Arc::new(OutboxMailer::new(
+            &config.from,
+            Some(config.outbox_dir.clone()),
+        )),
+    })
+}
+
+/// The address of a mailbox like `Jane Doe <jane@example.com>`.
+pub(crate) fn address(mailbox: &str) -> &str {
+    match (mailbox.rfind('<'), mailbox.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &mailbox[start + 1..end],
+        _ => mailbox.trim(),
+    }
+}
+
+/// Formats the mail as RFC 5322 message with CRLF line endings. Texts that are not plain
+/// ASCII with short lines are encoded as base64.
+pub(crate) fn format_message(from: &str, mail: &Mail, now: DateTime<Utc>) -> Result<String> {
+    for header in [from, &mail.to, &mail.subject] {
+        if header.contains(['\r', '\n']) {
+            return Err(anyhow!("Mail headers must not contain line breaks"));
+        }
+    }
+    if !address(&mail.to).contains('@') {
+        return Err(anyhow!("{} is not an email address", mail.to));
+    }
+
+    let domain = address(from).rsplit('@').next().unwrap_or("localhost");
+    let subject = if mail.subject.is_ascii() {
+        mail.subject.clone()
+    } else {
+        format!("=?UTF-8?B?{}?=", STANDARD.encode(&mail.subject))
+    };
+    let body = mail.body.replace("\r\n", "\n");
+    let plain = body.is_ascii() && body.lines().all(|line| line.len() <= 998);
+
+    let mut message = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMessage-ID: <{}@{}>\r\nMIME-Version: 1.0\r\n\
+         Content-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: {}\r\n\r\n",
+        from,
+        mail.to,
+        subject,
+        now.to_rfc2822(),
+        uuid::Uuid::new_v4().simple(),
+        domain,
+        if plain { "7bit" } else { "base64" },
+    );
+    if plain {
+        for line in body.lines() {
+            message.push_str(line);
+            message.push_str("\r\n");
+        }
+    } else {
+        let encoded = STANDARD.encode(body);
+        for chunk in encoded.as_bytes().chunks(76) {
+            message.push_str(std::str::from_utf8(chunk)?);
+            message.push_str("\r\n");
+        }
+    }
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_message() {
+        // given
+        let mail = Mail {
+            to: "jane@example.com".into(),
+            subject: "Reset your password".into(),
+            body: "Hello Jane,\n\nopen the link.\n".into(),
+        };
+
+        // when
+        let message = format_message("Studio <mail@studio.example>", &mail, Utc::now()).unwrap();
+
+        // then
+        assert!(
+            message.starts_with("From: Studio <mail@studio.example>\r\nTo: jane@example.com\r\n")
+        );
+        assert!(message.contains("@studio.example>\r\n"));
+        assert!(message.contains(
+            "Content-Transfer-Encoding: 7bit\r\n\r\nHello Jane,\r\n\r\nopen the link.\r\n"
+        ));
+        assert_eq!(
+            address("Studio <mail@studio.example>"),
+            "mail@studio.example"
+        );
+    }
+
+    #[test]
+    fn test_format_message_with_header_injection_should_fail() {
+        // given
+        let mail = Mail {
+            to: "jane@example.com\r\nBcc: all@example.com".into(),
+            subject: "Grüße".into(),
+            body: "Schöne Grüße".into(),
+        };
+
+        // when
+        let injected = format_message("mail@studio.example", &mail, Utc::now());
+        let encoded = format_message(
+            "mail@studio.example",
+            &Mail {
+                to: "jane@example.com".into(),
+                ..mail
+            },
+            Utc::now(),
+        )
+        .unwrap();
+
+        // then
+        assert!(injected.is_err());
+        assert!(encoded.contains("Subject: =?UTF-8?B?R3LDvMOfZQ==?=\r\n"));
+        assert!(
+            encoded.contains("Content-Transfer-Encoding: base64\r\n\r\nU2Now7ZuZSBHcsO8w59l\r\n")
+        );
+    }
+}
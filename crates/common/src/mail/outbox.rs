@@ -0,0 +1,91 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Mails for development, written to a directory and logged instead of being sent.
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::Utc;
+use tracing::info;
+
+use super::{format_message, Mail, Mailer};
+
+pub struct OutboxMailer {
+    from: String,
+    /// Without a directory the mails only end up in the log.
+    dir: Option<PathBuf>,
+}
+
+impl OutboxMailer {
+    pub fn new(from: &str, dir: Option<PathBuf>) -> Self {
+        OutboxMailer {
+            from: from.to_string(),
+            dir,
+        }
+    }
+}
+
+#[async_trait]
+impl Mailer for OutboxMailer {
+    async fn send(&self, mail: &Mail) -> Result<()> {
+        let now = Utc::now();
+        let message = format_message(&self.from, mail, now)?;
+
+        if let Some(dir) = &self.dir {
+            tokio::fs::create_dir_all(dir).await?;
+            let path = dir.join(format!(
+                "{}-{}.eml",
+                now.format("%Y%m%dT%H%M%S"),
+                uuid::Uuid::new_v4().simple()
+            ));
+            tokio::fs::write(&path, message).await?;
+            info!("Mail to {} written to {}", mail.to, path.display());
+        }
+        info!("Mail to {}: {}\n{}", mail.to, mail.subject, mail.body);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testdir::testdir;
+
+    #[tokio::test]
+    async fn test_mail_is_written_to_outbox() {
+        // given
+        let dir = testdir!().join("outbox");
+        let mailer = OutboxMailer::new("noreply@localhost", Some(dir.clone()));
+        let mail = Mail {
+            to: "jane@example.com".into(),
+            subject: "Verify".into(),
+            body: "Open http://localhost/verify".into(),
+        };
+
+        // when
+        mailer.send(&mail).await.unwrap();
+
+        // then
+        let files: Vec<_> = std::fs::read_dir(&dir).unwrap().collect();
+        assert_eq!(files.len(), 1);
+        let message = std::fs::read_to_string(files[0].as_ref().unwrap().path()).unwrap();
+        assert!(message.contains("To: jane@example.com\r\n"));
+        assert!(message.ends_with("\r\n\r\nOpen http://localhost/verify\r\n"));
+    }
+}
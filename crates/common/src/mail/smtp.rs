@@ -0,0 +1,214 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Delivery through an SMTP server with STARTTLS or implicit TLS, using `lettre`.
+
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use chrono::Utc;
+use lettre::address::Envelope;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::transport::smtp::client::{Tls, TlsParameters};
+use lettre::transport::smtp::extension::ClientId;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use tracing::info;
+
+use super::{address, format_message, Mail, Mailer};
+use crate::config::mail_config::{SmtpConfig, SmtpTls};
+
+/// Upper bound for delivering one mail, including connecting.
+const SEND_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    host: String,
+    from: String,
+}
+
+impl SmtpMailer {
+    pub fn new(config: &SmtpConfig, from: &str) -> Result<Self> {
+        let password = match (&config.password, &config.password_file) {
+            (Some(password), _) => Some(password.clone()),
+            (None, Some(path)) => Some(
+                std::fs::read_to_string(path)
+                    .with_context(|| {
+                        format!("Could not read SMTP password from {}", path.display())
+                    })?
+                    .trim()
+                    .to_string(),
+            ),
+            (None, None) => None,
+        };
+        if config.username.is_some() && config.tls == SmtpTls::None {
+            return Err(anyhow!(
+                "SMTP credentials are only sent over TLS, set `tls` to `starttls` or `tls`"
+            ));
+        }
+        if !address(from).contains('@') {
+            return Err(anyhow!("Sender {} has no email address", from));
+        }
+
+        let tls = match config.tls {
+            SmtpTls::Tls => Tls::Wrapper(TlsParameters::new(config.host.clone())?),
+            SmtpTls::StartTls => Tls::Required(TlsParameters::new(config.host.clone())?),
+            SmtpTls::None => Tls::None,
+        };
+        // Introduces this server by the domain of its sender address.
+        let hello_name = address(from)
+            .rsplit('@')
+            .next()
+            .unwrap_or("localhost")
+            .to_string();
+        let mut transport = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host)
+            .port(config.port)
+            .tls(tls)
+            .hello_name(ClientId::Domain(hello_name))
+            .timeout(Some(SEND_TIMEOUT));
+        if let Some(username) = &config.username {
+            transport = transport.credentials(Credentials::new(
+                username.clone(),
+                password.unwrap_or_default(),
+            ));
+        }
+
+        Ok(SmtpMailer {
+            transport: transport.build(),
+            host: config.host.clone(),
+            from: from.to_string(),
+        })
+    }
+
+    async fn deliver(&self, mail: &Mail) -> Result<()> {
+        let message = format_message(&self.from, mail, Utc::now())?;
+        let envelope = Envelope::new(
+            Some(address(&self.from).parse()?),
+            vec![address(&mail.to).parse()?],
+        )?;
+
+        // The transport ends the data with a line break of its own.
+        let data = message.strip_suffix("\r\n").unwrap_or(&message);
+        self.transport
+            .send_raw(&envelope, data.as_bytes())
+            .await
+            .with_context(|| format!("Sending mail through {} failed", self.host))?;
+
+        info!("Sent mail to {} through {}", mail.to, self.host);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, mail: &Mail) -> Result<()> {
+        tokio::time::timeout(SEND_TIMEOUT, self.deliver(mail))
+            .await
+            .map_err(|_| anyhow!("Sending mail through {} timed out", self.host))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    /// Accepts one mail like a local mail sink and returns the received message.
+    async fn mail_sink(listener: TcpListener) -> String {
+        let (socket, _) = listener.accept().await.unwrap();
+        let (reader, mut writer) = socket.into_split();
+        let mut reader = BufReader::new(reader);
+        writer.write_all(b"220 sink ESMTP\r\n").await.unwrap();
+
+        let mut message = String::new();
+        let mut in_data = false;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).await.unwrap() == 0 {
+                return message;
+            }
+            if in_data {
+                if line == ".\r\n" {
+                    in_data = false;
+                    writer.write_all(b"250 queued\r\n").await.unwrap();
+                } else {
+                    message.push_str(line.strip_prefix('.').unwrap_or(&line));
+                }
+                continue;
+            }
+            let reply: &[u8] = match line.split_whitespace().next().unwrap_or_default() {
+                "EHLO" => b"250-sink\r\n250 8BITMIME\r\n",
+                "DATA" => {
+                    in_data = true;
+                    b"354 go ahead\r\n"
+                }
+                "QUIT" => b"221 bye\r\n",
+                _ => b"250 ok\r\n",
+            };
+            writer.write_all(reply).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_to_mail_sink() {
+        // given
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let sink = tokio::spawn(mail_sink(listener));
+        let config = SmtpConfig {
+            host: "127.0.0.1".into(),
+            port,
+            tls: SmtpTls::None,
+            username: None,
+            password: None,
+            password_file: None,
+        };
+        let mailer = SmtpMailer::new(&config, "Studio <mail@studio.example>").unwrap();
+
+        // when
+        mailer
+            .send(&Mail {
+                to: "jane@example.com".into(),
+                subject: "Reset your password".into(),
+                body: "Open the link:\n.hidden\n".into(),
+            })
+            .await
+            .unwrap();
+
+        // then
+        let message = sink.await.unwrap();
+        assert!(message.contains("To: jane@example.com\r\nSubject: Reset your password\r\n"));
+        assert!(message.ends_with("\r\n\r\nOpen the link:\r\n.hidden\r\n"));
+    }
+
+    #[test]
+    fn test_credentials_without_tls_should_fail() {
+        // given
+        let config = SmtpConfig {
+            host: "mail.example.com".into(),
+            port: 25,
+            tls: SmtpTls::None,
+            username: Some("studio".into()),
+            password: Some("secret".into()),
+            password_file: None,
+        };
+
+        // then
+        assert!(SmtpMailer::new(&config, "mail@studio.example").is_err());
+    }
+}
@@ -0,0 +1,253 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! File type detection by magic bytes.
+//!
+//! Uploaded files are identified by their content rather than their name, so upper-case,
+//! wrong or missing extensions do not matter.
+
+/// Number of leading bytes needed by [`detect_mime_type`].
+pub const SNIFF_LEN: usize = 4096;
+
+/// Detects the MIME type of a file from its leading bytes.
+pub fn detect_mime_type(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        return Some("image/jpeg");
+    }
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Some("image/png");
+    }
+    if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        return Some("image/gif");
+    }
+    if bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+    if bytes.starts_with(b"II*\0") || bytes.starts_with(b"MM\0*") {
+        return Some(detect_tiff_variant(bytes));
+    }
+    if bytes.len() >= 12 && &bytes[4..8] == b"ftyp" {
+        return detect_iso_bmff(bytes);
+    }
+    if is_xmp(bytes) {
+        return Some("application/rdf+xml");
+    }
+    // QuickTime files written by older cameras may start without an `ftyp` box.
+    if bytes.len() >= 8
+        && matches!(
+            &bytes[4..8],
+            b"moov" | b"mdat" | b"wide" | b"free" | b"pnot"
+        )
+    {
+        return Some("video/quicktime");
+    }
+    None
+}
+
+/// XMP sidecars start with an `xpacket` processing instruction or the `x:xmpmeta` element,
+/// possibly after a byte order mark or whitespace.
+fn is_xmp(bytes: &[u8]) -> bool {
+    let text = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    let start = text
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(text.len());
+    let text = &text[start..];
+    text.starts_with(b"<?xpacket") || text.starts_with(b"<x:xmpmeta")
+}
+
+/// Checks a MIME type against an allow-list. Entries may end in `/*` to allow a whole family.
+pub fn is_mime_type_allowed(mime_type: &str, allowed: &[String]) -> bool {
+    allowed.iter().any(|entry| match entry.strip_suffix("/*") {
+        Some(family) => mime_type
+            .split_once('/')
+            .is_some_and(|(top, _)| top.eq_ignore_ascii_case(family)),
+        None => entry.eq_ignore_ascii_case(mime_type),
+    })
+}
+
+/// Distinguishes camera raw formats that share the TIFF container.
+fn detect_tiff_variant(bytes: &[u8]) -> &'static str {
+    if bytes.len() >= 10 && &bytes[8..10] == b"CR" {
+        return "image/x-canon-cr2";
+    }
+    if has_tiff_tag(bytes, DNG_VERSION_TAG) {
+        return "image/x-adobe-dng";
+    }
+    "image/tiff"
+}
+
+const DNG_VERSION_TAG: u16 = 0xC612;
+
+/// Looks for `tag` in the first IFD of a TIFF header.
+fn has_tiff_tag(bytes: &[u8], tag: u16) -> bool {
+    let little_endian = bytes.starts_with(b"II");
+    let read_u16 = |at: usize| -> Option<u16> {
+        let b: [u8; 2] = bytes.get(at..at + 2)?.try_into().ok()?;
+        Some(if little_endian {
+            u16::from_le_bytes(b)
+        } else {
+            u16::from_be_bytes(b)
+        })
+    };
+    let read_u32 = |at: usize| -> Option<u32> {
+        let b: [u8; 4] = bytes.get(at..at + 4)?.try_into().ok()?;
+        Some(if little_endian {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        })
+    };
+
+    let Some(ifd) = read_u32(4).map(|o| o as usize) else {
+        return false;
+    };
+    let Some(count) = read_u16(ifd) else {
+        return false;
+    };
+    (0..count as usize)
+        .map_while(|i| read_u16(ifd + 2 + i * 12))
+        .any(|t| t == tag)
+}
+
+/// Maps the brands of an ISO base media file (`ftyp` box) to a MIME type.
+fn detect_iso_bmff(bytes: &[u8]) -> Option<&'static str> {
+    let box_len = u32::from_be_bytes(bytes[0..4].try_into().ok()?) as usize;
+    let end = box_len.clamp(16, bytes.len());
+    let major = &bytes[8..12];
+    // Compatible brands follow the major brand and minor version.
+    let brands: Vec<&[u8]> = std::iter::once(major)
+        .chain(bytes.get(16..end).unwrap_or_default().chunks_exact(4))
+        .collect();
+    let has = |wanted: &[&[u8]]| brands.iter().any(|b| wanted.contains(b));
+
+    if has(&[b"avif", b"avis"]) {
+        return Some("image/avif");
+    }
+    if has(&[b"heic", b"heix", b"hevc", b"hevx", b"heim", b"heis"]) {
+        return Some("image/heic");
+    }
+    if has(&[b"mif1", b"msf1"]) {
+        return Some("image/heif");
+    }
+    if major == b"crx " {
+        return Some("image/x-canon-cr3");
+    }
+    if major == b"qt  " {
+        return Some("video/quicktime");
+    }
+    if major.starts_with(b"3g") {
+        return Some("video/3gpp");
+    }
+    if has(&[
+        b"isom", b"iso2", b"iso4", b"iso5", b"iso6", b"mp41", b"mp42", b"avc1", b"dash", b"M4V ",
+    ]) {
+        return Some("video/mp4");
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ftyp(major: &[u8; 4], compatible: &[&[u8; 4]]) -> Vec<u8> {
+        let len = 16 + compatible.len() * 4;
+        let mut bytes = (len as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(b"ftyp");
+        bytes.extend_from_slice(major);
+        bytes.extend_from_slice(&[0, 0, 0, 0]);
+        for brand in compatible {
+            bytes.extend_from_slice(*brand);
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_detect_common_images() {
+        // given
+        let jpeg = [0xFF, 0xD8, 0xFF, 0xE1, 0x00];
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        let webp = b"RIFF\x10\0\0\0WEBPVP8 ";
+
+        // when / then
+        assert_eq!(detect_mime_type(&jpeg), Some("image/jpeg"));
+        assert_eq!(detect_mime_type(png), Some("image/png"));
+        assert_eq!(detect_mime_type(b"GIF89a\x01\0"), Some("image/gif"));
+        assert_eq!(detect_mime_type(webp), Some("image/webp"));
+        assert_eq!(
+            detect_mime_type(b"<?xpacket begin=''?>"),
+            Some("application/rdf+xml")
+        );
+        assert_eq!(detect_mime_type(b"plain text"), None);
+    }
+
+    #[test]
+    fn test_detect_iso_bmff_brands() {
+        // when / then
+        assert_eq!(
+            detect_mime_type(&ftyp(b"heic", &[b"mif1", b"heic"])),
+            Some("image/heic")
+        );
+        assert_eq!(
+            detect_mime_type(&ftyp(b"mif1", &[b"mif1", b"avif"])),
+            Some("image/avif")
+        );
+        assert_eq!(
+            detect_mime_type(&ftyp(b"crx ", &[b"crx ", b"isom"])),
+            Some("image/x-canon-cr3")
+        );
+        assert_eq!(
+            detect_mime_type(&ftyp(b"qt  ", &[b"qt  "])),
+            Some("video/quicktime")
+        );
+        assert_eq!(
+            detect_mime_type(&ftyp(b"isom", &[b"isom", b"avc1"])),
+            Some("video/mp4")
+        );
+        assert_eq!(
+            detect_mime_type(b"\0\0\0\x08wide\0\0\0\0mdat"),
+            Some("video/quicktime")
+        );
+    }
+
+    #[test]
+    fn test_detect_tiff_variants() {
+        // given
+        let tiff = b"II*\0\x08\0\0\0\x01\0\x00\x01\x03\0\x01\0\0\0\0\0\0\0".to_vec();
+        let cr2 = b"II*\0\x10\0\0\0CR\x02\0".to_vec();
+        let mut dng = b"MM\0*\0\0\0\x08\0\x01".to_vec();
+        dng.extend_from_slice(&[0xC6, 0x12, 0, 1, 0, 0, 0, 4, 1, 4, 0, 0]);
+
+        // when / then
+        assert_eq!(detect_mime_type(&tiff), Some("image/tiff"));
+        assert_eq!(detect_mime_type(&cr2), Some("image/x-canon-cr2"));
+        assert_eq!(detect_mime_type(&dng), Some("image/x-adobe-dng"));
+    }
+
+    #[test]
+    fn test_allow_list_matching() {
+        // given
+        let allowed = vec!["image/*".to_string(), "video/mp4".to_string()];
+
+        // when / then
+        assert!(is_mime_type_allowed("image/heic", &allowed));
+        assert!(is_mime_type_allowed("video/mp4", &allowed));
+        assert!(!is_mime_type_allowed("video/quicktime", &allowed));
+        assert!(!is_mime_type_allowed("application/pdf", &allowed));
+    }
+}
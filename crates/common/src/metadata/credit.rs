@@ -0,0 +1,335 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Builds the IPTC (IIM) and XMP blocks that carry a [`CopyrightTemplate`] into delivered
+//! JPEGs. Stored originals are never touched; credited copies live in the rendition cache.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use sha2::{Digest, Sha256};
+
+use crate::database::copyright_template::{CopyrightScope, CopyrightTemplate};
+use crate::database::ArcDynDatabase;
+use crate::rendition::CACHE_BASE;
+
+use super::{jpeg, write_atomically, MetadataPolicy};
+
+pub(crate) const PHOTOSHOP_HEADER: &[u8] = b"Photoshop 3.0\0";
+
+const RESOURCE_SIGNATURE: &[u8] = b"8BIM";
+const RESOURCE_IPTC: u16 = 0x0404;
+/// MD5 digest of the IPTC block, stale once the block is replaced.
+const RESOURCE_IPTC_DIGEST: u16 = 0x0425;
+
+/// Effective template for an album: its override on top of the owner's account template.
+pub async fn template_for_album(
+    db: &ArcDynDatabase,
+    album_id: &str,
+) -> Result<Option<CopyrightTemplate>> {
+    let album = db.get_album(album_id).await?;
+    let account = db
+        .get_copyright_template(CopyrightScope::Account, &album.owner)
+        .await?
+        .unwrap_or_default();
+    let template = db
+        .get_copyright_template(CopyrightScope::Album, album_id)
+        .await?
+        .unwrap_or_default()
+        .or(&account);
+
+    Ok((!template.is_empty()).then_some(template))
+}
+
+pub fn template_hash(template: &CopyrightTemplate) -> String {
+    let mut hasher = Sha256::new();
+    for field in [
+        &template.creator,
+        &template.copyright_notice,
+        &template.credit_line,
+        &template.rights_url,
+        &template.contact_email,
+        &template.usage_terms,
+    ] {
+        // Separate the fields so that moving text between them changes the hash.
+        hasher.update(field.as_deref().map(str::as_bytes).unwrap_or(b"\x00"));
+        hasher.update(b"\x1F");
+    }
+    hex::encode(&hasher.finalize()[..6])
+}
+
+pub fn credited_path(
+    media_id: &str,
+    filename: &str,
+    policy: MetadataPolicy,
+    template: &CopyrightTemplate,
+) -> PathBuf {
+    PathBuf::from(CACHE_BASE)
+        .join(media_id)
+        .join("credited")
+        .join(format!("{}-{}", policy.as_str(), template_hash(template)))
+        .join(filename)
+}
+
+pub(crate) async fn get_or_create_credited(
+    source: &Path,
+    target: &Path,
+    template: &CopyrightTemplate,
+) -> Result<()> {
+    if target.exists() {
+        return Ok(());
+    }
+    let bytes = tokio::fs::read(source).await?;
+    let template = template.clone();
+    let credited =
+        tokio::task::spawn_blocking(move || jpeg::embed_credit(&bytes, &template)).await??;
+    write_atomically(target, &credited).await
+}
+
+/// IIM records for the APP13 block. Record 1:90 declares UTF-8 for all text datasets.
+pub(crate) fn iptc_iim(template: &CopyrightTemplate) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    push_dataset(&mut out, 1, 90, b"\x1B%G")?;
+    push_dataset(&mut out, 2, 0, &[0x00, 0x04])?;
+
+    for (dataset, value) in [
+        (80, &template.creator),
+        (110, &template.credit_line),
+        (116, &template.copyright_notice),
+        (118, &template.contact_email),
+    ] {
+        if let Some(value) = value {
+            push_dataset(&mut out, 2, dataset, value.as_bytes())?;
+        }
+    }
+    Ok(out)
+}
+
+fn push_dataset(out: &mut Vec<u8>, record: u8, dataset: u8, value: &[u8]) -> Result<()> {
+    if value.len() > 0x7FFF {
+        bail!("IPTC value of {}:{} too long", record, dataset);
+    }
+    out.extend_from_slice(&[0x1C, record, dataset]);
+    out.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    out.extend_from_slice(value);
+    Ok(())
+}
+
+/// Photoshop image resources with the IPTC block replaced. Other resources of an existing
+/// APP13 segment, like clipping paths, are carried over.
+pub(crate) fn photoshop_resources(existing: Option<&[u8]>, iim: &[u8]) -> Vec<u8> {
+    let mut out = PHOTOSHOP_HEADER.to_vec();
+
+    if let Some(existing) = existing.and_then(|e| e.strip_prefix(PHOTOSHOP_HEADER)) {
+        let mut pos = 0;
+        while let Some((id, end)) = next_resource(existing, pos) {
+            if id != RESOURCE_IPTC && id != RESOURCE_IPTC_DIGEST {
+                out.extend_from_slice(&existing[pos..end]);
+            }
+            pos = end;
+        }
+    }
+
+    out.extend_from_slice(RESOURCE_SIGNATURE);
+    out.extend_from_slice(&RESOURCE_IPTC.to_be_bytes());
+    // Empty Pascal name, padded to an even length.
+    out.extend_from_slice(&[0, 0]);
+    out.extend_from_slice(&(iim.len() as u32).to_be_bytes());
+    out.extend_from_slice(iim);
+    if iim.len() % 2 == 1 {
+        out.push(0);
+    }
+    out
+}
+
+/// Returns the id and the end offset of the resource starting at `pos`.
+fn next_resource(bytes: &[u8], pos: usize) -> Option<(u16, usize)> {
+    if bytes.get(pos..pos + 4)? != RESOURCE_SIGNATURE {
+        return None;
+    }
+    let id = u16::from_be_bytes(bytes.get(pos + 4..pos + 6)?.try_into().ok()?);
+    let name_len = *bytes.get(pos + 6)? as usize;
+    let name_end = pos + 6 + (1 + name_len).next_multiple_of(2);
+    let size = u32::from_be_bytes(bytes.get(name_end..name_end + 4)?.try_into().ok()?) as usize;
+    let end = name_end + 4 + size.next_multiple_of(2);
+    (end <= bytes.len()).then_some((id, end))
+}
+
+pub(crate) fn xmp_packet(template: &CopyrightTemplate) -> String {
+    let mut attributes = String::new();
+    let mut properties = String::new();
+
+    if let Some(credit) = &template.credit_line {
+        attributes.push_str(&format!("\n    photoshop:Credit=\"{}\"", escape(credit)));
+    }
+    if let Some(url) = &template.rights_url {
+        attributes.push_str(&format!("\n    xmpRights:WebStatement=\"{}\"", escape(url)));
+    }
+    if template.copyright_notice.is_some() {
+        attributes.push_str("\n    xmpRights:Marked=\"True\"");
+    }
+    if let Some(creator) = &template.creator {
+        properties.push_str(&format!(
+            "\n   <dc:creator><rdf:Seq><rdf:li>{}</rdf:li></rdf:Seq></dc:creator>",
+            escape(creator)
+        ));
+    }
+    if let Some(notice) = &template.copyright_notice {
+        properties.push_str(&format!("\n   <dc:rights>{}</dc:rights>", lang_alt(notice)));
+    }
+    if let Some(terms) = &template.usage_terms {
+        properties.push_str(&format!(
+            "\n   <xmpRights:UsageTerms>{}</xmpRights:UsageTerms>",
+            lang_alt(terms)
+        ));
+    }
+    if let Some(email) = &template.contact_email {
+        properties.push_str(&format!(
+            "\n   <Iptc4xmpCore:CreatorContactInfo rdf:parseType=\"Resource\">\
+             <Iptc4xmpCore:CiEmailWork>{}</Iptc4xmpCore:CiEmailWork>\
+             </Iptc4xmpCore:CreatorContactInfo>",
+            escape(email)
+        ));
+    }
+
+    format!(
+        "<?xpacket begin=\"\u{FEFF}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n\
+         <x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n \
+         <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n  \
+         <rdf:Description rdf:about=\"\"\n    \
+         xmlns:dc=\"http://purl.org/dc/elements/1.1/\"\n    \
+         xmlns:photoshop=\"http://ns.adobe.com/photoshop/1.0/\"\n    \
+         xmlns:xmpRights=\"http://ns.adobe.com/xap/1.0/rights/\"\n    \
+         xmlns:Iptc4xmpCore=\"http://iptc.org/std/Iptc4xmpCore/1.0/xmlns/\"{}>{}\n  \
+         </rdf:Description>\n \
+         </rdf:RDF>\n\
+         </x:xmpmeta>\n\
+         <?xpacket end=\"w\"?>",
+        attributes, properties
+    )
+}
+
+fn lang_alt(value: &str) -> String {
+    format!(
+        "<rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt>",
+        escape(value)
+    )
+}
+
+fn escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            // Control characters are not allowed in XML 1.0.
+            c if c.is_control() && !matches!(c, '\n' | '\t') => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template() -> CopyrightTemplate {
+        CopyrightTemplate {
+            creator: Some("Jane Doe".into()),
+            copyright_notice: Some("© 2026 Jane Doe <all rights reserved>".into()),
+            credit_line: Some("Jane Doe Photography".into()),
+            rights_url: Some("https://example.com/license?a=1&b=2".into()),
+            contact_email: Some("jane@example.com".into()),
+            usage_terms: None,
+        }
+    }
+
+    #[test]
+    fn test_iptc_iim_contains_datasets() {
+        // given
+        let template = template();
+
+        // when
+        let iim = iptc_iim(&template).unwrap();
+
+        // then
+        assert!(iim.starts_with(&[0x1C, 1, 90, 0, 3, 0x1B, b'%', b'G']));
+        let byline = [&[0x1C, 2, 80, 0, 8][..], b"Jane Doe"].concat();
+        assert!(iim.windows(byline.len()).any(|w| w == byline));
+        assert!(iim.windows(7).any(|w| w == [0x1C, 2, 0, 0, 2, 0, 4]));
+        assert!(!iim.windows(3).any(|w| w == [0x1C, 2, 119]));
+    }
+
+    #[test]
+    fn test_photoshop_resources_replace_iptc_and_keep_others() {
+        // given
+        let mut existing = PHOTOSHOP_HEADER.to_vec();
+        // a clipping path resource with a three letter name
+        existing.extend_from_slice(b"8BIM\x07\xD0\x03abc\x00\x00\x00\x02xy");
+        // an old IPTC block and its digest
+        existing.extend_from_slice(b"8BIM\x04\x04\x00\x00\x00\x00\x00\x03old\x00");
+        existing.extend_from_slice(b"8BIM\x04\x25\x00\x00\x00\x00\x00\x02md");
+
+        // when
+        let resources = photoshop_resources(Some(&existing), b"new");
+
+        // then
+        let body = resources.strip_prefix(PHOTOSHOP_HEADER).unwrap();
+        let mut ids = Vec::new();
+        let mut pos = 0;
+        while let Some((id, end)) = next_resource(body, pos) {
+            ids.push(id);
+            pos = end;
+        }
+        assert_eq!(ids, vec![0x07D0, RESOURCE_IPTC]);
+        assert_eq!(pos, body.len());
+        assert!(!body.windows(3).any(|w| w == b"old"));
+    }
+
+    #[test]
+    fn test_xmp_packet_escapes_values() {
+        // when
+        let xmp = xmp_packet(&template());
+
+        // then
+        assert!(xmp.contains("<rdf:li>Jane Doe</rdf:li>"));
+        assert!(xmp.contains("&lt;all rights reserved&gt;"));
+        assert!(xmp.contains("xmpRights:WebStatement=\"https://example.com/license?a=1&amp;b=2\""));
+        assert!(
+            xmp.contains("<Iptc4xmpCore:CiEmailWork>jane@example.com</Iptc4xmpCore:CiEmailWork>")
+        );
+        assert!(!xmp.contains("UsageTerms"));
+    }
+
+    #[test]
+    fn test_template_hash_changes_with_fields() {
+        // given
+        let template = template();
+        let moved = CopyrightTemplate {
+            creator: None,
+            credit_line: Some("Jane Doe".into()),
+            ..template.clone()
+        };
+
+        // then
+        assert_eq!(template_hash(&template), template_hash(&template.clone()));
+        assert_ne!(template_hash(&template), template_hash(&moved));
+    }
+}
@@ -0,0 +1,383 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Minimal EXIF (TIFF structure) reader and writer.
+//!
+//! Only what stripping needs: wiping the GPS directory in place, which keeps maker notes and
+//! their absolute offsets intact, and writing a fresh block with a handful of entries.
+
+use anyhow::{anyhow, bail, Result};
+
+pub(crate) const TAG_ORIENTATION: u16 = 0x0112;
+pub(crate) const TAG_COPYRIGHT: u16 = 0x8298;
+pub(crate) const TAG_EXIF_IFD: u16 = 0x8769;
+pub(crate) const TAG_GPS_IFD: u16 = 0x8825;
+pub(crate) const TAG_DATE_TIME_ORIGINAL: u16 = 0x9003;
+pub(crate) const TAG_OFFSET_TIME_ORIGINAL: u16 = 0x9011;
+pub(crate) const TAG_SUB_SEC_TIME_ORIGINAL: u16 = 0x9291;
+
+const TYPE_LONG: u16 = 4;
+
+/// A directory entry with its value bytes, in the byte order of the block it came from.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Entry {
+    pub tag: u16,
+    pub typ: u16,
+    pub count: u32,
+    pub data: Vec<u8>,
+}
+
+/// A parsed EXIF block, borrowing the raw TIFF bytes.
+pub(crate) struct Exif<'a> {
+    bytes: &'a [u8],
+    little_endian: bool,
+}
+
+impl<'a> Exif<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<Self> {
+        let little_endian = match bytes.get(0..4) {
+            Some(b"II*\0") => true,
+            Some(b"MM\0*") => false,
+            _ => bail!("Not a TIFF structure"),
+        };
+        Ok(Self {
+            bytes,
+            little_endian,
+        })
+    }
+
+    fn u16_at(&self, offset: usize) -> Result<u16> {
+        let b = self
+            .bytes
+            .get(offset..offset + 2)
+            .ok_or_else(|| anyhow!("Truncated EXIF"))?;
+        Ok(if self.little_endian {
+            u16::from_le_bytes([b[0], b[1]])
+        } else {
+            u16::from_be_bytes([b[0], b[1]])
+        })
+    }
+
+    fn u32_at(&self, offset: usize) -> Result<u32> {
+        let b = self
+            .bytes
+            .get(offset..offset + 4)
+            .ok_or_else(|| anyhow!("Truncated EXIF"))?;
+        Ok(if self.little_endian {
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+        } else {
+            u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+        })
+    }
+
+    fn ifd0_offset(&self) -> Result<usize> {
+        Ok(self.u32_at(4)? as usize)
+    }
+
+    /// Reads the entries of the directory at `offset`.
+    fn entries(&self, offset: usize) -> Result<Vec<Entry>> {
+        let count = self.u16_at(offset)? as usize;
+        let mut entries = Vec::with_capacity(count);
+        for i in 0..count {
+            let at = offset + 2 + i * 12;
+            let tag = self.u16_at(at)?;
+            let typ = self.u16_at(at + 2)?;
+            let n = self.u32_at(at + 4)?;
+            let len = type_size(typ).saturating_mul(n as usize);
+            let data_at = if len <= 4 {
+                at + 8
+            } else {
+                self.u32_at(at + 8)? as usize
+            };
+            let data = self
+                .bytes
+                .get(data_at..data_at.saturating_add(len))
+                .ok_or_else(|| anyhow!("EXIF value out of bounds"))?
+                .to_vec();
+            entries.push(Entry {
+                tag,
+                typ,
+                count: n,
+                data,
+            });
+        }
+        Ok(entries)
+    }
+
+    fn pointer(&self, entries: &[Entry], tag: u16) -> Option<usize> {
+        let entry = entries.iter().find(|e| e.tag == tag && e.data.len() == 4)?;
+        let d = &entry.data;
+        Some(if self.little_endian {
+            u32::from_le_bytes([d[0], d[1], d[2], d[3]])
+        } else {
+            u32::from_be_bytes([d[0], d[1], d[2], d[3]])
+        } as usize)
+    }
+
+    /// Keeps only the `ifd0_tags` of the main directory and `exif_tags` of the EXIF directory.
+    /// Returns `None` when nothing is left.
+    pub fn retain(&self, ifd0_tags: &[u16], exif_tags: &[u16]) -> Result<Option<Vec<u8>>> {
+        let ifd0 = self.entries(self.ifd0_offset()?)?;
+        let exif = match self.pointer(&ifd0, TAG_EXIF_IFD) {
+            Some(offset) => self.entries(offset)?,
+            None => vec![],
+        };
+
+        let ifd0: Vec<Entry> = ifd0
+            .into_iter()
+            .filter(|e| ifd0_tags.contains(&e.tag))
+            .collect();
+        let exif: Vec<Entry> = exif
+            .into_iter()
+            .filter(|e| exif_tags.contains(&e.tag))
+            .collect();
+        if ifd0.is_empty() && exif.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(write(self.little_endian, ifd0, exif)))
+    }
+}
+
+/// Empties the GPS directory without moving any other data.
+/// Returns whether there were GPS entries.
+pub(crate) fn wipe_gps(bytes: &mut [u8]) -> Result<bool> {
+    let (gps, entries) = {
+        let exif = Exif::parse(bytes)?;
+        let ifd0 = exif.entries(exif.ifd0_offset()?)?;
+        let Some(gps) = exif.pointer(&ifd0, TAG_GPS_IFD) else {
+            return Ok(false);
+        };
+        let count = exif.u16_at(gps)? as usize;
+        if count == 0 {
+            return Ok(false);
+        }
+        // Out of line values first, they are only reachable through the entries.
+        let mut ranges = vec![(gps + 2, count * 12 + 4)];
+        for i in 0..count {
+            let at = gps + 2 + i * 12;
+            let len = type_size(exif.u16_at(at + 2)?).saturating_mul(exif.u32_at(at + 4)? as usize);
+            if len > 4 {
+                ranges.push((exif.u32_at(at + 8)? as usize, len));
+            }
+        }
+        (gps, ranges)
+    };
+
+    for (start, len) in entries {
+        if let Some(region) = bytes.get_mut(start..start.saturating_add(len)) {
+            region.fill(0);
+        }
+    }
+    // An empty directory followed by a zero "next directory" offset.
+    if let Some(count) = bytes.get_mut(gps..gps + 2) {
+        count.fill(0);
+    }
+
+    Ok(true)
+}
+
+/// Writes a TIFF structure with an optional EXIF sub-directory.
+pub(crate) fn write(little_endian: bool, mut ifd0: Vec<Entry>, mut exif: Vec<Entry>) -> Vec<u8> {
+    let u16b = |v: u16| {
+        if little_endian {
+            v.to_le_bytes()
+        } else {
+            v.to_be_bytes()
+        }
+    };
+    let u32b = |v: u32| {
+        if little_endian {
+            v.to_le_bytes()
+        } else {
+            v.to_be_bytes()
+        }
+    };
+
+    if !exif.is_empty() {
+        ifd0.push(Entry {
+            tag: TAG_EXIF_IFD,
+            typ: TYPE_LONG,
+            count: 1,
+            data: vec![0; 4],
+        });
+    }
+    ifd0.sort_by_key(|e| e.tag);
+    exif.sort_by_key(|e| e.tag);
+
+    let exif_offset = 8 + directory_len(&ifd0);
+    if let Some(pointer) = ifd0.iter_mut().find(|e| e.tag == TAG_EXIF_IFD) {
+        pointer.data = u32b(exif_offset as u32).to_vec();
+    }
+
+    let mut out = Vec::with_capacity(exif_offset + directory_len(&exif));
+    out.extend_from_slice(if little_endian { b"II*\0" } else { b"MM\0*" });
+    out.extend_from_slice(&u32b(8));
+
+    for entries in [&ifd0, &exif] {
+        if entries.is_empty() {
+            continue;
+        }
+        let start = out.len();
+        let mut data_at = start + 2 + entries.len() * 12 + 4;
+        let mut data = vec![];
+
+        out.extend_from_slice(&u16b(entries.len() as u16));
+        for entry in entries {
+            out.extend_from_slice(&u16b(entry.tag));
+            out.extend_from_slice(&u16b(entry.typ));
+            out.extend_from_slice(&u32b(entry.count));
+            if entry.data.len() <= 4 {
+                let mut inline = entry.data.clone();
+                inline.resize(4, 0);
+                out.extend_from_slice(&inline);
+            } else {
+                out.extend_from_slice(&u32b(data_at as u32));
+                data.extend_from_slice(&entry.data);
+                if entry.data.len() % 2 == 1 {
+                    data.push(0);
+                }
+                data_at = start + 2 + entries.len() * 12 + 4 + data.len();
+            }
+        }
+        out.extend_from_slice(&u32b(0));
+        out.extend_from_slice(&data);
+    }
+
+    out
+}
+
+fn directory_len(entries: &[Entry]) -> usize {
+    if entries.is_empty() {
+        return 0;
+    }
+    let data: usize = entries
+        .iter()
+        .filter(|e| e.data.len() > 4)
+        .map(|e| e.data.len() + e.data.len() % 2)
+        .sum();
+    2 + entries.len() * 12 + 4 + data
+}
+
+fn type_size(typ: u16) -> usize {
+    match typ {
+        1 | 2 | 6 | 7 => 1,
+        3 | 8 => 2,
+        4 | 9 | 11 | 13 => 4,
+        5 | 10 | 12 => 8,
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn ascii(tag: u16, text: &str) -> Entry {
+        let mut data = text.as_bytes().to_vec();
+        data.push(0);
+        Entry {
+            tag,
+            typ: 2,
+            count: data.len() as u32,
+            data,
+        }
+    }
+
+    /// A block with orientation, copyright, a capture date and a GPS directory.
+    pub(crate) fn sample_exif() -> Vec<u8> {
+        let exif = vec![
+            ascii(TAG_DATE_TIME_ORIGINAL, "2024:05:01 10:00:00"),
+            ascii(0xA431, "SERIAL123"),
+        ];
+        let ifd0 = |gps_offset: u32| {
+            vec![
+                Entry {
+                    tag: TAG_ORIENTATION,
+                    typ: 3,
+                    count: 1,
+                    data: vec![6, 0],
+                },
+                ascii(0x010F, "Canon"),
+                ascii(TAG_COPYRIGHT, "Studio"),
+                Entry {
+                    tag: TAG_GPS_IFD,
+                    typ: TYPE_LONG,
+                    count: 1,
+                    data: gps_offset.to_le_bytes().to_vec(),
+                },
+            ]
+        };
+
+        // The pointer is stored inline, so its value does not change the layout.
+        let gps_offset = write(true, ifd0(0), exif.clone()).len() as u32;
+        let mut block = write(true, ifd0(gps_offset), exif);
+
+        // GPSLatitudeRef "N" inline, GPSLatitude as three rationals behind the directory.
+        block.extend_from_slice(&2u16.to_le_bytes());
+        block.extend_from_slice(&[1, 0, 2, 0, 2, 0, 0, 0, b'N', 0, 0, 0]);
+        block.extend_from_slice(&[2, 0, 5, 0, 3, 0, 0, 0]);
+        block.extend_from_slice(&(gps_offset + 2 + 24 + 4).to_le_bytes());
+        block.extend_from_slice(&0u32.to_le_bytes());
+        block.extend_from_slice(&[7; 24]);
+        block
+    }
+
+    #[test]
+    fn test_wipe_gps() {
+        // given
+        let mut block = sample_exif();
+        assert!(block.windows(24).any(|w| w == [7; 24]));
+
+        // when
+        let had_gps = wipe_gps(&mut block).unwrap();
+
+        // then
+        assert!(had_gps);
+        assert!(!block.windows(24).any(|w| w == [7; 24]));
+        assert!(block.windows(6).any(|w| w == b"Canon\0"));
+        assert!(!wipe_gps(&mut block).unwrap());
+    }
+
+    #[test]
+    fn test_retain() {
+        // given
+        let block = sample_exif();
+
+        // when
+        let retained = Exif::parse(&block)
+            .unwrap()
+            .retain(&[TAG_ORIENTATION, TAG_COPYRIGHT], &[TAG_DATE_TIME_ORIGINAL])
+            .unwrap()
+            .unwrap();
+
+        // then
+        let exif = Exif::parse(&retained).unwrap();
+        let ifd0 = exif.entries(exif.ifd0_offset().unwrap()).unwrap();
+        let tags: Vec<u16> = ifd0.iter().map(|e| e.tag).collect();
+        assert_eq!(tags, vec![TAG_ORIENTATION, TAG_COPYRIGHT, TAG_EXIF_IFD]);
+        let sub = exif
+            .entries(exif.pointer(&ifd0, TAG_EXIF_IFD).unwrap())
+            .unwrap();
+        assert_eq!(
+            sub,
+            vec![ascii(TAG_DATE_TIME_ORIGINAL, "2024:05:01 10:00:00")]
+        );
+        assert!(!retained.windows(9).any(|w| w == b"SERIAL123"));
+        assert!(!retained.windows(6).any(|w| w == b"Canon\0"));
+    }
+}
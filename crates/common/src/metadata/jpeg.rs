@@ -0,0 +1,342 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Metadata stripping for JPEG files.
+
+use anyhow::{bail, Result};
+
+use super::credit::{iptc_iim, photoshop_resources, xmp_packet, PHOTOSHOP_HEADER};
+use super::{filter_exif, MetadataPolicy};
+use crate::database::copyright_template::CopyrightTemplate;
+
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+const XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const XMP_EXTENSION_HEADER: &[u8] = b"http://ns.adobe.com/xmp/extension/\0";
+const MPF_HEADER: &[u8] = b"MPF\0";
+
+const SOI: u8 = 0xD8;
+const EOI: u8 = 0xD9;
+const SOS: u8 = 0xDA;
+const APP0: u8 = 0xE0;
+const APP1: u8 = 0xE1;
+const APP2: u8 = 0xE2;
+const APP13: u8 = 0xED;
+const COM: u8 = 0xFE;
+
+/// Rewrites the metadata segments in front of the image data. Segments needed to display
+/// the image (JFIF, ICC profile, Adobe color transform) always stay.
+pub(crate) fn strip(bytes: &[u8], policy: MetadataPolicy) -> Result<Vec<u8>> {
+    let (segments, pos) = read_segments(bytes)?;
+
+    let mut out = Vec::with_capacity(bytes.len());
+    out.extend_from_slice(&[0xFF, SOI]);
+
+    for segment in segments {
+        match segment_action(segment.marker, segment.payload(bytes), policy) {
+            Action::Keep => out.extend_from_slice(&bytes[segment.start..segment.end]),
+            Action::Drop => {}
+            Action::Replace(exif) => {
+                if let Some(tiff) = exif {
+                    push_segment(&mut out, APP1, &[EXIF_HEADER, &tiff].concat())?;
+                }
+            }
+        }
+    }
+
+    // Phones append further images (depth maps, previews) with their own metadata after
+    // the end of the primary image. Only keep them when nothing is stripped.
+    let image_end = if policy == MetadataPolicy::Keep {
+        bytes.len()
+    } else {
+        end_of_image(bytes, pos)?
+    };
+    out.extend_from_slice(&bytes[pos..image_end]);
+
+    Ok(out)
+}
+
+/// Writes the template as XMP and IPTC right behind the JFIF and EXIF headers, replacing
+/// XMP and IPTC blocks of the file. Image data is copied unchanged.
+pub(crate) fn embed_credit(bytes: &[u8], template: &CopyrightTemplate) -> Result<Vec<u8>> {
+    let (segments, pos) = read_segments(bytes)?;
+
+    let existing_resources = segments
+        .iter()
+        .map(|s| (s.marker, s.payload(bytes)))
+        .find(|(marker, payload)| *marker == APP13 && payload.starts_with(PHOTOSHOP_HEADER))
+        .map(|(_, payload)| payload);
+    let resources = photoshop_resources(existing_resources, &iptc_iim(template)?);
+    let xmp = [XMP_HEADER, xmp_packet(template).as_bytes()].concat();
+
+    let mut out = Vec::with_capacity(bytes.len() + xmp.len() + resources.len() + 8);
+    out.extend_from_slice(&[0xFF, SOI]);
+
+    // JFIF and EXIF readers expect their segment first.
+    let leading = segments
+        .iter()
+        .take_while(|s| {
+            s.marker == APP0 || (s.marker == APP1 && s.payload(bytes).starts_with(EXIF_HEADER))
+        })
+        .count();
+    for segment in &segments[..leading] {
+        out.extend_from_slice(&bytes[segment.start..segment.end]);
+    }
+    push_segment(&mut out, APP1, &xmp)?;
+    push_segment(&mut out, APP13, &resources)?;
+
+    for segment in &segments[leading..] {
+        let payload = segment.payload(bytes);
+        let replaced = match segment.marker {
+            APP1 => payload.starts_with(XMP_HEADER) || payload.starts_with(XMP_EXTENSION_HEADER),
+            APP13 => payload.starts_with(PHOTOSHOP_HEADER),
+            _ => false,
+        };
+        if !replaced {
+            out.extend_from_slice(&bytes[segment.start..segment.end]);
+        }
+    }
+    out.extend_from_slice(&bytes[pos..]);
+
+    Ok(out)
+}
+
+struct Segment {
+    marker: u8,
+    start: usize,
+    end: usize,
+}
+
+impl Segment {
+    fn payload<'a>(&self, bytes: &'a [u8]) -> &'a [u8] {
+        &bytes[self.start + 4..self.end]
+    }
+}
+
+/// Returns the segments in front of the first scan and the position of its SOS marker.
+fn read_segments(bytes: &[u8]) -> Result<(Vec<Segment>, usize)> {
+    if bytes.get(0..2) != Some(&[0xFF, SOI]) {
+        bail!("Not a JPEG file");
+    }
+
+    let mut segments = Vec::new();
+    let mut pos = 2;
+    loop {
+        // Fill bytes may precede a marker.
+        while bytes.get(pos) == Some(&0xFF) && bytes.get(pos + 1) == Some(&0xFF) {
+            pos += 1;
+        }
+        let (Some(&0xFF), Some(&marker)) = (bytes.get(pos), bytes.get(pos + 1)) else {
+            bail!("Malformed JPEG marker at {}", pos);
+        };
+        if marker == SOS {
+            return Ok((segments, pos));
+        }
+        let len = u16::from_be_bytes([
+            *bytes.get(pos + 2).unwrap_or(&0),
+            *bytes.get(pos + 3).unwrap_or(&0),
+        ]) as usize;
+        let end = pos + 2 + len;
+        if len < 2 || end > bytes.len() {
+            bail!("Truncated JPEG segment at {}", pos);
+        }
+        segments.push(Segment {
+            marker,
+            start: pos,
+            end,
+        });
+        pos = end;
+    }
+}
+
+fn push_segment(out: &mut Vec<u8>, marker: u8, payload: &[u8]) -> Result<()> {
+    let len = 2 + payload.len();
+    if len > u16::MAX as usize {
+        bail!("JPEG segment too large");
+    }
+    out.extend_from_slice(&[0xFF, marker]);
+    out.extend_from_slice(&(len as u16).to_be_bytes());
+    out.extend_from_slice(payload);
+    Ok(())
+}
+
+enum Action {
+    Keep,
+    Drop,
+    Replace(Option<Vec<u8>>),
+}
+
+fn segment_action(marker: u8, payload: &[u8], policy: MetadataPolicy) -> Action {
+    if policy == MetadataPolicy::Keep {
+        return Action::Keep;
+    }
+    match marker {
+        APP1 if payload.starts_with(EXIF_HEADER) => {
+            match filter_exif(&payload[EXIF_HEADER.len()..], policy) {
+                Ok(exif) => Action::Replace(exif),
+                // Unreadable EXIF cannot be filtered, so none of it is delivered.
+                Err(_) => Action::Drop,
+            }
+        }
+        // XMP may repeat the GPS position, drop it for every stripping policy.
+        APP1 if payload.starts_with(XMP_HEADER) || payload.starts_with(XMP_EXTENSION_HEADER) => {
+            Action::Drop
+        }
+        APP1 => Action::Drop,
+        APP2 if payload.starts_with(MPF_HEADER) => Action::Drop,
+        APP13 | COM if policy == MetadataPolicy::StripGps => Action::Keep,
+        APP13 | COM => Action::Drop,
+        // APP3 - APP12 and APP15 hold vendor data.
+        0xE3..=0xEC | 0xEF => Action::Drop,
+        _ => Action::Keep,
+    }
+}
+
+/// Position right after the EOI marker of the image whose first scan starts at `pos`.
+fn end_of_image(bytes: &[u8], mut pos: usize) -> Result<usize> {
+    while pos + 1 < bytes.len() {
+        if bytes[pos] != 0xFF {
+            pos += 1;
+            continue;
+        }
+        match bytes[pos + 1] {
+            // Stuffed zero byte, restart markers and fill bytes belong to the scan data.
+            0x00 | 0xD0..=0xD7 | 0xFF => pos += 1,
+            EOI => return Ok(pos + 2),
+            // Tables and further scans of progressive images have a length field.
+            _ => {
+                let len = u16::from_be_bytes([
+                    *bytes.get(pos + 2).unwrap_or(&0),
+                    *bytes.get(pos + 3).unwrap_or(&0),
+                ]) as usize;
+                pos += 2 + len.max(2);
+            }
+        }
+    }
+    // Files cut short still get delivered as far as they go.
+    Ok(bytes.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::exif::tests::sample_exif;
+
+    fn segment(marker: u8, payload: &[u8]) -> Vec<u8> {
+        let mut segment = vec![0xFF, marker];
+        segment.extend_from_slice(&((payload.len() + 2) as u16).to_be_bytes());
+        segment.extend_from_slice(payload);
+        segment
+    }
+
+    fn sample_jpeg() -> Vec<u8> {
+        let mut jpeg = vec![0xFF, SOI];
+        jpeg.extend(segment(0xE0, b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0"));
+        jpeg.extend(segment(APP1, &[EXIF_HEADER, &sample_exif()].concat()));
+        jpeg.extend(segment(
+            APP1,
+            &[XMP_HEADER, b"<x:xmpmeta>GPS</x:xmpmeta>"].concat(),
+        ));
+        jpeg.extend(segment(APP2, b"ICC_PROFILE\0\x01\x01profile"));
+        jpeg.extend(segment(COM, b"shot at home"));
+        jpeg.extend(segment(SOS, &[1, 1, 0, 0, 0x3F, 0]));
+        jpeg.extend_from_slice(&[0x12, 0xFF, 0x00, 0x34, 0xFF, 0xD0, 0x56, 0xFF, EOI]);
+        // A trailing secondary image, e.g. a depth map.
+        jpeg.extend_from_slice(&[0xFF, SOI, 0xFF, EOI]);
+        jpeg
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|w| w == needle)
+    }
+
+    #[test]
+    fn test_keep_returns_file_unchanged() {
+        // given
+        let jpeg = sample_jpeg();
+
+        // when
+        let stripped = strip(&jpeg, MetadataPolicy::Keep).unwrap();
+
+        // then
+        assert_eq!(stripped, jpeg);
+    }
+
+    #[test]
+    fn test_strip_gps() {
+        // given
+        let jpeg = sample_jpeg();
+
+        // when
+        let stripped = strip(&jpeg, MetadataPolicy::StripGps).unwrap();
+
+        // then
+        assert!(!contains(&stripped, &[7; 24]));
+        assert!(!contains(&stripped, b"<x:xmpmeta>"));
+        assert!(contains(&stripped, b"SERIAL123"));
+        assert!(contains(&stripped, b"shot at home"));
+        assert!(contains(&stripped, b"ICC_PROFILE"));
+        assert!(stripped.ends_with(&[0x56, 0xFF, EOI]));
+    }
+
+    #[test]
+    fn test_strip_all() {
+        // given
+        let jpeg = sample_jpeg();
+
+        // when
+        let stripped = strip(&jpeg, MetadataPolicy::StripAll).unwrap();
+
+        // then
+        assert!(!contains(&stripped, b"SERIAL123"));
+        assert!(!contains(&stripped, b"Studio"));
+        assert!(!contains(&stripped, b"shot at home"));
+        assert!(contains(&stripped, b"ICC_PROFILE"));
+        assert!(contains(&stripped, b"JFIF"));
+        assert!(stripped.ends_with(&[0x56, 0xFF, EOI]));
+    }
+
+    #[test]
+    fn test_embed_credit_replaces_xmp_and_keeps_image() {
+        // given
+        let jpeg = sample_jpeg();
+        let template = CopyrightTemplate {
+            creator: Some("Jane Doe".into()),
+            copyright_notice: Some("© Jane Doe".into()),
+            ..Default::default()
+        };
+
+        // when
+        let credited = embed_credit(&jpeg, &template).unwrap();
+
+        // then
+        let (segments, _) = read_segments(&credited).unwrap();
+        let markers: Vec<u8> = segments.iter().map(|s| s.marker).collect();
+        assert_eq!(markers, vec![0xE0, APP1, APP1, APP13, APP2, COM]);
+        assert!(segments[1].payload(&credited).starts_with(EXIF_HEADER));
+        assert!(segments[2].payload(&credited).starts_with(XMP_HEADER));
+        assert!(!contains(&credited, b"<x:xmpmeta>GPS</x:xmpmeta>"));
+        assert!(contains(
+            &credited,
+            "<rdf:li xml:lang=\"x-default\">© Jane Doe</rdf:li>".as_bytes()
+        ));
+        assert!(credited.ends_with(&[0x56, 0xFF, EOI, 0xFF, SOI, 0xFF, EOI]));
+
+        // embedding twice keeps a single credit
+        let twice = embed_credit(&credited, &template).unwrap();
+        assert_eq!(twice, credited);
+    }
+}
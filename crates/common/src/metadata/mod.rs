@@ -0,0 +1,349 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Metadata delivery policies.
+//!
+//! Originals handed out to customers and share visitors pass through the album's policy.
+//! JPEG, PNG and WebP files are rewritten without re-encoding, videos are remuxed with `ffmpeg`
+//! and HEIF images are delivered as metadata free JPEG conversions. Formats whose metadata
+//! cannot be removed are withheld rather than delivered with it. The orientation tag always
+//! survives, since images would otherwise be shown rotated. Derivatives are cached next to
+//! the renditions, so they are invalidated together with them.
+
+pub mod credit;
+mod exif;
+mod jpeg;
+mod png;
+mod webp;
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
+
+use crate::database::copyright_template::CopyrightTemplate;
+use crate::heif;
+use crate::media_type::{detect_mime_type, SNIFF_LEN};
+use crate::rendition::{get_or_create_rendition, Rendition, CACHE_BASE};
+
+/// Which metadata of originals survives delivery. Variants are ordered from lenient to strict.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MetadataPolicy {
+    /// Files are delivered as uploaded.
+    #[default]
+    Keep,
+    /// Removes GPS coordinates and XMP, which may repeat them.
+    StripGps,
+    /// Keeps only the copyright notice and the capture date.
+    CopyrightAndDate,
+    /// Removes all metadata.
+    StripAll,
+}
+
+impl MetadataPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MetadataPolicy::Keep => "keep",
+            MetadataPolicy::StripGps => "strip-gps",
+            MetadataPolicy::CopyrightAndDate => "copyright-and-date",
+            MetadataPolicy::StripAll => "strip-all",
+        }
+    }
+}
+
+impl fmt::Display for MetadataPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for MetadataPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(MetadataPolicy::Keep),
+            "strip-gps" => Ok(MetadataPolicy::StripGps),
+            "copyright-and-date" => Ok(MetadataPolicy::CopyrightAndDate),
+            "strip-all" => Ok(MetadataPolicy::StripAll),
+            _ => Err(anyhow::anyhow!("Unknown metadata policy: {}", s)),
+        }
+    }
+}
+
+/// Returned when a file's metadata cannot be removed, so it must not be delivered.
+#[derive(Debug)]
+pub struct UnsupportedFormat(pub String);
+
+impl fmt::Display for UnsupportedFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Metadata of {} files cannot be stripped", self.0)
+    }
+}
+
+impl std::error::Error for UnsupportedFormat {}
+
+/// A file ready for delivery under a metadata policy.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Delivery {
+    pub path: PathBuf,
+    pub mime_type: String,
+    /// File name for downloads, differs from the stored name after conversions.
+    pub filename: String,
+}
+
+/// Location of the cached stripped copy of a stored file.
+pub fn stripped_path(media_id: &str, filename: &str, policy: MetadataPolicy) -> PathBuf {
+    PathBuf::from(CACHE_BASE)
+        .join(media_id)
+        .join("stripped")
+        .join(policy.as_str())
+        .join(filename)
+}
+
+/// Returns a copy of `source` that only carries the metadata allowed by `policy`,
+/// creating and caching it if necessary. Fails with [`UnsupportedFormat`] for files
+/// that cannot be stripped.
+pub async fn get_or_create_stripped(
+    source: &Path,
+    media_id: &str,
+    policy: MetadataPolicy,
+) -> anyhow::Result<Delivery> {
+    let filename = source
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| media_id.to_string());
+    let mime_type = sniff(source).await?;
+
+    if policy == MetadataPolicy::Keep {
+        return Ok(Delivery {
+            path: source.to_path_buf(),
+            mime_type: mime_type.unwrap_or("application/octet-stream").to_string(),
+            filename,
+        });
+    }
+
+    match mime_type {
+        Some(mime @ ("image/jpeg" | "image/png" | "image/webp")) => {
+            let target = stripped_path(media_id, &filename, policy);
+            if !target.exists() {
+                rewrite(source, &target, mime, policy).await?;
+            }
+            Ok(Delivery {
+                path: target,
+                mime_type: mime.to_string(),
+                filename,
+            })
+        }
+        // GIFs carry no EXIF.
+        Some(mime @ "image/gif") => Ok(Delivery {
+            path: source.to_path_buf(),
+            mime_type: mime.to_string(),
+            filename,
+        }),
+        Some(mime) if mime.starts_with("video/") => {
+            let target = stripped_path(media_id, &filename, policy);
+            if !target.exists() {
+                remux(source, &target).await?;
+            }
+            Ok(Delivery {
+                path: target,
+                mime_type: mime.to_string(),
+                filename,
+            })
+        }
+        Some(mime) if heif::is_heif(mime) => {
+            let decoded = heif::decode(source, media_id)
+                .await
+                .ok_or_else(|| UnsupportedFormat(mime.to_string()))?;
+            let path = get_or_create_rendition(&decoded, media_id, Rendition::Full).await?;
+            let stem = filename
+                .rsplit_once('.')
+                .map(|(stem, _)| stem)
+                .unwrap_or(&filename);
+            Ok(Delivery {
+                path,
+                mime_type: "image/jpeg".to_string(),
+                filename: format!("{}.jpg", stem),
+            })
+        }
+        other => Err(UnsupportedFormat(other.unwrap_or("unknown").to_string()).into()),
+    }
+}
+
+/// Applies the metadata policy and then embeds the copyright template into JPEGs. Other
+/// formats are delivered without credit.
+pub async fn prepare_delivery(
+    source: &Path,
+    media_id: &str,
+    policy: MetadataPolicy,
+    template: Option<&CopyrightTemplate>,
+) -> anyhow::Result<Delivery> {
+    let delivery = get_or_create_stripped(source, media_id, policy).await?;
+    let Some(template) = template.filter(|_| delivery.mime_type == "image/jpeg") else {
+        return Ok(delivery);
+    };
+
+    let file_name = delivery
+        .path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| delivery.filename.clone());
+    let target = credit::credited_path(media_id, &file_name, policy, template);
+    credit::get_or_create_credited(&delivery.path, &target, template).await?;
+
+    Ok(Delivery {
+        path: target,
+        ..delivery
+    })
+}
+
+/// Filters a raw EXIF (TIFF structure) block. `None` when nothing is left to keep.
+pub(crate) fn filter_exif(tiff: &[u8], policy: MetadataPolicy) -> anyhow::Result<Option<Vec<u8>>> {
+    use exif::*;
+
+    match policy {
+        MetadataPolicy::Keep => Ok(Some(tiff.to_vec())),
+        MetadataPolicy::StripGps => {
+            let mut tiff = tiff.to_vec();
+            wipe_gps(&mut tiff)?;
+            Ok(Some(tiff))
+        }
+        MetadataPolicy::CopyrightAndDate => Exif::parse(tiff)?.retain(
+            &[TAG_ORIENTATION, TAG_COPYRIGHT],
+            &[
+                TAG_DATE_TIME_ORIGINAL,
+                TAG_OFFSET_TIME_ORIGINAL,
+                TAG_SUB_SEC_TIME_ORIGINAL,
+            ],
+        ),
+        MetadataPolicy::StripAll => Exif::parse(tiff)?.retain(&[TAG_ORIENTATION], &[]),
+    }
+}
+
+async fn sniff(source: &Path) -> anyhow::Result<Option<&'static str>> {
+    let mut head = vec![0u8; SNIFF_LEN];
+    let mut file = tokio::fs::File::open(source).await?;
+    let n = file.read(&mut head).await?;
+    Ok(detect_mime_type(&head[..n]))
+}
+
+async fn rewrite(
+    source: &Path,
+    target: &Path,
+    mime: &str,
+    policy: MetadataPolicy,
+) -> anyhow::Result<()> {
+    let bytes = tokio::fs::read(source).await?;
+    let mime = mime.to_string();
+
+    // Segment parsing of large files is CPU-bound — keep it off the async workers.
+    let stripped = tokio::task::spawn_blocking(move || match mime.as_str() {
+        "image/jpeg" => jpeg::strip(&bytes, policy),
+        "image/png" => png::strip(&bytes, policy),
+        _ => webp::strip(&bytes, policy),
+    })
+    .await??;
+
+    write_atomically(target, &stripped).await
+}
+
+/// Copies all streams into a new container without global, stream and chapter metadata.
+async fn remux(source: &Path, target: &Path) -> anyhow::Result<()> {
+    let parent = target
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("Invalid target"))?;
+    tokio::fs::create_dir_all(parent).await?;
+    // ffmpeg picks the container from the extension, so keep it on the temporary file.
+    let extension = target
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    let tmp = tempfile::Builder::new()
+        .suffix(&extension)
+        .tempfile_in(parent)?
+        .into_temp_path();
+
+    let status = Command::new("ffmpeg")
+        .args(["-v", "error", "-y", "-i"])
+        .arg(source)
+        .args([
+            "-map",
+            "0",
+            "-map_metadata",
+            "-1",
+            "-map_chapters",
+            "-1",
+            "-c",
+            "copy",
+        ])
+        .arg(&tmp)
+        .stdin(Stdio::null())
+        .status()
+        .await;
+
+    match status {
+        Ok(s) if s.success() => {
+            tmp.persist(target)?;
+            Ok(())
+        }
+        Ok(_) => Err(anyhow::anyhow!(
+            "ffmpeg could not remux {}",
+            source.display()
+        )),
+        // Without ffmpeg the metadata cannot be removed, so the video is withheld.
+        Err(_) => Err(UnsupportedFormat("video".to_string()).into()),
+    }
+}
+
+async fn write_atomically(target: &Path, bytes: &[u8]) -> anyhow::Result<()> {
+    if let Some(parent) = target.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    // Concurrent deliveries of the same file each write a temporary file of their own.
+    let dir = target.parent().unwrap_or_else(|| Path::new("."));
+    let tmp = tempfile::NamedTempFile::new_in(dir)?.into_temp_path();
+    tokio::fs::write(&tmp, bytes).await?;
+    tmp.persist(target)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata_policy_round_trip() {
+        for policy in [
+            MetadataPolicy::Keep,
+            MetadataPolicy::StripGps,
+            MetadataPolicy::CopyrightAndDate,
+            MetadataPolicy::StripAll,
+        ] {
+            let json = serde_json::to_string(&policy).unwrap();
+
+            assert_eq!(json, format!("\"{}\"", policy.as_str()));
+            assert_eq!(policy, policy.as_str().parse().unwrap());
+        }
+        assert!(MetadataPolicy::StripAll > MetadataPolicy::StripGps);
+    }
+}
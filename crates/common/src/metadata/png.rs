@@ -0,0 +1,136 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Metadata stripping for PNG files.
+
+use anyhow::{bail, Result};
+
+use super::{filter_exif, MetadataPolicy};
+
+const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const XMP_KEYWORD: &[u8] = b"XML:com.adobe.xmp";
+
+/// Rewrites the `eXIf` chunk and drops text chunks, which may carry XMP.
+pub(crate) fn strip(bytes: &[u8], policy: MetadataPolicy) -> Result<Vec<u8>> {
+    if !bytes.starts_with(SIGNATURE) {
+        bail!("Not a PNG file");
+    }
+
+    let mut out = Vec::with_capacity(bytes.len());
+    out.extend_from_slice(SIGNATURE);
+    let mut pos = SIGNATURE.len();
+
+    while pos + 8 <= bytes.len() {
+        let len = u32::from_be_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]])
+            as usize;
+        let kind = &bytes[pos + 4..pos + 8];
+        let end = pos + 12 + len;
+        if end > bytes.len() {
+            bail!("Truncated PNG chunk at {}", pos);
+        }
+        let data = &bytes[pos + 8..pos + 8 + len];
+
+        match (kind, policy) {
+            (_, MetadataPolicy::Keep) => out.extend_from_slice(&bytes[pos..end]),
+            (b"eXIf", _) => {
+                if let Ok(Some(exif)) = filter_exif(data, policy) {
+                    write_chunk(&mut out, b"eXIf", &exif);
+                }
+            }
+            (b"tEXt" | b"zTXt" | b"iTXt", MetadataPolicy::StripGps) => {
+                if !data.starts_with(XMP_KEYWORD) {
+                    out.extend_from_slice(&bytes[pos..end]);
+                }
+            }
+            (b"tEXt" | b"zTXt" | b"iTXt" | b"tIME", _) => {}
+            _ => out.extend_from_slice(&bytes[pos..end]),
+        }
+
+        pos = end;
+        if kind == b"IEND" {
+            break;
+        }
+    }
+
+    Ok(out)
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(kind);
+    crc.update(data);
+    out.extend_from_slice(&crc.finalize().to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::exif::tests::sample_exif;
+
+    fn sample_png() -> Vec<u8> {
+        let mut png = SIGNATURE.to_vec();
+        write_chunk(&mut png, b"IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 2, 0, 0, 0]);
+        write_chunk(&mut png, b"eXIf", &sample_exif());
+        write_chunk(
+            &mut png,
+            b"iTXt",
+            &[XMP_KEYWORD, b"\0\0\0\0\0<x:xmpmeta/>"].concat(),
+        );
+        write_chunk(&mut png, b"tEXt", b"Author\0Jane");
+        write_chunk(&mut png, b"IDAT", &[1, 2, 3]);
+        write_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|w| w == needle)
+    }
+
+    #[test]
+    fn test_strip_gps() {
+        // given
+        let png = sample_png();
+
+        // when
+        let stripped = strip(&png, MetadataPolicy::StripGps).unwrap();
+
+        // then
+        assert!(!contains(&stripped, &[7; 24]));
+        assert!(!contains(&stripped, b"<x:xmpmeta/>"));
+        assert!(contains(&stripped, b"Author\0Jane"));
+        assert!(contains(&stripped, b"SERIAL123"));
+        assert!(stripped.ends_with(b"IEND\xae\x42\x60\x82"));
+    }
+
+    #[test]
+    fn test_copyright_and_date() {
+        // given
+        let png = sample_png();
+
+        // when
+        let stripped = strip(&png, MetadataPolicy::CopyrightAndDate).unwrap();
+
+        // then
+        assert!(contains(&stripped, b"Studio"));
+        assert!(contains(&stripped, b"2024:05:01 10:00:00"));
+        assert!(!contains(&stripped, b"SERIAL123"));
+        assert!(!contains(&stripped, b"Author\0Jane"));
+    }
+}
@@ -0,0 +1,130 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Metadata stripping for WebP files.
+
+use anyhow::{bail, Result};
+
+use super::{filter_exif, MetadataPolicy};
+
+const FLAG_EXIF: u8 = 0x08;
+const FLAG_XMP: u8 = 0x04;
+
+/// Rewrites the `EXIF` chunk, drops the `XMP ` chunk and keeps the `VP8X` flags in sync.
+pub(crate) fn strip(bytes: &[u8], policy: MetadataPolicy) -> Result<Vec<u8>> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WEBP" {
+        bail!("Not a WebP file");
+    }
+    if policy == MetadataPolicy::Keep {
+        return Ok(bytes.to_vec());
+    }
+
+    let mut chunks: Vec<([u8; 4], Vec<u8>)> = vec![];
+    let mut pos = 12;
+    while pos + 8 <= bytes.len() {
+        let kind: [u8; 4] = bytes[pos..pos + 4].try_into()?;
+        let len = u32::from_le_bytes(bytes[pos + 4..pos + 8].try_into()?) as usize;
+        let end = pos + 8 + len;
+        if end > bytes.len() {
+            bail!("Truncated WebP chunk at {}", pos);
+        }
+        let data = &bytes[pos + 8..end];
+
+        match &kind {
+            b"EXIF" => {
+                if let Ok(Some(exif)) = filter_exif(data, policy) {
+                    chunks.push((kind, exif));
+                }
+            }
+            b"XMP " => {}
+            _ => chunks.push((kind, data.to_vec())),
+        }
+        pos = end + len % 2;
+    }
+
+    let has_exif = chunks.iter().any(|(kind, _)| kind == b"EXIF");
+    if let Some((_, vp8x)) = chunks.iter_mut().find(|(kind, _)| kind == b"VP8X") {
+        if let Some(flags) = vp8x.first_mut() {
+            *flags &= !FLAG_XMP;
+            if !has_exif {
+                *flags &= !FLAG_EXIF;
+            }
+        }
+    }
+
+    let mut body = b"WEBP".to_vec();
+    for (kind, data) in chunks {
+        body.extend_from_slice(&kind);
+        body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        body.extend_from_slice(&data);
+        if data.len() % 2 == 1 {
+            body.push(0);
+        }
+    }
+
+    let mut out = b"RIFF".to_vec();
+    out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    out.extend_from_slice(&body);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::exif::tests::sample_exif;
+
+    fn chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = kind.to_vec();
+        chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    #[test]
+    fn test_strip_all() {
+        // given
+        let mut body = b"WEBP".to_vec();
+        body.extend(chunk(
+            b"VP8X",
+            &[FLAG_EXIF | FLAG_XMP, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+        ));
+        body.extend(chunk(b"VP8 ", &[1, 2, 3]));
+        body.extend(chunk(b"EXIF", &sample_exif()));
+        body.extend(chunk(b"XMP ", b"<x:xmpmeta/>"));
+        let mut webp = b"RIFF".to_vec();
+        webp.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        webp.extend(body);
+
+        // when
+        let stripped = strip(&webp, MetadataPolicy::StripAll).unwrap();
+
+        // then
+        let mut expected = b"WEBP".to_vec();
+        expected.extend(chunk(b"VP8X", &[FLAG_EXIF, 0, 0, 0, 0, 0, 0, 0, 0, 0]));
+        expected.extend(chunk(b"VP8 ", &[1, 2, 3]));
+        assert!(stripped.starts_with(b"RIFF"));
+        assert!(stripped[8..].starts_with(&expected));
+        assert_eq!(
+            u32::from_le_bytes(stripped[4..8].try_into().unwrap()) as usize,
+            stripped.len() - 8
+        );
+        assert!(!stripped.windows(12).any(|w| w == b"<x:xmpmeta/>"));
+    }
+}
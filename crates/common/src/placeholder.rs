@@ -0,0 +1,125 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Placeholders for gallery grids: pixel size, dominant color and a BlurHash.
+//!
+//! Computed once at ingest from the decoded image (or a video's poster frame), so listings
+//! can include them without touching any file.
+
+use std::collections::BTreeMap;
+
+use anyhow::anyhow;
+use image::{DynamicImage, GenericImageView};
+
+use crate::database::placeholder::Placeholder;
+
+/// Longest edge of the thumbnail the BlurHash and dominant color are computed from.
+const SAMPLE_EDGE: u32 = 32;
+
+pub fn compute(media_id: &str, img: &DynamicImage) -> anyhow::Result<Placeholder> {
+    let (width, height) = img.dimensions();
+    let sample = img.thumbnail(SAMPLE_EDGE, SAMPLE_EDGE).to_rgba8();
+
+    // Four components along the longer edge keep the hash short but recognizable.
+    let (components_x, components_y) = if width >= height { (4, 3) } else { (3, 4) };
+    let blurhash = blurhash::encode(
+        components_x,
+        components_y,
+        sample.width(),
+        sample.height(),
+        sample.as_raw(),
+    )
+    .map_err(|e| anyhow!("BlurHash encoding failed: {:?}", e))?;
+
+    Ok(Placeholder {
+        media: media_id.to_string(),
+        width: width.try_into()?,
+        height: height.try_into()?,
+        dominant_color: dominant_color(&sample),
+        blurhash,
+    })
+}
+
+/// Mean color of the most common bucket when every channel is reduced to 4 bits.
+fn dominant_color(sample: &image::RgbaImage) -> String {
+    let mut buckets: BTreeMap<(u8, u8, u8), (u32, [u32; 3])> = BTreeMap::new();
+    for pixel in sample.pixels() {
+        let [r, g, b, a] = pixel.0;
+        if a < 128 {
+            continue;
+        }
+        let (count, sum) = buckets.entry((r >> 4, g >> 4, b >> 4)).or_default();
+        *count += 1;
+        sum[0] += r as u32;
+        sum[1] += g as u32;
+        sum[2] += b as u32;
+    }
+
+    // `max_by_key` returns the last maximum; iterating in reverse makes ties pick the darkest bucket.
+    let Some((count, sum)) = buckets.values().rev().max_by_key(|(count, _)| *count) else {
+        return "#000000".to_string();
+    };
+    format!(
+        "#{:02x}{:02x}{:02x}",
+        sum[0] / count,
+        sum[1] / count,
+        sum[2] / count
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    #[test]
+    fn test_compute_placeholder() {
+        // given: a portrait image, mostly blue with a red stripe
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(300, 400, |x, _| {
+            if x < 60 {
+                Rgba([200, 30, 30, 255])
+            } else {
+                Rgba([40, 80, 160, 255])
+            }
+        }));
+
+        // when
+        let placeholder = compute("media", &img).unwrap();
+
+        // then
+        assert_eq!((placeholder.width, placeholder.height), (300, 400));
+        assert_eq!(placeholder.dominant_color, "#2850a0");
+        // 3×4 components: size flag, max AC, DC and 11 AC values
+        assert_eq!(placeholder.blurhash.len(), 1 + 1 + 4 + 11 * 2);
+    }
+
+    #[test]
+    fn test_dominant_color_ignores_transparent_pixels() {
+        // given
+        let sample = RgbaImage::from_fn(4, 4, |x, _| {
+            if x == 0 {
+                Rgba([255, 255, 255, 255])
+            } else {
+                Rgba([0, 0, 0, 0])
+            }
+        });
+
+        // then
+        assert_eq!(dominant_color(&sample), "#ffffff");
+        assert_eq!(dominant_color(&RgbaImage::new(2, 2)), "#000000");
+    }
+}
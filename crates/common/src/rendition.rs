@@ -0,0 +1,237 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Scaled-down JPEG renditions of stored originals.
+//!
+//! Renditions are generated on first request and cached below `data/cache/renditions`
+//! so subsequent requests are served straight from disk.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+pub(crate) const CACHE_BASE: &str = "./data/cache/renditions";
+const JPEG_QUALITY: u8 = 85;
+
+/// Size variant of a media file delivered to clients.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rendition {
+    /// The unmodified uploaded file.
+    Original,
+    /// Longest edge limited to 2560 pixels.
+    #[default]
+    Large,
+    /// Longest edge limited to 1280 pixels.
+    Medium,
+    /// Longest edge limited to 480 pixels.
+    Small,
+    /// Full resolution JPEG conversion, e.g. of a HEIC original.
+    Full,
+}
+
+impl Rendition {
+    /// Maximum length of the longest edge, `None` for the original and full conversions.
+    pub fn max_edge(&self) -> Option<u32> {
+        match self {
+            Rendition::Original | Rendition::Full => None,
+            Rendition::Large => Some(2560),
+            Rendition::Medium => Some(1280),
+            Rendition::Small => Some(480),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Rendition::Original => "original",
+            Rendition::Large => "large",
+            Rendition::Medium => "medium",
+            Rendition::Small => "small",
+            Rendition::Full => "full",
+        }
+    }
+
+    /// Location of the cached rendition for a media item.
+    pub fn cache_path(&self, media_id: &str) -> PathBuf {
+        PathBuf::from(CACHE_BASE)
+            .join(media_id)
+            .join(format!("{}.jpg", self.as_str()))
+    }
+}
+
+impl fmt::Display for Rendition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for Rendition {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "original" => Ok(Rendition::Original),
+            "large" => Ok(Rendition::Large),
+            "medium" => Ok(Rendition::Medium),
+            "small" => Ok(Rendition::Small),
+            "full" => Ok(Rendition::Full),
+            _ => Err(anyhow::anyhow!("Unknown rendition: {}", s)),
+        }
+    }
+}
+
+/// Returns the path of the requested rendition, generating and caching it if necessary.
+/// The original is returned as-is for [`Rendition::Original`].
+pub async fn get_or_create_rendition(
+    source: &Path,
+    media_id: &str,
+    rendition: Rendition,
+) -> anyhow::Result<PathBuf> {
+    if rendition == Rendition::Original {
+        return Ok(source.to_path_buf());
+    }
+    let max_edge = rendition.max_edge();
+
+    let target = rendition.cache_path(media_id);
+    if target.exists() {
+        return Ok(target);
+    }
+    if let Some(parent) = target.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    let source = source.to_path_buf();
+    let target_clone = target.clone();
+
+    // Decoding and resampling is CPU-bound — keep it off the async workers.
+    tokio::task::spawn_blocking(move || render_jpeg(&source, &target_clone, max_edge)).await??;
+
+    Ok(target)
+}
+
+/// Invalidates all cached renditions of a media item.
+pub async fn invalidate(media_id: &str) {
+    let _ = tokio::fs::remove_dir_all(PathBuf::from(CACHE_BASE).join(media_id)).await;
+}
+
+fn render_jpeg(source: &Path, target: &Path, max_edge: Option<u32>) -> anyhow::Result<()> {
+    let (mut img, icc_profile) = decode_oriented(source)?;
+
+    if let Some(max_edge) = max_edge {
+        if img.width() > max_edge || img.height() > max_edge {
+            img = img.resize(max_edge, max_edge, image::imageops::FilterType::Lanczos3);
+        }
+    }
+
+    write_jpeg(&img, icc_profile, target)
+}
+
+/// Decodes an image upright, together with its embedded color profile.
+pub(crate) fn decode_oriented(
+    source: &Path,
+) -> anyhow::Result<(image::DynamicImage, Option<Vec<u8>>)> {
+    use image::{DynamicImage, ImageDecoder, ImageReader};
+
+    let mut decoder = ImageReader::open(source)?
+        .with_guessed_format()?
+        .into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let icc_profile = decoder.icc_profile()?;
+    let mut img = DynamicImage::from_decoder(decoder)?;
+    img.apply_orientation(orientation);
+
+    Ok((img, icc_profile))
+}
+
+/// Encodes a JPEG and atomically moves it to `target`. Every call writes its own temporary
+/// file, so concurrent first requests for the same rendition cannot mix their output.
+pub(crate) fn write_jpeg(
+    img: &image::DynamicImage,
+    icc_profile: Option<Vec<u8>>,
+    target: &Path,
+) -> anyhow::Result<()> {
+    use image::codecs::jpeg::JpegEncoder;
+    use image::ImageEncoder;
+    use std::io::Write;
+
+    let dir = target.parent().unwrap_or_else(|| Path::new("."));
+    let mut tmp = tempfile::NamedTempFile::new_in(dir)?;
+    let mut writer = std::io::BufWriter::new(tmp.as_file_mut());
+    let mut encoder = JpegEncoder::new_with_quality(&mut writer, JPEG_QUALITY);
+    // Wide gamut sources (Display P3 on iPhones) look washed out without their profile.
+    if let Some(icc_profile) = icc_profile {
+        let _ = encoder.set_icc_profile(icc_profile);
+    }
+    img.to_rgb8().write_with_encoder(encoder)?;
+    writer.flush()?;
+    drop(writer);
+    tmp.persist(target)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rendition_round_trip() {
+        for rendition in [
+            Rendition::Original,
+            Rendition::Large,
+            Rendition::Medium,
+            Rendition::Small,
+            Rendition::Full,
+        ] {
+            assert_eq!(rendition, rendition.as_str().parse().unwrap());
+        }
+        assert!("huge".parse::<Rendition>().is_err());
+    }
+
+    #[test]
+    fn test_rendition_deserialization() {
+        let rendition: Rendition = serde_json::from_str(r#""medium""#).unwrap();
+
+        assert_eq!(rendition, Rendition::Medium);
+        assert_eq!(Rendition::default(), Rendition::Large);
+    }
+
+    #[test]
+    fn test_write_jpeg_concurrently() {
+        // given
+        let dir = testdir::testdir!();
+        let target = dir.join("large.jpg");
+
+        // when
+        std::thread::scope(|scope| {
+            for size in 1..=8u32 {
+                let target = &target;
+                scope.spawn(move || {
+                    let img = image::DynamicImage::new_rgb8(size * 64, size * 48);
+                    write_jpeg(&img, None, target).unwrap();
+                });
+            }
+        });
+
+        // then
+        let written = image::open(&target).unwrap();
+        assert_eq!(written.width() * 3, written.height() * 4);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+    }
+}
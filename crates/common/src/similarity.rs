@@ -0,0 +1,241 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Perceptual hashes for near-duplicate detection and burst grouping.
+//!
+//! Every original image gets a 64 bit difference hash (dHash): the image is shrunk to 9×8
+//! grayscale pixels and each bit records whether a pixel is brighter than its right
+//! neighbour. Visually similar images differ in few bits, so the Hamming distance between
+//! two hashes measures how alike they look.
+
+use chrono::{DateTime, Duration, Utc};
+use image::imageops::FilterType;
+use image::DynamicImage;
+use serde::Serialize;
+
+use crate::database::image_hash::ImageHash;
+
+/// Distance up to which two images count as near duplicates.
+pub const DEFAULT_MAX_DISTANCE: u32 = 10;
+/// Longest pause between two frames of one burst.
+pub const DEFAULT_BURST_GAP_SECS: i64 = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SimilarImage {
+    pub media_id: String,
+    pub distance: u32,
+}
+
+/// Frames taken in quick succession that look alike, in capture order.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Burst {
+    pub media_ids: Vec<String>,
+    pub first_taken_at: DateTime<Utc>,
+    pub last_taken_at: DateTime<Utc>,
+}
+
+pub fn dhash(img: &DynamicImage) -> u64 {
+    let small = img.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if small.get_pixel(x, y)[0] < small.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+    hash
+}
+
+pub fn hamming(a: i64, b: i64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Images within `max_distance` of the media item, closest first.
+pub fn find_similar(
+    media_id: &str,
+    hashes: &[ImageHash],
+    max_distance: u32,
+) -> Option<Vec<SimilarImage>> {
+    let target = hashes.iter().find(|h| h.media_id == media_id)?;
+
+    let mut similar: Vec<SimilarImage> = hashes
+        .iter()
+        .filter(|h| h.media_id != media_id)
+        .map(|h| SimilarImage {
+            media_id: h.media_id.clone(),
+            distance: hamming(target.hash, h.hash),
+        })
+        .filter(|s| s.distance <= max_distance)
+        .collect();
+    // A media item may hold several originals, list it once with its closest one.
+    similar.sort_by(|a, b| {
+        a.media_id
+            .cmp(&b.media_id)
+            .then_with(|| a.distance.cmp(&b.distance))
+    });
+    similar.dedup_by(|a, b| a.media_id == b.media_id);
+    similar.sort_by(|a, b| {
+        a.distance
+            .cmp(&b.distance)
+            .then_with(|| a.media_id.cmp(&b.media_id))
+    });
+    Some(similar)
+}
+
+/// Groups images into bursts. A frame joins the current burst when it was taken at most
+/// `max_gap` after the previous frame and looks like it, so slow pans still stay together.
+/// Images without a capture time and single frames are left out.
+pub fn group_bursts(
+    mut hashes: Vec<ImageHash>,
+    max_gap: Duration,
+    max_distance: u32,
+) -> Vec<Burst> {
+    hashes.retain(|h| h.taken_at.is_some());
+    hashes.sort_by(|a, b| {
+        a.taken_at
+            .cmp(&b.taken_at)
+            .then_with(|| a.media_id.cmp(&b.media_id))
+    });
+
+    let mut bursts = Vec::new();
+    let mut current: Vec<ImageHash> = Vec::new();
+    for frame in hashes {
+        let joins = current.last().is_some_and(|previous| {
+            frame
+                .taken_at
+                .zip(previous.taken_at)
+                .is_some_and(|(t, p)| t - p <= max_gap)
+                && hamming(previous.hash, frame.hash) <= max_distance
+        });
+        if !joins {
+            bursts.extend(to_burst(std::mem::take(&mut current)));
+        }
+        current.push(frame);
+    }
+    bursts.extend(to_burst(current));
+    bursts
+}
+
+fn to_burst(frames: Vec<ImageHash>) -> Option<Burst> {
+    if frames.len() < 2 {
+        return None;
+    }
+    Some(Burst {
+        first_taken_at: frames.first()?.taken_at?,
+        last_taken_at: frames.last()?.taken_at?,
+        media_ids: frames.into_iter().map(|f| f.media_id).collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, Luma};
+
+    fn frame(media_id: &str, hash: i64, seconds: i64) -> ImageHash {
+        ImageHash {
+            reference_id: format!("ref-{}", media_id),
+            media_id: media_id.to_string(),
+            hash,
+            taken_at: Some(DateTime::from_timestamp(1_700_000_000 + seconds, 0).unwrap()),
+        }
+    }
+
+    fn gradient(width: u32, height: u32, offset: u8) -> DynamicImage {
+        DynamicImage::ImageLuma8(GrayImage::from_fn(width, height, |x, y| {
+            Luma([
+                ((x * 255 / width) as u8 / 2 + (y * 255 / height) as u8 / 3).saturating_add(offset)
+            ])
+        }))
+    }
+
+    #[test]
+    fn test_dhash_tolerates_scaling_and_brightness() {
+        // given
+        let original = gradient(640, 480, 0);
+        let resized = gradient(320, 240, 20);
+        let flipped =
+            DynamicImage::ImageLuma8(image::imageops::flip_horizontal(&original.to_luma8()));
+
+        // when
+        let (a, b, c) = (
+            dhash(&original) as i64,
+            dhash(&resized) as i64,
+            dhash(&flipped) as i64,
+        );
+
+        // then
+        assert!(hamming(a, b) <= 4);
+        assert!(hamming(a, c) > DEFAULT_MAX_DISTANCE);
+    }
+
+    #[test]
+    fn test_find_similar() {
+        // given
+        let hashes = vec![
+            frame("a", 0b1111, 0),
+            frame("b", 0b1110, 60),
+            frame("c", !0b1111, 120),
+        ];
+
+        // when
+        let similar = find_similar("a", &hashes, DEFAULT_MAX_DISTANCE).unwrap();
+
+        // then
+        assert_eq!(
+            similar,
+            vec![SimilarImage {
+                media_id: "b".into(),
+                distance: 1
+            }]
+        );
+        assert!(find_similar("unknown", &hashes, DEFAULT_MAX_DISTANCE).is_none());
+    }
+
+    #[test]
+    fn test_group_bursts_by_time_and_similarity() {
+        // given
+        let hashes = vec![
+            frame("b", 0b0001, 1),
+            frame("a", 0b0000, 0),
+            frame("c", 0b0011, 2),
+            // same scene, but a minute later
+            frame("d", 0b0011, 62),
+            // right after, but a different motif
+            frame("e", !0b0011, 63),
+            frame("f", !0b0011, 64),
+        ];
+
+        // when
+        let bursts = group_bursts(
+            hashes,
+            Duration::seconds(DEFAULT_BURST_GAP_SECS),
+            DEFAULT_MAX_DISTANCE,
+        );
+
+        // then
+        assert_eq!(bursts.len(), 2);
+        assert_eq!(bursts[0].media_ids, vec!["a", "b", "c"]);
+        assert_eq!(
+            bursts[0].last_taken_at - bursts[0].first_taken_at,
+            Duration::seconds(2)
+        );
+        assert_eq!(bursts[1].media_ids, vec!["e", "f"]);
+    }
+}
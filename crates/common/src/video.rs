@@ -0,0 +1,277 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Video metadata and poster frames.
+//!
+//! Extraction shells out to a locally installed `ffprobe` / `ffmpeg`. When the tools are not
+//! available videos are still stored and streamed, just without metadata and thumbnails.
+
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use serde::Deserialize;
+use tokio::process::Command;
+use tracing::{debug, info, warn};
+
+use crate::database::video_details::VideoDetails;
+use crate::database::ArcDynDatabase;
+use crate::image_index::index_poster;
+use crate::rendition::CACHE_BASE;
+
+pub fn is_video(mime_type: &str) -> bool {
+    mime_type.starts_with("video/")
+}
+
+/// Location of the cached poster frame of a video.
+pub fn poster_path(media_id: &str) -> PathBuf {
+    PathBuf::from(CACHE_BASE).join(media_id).join("poster.jpg")
+}
+
+/// Probes a freshly uploaded video, extracts its poster frame and stores both.
+pub async fn index_video(
+    db: ArcDynDatabase,
+    media_id: String,
+    source: PathBuf,
+) -> anyhow::Result<()> {
+    let mut details = probe(&source, &media_id)
+        .await
+        .unwrap_or_else(|| VideoDetails {
+            media: media_id.clone(),
+            ..Default::default()
+        });
+    let poster = extract_poster(&source, &media_id, details.duration_ms).await;
+    if let Some(poster) = &poster {
+        index_poster(&db, &media_id, poster).await;
+    }
+    details.poster_path = poster.map(|p| p.to_string_lossy().to_string());
+
+    db.upsert_video_details(&details).await?;
+    info!("Indexed video {}", media_id);
+    Ok(())
+}
+
+/// Reads duration, resolution, codecs and frame rate via `ffprobe`.
+pub async fn probe(source: &Path, media_id: &str) -> Option<VideoDetails> {
+    let output = Command::new("ffprobe")
+        .args([
+            "-v",
+            "error",
+            "-print_format",
+            "json",
+            "-show_streams",
+            "-show_format",
+        ])
+        .arg(source)
+        .stdin(Stdio::null())
+        .output()
+        .await;
+
+    match output {
+        Ok(out) if out.status.success() => {
+            parse_ffprobe_output(&String::from_utf8_lossy(&out.stdout), media_id)
+        }
+        Ok(out) => {
+            warn!(
+                "ffprobe failed for {}: {}",
+                source.display(),
+                String::from_utf8_lossy(&out.stderr).trim()
+            );
+            None
+        }
+        Err(e) => {
+            debug!("ffprobe not available: {}", e);
+            None
+        }
+    }
+}
+
+/// Returns the poster frame of a video, extracting it with `ffmpeg` when not cached yet.
+pub async fn extract_poster(
+    source: &Path,
+    media_id: &str,
+    duration_ms: Option<i64>,
+) -> Option<PathBuf> {
+    let target = poster_path(media_id);
+    if target.exists() {
+        return Some(target);
+    }
+    tokio::fs::create_dir_all(target.parent()?).await.ok()?;
+
+    // One second in skips fade-ins, but very short clips only have their first frames.
+    let seek = duration_ms
+        .map(|ms| (ms as f64 / 2000.0).min(1.0))
+        .unwrap_or(0.0);
+    // Concurrent requests for the same poster each extract into a file of their own.
+    let tmp = tempfile::Builder::new()
+        .suffix(".jpg")
+        .tempfile_in(target.parent()?)
+        .ok()?
+        .into_temp_path();
+
+    let status = Command::new("ffmpeg")
+        .args(["-v", "error", "-y", "-ss", &format!("{:.3}", seek), "-i"])
+        .arg(source)
+        .args(["-frames:v", "1", "-q:v", "3", "-f", "image2"])
+        .arg(&tmp)
+        .stdin(Stdio::null())
+        .status()
+        .await;
+
+    match status {
+        Ok(s) if s.success() => {
+            tmp.persist(&target).ok()?;
+            Some(target)
+        }
+        Ok(_) => {
+            warn!(
+                "ffmpeg could not extract a poster frame from {}",
+                source.display()
+            );
+            None
+        }
+        Err(e) => {
+            debug!("ffmpeg not available: {}", e);
+            None
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct FfprobeOutput {
+    #[serde(default)]
+    streams: Vec<FfprobeStream>,
+    format: Option<FfprobeFormat>,
+}
+
+#[derive(Deserialize)]
+struct FfprobeStream {
+    codec_type: Option<String>,
+    codec_name: Option<String>,
+    width: Option<i32>,
+    height: Option<i32>,
+    avg_frame_rate: Option<String>,
+    r_frame_rate: Option<String>,
+    tags: Option<FfprobeTags>,
+    #[serde(default)]
+    side_data_list: Vec<FfprobeSideData>,
+}
+
+#[derive(Deserialize)]
+struct FfprobeTags {
+    rotate: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct FfprobeSideData {
+    rotation: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct FfprobeFormat {
+    duration: Option<String>,
+}
+
+/// Maps the JSON printed by `ffprobe -print_format json -show_streams -show_format`.
+fn parse_ffprobe_output(json: &str, media_id: &str) -> Option<VideoDetails> {
+    let output: FfprobeOutput = serde_json::from_str(json).ok()?;
+
+    let video = output
+        .streams
+        .iter()
+        .find(|s| s.codec_type.as_deref() == Some("video"));
+    let audio = output
+        .streams
+        .iter()
+        .find(|s| s.codec_type.as_deref() == Some("audio"));
+
+    let (mut width, mut height) = (video.and_then(|v| v.width), video.and_then(|v| v.height));
+    // Phones record portrait videos as landscape plus a rotation hint.
+    let rotation = video.and_then(|v| {
+        v.side_data_list
+            .iter()
+            .find_map(|d| d.rotation)
+            .or_else(|| v.tags.as_ref()?.rotate.as_deref()?.parse().ok())
+    });
+    if rotation.is_some_and(|r| (r.abs() as i64) % 180 == 90) {
+        std::mem::swap(&mut width, &mut height);
+    }
+
+    let frame_rate = video.and_then(|v| {
+        parse_rational(v.avg_frame_rate.as_deref()?)
+            .or_else(|| parse_rational(v.r_frame_rate.as_deref()?))
+    });
+
+    let duration_ms = output
+        .format
+        .and_then(|f| f.duration)
+        .and_then(|d| d.parse::<f64>().ok())
+        .map(|secs| (secs * 1000.0).round() as i64);
+
+    Some(VideoDetails {
+        media: media_id.to_string(),
+        duration_ms,
+        width,
+        height,
+        video_codec: video.and_then(|v| v.codec_name.clone()),
+        audio_codec: audio.and_then(|a| a.codec_name.clone()),
+        frame_rate,
+        poster_path: None,
+    })
+}
+
+/// Parses ffprobe rationals such as `30000/1001`; `0/0` means unknown.
+fn parse_rational(value: &str) -> Option<f64> {
+    let (num, den) = value.split_once('/')?;
+    let (num, den): (f64, f64) = (num.parse().ok()?, den.parse().ok()?);
+    (num > 0.0 && den > 0.0).then(|| num / den)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ffprobe_output() {
+        // given
+        let json = r#"{
+            "streams": [
+                { "codec_type": "video", "codec_name": "h264", "width": 1920, "height": 1080,
+                  "avg_frame_rate": "30000/1001", "r_frame_rate": "30/1",
+                  "side_data_list": [ { "rotation": -90 } ] },
+                { "codec_type": "audio", "codec_name": "aac" }
+            ],
+            "format": { "duration": "12.345000" }
+        }"#;
+
+        // when
+        let details = parse_ffprobe_output(json, "m1").unwrap();
+
+        // then
+        assert_eq!(details.duration_ms, Some(12345));
+        assert_eq!((details.width, details.height), (Some(1080), Some(1920)));
+        assert_eq!(details.video_codec.as_deref(), Some("h264"));
+        assert_eq!(details.audio_codec.as_deref(), Some("aac"));
+        assert!((details.frame_rate.unwrap() - 29.97).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_parse_rational() {
+        assert_eq!(parse_rational("25/1"), Some(25.0));
+        assert_eq!(parse_rational("0/0"), None);
+        assert_eq!(parse_rational("abc"), None);
+    }
+}
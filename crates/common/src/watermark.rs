@@ -0,0 +1,340 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Watermarked renditions for proof galleries.
+//!
+//! Watermarks are stamped onto already scaled renditions and cached next to them. The cache
+//! file name carries a hash of the album's watermark settings, so changing them never serves
+//! stale proofs. Text is set with the TrueType font from `WATERMARK_FONT`, or one of the
+//! fonts commonly installed on Linux.
+
+use std::path::{Path, PathBuf};
+
+use ab_glyph::{point, Font, FontVec, PxScale, ScaleFont};
+use image::{imageops, DynamicImage, Pixel, Rgba, RgbaImage};
+use sha2::{Digest, Sha256};
+
+use crate::database::watermark::{AlbumWatermark, WatermarkKind, WatermarkPosition};
+use crate::rendition::{decode_oriented, write_jpeg, Rendition, CACHE_BASE};
+
+const FONT_CANDIDATES: &[&str] = &[
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans-Bold.ttf",
+    "/usr/share/fonts/TTF/DejaVuSans-Bold.ttf",
+    "/usr/share/fonts/dejavu/DejaVuSans-Bold.ttf",
+    "/usr/share/fonts/truetype/liberation/LiberationSans-Bold.ttf",
+];
+
+/// Base directory of uploaded watermark images.
+pub const IMAGE_BASE: &str = "./data/watermarks";
+
+/// Location of the uploaded watermark image of an album.
+pub fn image_path(album_id: &str) -> PathBuf {
+    PathBuf::from(IMAGE_BASE).join(format!("{}.png", album_id))
+}
+
+/// Location of the cached watermarked rendition for the given settings.
+pub fn watermarked_path(
+    media_id: &str,
+    rendition: Rendition,
+    watermark: &AlbumWatermark,
+) -> PathBuf {
+    PathBuf::from(CACHE_BASE).join(media_id).join(format!(
+        "{}.wm-{}.jpg",
+        rendition,
+        settings_hash(watermark)
+    ))
+}
+
+/// Returns the watermarked copy of a rendition, generating and caching it if necessary.
+pub async fn get_or_create_watermarked(
+    source: &Path,
+    media_id: &str,
+    rendition: Rendition,
+    watermark: &AlbumWatermark,
+) -> anyhow::Result<PathBuf> {
+    let target = watermarked_path(media_id, rendition, watermark);
+    if target.exists() {
+        return Ok(target);
+    }
+    if let Some(parent) = target.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    let source = source.to_path_buf();
+    let target_clone = target.clone();
+    let watermark = watermark.clone();
+
+    // Compositing is CPU-bound — keep it off the async workers.
+    tokio::task::spawn_blocking(move || render(&source, &target_clone, &watermark)).await??;
+
+    Ok(target)
+}
+
+fn settings_hash(watermark: &AlbumWatermark) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(watermark.kind.as_str());
+    hasher.update(watermark.text.as_deref().unwrap_or_default());
+    hasher.update(watermark.image_path.as_deref().unwrap_or_default());
+    hasher.update(watermark.position.as_str());
+    hasher.update(watermark.opacity.to_le_bytes());
+    hasher.update(watermark.scale.to_le_bytes());
+    hasher.update(watermark.updated_at.timestamp_millis().to_le_bytes());
+    hex::encode(&hasher.finalize()[..6])
+}
+
+fn render(source: &Path, target: &Path, watermark: &AlbumWatermark) -> anyhow::Result<()> {
+    let (img, icc_profile) = decode_oriented(source)?;
+    let mut canvas = img.to_rgba8();
+
+    let target_width = ((canvas.width() as f64) * watermark.scale.clamp(0.01, 1.0)).max(1.0) as u32;
+    let mut stamp = match watermark.kind {
+        WatermarkKind::Text => {
+            render_text(watermark.text.as_deref().unwrap_or_default(), target_width)?
+        }
+        WatermarkKind::Image => {
+            let path = watermark
+                .image_path
+                .as_deref()
+                .ok_or_else(|| anyhow::anyhow!("Watermark image missing"))?;
+            render_image(Path::new(path), target_width)?
+        }
+    };
+    apply_opacity(&mut stamp, watermark.opacity);
+
+    for (x, y) in placements(watermark.position, canvas.dimensions(), stamp.dimensions()) {
+        imageops::overlay(&mut canvas, &stamp, x, y);
+    }
+
+    write_jpeg(&DynamicImage::ImageRgba8(canvas), icc_profile, target)
+}
+
+fn load_font() -> anyhow::Result<FontVec> {
+    let configured = std::env::var("WATERMARK_FONT").ok();
+    let candidates = configured
+        .iter()
+        .map(String::as_str)
+        .chain(FONT_CANDIDATES.iter().copied());
+
+    for path in candidates {
+        if let Ok(bytes) = std::fs::read(path) {
+            return FontVec::try_from_vec(bytes)
+                .map_err(|e| anyhow::anyhow!("Invalid font {}: {}", path, e));
+        }
+    }
+
+    Err(anyhow::anyhow!(
+        "No font for text watermarks found, set WATERMARK_FONT"
+    ))
+}
+
+/// Sets white text with a soft dark shadow, so it stays readable on light and dark photos.
+fn render_text(text: &str, target_width: u32) -> anyhow::Result<RgbaImage> {
+    if text.trim().is_empty() {
+        return Err(anyhow::anyhow!("Watermark text is empty"));
+    }
+    let font = load_font()?;
+
+    // Measure at a reference size, then scale the text to the requested width.
+    let reference_width = layout_width(&font, text, 100.0).max(1.0);
+    let px = (100.0 * target_width as f32 / reference_width).max(4.0);
+    let scaled = font.as_scaled(PxScale::from(px));
+    let shadow = (px / 24.0).ceil().max(1.0);
+
+    let width = (layout_width(&font, text, px) + shadow).ceil() as u32 + 1;
+    let height = (scaled.ascent() - scaled.descent() + shadow).ceil() as u32 + 1;
+    let mut stamp = RgbaImage::new(width, height);
+
+    for (offset, color, strength) in [(shadow, 0u8, 140.0), (0.0, 255u8, 255.0)] {
+        let mut caret = 0.0;
+        let mut previous = None;
+        for c in text.chars() {
+            let id = scaled.glyph_id(c);
+            if let Some(previous) = previous {
+                caret += scaled.kern(previous, id);
+            }
+            previous = Some(id);
+
+            let glyph =
+                id.with_scale_and_position(px, point(caret + offset, scaled.ascent() + offset));
+            caret += scaled.h_advance(id);
+
+            let Some(outlined) = font.outline_glyph(glyph) else {
+                continue;
+            };
+            let bounds = outlined.px_bounds();
+            outlined.draw(|x, y, coverage| {
+                let px = bounds.min.x as i64 + x as i64;
+                let py = bounds.min.y as i64 + y as i64;
+                if px < 0 || py < 0 || px >= width as i64 || py >= height as i64 {
+                    return;
+                }
+                let alpha = (coverage.clamp(0.0, 1.0) * strength) as u8;
+                stamp
+                    .get_pixel_mut(px as u32, py as u32)
+                    .blend(&Rgba([color, color, color, alpha]));
+            });
+        }
+    }
+
+    Ok(stamp)
+}
+
+fn layout_width(font: &FontVec, text: &str, px: f32) -> f32 {
+    let scaled = font.as_scaled(PxScale::from(px));
+    let mut width = 0.0;
+    let mut previous = None;
+    for c in text.chars() {
+        let id = scaled.glyph_id(c);
+        if let Some(previous) = previous {
+            width += scaled.kern(previous, id);
+        }
+        width += scaled.h_advance(id);
+        previous = Some(id);
+    }
+    width
+}
+
+fn render_image(path: &Path, target_width: u32) -> anyhow::Result<RgbaImage> {
+    let logo = image::ImageReader::open(path)?
+        .with_guessed_format()?
+        .decode()?
+        .to_rgba8();
+    let target_height = ((logo.height() as f64) * target_width as f64 / logo.width().max(1) as f64)
+        .round()
+        .max(1.0) as u32;
+
+    Ok(imageops::resize(
+        &logo,
+        target_width,
+        target_height,
+        imageops::FilterType::Lanczos3,
+    ))
+}
+
+fn apply_opacity(stamp: &mut RgbaImage, opacity: f64) {
+    let opacity = opacity.clamp(0.0, 1.0);
+    for pixel in stamp.pixels_mut() {
+        pixel[3] = (pixel[3] as f64 * opacity).round() as u8;
+    }
+}
+
+/// Top left corners of all stamps on the canvas.
+fn placements(
+    position: WatermarkPosition,
+    canvas: (u32, u32),
+    stamp: (u32, u32),
+) -> Vec<(i64, i64)> {
+    let (cw, ch) = (canvas.0 as i64, canvas.1 as i64);
+    let (sw, sh) = (stamp.0 as i64, stamp.1 as i64);
+    let margin = cw.min(ch) * 3 / 100;
+
+    match position {
+        WatermarkPosition::TopLeft => vec![(margin, margin)],
+        WatermarkPosition::TopRight => vec![(cw - sw - margin, margin)],
+        WatermarkPosition::Center => vec![((cw - sw) / 2, (ch - sh) / 2)],
+        WatermarkPosition::BottomLeft => vec![(margin, ch - sh - margin)],
+        WatermarkPosition::BottomRight => vec![(cw - sw - margin, ch - sh - margin)],
+        WatermarkPosition::Tile => {
+            // Staggered rows, so cropping never removes every stamp.
+            let step_x = (sw * 3 / 2).max(1);
+            let step_y = (sh * 3).max(1);
+            let mut tiles = vec![];
+            let mut row = 0;
+            let mut y = margin;
+            while y < ch {
+                let mut x = if row % 2 == 0 {
+                    margin
+                } else {
+                    margin - step_x / 2
+                };
+                while x < cw {
+                    tiles.push((x, y));
+                    x += step_x;
+                }
+                y += step_y;
+                row += 1;
+            }
+            tiles
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn watermark(position: WatermarkPosition) -> AlbumWatermark {
+        AlbumWatermark {
+            album_id: "album".into(),
+            kind: WatermarkKind::Text,
+            text: Some("PROOF".into()),
+            image_path: None,
+            position,
+            opacity: 0.5,
+            scale: 0.25,
+            updated_at: chrono::Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_placements() {
+        // given
+        let canvas = (1000, 500);
+        let stamp = (100, 50);
+
+        // when
+        let bottom_right = placements(WatermarkPosition::BottomRight, canvas, stamp);
+        let center = placements(WatermarkPosition::Center, canvas, stamp);
+        let tiles = placements(WatermarkPosition::Tile, canvas, stamp);
+
+        // then
+        assert_eq!(bottom_right, vec![(885, 435)]);
+        assert_eq!(center, vec![(450, 225)]);
+        assert!(tiles.len() > 10);
+    }
+
+    #[test]
+    fn test_watermarked_path_changes_with_settings() {
+        // given
+        let first = watermark(WatermarkPosition::BottomRight);
+        let mut second = first.clone();
+        second.opacity = 0.8;
+
+        // when
+        let first_path = watermarked_path("media", Rendition::Large, &first);
+        let second_path = watermarked_path("media", Rendition::Large, &second);
+
+        // then
+        assert_ne!(first_path, second_path);
+        assert_eq!(
+            first_path,
+            watermarked_path("media", Rendition::Large, &first)
+        );
+    }
+
+    #[test]
+    fn test_apply_opacity() {
+        // given
+        let mut stamp = RgbaImage::from_pixel(2, 2, Rgba([255, 255, 255, 200]));
+
+        // when
+        apply_opacity(&mut stamp, 0.5);
+
+        // then
+        assert_eq!(stamp.get_pixel(0, 0)[3], 100);
+    }
+}
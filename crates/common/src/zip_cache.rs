@@ -1,25 +1,86 @@
-use std::collections::HashMap;
-use std::io::Write;
-use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
-use std::time::Duration;
-use tokio::task::JoinHandle;
+use std::fs::FileTimes;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
-use crate::database::{media_item::MediaItem, ArcDynDatabase};
+use chrono::{DateTime, Utc};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::database::{media_item::MediaItem, reference::ReferenceRole, ArcDynDatabase};
+use crate::heif;
+use crate::jobs::{self, Job};
+use crate::config::media_config::ZipCacheConfig;
+use crate::database::copyright_template::CopyrightTemplate;
+use crate::metadata::credit::{template_for_album, template_hash};
+use crate::metadata::{prepare_delivery, MetadataPolicy};
+use crate::rendition::{get_or_create_rendition, Rendition};
 
 const CACHE_BASE: &str = "./data/cache/albums";
 const DEBOUNCE_SECS: u64 = 300;
+const EVICTION_INTERVAL_SECS: u64 = 600;
+/// Directory next to the album directories holding ZIPs of selections across albums.
+const SELECTIONS_DIR: &str = "selections";
+/// Temporary files this old belong to builds that crashed.
+const ABANDONED_BUILD_SECS: i64 = 24 * 3600;
 
-pub struct ZipCacheManager {
-    pending: Arc<Mutex<HashMap<String, JoinHandle<()>>>>,
+/// Files packed per media item unless a download asks for other roles.
+pub const DEFAULT_ZIP_ROLES: &[ReferenceRole] = &[ReferenceRole::Original];
+
+/// How images browsers cannot display are packed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ZipFormat {
+    /// Files exactly as uploaded.
+    #[default]
+    Original,
+    /// HEIC and AVIF images converted to full resolution JPEGs.
+    Jpeg,
 }
 
-impl ZipCacheManager {
-    pub fn new() -> Self {
+/// How album ZIPs are delivered on a cache miss.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ZipDelivery {
+    /// Built in the background and cached, the client gets a page that refreshes until done.
+    #[default]
+    Cached,
+    /// Uncompressed ZIP written while reading the files, the download starts immediately.
+    Stream,
+}
+
+/// Selects what goes into a ZIP for each media item.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZipOptions {
+    pub roles: Vec<ReferenceRole>,
+    pub format: ZipFormat,
+    pub metadata_policy: MetadataPolicy,
+    /// Credit embedded into the packed JPEGs.
+    pub copyright: Option<CopyrightTemplate>,
+}
+
+impl Default for ZipOptions {
+    fn default() -> Self {
         Self {
-            pending: Arc::new(Mutex::new(HashMap::new())),
+            roles: DEFAULT_ZIP_ROLES.to_vec(),
+            format: ZipFormat::default(),
+            metadata_policy: MetadataPolicy::default(),
+            copyright: None,
         }
     }
+}
+
+/// Paths of cached album ZIPs, their regeneration through the job queue and eviction.
+#[derive(Default)]
+pub struct ZipCacheManager {
+    limits: ZipCacheConfig,
+}
+
+impl ZipCacheManager {
+    pub fn new(limits: ZipCacheConfig) -> Self {
+        Self { limits }
+    }
 
     pub fn all_zip_path(album_id: &str) -> PathBuf {
         PathBuf::from(CACHE_BASE).join(album_id).join("all.zip")
@@ -31,61 +92,345 @@ impl ZipCacheManager {
             .join(format!("{}.zip", caller_id))
     }
 
-    /// Delete all cached ZIPs for an album and cancel any pending generation task.
-    pub async fn invalidate(&self, album_id: &str) {
-        let handle = self.pending.lock().unwrap().remove(album_id);
-        if let Some(h) = handle {
-            h.abort();
+    /// Cache path of a ZIP of a selection across albums, keyed by the files it packs so
+    /// changed files or options lead to a new ZIP.
+    pub async fn selection_zip_path(entries: &[(String, PathBuf)]) -> PathBuf {
+        let mut hasher = Sha256::new();
+        for (name, path) in entries {
+            let (size, modified) = match tokio::fs::metadata(path).await {
+                Ok(metadata) => (metadata.len(), metadata.modified().ok()),
+                Err(_) => (0, None),
+            };
+            let modified = modified
+                .and_then(|m| m.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .map(|d| d.as_nanos())
+                .unwrap_or_default();
+            hasher.update(format!("{}\n{}\n{}\n{}\n", name, path.display(), size, modified).as_bytes());
+        }
+        PathBuf::from(CACHE_BASE)
+            .join(SELECTIONS_DIR)
+            .join(format!("{}.zip", hex::encode(&hasher.finalize()[..16])))
+    }
+
+    /// Cache path of a ZIP built with non-default options,
+    /// e.g. `all.original-raw.zip`, `all.original.jpeg.zip` or `all.original.strip-gps.zip`.
+    pub fn variant_path(path: PathBuf, options: &ZipOptions) -> PathBuf {
+        if *options == ZipOptions::default() {
+            return path;
+        }
+        let stem = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let suffix: Vec<&str> = options.roles.iter().map(|r| r.as_str()).collect();
+        let mut name = format!("{}.{}", stem, suffix.join("-"));
+        if options.format == ZipFormat::Jpeg {
+            name.push_str(".jpeg");
+        }
+        if options.metadata_policy != MetadataPolicy::Keep {
+            name.push('.');
+            name.push_str(options.metadata_policy.as_str());
         }
+        if let Some(copyright) = &options.copyright {
+            name.push_str(".c-");
+            name.push_str(&template_hash(copyright));
+        }
+        path.with_file_name(format!("{}.zip", name))
+    }
+
+    /// Delete all cached ZIPs for an album.
+    pub async fn invalidate(&self, album_id: &str) {
         let _ = tokio::fs::remove_dir_all(PathBuf::from(CACHE_BASE).join(album_id)).await;
     }
 
+    /// Delete cached ZIPs of one or all albums, returning what was freed.
+    pub async fn clear(&self, album_id: Option<&str>) -> Eviction {
+        let base = PathBuf::from(CACHE_BASE);
+        let zips = match album_id {
+            Some(album_id) => scan_cache(&base).await.into_iter().filter(|z| z.album_id == album_id).collect(),
+            None => scan_cache(&base).await,
+        };
+        let eviction = Eviction::of(&zips);
+        match album_id {
+            Some(album_id) => self.invalidate(album_id).await,
+            None => {
+                let _ = tokio::fs::remove_dir_all(&base).await;
+            }
+        }
+        eviction
+    }
+
+    /// Cached ZIPs per album, largest album first.
+    pub async fn usage(&self) -> CacheUsage {
+        let zips = scan_cache(Path::new(CACHE_BASE)).await;
+        let mut albums: Vec<AlbumCacheUsage> = Vec::new();
+        for zip in zips {
+            match albums.iter_mut().find(|a| a.album_id == zip.album_id) {
+                Some(album) => {
+                    album.total_bytes += zip.size;
+                    album.zips.push(zip);
+                }
+                None => albums.push(AlbumCacheUsage {
+                    album_id: zip.album_id.clone(),
+                    total_bytes: zip.size,
+                    zips: vec![zip],
+                }),
+            }
+        }
+        albums.sort_by_key(|a| std::cmp::Reverse(a.total_bytes));
+        CacheUsage {
+            total_bytes: albums.iter().map(|a| a.total_bytes).sum(),
+            max_bytes: self.limits.max_size_mb * 1024 * 1024,
+            albums,
+        }
+    }
+
+    /// Evicts ZIPs over the configured age, idle customer ZIPs and, while the cache is
+    /// too large, the least recently downloaded ones.
+    pub async fn enforce_limits(&self) -> Eviction {
+        let zips = scan_cache(Path::new(CACHE_BASE)).await;
+        let evicted: Vec<CachedZip> = select_evictions(&zips, &self.limits, SystemTime::now())
+            .into_iter()
+            .map(|i| zips[i].clone())
+            .collect();
+        for zip in &evicted {
+            if let Err(e) = tokio::fs::remove_file(&zip.path).await {
+                tracing::warn!("Could not evict {}: {:?}", zip.path.display(), e);
+            }
+            if let Some(dir) = zip.path.parent() {
+                // Only succeeds once the album has no cached files left.
+                let _ = tokio::fs::remove_dir(dir).await;
+            }
+        }
+        Eviction::of(&evicted)
+    }
+
+    /// Enforces the limits periodically.
+    pub fn spawn_eviction(self: Arc<Self>) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(EVICTION_INTERVAL_SECS));
+            loop {
+                interval.tick().await;
+                let eviction = self.enforce_limits().await;
+                if eviction.removed > 0 {
+                    tracing::info!(
+                        "ZIP cache: evicted {} file(s), {} bytes freed",
+                        eviction.removed, eviction.freed_bytes
+                    );
+                }
+            }
+        })
+    }
+
     /// Schedule eager "all items" ZIP generation after a debounce delay.
-    /// Cancels any previously scheduled task for the same album.
+    /// Further changes to the album within the delay push the build back.
     pub async fn schedule_generation(&self, album_id: String, db: ArcDynDatabase) {
-        let prev = self.pending.lock().unwrap().remove(&album_id);
-        if let Some(h) = prev {
-            h.abort();
-        }
-
-        let pending = Arc::clone(&self.pending);
-        let album_id_clone = album_id.clone();
-
-        let handle = tokio::spawn(async move {
-            tokio::time::sleep(Duration::from_secs(DEBOUNCE_SECS)).await;
-            match generate_and_write_all_zip(&album_id_clone, &db).await {
-                Ok(_) => tracing::info!("Eager ZIP cached for album {}", album_id_clone),
-                Err(e) => tracing::warn!(
-                    "Eager ZIP pre-generation failed for album {}: {:?}",
-                    album_id_clone, e
-                ),
+        let key = format!("zip:{}", album_id);
+        let job = Job::BuildAlbumZip { album_id };
+        if let Err(e) = jobs::enqueue_debounced(&db, job, &key, Duration::from_secs(DEBOUNCE_SECS)).await {
+            tracing::warn!("Could not schedule {}: {:?}", key, e);
+        }
+    }
+}
+
+/// A file in the ZIP cache.
+#[derive(Debug, Clone, Serialize)]
+pub struct CachedZip {
+    pub album_id: String,
+    pub file_name: String,
+    pub size: u64,
+    pub built_at: DateTime<Utc>,
+    pub last_access: DateTime<Utc>,
+    /// Built for a customer's selection rather than the whole album.
+    pub per_customer: bool,
+    /// Still being written.
+    pub in_progress: bool,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AlbumCacheUsage {
+    pub album_id: String,
+    pub total_bytes: u64,
+    pub zips: Vec<CachedZip>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheUsage {
+    pub total_bytes: u64,
+    pub max_bytes: u64,
+    pub albums: Vec<AlbumCacheUsage>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Eviction {
+    pub removed: usize,
+    pub freed_bytes: u64,
+}
+
+impl Eviction {
+    fn of(zips: &[CachedZip]) -> Self {
+        Self {
+            removed: zips.len(),
+            freed_bytes: zips.iter().map(|z| z.size).sum(),
+        }
+    }
+}
+
+/// Records a download of a cached ZIP for LRU eviction. The access time is set explicitly,
+/// file systems mounted with `noatime` would not update it on reads.
+pub fn touch(path: &Path) {
+    let result = std::fs::File::options()
+        .write(true)
+        .open(path)
+        .and_then(|f| f.set_times(FileTimes::new().set_accessed(SystemTime::now())));
+    if let Err(e) = result {
+        tracing::debug!("Could not record access to {}: {:?}", path.display(), e);
+    }
+}
+
+/// Lists all ZIPs below `base`, which holds one directory per album.
+async fn scan_cache(base: &Path) -> Vec<CachedZip> {
+    let base = base.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut zips = Vec::new();
+        let Ok(albums) = std::fs::read_dir(&base) else {
+            return zips;
+        };
+        for album in albums.flatten() {
+            let album_id = album.file_name().to_string_lossy().to_string();
+            let Ok(files) = std::fs::read_dir(album.path()) else {
+                continue;
+            };
+            for file in files.flatten() {
+                let Ok(metadata) = file.metadata() else {
+                    continue;
+                };
+                let file_name = file.file_name().to_string_lossy().to_string();
+                if !metadata.is_file() || !(file_name.ends_with(".zip") || file_name.ends_with(".zip.tmp")) {
+                    continue;
+                }
+                let built_at = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                let last_access = metadata.accessed().unwrap_or(built_at).max(built_at);
+                zips.push(CachedZip {
+                    album_id: album_id.clone(),
+                    per_customer: !(file_name == "all.zip" || file_name.starts_with("all.")),
+                    in_progress: file_name.ends_with(".tmp"),
+                    file_name,
+                    size: metadata.len(),
+                    built_at: built_at.into(),
+                    last_access: last_access.into(),
+                    path: file.path(),
+                });
             }
-            pending.lock().unwrap().remove(&album_id_clone);
-        });
+        }
+        zips
+    })
+    .await
+    .unwrap_or_default()
+}
+
+/// Indices of the ZIPs to evict under `limits` at `now`.
+fn select_evictions(zips: &[CachedZip], limits: &ZipCacheConfig, now: SystemTime) -> Vec<usize> {
+    let now: DateTime<Utc> = now.into();
+    let max_age = chrono::Duration::days(limits.max_age_days as i64);
+    let customer_idle = chrono::Duration::days(limits.customer_idle_days as i64);
+    let abandoned = chrono::Duration::seconds(ABANDONED_BUILD_SECS);
 
-        self.pending.lock().unwrap().insert(album_id, handle);
+    let mut evict: Vec<usize> = Vec::new();
+    let mut kept: Vec<usize> = Vec::new();
+    for (i, zip) in zips.iter().enumerate() {
+        let expired = if zip.in_progress {
+            now - zip.built_at > abandoned
+        } else {
+            now - zip.built_at > max_age || (zip.per_customer && now - zip.last_access > customer_idle)
+        };
+        if expired {
+            evict.push(i);
+        } else if !zip.in_progress {
+            kept.push(i);
+        }
     }
+
+    let max_bytes = limits.max_size_mb * 1024 * 1024;
+    let mut total: u64 = zips
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !evict.contains(i))
+        .map(|(_, z)| z.size)
+        .sum();
+    kept.sort_by_key(|i| zips[*i].last_access);
+    for i in kept {
+        if total <= max_bytes {
+            break;
+        }
+        total -= zips[i].size;
+        evict.push(i);
+    }
+    evict
+}
+
+/// Collects the `(entry_name, file_path)` pairs packed for `items`, converting and
+/// stripping files as `options` require.
+pub async fn zip_entries(items: &[MediaItem], db: &ArcDynDatabase, options: &ZipOptions) -> Vec<(String, PathBuf)> {
+    // Items without a file in `options.roles` fall back to their primary file.
+    let mut entries: Vec<(String, PathBuf)> = Vec::new();
+    for (i, item) in items.iter().enumerate() {
+        let files = db.get_reference_files(&item.uuid).await.unwrap_or_default();
+        let mut chosen: Vec<_> = files
+            .iter()
+            .filter(|f| options.roles.contains(&f.role))
+            .collect();
+        if chosen.is_empty() {
+            chosen.extend(files.first());
+        }
+        for file in chosen {
+            let source = PathBuf::from(&file.filepath).join(&file.filename);
+            let converted = match (options.format, file.mime_type.as_deref()) {
+                (ZipFormat::Jpeg, Some(mime_type)) if heif::is_heif(mime_type) => {
+                    convert_to_jpeg(&source, &item.uuid).await
+                }
+                _ => None,
+            };
+            let (converted_name, source) = match converted {
+                Some(jpeg) => (Some(jpeg_filename(&file.filename)), jpeg),
+                None => (None, source),
+            };
+            let (filename, full_path) = if options.metadata_policy == MetadataPolicy::Keep && options.copyright.is_none() {
+                (converted_name.unwrap_or_else(|| file.filename.clone()), source)
+            } else {
+                let copyright = options.copyright.as_ref();
+                match prepare_delivery(&source, &item.uuid, options.metadata_policy, copyright).await {
+                    Ok(delivery) => (converted_name.unwrap_or(delivery.filename), delivery.path),
+                    // Files whose metadata cannot be stripped are left out.
+                    Err(e) => {
+                        tracing::warn!("Leaving {} out of ZIP: {:?}", source.display(), e);
+                        continue;
+                    }
+                }
+            };
+            entries.push((format!("{:03}_{}", i + 1, filename), full_path));
+        }
+    }
+    entries
 }
 
 /// Build a ZIP and write it atomically to `path`.
-/// Each photo is read and compressed individually — peak RAM use is one photo, not the whole album.
+/// Each file is streamed and compressed individually — peak RAM use stays constant, even for videos.
 pub async fn build_zip_to_file(
     items: &[MediaItem],
     path: &PathBuf,
     db: &ArcDynDatabase,
+    options: &ZipOptions,
 ) -> anyhow::Result<()> {
-    use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+    let entries = zip_entries(items, db, options).await;
+    build_entries_to_file(entries, path).await
+}
 
-    // Collect (entry_name, file_path) pairs via async DB calls.
-    let mut entries: Vec<(String, String)> = Vec::new();
-    for (i, item) in items.iter().enumerate() {
-        if let Ok(Some((filepath, filename))) = db.get_media_file_path(&item.uuid).await {
-            entries.push((
-                format!("{:03}_{}", i + 1, filename),
-                format!("{}/{}", filepath, filename),
-            ));
-        }
-    }
+/// Build a ZIP of `(entry_name, file_path)` pairs and write it atomically to `path`.
+pub async fn build_entries_to_file(entries: Vec<(String, PathBuf)>, path: &PathBuf) -> anyhow::Result<()> {
+    use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
 
     if let Some(parent) = path.parent() {
         tokio::fs::create_dir_all(parent).await?;
@@ -103,9 +448,10 @@ pub async fn build_zip_to_file(
             SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
 
         for (entry_name, full_path) in entries {
-            if let Ok(bytes) = std::fs::read(&full_path) {
+            // Copy in chunks — videos can be far larger than available memory.
+            if let Ok(mut source) = std::fs::File::open(&full_path) {
                 if zip.start_file(entry_name, options).is_ok() {
-                    let _ = zip.write_all(&bytes);
+                    let _ = std::io::copy(&mut source, &mut zip);
                 }
             }
         }
@@ -119,6 +465,23 @@ pub async fn build_zip_to_file(
     Ok(())
 }
 
+/// Full resolution JPEG of a HEIF image, `None` when it cannot be decoded.
+async fn convert_to_jpeg(source: &std::path::Path, media_id: &str) -> Option<PathBuf> {
+    let decoded = heif::decode(source, media_id).await?;
+    match get_or_create_rendition(&decoded, media_id, Rendition::Full).await {
+        Ok(path) => Some(path),
+        Err(e) => {
+            tracing::warn!("Failed to convert {} to JPEG: {:?}", source.display(), e);
+            None
+        }
+    }
+}
+
+fn jpeg_filename(filename: &str) -> String {
+    let stem = filename.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(filename);
+    format!("{}.jpg", stem)
+}
+
 /// Write bytes to a cache path atomically (temp file → rename).
 pub async fn write_cache_file(path: &PathBuf, bytes: Vec<u8>) {
     if let Some(parent) = path.parent() {
@@ -141,8 +504,19 @@ pub async fn generate_and_write_all_zip(
     db: &ArcDynDatabase,
 ) -> anyhow::Result<()> {
     let items = db.get_media_for_album(album_id).await?;
-    let path = ZipCacheManager::all_zip_path(album_id);
-    build_zip_to_file(&items, &path, db).await
+    let options = all_zip_options(album_id, db).await?;
+    let path = ZipCacheManager::variant_path(ZipCacheManager::all_zip_path(album_id), &options);
+    build_zip_to_file(&items, &path, db, &options).await
+}
+
+/// Options of the eagerly generated ZIP: what customers download, under the album's
+/// metadata policy and credited with its copyright template.
+pub async fn all_zip_options(album_id: &str, db: &ArcDynDatabase) -> anyhow::Result<ZipOptions> {
+    Ok(ZipOptions {
+        metadata_policy: db.get_album_metadata_policy(album_id).await?,
+        copyright: template_for_album(db, album_id).await?,
+        ..Default::default()
+    })
 }
 
 pub fn zip_tmp_path(path: &PathBuf) -> PathBuf {
@@ -156,3 +530,101 @@ fn tmp_path(path: &PathBuf) -> PathBuf {
         .unwrap_or_else(|| "archive.zip.tmp".to_string());
     path.with_file_name(name)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_variant_path() {
+        // given
+        let path = ZipCacheManager::all_zip_path("album");
+        let options = ZipOptions {
+            roles: vec![ReferenceRole::Original, ReferenceRole::Raw],
+            format: ZipFormat::Jpeg,
+            metadata_policy: MetadataPolicy::StripGps,
+            copyright: None,
+        };
+
+        // when
+        let default_variant = ZipCacheManager::variant_path(path.clone(), &ZipOptions::default());
+        let variant = ZipCacheManager::variant_path(path.clone(), &options);
+
+        // then
+        assert_eq!(default_variant, path);
+        assert_eq!(variant, path.with_file_name("all.original-raw.jpeg.strip-gps.zip"));
+        assert_eq!(jpeg_filename("IMG_0001.HEIC"), "IMG_0001.jpg");
+
+        // when
+        let copyright = CopyrightTemplate {
+            creator: Some("Jane Doe".into()),
+            ..Default::default()
+        };
+        let credited = ZipCacheManager::variant_path(
+            path.clone(),
+            &ZipOptions { copyright: Some(copyright.clone()), ..Default::default() },
+        );
+
+        // then
+        assert_eq!(
+            credited,
+            path.with_file_name(format!("all.original.c-{}.zip", template_hash(&copyright)))
+        );
+    }
+
+    fn cached(file_name: &str, size: u64, built_days_ago: u64, accessed_days_ago: u64, now: SystemTime) -> CachedZip {
+        let day = Duration::from_secs(24 * 3600);
+        let built_at: DateTime<Utc> = (now - day * built_days_ago as u32).into();
+        let last_access: DateTime<Utc> = (now - day * accessed_days_ago as u32).into();
+        CachedZip {
+            album_id: "album".into(),
+            file_name: file_name.into(),
+            size,
+            built_at,
+            last_access,
+            per_customer: !file_name.starts_with("all."),
+            in_progress: file_name.ends_with(".tmp"),
+            path: PathBuf::from(file_name),
+        }
+    }
+
+    #[test]
+    fn test_select_evictions_by_age_and_idle_customer_zips() {
+        // given
+        let now = SystemTime::now();
+        let limits = ZipCacheConfig { max_size_mb: 1024, max_age_days: 30, customer_idle_days: 7 };
+        let zips = vec![
+            cached("all.zip", 10, 40, 1, now),
+            cached("all.original-raw.zip", 10, 5, 20, now),
+            cached("customer.zip", 10, 10, 8, now),
+            cached("other.zip", 10, 10, 2, now),
+            cached("all.zip.tmp", 10, 2, 2, now),
+        ];
+
+        // when
+        let evicted = select_evictions(&zips, &limits, now);
+
+        // then
+        assert_eq!(evicted, vec![0, 2, 4]);
+    }
+
+    #[test]
+    fn test_select_evictions_least_recently_used_over_size() {
+        // given
+        let now = SystemTime::now();
+        let mb = 1024 * 1024;
+        let limits = ZipCacheConfig { max_size_mb: 3, max_age_days: 30, customer_idle_days: 7 };
+        let zips = vec![
+            cached("all.zip", 2 * mb, 1, 1, now),
+            cached("a.zip", mb, 1, 3, now),
+            cached("b.zip", mb, 1, 2, now),
+            cached("c.zip.tmp", mb, 0, 0, now),
+        ];
+
+        // when
+        let evicted = select_evictions(&zips, &limits, now);
+
+        // then
+        assert_eq!(evicted, vec![1, 2]);
+    }
+}
@@ -0,0 +1,521 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Album ZIPs written on the fly.
+//!
+//! Files are stored uncompressed (STORE), so the layout of the whole archive follows from
+//! the entry names and file sizes alone. That gives an exact `Content-Length` before the
+//! first byte is sent and lets any byte range be produced again for resumed downloads.
+//! CRC-32 checksums are computed right before a file's header is written and cached, so
+//! local headers carry the real values and no data descriptors are needed. Entries and
+//! offsets beyond 4 GiB switch to ZIP64.
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+use std::time::SystemTime;
+
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+
+const LOCAL_HEADER_SIG: u32 = 0x0403_4b50;
+const CENTRAL_HEADER_SIG: u32 = 0x0201_4b50;
+const ZIP64_END_SIG: u32 = 0x0606_4b50;
+const ZIP64_LOCATOR_SIG: u32 = 0x0706_4b50;
+const END_SIG: u32 = 0x0605_4b50;
+
+const LOCAL_HEADER_LEN: u64 = 30;
+const CENTRAL_HEADER_LEN: u64 = 46;
+const ZIP64_END_LEN: u64 = 56;
+const ZIP64_LOCATOR_LEN: u64 = 20;
+const END_LEN: u64 = 22;
+
+/// 32 bit fields holding this value are found in the ZIP64 extra field instead.
+const ZIP64_MARKER: u32 = 0xFFFF_FFFF;
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+
+/// General purpose flag: entry names are UTF-8.
+const FLAG_UTF8: u16 = 1 << 11;
+const VERSION_DEFAULT: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+
+const CHUNK_SIZE: usize = 256 * 1024;
+
+/// Size, mtime and CRC-32 of a file.
+type Checksum = (u64, SystemTime, u32);
+
+/// Checksums of files already streamed, keyed by path and validated by size and mtime.
+static CRC_CACHE: LazyLock<Mutex<HashMap<PathBuf, Checksum>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Debug, Clone)]
+struct Entry {
+    name: String,
+    path: PathBuf,
+    size: u64,
+    modified: SystemTime,
+    /// Offset of the local header.
+    offset: u64,
+}
+
+impl Entry {
+    fn is_large(&self) -> bool {
+        self.size >= ZIP64_MARKER as u64
+    }
+
+    fn local_header_len(&self) -> u64 {
+        let extra = if self.is_large() { 20 } else { 0 };
+        LOCAL_HEADER_LEN + self.name.len() as u64 + extra
+    }
+
+    fn data_offset(&self) -> u64 {
+        self.offset + self.local_header_len()
+    }
+
+    fn end(&self) -> u64 {
+        self.data_offset() + self.size
+    }
+
+    fn central_extra_len(&self) -> u64 {
+        let mut fields = 0;
+        if self.is_large() {
+            fields += 2;
+        }
+        if self.offset >= ZIP64_MARKER as u64 {
+            fields += 1;
+        }
+        if fields == 0 {
+            0
+        } else {
+            4 + 8 * fields
+        }
+    }
+
+    fn central_header_len(&self) -> u64 {
+        CENTRAL_HEADER_LEN + self.name.len() as u64 + self.central_extra_len()
+    }
+
+    fn version(&self) -> u16 {
+        if self.central_extra_len() > 0 {
+            VERSION_ZIP64
+        } else {
+            VERSION_DEFAULT
+        }
+    }
+}
+
+/// The layout of a STORE ZIP over files on disk.
+#[derive(Debug, Clone)]
+pub struct StreamedZip {
+    entries: Vec<Entry>,
+    central_offset: u64,
+    central_len: u64,
+}
+
+impl StreamedZip {
+    /// Lays out the archive for `(entry_name, file_path)` pairs. Files that disappeared
+    /// in the meantime are left out.
+    pub async fn plan(files: Vec<(String, PathBuf)>) -> Self {
+        let mut entries = Vec::with_capacity(files.len());
+        let mut offset = 0;
+        for (name, path) in files {
+            let metadata = match tokio::fs::metadata(&path).await {
+                Ok(metadata) if metadata.is_file() => metadata,
+                _ => {
+                    tracing::warn!(
+                        "Leaving {} out of streamed ZIP: file not found",
+                        path.display()
+                    );
+                    continue;
+                }
+            };
+            let entry = Entry {
+                name,
+                path,
+                size: metadata.len(),
+                modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                offset,
+            };
+            offset = entry.end();
+            entries.push(entry);
+        }
+        let central_len = entries.iter().map(Entry::central_header_len).sum();
+        Self {
+            entries,
+            central_offset: offset,
+            central_len,
+        }
+    }
+
+    fn needs_zip64_end(&self) -> bool {
+        self.entries.len() >= 0xFFFF
+            || self.central_offset >= ZIP64_MARKER as u64
+            || self.central_len >= ZIP64_MARKER as u64
+    }
+
+    fn end_len(&self) -> u64 {
+        let zip64 = if self.needs_zip64_end() {
+            ZIP64_END_LEN + ZIP64_LOCATOR_LEN
+        } else {
+            0
+        };
+        zip64 + END_LEN
+    }
+
+    /// Size of the whole archive in bytes.
+    pub fn len(&self) -> u64 {
+        self.central_offset + self.central_len + self.end_len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Writes the bytes `start..=end` of the archive to `out`.
+    pub async fn write_range<W: AsyncWrite + Unpin>(
+        &self,
+        start: u64,
+        end: u64,
+        out: &mut W,
+    ) -> anyhow::Result<()> {
+        let range = start..end.saturating_add(1).min(self.len());
+
+        for entry in &self.entries {
+            if entry.end() <= range.start {
+                continue;
+            }
+            if entry.offset >= range.end {
+                break;
+            }
+            if overlaps(entry.offset, entry.data_offset(), &range) {
+                let crc = checksum(entry).await?;
+                write_slice(out, &local_header(entry, crc), entry.offset, &range).await?;
+            }
+            if overlaps(entry.data_offset(), entry.end(), &range) {
+                write_file_slice(out, entry, &range).await?;
+            }
+        }
+
+        let central_end = self.central_offset + self.central_len;
+        if overlaps(self.central_offset, central_end, &range) {
+            let mut central = Vec::with_capacity(self.central_len as usize);
+            for entry in &self.entries {
+                central.extend(central_header(entry, checksum(entry).await?));
+            }
+            write_slice(out, &central, self.central_offset, &range).await?;
+        }
+        if overlaps(central_end, self.len(), &range) {
+            write_slice(out, &self.end_records(), central_end, &range).await?;
+        }
+
+        out.flush().await?;
+        Ok(())
+    }
+
+    fn end_records(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.end_len() as usize);
+        let count = self.entries.len() as u64;
+        if self.needs_zip64_end() {
+            let zip64_end_offset = self.central_offset + self.central_len;
+            put_u32(&mut buf, ZIP64_END_SIG);
+            put_u64(&mut buf, ZIP64_END_LEN - 12);
+            put_u16(&mut buf, VERSION_ZIP64);
+            put_u16(&mut buf, VERSION_ZIP64);
+            put_u32(&mut buf, 0); // this disk
+            put_u32(&mut buf, 0); // disk with the central directory
+            put_u64(&mut buf, count);
+            put_u64(&mut buf, count);
+            put_u64(&mut buf, self.central_len);
+            put_u64(&mut buf, self.central_offset);
+
+            put_u32(&mut buf, ZIP64_LOCATOR_SIG);
+            put_u32(&mut buf, 0);
+            put_u64(&mut buf, zip64_end_offset);
+            put_u32(&mut buf, 1); // total disks
+        }
+        put_u32(&mut buf, END_SIG);
+        put_u16(&mut buf, 0);
+        put_u16(&mut buf, 0);
+        put_u16(&mut buf, count.min(0xFFFF) as u16);
+        put_u16(&mut buf, count.min(0xFFFF) as u16);
+        put_u32(&mut buf, clamp32(self.central_len));
+        put_u32(&mut buf, clamp32(self.central_offset));
+        put_u16(&mut buf, 0); // comment length
+        buf
+    }
+}
+
+fn overlaps(from: u64, to: u64, range: &std::ops::Range<u64>) -> bool {
+    from < range.end && to > range.start
+}
+
+/// Writes the part of `bytes`, located at `offset` in the archive, that falls into `range`.
+async fn write_slice<W: AsyncWrite + Unpin>(
+    out: &mut W,
+    bytes: &[u8],
+    offset: u64,
+    range: &std::ops::Range<u64>,
+) -> std::io::Result<()> {
+    let from = range.start.saturating_sub(offset) as usize;
+    let to = ((range.end - offset) as usize).min(bytes.len());
+    out.write_all(&bytes[from..to]).await
+}
+
+async fn write_file_slice<W: AsyncWrite + Unpin>(
+    out: &mut W,
+    entry: &Entry,
+    range: &std::ops::Range<u64>,
+) -> anyhow::Result<()> {
+    let data_offset = entry.data_offset();
+    let from = range.start.saturating_sub(data_offset);
+    let to = (range.end - data_offset).min(entry.size);
+
+    let mut file = tokio::fs::File::open(&entry.path).await?;
+    if file.metadata().await?.len() != entry.size {
+        anyhow::bail!("{} changed while streaming", entry.path.display());
+    }
+    file.seek(std::io::SeekFrom::Start(from)).await?;
+    let mut remaining = file.take(to - from);
+    let copied = tokio::io::copy(&mut remaining, out).await?;
+    if copied != to - from {
+        anyhow::bail!("{} ended early while streaming", entry.path.display());
+    }
+    Ok(())
+}
+
+fn local_header(entry: &Entry, crc: u32) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(entry.local_header_len() as usize);
+    let (time, date) = dos_date_time(entry.modified);
+    let size = if entry.is_large() {
+        ZIP64_MARKER
+    } else {
+        entry.size as u32
+    };
+    put_u32(&mut buf, LOCAL_HEADER_SIG);
+    put_u16(
+        &mut buf,
+        if entry.is_large() {
+            VERSION_ZIP64
+        } else {
+            VERSION_DEFAULT
+        },
+    );
+    put_u16(&mut buf, FLAG_UTF8);
+    put_u16(&mut buf, 0); // STORE
+    put_u16(&mut buf, time);
+    put_u16(&mut buf, date);
+    put_u32(&mut buf, crc);
+    put_u32(&mut buf, size);
+    put_u32(&mut buf, size);
+    put_u16(&mut buf, entry.name.len() as u16);
+    put_u16(&mut buf, if entry.is_large() { 20 } else { 0 });
+    buf.extend(entry.name.as_bytes());
+    if entry.is_large() {
+        put_u16(&mut buf, ZIP64_EXTRA_ID);
+        put_u16(&mut buf, 16);
+        put_u64(&mut buf, entry.size);
+        put_u64(&mut buf, entry.size);
+    }
+    buf
+}
+
+fn central_header(entry: &Entry, crc: u32) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(entry.central_header_len() as usize);
+    let (time, date) = dos_date_time(entry.modified);
+    let size = if entry.is_large() {
+        ZIP64_MARKER
+    } else {
+        entry.size as u32
+    };
+    let extra_len = entry.central_extra_len();
+    put_u32(&mut buf, CENTRAL_HEADER_SIG);
+    put_u16(&mut buf, entry.version()); // made by
+    put_u16(&mut buf, entry.version()); // needed to extract
+    put_u16(&mut buf, FLAG_UTF8);
+    put_u16(&mut buf, 0); // STORE
+    put_u16(&mut buf, time);
+    put_u16(&mut buf, date);
+    put_u32(&mut buf, crc);
+    put_u32(&mut buf, size);
+    put_u32(&mut buf, size);
+    put_u16(&mut buf, entry.name.len() as u16);
+    put_u16(&mut buf, extra_len as u16);
+    put_u16(&mut buf, 0); // comment length
+    put_u16(&mut buf, 0); // disk number
+    put_u16(&mut buf, 0); // internal attributes
+    put_u32(&mut buf, 0); // external attributes
+    put_u32(&mut buf, clamp32(entry.offset));
+    buf.extend(entry.name.as_bytes());
+    if extra_len > 0 {
+        put_u16(&mut buf, ZIP64_EXTRA_ID);
+        put_u16(&mut buf, (extra_len - 4) as u16);
+        if entry.is_large() {
+            put_u64(&mut buf, entry.size);
+            put_u64(&mut buf, entry.size);
+        }
+        if entry.offset >= ZIP64_MARKER as u64 {
+            put_u64(&mut buf, entry.offset);
+        }
+    }
+    buf
+}
+
+/// CRC-32 of an entry's file, read once and then served from the cache.
+async fn checksum(entry: &Entry) -> anyhow::Result<u32> {
+    if let Some((size, modified, crc)) = CRC_CACHE.lock().unwrap().get(&entry.path) {
+        if *size == entry.size && *modified == entry.modified {
+            return Ok(*crc);
+        }
+    }
+    let path = entry.path.clone();
+    let crc = tokio::task::spawn_blocking(move || crc32_of_file(&path)).await??;
+    CRC_CACHE
+        .lock()
+        .unwrap()
+        .insert(entry.path.clone(), (entry.size, entry.modified, crc));
+    Ok(crc)
+}
+
+fn crc32_of_file(path: &Path) -> std::io::Result<u32> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finalize())
+}
+
+/// MS-DOS time and date fields, which cannot express dates before 1980.
+fn dos_date_time(modified: SystemTime) -> (u16, u16) {
+    let t: DateTime<Utc> = modified.into();
+    if t.year() < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let time = ((t.hour() << 11) | (t.minute() << 5) | (t.second() / 2)) as u16;
+    let date = ((((t.year() - 1980) as u32).min(127) << 9) | (t.month() << 5) | t.day()) as u16;
+    (time, date)
+}
+
+fn clamp32(value: u64) -> u32 {
+    value.min(ZIP64_MARKER as u64) as u32
+}
+
+fn put_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend(value.to_le_bytes());
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend(value.to_le_bytes());
+}
+
+fn put_u64(buf: &mut Vec<u8>, value: u64) {
+    buf.extend(value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn write_files(dir: &Path, files: &[(&str, &[u8])]) -> Vec<(String, PathBuf)> {
+        std::fs::create_dir_all(dir).unwrap();
+        files
+            .iter()
+            .map(|(name, content)| {
+                let path = dir.join(name);
+                std::fs::write(&path, content).unwrap();
+                (format!("001_{}", name), path)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_streamed_zip_is_readable_and_has_exact_length() {
+        // given
+        let dir = std::env::temp_dir().join(format!("zip_stream_{}", uuid::Uuid::new_v4()));
+        let files = write_files(&dir, &[("a.jpg", b"first file"), ("b.mp4", &[7u8; 5000])]);
+        let zip = StreamedZip::plan(files).await;
+
+        // when
+        let mut out = Vec::new();
+        zip.write_range(0, zip.len() - 1, &mut out).await.unwrap();
+
+        // then
+        assert_eq!(out.len() as u64, zip.len());
+        let mut archive = zip::ZipArchive::new(Cursor::new(out)).unwrap();
+        assert_eq!(archive.len(), 2);
+        let mut content = Vec::new();
+        archive
+            .by_name("001_b.mp4")
+            .unwrap()
+            .read_to_end(&mut content)
+            .unwrap();
+        assert_eq!(content, vec![7u8; 5000]);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn test_ranges_concatenate_to_full_archive() {
+        // given
+        let dir = std::env::temp_dir().join(format!("zip_stream_{}", uuid::Uuid::new_v4()));
+        let files = write_files(&dir, &[("a.jpg", &[1u8; 300]), ("b.jpg", &[2u8; 700])]);
+        let zip = StreamedZip::plan(files).await;
+        let mut full = Vec::new();
+        zip.write_range(0, zip.len() - 1, &mut full).await.unwrap();
+
+        // when
+        let mut parts = Vec::new();
+        let mut start = 0;
+        while start < zip.len() {
+            let end = (start + 97).min(zip.len() - 1);
+            zip.write_range(start, end, &mut parts).await.unwrap();
+            start = end + 1;
+        }
+
+        // then
+        assert_eq!(parts, full);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_large_entries_use_zip64_fields() {
+        // given
+        let entry = Entry {
+            name: "001_video.mp4".into(),
+            path: PathBuf::from("video.mp4"),
+            size: 5 * 1024 * 1024 * 1024,
+            modified: SystemTime::UNIX_EPOCH,
+            offset: 6 * 1024 * 1024 * 1024,
+        };
+
+        // when
+        let local = local_header(&entry, 0);
+        let central = central_header(&entry, 0);
+
+        // then
+        assert_eq!(local.len() as u64, entry.local_header_len());
+        assert_eq!(central.len() as u64, entry.central_header_len());
+        assert_eq!(&central[20..28], &[0xFF; 8]);
+        assert_eq!(&central[42..46], &[0xFF; 4]);
+    }
+}
@@ -0,0 +1,13 @@
+-- Public share links granting anonymous, token based access to an album
+CREATE TABLE IF NOT EXISTS album_share_links (
+    token          VARCHAR PRIMARY KEY,
+    album_id       VARCHAR NOT NULL REFERENCES albums(album_id) ON DELETE CASCADE,
+    created_by     VARCHAR NOT NULL REFERENCES accounts(account_id),
+    password_hash  VARCHAR DEFAULT NULL,
+    allow_download BOOLEAN NOT NULL DEFAULT FALSE,
+    rendition      VARCHAR NOT NULL DEFAULT 'large', -- 'original', 'large', 'medium' or 'small'
+    expires_at     TIMESTAMPTZ DEFAULT NULL,
+    created_at     TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_album_share_links_album_id ON album_share_links(album_id);
@@ -0,0 +1,3 @@
+-- MIME type detected from the file content at upload time.
+-- NULL for files uploaded before content sniffing was introduced.
+ALTER TABLE reference ADD COLUMN mime_type VARCHAR DEFAULT NULL;
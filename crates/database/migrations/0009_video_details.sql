@@ -0,0 +1,11 @@
+-- Technical metadata of video media items, extracted with ffprobe when available
+CREATE TABLE IF NOT EXISTS video_details (
+    media        VARCHAR PRIMARY KEY REFERENCES media(uuid),
+    duration_ms  BIGINT DEFAULT NULL,
+    width        INTEGER DEFAULT NULL,
+    height       INTEGER DEFAULT NULL,
+    video_codec  VARCHAR DEFAULT NULL,
+    audio_codec  VARCHAR DEFAULT NULL,
+    frame_rate   FLOAT DEFAULT NULL,
+    poster_path  VARCHAR DEFAULT NULL
+);
@@ -0,0 +1,4 @@
+-- Purpose of a file within its media item: original, raw, sidecar, rendition or live-video
+ALTER TABLE reference ADD COLUMN role VARCHAR NOT NULL DEFAULT 'original';
+
+CREATE INDEX IF NOT EXISTS idx_reference_media ON reference (media);
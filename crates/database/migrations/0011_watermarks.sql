@@ -0,0 +1,14 @@
+-- Per-album watermark settings for proof galleries
+CREATE TABLE IF NOT EXISTS album_watermarks (
+    album_id   VARCHAR PRIMARY KEY REFERENCES albums(album_id) ON DELETE CASCADE,
+    kind       VARCHAR NOT NULL DEFAULT 'text', -- 'text' or 'image'
+    text       VARCHAR DEFAULT NULL,
+    image_path VARCHAR DEFAULT NULL,
+    position   VARCHAR NOT NULL DEFAULT 'bottom-right',
+    opacity    FLOAT NOT NULL DEFAULT 0.5,
+    scale      FLOAT NOT NULL DEFAULT 0.25,
+    updated_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Paid customers see clean images and may download originals
+ALTER TABLE customers ADD COLUMN paid BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,2 @@
+-- Metadata delivered with originals: 'keep', 'strip-gps', 'copyright-and-date' or 'strip-all'
+ALTER TABLE albums ADD COLUMN metadata_policy VARCHAR NOT NULL DEFAULT 'keep';
@@ -0,0 +1,13 @@
+-- IPTC/XMP credit embedded into delivered JPEGs, per account with per-album overrides
+CREATE TABLE IF NOT EXISTS copyright_templates (
+    scope            VARCHAR NOT NULL, -- 'account' or 'album'
+    owner_id         VARCHAR NOT NULL,
+    creator          VARCHAR DEFAULT NULL,
+    copyright_notice VARCHAR DEFAULT NULL,
+    credit_line      VARCHAR DEFAULT NULL,
+    rights_url       VARCHAR DEFAULT NULL,
+    contact_email    VARCHAR DEFAULT NULL,
+    usage_terms      VARCHAR DEFAULT NULL,
+    updated_at       TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (scope, owner_id)
+);
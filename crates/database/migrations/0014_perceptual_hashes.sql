@@ -0,0 +1,2 @@
+-- 64 bit difference hash of image references, used to find near duplicates and bursts
+ALTER TABLE reference ADD COLUMN perceptual_hash BIGINT DEFAULT NULL;
@@ -0,0 +1,8 @@
+-- Placeholders shown while gallery grids load: size, dominant color and a BlurHash
+CREATE TABLE IF NOT EXISTS media_placeholders (
+    media          VARCHAR PRIMARY KEY REFERENCES media(uuid),
+    width          INTEGER NOT NULL,
+    height         INTEGER NOT NULL,
+    dominant_color VARCHAR NOT NULL,
+    blurhash       VARCHAR NOT NULL
+);
@@ -0,0 +1,17 @@
+-- Background jobs: media processing, ZIP builds and metadata scrubbing
+CREATE TABLE IF NOT EXISTS jobs (
+    job_id       VARCHAR PRIMARY KEY,
+    kind         VARCHAR NOT NULL,
+    payload      VARCHAR NOT NULL, -- JSON of the typed job
+    status       VARCHAR NOT NULL DEFAULT 'queued', -- queued, running or failed
+    attempts     INTEGER NOT NULL DEFAULT 0,
+    max_attempts INTEGER NOT NULL,
+    run_at       TIMESTAMPTZ NOT NULL,
+    last_error   VARCHAR DEFAULT NULL,
+    dedupe_key   VARCHAR DEFAULT NULL,
+    created_at   TIMESTAMPTZ NOT NULL,
+    updated_at   TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_jobs_due ON jobs (status, kind, run_at);
+CREATE INDEX IF NOT EXISTS idx_jobs_dedupe_key ON jobs (dedupe_key);
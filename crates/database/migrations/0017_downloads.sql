@@ -0,0 +1,11 @@
+-- Media selections across albums, downloaded as a single ZIP
+CREATE TABLE IF NOT EXISTS downloads (
+    download_id    VARCHAR PRIMARY KEY,
+    requester_id   VARCHAR NOT NULL,
+    requester_role VARCHAR NOT NULL, -- account or customer
+    contents       VARCHAR NOT NULL, -- JSON: selected items and ZIP options
+    created_at     TIMESTAMPTZ NOT NULL,
+    expires_at     TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_downloads_expires_at ON downloads (expires_at);
@@ -0,0 +1,18 @@
+-- Signed in devices, each holding a rotating refresh token
+CREATE TABLE IF NOT EXISTS sessions (
+    session_id          VARCHAR PRIMARY KEY,
+    subject_id          VARCHAR NOT NULL,
+    role                VARCHAR NOT NULL, -- account or customer
+    refresh_token_hash  VARCHAR NOT NULL, -- SHA-256 of the current refresh token
+    previous_token_hash VARCHAR,          -- SHA-256 of the rotated one, to detect reuse
+    user_agent          VARCHAR,
+    ip_address          VARCHAR,
+    created_at          TIMESTAMPTZ NOT NULL,
+    last_used_at        TIMESTAMPTZ NOT NULL,
+    expires_at          TIMESTAMPTZ NOT NULL,
+    revoked_at          TIMESTAMPTZ
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_sessions_refresh_token_hash ON sessions (refresh_token_hash);
+CREATE INDEX IF NOT EXISTS idx_sessions_previous_token_hash ON sessions (previous_token_hash);
+CREATE INDEX IF NOT EXISTS idx_sessions_subject ON sessions (subject_id, role);
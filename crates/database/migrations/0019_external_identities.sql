@@ -0,0 +1,12 @@
+-- Accounts signed in through an external OpenID Connect provider
+CREATE TABLE IF NOT EXISTS external_identities (
+    provider      VARCHAR NOT NULL, -- name of the provider in the configuration
+    subject       VARCHAR NOT NULL, -- `sub` claim issued by the provider
+    account_id    VARCHAR NOT NULL,
+    email         VARCHAR,
+    created_at    TIMESTAMPTZ NOT NULL,
+    last_login_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (provider, subject)
+);
+
+CREATE INDEX IF NOT EXISTS idx_external_identities_account ON external_identities (account_id);
@@ -0,0 +1,16 @@
+-- TOTP second factor of accounts (RFC 6238)
+CREATE TABLE IF NOT EXISTS account_mfa (
+    account_id     VARCHAR PRIMARY KEY,
+    totp_secret    VARCHAR NOT NULL,     -- base32, as shown to the authenticator app
+    enabled_at     TIMESTAMPTZ,          -- NULL until the first code was confirmed
+    last_used_step BIGINT,               -- time step of the last accepted code, against replays
+    created_at     TIMESTAMPTZ NOT NULL
+);
+
+-- One-time codes to sign in without the authenticator app
+CREATE TABLE IF NOT EXISTS account_recovery_codes (
+    account_id VARCHAR NOT NULL,
+    code_hash  VARCHAR NOT NULL, -- SHA-256 of the normalized code
+    used_at    TIMESTAMPTZ,
+    PRIMARY KEY (account_id, code_hash)
+);
@@ -0,0 +1,14 @@
+-- WebAuthn credentials (passkeys) of accounts
+CREATE TABLE IF NOT EXISTS account_passkeys (
+    credential_id VARCHAR PRIMARY KEY, -- base64url, as sent by the authenticator
+    account_id    VARCHAR NOT NULL,
+    name          VARCHAR NOT NULL,    -- chosen by the user, e.g. "Phone"
+    public_key    VARCHAR NOT NULL,    -- COSE key, base64url
+    algorithm     BIGINT NOT NULL,     -- COSE algorithm, -7 (ES256) or -257 (RS256)
+    sign_count    BIGINT NOT NULL,
+    transports    VARCHAR,             -- comma separated hints like "internal,hybrid"
+    created_at    TIMESTAMPTZ NOT NULL,
+    last_used_at  TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS idx_account_passkeys_account ON account_passkeys (account_id);
@@ -0,0 +1,28 @@
+-- Set once the owner of the account proved to read mails sent to its address
+ALTER TABLE accounts ADD COLUMN email_verified_at TIMESTAMPTZ DEFAULT NULL;
+
+-- Accounts from before verification mails keep signing in when `require_verified_email` is turned on
+UPDATE accounts SET email_verified_at = COALESCE(created_at, CURRENT_TIMESTAMP);
+
+-- Single-use tokens mailed to verify the address or to reset the password
+CREATE TABLE IF NOT EXISTS account_tokens (
+    token_hash VARCHAR PRIMARY KEY, -- SHA-256 of the token
+    account_id VARCHAR NOT NULL,
+    purpose    VARCHAR NOT NULL,    -- verify_email or password_reset
+    email      VARCHAR NOT NULL,    -- address the token was sent to
+    created_at TIMESTAMPTZ NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at    TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS idx_account_tokens_account ON account_tokens (account_id, purpose);
+
+-- Wording of the mails to accounts of a studio, replacing the built-in texts
+CREATE TABLE IF NOT EXISTS mail_templates (
+    owner_id   VARCHAR NOT NULL, -- account of the studio
+    kind       VARCHAR NOT NULL, -- verify_email, password_reset or password_changed
+    subject    VARCHAR NOT NULL,
+    body       VARCHAR NOT NULL,
+    updated_at TIMESTAMPTZ DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (owner_id, kind)
+);
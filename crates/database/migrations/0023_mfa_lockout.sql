@@ -0,0 +1,5 @@
+-- Wrong second factors in a row, across logins. Only a right one resets the count.
+ALTER TABLE account_mfa ADD COLUMN failed_attempts BIGINT NOT NULL DEFAULT 0;
+
+-- Second factors are refused until then, once too many wrong ones were given
+ALTER TABLE account_mfa ADD COLUMN locked_until TIMESTAMPTZ DEFAULT NULL;
@@ -0,0 +1,3 @@
+-- SHA-256 of the file taken at upload, the strong validator of downloads.
+-- NULL for files uploaded before hashing was introduced.
+ALTER TABLE reference ADD COLUMN content_hash VARCHAR DEFAULT NULL;
@@ -1,3 +1,5 @@
+#[cfg(feature = "test-util")]
+pub mod memory;
 pub mod mysql;
 pub mod postgres;
 pub mod sqlite;
@@ -0,0 +1,1924 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! An in-memory database for tests of request handlers.
+//!
+//! Mirrors the behaviour of the SQL databases, including the order of listings, so tests
+//! of other crates run without a database server or migrations.
+
+use std::collections::BTreeMap;
+use std::sync::{Mutex, MutexGuard};
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use common::auth::account::Account;
+use common::auth::account_token::{AccountToken, TokenPurpose};
+use common::auth::account_with_albums::{AccountWithAlbums, AlbumRef};
+use common::auth::album_account::AlbumAccountEntry;
+use common::auth::customer::Customer;
+use common::auth::mfa::AccountMfa;
+use common::auth::passkey::Passkey;
+use common::auth::session::{Session, SessionClient};
+use common::database::album::Album;
+use common::database::album_stats::{AlbumStats, ViewerEntry};
+use common::database::copyright_template::{CopyrightScope, CopyrightTemplate};
+use common::database::download::Download;
+use common::database::image_hash::{ImageHash, UnindexedReference};
+use common::database::job::{JobRecord, JobStatus};
+use common::database::mail_template::{MailKind, MailTemplate};
+use common::database::media_item::MediaItem;
+use common::database::placeholder::Placeholder;
+use common::database::reference::{self, Reference, ReferenceFile, ReferenceRole};
+use common::database::share_link::ShareLink;
+use common::database::video_details::VideoDetails;
+use common::database::watermark::AlbumWatermark;
+use common::database::{AlbumCodeEntry, Database};
+use common::metadata::MetadataPolicy;
+use sqlx::types::chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+struct StoredMedia {
+    owner: String,
+    item: MediaItem,
+}
+
+struct StoredReference {
+    media_id: String,
+    owner: String,
+    reference: Reference,
+    perceptual_hash: Option<i64>,
+}
+
+struct StoredAlbum {
+    album: Album,
+    metadata_policy: MetadataPolicy,
+}
+
+struct AlbumMedia {
+    album_id: String,
+    media_id: String,
+    added_at: DateTime<Utc>,
+}
+
+struct CustomerAlbum {
+    customer_id: String,
+    album_id: String,
+    assigned_at: DateTime<Utc>,
+}
+
+struct AccountAccessCode {
+    account_id: String,
+    customer_id: String,
+    linked_at: DateTime<Utc>,
+}
+
+struct ExternalIdentity {
+    provider: String,
+    subject: String,
+    account_id: String,
+}
+
+struct RecoveryCode {
+    account_id: String,
+    code_hash: String,
+    used_at: Option<DateTime<Utc>>,
+}
+
+struct AlbumView {
+    album_id: String,
+    viewer_id: String,
+    viewer_role: String,
+}
+
+#[derive(Default)]
+struct State {
+    accounts: Vec<Account>,
+    customers: Vec<Customer>,
+    albums: Vec<StoredAlbum>,
+    media: Vec<StoredMedia>,
+    references: Vec<StoredReference>,
+    video_details: Vec<VideoDetails>,
+    placeholders: Vec<Placeholder>,
+    album_media: Vec<AlbumMedia>,
+    customer_albums: Vec<CustomerAlbum>,
+    album_accounts: Vec<AlbumAccountEntry>,
+    account_access_codes: Vec<AccountAccessCode>,
+    /// `(customer_id, album_id, media_id)`
+    customer_items: Vec<(String, String, String)>,
+    jobs: Vec<JobRecord>,
+    downloads: Vec<Download>,
+    sessions: Vec<Session>,
+    identities: Vec<ExternalIdentity>,
+    mfa: Vec<AccountMfa>,
+    recovery_codes: Vec<RecoveryCode>,
+    passkeys: Vec<Passkey>,
+    account_tokens: Vec<AccountToken>,
+    album_views: Vec<AlbumView>,
+    /// `(media_id, album_id)`, only downloads of an album are counted.
+    media_downloads: Vec<(String, Option<String>)>,
+    share_links: Vec<ShareLink>,
+    watermarks: Vec<AlbumWatermark>,
+    copyright_templates: Vec<(CopyrightScope, String, CopyrightTemplate)>,
+    mail_templates: Vec<(String, MailKind, MailTemplate)>,
+}
+
+impl State {
+    fn account_mut(&mut self, account_id: &str) -> Option<&mut Account> {
+        self.accounts.iter_mut().find(|a| a.account_id == account_id)
+    }
+
+    fn album(&self, album_id: &str) -> Option<&StoredAlbum> {
+        self.albums.iter().find(|a| a.album.album_id == album_id)
+    }
+
+    fn create_customer(
+        &mut self,
+        customer_id: String,
+        access_code: String,
+        display_name: String,
+    ) -> Result<()> {
+        if self.customers.iter().any(|c| c.access_code == access_code) {
+            return Err(anyhow!("Access code {} is already in use", access_code));
+        }
+        self.customers.push(Customer {
+            customer_id,
+            access_code,
+            display_name: Some(display_name),
+            created_at: Utc::now(),
+            updated_at: None,
+            last_login_at: None,
+            paid: false,
+        });
+        Ok(())
+    }
+
+    fn assign_album_to_customer(&mut self, album_id: &str, customer_id: &str) {
+        if !self
+            .customer_albums
+            .iter()
+            .any(|ca| ca.album_id == album_id && ca.customer_id == customer_id)
+        {
+            self.customer_albums.push(CustomerAlbum {
+                customer_id: customer_id.to_string(),
+                album_id: album_id.to_string(),
+                assigned_at: Utc::now(),
+            });
+        }
+    }
+
+    /// A media item with its video details and placeholder, as returned by listings.
+    fn media_item(&self, media: &StoredMedia) -> MediaItem {
+        MediaItem {
+            video: self
+                .video_details
+                .iter()
+                .find(|vd| vd.media == media.item.uuid)
+                .cloned(),
+            placeholder: self
+                .placeholders
+                .iter()
+                .find(|ph| ph.media == media.item.uuid)
+                .cloned(),
+            ..media.item.clone()
+        }
+    }
+
+    /// References of a media item, primary file first.
+    fn references_of(&self, media_id: &str) -> Vec<&StoredReference> {
+        let mut references = self
+            .references
+            .iter()
+            .filter(|r| r.media_id == media_id)
+            .collect::<Vec<_>>();
+        references.sort_by(|a, b| {
+            role_rank(a.reference.role)
+                .cmp(&role_rank(b.reference.role))
+                .then_with(|| a.reference.filename.cmp(&b.reference.filename))
+        });
+        references
+    }
+
+    fn media_in_album(&self, album_id: &str, media_id: &str) -> bool {
+        self.album_media
+            .iter()
+            .any(|am| am.album_id == album_id && am.media_id == media_id)
+    }
+
+    fn image_hash(&self, reference: &StoredReference) -> Option<ImageHash> {
+        if reference.reference.role != ReferenceRole::Original {
+            return None;
+        }
+        Some(ImageHash {
+            reference_id: reference.reference.uuid.clone(),
+            media_id: reference.media_id.clone(),
+            hash: reference.perceptual_hash?,
+            taken_at: self
+                .media
+                .iter()
+                .find(|m| m.item.uuid == reference.media_id)?
+                .item
+                .taken_at,
+        })
+    }
+
+    fn album_stats(&self, album_id: &str) -> AlbumStats {
+        let views = self
+            .album_views
+            .iter()
+            .filter(|v| v.album_id == album_id)
+            .collect::<Vec<_>>();
+
+        let mut viewers: Vec<ViewerEntry> = vec![];
+        for view in &views {
+            match viewers
+                .iter_mut()
+                .find(|e| e.viewer_id == view.viewer_id && e.viewer_role == view.viewer_role)
+            {
+                Some(entry) => entry.view_count += 1,
+                None => viewers.push(ViewerEntry {
+                    viewer_id: view.viewer_id.clone(),
+                    viewer_role: view.viewer_role.clone(),
+                    view_count: 1,
+                }),
+            }
+        }
+        viewers.sort_by_key(|x| std::cmp::Reverse(x.view_count));
+
+        let mut unique_viewers = views.iter().map(|v| &v.viewer_id).collect::<Vec<_>>();
+        unique_viewers.sort();
+        unique_viewers.dedup();
+
+        AlbumStats {
+            album_id: album_id.to_string(),
+            total_views: views.len() as i64,
+            unique_viewers: unique_viewers.len() as i64,
+            total_downloads: self
+                .media_downloads
+                .iter()
+                .filter(|(_, album)| album.as_deref() == Some(album_id))
+                .count() as i64,
+            viewers,
+        }
+    }
+
+    fn mfa_mut(&mut self, account_id: &str) -> Option<&mut AccountMfa> {
+        self.mfa.iter_mut().find(|m| m.account_id == account_id)
+    }
+
+    fn replace_recovery_codes(&mut self, account_id: &str, code_hashes: &[String]) {
+        self.recovery_codes.retain(|c| c.account_id != account_id);
+        self.recovery_codes
+            .extend(code_hashes.iter().map(|code_hash| RecoveryCode {
+                account_id: account_id.to_string(),
+                code_hash: code_hash.clone(),
+                used_at: None,
+            }));
+    }
+
+    fn job_mut(&mut self, job_id: &str) -> Option<&mut JobRecord> {
+        self.jobs.iter_mut().find(|j| j.job_id == job_id)
+    }
+}
+
+/// Order of the roles when looking for the primary file of a media item.
+fn role_rank(role: ReferenceRole) -> u8 {
+    match role {
+        ReferenceRole::Original => 0,
+        ReferenceRole::Raw => 1,
+        ReferenceRole::LiveVideo => 2,
+        ReferenceRole::Rendition => 3,
+        ReferenceRole::Sidecar => 4,
+    }
+}
+
+fn generate_access_code() -> String {
+    use rand::Rng;
+    const CHARSET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+    let mut rng = rand::thread_rng();
+    (0..8)
+        .map(|_| CHARSET[rng.gen_range(0..CHARSET.len())] as char)
+        .collect()
+}
+
+#[derive(Default)]
+pub struct MemoryDatabase {
+    state: Mutex<State>,
+}
+
+impl MemoryDatabase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+}
+
+#[async_trait]
+impl Database for MemoryDatabase {
+    async fn get_media_items(&self, user_id: &str) -> Result<Vec<MediaItem>> {
+        let state = self.state();
+        let mut items = state
+            .media
+            .iter()
+            .filter(|m| m.owner == user_id)
+            .map(|m| state.media_item(m))
+            .collect::<Vec<_>>();
+        items.sort_by(|a, b| b.taken_at.cmp(&a.taken_at).then_with(|| a.name.cmp(&b.name)));
+        Ok(items)
+    }
+
+    async fn delete_media_item(&self, media_id: &str) -> Result<()> {
+        let mut state = self.state();
+        state.placeholders.retain(|ph| ph.media != media_id);
+        state.video_details.retain(|vd| vd.media != media_id);
+        state.references.retain(|r| r.media_id != media_id);
+        state.album_media.retain(|am| am.media_id != media_id);
+        state.media.retain(|m| m.item.uuid != media_id);
+        Ok(())
+    }
+
+    async fn create_media_item(
+        &self,
+        user_id: &str,
+        name: &str,
+        date_taken: DateTime<Utc>,
+    ) -> Result<String> {
+        let mut state = self.state();
+        if let Some(existing) = state.media.iter().find(|m| {
+            m.owner == user_id && m.item.name == name && m.item.taken_at == Some(date_taken)
+        }) {
+            return Ok(existing.item.uuid.clone());
+        }
+
+        let uuid = Uuid::new_v4().hyphenated().to_string();
+        state.media.push(StoredMedia {
+            owner: user_id.to_string(),
+            item: MediaItem {
+                uuid: uuid.clone(),
+                name: name.to_string(),
+                added_at: Utc::now(),
+                taken_at: Some(date_taken),
+                details: None,
+                tags: None,
+                location: None,
+                references: None,
+                video: None,
+                placeholder: None,
+            },
+        });
+        Ok(uuid)
+    }
+
+    async fn get_media_item(&self, media_id: &str) -> Result<MediaItem> {
+        let state = self.state();
+        let media = state
+            .media
+            .iter()
+            .find(|m| m.item.uuid == media_id)
+            .ok_or_else(|| anyhow!("Media item {} not found", media_id))?;
+        let references = state
+            .references_of(media_id)
+            .into_iter()
+            .map(|r| r.reference.clone())
+            .collect();
+
+        Ok(MediaItem {
+            references: Some(references),
+            ..state.media_item(media)
+        })
+    }
+
+    async fn add_reference(
+        &self,
+        user_id: &str,
+        media_id: &str,
+        reference: &Reference,
+    ) -> Result<String> {
+        let id = Uuid::new_v4().hyphenated().to_string();
+        self.state().references.push(StoredReference {
+            media_id: media_id.to_string(),
+            owner: user_id.to_string(),
+            reference: Reference {
+                uuid: id.clone(),
+                ..reference.clone()
+            },
+            perceptual_hash: None,
+        });
+        Ok(id)
+    }
+
+    async fn update_reference(&self, reference_id: &str, reference: &Reference) -> Result<()> {
+        let mut state = self.state();
+        let stored = state
+            .references
+            .iter_mut()
+            .find(|r| r.reference.uuid == reference_id)
+            .ok_or_else(|| anyhow!("Reference {} not found", reference_id))?;
+        stored.reference = Reference {
+            uuid: reference_id.to_string(),
+            ..reference.clone()
+        };
+        Ok(())
+    }
+
+    async fn remove_reference(&self, media_id: &str, reference_id: &str) -> Result<()> {
+        self.state()
+            .references
+            .retain(|r| r.media_id != media_id || r.reference.uuid != reference_id);
+        Ok(())
+    }
+
+    async fn get_customer(&self, customer_id: &str) -> Result<Customer> {
+        self.state()
+            .customers
+            .iter()
+            .find(|c| c.customer_id == customer_id)
+            .cloned()
+            .ok_or_else(|| anyhow!("Customer {} not found", customer_id))
+    }
+
+    async fn create_customer(
+        &self,
+        customer_id: String,
+        access_code: String,
+        display_name: String,
+    ) -> Result<()> {
+        self.state()
+            .create_customer(customer_id, access_code, display_name)
+    }
+
+    async fn get_customer_by_access_code(&self, code: &str) -> Result<Customer> {
+        self.state()
+            .customers
+            .iter()
+            .find(|c| c.access_code == code)
+            .cloned()
+            .ok_or_else(|| anyhow!("No customer found for access code"))
+    }
+
+    async fn update_last_login_for_customer(&self, customer_id: &str) -> Result<()> {
+        if let Some(customer) = self
+            .state()
+            .customers
+            .iter_mut()
+            .find(|c| c.customer_id == customer_id)
+        {
+            customer.last_login_at = Some(Utc::now());
+        }
+        Ok(())
+    }
+
+    async fn list_customers(&self) -> Result<Vec<Customer>> {
+        let mut customers = self.state().customers.clone();
+        customers.reverse();
+        customers.sort_by_key(|x| std::cmp::Reverse(x.created_at));
+        Ok(customers)
+    }
+
+    async fn delete_customer(&self, customer_id: &str) -> Result<()> {
+        let mut state = self.state();
+        state.customers.retain(|c| c.customer_id != customer_id);
+        state.customer_albums.retain(|ca| ca.customer_id != customer_id);
+        state
+            .account_access_codes
+            .retain(|ac| ac.customer_id != customer_id);
+        state
+            .customer_items
+            .retain(|(customer, _, _)| customer != customer_id);
+        Ok(())
+    }
+
+    async fn set_customer_paid(&self, customer_id: &str, paid: bool) -> Result<()> {
+        if let Some(customer) = self
+            .state()
+            .customers
+            .iter_mut()
+            .find(|c| c.customer_id == customer_id)
+        {
+            customer.paid = paid;
+            customer.updated_at = Some(Utc::now());
+        }
+        Ok(())
+    }
+
+    async fn create_account(
+        &self,
+        account_id: String,
+        email: String,
+        password_hash: String,
+        display_name: Option<String>,
+    ) -> Result<()> {
+        let mut state = self.state();
+        if state.accounts.iter().any(|a| a.email == email) {
+            return Err(anyhow!("Account with email {} already exists", email));
+        }
+        state.accounts.push(Account {
+            account_id,
+            email,
+            password_hash,
+            display_name,
+            created_at: Utc::now(),
+            updated_at: None,
+            last_login_at: None,
+            is_admin: false,
+            email_verified_at: None,
+        });
+        Ok(())
+    }
+
+    async fn get_account_by_email(&self, email: &str) -> Result<Account> {
+        self.state()
+            .accounts
+            .iter()
+            .find(|a| a.email == email)
+            .cloned()
+            .ok_or_else(|| anyhow!("No account found for email"))
+    }
+
+    async fn get_account_by_id(&self, account_id: &str) -> Result<Account> {
+        self.state()
+            .accounts
+            .iter()
+            .find(|a| a.account_id == account_id)
+            .cloned()
+            .ok_or_else(|| anyhow!("No account found: {}", account_id))
+    }
+
+    async fn link_access_code_to_account(&self, account_id: &str, customer_id: &str) -> Result<()> {
+        let mut state = self.state();
+        if !state
+            .account_access_codes
+            .iter()
+            .any(|ac| ac.account_id == account_id && ac.customer_id == customer_id)
+        {
+            state.account_access_codes.push(AccountAccessCode {
+                account_id: account_id.to_string(),
+                customer_id: customer_id.to_string(),
+                linked_at: Utc::now(),
+            });
+        }
+        Ok(())
+    }
+
+    async fn get_customer_ids_for_account(&self, account_id: &str) -> Result<Vec<String>> {
+        Ok(self
+            .state()
+            .account_access_codes
+            .iter()
+            .filter(|ac| ac.account_id == account_id)
+            .map(|ac| ac.customer_id.clone())
+            .collect())
+    }
+
+    async fn get_albums_for_account(&self, account_id: &str) -> Result<Vec<Album>> {
+        let state = self.state();
+        let mut albums = state
+            .albums
+            .iter()
+            .map(|a| &a.album)
+            .filter(|album| {
+                !album.is_archived
+                    && (state
+                        .album_accounts
+                        .iter()
+                        .any(|aa| aa.album_id == album.album_id && aa.account_id == account_id)
+                        || state.account_access_codes.iter().any(|ac| {
+                            ac.account_id == account_id
+                                && state.customer_albums.iter().any(|ca| {
+                                    ca.customer_id == ac.customer_id
+                                        && ca.album_id == album.album_id
+                                })
+                        }))
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        albums.sort_by_key(|x| std::cmp::Reverse(x.created_at));
+        Ok(albums)
+    }
+
+    async fn update_last_login_for_account(&self, account_id: &str) -> Result<()> {
+        if let Some(account) = self.state().account_mut(account_id) {
+            account.last_login_at = Some(Utc::now());
+        }
+        Ok(())
+    }
+
+    async fn is_account_admin(&self, account_id: &str) -> Result<bool, sqlx::Error> {
+        Ok(self
+            .state()
+            .accounts
+            .iter()
+            .any(|a| a.account_id == account_id && a.is_admin))
+    }
+
+    async fn set_account_admin(&self, account_id: &str, is_admin: bool) -> Result<(), sqlx::Error> {
+        if let Some(account) = self.state().account_mut(account_id) {
+            account.is_admin = is_admin;
+        }
+        Ok(())
+    }
+
+    async fn grant_album_to_account(
+        &self,
+        account_id: &str,
+        album_id: &str,
+        role: &str,
+    ) -> Result<(), sqlx::Error> {
+        let mut state = self.state();
+        state
+            .album_accounts
+            .retain(|aa| aa.account_id != account_id || aa.album_id != album_id);
+        state.album_accounts.push(AlbumAccountEntry {
+            account_id: account_id.to_string(),
+            album_id: album_id.to_string(),
+            role: role.to_string(),
+        });
+        Ok(())
+    }
+
+    async fn revoke_album_from_account(
+        &self,
+        account_id: &str,
+        album_id: &str,
+    ) -> Result<(), sqlx::Error> {
+        self.state()
+            .album_accounts
+            .retain(|aa| aa.account_id != account_id || aa.album_id != album_id);
+        Ok(())
+    }
+
+    async fn get_album_account_role(
+        &self,
+        account_id: &str,
+        album_id: &str,
+    ) -> Result<Option<String>, sqlx::Error> {
+        Ok(self
+            .state()
+            .album_accounts
+            .iter()
+            .find(|aa| aa.account_id == account_id && aa.album_id == album_id)
+            .map(|aa| aa.role.clone()))
+    }
+
+    async fn list_accounts_for_album(
+        &self,
+        album_id: &str,
+    ) -> Result<Vec<AlbumAccountEntry>, sqlx::Error> {
+        Ok(self
+            .state()
+            .album_accounts
+            .iter()
+            .filter(|aa| aa.album_id == album_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_albums_owned_by_account(
+        &self,
+        account_id: &str,
+    ) -> Result<Vec<Album>, sqlx::Error> {
+        let state = self.state();
+        let mut albums = state
+            .albums
+            .iter()
+            .map(|a| &a.album)
+            .filter(|album| {
+                state.album_accounts.iter().any(|aa| {
+                    aa.album_id == album.album_id
+                        && aa.account_id == account_id
+                        && aa.role == "owner"
+                })
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        albums.sort_by_key(|x| std::cmp::Reverse(x.created_at));
+        Ok(albums)
+    }
+
+    async fn list_all_accounts(&self) -> Result<Vec<Account>, sqlx::Error> {
+        let mut accounts = self.state().accounts.clone();
+        accounts.sort_by_key(|x| x.created_at);
+        Ok(accounts)
+    }
+
+    async fn list_all_albums(&self) -> Result<Vec<Album>, sqlx::Error> {
+        let mut albums = self
+            .state()
+            .albums
+            .iter()
+            .map(|a| a.album.clone())
+            .collect::<Vec<_>>();
+        albums.sort_by_key(|x| std::cmp::Reverse(x.created_at));
+        Ok(albums)
+    }
+
+    async fn get_accounts_with_albums(&self) -> Result<Vec<AccountWithAlbums>, sqlx::Error> {
+        let state = self.state();
+        let mut map: BTreeMap<String, AccountWithAlbums> = BTreeMap::new();
+
+        for account in &state.accounts {
+            let mut albums = state
+                .album_accounts
+                .iter()
+                .filter(|aa| aa.account_id == account.account_id)
+                .map(|aa| AlbumRef {
+                    album_id: aa.album_id.clone(),
+                    album_name: state
+                        .album(&aa.album_id)
+                        .map(|a| a.album.name.clone())
+                        .unwrap_or_default(),
+                    role: aa.role.clone(),
+                })
+                .collect::<Vec<_>>();
+            albums.sort_by(|a, b| a.role.cmp(&b.role));
+
+            map.insert(
+                account.account_id.clone(),
+                AccountWithAlbums {
+                    account_id: account.account_id.clone(),
+                    email: account.email.clone(),
+                    display_name: account.display_name.clone(),
+                    last_login_at: account.last_login_at,
+                    is_admin: account.is_admin,
+                    albums,
+                },
+            );
+        }
+
+        Ok(map.into_values().collect())
+    }
+
+    async fn create_album(
+        &self,
+        owner_id: &str,
+        name: &str,
+        description: Option<&str>,
+    ) -> Result<String> {
+        let album_id = Uuid::new_v4().hyphenated().to_string();
+        let now = Utc::now();
+        let mut state = self.state();
+        state.albums.push(StoredAlbum {
+            album: Album {
+                album_id: album_id.clone(),
+                owner: owner_id.to_string(),
+                name: name.to_string(),
+                description: description.map(str::to_string),
+                cover_media_id: None,
+                is_archived: false,
+                created_at: now,
+                updated_at: now,
+            },
+            metadata_policy: MetadataPolicy::Keep,
+        });
+        state.album_accounts.push(AlbumAccountEntry {
+            account_id: owner_id.to_string(),
+            album_id: album_id.clone(),
+            role: "owner".to_string(),
+        });
+        Ok(album_id)
+    }
+
+    async fn get_albums_for_user(&self, owner_id: &str) -> Result<Vec<Album>> {
+        let mut albums = self
+            .state()
+            .albums
+            .iter()
+            .filter(|a| a.album.owner == owner_id)
+            .map(|a| a.album.clone())
+            .collect::<Vec<_>>();
+        albums.sort_by_key(|x| std::cmp::Reverse(x.created_at));
+        Ok(albums)
+    }
+
+    async fn get_album(&self, album_id: &str) -> Result<Album> {
+        self.state()
+            .album(album_id)
+            .map(|a| a.album.clone())
+            .ok_or_else(|| anyhow!("Album not found: {}", album_id))
+    }
+
+    async fn update_album(
+        &self,
+        album_id: &str,
+        name: Option<&str>,
+        description: Option<&str>,
+    ) -> Result<()> {
+        let mut state = self.state();
+        if let Some(stored) = state.albums.iter_mut().find(|a| a.album.album_id == album_id) {
+            if let Some(name) = name {
+                stored.album.name = name.to_string();
+                stored.album.updated_at = Utc::now();
+            }
+            if let Some(description) = description {
+                stored.album.description = Some(description.to_string());
+                stored.album.updated_at = Utc::now();
+            }
+        }
+        Ok(())
+    }
+
+    async fn delete_album(&self, album_id: &str) -> Result<()> {
+        let mut state = self.state();
+        state.albums.retain(|a| a.album.album_id != album_id);
+        state.album_media.retain(|am| am.album_id != album_id);
+        state.customer_albums.retain(|ca| ca.album_id != album_id);
+        state.album_accounts.retain(|aa| aa.album_id != album_id);
+        state
+            .customer_items
+            .retain(|(_, album, _)| album != album_id);
+        state.share_links.retain(|l| l.album_id != album_id);
+        state.watermarks.retain(|w| w.album_id != album_id);
+        state.album_views.retain(|v| v.album_id != album_id);
+        Ok(())
+    }
+
+    async fn get_album_metadata_policy(&self, album_id: &str) -> Result<MetadataPolicy> {
+        self.state()
+            .album(album_id)
+            .map(|a| a.metadata_policy)
+            .ok_or_else(|| anyhow!("Album not found: {}", album_id))
+    }
+
+    async fn set_album_metadata_policy(&self, album_id: &str, policy: MetadataPolicy) -> Result<()> {
+        if let Some(stored) = self
+            .state()
+            .albums
+            .iter_mut()
+            .find(|a| a.album.album_id == album_id)
+        {
+            stored.metadata_policy = policy;
+            stored.album.updated_at = Utc::now();
+        }
+        Ok(())
+    }
+
+    async fn get_metadata_policy_for_media(
+        &self,
+        media_id: &str,
+    ) -> Result<Option<(String, MetadataPolicy)>> {
+        let state = self.state();
+        Ok(state
+            .albums
+            .iter()
+            .filter(|a| {
+                a.metadata_policy != MetadataPolicy::Keep
+                    && state.media_in_album(&a.album.album_id, media_id)
+            })
+            .map(|a| (a.album.album_id.clone(), a.metadata_policy))
+            .max_by_key(|(_, policy)| *policy))
+    }
+
+    async fn get_album_ids_for_media(&self, media_id: &str) -> Result<Vec<String>> {
+        let state = self.state();
+        let mut memberships = state
+            .album_media
+            .iter()
+            .filter(|am| am.media_id == media_id)
+            .collect::<Vec<_>>();
+        memberships.sort_by(|a, b| {
+            a.added_at
+                .cmp(&b.added_at)
+                .then_with(|| a.album_id.cmp(&b.album_id))
+        });
+        Ok(memberships.iter().map(|am| am.album_id.clone()).collect())
+    }
+
+    async fn add_media_to_album(&self, album_id: &str, media_id: &str) -> Result<()> {
+        let mut state = self.state();
+        if !state.media_in_album(album_id, media_id) {
+            state.album_media.push(AlbumMedia {
+                album_id: album_id.to_string(),
+                media_id: media_id.to_string(),
+                added_at: Utc::now(),
+            });
+        }
+        Ok(())
+    }
+
+    async fn remove_media_from_album(&self, album_id: &str, media_id: &str) -> Result<()> {
+        self.state()
+            .album_media
+            .retain(|am| am.album_id != album_id || am.media_id != media_id);
+        Ok(())
+    }
+
+    async fn get_media_for_album(&self, album_id: &str) -> Result<Vec<MediaItem>> {
+        let state = self.state();
+        let mut items = state
+            .media
+            .iter()
+            .filter(|m| state.media_in_album(album_id, &m.item.uuid))
+            .map(|m| state.media_item(m))
+            .collect::<Vec<_>>();
+        items.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(items)
+    }
+
+    async fn assign_album_to_customer(&self, album_id: &str, customer_id: &str) -> Result<()> {
+        self.state().assign_album_to_customer(album_id, customer_id);
+        Ok(())
+    }
+
+    async fn unassign_album_from_customer(
+        &self,
+        album_id: &str,
+        customer_id: &str,
+    ) -> Result<()> {
+        self.state()
+            .customer_albums
+            .retain(|ca| ca.album_id != album_id || ca.customer_id != customer_id);
+        Ok(())
+    }
+
+    async fn get_albums_for_customer(&self, customer_id: &str) -> Result<Vec<Album>> {
+        let state = self.state();
+        let mut assignments = state
+            .customer_albums
+            .iter()
+            .filter(|ca| ca.customer_id == customer_id)
+            .collect::<Vec<_>>();
+        assignments.sort_by_key(|x| std::cmp::Reverse(x.assigned_at));
+        Ok(assignments
+            .iter()
+            .filter_map(|ca| state.album(&ca.album_id))
+            .filter(|a| !a.album.is_archived)
+            .map(|a| a.album.clone())
+            .collect())
+    }
+
+    async fn set_customer_album_items(
+        &self,
+        customer_id: &str,
+        album_id: &str,
+        media_ids: &[&str],
+    ) -> Result<()> {
+        let mut state = self.state();
+        state
+            .customer_items
+            .retain(|(customer, album, _)| customer != customer_id || album != album_id);
+        for media_id in media_ids {
+            state.customer_items.push((
+                customer_id.to_string(),
+                album_id.to_string(),
+                media_id.to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    async fn get_customer_album_items(
+        &self,
+        customer_id: &str,
+        album_id: &str,
+    ) -> Result<Vec<String>> {
+        Ok(self
+            .state()
+            .customer_items
+            .iter()
+            .filter(|(customer, album, _)| customer == customer_id && album == album_id)
+            .map(|(_, _, media)| media.clone())
+            .collect())
+    }
+
+    async fn get_media_file_path(&self, media_id: &str) -> Result<Option<(String, String)>> {
+        Ok(self
+            .state()
+            .references_of(media_id)
+            .first()
+            .map(|r| (r.reference.filepath.clone(), r.reference.filename.clone())))
+    }
+
+    async fn get_reference_files(&self, media_id: &str) -> Result<Vec<ReferenceFile>> {
+        Ok(self
+            .state()
+            .references_of(media_id)
+            .iter()
+            .map(|r| ReferenceFile {
+                filepath: r.reference.filepath.clone(),
+                filename: r.reference.filename.clone(),
+                mime_type: r.reference.mime_type.clone(),
+                role: r.reference.role,
+                content_hash: r.reference.content_hash.clone(),
+            })
+            .collect())
+    }
+
+    async fn find_album_media_by_basename(
+        &self,
+        album_id: &str,
+        basename: &str,
+    ) -> Result<Option<String>> {
+        let state = self.state();
+        Ok(state
+            .references
+            .iter()
+            .find(|r| {
+                state.media_in_album(album_id, &r.media_id)
+                    && reference::basename(&r.reference.filename).eq_ignore_ascii_case(basename)
+            })
+            .map(|r| r.media_id.clone()))
+    }
+
+    async fn find_owned_media_by_basename(
+        &self,
+        owner_id: &str,
+        basename: &str,
+    ) -> Result<Option<String>> {
+        Ok(self
+            .state()
+            .references
+            .iter()
+            .find(|r| {
+                r.owner == owner_id
+                    && reference::basename(&r.reference.filename).eq_ignore_ascii_case(basename)
+            })
+            .map(|r| r.media_id.clone()))
+    }
+
+    async fn set_reference_role(
+        &self,
+        media_id: &str,
+        filename: &str,
+        role: ReferenceRole,
+    ) -> Result<()> {
+        for stored in self
+            .state()
+            .references
+            .iter_mut()
+            .filter(|r| r.media_id == media_id && r.reference.filename == filename)
+        {
+            stored.reference.role = role;
+        }
+        Ok(())
+    }
+
+    async fn get_media_mime_type(&self, media_id: &str) -> Result<Option<String>> {
+        Ok(self
+            .state()
+            .references_of(media_id)
+            .first()
+            .and_then(|r| r.reference.mime_type.clone()))
+    }
+
+    async fn upsert_video_details(&self, details: &VideoDetails) -> Result<()> {
+        let mut state = self.state();
+        state.video_details.retain(|vd| vd.media != details.media);
+        state.video_details.push(details.clone());
+        Ok(())
+    }
+
+    async fn get_video_details(&self, media_id: &str) -> Result<Option<VideoDetails>> {
+        Ok(self
+            .state()
+            .video_details
+            .iter()
+            .find(|vd| vd.media == media_id)
+            .cloned())
+    }
+
+    async fn delete_video_details(&self, media_id: &str) -> Result<()> {
+        self.state().video_details.retain(|vd| vd.media != media_id);
+        Ok(())
+    }
+
+    async fn set_perceptual_hash(&self, reference_id: &str, hash: i64) -> Result<()> {
+        if let Some(reference) = self
+            .state()
+            .references
+            .iter_mut()
+            .find(|r| r.reference.uuid == reference_id)
+        {
+            reference.perceptual_hash = Some(hash);
+        }
+        Ok(())
+    }
+
+    async fn get_image_hashes_for_owner(&self, owner_id: &str) -> Result<Vec<ImageHash>> {
+        let state = self.state();
+        Ok(state
+            .references
+            .iter()
+            .filter(|r| r.owner == owner_id)
+            .filter_map(|r| state.image_hash(r))
+            .collect())
+    }
+
+    async fn get_image_hashes_for_album(&self, album_id: &str) -> Result<Vec<ImageHash>> {
+        let state = self.state();
+        Ok(state
+            .references
+            .iter()
+            .filter(|r| state.media_in_album(album_id, &r.media_id))
+            .filter_map(|r| state.image_hash(r))
+            .collect())
+    }
+
+    async fn get_unindexed_image_references(&self) -> Result<Vec<UnindexedReference>> {
+        let state = self.state();
+        Ok(state
+            .references
+            .iter()
+            .filter(|r| {
+                r.perceptual_hash.is_none()
+                    || !state.placeholders.iter().any(|ph| ph.media == r.media_id)
+            })
+            .filter(|r| r.reference.role == ReferenceRole::Original)
+            .filter_map(|r| {
+                let mime_type = r.reference.mime_type.clone()?;
+                mime_type.starts_with("image/").then(|| UnindexedReference {
+                    reference_id: r.reference.uuid.clone(),
+                    media_id: r.media_id.clone(),
+                    filepath: r.reference.filepath.clone(),
+                    filename: r.reference.filename.clone(),
+                    mime_type,
+                })
+            })
+            .collect())
+    }
+
+    async fn upsert_placeholder(&self, placeholder: &Placeholder) -> Result<()> {
+        let mut state = self.state();
+        state.placeholders.retain(|ph| ph.media != placeholder.media);
+        state.placeholders.push(placeholder.clone());
+        Ok(())
+    }
+
+    async fn insert_job(&self, job: &JobRecord) -> Result<()> {
+        let mut state = self.state();
+        if state.jobs.iter().any(|j| j.job_id == job.job_id) {
+            return Err(anyhow!("Job {} already exists", job.job_id));
+        }
+        state.jobs.push(job.clone());
+        Ok(())
+    }
+
+    async fn find_active_job(&self, dedupe_key: &str) -> Result<Option<JobRecord>> {
+        Ok(self
+            .state()
+            .jobs
+            .iter()
+            .filter(|j| {
+                j.dedupe_key.as_deref() == Some(dedupe_key)
+                    && matches!(j.status, JobStatus::Queued | JobStatus::Running)
+            })
+            .min_by_key(|j| j.created_at)
+            .cloned())
+    }
+
+    async fn reschedule_job(&self, job_id: &str, run_at: DateTime<Utc>) -> Result<()> {
+        if let Some(job) = self.state().job_mut(job_id) {
+            if job.status == JobStatus::Queued {
+                job.run_at = run_at;
+                job.updated_at = run_at;
+            }
+        }
+        Ok(())
+    }
+
+    async fn get_due_jobs(
+        &self,
+        kind: &str,
+        now: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<JobRecord>> {
+        let mut jobs = self
+            .state()
+            .jobs
+            .iter()
+            .filter(|j| j.status == JobStatus::Queued && j.kind == kind && j.run_at <= now)
+            .cloned()
+            .collect::<Vec<_>>();
+        jobs.sort_by_key(|x| x.run_at);
+        jobs.truncate(usize::try_from(limit).unwrap_or(0));
+        Ok(jobs)
+    }
+
+    async fn claim_job(&self, job_id: &str, now: DateTime<Utc>) -> Result<bool> {
+        match self.state().job_mut(job_id) {
+            Some(job) if job.status == JobStatus::Queued => {
+                job.status = JobStatus::Running;
+                job.attempts += 1;
+                job.updated_at = now;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn complete_job(&self, job_id: &str) -> Result<()> {
+        self.state().jobs.retain(|j| j.job_id != job_id);
+        Ok(())
+    }
+
+    async fn fail_job(
+        &self,
+        job_id: &str,
+        error: &str,
+        retry_at: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> Result<()> {
+        if let Some(job) = self.state().job_mut(job_id) {
+            job.status = if retry_at.is_some() {
+                JobStatus::Queued
+            } else {
+                JobStatus::Failed
+            };
+            job.run_at = retry_at.unwrap_or(job.run_at);
+            job.last_error = Some(error.to_string());
+            job.updated_at = now;
+        }
+        Ok(())
+    }
+
+    async fn requeue_running_jobs(&self, now: DateTime<Utc>) -> Result<u64> {
+        let mut requeued = 0;
+        for job in self
+            .state()
+            .jobs
+            .iter_mut()
+            .filter(|j| j.status == JobStatus::Running)
+        {
+            job.status = JobStatus::Queued;
+            job.run_at = now;
+            job.updated_at = now;
+            requeued += 1;
+        }
+        Ok(requeued)
+    }
+
+    async fn retry_failed_job(&self, job_id: &str, now: DateTime<Utc>) -> Result<bool> {
+        match self.state().job_mut(job_id) {
+            Some(job) if job.status == JobStatus::Failed => {
+                job.status = JobStatus::Queued;
+                job.attempts = 0;
+                job.run_at = now;
+                job.updated_at = now;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn list_jobs(&self, status: Option<JobStatus>) -> Result<Vec<JobRecord>> {
+        let mut jobs = self
+            .state()
+            .jobs
+            .iter()
+            .filter(|j| status.is_none_or(|status| j.status == status))
+            .cloned()
+            .collect::<Vec<_>>();
+        jobs.sort_by_key(|x| x.run_at);
+        Ok(jobs)
+    }
+
+    async fn create_download(&self, download: &Download) -> Result<()> {
+        self.state().downloads.push(download.clone());
+        Ok(())
+    }
+
+    async fn get_download(&self, download_id: &str) -> Result<Option<Download>> {
+        Ok(self
+            .state()
+            .downloads
+            .iter()
+            .find(|d| d.download_id == download_id)
+            .cloned())
+    }
+
+    async fn delete_expired_downloads(&self, now: DateTime<Utc>) -> Result<u64> {
+        let mut state = self.state();
+        let before = state.downloads.len();
+        state.downloads.retain(|d| d.expires_at > now);
+        Ok((before - state.downloads.len()) as u64)
+    }
+
+    async fn create_session(&self, session: &Session) -> Result<()> {
+        self.state().sessions.push(session.clone());
+        Ok(())
+    }
+
+    async fn get_session(&self, session_id: &str) -> Result<Option<Session>> {
+        Ok(self
+            .state()
+            .sessions
+            .iter()
+            .find(|s| s.session_id == session_id)
+            .cloned())
+    }
+
+    async fn get_session_by_refresh_token(&self, token_hash: &str) -> Result<Option<Session>> {
+        Ok(self
+            .state()
+            .sessions
+            .iter()
+            .find(|s| {
+                s.refresh_token_hash == token_hash
+                    || s.previous_token_hash.as_deref() == Some(token_hash)
+            })
+            .cloned())
+    }
+
+    async fn rotate_session_token(
+        &self,
+        session_id: &str,
+        current_hash: &str,
+        new_hash: &str,
+        client: &SessionClient,
+        now: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool> {
+        let mut state = self.state();
+        let Some(session) = state.sessions.iter_mut().find(|s| {
+            s.session_id == session_id
+                && s.refresh_token_hash == current_hash
+                && s.revoked_at.is_none()
+        }) else {
+            return Ok(false);
+        };
+        session.previous_token_hash = Some(current_hash.to_string());
+        session.refresh_token_hash = new_hash.to_string();
+        session.user_agent = client.user_agent.clone().or(session.user_agent.take());
+        session.ip_address = client.ip_address.clone().or(session.ip_address.take());
+        session.last_used_at = now;
+        session.expires_at = expires_at;
+        Ok(true)
+    }
+
+    async fn revoke_session(&self, session_id: &str, now: DateTime<Utc>) -> Result<bool> {
+        let mut state = self.state();
+        match state
+            .sessions
+            .iter_mut()
+            .find(|s| s.session_id == session_id && s.revoked_at.is_none())
+        {
+            Some(session) => {
+                session.revoked_at = Some(now);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn list_sessions(
+        &self,
+        subject_id: &str,
+        role: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<Session>> {
+        let mut sessions = self
+            .state()
+            .sessions
+            .iter()
+            .filter(|s| s.subject_id == subject_id && s.role == role && s.is_active(now))
+            .cloned()
+            .collect::<Vec<_>>();
+        sessions.sort_by_key(|x| std::cmp::Reverse(x.last_used_at));
+        Ok(sessions)
+    }
+
+    async fn list_revoked_session_ids(&self, since: DateTime<Utc>) -> Result<Vec<String>> {
+        Ok(self
+            .state()
+            .sessions
+            .iter()
+            .filter(|s| s.revoked_at.is_some_and(|revoked_at| revoked_at > since))
+            .map(|s| s.session_id.clone())
+            .collect())
+    }
+
+    async fn delete_expired_sessions(&self, now: DateTime<Utc>) -> Result<u64> {
+        let mut state = self.state();
+        let before = state.sessions.len();
+        state.sessions.retain(|s| s.expires_at > now);
+        Ok((before - state.sessions.len()) as u64)
+    }
+
+    async fn get_linked_account(&self, provider: &str, subject: &str) -> Result<Option<String>> {
+        Ok(self
+            .state()
+            .identities
+            .iter()
+            .find(|i| i.provider == provider && i.subject == subject)
+            .map(|i| i.account_id.clone()))
+    }
+
+    async fn link_external_identity(
+        &self,
+        provider: &str,
+        subject: &str,
+        account_id: &str,
+        _email: Option<&str>,
+        _now: DateTime<Utc>,
+    ) -> Result<()> {
+        let mut state = self.state();
+        if !state
+            .identities
+            .iter()
+            .any(|i| i.provider == provider && i.subject == subject)
+        {
+            state.identities.push(ExternalIdentity {
+                provider: provider.to_string(),
+                subject: subject.to_string(),
+                account_id: account_id.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    async fn get_account_mfa(&self, account_id: &str) -> Result<Option<AccountMfa>> {
+        Ok(self
+            .state()
+            .mfa
+            .iter()
+            .find(|m| m.account_id == account_id)
+            .cloned())
+    }
+
+    async fn set_pending_totp_secret(
+        &self,
+        account_id: &str,
+        secret: &str,
+        now: DateTime<Utc>,
+    ) -> Result<()> {
+        let mut state = self.state();
+        match state.mfa_mut(account_id) {
+            Some(mfa) if mfa.enabled_at.is_some() => {}
+            Some(mfa) => {
+                mfa.totp_secret = secret.to_string();
+                mfa.created_at = now;
+            }
+            None => state.mfa.push(AccountMfa {
+                account_id: account_id.to_string(),
+                totp_secret: secret.to_string(),
+                enabled_at: None,
+                last_used_step: None,
+                created_at: now,
+                failed_attempts: 0,
+                locked_until: None,
+            }),
+        }
+        Ok(())
+    }
+
+    async fn enable_totp(
+        &self,
+        account_id: &str,
+        step: i64,
+        recovery_code_hashes: &[String],
+        now: DateTime<Utc>,
+    ) -> Result<()> {
+        let mut state = self.state();
+        if let Some(mfa) = state.mfa_mut(account_id) {
+            mfa.enabled_at = Some(now);
+            mfa.last_used_step = Some(step);
+        }
+        state.replace_recovery_codes(account_id, recovery_code_hashes);
+        Ok(())
+    }
+
+    async fn delete_account_mfa(&self, account_id: &str) -> Result<()> {
+        let mut state = self.state();
+        state.recovery_codes.retain(|c| c.account_id != account_id);
+        state.mfa.retain(|m| m.account_id != account_id);
+        Ok(())
+    }
+
+    async fn use_totp_step(&self, account_id: &str, step: i64) -> Result<bool> {
+        match self.state().mfa_mut(account_id) {
+            Some(mfa) if mfa.last_used_step.is_none_or(|used| used < step) => {
+                mfa.last_used_step = Some(step);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn replace_recovery_codes(&self, account_id: &str, code_hashes: &[String]) -> Result<()> {
+        self.state()
+            .replace_recovery_codes(account_id, code_hashes);
+        Ok(())
+    }
+
+    async fn use_recovery_code(
+        &self,
+        account_id: &str,
+        code_hash: &str,
+        now: DateTime<Utc>,
+    ) -> Result<bool> {
+        match self.state().recovery_codes.iter_mut().find(|c| {
+            c.account_id == account_id && c.code_hash == code_hash && c.used_at.is_none()
+        }) {
+            Some(code) => {
+                code.used_at = Some(now);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn count_unused_recovery_codes(&self, account_id: &str) -> Result<i64> {
+        Ok(self
+            .state()
+            .recovery_codes
+            .iter()
+            .filter(|c| c.account_id == account_id && c.used_at.is_none())
+            .count() as i64)
+    }
+
+    async fn record_mfa_failure(
+        &self,
+        account_id: &str,
+        max_failures: i64,
+        locked_until: DateTime<Utc>,
+    ) -> Result<()> {
+        if let Some(mfa) = self.state().mfa_mut(account_id) {
+            mfa.failed_attempts += 1;
+            if mfa.failed_attempts >= max_failures {
+                mfa.locked_until = Some(locked_until);
+            }
+        }
+        Ok(())
+    }
+
+    async fn reset_mfa_failures(&self, account_id: &str) -> Result<()> {
+        if let Some(mfa) = self.state().mfa_mut(account_id) {
+            mfa.failed_attempts = 0;
+            mfa.locked_until = None;
+        }
+        Ok(())
+    }
+
+    async fn create_passkey(&self, passkey: &Passkey) -> Result<()> {
+        let mut state = self.state();
+        if state
+            .passkeys
+            .iter()
+            .any(|p| p.credential_id == passkey.credential_id)
+        {
+            return Err(anyhow!("Passkey {} already exists", passkey.credential_id));
+        }
+        state.passkeys.push(passkey.clone());
+        Ok(())
+    }
+
+    async fn get_passkey(&self, credential_id: &str) -> Result<Option<Passkey>> {
+        Ok(self
+            .state()
+            .passkeys
+            .iter()
+            .find(|p| p.credential_id == credential_id)
+            .cloned())
+    }
+
+    async fn list_passkeys(&self, account_id: &str) -> Result<Vec<Passkey>> {
+        let mut passkeys = self
+            .state()
+            .passkeys
+            .iter()
+            .filter(|p| p.account_id == account_id)
+            .cloned()
+            .collect::<Vec<_>>();
+        passkeys.sort_by_key(|x| x.created_at);
+        Ok(passkeys)
+    }
+
+    async fn update_passkey_usage(
+        &self,
+        credential_id: &str,
+        sign_count: i64,
+        now: DateTime<Utc>,
+    ) -> Result<()> {
+        if let Some(passkey) = self
+            .state()
+            .passkeys
+            .iter_mut()
+            .find(|p| p.credential_id == credential_id)
+        {
+            passkey.sign_count = sign_count;
+            passkey.last_used_at = Some(now);
+        }
+        Ok(())
+    }
+
+    async fn delete_passkey(&self, account_id: &str, credential_id: &str) -> Result<bool> {
+        let mut state = self.state();
+        let before = state.passkeys.len();
+        state
+            .passkeys
+            .retain(|p| p.account_id != account_id || p.credential_id != credential_id);
+        Ok(state.passkeys.len() < before)
+    }
+
+    async fn set_account_password(
+        &self,
+        account_id: &str,
+        password_hash: &str,
+        now: DateTime<Utc>,
+    ) -> Result<()> {
+        if let Some(account) = self.state().account_mut(account_id) {
+            account.password_hash = password_hash.to_string();
+            account.updated_at = Some(now);
+        }
+        Ok(())
+    }
+
+    async fn set_email_verified(&self, account_id: &str, now: DateTime<Utc>) -> Result<()> {
+        if let Some(account) = self.state().account_mut(account_id) {
+            account.email_verified_at = Some(now);
+        }
+        Ok(())
+    }
+
+    async fn create_account_token(&self, token: &AccountToken) -> Result<()> {
+        self.state().account_tokens.push(token.clone());
+        Ok(())
+    }
+
+    async fn use_account_token(
+        &self,
+        token_hash: &str,
+        purpose: TokenPurpose,
+        now: DateTime<Utc>,
+    ) -> Result<Option<AccountToken>> {
+        Ok(self
+            .state()
+            .account_tokens
+            .iter_mut()
+            .find(|t| {
+                t.token_hash == token_hash
+                    && t.purpose == purpose.as_str()
+                    && t.used_at.is_none()
+                    && t.expires_at > now
+            })
+            .map(|token| {
+                token.used_at = Some(now);
+                token.clone()
+            }))
+    }
+
+    async fn delete_account_tokens(&self, account_id: &str, purpose: TokenPurpose) -> Result<()> {
+        self.state()
+            .account_tokens
+            .retain(|t| t.account_id != account_id || t.purpose != purpose.as_str());
+        Ok(())
+    }
+
+    async fn get_studio_of_account(&self, account_id: &str) -> Result<Option<String>> {
+        let state = self.state();
+        let mut candidates = state
+            .account_access_codes
+            .iter()
+            .filter(|ac| ac.account_id == account_id)
+            .flat_map(|ac| {
+                state
+                    .customer_albums
+                    .iter()
+                    .filter(move |ca| ca.customer_id == ac.customer_id)
+                    .filter_map(|ca| {
+                        let album = state.album(&ca.album_id)?;
+                        Some((ac.linked_at, ca.assigned_at, album.album.owner.clone()))
+                    })
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_by_key(|x| (x.0, x.1));
+        Ok(candidates.into_iter().next().map(|(_, _, owner)| owner))
+    }
+
+    async fn record_album_view(
+        &self,
+        album_id: &str,
+        viewer_id: &str,
+        viewer_role: &str,
+    ) -> Result<()> {
+        self.state().album_views.push(AlbumView {
+            album_id: album_id.to_string(),
+            viewer_id: viewer_id.to_string(),
+            viewer_role: viewer_role.to_string(),
+        });
+        Ok(())
+    }
+
+    async fn record_media_download(
+        &self,
+        media_id: &str,
+        album_id: Option<&str>,
+        _downloader_id: &str,
+        _downloader_role: &str,
+    ) -> Result<()> {
+        self.state()
+            .media_downloads
+            .push((media_id.to_string(), album_id.map(str::to_string)));
+        Ok(())
+    }
+
+    async fn get_album_stats(&self, album_id: &str) -> Result<AlbumStats> {
+        Ok(self.state().album_stats(album_id))
+    }
+
+    async fn get_stats_for_owned_albums(&self, account_id: &str) -> Result<Vec<AlbumStats>> {
+        let state = self.state();
+        Ok(state
+            .album_accounts
+            .iter()
+            .filter(|aa| aa.account_id == account_id && aa.role == "owner")
+            .map(|aa| state.album_stats(&aa.album_id))
+            .collect())
+    }
+
+    async fn get_access_codes_for_album(&self, album_id: &str) -> Result<Vec<AlbumCodeEntry>> {
+        let state = self.state();
+        let mut entries = state
+            .customer_albums
+            .iter()
+            .filter(|ca| ca.album_id == album_id)
+            .filter_map(|ca| {
+                state
+                    .customers
+                    .iter()
+                    .find(|c| c.customer_id == ca.customer_id)
+            })
+            .map(|c| AlbumCodeEntry {
+                access_code: c.access_code.clone(),
+                display_name: c.display_name.clone(),
+                customer_id: c.customer_id.clone(),
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by(|a, b| {
+            (&a.display_name, &a.access_code).cmp(&(&b.display_name, &b.access_code))
+        });
+        Ok(entries)
+    }
+
+    async fn generate_and_assign_code(&self, album_id: &str, display_name: &str) -> Result<String> {
+        let customer_id = Uuid::new_v4().hyphenated().to_string();
+        let access_code = generate_access_code();
+        let mut state = self.state();
+        state.create_customer(
+            customer_id.clone(),
+            access_code.clone(),
+            display_name.to_string(),
+        )?;
+        state.assign_album_to_customer(album_id, &customer_id);
+        Ok(access_code)
+    }
+
+    async fn generate_code(&self, display_name: &str) -> Result<String> {
+        let customer_id = Uuid::new_v4().hyphenated().to_string();
+        let access_code = generate_access_code();
+        self.state().create_customer(
+            customer_id,
+            access_code.clone(),
+            display_name.to_string(),
+        )?;
+        Ok(access_code)
+    }
+
+    async fn create_share_link(&self, link: &ShareLink) -> Result<()> {
+        self.state().share_links.push(link.clone());
+        Ok(())
+    }
+
+    async fn get_share_link(&self, token: &str) -> Result<Option<ShareLink>> {
+        Ok(self
+            .state()
+            .share_links
+            .iter()
+            .find(|l| l.token == token)
+            .cloned())
+    }
+
+    async fn list_share_links_for_album(&self, album_id: &str) -> Result<Vec<ShareLink>> {
+        let mut links = self
+            .state()
+            .share_links
+            .iter()
+            .filter(|l| l.album_id == album_id)
+            .cloned()
+            .collect::<Vec<_>>();
+        links.reverse();
+        links.sort_by_key(|x| std::cmp::Reverse(x.created_at));
+        Ok(links)
+    }
+
+    async fn revoke_share_links(&self, album_id: &str, token: Option<&str>) -> Result<u64> {
+        let mut state = self.state();
+        let before = state.share_links.len();
+        state
+            .share_links
+            .retain(|l| l.album_id != album_id || token.is_some_and(|t| l.token != t));
+        Ok((before - state.share_links.len()) as u64)
+    }
+
+    async fn get_album_watermark(&self, album_id: &str) -> Result<Option<AlbumWatermark>> {
+        Ok(self
+            .state()
+            .watermarks
+            .iter()
+            .find(|w| w.album_id == album_id)
+            .cloned())
+    }
+
+    async fn set_album_watermark(&self, watermark: &AlbumWatermark) -> Result<()> {
+        let mut state = self.state();
+        state.watermarks.retain(|w| w.album_id != watermark.album_id);
+        state.watermarks.push(watermark.clone());
+        Ok(())
+    }
+
+    async fn delete_album_watermark(&self, album_id: &str) -> Result<()> {
+        self.state().watermarks.retain(|w| w.album_id != album_id);
+        Ok(())
+    }
+
+    async fn get_copyright_template(
+        &self,
+        scope: CopyrightScope,
+        owner_id: &str,
+    ) -> Result<Option<CopyrightTemplate>> {
+        Ok(self
+            .state()
+            .copyright_templates
+            .iter()
+            .find(|(s, owner, _)| *s == scope && owner == owner_id)
+            .map(|(_, _, template)| template.clone()))
+    }
+
+    async fn set_copyright_template(
+        &self,
+        scope: CopyrightScope,
+        owner_id: &str,
+        template: &CopyrightTemplate,
+    ) -> Result<()> {
+        let mut state = self.state();
+        state
+            .copyright_templates
+            .retain(|(s, owner, _)| *s != scope || owner != owner_id);
+        state
+            .copyright_templates
+            .push((scope, owner_id.to_string(), template.clone()));
+        Ok(())
+    }
+
+    async fn delete_copyright_template(
+        &self,
+        scope: CopyrightScope,
+        owner_id: &str,
+    ) -> Result<()> {
+        self.state()
+            .copyright_templates
+            .retain(|(s, owner, _)| *s != scope || owner != owner_id);
+        Ok(())
+    }
+
+    async fn get_mail_template(
+        &self,
+        owner_id: &str,
+        kind: MailKind,
+    ) -> Result<Option<MailTemplate>> {
+        Ok(self
+            .state()
+            .mail_templates
+            .iter()
+            .find(|(owner, k, _)| owner == owner_id && *k == kind)
+            .map(|(_, _, template)| template.clone()))
+    }
+
+    async fn set_mail_template(
+        &self,
+        owner_id: &str,
+        kind: MailKind,
+        template: &MailTemplate,
+    ) -> Result<()> {
+        let mut state = self.state();
+        state
+            .mail_templates
+            .retain(|(owner, k, _)| owner != owner_id || *k != kind);
+        state
+            .mail_templates
+            .push((owner_id.to_string(), kind, template.clone()));
+        Ok(())
+    }
+
+    async fn delete_mail_template(&self, owner_id: &str, kind: MailKind) -> Result<()> {
+        self.state()
+            .mail_templates
+            .retain(|(owner, k, _)| owner != owner_id || *k != kind);
+        Ok(())
+    }
+}
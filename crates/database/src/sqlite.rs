@@ -20,14 +20,28 @@
 use anyhow::Result;
 use async_trait::async_trait;
 use common::auth::account::Account;
+use common::auth::account_token::{AccountToken, TokenPurpose};
 use common::auth::account_with_albums::{AccountWithAlbums, AlbumRef};
 use common::auth::album_account::AlbumAccountEntry;
 use common::auth::customer::Customer;
+use common::auth::mfa::AccountMfa;
+use common::auth::passkey::Passkey;
+use common::auth::session::{Session, SessionClient};
 use common::database::album::Album;
 use common::database::album_stats::{AlbumStats, ViewerEntry};
+use common::database::copyright_template::{CopyrightScope, CopyrightTemplate};
+use common::database::image_hash::{ImageHash, UnindexedReference};
+use common::database::job::{JobRecord, JobStatus};
+use common::database::mail_template::{MailKind, MailTemplate};
+use common::database::download::Download;
 use common::database::{AlbumCodeEntry, Database};
+use common::metadata::MetadataPolicy;
 use common::database::media_item::MediaItem;
-use common::database::reference::Reference;
+use common::database::placeholder::Placeholder;
+use common::database::reference::{self, Reference, ReferenceFile, ReferenceRole};
+use common::database::share_link::ShareLink;
+use common::database::video_details::VideoDetails;
+use common::database::watermark::AlbumWatermark;
 use sqlx::sqlite::SqliteQueryResult;
 use sqlx::types::chrono::{DateTime, Utc};
 use sqlx::Row;
@@ -54,12 +68,34 @@ impl SqliteDatabase {
 
 #[async_trait]
 impl Database for SqliteDatabase {
-    async fn get_media_items(&self, _user_id: &str) -> Result<Vec<MediaItem>> {
-        error!("get_media_items not implemented!");
-        Ok(vec![])
+    async fn get_media_items(&self, user_id: &str) -> Result<Vec<MediaItem>> {
+        let rows = sqlx::query(
+            "SELECT m.uuid, m.name, m.added_at, m.taken_at, \
+                    vd.media AS vd_media, vd.duration_ms, vd.width, vd.height, \
+                    vd.video_codec, vd.audio_codec, vd.frame_rate, vd.poster_path, \
+                    ph.media AS ph_media, ph.width AS ph_width, ph.height AS ph_height, \
+                    ph.dominant_color, ph.blurhash \
+             FROM media m \
+             LEFT JOIN video_details vd ON vd.media = m.uuid \
+             LEFT JOIN media_placeholders ph ON ph.media = m.uuid \
+             WHERE m.owner = $1 ORDER BY m.taken_at DESC, m.name ASC"
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(media_item_from_row).collect())
     }
 
     async fn delete_media_item(&self, media_id: &str) -> Result<()> {
+        sqlx::query("DELETE FROM media_placeholders WHERE media = $1")
+            .bind(media_id)
+            .execute(&self.pool)
+            .await?;
+        sqlx::query("DELETE FROM video_details WHERE media = $1")
+            .bind(media_id)
+            .execute(&self.pool)
+            .await?;
         sqlx::query("DELETE FROM reference WHERE media = $1")
             .bind(media_id)
             .execute(&self.pool)
@@ -133,7 +169,7 @@ impl Database for SqliteDatabase {
         media_id: &str,
         reference: &Reference,
     ) -> Result<String> {
-        let query = "INSERT INTO reference (uuid, media, owner, filepath, filename, size) VALUES ($1, $2, $3, $4, $5, $6)";
+        let query = "INSERT INTO reference (uuid, media, owner, filepath, filename, size, mime_type, role, content_hash) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)";
         let id = Uuid::new_v4().hyphenated().to_string();
         let _res: SqliteQueryResult = sqlx::query(query)
             .bind(id.clone())
@@ -142,6 +178,9 @@ impl Database for SqliteDatabase {
             .bind(&reference.filepath)
             .bind(&reference.filename)
             .bind(i64::try_from(reference.size).unwrap())
+            .bind(&reference.mime_type)
+            .bind(reference.role.as_str())
+            .bind(&reference.content_hash)
             .execute(&self.pool)
             .await?;
 
@@ -159,7 +198,7 @@ impl Database for SqliteDatabase {
     ///// Customer operations /////
 
     async fn get_customer(&self, customer_id: &str) -> Result<Customer> {
-        let query = "SELECT customer_id, access_code, display_name, created_at, updated_at, last_login_at FROM customers WHERE customer_id = $1";
+        let query = "SELECT customer_id, access_code, display_name, created_at, updated_at, last_login_at, paid FROM customers WHERE customer_id = $1";
 
         let row = sqlx::query_as::<_, Customer>(query)
             .bind(customer_id)
@@ -187,7 +226,7 @@ impl Database for SqliteDatabase {
 
     async fn get_customer_by_access_code(&self, code: &str) -> Result<Customer> {
         let customer = sqlx::query_as::<_, Customer>(
-            "SELECT customer_id, access_code, display_name, created_at, updated_at, last_login_at FROM customers WHERE access_code = $1"
+            "SELECT customer_id, access_code, display_name, created_at, updated_at, last_login_at, paid FROM customers WHERE access_code = $1"
         )
         .bind(code)
         .fetch_optional(&self.pool)
@@ -210,7 +249,7 @@ impl Database for SqliteDatabase {
 
     async fn list_customers(&self) -> Result<Vec<Customer>> {
         let customers = sqlx::query_as::<_, Customer>(
-            "SELECT customer_id, access_code, display_name, created_at, updated_at, last_login_at FROM customers ORDER BY created_at DESC"
+            "SELECT customer_id, access_code, display_name, created_at, updated_at, last_login_at, paid FROM customers ORDER BY created_at DESC"
         )
         .fetch_all(&self.pool)
         .await?;
@@ -227,6 +266,16 @@ impl Database for SqliteDatabase {
         Ok(())
     }
 
+    async fn set_customer_paid(&self, customer_id: &str, paid: bool) -> Result<()> {
+        sqlx::query("UPDATE customers SET paid = $1, updated_at = CURRENT_TIMESTAMP WHERE customer_id = $2")
+            .bind(paid)
+            .bind(customer_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
     ///// Account operations /////
 
     async fn create_account(
@@ -251,7 +300,7 @@ impl Database for SqliteDatabase {
 
     async fn get_account_by_email(&self, email: &str) -> Result<Account> {
         let account = sqlx::query_as::<_, Account>(
-            "SELECT account_id, email, password_hash, display_name, created_at, updated_at, last_login_at, is_admin FROM accounts WHERE email = $1"
+            "SELECT account_id, email, password_hash, display_name, created_at, updated_at, last_login_at, is_admin, email_verified_at FROM accounts WHERE email = $1"
         )
         .bind(email)
         .fetch_optional(&self.pool)
@@ -262,7 +311,7 @@ impl Database for SqliteDatabase {
 
     async fn get_account_by_id(&self, account_id: &str) -> Result<Account> {
         let account = sqlx::query_as::<_, Account>(
-            "SELECT account_id, email, password_hash, display_name, created_at, updated_at, last_login_at, is_admin FROM accounts WHERE account_id = $1"
+            "SELECT account_id, email, password_hash, display_name, created_at, updated_at, last_login_at, is_admin, email_verified_at FROM accounts WHERE account_id = $1"
         )
         .bind(account_id)
         .fetch_optional(&self.pool)
@@ -570,6 +619,56 @@ impl Database for SqliteDatabase {
         Ok(())
     }
 
+    async fn get_album_metadata_policy(&self, album_id: &str) -> Result<MetadataPolicy> {
+        let policy: String = sqlx::query_scalar("SELECT metadata_policy FROM albums WHERE album_id = $1")
+            .bind(album_id)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(policy.parse().unwrap_or(MetadataPolicy::StripAll))
+    }
+
+    async fn set_album_metadata_policy(&self, album_id: &str, policy: MetadataPolicy) -> Result<()> {
+        sqlx::query("UPDATE albums SET metadata_policy = $1, updated_at = CURRENT_TIMESTAMP WHERE album_id = $2")
+            .bind(policy.as_str())
+            .bind(album_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_metadata_policy_for_media(&self, media_id: &str) -> Result<Option<(String, MetadataPolicy)>> {
+        let rows = sqlx::query(
+            "SELECT a.album_id, a.metadata_policy FROM albums a \
+             JOIN album_media am ON am.album_id = a.album_id \
+             WHERE am.media_id = $1 AND a.metadata_policy <> 'keep'"
+        )
+        .bind(media_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        // Unknown values are treated as the strictest policy.
+        Ok(rows
+            .iter()
+            .map(|r| {
+                let policy: String = r.get("metadata_policy");
+                (r.get("album_id"), policy.parse().unwrap_or(MetadataPolicy::StripAll))
+            })
+            .max_by_key(|(_, policy)| *policy))
+    }
+
+    async fn get_album_ids_for_media(&self, media_id: &str) -> Result<Vec<String>> {
+        let album_ids: Vec<String> = sqlx::query_scalar(
+            "SELECT album_id FROM album_media WHERE media_id = $1 ORDER BY added_at ASC, album_id ASC"
+        )
+        .bind(media_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(album_ids)
+    }
+
     ///// Album-media junction /////
 
     async fn add_media_to_album(&self, album_id: &str, media_id: &str) -> Result<()> {
@@ -596,29 +695,22 @@ impl Database for SqliteDatabase {
 
     async fn get_media_for_album(&self, album_id: &str) -> Result<Vec<MediaItem>> {
         let rows = sqlx::query(
-            "SELECT m.uuid, m.name, m.added_at, m.taken_at FROM media m \
+            "SELECT m.uuid, m.name, m.added_at, m.taken_at, \
+                    vd.media AS vd_media, vd.duration_ms, vd.width, vd.height, \
+                    vd.video_codec, vd.audio_codec, vd.frame_rate, vd.poster_path, \
+                    ph.media AS ph_media, ph.width AS ph_width, ph.height AS ph_height, \
+                    ph.dominant_color, ph.blurhash \
+             FROM media m \
              JOIN album_media am ON am.media_id = m.uuid \
+             LEFT JOIN video_details vd ON vd.media = m.uuid \
+             LEFT JOIN media_placeholders ph ON ph.media = m.uuid \
              WHERE am.album_id = $1 ORDER BY am.position ASC, m.name ASC"
         )
         .bind(album_id)
         .fetch_all(&self.pool)
         .await?;
 
-        let items = rows
-            .iter()
-            .map(|row| MediaItem {
-                uuid: row.get("uuid"),
-                name: row.get("name"),
-                added_at: row.get("added_at"),
-                taken_at: row.get("taken_at"),
-                details: None,
-                tags: None,
-                location: None,
-                references: None,
-            })
-            .collect();
-
-        Ok(items)
+        Ok(rows.iter().map(media_item_from_row).collect())
     }
 
     ///// Customer-album assignment /////
@@ -699,7 +791,10 @@ impl Database for SqliteDatabase {
     }
 
     async fn get_media_file_path(&self, media_id: &str) -> Result<Option<(String, String)>> {
-        let row = sqlx::query("SELECT filepath, filename FROM reference WHERE media = $1 LIMIT 1")
+        let row = sqlx::query(
+            "SELECT filepath, filename FROM reference WHERE media = $1 \
+             ORDER BY CASE role WHEN 'original' THEN 0 WHEN 'raw' THEN 1 WHEN 'live-video' THEN 2 WHEN 'rendition' THEN 3 ELSE 4 END LIMIT 1"
+        )
             .bind(media_id)
             .fetch_optional(&self.pool)
             .await?;
@@ -707,162 +802,1244 @@ impl Database for SqliteDatabase {
         Ok(row.map(|r| (r.get::<String, _>("filepath"), r.get::<String, _>("filename"))))
     }
 
-    ///// Stats /////
+    async fn get_reference_files(&self, media_id: &str) -> Result<Vec<ReferenceFile>> {
+        let rows = sqlx::query(
+            "SELECT filepath, filename, mime_type, role, content_hash FROM reference WHERE media = $1 \
+             ORDER BY CASE role WHEN 'original' THEN 0 WHEN 'raw' THEN 1 WHEN 'live-video' THEN 2 WHEN 'rendition' THEN 3 ELSE 4 END, filename ASC"
+        )
+        .bind(media_id)
+        .fetch_all(&self.pool)
+        .await?;
 
-    async fn record_album_view(&self, album_id: &str, viewer_id: &str, viewer_role: &str) -> Result<()> {
-        sqlx::query(
-            "INSERT INTO album_views (album_id, viewer_id, viewer_role) VALUES ($1, $2, $3)"
+        Ok(rows
+            .iter()
+            .map(|r| ReferenceFile {
+                filepath: r.get("filepath"),
+                filename: r.get("filename"),
+                mime_type: r.get("mime_type"),
+                role: r.get::<String, _>("role").parse().unwrap_or_default(),
+                content_hash: r.get("content_hash"),
+            })
+            .collect())
+    }
+
+    async fn find_album_media_by_basename(&self, album_id: &str, basename: &str) -> Result<Option<String>> {
+        // LIKE narrows the candidates; `_` in file names is a wildcard, so compare exactly below.
+        let rows = sqlx::query(
+            "SELECT r.media, r.filename FROM reference r \
+             JOIN album_media am ON am.media_id = r.media \
+             WHERE am.album_id = $1 AND LOWER(r.filename) LIKE $2"
         )
         .bind(album_id)
-        .bind(viewer_id)
-        .bind(viewer_role)
-        .execute(&self.pool)
+        .bind(format!("{}.%", basename.to_lowercase()))
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .iter()
+            .find(|r| reference::basename(&r.get::<String, _>("filename")).eq_ignore_ascii_case(basename))
+            .map(|r| r.get("media")))
+    }
+
+    async fn find_owned_media_by_basename(&self, owner_id: &str, basename: &str) -> Result<Option<String>> {
+        // LIKE narrows the candidates; `_` in file names is a wildcard, so compare exactly below.
+        let rows = sqlx::query(
+            "SELECT media, filename FROM reference WHERE owner = $1 AND LOWER(filename) LIKE $2"
+        )
+        .bind(owner_id)
+        .bind(format!("{}.%", basename.to_lowercase()))
+        .fetch_all(&self.pool)
         .await?;
 
+        Ok(rows
+            .iter()
+            .find(|r| reference::basename(&r.get::<String, _>("filename")).eq_ignore_ascii_case(basename))
+            .map(|r| r.get("media")))
+    }
+
+    async fn set_reference_role(&self, media_id: &str, filename: &str, role: ReferenceRole) -> Result<()> {
+        sqlx::query("UPDATE reference SET role = $1 WHERE media = $2 AND filename = $3")
+            .bind(role.as_str())
+            .bind(media_id)
+            .bind(filename)
+            .execute(&self.pool)
+            .await?;
+
         Ok(())
     }
 
-    async fn record_media_download(&self, media_id: &str, album_id: Option<&str>, downloader_id: &str, downloader_role: &str) -> Result<()> {
+    async fn get_media_mime_type(&self, media_id: &str) -> Result<Option<String>> {
+        let row = sqlx::query(
+            "SELECT mime_type FROM reference WHERE media = $1 \
+             ORDER BY CASE role WHEN 'original' THEN 0 WHEN 'raw' THEN 1 WHEN 'live-video' THEN 2 WHEN 'rendition' THEN 3 ELSE 4 END LIMIT 1"
+        )
+            .bind(media_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.and_then(|r| r.get::<Option<String>, _>("mime_type")))
+    }
+
+    ///// Video metadata /////
+
+    async fn upsert_video_details(&self, details: &VideoDetails) -> Result<()> {
         sqlx::query(
-            "INSERT INTO media_downloads (media_id, album_id, downloader_id, downloader_role) VALUES ($1, $2, $3, $4)"
+            "INSERT INTO video_details (media, duration_ms, width, height, video_codec, audio_codec, frame_rate, poster_path) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8) \
+             ON CONFLICT (media) DO UPDATE SET \
+                duration_ms = excluded.duration_ms, width = excluded.width, height = excluded.height, \
+                video_codec = excluded.video_codec, audio_codec = excluded.audio_codec, \
+                frame_rate = excluded.frame_rate, poster_path = excluded.poster_path"
         )
-        .bind(media_id)
-        .bind(album_id)
-        .bind(downloader_id)
-        .bind(downloader_role)
+        .bind(&details.media)
+        .bind(details.duration_ms)
+        .bind(details.width)
+        .bind(details.height)
+        .bind(&details.video_codec)
+        .bind(&details.audio_codec)
+        .bind(details.frame_rate)
+        .bind(&details.poster_path)
         .execute(&self.pool)
         .await?;
 
         Ok(())
     }
 
-    async fn get_album_stats(&self, album_id: &str) -> Result<AlbumStats> {
-        let total_views: i64 = sqlx::query_scalar(
-            "SELECT COUNT(*) FROM album_views WHERE album_id = $1"
+    async fn get_video_details(&self, media_id: &str) -> Result<Option<VideoDetails>> {
+        let details = sqlx::query_as::<_, VideoDetails>(
+            "SELECT media, duration_ms, width, height, video_codec, audio_codec, frame_rate, poster_path \
+             FROM video_details WHERE media = $1"
         )
-        .bind(album_id)
-        .fetch_one(&self.pool)
+        .bind(media_id)
+        .fetch_optional(&self.pool)
         .await?;
 
-        let unique_viewers: i64 = sqlx::query_scalar(
-            "SELECT COUNT(DISTINCT viewer_id) FROM album_views WHERE album_id = $1"
+        Ok(details)
+    }
+
+    async fn delete_video_details(&self, media_id: &str) -> Result<()> {
+        sqlx::query("DELETE FROM video_details WHERE media = $1")
+            .bind(media_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    ///// Perceptual hashes /////
+
+    async fn set_perceptual_hash(&self, reference_id: &str, hash: i64) -> Result<()> {
+        sqlx::query("UPDATE reference SET perceptual_hash = $1 WHERE uuid = $2")
+            .bind(hash)
+            .bind(reference_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_image_hashes_for_owner(&self, owner_id: &str) -> Result<Vec<ImageHash>> {
+        let hashes = sqlx::query_as::<_, ImageHash>(
+            "SELECT r.uuid AS reference_id, r.media AS media_id, r.perceptual_hash AS hash, m.taken_at \
+             FROM reference r JOIN media m ON m.uuid = r.media \
+             WHERE r.owner = $1 AND r.role = 'original' AND r.perceptual_hash IS NOT NULL"
         )
-        .bind(album_id)
-        .fetch_one(&self.pool)
+        .bind(owner_id)
+        .fetch_all(&self.pool)
         .await?;
 
-        let total_downloads: i64 = sqlx::query_scalar(
-            "SELECT COUNT(*) FROM media_downloads WHERE album_id = $1"
+        Ok(hashes)
+    }
+
+    async fn get_image_hashes_for_album(&self, album_id: &str) -> Result<Vec<ImageHash>> {
+        let hashes = sqlx::query_as::<_, ImageHash>(
+            "SELECT r.uuid AS reference_id, r.media AS media_id, r.perceptual_hash AS hash, m.taken_at \
+             FROM album_media am \
+             JOIN media m ON m.uuid = am.media_id \
+             JOIN reference r ON r.media = m.uuid \
+             WHERE am.album_id = $1 AND r.role = 'original' AND r.perceptual_hash IS NOT NULL"
         )
         .bind(album_id)
-        .fetch_one(&self.pool)
+        .fetch_all(&self.pool)
         .await?;
 
-        let rows = sqlx::query(
-            "SELECT viewer_id, viewer_role, COUNT(*) as view_count \
-             FROM album_views WHERE album_id = $1 \
-             GROUP BY viewer_id, viewer_role \
-             ORDER BY view_count DESC"
+        Ok(hashes)
+    }
+
+    async fn get_unindexed_image_references(&self) -> Result<Vec<UnindexedReference>> {
+        let references = sqlx::query_as::<_, UnindexedReference>(
+            "SELECT r.uuid AS reference_id, r.media AS media_id, r.filepath, r.filename, r.mime_type \
+             FROM reference r \
+             LEFT JOIN media_placeholders ph ON ph.media = r.media \
+             WHERE (r.perceptual_hash IS NULL OR ph.media IS NULL) \
+               AND r.role = 'original' AND r.mime_type LIKE 'image/%'"
         )
-        .bind(album_id)
         .fetch_all(&self.pool)
         .await?;
 
-        let viewers = rows
-            .iter()
-            .map(|row| ViewerEntry {
-                viewer_id: row.get("viewer_id"),
-                viewer_role: row.get("viewer_role"),
-                view_count: row.get("view_count"),
-            })
-            .collect();
+        Ok(references)
+    }
 
-        Ok(AlbumStats {
-            album_id: album_id.to_string(),
-            total_views,
-            unique_viewers,
-            total_downloads,
-            viewers,
-        })
+    ///// Placeholders /////
+
+    async fn upsert_placeholder(&self, placeholder: &Placeholder) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO media_placeholders (media, width, height, dominant_color, blurhash) \
+             VALUES ($1, $2, $3, $4, $5) \
+             ON CONFLICT (media) DO UPDATE SET \
+                width = excluded.width, height = excluded.height, \
+                dominant_color = excluded.dominant_color, blurhash = excluded.blurhash"
+        )
+        .bind(&placeholder.media)
+        .bind(placeholder.width)
+        .bind(placeholder.height)
+        .bind(&placeholder.dominant_color)
+        .bind(&placeholder.blurhash)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
     }
 
-    async fn get_stats_for_owned_albums(&self, account_id: &str) -> Result<Vec<AlbumStats>> {
-        let rows = sqlx::query(
-            "SELECT album_id FROM album_accounts WHERE account_id = $1 AND role = 'owner'"
+    ///// Background jobs /////
+
+    async fn insert_job(&self, job: &JobRecord) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO jobs (job_id, kind, payload, status, attempts, max_attempts, run_at, last_error, dedupe_key, created_at, updated_at) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)"
         )
-        .bind(account_id)
-        .fetch_all(&self.pool)
+        .bind(&job.job_id)
+        .bind(&job.kind)
+        .bind(&job.payload)
+        .bind(job.status.as_str())
+        .bind(job.attempts)
+        .bind(job.max_attempts)
+        .bind(job.run_at)
+        .bind(&job.last_error)
+        .bind(&job.dedupe_key)
+        .bind(job.created_at)
+        .bind(job.updated_at)
+        .execute(&self.pool)
         .await?;
 
-        let album_ids: Vec<String> = rows.iter().map(|r| r.get("album_id")).collect();
+        Ok(())
+    }
 
-        let mut stats = Vec::new();
-        for album_id in album_ids {
-            let s = self.get_album_stats(&album_id).await?;
-            stats.push(s);
-        }
+    async fn find_active_job(&self, dedupe_key: &str) -> Result<Option<JobRecord>> {
+        let job = sqlx::query_as::<_, JobRecord>(
+            "SELECT * FROM jobs WHERE dedupe_key = $1 AND status IN ('queued', 'running') \
+             ORDER BY created_at ASC LIMIT 1"
+        )
+        .bind(dedupe_key)
+        .fetch_optional(&self.pool)
+        .await?;
 
-        Ok(stats)
+        Ok(job)
     }
 
-    async fn get_access_codes_for_album(&self, album_id: &str) -> Result<Vec<AlbumCodeEntry>> {
-        let rows = sqlx::query(
-            "SELECT c.access_code, c.display_name, c.customer_id \
-             FROM customer_albums ca \
-             JOIN customers c ON c.customer_id = ca.customer_id \
-             WHERE ca.album_id = $1 \
-             ORDER BY c.display_name, c.access_code"
+    async fn reschedule_job(&self, job_id: &str, run_at: DateTime<Utc>) -> Result<()> {
+        sqlx::query("UPDATE jobs SET run_at = $1, updated_at = $1 WHERE job_id = $2 AND status = 'queued'")
+            .bind(run_at)
+            .bind(job_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_due_jobs(&self, kind: &str, now: DateTime<Utc>, limit: i64) -> Result<Vec<JobRecord>> {
+        let jobs = sqlx::query_as::<_, JobRecord>(
+            "SELECT * FROM jobs WHERE status = 'queued' AND kind = $1 AND run_at <= $2 \
+             ORDER BY run_at ASC LIMIT $3"
         )
-        .bind(album_id)
+        .bind(kind)
+        .bind(now)
+        .bind(limit)
         .fetch_all(&self.pool)
         .await?;
 
-        Ok(rows.iter().map(|r| AlbumCodeEntry {
-            access_code: r.get("access_code"),
-            display_name: r.get("display_name"),
-            customer_id: r.get("customer_id"),
-        }).collect())
+        Ok(jobs)
     }
 
-    async fn generate_and_assign_code(&self, album_id: &str, display_name: &str) -> Result<String> {
-        let customer_id = Uuid::new_v4().hyphenated().to_string();
-        let access_code = self.generate_access_code();
-        self.create_customer(customer_id.clone(), access_code.clone(), display_name.to_string()).await?;
-        self.assign_album_to_customer(album_id, &customer_id).await?;
-        Ok(access_code)
+    async fn claim_job(&self, job_id: &str, now: DateTime<Utc>) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE jobs SET status = 'running', attempts = attempts + 1, updated_at = $1 \
+             WHERE job_id = $2 AND status = 'queued'"
+        )
+        .bind(now)
+        .bind(job_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
     }
 
-    async fn generate_code(&self, display_name: &str) -> Result<String> {
-        let customer_id = Uuid::new_v4().hyphenated().to_string();
-        let access_code = self.generate_access_code();
-        self.create_customer(customer_id, access_code.clone(), display_name.to_string()).await?;
-        Ok(access_code)
+    async fn complete_job(&self, job_id: &str) -> Result<()> {
+        sqlx::query("DELETE FROM jobs WHERE job_id = $1")
+            .bind(job_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
     }
 
-}
+    async fn fail_job(&self, job_id: &str, error: &str, retry_at: Option<DateTime<Utc>>, now: DateTime<Utc>) -> Result<()> {
+        let status = if retry_at.is_some() { "queued" } else { "failed" };
+        sqlx::query(
+            "UPDATE jobs SET status = $1, run_at = COALESCE($2, run_at), last_error = $3, updated_at = $4 \
+             WHERE job_id = $5"
+        )
+        .bind(status)
+        .bind(retry_at)
+        .bind(error)
+        .bind(now)
+        .bind(job_id)
+        .execute(&self.pool)
+        .await?;
 
-impl SqliteDatabase {
-    #[inline]
-    fn generate_access_code(&self) -> String {
-        use rand::Rng;
-        const CHARSET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
-        let mut rng = rand::thread_rng();
-        (0..8)
-            .map(|_| {
-                let idx = rng.gen_range(0..CHARSET.len());
-                CHARSET[idx] as char
-            })
-            .collect()
+        Ok(())
     }
 
-    /// Generate and create a new customer with a generated access code
-    pub async fn create_customer_with_generated_code(&self, display_name: &str) -> Result<String> {
-        let customer_id = Uuid::new_v4().to_string();
-        let access_code = self.generate_access_code();
+    async fn requeue_running_jobs(&self, now: DateTime<Utc>) -> Result<u64> {
+        let result = sqlx::query("UPDATE jobs SET status = 'queued', run_at = $1, updated_at = $1 WHERE status = 'running'")
+            .bind(now)
+            .execute(&self.pool)
+            .await?;
 
-        self.create_customer(customer_id.clone(), access_code, display_name.to_string()).await?;
+        Ok(result.rows_affected())
+    }
 
-        Ok(customer_id)
+    async fn retry_failed_job(&self, job_id: &str, now: DateTime<Utc>) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE jobs SET status = 'queued', attempts = 0, run_at = $1, updated_at = $1 \
+             WHERE job_id = $2 AND status = 'failed'"
+        )
+        .bind(now)
+        .bind(job_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn list_jobs(&self, status: Option<JobStatus>) -> Result<Vec<JobRecord>> {
+        let jobs = match status {
+            Some(status) => {
+                sqlx::query_as::<_, JobRecord>("SELECT * FROM jobs WHERE status = $1 ORDER BY run_at ASC")
+                    .bind(status.as_str())
+                    .fetch_all(&self.pool)
+                    .await?
+            }
+            None => {
+                sqlx::query_as::<_, JobRecord>("SELECT * FROM jobs ORDER BY run_at ASC")
+                    .fetch_all(&self.pool)
+                    .await?
+            }
+        };
+
+        Ok(jobs)
+    }
+
+    ///// Downloads /////
+
+    async fn create_download(&self, download: &Download) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO downloads (download_id, requester_id, requester_role, contents, created_at, expires_at) \
+             VALUES ($1, $2, $3, $4, $5, $6)"
+        )
+        .bind(&download.download_id)
+        .bind(&download.requester_id)
+        .bind(&download.requester_role)
+        .bind(download.contents.to_json()?)
+        .bind(download.created_at)
+        .bind(download.expires_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_download(&self, download_id: &str) -> Result<Option<Download>> {
+        let download = sqlx::query_as::<_, Download>("SELECT * FROM downloads WHERE download_id = $1")
+            .bind(download_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(download)
+    }
+
+    async fn delete_expired_downloads(&self, now: DateTime<Utc>) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM downloads WHERE expires_at <= $1")
+            .bind(now)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    ///// Sessions /////
+
+    async fn create_session(&self, session: &Session) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO sessions (session_id, subject_id, role, refresh_token_hash, user_agent, ip_address, created_at, last_used_at, expires_at) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)"
+        )
+        .bind(&session.session_id)
+        .bind(&session.subject_id)
+        .bind(&session.role)
+        .bind(&session.refresh_token_hash)
+        .bind(&session.user_agent)
+        .bind(&session.ip_address)
+        .bind(session.created_at)
+        .bind(session.last_used_at)
+        .bind(session.expires_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_session(&self, session_id: &str) -> Result<Option<Session>> {
+        let session = sqlx::query_as::<_, Session>("SELECT * FROM sessions WHERE session_id = $1")
+            .bind(session_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(session)
+    }
+
+    async fn get_session_by_refresh_token(&self, token_hash: &str) -> Result<Option<Session>> {
+        let session = sqlx::query_as::<_, Session>(
+            "SELECT * FROM sessions WHERE refresh_token_hash = $1 OR previous_token_hash = $1"
+        )
+        .bind(token_hash)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(session)
+    }
+
+    async fn rotate_session_token(
+        &self,
+        session_id: &str,
+        current_hash: &str,
+        new_hash: &str,
+        client: &SessionClient,
+        now: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE sessions SET refresh_token_hash = $1, previous_token_hash = $2, \
+             user_agent = COALESCE($3, user_agent), ip_address = COALESCE($4, ip_address), \
+             last_used_at = $5, expires_at = $6 \
+             WHERE session_id = $7 AND refresh_token_hash = $2 AND revoked_at IS NULL"
+        )
+        .bind(new_hash)
+        .bind(current_hash)
+        .bind(&client.user_agent)
+        .bind(&client.ip_address)
+        .bind(now)
+        .bind(expires_at)
+        .bind(session_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn revoke_session(&self, session_id: &str, now: DateTime<Utc>) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE sessions SET revoked_at = $1 WHERE session_id = $2 AND revoked_at IS NULL"
+        )
+        .bind(now)
+        .bind(session_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn list_sessions(&self, subject_id: &str, role: &str, now: DateTime<Utc>) -> Result<Vec<Session>> {
+        let sessions = sqlx::query_as::<_, Session>(
+            "SELECT * FROM sessions \
+             WHERE subject_id = $1 AND role = $2 AND revoked_at IS NULL AND expires_at > $3 \
+             ORDER BY last_used_at DESC"
+        )
+        .bind(subject_id)
+        .bind(role)
+        .bind(now)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(sessions)
+    }
+
+    async fn list_revoked_session_ids(&self, since: DateTime<Utc>) -> Result<Vec<String>> {
+        let ids = sqlx::query_scalar::<_, String>(
+            "SELECT session_id FROM sessions WHERE revoked_at > $1"
+        )
+        .bind(since)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(ids)
+    }
+
+    async fn delete_expired_sessions(&self, now: DateTime<Utc>) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM sessions WHERE expires_at <= $1")
+            .bind(now)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn get_linked_account(&self, provider: &str, subject: &str) -> Result<Option<String>> {
+        let account_id = sqlx::query_scalar::<_, String>(
+            "SELECT account_id FROM external_identities WHERE provider = $1 AND subject = $2"
+        )
+        .bind(provider)
+        .bind(subject)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(account_id)
+    }
+
+    async fn link_external_identity(
+        &self,
+        provider: &str,
+        subject: &str,
+        account_id: &str,
+        email: Option<&str>,
+        now: DateTime<Utc>,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO external_identities (provider, subject, account_id, email, created_at, last_login_at) \
+             VALUES ($1, $2, $3, $4, $5, $5) \
+             ON CONFLICT (provider, subject) DO UPDATE SET email = $4, last_login_at = $5"
+        )
+        .bind(provider)
+        .bind(subject)
+        .bind(account_id)
+        .bind(email)
+        .bind(now)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_account_mfa(&self, account_id: &str) -> Result<Option<AccountMfa>> {
+        let mfa = sqlx::query_as::<_, AccountMfa>("SELECT * FROM account_mfa WHERE account_id = $1")
+            .bind(account_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(mfa)
+    }
+
+    async fn set_pending_totp_secret(&self, account_id: &str, secret: &str, now: DateTime<Utc>) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO account_mfa (account_id, totp_secret, created_at) VALUES ($1, $2, $3) \
+             ON CONFLICT (account_id) DO UPDATE SET totp_secret = $2, created_at = $3 \
+             WHERE account_mfa.enabled_at IS NULL"
+        )
+        .bind(account_id)
+        .bind(secret)
+        .bind(now)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn enable_totp(
+        &self,
+        account_id: &str,
+        step: i64,
+        recovery_code_hashes: &[String],
+        now: DateTime<Utc>,
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("UPDATE account_mfa SET enabled_at = $2, last_used_step = $3 WHERE account_id = $1")
+            .bind(account_id)
+            .bind(now)
+            .bind(step)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query("DELETE FROM account_recovery_codes WHERE account_id = $1")
+            .bind(account_id)
+            .execute(&mut *tx)
+            .await?;
+
+        for code_hash in recovery_code_hashes {
+            sqlx::query("INSERT INTO account_recovery_codes (account_id, code_hash) VALUES ($1, $2)")
+                .bind(account_id)
+                .bind(code_hash)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn delete_account_mfa(&self, account_id: &str) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("DELETE FROM account_recovery_codes WHERE account_id = $1")
+            .bind(account_id)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query("DELETE FROM account_mfa WHERE account_id = $1")
+            .bind(account_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn use_totp_step(&self, account_id: &str, step: i64) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE account_mfa SET last_used_step = $2 \
+             WHERE account_id = $1 AND (last_used_step IS NULL OR last_used_step < $2)"
+        )
+        .bind(account_id)
+        .bind(step)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn replace_recovery_codes(&self, account_id: &str, code_hashes: &[String]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("DELETE FROM account_recovery_codes WHERE account_id = $1")
+            .bind(account_id)
+            .execute(&mut *tx)
+            .await?;
+
+        for code_hash in code_hashes {
+            sqlx::query("INSERT INTO account_recovery_codes (account_id, code_hash) VALUES ($1, $2)")
+                .bind(account_id)
+                .bind(code_hash)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn use_recovery_code(&self, account_id: &str, code_hash: &str, now: DateTime<Utc>) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE account_recovery_codes SET used_at = $3 \
+             WHERE account_id = $1 AND code_hash = $2 AND used_at IS NULL"
+        )
+        .bind(account_id)
+        .bind(code_hash)
+        .bind(now)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn count_unused_recovery_codes(&self, account_id: &str) -> Result<i64> {
+        let count = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM account_recovery_codes WHERE account_id = $1 AND used_at IS NULL"
+        )
+        .bind(account_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count)
+    }
+
+    async fn record_mfa_failure(&self, account_id: &str, max_failures: i64, locked_until: DateTime<Utc>) -> Result<()> {
+        // `locked_until` comes first, MySQL sees already updated columns in later assignments.
+        sqlx::query(
+            "UPDATE account_mfa SET \
+             locked_until = CASE WHEN failed_attempts + 1 >= $2 THEN $3 ELSE locked_until END, \
+             failed_attempts = failed_attempts + 1 \
+             WHERE account_id = $1"
+        )
+        .bind(account_id)
+        .bind(max_failures)
+        .bind(locked_until)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn reset_mfa_failures(&self, account_id: &str) -> Result<()> {
+        sqlx::query("UPDATE account_mfa SET failed_attempts = 0, locked_until = NULL WHERE account_id = $1")
+            .bind(account_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn create_passkey(&self, passkey: &Passkey) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO account_passkeys \
+             (credential_id, account_id, name, public_key, algorithm, sign_count, transports, created_at, last_used_at) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)"
+        )
+        .bind(&passkey.credential_id)
+        .bind(&passkey.account_id)
+        .bind(&passkey.name)
+        .bind(&passkey.public_key)
+        .bind(passkey.algorithm)
+        .bind(passkey.sign_count)
+        .bind(&passkey.transports)
+        .bind(passkey.created_at)
+        .bind(passkey.last_used_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_passkey(&self, credential_id: &str) -> Result<Option<Passkey>> {
+        let passkey = sqlx::query_as::<_, Passkey>("SELECT * FROM account_passkeys WHERE credential_id = $1")
+            .bind(credential_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(passkey)
+    }
+
+    async fn list_passkeys(&self, account_id: &str) -> Result<Vec<Passkey>> {
+        let passkeys = sqlx::query_as::<_, Passkey>(
+            "SELECT * FROM account_passkeys WHERE account_id = $1 ORDER BY created_at"
+        )
+        .bind(account_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(passkeys)
+    }
+
+    async fn update_passkey_usage(&self, credential_id: &str, sign_count: i64, now: DateTime<Utc>) -> Result<()> {
+        sqlx::query("UPDATE account_passkeys SET sign_count = $2, last_used_at = $3 WHERE credential_id = $1")
+            .bind(credential_id)
+            .bind(sign_count)
+            .bind(now)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn delete_passkey(&self, account_id: &str, credential_id: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM account_passkeys WHERE account_id = $1 AND credential_id = $2")
+            .bind(account_id)
+            .bind(credential_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    ///// Email verification and password reset /////
+
+    async fn set_account_password(&self, account_id: &str, password_hash: &str, now: DateTime<Utc>) -> Result<()> {
+        sqlx::query("UPDATE accounts SET password_hash = $1, updated_at = $2 WHERE account_id = $3")
+            .bind(password_hash)
+            .bind(now)
+            .bind(account_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn set_email_verified(&self, account_id: &str, now: DateTime<Utc>) -> Result<()> {
+        sqlx::query("UPDATE accounts SET email_verified_at = $1 WHERE account_id = $2")
+            .bind(now)
+            .bind(account_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn create_account_token(&self, token: &AccountToken) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO account_tokens (token_hash, account_id, purpose, email, created_at, expires_at) \
+             VALUES ($1, $2, $3, $4, $5, $6)"
+        )
+        .bind(&token.token_hash)
+        .bind(&token.account_id)
+        .bind(&token.purpose)
+        .bind(&token.email)
+        .bind(token.created_at)
+        .bind(token.expires_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn use_account_token(
+        &self,
+        token_hash: &str,
+        purpose: TokenPurpose,
+        now: DateTime<Utc>,
+    ) -> Result<Option<AccountToken>> {
+        let result = sqlx::query(
+            "UPDATE account_tokens SET used_at = $1 \
+             WHERE token_hash = $2 AND purpose = $3 AND used_at IS NULL AND expires_at > $1"
+        )
+        .bind(now)
+        .bind(token_hash)
+        .bind(purpose.as_str())
+        .execute(&self.pool)
+        .await?;
+        if result.rows_affected() != 1 {
+            return Ok(None);
+        }
+
+        let token = sqlx::query_as::<_, AccountToken>("SELECT * FROM account_tokens WHERE token_hash = $1")
+            .bind(token_hash)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(token)
+    }
+
+    async fn delete_account_tokens(&self, account_id: &str, purpose: TokenPurpose) -> Result<()> {
+        sqlx::query("DELETE FROM account_tokens WHERE account_id = $1 AND purpose = $2")
+            .bind(account_id)
+            .bind(purpose.as_str())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_studio_of_account(&self, account_id: &str) -> Result<Option<String>> {
+        let owner = sqlx::query_scalar::<_, String>(
+            "SELECT al.owner FROM account_access_codes ac \
+             JOIN customer_albums ca ON ca.customer_id = ac.customer_id \
+             JOIN albums al ON al.album_id = ca.album_id \
+             WHERE ac.account_id = $1 \
+             ORDER BY ac.linked_at, ca.assigned_at LIMIT 1"
+        )
+        .bind(account_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(owner)
+    }
+
+    ///// Stats /////
+
+    async fn record_album_view(&self, album_id: &str, viewer_id: &str, viewer_role: &str) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO album_views (album_id, viewer_id, viewer_role) VALUES ($1, $2, $3)"
+        )
+        .bind(album_id)
+        .bind(viewer_id)
+        .bind(viewer_role)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn record_media_download(&self, media_id: &str, album_id: Option<&str>, downloader_id: &str, downloader_role: &str) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO media_downloads (media_id, album_id, downloader_id, downloader_role) VALUES ($1, $2, $3, $4)"
+        )
+        .bind(media_id)
+        .bind(album_id)
+        .bind(downloader_id)
+        .bind(downloader_role)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_album_stats(&self, album_id: &str) -> Result<AlbumStats> {
+        let total_views: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM album_views WHERE album_id = $1"
+        )
+        .bind(album_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        let unique_viewers: i64 = sqlx::query_scalar(
+            "SELECT COUNT(DISTINCT viewer_id) FROM album_views WHERE album_id = $1"
+        )
+        .bind(album_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        let total_downloads: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM media_downloads WHERE album_id = $1"
+        )
+        .bind(album_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        let rows = sqlx::query(
+            "SELECT viewer_id, viewer_role, COUNT(*) as view_count \
+             FROM album_views WHERE album_id = $1 \
+             GROUP BY viewer_id, viewer_role \
+             ORDER BY view_count DESC"
+        )
+        .bind(album_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let viewers = rows
+            .iter()
+            .map(|row| ViewerEntry {
+                viewer_id: row.get("viewer_id"),
+                viewer_role: row.get("viewer_role"),
+                view_count: row.get("view_count"),
+            })
+            .collect();
+
+        Ok(AlbumStats {
+            album_id: album_id.to_string(),
+            total_views,
+            unique_viewers,
+            total_downloads,
+            viewers,
+        })
+    }
+
+    async fn get_stats_for_owned_albums(&self, account_id: &str) -> Result<Vec<AlbumStats>> {
+        let rows = sqlx::query(
+            "SELECT album_id FROM album_accounts WHERE account_id = $1 AND role = 'owner'"
+        )
+        .bind(account_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let album_ids: Vec<String> = rows.iter().map(|r| r.get("album_id")).collect();
+
+        let mut stats = Vec::new();
+        for album_id in album_ids {
+            let s = self.get_album_stats(&album_id).await?;
+            stats.push(s);
+        }
+
+        Ok(stats)
+    }
+
+    async fn get_access_codes_for_album(&self, album_id: &str) -> Result<Vec<AlbumCodeEntry>> {
+        let rows = sqlx::query(
+            "SELECT c.access_code, c.display_name, c.customer_id \
+             FROM customer_albums ca \
+             JOIN customers c ON c.customer_id = ca.customer_id \
+             WHERE ca.album_id = $1 \
+             ORDER BY c.display_name, c.access_code"
+        )
+        .bind(album_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.iter().map(|r| AlbumCodeEntry {
+            access_code: r.get("access_code"),
+            display_name: r.get("display_name"),
+            customer_id: r.get("customer_id"),
+        }).collect())
+    }
+
+    async fn generate_and_assign_code(&self, album_id: &str, display_name: &str) -> Result<String> {
+        let customer_id = Uuid::new_v4().hyphenated().to_string();
+        let access_code = self.generate_access_code();
+        self.create_customer(customer_id.clone(), access_code.clone(), display_name.to_string()).await?;
+        self.assign_album_to_customer(album_id, &customer_id).await?;
+        Ok(access_code)
+    }
+
+    async fn generate_code(&self, display_name: &str) -> Result<String> {
+        let customer_id = Uuid::new_v4().hyphenated().to_string();
+        let access_code = self.generate_access_code();
+        self.create_customer(customer_id, access_code.clone(), display_name.to_string()).await?;
+        Ok(access_code)
+    }
+
+    ///// Share links /////
+
+    async fn create_share_link(&self, link: &ShareLink) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO album_share_links (token, album_id, created_by, password_hash, allow_download, rendition, expires_at, created_at) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"
+        )
+        .bind(&link.token)
+        .bind(&link.album_id)
+        .bind(&link.created_by)
+        .bind(&link.password_hash)
+        .bind(link.allow_download)
+        .bind(&link.rendition)
+        .bind(link.expires_at)
+        .bind(link.created_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_share_link(&self, token: &str) -> Result<Option<ShareLink>> {
+        let link = sqlx::query_as::<_, ShareLink>(
+            "SELECT token, album_id, created_by, password_hash, allow_download, rendition, expires_at, created_at \
+             FROM album_share_links WHERE token = $1"
+        )
+        .bind(token)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(link)
+    }
+
+    async fn list_share_links_for_album(&self, album_id: &str) -> Result<Vec<ShareLink>> {
+        let links = sqlx::query_as::<_, ShareLink>(
+            "SELECT token, album_id, created_by, password_hash, allow_download, rendition, expires_at, created_at \
+             FROM album_share_links WHERE album_id = $1 ORDER BY created_at DESC"
+        )
+        .bind(album_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(links)
+    }
+
+    async fn revoke_share_links(&self, album_id: &str, token: Option<&str>) -> Result<u64> {
+        let result = match token {
+            Some(t) => {
+                sqlx::query("DELETE FROM album_share_links WHERE album_id = $1 AND token = $2")
+                    .bind(album_id)
+                    .bind(t)
+                    .execute(&self.pool)
+                    .await?
+            }
+            None => {
+                sqlx::query("DELETE FROM album_share_links WHERE album_id = $1")
+                    .bind(album_id)
+                    .execute(&self.pool)
+                    .await?
+            }
+        };
+
+        Ok(result.rows_affected())
+    }
+
+    ///// Watermarks /////
+
+    async fn get_album_watermark(&self, album_id: &str) -> Result<Option<AlbumWatermark>> {
+        let watermark = sqlx::query_as::<_, AlbumWatermark>(
+            "SELECT album_id, kind, text, image_path, position, opacity, scale, updated_at \
+             FROM album_watermarks WHERE album_id = $1"
+        )
+        .bind(album_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(watermark)
+    }
+
+    async fn set_album_watermark(&self, watermark: &AlbumWatermark) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO album_watermarks (album_id, kind, text, image_path, position, opacity, scale, updated_at) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8) \
+             ON CONFLICT (album_id) DO UPDATE SET \
+                kind = excluded.kind, text = excluded.text, image_path = excluded.image_path, \
+                position = excluded.position, opacity = excluded.opacity, scale = excluded.scale, \
+                updated_at = excluded.updated_at"
+        )
+        .bind(&watermark.album_id)
+        .bind(watermark.kind.as_str())
+        .bind(&watermark.text)
+        .bind(&watermark.image_path)
+        .bind(watermark.position.as_str())
+        .bind(watermark.opacity)
+        .bind(watermark.scale)
+        .bind(watermark.updated_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn delete_album_watermark(&self, album_id: &str) -> Result<()> {
+        sqlx::query("DELETE FROM album_watermarks WHERE album_id = $1")
+            .bind(album_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    ///// Copyright templates /////
+
+    async fn get_copyright_template(&self, scope: CopyrightScope, owner_id: &str) -> Result<Option<CopyrightTemplate>> {
+        let template = sqlx::query_as::<_, CopyrightTemplate>(
+            "SELECT creator, copyright_notice, credit_line, rights_url, contact_email, usage_terms \
+             FROM copyright_templates WHERE scope = $1 AND owner_id = $2"
+        )
+        .bind(scope.as_str())
+        .bind(owner_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(template)
+    }
+
+    async fn set_copyright_template(
+        &self,
+        scope: CopyrightScope,
+        owner_id: &str,
+        template: &CopyrightTemplate,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO copyright_templates \
+                (scope, owner_id, creator, copyright_notice, credit_line, rights_url, contact_email, usage_terms, updated_at) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, CURRENT_TIMESTAMP) \
+             ON CONFLICT (scope, owner_id) DO UPDATE SET \
+                creator = excluded.creator, copyright_notice = excluded.copyright_notice, \
+                credit_line = excluded.credit_line, rights_url = excluded.rights_url, \
+                contact_email = excluded.contact_email, usage_terms = excluded.usage_terms, \
+                updated_at = excluded.updated_at"
+        )
+        .bind(scope.as_str())
+        .bind(owner_id)
+        .bind(&template.creator)
+        .bind(&template.copyright_notice)
+        .bind(&template.credit_line)
+        .bind(&template.rights_url)
+        .bind(&template.contact_email)
+        .bind(&template.usage_terms)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn delete_copyright_template(&self, scope: CopyrightScope, owner_id: &str) -> Result<()> {
+        sqlx::query("DELETE FROM copyright_templates WHERE scope = $1 AND owner_id = $2")
+            .bind(scope.as_str())
+            .bind(owner_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+
+    ///// Mail templates /////
+
+    async fn get_mail_template(&self, owner_id: &str, kind: MailKind) -> Result<Option<MailTemplate>> {
+        let template = sqlx::query_as::<_, MailTemplate>(
+            "SELECT subject, body FROM mail_templates WHERE owner_id = $1 AND kind = $2"
+        )
+        .bind(owner_id)
+        .bind(kind.as_str())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(template)
+    }
+
+    async fn set_mail_template(&self, owner_id: &str, kind: MailKind, template: &MailTemplate) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO mail_templates (owner_id, kind, subject, body, updated_at) \
+             VALUES ($1, $2, $3, $4, CURRENT_TIMESTAMP) \
+             ON CONFLICT (owner_id, kind) DO UPDATE SET \
+                subject = excluded.subject, body = excluded.body, updated_at = excluded.updated_at"
+        )
+        .bind(owner_id)
+        .bind(kind.as_str())
+        .bind(&template.subject)
+        .bind(&template.body)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn delete_mail_template(&self, owner_id: &str, kind: MailKind) -> Result<()> {
+        sqlx::query("DELETE FROM mail_templates WHERE owner_id = $1 AND kind = $2")
+            .bind(owner_id)
+            .bind(kind.as_str())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+}
+
+impl SqliteDatabase {
+    #[inline]
+    fn generate_access_code(&self) -> String {
+        use rand::Rng;
+        const CHARSET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+        let mut rng = rand::thread_rng();
+        (0..8)
+            .map(|_| {
+                let idx = rng.gen_range(0..CHARSET.len());
+                CHARSET[idx] as char
+            })
+            .collect()
+    }
+
+    /// Generate and create a new customer with a generated access code
+    pub async fn create_customer_with_generated_code(&self, display_name: &str) -> Result<String> {
+        let customer_id = Uuid::new_v4().to_string();
+        let access_code = self.generate_access_code();
+
+        self.create_customer(customer_id.clone(), access_code, display_name.to_string()).await?;
+
+        Ok(customer_id)
+    }
+}
+
+/// Maps a row of the media listing queries, with video details and placeholder joined in.
+fn media_item_from_row(row: &sqlx::sqlite::SqliteRow) -> MediaItem {
+    MediaItem {
+        uuid: row.get("uuid"),
+        name: row.get("name"),
+        added_at: row.get("added_at"),
+        taken_at: row.get("taken_at"),
+        details: None,
+        tags: None,
+        location: None,
+        references: None,
+        video: row
+            .get::<Option<String>, _>("vd_media")
+            .map(|media| VideoDetails {
+                media,
+                duration_ms: row.get("duration_ms"),
+                width: row.get("width"),
+                height: row.get("height"),
+                video_codec: row.get("video_codec"),
+                audio_codec: row.get("audio_codec"),
+                frame_rate: row.get("frame_rate"),
+                poster_path: row.get("poster_path"),
+            }),
+        placeholder: row
+            .get::<Option<String>, _>("ph_media")
+            .map(|media| Placeholder {
+                media,
+                width: row.get("ph_width"),
+                height: row.get("ph_height"),
+                dominant_color: row.get("dominant_color"),
+                blurhash: row.get("blurhash"),
+            }),
     }
 }
 
@@ -870,6 +2047,7 @@ impl SqliteDatabase {
 mod tests {
     use super::*;
     use std::path::PathBuf;
+    use common::database::reference::ReferenceRole;
     use testdir::testdir;
 
     //noinspection DuplicatedCode
@@ -992,6 +2170,9 @@ mod tests {
                 .parse::<DateTime<Utc>>()
                 .unwrap(),
             is_missing: false,
+            mime_type: Some("image/jpeg".to_string()),
+            role: ReferenceRole::Original,
+            content_hash: Some(reference::content_hash(b"fake image data")),
         };
 
         // when
@@ -1,2 +1,5 @@
 pub mod router;
 pub(crate) mod routes;
+
+#[cfg(test)]
+pub(crate) mod test_util;
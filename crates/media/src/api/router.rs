@@ -19,8 +19,10 @@ use super::routes::delete_album_media::delete_album_media;
 use super::routes::delete_media_id::delete_media_id;
 use super::routes::get_albums::get_albums;
 use super::routes::get_albums_id::get_albums_id;
+use super::routes::get_albums_id_bursts::get_albums_id_bursts;
 use super::routes::get_media::get_media;
 use super::routes::get_media_id::get_media_id;
+use super::routes::get_media_id_similar::get_media_id_similar;
 use super::routes::patch_albums_id::patch_albums_id;
 use super::routes::patch_albums_id_share::patch_albums_id_share;
 use super::routes::patch_albums_id_unshare::patch_albums_id_unshare;
@@ -73,6 +75,7 @@ impl MediaApi {
             .route("/media/:media_id", patch(patch_media_id))
             // Deletes the given item owned by the user
             .route("/media/:media_id", delete(delete_media_id))
+            .route("/media/:media_id/similar", get(get_media_id_similar))
             // list owned and shared albums
             .route("/albums", get(get_albums))
             // create new album
@@ -81,6 +84,7 @@ impl MediaApi {
             .route("/albums/:entity_id", get(get_albums_id))
             // updates the given album owned by the user
             .route("/albums/:entity_id", patch(patch_albums_id))
+            .route("/albums/:album_id/bursts", get(get_albums_id_bursts))
             // upload file, create media item, and link to album
             .route("/albums/:album_id/media", post(post_albums_id_media))
             // remove a media item from an album and delete the file
@@ -96,29 +100,24 @@ impl MediaApi {
 
 #[cfg(test)]
 mod tests {
-    use std::collections::HashMap;
-
     use super::*;
+    use crate::api::test_util::{bearer_token, test_app, test_state};
     use axum::{
         body::Body,
         http::{self, Request, StatusCode},
     };
-    use common::config::configuration::Configuration;
+    use database::memory::MemoryDatabase;
     use database::sqlite::SqliteDatabase;
     use serde_json::json;
     use sqlx::SqlitePool;
     use tower::ServiceExt;
 
-    #[sqlx::test]
-    async fn get_media_with_query_success(pool: SqlitePool) {
+    #[tokio::test]
+    async fn get_media_with_query_success() {
         // given
-        let state: ApplicationState = ApplicationState {
-            config: Configuration::empty().into(),
-            plugins: HashMap::new(),
-            router: None,
-            database: Arc::new(SqliteDatabase { pool }),
-        };
-        let app = Router::new().nest("/", MediaApi::routes(&state).await);
+        let state = test_state(Arc::new(MemoryDatabase::new()));
+        let token = bearer_token(&state, "605ee8be-baf2-4499-b8d4-ba8c74e8b242").await;
+        let app = test_app(&state).await;
 
         // when
         let response = app
@@ -126,7 +125,7 @@ mod tests {
                 Request::builder()
                     .uri("/media?limit=100000&offset=1")
                     .method("GET")
-                    .header("Authorization", "FakeAuth")
+                    .header("Authorization", token)
                     .body(Body::empty())
                     .unwrap(),
             )
@@ -137,21 +136,17 @@ mod tests {
         assert_eq!(response.status(), StatusCode::OK);
 
         let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
-        let body: String = serde_json::from_slice(&body).unwrap();
+        let body: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
 
-        assert_eq!(body, "list media items. limit=100000, offset=1");
+        assert!(body.is_empty());
     }
 
-    #[sqlx::test]
-    async fn get_media_without_query_success(pool: SqlitePool) {
+    #[tokio::test]
+    async fn get_media_without_query_success() {
         // given
-        let state: ApplicationState = ApplicationState {
-            config: Configuration::empty().into(),
-            plugins: HashMap::new(),
-            router: None,
-            database: Arc::new(SqliteDatabase { pool }),
-        };
-        let app = Router::new().nest("/", MediaApi::routes(&state).await);
+        let state = test_state(Arc::new(MemoryDatabase::new()));
+        let token = bearer_token(&state, "605ee8be-baf2-4499-b8d4-ba8c74e8b242").await;
+        let app = test_app(&state).await;
 
         // when
         let response = app
@@ -159,7 +154,7 @@ mod tests {
                 Request::builder()
                     .uri("/media")
                     .method("GET")
-                    .header("Authorization", "FakeAuth")
+                    .header("Authorization", token)
                     .body(Body::empty())
                     .unwrap(),
             )
@@ -170,21 +165,16 @@ mod tests {
         assert_eq!(response.status(), StatusCode::OK);
 
         let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
-        let body: String = serde_json::from_slice(&body).unwrap();
+        let body: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
 
-        assert_eq!(body, "list media items. limit=1000, offset=0");
+        assert!(body.is_empty());
     }
 
     #[sqlx::test]
     async fn post_media_without_user_fail(pool: SqlitePool) {
         // given
-        let state: ApplicationState = ApplicationState {
-            config: Configuration::empty().into(),
-            plugins: HashMap::new(),
-            router: None,
-            database: Arc::new(SqliteDatabase { pool }),
-        };
-        let app = Router::new().nest("/", MediaApi::routes(&state).await);
+        let state = test_state(Arc::new(SqliteDatabase { pool }));
+        let app = test_app(&state).await;
 
         // when
         let response = app
@@ -14,9 +14,57 @@
  * You should have received a copy of the GNU Affero General Public License
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
+//! Returns a single album with its media items
+//!
+use axum::{
+    extract::{Extension, Path},
+    http::StatusCode,
+    Json,
+};
+use common::{
+    auth::{
+        permissions::{has_album_permission, AlbumPermission},
+        user::User,
+    },
+    database::{media_item::MediaItem, ArcDynDatabase},
+};
+use serde::Serialize;
+use tracing::error;
 
-use axum::http::StatusCode;
+use super::get_albums::AlbumResponse;
 
-pub(crate) async fn get_albums_id() -> std::result::Result<String, StatusCode> {
-    Err(StatusCode::NOT_IMPLEMENTED)
+#[derive(Serialize)]
+pub struct AlbumDetailResponse {
+    #[serde(flatten)]
+    pub album: AlbumResponse,
+    /// In album order, each with its placeholder once indexed.
+    pub media: Vec<MediaItem>,
+}
+
+pub(crate) async fn get_albums_id(
+    Extension(db): Extension<ArcDynDatabase>,
+    Path(album_id): Path<String>,
+    user: User,
+) -> Result<Json<AlbumDetailResponse>, StatusCode> {
+    if !has_album_permission(&db, &user.uuid, &album_id, AlbumPermission::Read)
+        .await
+        .unwrap_or(false)
+    {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let album = db
+        .get_album(&album_id)
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+    match db.get_media_for_album(&album_id).await {
+        Ok(media) => Ok(Json(AlbumDetailResponse {
+            album: album.into(),
+            media,
+        })),
+        Err(e) => {
+            error!("Failed to get media of album {}: {:?}", album_id, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
 }
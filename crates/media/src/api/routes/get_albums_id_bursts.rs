@@ -0,0 +1,83 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Groups the images of an album into bursts for culling
+//!
+use axum::{
+    extract::{Extension, Path, Query},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use chrono::Duration;
+use common::{
+    auth::{
+        permissions::{has_album_permission, AlbumPermission},
+        user::User,
+    },
+    database::ArcDynDatabase,
+    similarity::{group_bursts, DEFAULT_BURST_GAP_SECS, DEFAULT_MAX_DISTANCE},
+};
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct BurstQuery {
+    /// Longest pause between two frames of a burst.
+    max_gap_secs: Option<i64>,
+    /// Largest Hamming distance between consecutive frames, 0 - 64.
+    max_distance: Option<u32>,
+}
+
+pub(crate) async fn get_albums_id_bursts(
+    Extension(db): Extension<ArcDynDatabase>,
+    Path(album_id): Path<String>,
+    Query(query): Query<BurstQuery>,
+    user: User,
+) -> impl IntoResponse {
+    if !has_album_permission(&db, &user.uuid, &album_id, AlbumPermission::Owner)
+        .await
+        .unwrap_or(false)
+    {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(serde_json::json!({"error": "Owner access required"})),
+        )
+            .into_response();
+    }
+
+    let max_gap_secs = query.max_gap_secs.unwrap_or(DEFAULT_BURST_GAP_SECS);
+    let max_distance = query.max_distance.unwrap_or(DEFAULT_MAX_DISTANCE);
+    if !(0..=3600).contains(&max_gap_secs) || max_distance > 64 {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"error": "max_gap_secs must be 0 - 3600 and max_distance 0 - 64"})),
+        )
+            .into_response();
+    }
+
+    match db.get_image_hashes_for_album(&album_id).await {
+        Ok(hashes) => {
+            let bursts = group_bursts(hashes, Duration::seconds(max_gap_secs), max_distance);
+            (StatusCode::OK, Json(bursts)).into_response()
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
+}
@@ -39,56 +39,41 @@ pub(crate) async fn get_media(
     State(repo): State<MediaRepositoryState>,
     user: User,
     Query(query): Query<MediaListQuery>,
-) -> Result<Json<String>, StatusCode> {
+) -> Result<Json<Vec<MediaItem>>, StatusCode> {
     let items: Result<Vec<MediaItem>, DataAccessError> = repo
         .get_media_items_for_user(Uuid::parse_str(user.uuid.as_str()).unwrap())
         .await;
     match items {
-        Ok(i) => {
-            error!("Found {} items for user.", i.len());
+        Ok(items) => {
+            let offset = query.offset.unwrap_or(0).max(0) as usize;
+            let limit = query.limit.unwrap_or(1000).max(0) as usize;
+            Ok(Json(items.into_iter().skip(offset).take(limit).collect()))
         }
         Err(_) => {
             error!("Failed to get media items!");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
         }
     }
-    // TODO: read list from persistency
-    // TODO: return list
-    Ok(Json(
-        format!(
-            "list media items. limit={}, offset={}",
-            query.limit.unwrap_or(1000),
-            query.offset.unwrap_or(0)
-        )
-        .to_owned(),
-    ))
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{collections::HashMap, sync::Arc};
+    use std::sync::Arc;
 
-    use axum::Router;
-    use common::{config::configuration::Configuration, ApplicationState};
     use database::sqlite::SqliteDatabase;
     use hyper::{Body, Request};
     use sqlx::SqlitePool;
     use tower::ServiceExt;
 
-    use crate::api::router::MediaApi;
+    use crate::api::test_util::{test_app, test_state};
 
     use super::*;
 
     #[sqlx::test]
     async fn get_media_unauthorized_should_not_fail(pool: SqlitePool) {
         // given
-        let state: ApplicationState = ApplicationState {
-            config: Configuration::empty().into(),
-            plugins: HashMap::new(),
-            router: None,
-            database: Arc::new(SqliteDatabase { pool }),
-        };
-
-        let app = Router::new().nest("/", MediaApi::routes(&state).await);
+        let state = test_state(Arc::new(SqliteDatabase { pool }));
+        let app = test_app(&state).await;
 
         // when
         let response = app
@@ -0,0 +1,74 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Returns near duplicates of a media item among the current user's images
+//!
+use axum::{
+    extract::{Extension, Path, Query},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use common::{
+    auth::user::User,
+    database::ArcDynDatabase,
+    similarity::{find_similar, DEFAULT_MAX_DISTANCE},
+};
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct SimilarQuery {
+    /// Largest Hamming distance between two hashes, 0 - 64.
+    max_distance: Option<u32>,
+}
+
+pub(crate) async fn get_media_id_similar(
+    Extension(db): Extension<ArcDynDatabase>,
+    Path(media_id): Path<String>,
+    Query(query): Query<SimilarQuery>,
+    user: User,
+) -> impl IntoResponse {
+    let max_distance = query.max_distance.unwrap_or(DEFAULT_MAX_DISTANCE);
+    if max_distance > 64 {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"error": "max_distance must be between 0 and 64"})),
+        )
+            .into_response();
+    }
+
+    let hashes = match db.get_image_hashes_for_owner(&user.uuid).await {
+        Ok(hashes) => hashes,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({"error": e.to_string()})),
+            )
+                .into_response()
+        }
+    };
+
+    // Unknown, foreign and not yet hashed media items look the same to the caller.
+    match find_similar(&media_id, &hashes, max_distance) {
+        Some(similar) => (StatusCode::OK, Json(similar)).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error": "No perceptual hash for this media item"})),
+        )
+            .into_response(),
+    }
+}
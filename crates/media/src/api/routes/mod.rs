@@ -2,8 +2,10 @@ pub(crate) mod delete_album_media;
 pub(crate) mod delete_media_id;
 pub(crate) mod get_albums;
 pub(crate) mod get_albums_id;
+pub(crate) mod get_albums_id_bursts;
 pub(crate) mod get_media;
 pub(crate) mod get_media_id;
+pub(crate) mod get_media_id_similar;
 pub(crate) mod patch_albums_id;
 pub(crate) mod patch_albums_id_share;
 pub(crate) mod patch_albums_id_unshare;
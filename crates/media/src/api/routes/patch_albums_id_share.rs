@@ -15,8 +15,113 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use axum::http::StatusCode;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use common::{
+    auth::{
+        permissions::{has_album_permission, AlbumPermission},
+        user::User,
+    },
+    database::{share_link::ShareLink, ArcDynDatabase},
+    rendition::Rendition,
+};
+use serde::Deserialize;
+use tracing::error;
 
-pub(crate) async fn patch_albums_id_share() -> std::result::Result<String, StatusCode> {
-    Err(StatusCode::NOT_IMPLEMENTED)
+use crate::repository::MediaRepositoryState;
+
+#[derive(Deserialize)]
+pub struct ShareAlbumRequest {
+    pub password: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub allow_download: bool,
+    #[serde(default)]
+    pub rendition: Rendition,
+}
+
+pub(crate) async fn patch_albums_id_share(
+    State(_repo): State<MediaRepositoryState>,
+    Extension(db): Extension<ArcDynDatabase>,
+    Path(album_id): Path<String>,
+    user: User,
+    Json(body): Json<ShareAlbumRequest>,
+) -> impl IntoResponse {
+    if !has_album_permission(&db, &user.uuid, &album_id, AlbumPermission::Owner)
+        .await
+        .unwrap_or(false)
+    {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(serde_json::json!({"error": "Owner access required"})),
+        )
+            .into_response();
+    }
+
+    if db.get_album(&album_id).await.is_err() {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error": "Album not found"})),
+        )
+            .into_response();
+    }
+
+    if body.expires_at.is_some_and(|at| at <= Utc::now()) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"error": "expires_at must be in the future"})),
+        )
+            .into_response();
+    }
+
+    let password_hash = match body.password.filter(|p| !p.is_empty()) {
+        Some(password) => match bcrypt::hash(&password, bcrypt::DEFAULT_COST) {
+            Ok(hash) => Some(hash),
+            Err(e) => {
+                error!("Failed to hash share link password: {:?}", e);
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(serde_json::json!({"error": "Failed to hash password"})),
+                )
+                    .into_response();
+            }
+        },
+        None => None,
+    };
+
+    let link = ShareLink {
+        token: ShareLink::generate_token(),
+        album_id,
+        created_by: user.uuid.clone(),
+        password_hash,
+        allow_download: body.allow_download,
+        rendition: body.rendition.to_string(),
+        expires_at: body.expires_at,
+        created_at: Utc::now(),
+    };
+
+    match db.create_share_link(&link).await {
+        Ok(()) => (
+            StatusCode::CREATED,
+            Json(serde_json::json!({
+                "token": link.token,
+                "url": format!("/share/{}", link.token),
+                "allow_download": link.allow_download,
+                "rendition": link.rendition,
+                "expires_at": link.expires_at,
+                "password_protected": link.is_password_protected(),
+            })),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
 }
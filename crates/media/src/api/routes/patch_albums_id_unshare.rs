@@ -15,8 +15,67 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use axum::http::StatusCode;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use common::{
+    auth::{
+        permissions::{has_album_permission, AlbumPermission},
+        user::User,
+    },
+    database::ArcDynDatabase,
+};
+use serde::Deserialize;
 
-pub(crate) async fn patch_albums_id_unshare() -> std::result::Result<String, StatusCode> {
-    Err(StatusCode::NOT_IMPLEMENTED)
+use crate::repository::MediaRepositoryState;
+
+#[derive(Deserialize, Default)]
+pub struct UnshareAlbumRequest {
+    /// Revokes only this link; all links of the album are revoked when omitted.
+    pub token: Option<String>,
+}
+
+pub(crate) async fn patch_albums_id_unshare(
+    State(_repo): State<MediaRepositoryState>,
+    Extension(db): Extension<ArcDynDatabase>,
+    Path(album_id): Path<String>,
+    user: User,
+    body: Option<Json<UnshareAlbumRequest>>,
+) -> impl IntoResponse {
+    if !has_album_permission(&db, &user.uuid, &album_id, AlbumPermission::Owner)
+        .await
+        .unwrap_or(false)
+    {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(serde_json::json!({"error": "Owner access required"})),
+        )
+            .into_response();
+    }
+
+    let body = body.map(|Json(b)| b).unwrap_or_default();
+
+    match db
+        .revoke_share_links(&album_id, body.token.as_deref())
+        .await
+    {
+        Ok(0) if body.token.is_some() => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error": "Share link not found"})),
+        )
+            .into_response(),
+        Ok(revoked) => (
+            StatusCode::OK,
+            Json(serde_json::json!({"status": "unshared", "revoked": revoked})),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
 }
@@ -29,6 +29,7 @@ use std::path::Path as FsPath;
 use std::sync::Arc;
 use uuid::Uuid;
 
+use crate::data::error::DataAccessError;
 use crate::repository::MediaRepositoryState;
 
 pub(crate) async fn post_albums_id_media(
@@ -81,6 +82,14 @@ pub(crate) async fn post_albums_id_media(
         }
     };
 
+    if let Err(DataAccessError::UnsupportedMediaType(mime_type)) = repo.check_upload_type(&bytes) {
+        return (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Json(serde_json::json!({"error": format!("File type {} is not allowed", mime_type)})),
+        )
+            .into_response();
+    }
+
     let user_id = match uuid::Uuid::parse_str(&user.uuid) {
         Ok(id) => id,
         Err(_) => {
@@ -92,12 +101,21 @@ pub(crate) async fn post_albums_id_media(
         }
     };
 
-    let media_id = match repo
-        .create_media_item_for_user(user_id, filename.clone(), Utc::now())
-        .await
-    {
-        Ok(id) => id.hyphenated().to_string(),
-        Err(_) => Uuid::new_v4().hyphenated().to_string(),
+    // RAW+JPEG pairs, Live Photos and XMP sidecars share a basename and become one media item.
+    let sibling = repo
+        .find_sibling_media(user_id, Some(&album_id), &filename)
+        .await;
+    let grouped = sibling.is_some();
+
+    let media_id = match sibling {
+        Some(id) => id,
+        None => match repo
+            .create_media_item_for_user(user_id, filename.clone(), Utc::now())
+            .await
+        {
+            Ok(id) => id.hyphenated().to_string(),
+            Err(_) => Uuid::new_v4().hyphenated().to_string(),
+        },
     };
 
     let storage_dir = FsPath::new("data/files/").join(&user.uuid).join(&media_id);
@@ -121,12 +139,14 @@ pub(crate) async fn post_albums_id_media(
             .into_response();
     }
 
-    if let Err(e) = repo.add_media_to_album(&album_id, &media_id).await {
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(serde_json::json!({"error": format!("Album link failed: {:?}", e)})),
-        )
-            .into_response();
+    if !grouped {
+        if let Err(e) = repo.add_media_to_album(&album_id, &media_id).await {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({"error": format!("Album link failed: {:?}", e)})),
+            )
+                .into_response();
+        }
     }
 
     // Invalidate stale cache and schedule eager re-generation after the debounce window.
@@ -135,7 +155,7 @@ pub(crate) async fn post_albums_id_media(
 
     (
         StatusCode::CREATED,
-        Json(serde_json::json!({"media_id": media_id, "name": filename})),
+        Json(serde_json::json!({"media_id": media_id, "name": filename, "grouped": grouped})),
     )
         .into_response()
 }
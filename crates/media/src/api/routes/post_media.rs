@@ -65,12 +65,18 @@ pub(crate) async fn post_media(
         return Err(StatusCode::BAD_REQUEST);
     }
 
+    let user_id = Uuid::parse_str(user.uuid.as_str()).unwrap();
+
+    // RAW+JPEG pairs, Live Photos and XMP sidecars share a basename and become one media item.
+    if let Some(id) = repo
+        .find_sibling_media(user_id, None, name.as_deref().unwrap())
+        .await
+    {
+        return Ok(Redirect::to(&format!("/media/{id}")).into_response());
+    }
+
     let result = repo
-        .create_media_item_for_user(
-            Uuid::parse_str(user.uuid.as_str()).unwrap(),
-            name.clone().unwrap(),
-            date.unwrap(),
-        )
+        .create_media_item_for_user(user_id, name.clone().unwrap(), date.unwrap())
         .await;
 
     match result {
@@ -101,12 +107,10 @@ pub(crate) async fn post_media(
 
 #[cfg(test)]
 mod tests {
-    use std::collections::HashMap;
     use std::io;
     use std::sync::Arc;
 
-    use axum::Router;
-    use common::{config::configuration::Configuration, ApplicationState};
+    use database::memory::MemoryDatabase;
     use database::sqlite::SqliteDatabase;
     use hyper::{Body, Request};
     use mime::BOUNDARY;
@@ -114,7 +118,7 @@ mod tests {
     use tokio::fs::File;
     use tower::ServiceExt;
 
-    use crate::api::router::MediaApi;
+    use crate::api::test_util::{bearer_token, test_app, test_state};
     use axum::http::header::CONTENT_TYPE;
     use std::io::Write;
     use std::path::PathBuf;
@@ -126,14 +130,8 @@ mod tests {
     #[sqlx::test]
     async fn post_media_unauthorized_should_fail(pool: SqlitePool) {
         // given
-        let state: ApplicationState = ApplicationState {
-            config: Configuration::empty().into(),
-            plugins: HashMap::new(),
-            router: None,
-            database: Arc::new(SqliteDatabase { pool }),
-        };
-
-        let app = Router::new().nest("/", MediaApi::routes(&state).await);
+        let state = test_state(Arc::new(SqliteDatabase { pool }));
+        let app = test_app(&state).await;
 
         // when
         let response = app
@@ -151,17 +149,13 @@ mod tests {
         assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
     }
 
-    #[sqlx::test]
+    #[tokio::test]
     #[ignore]
-    async fn post_media_authorized_without_name_field(pool: SqlitePool) {
+    async fn post_media_authorized_without_name_field() {
         // given
-        let state: ApplicationState = ApplicationState {
-            config: Configuration::empty().into(),
-            plugins: HashMap::new(),
-            router: None,
-            database: Arc::new(SqliteDatabase { pool }),
-        };
-        let app = Router::new().nest("/", MediaApi::routes(&state).await);
+        let state = test_state(Arc::new(MemoryDatabase::new()));
+        let token = bearer_token(&state, "605ee8be-baf2-4499-b8d4-ba8c74e8b242").await;
+        let app = test_app(&state).await;
         let data = media_item_form_data().await.unwrap();
 
         // when
@@ -170,7 +164,7 @@ mod tests {
                 Request::builder()
                     .method("POST")
                     .uri("/media")
-                    .header(hyper::header::AUTHORIZATION, "FakeAuth")
+                    .header(hyper::header::AUTHORIZATION, token)
                     .header(
                         CONTENT_TYPE,
                         format!("multipart/form-data; boundary={}", BOUNDARY),
@@ -85,6 +85,7 @@ pub(crate) async fn post_media_id(
             DataAccessError::AlreadyExist(id) => {
                 Ok(Redirect::to(&format!("/media/{media_id}/{id}")).into_response())
             }
+            DataAccessError::UnsupportedMediaType(_) => Err(StatusCode::UNSUPPORTED_MEDIA_TYPE),
             _ => Err(StatusCode::INTERNAL_SERVER_ERROR),
         },
     }
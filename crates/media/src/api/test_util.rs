@@ -0,0 +1,83 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Fixtures shared by the API tests.
+//!
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::{Extension, Router};
+use common::auth::account_token::AccountMailSettings;
+use common::auth::auth_manager::AuthManager;
+use common::auth::keys::{JwtKey, KeyRing};
+use common::auth::session::TrustedProxies;
+use common::auth::webauthn::RelyingParty;
+use common::config::configuration::Configuration;
+use common::database::ArcDynDatabase;
+use common::mail::outbox::OutboxMailer;
+use common::ApplicationState;
+
+use crate::api::router::MediaApi;
+
+/// Application state around the given database, signing tokens with a test key.
+pub(crate) fn test_state(database: ArcDynDatabase) -> ApplicationState {
+    ApplicationState {
+        config: Configuration::empty().into(),
+        plugins: HashMap::new(),
+        router: None,
+        database,
+        keys: Arc::new(KeyRing::new(vec![JwtKey::new("test", b"test-secret")], None).unwrap()),
+        relying_party: Arc::new(RelyingParty {
+            id: "localhost".into(),
+            name: "Photos.network".into(),
+            origins: vec![],
+        }),
+        mailer: Arc::new(OutboxMailer::new("noreply@localhost", None)),
+        account_mail: Arc::new(AccountMailSettings {
+            verify_email_url: String::new(),
+            password_reset_url: String::new(),
+            require_verified_email: false,
+        }),
+        trusted_proxies: Arc::new(TrustedProxies::default()),
+    }
+}
+
+/// The media routes with the extensions the application layers around them.
+pub(crate) async fn test_app(state: &ApplicationState) -> Router {
+    Router::new()
+        .nest("/", MediaApi::routes(state).await)
+        .layer(Extension(Arc::clone(&state.database)))
+        .layer(Extension(Arc::clone(&state.keys)))
+}
+
+/// Creates an account and returns an `Authorization` header value for it.
+pub(crate) async fn bearer_token(state: &ApplicationState, account_id: &str) -> String {
+    state
+        .database
+        .create_account(
+            account_id.to_string(),
+            format!("{}@localhost", account_id),
+            String::new(),
+            None,
+        )
+        .await
+        .unwrap();
+    let token =
+        AuthManager::generate_access_token(&state.keys, account_id, "account", false, "session")
+            .unwrap();
+    format!("Bearer {}", token)
+}
@@ -22,6 +22,8 @@ pub enum DataAccessError {
     #[allow(dead_code)]
     InvalidDateFormat,
     AlreadyExist(String),
+    /// The detected MIME type is not on the configured allow-list.
+    UnsupportedMediaType(String),
     TechnicalError,
     #[allow(dead_code)]
     OtherError,
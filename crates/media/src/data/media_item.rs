@@ -15,6 +15,7 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use common::database::placeholder::Placeholder;
 use serde::{Deserialize, Serialize};
 use sqlx::types::chrono::{DateTime, Utc};
 
@@ -32,4 +33,6 @@ pub struct MediaItem {
     pub tags: Option<Vec<String>>,
     pub location: Option<Location>,
     pub references: Option<Vec<File>>,
+    /// Size, dominant color and BlurHash for laying out grids before images load.
+    pub placeholder: Option<Placeholder>,
 }
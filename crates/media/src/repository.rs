@@ -22,8 +22,12 @@ use axum::async_trait;
 use bytes::Bytes;
 use common::config::configuration::Configuration;
 use common::database::album::Album;
-use common::database::reference::Reference;
+use common::database::reference::{basename, content_hash, Reference, ReferenceRole};
 use common::database::ArcDynDatabase;
+use common::media_type::{detect_mime_type, is_mime_type_allowed};
+use common::jobs::{enqueue, Job};
+use common::rendition;
+use common::video::is_video;
 use sqlx::types::chrono::{DateTime, Utc};
 use std::fs;
 use std::path::Path;
@@ -63,6 +67,19 @@ pub trait MediaRepositoryTrait {
         bytes: Bytes,
     ) -> Result<Uuid, DataAccessError>;
 
+    /// Returns the media item holding a related file with the same basename, looking in the
+    /// album if given and in all items of the user otherwise. Uploading a file name that the
+    /// item already contains starts a new item instead of replacing it.
+    async fn find_sibling_media(
+        &self,
+        user_id: Uuid,
+        album_id: Option<&str>,
+        filename: &str,
+    ) -> Option<String>;
+
+    /// Detects the MIME type of an upload and checks it against the configured allow-list.
+    fn check_upload_type(&self, bytes: &[u8]) -> Result<String, DataAccessError>;
+
     async fn get_albums_for_user(&self, user_id: Uuid) -> Result<Vec<Album>, DataAccessError>;
 
     async fn create_album(
@@ -112,6 +129,7 @@ impl MediaRepositoryTrait for MediaRepository {
                         tags: None,
                         location: None,
                         references: None,
+                        placeholder: d.placeholder.clone(),
                     })
                     .collect())
             }
@@ -148,6 +166,18 @@ impl MediaRepositoryTrait for MediaRepository {
         name: String,
         bytes: Bytes,
     ) -> Result<Uuid, DataAccessError> {
+        let mime_type = self.check_upload_type(&bytes)?;
+
+        let siblings = self
+            .database
+            .get_reference_files(media_id)
+            .await
+            .unwrap_or_default();
+        let has_photo_sibling = siblings
+            .iter()
+            .any(|f| f.mime_type.as_deref().is_some_and(|m| m.starts_with("image/")));
+        let role = ReferenceRole::classify(&mime_type, &name, has_photo_sibling);
+
         let path = Path::new("data/files/")
             .join(user_id.hyphenated().to_string())
             .join(media_id);
@@ -180,6 +210,9 @@ impl MediaRepositoryTrait for MediaRepository {
             description: String::new(),
             last_modified: Utc::now(),
             is_missing: false,
+            mime_type: Some(mime_type.clone()),
+            role,
+            content_hash: Some(content_hash(&bytes)),
         };
         let db_result = &self
             .database
@@ -192,7 +225,52 @@ impl MediaRepositoryTrait for MediaRepository {
 
         match db_result {
             Ok(uuid) => {
-                info!("added reference with id {}", uuid.clone());
+                info!("added {} reference with id {}", role, uuid.clone());
+                // A video uploaded before its photo is the motion part of a Live Photo.
+                if role == ReferenceRole::Original && mime_type.starts_with("image/") {
+                    for video in siblings.iter().filter(|f| {
+                        f.role == ReferenceRole::Original
+                            && f.mime_type.as_deref().is_some_and(is_video)
+                    }) {
+                        if let Err(e) = self
+                            .database
+                            .set_reference_role(media_id, &video.filename, ReferenceRole::LiveVideo)
+                            .await
+                        {
+                            warn!("Could not mark {} as live video: {:?}", video.filename, e);
+                            continue;
+                        }
+                        let _ = self.database.delete_video_details(media_id).await;
+                        rendition::invalidate(media_id).await;
+                    }
+                }
+                // Probing, indexing and thumbnailing can take a while — don't hold the upload.
+                let mut jobs = Vec::new();
+                if role == ReferenceRole::Original && is_video(&mime_type) {
+                    jobs.push(Job::IndexVideo {
+                        media_id: media_id.to_string(),
+                        source: file_path.clone(),
+                    });
+                } else if role == ReferenceRole::Original && mime_type.starts_with("image/") {
+                    jobs.push(Job::IndexImage {
+                        reference_id: uuid.clone(),
+                        media_id: media_id.to_string(),
+                        source: file_path.clone(),
+                        mime_type: mime_type.clone(),
+                    });
+                }
+                if !jobs.is_empty() {
+                    jobs.push(Job::GenerateRenditions {
+                        media_id: media_id.to_string(),
+                        source: file_path.clone(),
+                        mime_type: mime_type.clone(),
+                    });
+                }
+                for job in jobs {
+                    if let Err(e) = enqueue(&self.database, job).await {
+                        warn!("Could not queue processing of media {}: {:?}", media_id, e);
+                    }
+                }
                 Ok(Uuid::parse_str(uuid.as_str()).unwrap())
             }
             Err(e) => {
@@ -202,6 +280,49 @@ impl MediaRepositoryTrait for MediaRepository {
         }
     }
 
+    async fn find_sibling_media(
+        &self,
+        user_id: Uuid,
+        album_id: Option<&str>,
+        filename: &str,
+    ) -> Option<String> {
+        let media_id = match album_id {
+            Some(album_id) => {
+                self.database
+                    .find_album_media_by_basename(album_id, basename(filename))
+                    .await
+            }
+            None => {
+                self.database
+                    .find_owned_media_by_basename(
+                        user_id.hyphenated().to_string().as_str(),
+                        basename(filename),
+                    )
+                    .await
+            }
+        }
+        .ok()
+        .flatten()?;
+
+        let files = self.database.get_reference_files(&media_id).await.ok()?;
+        if files.iter().any(|f| f.filename.eq_ignore_ascii_case(filename)) {
+            return None;
+        }
+
+        Some(media_id)
+    }
+
+    fn check_upload_type(&self, bytes: &[u8]) -> Result<String, DataAccessError> {
+        let mime_type = detect_mime_type(bytes).unwrap_or("application/octet-stream");
+
+        if is_mime_type_allowed(mime_type, &self.config.media.allowed_mime_types) {
+            Ok(mime_type.to_string())
+        } else {
+            warn!("Rejected upload of type {}", mime_type);
+            Err(DataAccessError::UnsupportedMediaType(mime_type.to_string()))
+        }
+    }
+
     async fn get_albums_for_user(&self, user_id: Uuid) -> Result<Vec<Album>, DataAccessError> {
         self.database
             .get_albums_for_user(user_id.hyphenated().to_string().as_str())
@@ -240,10 +361,11 @@ impl MediaRepositoryTrait for MediaRepository {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use database::memory::MemoryDatabase;
     use database::sqlite::SqliteDatabase;
     use sqlx::SqlitePool;
 
-    #[sqlx::test]
+    #[sqlx::test(migrations = "../database/migrations")]
     async fn get_media_items_should_succeed(pool: SqlitePool) -> Result<()> {
         // given
         let user_id = "605EE8BE-BAF2-4499-B8D4-BA8C74E8B242";
@@ -260,4 +382,64 @@ mod tests {
 
         Ok(())
     }
+
+    #[sqlx::test]
+    async fn check_upload_type_should_reject_disallowed_types(pool: SqlitePool) -> Result<()> {
+        // given
+        let mut config = Configuration::empty();
+        config.media.allowed_mime_types = vec!["image/*".into()];
+        let repository = MediaRepository::new(Arc::new(SqliteDatabase { pool }), config.into()).await;
+
+        // when
+        let jpeg = repository.check_upload_type(&[0xFF, 0xD8, 0xFF, 0xE0]);
+        let text = repository.check_upload_type(b"#!/bin/sh");
+
+        // then
+        assert_eq!(jpeg.unwrap(), "image/jpeg");
+        assert!(matches!(text, Err(DataAccessError::UnsupportedMediaType(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn add_photo_after_video_should_make_it_live_video() -> Result<()> {
+        // given
+        let user_id = Uuid::new_v4();
+        let mut config = Configuration::empty();
+        config.media.allowed_mime_types = vec!["image/*".into(), "video/*".into()];
+        let db: ArcDynDatabase = Arc::new(MemoryDatabase::new());
+        let repository = MediaRepository::new(db.clone(), config.into()).await;
+        let media_id = repository
+            .create_media_item_for_user(user_id, "IMG_0001".into(), Utc::now())
+            .await
+            .unwrap()
+            .hyphenated()
+            .to_string();
+        let mov = Bytes::from_static(b"\0\0\0\x14ftypqt  \0\0\0\0qt  ");
+        repository
+            .add_reference_for_media_item(user_id, &media_id, "IMG_0001.MOV".into(), mov)
+            .await
+            .unwrap();
+
+        // when
+        let jpeg = Bytes::from_static(&[0xFF, 0xD8, 0xFF, 0xE0]);
+        let result = repository
+            .add_reference_for_media_item(user_id, &media_id, "IMG_0001.JPG".into(), jpeg)
+            .await;
+        let files = db.get_reference_files(&media_id).await?;
+        let _ = fs::remove_dir_all(Path::new("data/files/").join(user_id.hyphenated().to_string()));
+
+        // then
+        assert!(result.is_ok());
+        let roles: Vec<_> = files.iter().map(|f| (f.filename.as_str(), f.role)).collect();
+        assert_eq!(
+            roles,
+            vec![
+                ("IMG_0001.JPG", ReferenceRole::Original),
+                ("IMG_0001.MOV", ReferenceRole::LiveVideo),
+            ]
+        );
+
+        Ok(())
+    }
 }
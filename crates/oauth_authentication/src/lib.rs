@@ -19,269 +19,166 @@
 //!
 //! To identify users and granting them access to the applications content, the Open Authorization (OAuth) standard is used so users can login without sharing credentials theirselfs.
 //!
-use anyhow::{anyhow, Ok};
-use axum::Router;
-use openidconnect::core::{
-    CoreAuthenticationFlow, CoreClient, CoreProviderMetadata, CoreResponseType, CoreUserInfoClaims,
-};
-use openidconnect::{
-    AccessTokenHash, AuthorizationCode, ClientId, ClientSecret, CsrfToken,
-    EmptyAdditionalProviderMetadata, IssuerUrl, Nonce, PkceCodeChallenge, PkceCodeVerifier,
-    ProviderMetadata, RedirectUrl, Scope,
-};
+//! Each client with an `issuer` in the configuration is an external OpenID Connect provider.
+//! A user signing in through one is linked to the account with the same email once that
+//! account verified it, or gets a new account if the provider allows sign-ups for their address.
+//!
+use std::sync::Arc;
 
-use anyhow::Result;
-use openidconnect::reqwest::http_client;
-use thiserror::Error;
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use common::auth::account::Account;
+use common::auth::account_token::EmailNotVerified;
+use common::auth::auth_manager::AuthManager;
+use common::auth::keys::KeyRing;
+use common::config::client::OAuthClientConfig;
+use common::database::ArcDynDatabase;
+use tracing::{error, info};
 
-// Use OpenID Connect Discovery to fetch the provider metadata.
-use openidconnect::{OAuth2TokenResponse, TokenResponse};
+pub mod provider;
 
-pub struct AuthenticationManager {
-    #[allow(clippy::type_complexity)]
-    pub client: openidconnect::Client<
-        openidconnect::EmptyAdditionalClaims,
-        openidconnect::core::CoreAuthDisplay,
-        openidconnect::core::CoreGenderClaim,
-        openidconnect::core::CoreJweContentEncryptionAlgorithm,
-        openidconnect::core::CoreJwsSigningAlgorithm,
-        openidconnect::core::CoreJsonWebKeyType,
-        openidconnect::core::CoreJsonWebKeyUse,
-        openidconnect::core::CoreJsonWebKey,
-        openidconnect::core::CoreAuthPrompt,
-        openidconnect::StandardErrorResponse<openidconnect::core::CoreErrorResponseType>,
-        openidconnect::StandardTokenResponse<
-            openidconnect::IdTokenFields<
-                openidconnect::EmptyAdditionalClaims,
-                openidconnect::EmptyExtraTokenFields,
-                openidconnect::core::CoreGenderClaim,
-                openidconnect::core::CoreJweContentEncryptionAlgorithm,
-                openidconnect::core::CoreJwsSigningAlgorithm,
-                openidconnect::core::CoreJsonWebKeyType,
-            >,
-            openidconnect::core::CoreTokenType,
-        >,
-        openidconnect::core::CoreTokenType,
-        openidconnect::StandardTokenIntrospectionResponse<
-            openidconnect::EmptyExtraTokenFields,
-            openidconnect::core::CoreTokenType,
-        >,
-        openidconnect::core::CoreRevocableToken,
-        openidconnect::StandardErrorResponse<openidconnect::RevocationErrorResponseType>,
-    >,
-    #[allow(clippy::type_complexity)]
-    pub provider_metadata: ProviderMetadata<
-        EmptyAdditionalProviderMetadata,
-        openidconnect::core::CoreAuthDisplay,
-        openidconnect::core::CoreClientAuthMethod,
-        openidconnect::core::CoreClaimName,
-        openidconnect::core::CoreClaimType,
-        openidconnect::core::CoreGrantType,
-        openidconnect::core::CoreJweContentEncryptionAlgorithm,
-        openidconnect::core::CoreJweKeyManagementAlgorithm,
-        openidconnect::core::CoreJwsSigningAlgorithm,
-        openidconnect::core::CoreJsonWebKeyType,
-        openidconnect::core::CoreJsonWebKeyUse,
-        openidconnect::core::CoreJsonWebKey,
-        openidconnect::core::CoreResponseMode,
-        CoreResponseType,
-        openidconnect::core::CoreSubjectIdentifierType,
-    >,
-    pub pkce_challenge: PkceCodeChallenge,
-    pub pkce_verifier: PkceCodeVerifier,
-}
+pub use provider::{ExternalIdentity, ExternalProvider, PendingLogin};
 
-impl AuthenticationManager {
-    pub fn routes<S>() -> Router<S>
-    where
-        S: Send + Sync + 'static + Clone,
-    {
-        Router::new().layer(tower_http::trace::TraceLayer::new_for_http())
-    }
+pub struct AuthenticationManager {
+    providers: Vec<ExternalProvider>,
+    db: ArcDynDatabase,
+    keys: Arc<KeyRing>,
 }
 
-#[allow(dead_code)]
-#[derive(Debug, Error)]
-enum AuthError {}
-
 impl AuthenticationManager {
-    pub fn new() -> Result<Self, anyhow::Error> {
-        tracing::error!("run setup");
+    /// Discovers the configured providers. Providers that cannot be reached are left out.
+    pub async fn new(
+        clients: &[OAuthClientConfig],
+        callback_url: &str,
+        db: ArcDynDatabase,
+        keys: Arc<KeyRing>,
+    ) -> Self {
+        let mut providers = vec![];
+        for config in clients.iter().filter(|client| client.issuer.is_some()) {
+            let redirect_url = config
+                .redirect_uris
+                .first()
+                .cloned()
+                .unwrap_or_else(|| callback_url.to_string());
+            match ExternalProvider::discover(config.clone(), redirect_url).await {
+                Ok(provider) => {
+                    info!("Sign-in with {} enabled", provider.name());
+                    providers.push(provider);
+                }
+                Err(e) => error!(
+                    "Could not discover identity provider {}: {}",
+                    config.name, e
+                ),
+            }
+        }
 
-        let provider_metadata = CoreProviderMetadata::discover(
-            &IssuerUrl::new("https://accounts.google.com".to_string())?,
-            http_client,
-        )?;
+        Self {
+            providers,
+            db,
+            keys,
+        }
+    }
 
-        // Generate a PKCE challenge.
-        let (pkce_challenge, pkce_verifier): (PkceCodeChallenge, openidconnect::PkceCodeVerifier) =
-            PkceCodeChallenge::new_random_sha256();
+    pub fn providers(&self) -> &[ExternalProvider] {
+        &self.providers
+    }
 
-        Ok(Self {
-            provider_metadata: provider_metadata.clone(),
-            client: CoreClient::from_provider_metadata(
-                provider_metadata,
-                ClientId::new(
-                    "953760225864-77il4losuech1dtsea36tmma2e8bko3h.apps.googleusercontent.com"
-                        .to_string(),
-                ),
-                Some(ClientSecret::new(
-                    "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".to_string(),
-                )),
-            )
-            // Set the URL the user will be redirected to after the authorization process.
-            .set_redirect_uri(RedirectUrl::new("http://127.0.0.1/callback".to_string())?),
-            pkce_challenge,
-            pkce_verifier,
-        })
+    pub fn provider(&self, name: &str) -> Option<&ExternalProvider> {
+        self.providers
+            .iter()
+            .find(|provider| provider.name() == name)
     }
 
-    #[allow(clippy::type_complexity)]
-    pub fn create_authorization_url(
-        client: openidconnect::Client<
-            openidconnect::EmptyAdditionalClaims,
-            openidconnect::core::CoreAuthDisplay,
-            openidconnect::core::CoreGenderClaim,
-            openidconnect::core::CoreJweContentEncryptionAlgorithm,
-            openidconnect::core::CoreJwsSigningAlgorithm,
-            openidconnect::core::CoreJsonWebKeyType,
-            openidconnect::core::CoreJsonWebKeyUse,
-            openidconnect::core::CoreJsonWebKey,
-            openidconnect::core::CoreAuthPrompt,
-            openidconnect::StandardErrorResponse<openidconnect::core::CoreErrorResponseType>,
-            openidconnect::StandardTokenResponse<
-                openidconnect::IdTokenFields<
-                    openidconnect::EmptyAdditionalClaims,
-                    openidconnect::EmptyExtraTokenFields,
-                    openidconnect::core::CoreGenderClaim,
-                    openidconnect::core::CoreJweContentEncryptionAlgorithm,
-                    openidconnect::core::CoreJwsSigningAlgorithm,
-                    openidconnect::core::CoreJsonWebKeyType,
-                >,
-                openidconnect::core::CoreTokenType,
-            >,
-            openidconnect::core::CoreTokenType,
-            openidconnect::StandardTokenIntrospectionResponse<
-                openidconnect::EmptyExtraTokenFields,
-                openidconnect::core::CoreTokenType,
-            >,
-            openidconnect::core::CoreRevocableToken,
-            openidconnect::StandardErrorResponse<openidconnect::RevocationErrorResponseType>,
-        >,
-        pkce_challenge: PkceCodeChallenge,
-    ) -> Result<Nonce> {
-        let nonce = Nonce::new_random;
-        // Generate the full authorization URL.
-        let (auth_url, _csrf_token, nonce) = client
-            .authorize_url(
-                CoreAuthenticationFlow::AuthorizationCode,
-                CsrfToken::new_random,
-                nonce,
+    /// The account of a user the provider signed in, linked or created on the first sign-in.
+    pub async fn sign_in(
+        &self,
+        provider: &ExternalProvider,
+        identity: &ExternalIdentity,
+    ) -> Result<Account> {
+        let account_id = match self
+            .db
+            .get_linked_account(provider.name(), &identity.subject)
+            .await?
+        {
+            Some(account_id) => account_id,
+            None => self.link_or_create_account(provider, identity).await?,
+        };
+
+        self.db
+            .link_external_identity(
+                provider.name(),
+                &identity.subject,
+                &account_id,
+                identity.email.as_deref(),
+                Utc::now(),
             )
-            // Set the desired scopes.
-            .add_scope(Scope::new("email".to_string()))
-            .add_scope(Scope::new("profile".to_string()))
-            // Set the PKCE code challenge.
-            .set_pkce_challenge(pkce_challenge)
-            .url();
-
-        // This is the URL you should redirect the user to, in order to trigger the authorization
-        // process.
-        println!("Browse to: {}", auth_url);
+            .await?;
+        if let Some(is_admin) = provider.config.admin_flag(&identity.groups) {
+            self.db.set_account_admin(&account_id, is_admin).await?;
+        }
 
-        Ok(nonce)
+        self.db.get_account_by_id(&account_id).await
     }
 
-    #[allow(clippy::type_complexity)]
-    pub fn exchange_code(
-        client: openidconnect::Client<
-            openidconnect::EmptyAdditionalClaims,
-            openidconnect::core::CoreAuthDisplay,
-            openidconnect::core::CoreGenderClaim,
-            openidconnect::core::CoreJweContentEncryptionAlgorithm,
-            openidconnect::core::CoreJwsSigningAlgorithm,
-            openidconnect::core::CoreJsonWebKeyType,
-            openidconnect::core::CoreJsonWebKeyUse,
-            openidconnect::core::CoreJsonWebKey,
-            openidconnect::core::CoreAuthPrompt,
-            openidconnect::StandardErrorResponse<openidconnect::core::CoreErrorResponseType>,
-            openidconnect::StandardTokenResponse<
-                openidconnect::IdTokenFields<
-                    openidconnect::EmptyAdditionalClaims,
-                    openidconnect::EmptyExtraTokenFields,
-                    openidconnect::core::CoreGenderClaim,
-                    openidconnect::core::CoreJweContentEncryptionAlgorithm,
-                    openidconnect::core::CoreJwsSigningAlgorithm,
-                    openidconnect::core::CoreJsonWebKeyType,
-                >,
-                openidconnect::core::CoreTokenType,
-            >,
-            openidconnect::core::CoreTokenType,
-            openidconnect::StandardTokenIntrospectionResponse<
-                openidconnect::EmptyExtraTokenFields,
-                openidconnect::core::CoreTokenType,
-            >,
-            openidconnect::core::CoreRevocableToken,
-            openidconnect::StandardErrorResponse<openidconnect::RevocationErrorResponseType>,
-        >,
-        pkce_verifier: PkceCodeVerifier,
-        authorization_code: String,
-        nonce: Nonce,
-    ) -> Result<()> {
-        // Once the user has been redirected to the redirect URL, you'll have access to the
-        // authorization code. For security reasons, your code should verify that the `state`
-        // parameter returned by the server matches `csrf_state`.
-
-        // Now you can exchange it for an access token and ID token.
-        let token_response = client
-            .exchange_code(AuthorizationCode::new(authorization_code))
-            // Set the PKCE code verifier.
-            .set_pkce_verifier(pkce_verifier)
-            .request(http_client)?;
-
-        // Extract the ID token claims after verifying its authenticity and nonce.
-        let id_token = token_response
-            .id_token()
-            .ok_or_else(|| anyhow!("Server did not return an ID token"))?;
-        let claims = id_token.claims(&client.id_token_verifier(), &nonce)?;
-
-        // Verify the access token hash to ensure that the access token hasn't been substituted for
-        // another user's.
-        if let Some(expected_access_token_hash) = claims.access_token_hash() {
-            let actual_access_token_hash = AccessTokenHash::from_token(
-                token_response.access_token(),
-                &id_token.signing_alg()?,
-            )?;
-            if actual_access_token_hash != *expected_access_token_hash {
-                return Err(anyhow!("Invalid access token"));
+    async fn link_or_create_account(
+        &self,
+        provider: &ExternalProvider,
+        identity: &ExternalIdentity,
+    ) -> Result<String> {
+        let email = identity.verified_email().ok_or_else(|| {
+            anyhow!(
+                "{} did not provide a verified email address",
+                provider.name()
+            )
+        })?;
+
+        // Someone else may have registered the address before its owner, so only accounts
+        // whose owner followed a verification or password reset mail are linked.
+        if let Ok(account) = self.db.get_account_by_email(email).await {
+            if account.email_verified_at.is_none() {
+                info!(
+                    "Not linking {} sign-in of {} to unverified account {}",
+                    provider.name(),
+                    email,
+                    account.account_id
+                );
+                return Err(EmailNotVerified.into());
             }
+            info!(
+                "Linking {} sign-in of {} to account {}",
+                provider.name(),
+                email,
+                account.account_id
+            );
+            return Ok(account.account_id);
+        }
+        if !provider.config.allows_signup(email) {
+            return Err(anyhow!(
+                "No account for {} and {} does not allow sign-ups",
+                email,
+                provider.name()
+            ));
         }
 
-        // The authenticated user's identity is now available. See the IdTokenClaims struct for a
-        // complete listing of the available claims.
-        println!(
-            "User {} with e-mail address {} has authenticated successfully",
-            claims.subject().as_str(),
-            claims
-                .email()
-                .map(|email| email.as_str())
-                .unwrap_or("<not provided>"),
-        );
-
-        // If available, we can use the UserInfo endpoint to request additional information.
-
-        // The user_info request uses the AccessToken returned in the token response. To parse custom
-        // claims, use UserInfoClaims directly (with the desired type parameters) rather than using the
-        // CoreUserInfoClaims type alias.
-        let _userinfo: CoreUserInfoClaims = client
-            .user_info(token_response.access_token().to_owned(), None)
-            .map_err(|err| anyhow!("No user info endpoint: {:?}", err))?
-            .request(http_client)
-            .map_err(|err| anyhow!("Failed requesting user info: {:?}", err))?;
+        // The account can only sign in through the provider until a password is set,
+        // e.g. with a password reset mail.
+        let account_id = AuthManager::new(self.db.clone(), Arc::clone(&self.keys))
+            .create_account(
+                email.to_string(),
+                new_unusable_password(),
+                identity.name.clone().unwrap_or_else(|| email.to_string()),
+                None,
+            )
+            .await?;
+        self.db.set_email_verified(&account_id, Utc::now()).await?;
+        Ok(account_id)
+    }
+}
 
-        // See the OAuth2TokenResponse trait for a listing of other available fields such as
-        // access_token() and refresh_token().
+fn new_unusable_password() -> String {
+    use rand::RngCore;
 
-        Ok(())
-    }
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
 }
@@ -0,0 +1,213 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! An external OpenID Connect provider, signing users in with the authorization code flow and PKCE.
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use common::config::client::OAuthClientConfig;
+use openidconnect::core::{
+    CoreAuthDisplay, CoreAuthPrompt, CoreAuthenticationFlow, CoreErrorResponseType,
+    CoreGenderClaim, CoreJsonWebKey, CoreJsonWebKeyType, CoreJsonWebKeyUse,
+    CoreJweContentEncryptionAlgorithm, CoreJwsSigningAlgorithm, CoreProviderMetadata,
+    CoreRevocableToken, CoreRevocationErrorResponse, CoreTokenIntrospectionResponse, CoreTokenType,
+};
+use openidconnect::reqwest::async_http_client;
+use openidconnect::{
+    AccessTokenHash, AdditionalClaims, AuthorizationCode, ClientId, ClientSecret, CsrfToken,
+    EmptyExtraTokenFields, IdTokenFields, IssuerUrl, Nonce, OAuth2TokenResponse, PkceCodeChallenge,
+    PkceCodeVerifier, RedirectUrl, Scope, StandardErrorResponse, StandardTokenResponse,
+    TokenResponse,
+};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// Claims beyond the standard ones, among them the configurable groups claim.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExtraClaims {
+    #[serde(flatten)]
+    claims: HashMap<String, serde_json::Value>,
+}
+
+impl AdditionalClaims for ExtraClaims {}
+
+type ProviderTokenResponse = StandardTokenResponse<
+    IdTokenFields<
+        ExtraClaims,
+        EmptyExtraTokenFields,
+        CoreGenderClaim,
+        CoreJweContentEncryptionAlgorithm,
+        CoreJwsSigningAlgorithm,
+        CoreJsonWebKeyType,
+    >,
+    CoreTokenType,
+>;
+
+type ProviderClient = openidconnect::Client<
+    ExtraClaims,
+    CoreAuthDisplay,
+    CoreGenderClaim,
+    CoreJweContentEncryptionAlgorithm,
+    CoreJwsSigningAlgorithm,
+    CoreJsonWebKeyType,
+    CoreJsonWebKeyUse,
+    CoreJsonWebKey,
+    CoreAuthPrompt,
+    StandardErrorResponse<CoreErrorResponseType>,
+    ProviderTokenResponse,
+    CoreTokenType,
+    CoreTokenIntrospectionResponse,
+    CoreRevocableToken,
+    CoreRevocationErrorResponse,
+>;
+
+/// A user as the provider knows them, taken from a verified ID token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExternalIdentity {
+    pub subject: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub name: Option<String>,
+    pub groups: Vec<String>,
+}
+
+impl ExternalIdentity {
+    pub fn verified_email(&self) -> Option<&str> {
+        self.email.as_deref().filter(|_| self.email_verified)
+    }
+}
+
+/// Secrets of a sign-in sent to the provider, kept until it redirects back.
+#[derive(Debug, Clone)]
+pub struct PendingLogin {
+    pub provider: String,
+    /// The `state` parameter the provider sends back.
+    pub csrf_state: String,
+    nonce: Nonce,
+    pkce_verifier: String,
+}
+
+pub struct ExternalProvider {
+    pub config: OAuthClientConfig,
+    client: ProviderClient,
+}
+
+impl ExternalProvider {
+    /// Fetches the discovery document of the configured issuer.
+    pub async fn discover(config: OAuthClientConfig, redirect_url: String) -> Result<Self> {
+        let issuer = config
+            .issuer
+            .clone()
+            .ok_or_else(|| anyhow!("Client {} has no issuer", config.name))?;
+        let metadata =
+            CoreProviderMetadata::discover_async(IssuerUrl::new(issuer)?, async_http_client)
+                .await?;
+
+        let client = ProviderClient::from_provider_metadata(
+            metadata,
+            ClientId::new(config.client_id.clone()),
+            Some(ClientSecret::new(config.client_secret.clone())),
+        )
+        .set_redirect_uri(RedirectUrl::new(redirect_url)?);
+
+        Ok(Self { config, client })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    /// The provider's authorization URL to send the browser to.
+    pub fn begin_login(&self) -> (Url, PendingLogin) {
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        let (url, csrf_state, nonce) = self
+            .client
+            .authorize_url(
+                CoreAuthenticationFlow::AuthorizationCode,
+                CsrfToken::new_random,
+                Nonce::new_random,
+            )
+            .add_scope(Scope::new("email".to_string()))
+            .add_scope(Scope::new("profile".to_string()))
+            .set_pkce_challenge(pkce_challenge)
+            .url();
+
+        let pending = PendingLogin {
+            provider: self.name().to_string(),
+            csrf_state: csrf_state.secret().clone(),
+            nonce,
+            pkce_verifier: pkce_verifier.secret().clone(),
+        };
+        (url, pending)
+    }
+
+    /// Exchanges the code the provider redirected back with and verifies the ID token.
+    pub async fn finish_login(
+        &self,
+        code: String,
+        pending: PendingLogin,
+    ) -> Result<ExternalIdentity> {
+        let token_response = self
+            .client
+            .exchange_code(AuthorizationCode::new(code))
+            .set_pkce_verifier(PkceCodeVerifier::new(pending.pkce_verifier))
+            .request_async(async_http_client)
+            .await
+            .map_err(|e| anyhow!("Code exchange with {} failed: {:?}", self.name(), e))?;
+
+        let id_token = token_response
+            .id_token()
+            .ok_or_else(|| anyhow!("{} did not return an ID token", self.name()))?;
+        let claims = id_token.claims(&self.client.id_token_verifier(), &pending.nonce)?;
+
+        // The access token must be the one the ID token was issued with.
+        if let Some(expected_hash) = claims.access_token_hash() {
+            let actual_hash = AccessTokenHash::from_token(
+                token_response.access_token(),
+                &id_token.signing_alg()?,
+            )?;
+            if actual_hash != *expected_hash {
+                return Err(anyhow!("Invalid access token"));
+            }
+        }
+
+        let groups = match claims
+            .additional_claims()
+            .claims
+            .get(self.config.groups_claim())
+        {
+            Some(serde_json::Value::Array(groups)) => groups
+                .iter()
+                .filter_map(|group| group.as_str().map(str::to_string))
+                .collect(),
+            Some(serde_json::Value::String(group)) => vec![group.clone()],
+            _ => vec![],
+        };
+
+        Ok(ExternalIdentity {
+            subject: claims.subject().to_string(),
+            email: claims.email().map(|email| email.to_string()),
+            email_verified: claims.email_verified().unwrap_or(false),
+            name: claims
+                .name()
+                .and_then(|name| name.get(None))
+                .map(|name| name.to_string()),
+            groups,
+        })
+    }
+}
@@ -0,0 +1,269 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Signs in through a mock identity provider running on a local port.
+//!
+//! The provider serves a discovery document, its JWKS and a token endpoint
+//! answering every code with an RS256 ID token for the nonce the client sent.
+
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{Arc, LazyLock, Mutex};
+
+use axum::extract::State;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use common::auth::account_token::EmailNotVerified;
+use common::auth::keys::{JwtKey, KeyRing};
+use common::config::client::OAuthClientConfig;
+use common::database::ArcDynDatabase;
+use database::memory::MemoryDatabase;
+use jsonwebtoken::{EncodingKey, Header};
+use oauth_authentication::{AuthenticationManager, ExternalIdentity, ExternalProvider};
+use rsa::pkcs1::EncodeRsaPrivateKey;
+use rsa::pkcs8::LineEnding;
+use rsa::traits::PublicKeyParts;
+use rsa::RsaPrivateKey;
+
+/// Generating RSA keys is slow in debug builds, all tests share one.
+static KEY: LazyLock<RsaPrivateKey> =
+    LazyLock::new(|| RsaPrivateKey::new(&mut rand::rngs::OsRng, 2048).unwrap());
+
+#[derive(Clone)]
+struct MockProvider {
+    issuer: String,
+    key: RsaPrivateKey,
+    nonce: Arc<Mutex<Option<String>>>,
+}
+
+async fn discovery(State(idp): State<MockProvider>) -> Json<serde_json::Value> {
+    Json(serde_json::json!({
+        "issuer": idp.issuer,
+        "authorization_endpoint": format!("{}/authorize", idp.issuer),
+        "token_endpoint": format!("{}/token", idp.issuer),
+        "jwks_uri": format!("{}/jwks", idp.issuer),
+        "response_types_supported": ["code"],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": ["RS256"],
+    }))
+}
+
+async fn jwks(State(idp): State<MockProvider>) -> Json<serde_json::Value> {
+    let public_key = idp.key.to_public_key();
+    Json(serde_json::json!({
+        "keys": [{
+            "kty": "RSA",
+            "use": "sig",
+            "alg": "RS256",
+            "kid": "mock",
+            "n": URL_SAFE_NO_PAD.encode(public_key.n().to_bytes_be()),
+            "e": URL_SAFE_NO_PAD.encode(public_key.e().to_bytes_be()),
+        }]
+    }))
+}
+
+async fn token(State(idp): State<MockProvider>) -> Json<serde_json::Value> {
+    let now = chrono::Utc::now().timestamp();
+    let claims = serde_json::json!({
+        "iss": idp.issuer,
+        "sub": "idp-user-42",
+        "aud": "photos",
+        "exp": now + 300,
+        "iat": now,
+        "nonce": idp.nonce.lock().unwrap().clone(),
+        "email": "alice@example.com",
+        "email_verified": true,
+        "name": "Alice",
+        "roles": ["photographers", "admins"],
+    });
+    let mut header = Header::new(jsonwebtoken::Algorithm::RS256);
+    header.kid = Some("mock".to_string());
+    let pem = idp.key.to_pkcs1_pem(LineEnding::LF).unwrap();
+    let id_token = jsonwebtoken::encode(
+        &header,
+        &claims,
+        &EncodingKey::from_rsa_pem(pem.as_bytes()).unwrap(),
+    )
+    .unwrap();
+
+    Json(serde_json::json!({
+        "access_token": "mock-access-token",
+        "token_type": "Bearer",
+        "expires_in": 300,
+        "id_token": id_token,
+    }))
+}
+
+fn start_mock_provider() -> MockProvider {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr: SocketAddr = listener.local_addr().unwrap();
+    let idp = MockProvider {
+        issuer: format!("http://{}", addr),
+        key: KEY.clone(),
+        nonce: Arc::new(Mutex::new(None)),
+    };
+
+    let router = Router::new()
+        .route("/.well-known/openid-configuration", get(discovery))
+        .route("/jwks", get(jwks))
+        .route("/token", post(token))
+        .with_state(idp.clone());
+    tokio::spawn(
+        axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(router.into_make_service()),
+    );
+
+    idp
+}
+
+#[tokio::test]
+async fn test_sign_in_with_mock_provider_should_succeed() {
+    // given
+    let idp = start_mock_provider();
+    let config = OAuthClientConfig {
+        name: "mock".into(),
+        client_id: "photos".into(),
+        client_secret: "secret".into(),
+        issuer: Some(idp.issuer.clone()),
+        groups_claim: Some("roles".into()),
+        ..Default::default()
+    };
+    let provider = ExternalProvider::discover(config, "http://127.0.0.1:7777/oidc/callback".into())
+        .await
+        .unwrap();
+
+    // when
+    let (url, pending) = provider.begin_login();
+    let query: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+    let param = |name: &str| {
+        query
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.clone())
+    };
+    *idp.nonce.lock().unwrap() = param("nonce");
+    let identity = provider
+        .finish_login("mock-code".into(), pending.clone())
+        .await
+        .unwrap();
+
+    // then
+    assert!(url
+        .as_str()
+        .starts_with(&format!("{}/authorize", idp.issuer)));
+    assert_eq!(param("state"), Some(pending.csrf_state));
+    assert_eq!(param("code_challenge_method").as_deref(), Some("S256"));
+    assert_eq!(
+        param("redirect_uri").as_deref(),
+        Some("http://127.0.0.1:7777/oidc/callback")
+    );
+    assert_eq!(
+        identity,
+        ExternalIdentity {
+            subject: "idp-user-42".into(),
+            email: Some("alice@example.com".into()),
+            email_verified: true,
+            name: Some("Alice".into()),
+            groups: vec!["photographers".into(), "admins".into()],
+        }
+    );
+}
+
+#[tokio::test]
+async fn test_sign_in_with_foreign_nonce_should_fail() {
+    // given
+    let idp = start_mock_provider();
+    let config = OAuthClientConfig {
+        name: "mock".into(),
+        client_id: "photos".into(),
+        client_secret: "secret".into(),
+        issuer: Some(idp.issuer.clone()),
+        ..Default::default()
+    };
+    let provider = ExternalProvider::discover(config, "http://127.0.0.1:7777/oidc/callback".into())
+        .await
+        .unwrap();
+
+    // when
+    let (_, pending) = provider.begin_login();
+    *idp.nonce.lock().unwrap() = Some("replayed-nonce".into());
+    let result = provider.finish_login("mock-code".into(), pending).await;
+
+    // then
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_sign_in_to_unverified_account_should_fail() {
+    // given
+    let idp = start_mock_provider();
+    let config = OAuthClientConfig {
+        name: "mock".into(),
+        client_id: "photos".into(),
+        client_secret: "secret".into(),
+        issuer: Some(idp.issuer.clone()),
+        ..Default::default()
+    };
+    let db: ArcDynDatabase = Arc::new(MemoryDatabase::new());
+    db.create_account(
+        "squatter".into(),
+        "alice@example.com".into(),
+        "hash".into(),
+        None,
+    )
+    .await
+    .unwrap();
+    let keys = KeyRing::new(vec![JwtKey::new("test", b"test-secret")], None).unwrap();
+    let manager = AuthenticationManager::new(
+        &[config],
+        "http://127.0.0.1:7777/oidc/callback",
+        db.clone(),
+        Arc::new(keys),
+    )
+    .await;
+    let provider = manager.provider("mock").unwrap();
+    let (url, pending) = provider.begin_login();
+    let nonce = url
+        .query_pairs()
+        .find(|(key, _)| key == "nonce")
+        .map(|(_, value)| value.into_owned());
+    *idp.nonce.lock().unwrap() = nonce;
+    let identity = provider
+        .finish_login("mock-code".into(), pending)
+        .await
+        .unwrap();
+
+    // when
+    let refused = manager.sign_in(provider, &identity).await;
+    db.set_email_verified("squatter", chrono::Utc::now())
+        .await
+        .unwrap();
+    let linked = manager.sign_in(provider, &identity).await;
+
+    // then
+    assert!(refused.unwrap_err().is::<EmailNotVerified>());
+    assert_eq!(linked.unwrap().account_id, "squatter");
+    assert_eq!(
+        db.get_linked_account("mock", "idp-user-42")
+            .await
+            .unwrap()
+            .as_deref(),
+        Some("squatter")
+    );
+}
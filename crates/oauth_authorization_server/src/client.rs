@@ -15,6 +15,9 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use axum::http::{header, HeaderMap};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -22,4 +25,94 @@ pub struct Client {
     pub id: String,
     pub secret: Option<String>,
     pub redirect_uri: String,
+    /// Where the end-session endpoint may send the browser after a logout.
+    /// Without one, only `redirect_uri` is accepted.
+    #[serde(default)]
+    pub post_logout_redirect_uri: Option<String>,
+}
+
+impl Client {
+    pub(crate) fn accepts_post_logout_redirect_uri(&self, uri: &url::Url) -> bool {
+        let registered = self
+            .post_logout_redirect_uri
+            .as_ref()
+            .unwrap_or(&self.redirect_uri);
+        url::Url::parse(registered).is_ok_and(|registered| &registered == uri)
+    }
+}
+
+/// Client id and secret from the `Authorization: Basic` header, or else the ones sent in the
+/// form body.
+pub(crate) fn client_credentials(
+    headers: &HeaderMap,
+    client_id: Option<&str>,
+    client_secret: Option<&str>,
+) -> (Option<String>, Option<String>) {
+    let basic = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Basic "))
+        .and_then(|v| STANDARD.decode(v.trim()).ok())
+        .and_then(|v| String::from_utf8(v).ok())
+        .and_then(|v| {
+            v.split_once(':')
+                .map(|(id, secret)| (id.to_string(), secret.to_string()))
+        });
+
+    match basic {
+        Some((id, secret)) => (Some(id), Some(secret)),
+        None => (
+            client_id.map(str::to_string),
+            client_secret.map(str::to_string),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_post_logout_redirect_uri_defaults_to_redirect_uri() {
+        // given
+        let mut client = Client {
+            id: String::from("mobile-app"),
+            secret: None,
+            redirect_uri: String::from("photosapp://authenticate"),
+            post_logout_redirect_uri: None,
+        };
+        let redirect_uri = url::Url::parse("photosapp://authenticate").unwrap();
+        let logout_uri = url::Url::parse("photosapp://logout").unwrap();
+
+        // then
+        assert!(client.accepts_post_logout_redirect_uri(&redirect_uri));
+        assert!(!client.accepts_post_logout_redirect_uri(&logout_uri));
+
+        // when
+        client.post_logout_redirect_uri = Some(String::from("photosapp://logout"));
+
+        // then
+        assert!(client.accepts_post_logout_redirect_uri(&logout_uri));
+        assert!(!client.accepts_post_logout_redirect_uri(&redirect_uri));
+    }
+
+    #[test]
+    fn test_client_credentials_should_prefer_basic_authorization() {
+        // given
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            format!("Basic {}", STANDARD.encode("web-app:s3cret"))
+                .parse()
+                .unwrap(),
+        );
+
+        // when
+        let basic = client_credentials(&headers, Some("form-app"), Some("form"));
+        let form = client_credentials(&HeaderMap::new(), Some("form-app"), None);
+
+        // then
+        assert_eq!(basic, (Some("web-app".into()), Some("s3cret".into())));
+        assert_eq!(form, (Some("form-app".into()), None));
+    }
 }
@@ -21,9 +21,11 @@
 //!
 use axum::extract::{Query, State};
 use axum::http::StatusCode;
-use axum::response::Redirect;
+use axum::response::{IntoResponse, Redirect, Response};
 use std::sync::{Arc, RwLock};
+use url::Url;
 
+use crate::pkce;
 use crate::query::AuthorizeQuery;
 use crate::request::AuthRequest;
 use crate::state::ServerState;
@@ -33,32 +35,81 @@ pub(crate) type SharedState = Arc<RwLock<ServerState>>;
 pub(crate) async fn authorization_handler(
     Query(query): Query<AuthorizeQuery>,
     State(state): State<SharedState>,
-) -> std::result::Result<Redirect, StatusCode> {
+) -> Response {
+    let mut state = state.write().unwrap();
+    let Some(realm) = state
+        .realms_mut()
+        .find(|realm| realm.client(&query.client_id).is_some())
+    else {
+        return (StatusCode::BAD_REQUEST, "Unknown client_id").into_response();
+    };
+
+    // Errors are only reported to the client once its redirect_uri is known to be its own.
+    let registered = realm
+        .client(&query.client_id)
+        .and_then(|client| Url::parse(&client.redirect_uri).ok());
+    if registered.as_ref() != Some(&query.redirect_uri) {
+        return (
+            StatusCode::BAD_REQUEST,
+            "redirect_uri is not registered for this client",
+        )
+            .into_response();
+    }
+
+    if query.response_type != "code" {
+        return error_redirect(
+            &query,
+            "unsupported_response_type",
+            "Only the code flow is supported",
+        );
+    }
+    let code_challenge = match (
+        &query.code_challenge,
+        query.code_challenge_method.as_deref(),
+    ) {
+        (Some(challenge), Some(pkce::METHOD_S256)) if pkce::is_well_formed(challenge) => {
+            challenge.clone()
+        }
+        _ => {
+            return error_redirect(
+                &query,
+                "invalid_request",
+                "PKCE with code_challenge_method S256 is required",
+            )
+        }
+    };
+
+    let now = chrono::Utc::now().naive_utc();
     let req = AuthRequest {
         id: uuid::Uuid::new_v4(),
-        code_challenge: query.code_challenge,
-        code: None,
-        created_at: chrono::Utc::now().naive_utc(),
-        state: query.state,
-        nonce: query.nonce,
+        client_id: query.client_id.clone(),
+        redirect_uri: query.redirect_uri.to_string(),
+        scope: query.scope.clone(),
+        created_at: now,
+        state: query.state.clone(),
+        code_challenge,
+        nonce: query.nonce.clone(),
+        external_login: None,
+        grant: None,
     };
-    for realm in state.write().unwrap().realms.iter_mut() {
-        for client in realm.clients.iter() {
-            if client.id == query.client_id {
-                realm.requests.push(req);
-                let realm_login_url = format!("/{}/login", &realm.name);
-                return Ok(Redirect::to(&realm_login_url));
-            }
-        }
-    }
+    let realm_login_url = format!("/{}/login?request_id={}", realm.name, req.id);
+    realm.prune_requests(now);
+    realm.requests.push(req);
+
+    Redirect::to(&realm_login_url).into_response()
+}
 
-    for client in state.read().unwrap().master_realm.clients.iter() {
-        if client.id == query.client_id {
-            state.write().unwrap().master_realm.requests.push(req);
-            let realm_login_url = format!("/{}/login", &state.read().unwrap().master_realm.name);
-            return Ok(Redirect::to(&realm_login_url));
+/// Sends the user agent back to the client with an error (RFC 6749, section 4.1.2.1).
+fn error_redirect(query: &AuthorizeQuery, error: &str, description: &str) -> Response {
+    let mut url = query.redirect_uri.clone();
+    {
+        let mut pairs = url.query_pairs_mut();
+        pairs.append_pair("error", error);
+        pairs.append_pair("error_description", description);
+        if let Some(state) = &query.state {
+            pairs.append_pair("state", state);
         }
     }
 
-    Err(StatusCode::UNAUTHORIZED)
+    Redirect::to(url.as_str()).into_response()
 }
@@ -18,14 +18,14 @@
 use axum::extract::State;
 use axum::Json;
 use axum::{headers::Host, TypedHeader};
-use openidconnect::core::CoreProviderMetadata;
 
 use super::authorize::SharedState;
+use crate::realm::ProviderMetadata;
 
 pub(crate) async fn openid_discover_handler(
     State(state): State<SharedState>,
     TypedHeader(host): TypedHeader<Host>,
-) -> Json<CoreProviderMetadata> {
+) -> Json<ProviderMetadata> {
     for realm in state.read().unwrap().realms.iter() {
         if realm.domain == host.hostname() {
             return Json(realm.provider_metadata.clone());
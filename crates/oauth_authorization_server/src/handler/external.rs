@@ -0,0 +1,201 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Sign-in with an external OpenID Connect provider as an alternative to the password form.
+//!
+//! GET /:realm/login/:provider — sends the user agent to the provider.
+//! GET /oidc/callback          — the provider's redirect back, completing the authorization request.
+//!
+//! Accounts with an authenticator app continue at POST /:realm/login/otp with a one-time code.
+
+use std::sync::Arc;
+
+use axum::extract::{Extension, Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Redirect, Response};
+use common::auth::account_token::EmailNotVerified;
+use common::auth::auth_manager::AuthManager;
+use common::auth::keys::KeyRing;
+use common::config::configuration::Configuration;
+use serde::Deserialize;
+
+use super::authorize::SharedState;
+use super::login::{expired_request, issue_code, render_login_form, render_otp_form, LoginQuery};
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct CallbackQuery {
+    state: String,
+    code: Option<String>,
+    error: Option<String>,
+}
+
+pub(crate) async fn external_login(
+    Path((realm, provider)): Path<(String, String)>,
+    Query(query): Query<LoginQuery>,
+    State(state): State<SharedState>,
+) -> Response {
+    let mut state = state.write().unwrap();
+    let manager = state.identity_providers.clone();
+    let Some(provider) = manager
+        .as_ref()
+        .and_then(|manager| manager.provider(&provider))
+    else {
+        return (StatusCode::NOT_FOUND, "Unknown identity provider").into_response();
+    };
+
+    let now = chrono::Utc::now().naive_utc();
+    let Some(request) = state.realm_mut(&realm).and_then(|r| {
+        r.requests
+            .iter_mut()
+            .find(|r| r.id == query.request_id && r.grant.is_none() && !r.is_expired(now))
+    }) else {
+        return expired_request();
+    };
+
+    let (url, pending) = provider.begin_login();
+    request.external_login = Some(pending);
+
+    Redirect::to(url.as_str()).into_response()
+}
+
+pub(crate) async fn external_callback(
+    Query(query): Query<CallbackQuery>,
+    State(state): State<SharedState>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    Extension(config): Extension<Arc<Configuration>>,
+) -> Response {
+    // The state parameter is single use, like the authorization code.
+    let found = state.write().unwrap().realms_mut().find_map(|realm| {
+        let request = realm.requests.iter_mut().find(|r| {
+            r.external_login
+                .as_ref()
+                .is_some_and(|pending| pending.csrf_state == query.state)
+        })?;
+        Some((
+            realm.name.clone(),
+            request.id,
+            request.external_login.take()?,
+        ))
+    });
+    let Some((realm, request_id, pending)) = found else {
+        return expired_request();
+    };
+    let manager = state.read().unwrap().identity_providers.clone();
+    let Some((manager, provider)) = manager
+        .as_ref()
+        .and_then(|manager| Some((manager, manager.provider(&pending.provider)?)))
+    else {
+        return expired_request();
+    };
+
+    let Some(code) = query.code.filter(|_| query.error.is_none()) else {
+        tracing::info!(
+            "sign-in with {} was not completed: {:?}",
+            provider.name(),
+            query.error
+        );
+        return render_login_form(
+            &state,
+            &realm,
+            StatusCode::UNAUTHORIZED,
+            request_id,
+            "Sign-in was cancelled",
+        );
+    };
+    let identity = match provider.finish_login(code, pending).await {
+        Ok(identity) => identity,
+        Err(e) => {
+            tracing::error!("sign-in with {} failed: {}", provider.name(), e);
+            return render_login_form(
+                &state,
+                &realm,
+                StatusCode::UNAUTHORIZED,
+                request_id,
+                "Sign-in failed",
+            );
+        }
+    };
+    let account = match manager.sign_in(provider, &identity).await {
+        Ok(account) => account,
+        Err(e) => {
+            tracing::info!("sign-in with {} refused: {}", provider.name(), e);
+            let message = if e.is::<EmailNotVerified>() {
+                "Please verify your email address, or reset your password, before signing in this way"
+            } else {
+                "There is no account for this sign-in"
+            };
+            return render_login_form(&state, &realm, StatusCode::FORBIDDEN, request_id, message);
+        }
+    };
+
+    // A second factor at the provider is not known here, so the account's own applies as well.
+    let db = Arc::clone(&state.read().unwrap().db);
+    match AuthManager::new(db, keys)
+        .mfa_challenge_for(
+            &account.account_id,
+            account.is_admin,
+            config.auth.require_admin_mfa,
+        )
+        .await
+    {
+        Ok(None) => {}
+        Ok(Some(challenge)) if challenge.enrollment_required => {
+            return render_login_form(
+                &state,
+                &realm,
+                StatusCode::FORBIDDEN,
+                request_id,
+                "Admin accounts need two-factor authentication, please enroll it in the app first",
+            );
+        }
+        Ok(Some(challenge)) if challenge.methods.iter().any(|method| method == "totp") => {
+            return render_otp_form(&realm, StatusCode::OK, request_id, &challenge.mfa_token, "");
+        }
+        Ok(Some(_)) => {
+            return render_login_form(
+                &state,
+                &realm,
+                StatusCode::UNAUTHORIZED,
+                request_id,
+                "Please sign in with your passkey",
+            );
+        }
+        Err(e) => {
+            tracing::error!("could not look up second factors: {}", e);
+            return render_login_form(
+                &state,
+                &realm,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                request_id,
+                "Sign-in is not possible right now",
+            );
+        }
+    }
+
+    // The request may have expired while the provider was asked.
+    let now = chrono::Utc::now().naive_utc();
+    let mut state = state.write().unwrap();
+    let Some(request) = state.realm_mut(&realm).and_then(|r| {
+        r.requests
+            .iter_mut()
+            .find(|r| r.id == request_id && r.grant.is_none() && !r.is_expired(now))
+    }) else {
+        return expired_request();
+    };
+
+    issue_code(request, account.account_id)
+}
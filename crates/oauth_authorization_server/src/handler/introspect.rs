@@ -0,0 +1,196 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! POST /oidc/introspect — token introspection for resource servers (RFC 7662).
+//!
+//! Only confidential clients may ask, authenticated with HTTP Basic or
+//! `client_id` and `client_secret` in the form body.
+
+use std::sync::Arc;
+
+use axum::extract::{Extension, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::{Form, Json};
+use common::auth::auth_manager::AuthManager;
+use common::auth::keys::KeyRing;
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use super::authorize::SharedState;
+use crate::client::client_credentials;
+
+/// A `token_type_hint` is ignored, access and refresh tokens are both looked up.
+#[derive(Debug, Deserialize)]
+pub struct IntrospectionRequest {
+    pub token: String,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+/// Inactive tokens are answered with `{"active": false}` and nothing else.
+#[derive(Debug, Default, Serialize)]
+pub struct IntrospectionResponse {
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iat: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_admin: Option<bool>,
+}
+
+pub(crate) async fn introspection_endpoint(
+    State(state): State<SharedState>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    headers: HeaderMap,
+    Form(req): Form<IntrospectionRequest>,
+) -> Response {
+    let authenticated = match client_credentials(
+        &headers,
+        req.client_id.as_deref(),
+        req.client_secret.as_deref(),
+    ) {
+        (Some(id), Some(secret)) => state.read().unwrap().authenticate_client(&id, &secret),
+        _ => false,
+    };
+    if !authenticated {
+        return (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Basic")],
+            Json(serde_json::json!({"error": "invalid_client", "error_description": "Client authentication failed"})),
+        )
+            .into_response();
+    }
+
+    let db = Arc::clone(&state.read().unwrap().db);
+    match introspect(&AuthManager::new(db, keys), &req.token).await {
+        Ok(response) => Json(response).into_response(),
+        Err(e) => {
+            error!("token introspection failed: {}", e);
+            StatusCode::SERVICE_UNAVAILABLE.into_response()
+        }
+    }
+}
+
+async fn introspect(
+    auth: &AuthManager,
+    token: &str,
+) -> Result<IntrospectionResponse, anyhow::Error> {
+    if let Ok(access) = AuthManager::decode_access_token(&auth.keys, token) {
+        // Share tokens are not bound to a session but end with their share link.
+        let active = match &access.session_id {
+            Some(session_id) => auth.active_session(session_id).await?.is_some(),
+            None => access.role == "share" && auth.active_share_link(&access.sub).await?.is_some(),
+        };
+        if !active {
+            return Ok(IntrospectionResponse::default());
+        }
+        return Ok(IntrospectionResponse {
+            active: true,
+            token_type: Some("access_token"),
+            sub: Some(access.sub),
+            iss: Some(access.issuer),
+            exp: Some(access.expires_at),
+            iat: Some(access.issued_at),
+            sid: access.session_id,
+            role: Some(access.role),
+            is_admin: Some(access.is_admin),
+        });
+    }
+
+    Ok(match auth.session_of_refresh_token(token).await? {
+        Some(session) => IntrospectionResponse {
+            active: true,
+            token_type: Some("refresh_token"),
+            sub: Some(session.subject_id),
+            exp: Some(session.expires_at.timestamp()),
+            iat: Some(session.last_used_at.timestamp()),
+            sid: Some(session.session_id),
+            role: Some(session.role),
+            ..Default::default()
+        },
+        None => IntrospectionResponse::default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, Utc};
+    use common::auth::keys::JwtKey;
+    use common::database::share_link::ShareLink;
+    use common::database::ArcDynDatabase;
+    use database::memory::MemoryDatabase;
+
+    async fn introspect_share_token(expires_at: chrono::DateTime<Utc>, revoke: bool) -> bool {
+        let db: ArcDynDatabase = Arc::new(MemoryDatabase::new());
+        let keys = Arc::new(KeyRing::new(vec![JwtKey::new("test", b"test-secret")], None).unwrap());
+        let link = ShareLink {
+            token: ShareLink::generate_token(),
+            album_id: "album".into(),
+            created_by: "owner".into(),
+            password_hash: None,
+            allow_download: false,
+            rendition: "large".into(),
+            expires_at: Some(expires_at),
+            created_at: Utc::now(),
+        };
+        db.create_share_link(&link).await.unwrap();
+        let (token, _) = AuthManager::generate_share_jwt(
+            &keys,
+            &link.token,
+            Some(Utc::now() + Duration::hours(1)),
+        )
+        .unwrap();
+        if revoke {
+            db.revoke_share_links("album", Some(&link.token))
+                .await
+                .unwrap();
+        }
+
+        introspect(&AuthManager::new(db, keys), &token)
+            .await
+            .unwrap()
+            .active
+    }
+
+    #[tokio::test]
+    async fn test_introspect_share_token_should_succeed() {
+        assert!(introspect_share_token(Utc::now() + Duration::hours(1), false).await);
+    }
+
+    #[tokio::test]
+    async fn test_introspect_share_token_of_revoked_link_should_fail() {
+        assert!(!introspect_share_token(Utc::now() + Duration::hours(1), true).await);
+    }
+
+    #[tokio::test]
+    async fn test_introspect_share_token_of_expired_link_should_fail() {
+        assert!(!introspect_share_token(Utc::now() - Duration::minutes(1), false).await);
+    }
+}
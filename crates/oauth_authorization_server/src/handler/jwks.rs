@@ -18,19 +18,18 @@
 use axum::extract::State;
 use axum::Json;
 use axum::{headers::Host, TypedHeader};
-use openidconnect::core::CoreJsonWebKeySet;
 
 use super::authorize::SharedState;
 
 pub(crate) async fn openid_jwks_handler(
     State(state): State<SharedState>,
     TypedHeader(host): TypedHeader<Host>,
-) -> Json<CoreJsonWebKeySet> {
+) -> Json<serde_json::Value> {
     for realm in state.read().unwrap().realms.iter() {
         if realm.domain == host.hostname() {
-            return Json(realm.jwks.clone());
+            return Json(realm.jwks());
         }
     }
 
-    Json(state.read().unwrap().master_realm.jwks.clone())
+    Json(state.read().unwrap().master_realm.jwks())
 }
@@ -15,10 +15,22 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use axum::response::Html;
-use axum::Form;
+use std::sync::Arc;
 
+use axum::extract::{Extension, Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use axum::Form;
+use common::auth::account_token::{AccountMailSettings, EmailNotVerified};
+use common::auth::auth_manager::AuthManager;
+use common::auth::keys::KeyRing;
+use common::auth::mfa::{MfaPurpose, SecondFactor, SecondFactorLocked};
+use common::config::configuration::Configuration;
 use serde::Deserialize;
+use url::Url;
+
+use super::authorize::SharedState;
+use crate::request::{AuthGrant, AuthRequest};
 
 static LOGIN_FORM_TEMPLATE: &str = r#"
 <html>
@@ -56,58 +68,398 @@ static LOGIN_FORM_TEMPLATE: &str = r#"
 </head>
 <body>
 <form method="post">
+    <p id="error" class="text-sm text-red-600">{{error}}</p>
     <label for="username" class="leading-7 text-sm text-gray-600"><b>Username</b></label>
     <input type="text" id="username" name="username" class="w-full bg-white rounded border border-gray-300 focus:border-indigo-500 focus:ring-2 focus:ring-indigo-200 text-base outline-none text-gray-700 py-1 px-3 leading-8 transition-colors duration-200 ease-in-out" />
 
     <label for="password" class="leading-7 text-sm text-gray-600"><b>Password</b></label>
     <input type="password" id="password" name="password" class="w-full bg-white rounded border border-gray-300 focus:border-indigo-500 focus:ring-2 focus:ring-indigo-200 text-base outline-none text-gray-700 py-1 px-3 leading-8 transition-colors duration-200 ease-in-out" />
+
+    <label for="otp" class="leading-7 text-sm text-gray-600"><b>One-time code</b> (if two-factor authentication is enabled)</label>
+    <input type="text" id="otp" name="otp" inputmode="numeric" autocomplete="one-time-code" class="w-full bg-white rounded border border-gray-300 focus:border-indigo-500 focus:ring-2 focus:ring-indigo-200 text-base outline-none text-gray-700 py-1 px-3 leading-8 transition-colors duration-200 ease-in-out" />
     
     <input type="hidden" id="request_id" name="request_id" value="{{request_id}}" />
     <input type="submit" id="submit" value="Submit" class="text-white bg-indigo-500 border-0 py-2 px-6 focus:outline-none hover:bg-indigo-600 rounded text-lg" />
 </form>
+<button type="button" id="passkey" hidden class="text-indigo-500 py-2">Sign in with a passkey</button>
+{{providers}}
+<script>
+  const fromBase64url = s => Uint8Array.from(atob(s.replace(/-/g, '+').replace(/_/g, '/')), c => c.charCodeAt(0));
+  const toBase64url = buffer => btoa(String.fromCharCode(...new Uint8Array(buffer)))
+    .replace(/\+/g, '-').replace(/\//g, '_').replace(/=+$/, '');
+  const passkeyButton = document.getElementById('passkey');
+  if (window.PublicKeyCredential) {
+    passkeyButton.hidden = false;
+    passkeyButton.onclick = async () => {
+      try {
+        const options = await (await fetch('/{{realm}}/login/passkey?request_id={{request_id}}')).json();
+        if (options.error) throw new Error(options.error);
+        options.challenge = fromBase64url(options.challenge);
+        options.allowCredentials = options.allowCredentials.map(c => ({ ...c, id: fromBase64url(c.id) }));
+        const credential = await navigator.credentials.get({ publicKey: options });
+        const response = credential.response;
+        const result = await (await fetch('/{{realm}}/login/passkey', {
+          method: 'POST',
+          headers: { 'Content-Type': 'application/json' },
+          body: JSON.stringify({
+            request_id: '{{request_id}}',
+            credential: {
+              id: credential.id,
+              response: {
+                clientDataJSON: toBase64url(response.clientDataJSON),
+                authenticatorData: toBase64url(response.authenticatorData),
+                signature: toBase64url(response.signature),
+                userHandle: response.userHandle ? toBase64url(response.userHandle) : null,
+              },
+            },
+          }),
+        })).json();
+        if (!result.redirect_to) throw new Error(result.error);
+        window.location = result.redirect_to;
+      } catch (e) {
+        document.getElementById('error').textContent = e.message;
+      }
+    };
+  }
+</script>
 </body>
 </html>
 "#;
 
-pub(crate) async fn get_realm_login_form(
-    axum::extract::Path(realm): axum::extract::Path<String>,
-    axum::extract::Query(query): axum::extract::Query<LoginQuery>,
-) -> Html<String> {
-    // create a VirtualDom with the app component
-    // rebuild the VirtualDom before rendering
+/// Second step of a sign-in with an external provider, for accounts with an authenticator app.
+static OTP_FORM_TEMPLATE: &str = r#"
+<html>
+<head>
+  <script src="https://cdn.tailwindcss.com?plugins=forms,typography,aspect-ratio,line-clamp"></script>
+  <style>
+    body {font-family: Arial, Helvetica, sans-serif;}
+    form {border: 3px solid #f1f1f1;}
+  </style>
+</head>
+<body>
+<form method="post" action="/{{realm}}/login/otp">
+    <p id="error" class="text-sm text-red-600">{{error}}</p>
+    <label for="otp" class="leading-7 text-sm text-gray-600"><b>One-time code</b> of your authenticator app, or a recovery code</label>
+    <input type="text" id="otp" name="otp" inputmode="numeric" autocomplete="one-time-code" autofocus class="w-full bg-white rounded border border-gray-300 focus:border-indigo-500 focus:ring-2 focus:ring-indigo-200 text-base outline-none text-gray-700 py-1 px-3 leading-8 transition-colors duration-200 ease-in-out" />
 
+    <input type="hidden" id="mfa_token" name="mfa_token" value="{{mfa_token}}" />
+    <input type="hidden" id="request_id" name="request_id" value="{{request_id}}" />
+    <input type="submit" id="submit" value="Submit" class="text-white bg-indigo-500 border-0 py-2 px-6 focus:outline-none hover:bg-indigo-600 rounded text-lg" />
+</form>
+</body>
+</html>
+"#;
+
+pub(crate) async fn get_realm_login_form(
+    Path(realm): Path<String>,
+    Query(query): Query<LoginQuery>,
+    State(state): State<SharedState>,
+) -> Response {
     tracing::debug!(
         "Rendering form for request_id={} and realm={}",
         query.request_id,
         realm
     );
 
-    // render the VirtualDom to HTML
-    // Html(dioxus_ssr::render(&app))
-    Html(LOGIN_FORM_TEMPLATE.to_string())
-    //Html("<h1>Login</h1><form method='post'><label for=\"username\"><b>Username</b></label` y><input type=\"text\" placeholder=\"Enter Username\" name=\"username\" required>i
-    //<label for=\"password\"><b>Password</b></label><input type=\"password\" placeholder=\"Enter Password\" name=\"password\" required><button type=\"submit\">Login</button></form>".to_string())
+    if !is_pending(&state, &realm, query.request_id) {
+        return expired_request();
+    }
+
+    render_login_form(&state, &realm, StatusCode::OK, query.request_id, "")
 }
 
-pub(crate) async fn post_realm_login(Form(login_form): Form<LoginFormData>) -> Html<String> {
+pub(crate) async fn post_realm_login(
+    Path(realm): Path<String>,
+    State(state): State<SharedState>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    Extension(account_mail): Extension<Arc<AccountMailSettings>>,
+    Extension(config): Extension<Arc<Configuration>>,
+    Form(login_form): Form<LoginFormData>,
+) -> Response {
     tracing::debug!(
-        "username: {}, password: {}",
+        "login attempt of {} in realm {}",
         login_form.username,
-        login_form.password
+        realm
     );
 
-    // TODO: validate credentials
+    if !is_pending(&state, &realm, login_form.request_id) {
+        return expired_request();
+    }
+
+    let db = Arc::clone(&state.read().unwrap().db);
+    let auth = AuthManager::new(db, keys);
+    let account = match auth
+        .check_account_credentials(&account_mail, &login_form.username, &login_form.password)
+        .await
+    {
+        Ok(account) => account,
+        Err(e) => {
+            tracing::info!("login in realm {} failed: {}", realm, e);
+            let message = if e.is::<EmailNotVerified>() {
+                "Please verify your email address with the link we sent you"
+            } else {
+                "Invalid email or password"
+            };
+            return render_login_form(
+                &state,
+                &realm,
+                StatusCode::UNAUTHORIZED,
+                login_form.request_id,
+                message,
+            );
+        }
+    };
+
+    // Accounts with a second factor need a code of their authenticator app or a recovery code.
+    // Passkeys sign in on their own, with the button below the form.
+    let second_factors = match auth.second_factors(&account.account_id).await {
+        Ok(second_factors) => second_factors,
+        Err(e) => {
+            tracing::error!("could not look up second factors: {}", e);
+            return render_login_form(
+                &state,
+                &realm,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                login_form.request_id,
+                "Sign-in is not possible right now",
+            );
+        }
+    };
+    if second_factors.contains(&SecondFactor::Totp) {
+        let otp = login_form.otp.as_deref().unwrap_or_default();
+        let verified = if otp.trim().is_empty() {
+            Ok(false)
+        } else {
+            auth.verify_second_factor(&account.account_id, otp).await
+        };
+        if !matches!(verified, Ok(true)) {
+            tracing::info!(
+                "second factor of {} in realm {} missing or wrong",
+                account.account_id,
+                realm
+            );
+            let message = match verified {
+                Err(e) if e.is::<SecondFactorLocked>() => {
+                    "Too many wrong codes, please try again later"
+                }
+                _ => "Invalid or missing one-time code",
+            };
+            return render_login_form(
+                &state,
+                &realm,
+                StatusCode::UNAUTHORIZED,
+                login_form.request_id,
+                message,
+            );
+        }
+    } else if second_factors.contains(&SecondFactor::Passkey) {
+        return render_login_form(
+            &state,
+            &realm,
+            StatusCode::UNAUTHORIZED,
+            login_form.request_id,
+            "Please sign in with your passkey",
+        );
+    } else if account.is_admin && config.auth.require_admin_mfa {
+        return render_login_form(
+            &state,
+            &realm,
+            StatusCode::FORBIDDEN,
+            login_form.request_id,
+            "Admin accounts need two-factor authentication, please enroll it in the app first",
+        );
+    }
 
-    Html(String::from("<div>Success</div>"))
+    // The request may have expired while the password was checked.
+    let mut state = state.write().unwrap();
+    let Some(request) = state.realm_mut(&realm).and_then(|r| {
+        r.requests
+            .iter_mut()
+            .find(|r| r.id == login_form.request_id && r.grant.is_none())
+    }) else {
+        return expired_request();
+    };
+
+    issue_code(request, account.account_id)
+}
+
+/// Completes a sign-in with an external provider with the code of the account's authenticator app.
+pub(crate) async fn post_realm_otp(
+    Path(realm): Path<String>,
+    State(state): State<SharedState>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    Form(otp_form): Form<OtpFormData>,
+) -> Response {
+    if !is_pending(&state, &realm, otp_form.request_id) {
+        return expired_request();
+    }
+
+    let db = Arc::clone(&state.read().unwrap().db);
+    let account_id = match AuthManager::new(db, keys)
+        .verify_mfa_challenge(&otp_form.mfa_token, &otp_form.otp)
+        .await
+    {
+        Ok(account_id) => account_id,
+        Err(e) => {
+            tracing::info!("second factor in realm {} refused: {}", realm, e);
+            if e.is::<SecondFactorLocked>() {
+                return render_login_form(
+                    &state,
+                    &realm,
+                    StatusCode::UNAUTHORIZED,
+                    otp_form.request_id,
+                    "Too many wrong codes, please try again later",
+                );
+            }
+            // The challenge is dropped after too many wrong codes.
+            if AuthManager::mfa_challenge_account(&otp_form.mfa_token, MfaPurpose::Verify).is_none()
+            {
+                return render_login_form(
+                    &state,
+                    &realm,
+                    StatusCode::UNAUTHORIZED,
+                    otp_form.request_id,
+                    "Too many wrong codes, please sign in again",
+                );
+            }
+            return render_otp_form(
+                &realm,
+                StatusCode::UNAUTHORIZED,
+                otp_form.request_id,
+                &otp_form.mfa_token,
+                "Invalid one-time code",
+            );
+        }
+    };
+
+    let mut state = state.write().unwrap();
+    let Some(request) = state.realm_mut(&realm).and_then(|r| {
+        r.requests
+            .iter_mut()
+            .find(|r| r.id == otp_form.request_id && r.grant.is_none())
+    }) else {
+        return expired_request();
+    };
+
+    issue_code(request, account_id)
+}
+
+/// Completes a request for the account that signed in, sending the user agent back to
+/// the client with a fresh authorization code.
+pub(crate) fn issue_code(request: &mut AuthRequest, account_id: String) -> Response {
+    match grant_code(request, account_id) {
+        Some(redirect) => Redirect::to(redirect.as_str()).into_response(),
+        None => expired_request(),
+    }
+}
+
+/// Grants a fresh authorization code, returning where to send the user agent with it.
+pub(crate) fn grant_code(request: &mut AuthRequest, account_id: String) -> Option<Url> {
+    let grant = AuthGrant {
+        code: new_authorization_code(),
+        account_id,
+        issued_at: chrono::Utc::now().naive_utc(),
+    };
+    let mut redirect = Url::parse(&request.redirect_uri).ok()?;
+    {
+        let mut pairs = redirect.query_pairs_mut();
+        pairs.append_pair("code", &grant.code);
+        if let Some(state) = &request.state {
+            pairs.append_pair("state", state);
+        }
+    }
+    request.grant = Some(grant);
+
+    Some(redirect)
+}
+
+pub(crate) fn is_pending(state: &SharedState, realm: &str, request_id: uuid::Uuid) -> bool {
+    let now = chrono::Utc::now().naive_utc();
+    state.read().unwrap().realm(realm).is_some_and(|r| {
+        r.requests
+            .iter()
+            .any(|r| r.id == request_id && r.grant.is_none() && !r.is_expired(now))
+    })
+}
+
+pub(crate) fn render_login_form(
+    state: &SharedState,
+    realm: &str,
+    status: StatusCode,
+    request_id: uuid::Uuid,
+    error: &'static str,
+) -> Response {
+    let providers: String = state
+        .read()
+        .unwrap()
+        .identity_provider_names()
+        .iter()
+        .map(|name| {
+            format!(
+                r#"<a href="/{realm}/login/{name}?request_id={request_id}" class="block text-indigo-500 py-2">Sign in with {name}</a>"#
+            )
+        })
+        .collect();
+    let html = LOGIN_FORM_TEMPLATE
+        .replace("{{request_id}}", &request_id.to_string())
+        .replace("{{realm}}", realm)
+        .replace("{{error}}", error)
+        .replace("{{providers}}", &providers);
+
+    (status, Html(html)).into_response()
+}
+
+pub(crate) fn render_otp_form(
+    realm: &str,
+    status: StatusCode,
+    request_id: uuid::Uuid,
+    mfa_token: &str,
+    error: &'static str,
+) -> Response {
+    let html = OTP_FORM_TEMPLATE
+        .replace("{{request_id}}", &request_id.to_string())
+        .replace("{{realm}}", realm)
+        .replace("{{mfa_token}}", mfa_token)
+        .replace("{{error}}", error);
+
+    (status, Html(html)).into_response()
+}
+
+pub(crate) fn expired_request() -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Html(String::from(
+            "<div>This sign-in has expired, please start over from the app.</div>",
+        )),
+    )
+        .into_response()
+}
+
+fn new_authorization_code() -> String {
+    use rand::RngCore;
+
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
 }
 
 #[derive(Debug, Deserialize)]
 pub(crate) struct LoginQuery {
-    request_id: String,
+    pub(crate) request_id: uuid::Uuid,
 }
 
 #[derive(Debug, Deserialize)]
 pub(crate) struct LoginFormData {
     username: String,
     password: String,
+    #[serde(default)]
+    otp: Option<String>,
+    request_id: uuid::Uuid,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct OtpFormData {
+    mfa_token: String,
+    otp: String,
+    request_id: uuid::Uuid,
 }
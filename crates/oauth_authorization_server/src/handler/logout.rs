@@ -0,0 +1,125 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! GET|POST /oidc/logout — RP-initiated logout, ends the session an ID token was issued for.
+//!
+//! See https://openid.net/specs/openid-connect-rpinitiated-1_0.html
+
+use std::sync::Arc;
+
+use axum::extract::{Extension, Query, State};
+use axum::http::StatusCode;
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use axum::Form;
+use common::auth::auth_manager::AuthManager;
+use common::auth::keys::KeyRing;
+use serde::Deserialize;
+use url::Url;
+
+use super::authorize::SharedState;
+
+#[derive(Debug, Deserialize)]
+pub struct EndSessionRequest {
+    pub id_token_hint: Option<String>,
+    pub client_id: Option<String>,
+    pub post_logout_redirect_uri: Option<Url>,
+    pub state: Option<String>,
+}
+
+/// The claims of an ID token needed to find its session.
+#[derive(Debug, Deserialize)]
+struct IdTokenHint {
+    aud: String,
+    sid: String,
+}
+
+pub(crate) async fn get_end_session(
+    State(state): State<SharedState>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    Query(req): Query<EndSessionRequest>,
+) -> Response {
+    end_session(state, keys, req).await
+}
+
+pub(crate) async fn post_end_session(
+    State(state): State<SharedState>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    Form(req): Form<EndSessionRequest>,
+) -> Response {
+    end_session(state, keys, req).await
+}
+
+async fn end_session(state: SharedState, keys: Arc<KeyRing>, req: EndSessionRequest) -> Response {
+    let Some(id_token) = req.id_token_hint.as_deref() else {
+        return (StatusCode::BAD_REQUEST, "id_token_hint is required").into_response();
+    };
+
+    // Expired ID tokens still identify the session they belong to.
+    let mut validation = jsonwebtoken::Validation::default();
+    validation.validate_exp = false;
+    validation.validate_aud = false;
+    let hint = match keys.decode::<IdTokenHint>(id_token, &validation) {
+        Ok(data) => data.claims,
+        Err(e) => {
+            tracing::info!("logout with an invalid id_token_hint: {}", e);
+            return (StatusCode::BAD_REQUEST, "id_token_hint is invalid").into_response();
+        }
+    };
+    if req
+        .client_id
+        .as_ref()
+        .is_some_and(|client_id| *client_id != hint.aud)
+    {
+        return (
+            StatusCode::BAD_REQUEST,
+            "client_id does not match the id_token_hint",
+        )
+            .into_response();
+    }
+
+    // Checked before the session ends, so a rejected request changes nothing.
+    if let Some(uri) = &req.post_logout_redirect_uri {
+        let registered = state
+            .read()
+            .unwrap()
+            .client(&hint.aud)
+            .is_some_and(|client| client.accepts_post_logout_redirect_uri(uri));
+        if !registered {
+            return (
+                StatusCode::BAD_REQUEST,
+                "post_logout_redirect_uri is not registered for this client",
+            )
+                .into_response();
+        }
+    }
+
+    let db = Arc::clone(&state.read().unwrap().db);
+    if let Err(e) = AuthManager::new(db, keys).revoke_session(&hint.sid).await {
+        tracing::error!("logout of session {} failed: {}", hint.sid, e);
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    }
+
+    match req.post_logout_redirect_uri {
+        Some(mut uri) => {
+            if let Some(state) = &req.state {
+                uri.query_pairs_mut().append_pair("state", state);
+            }
+            Redirect::to(uri.as_str()).into_response()
+        }
+        None => Html("<div>You have been signed out.</div>").into_response(),
+    }
+}
@@ -0,0 +1,125 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Passwordless sign-in with a passkey, started from the button on the login form.
+//!
+//! GET  /:realm/login/passkey  — options for `navigator.credentials.get()`
+//! POST /:realm/login/passkey  — the assertion, answered with where to send the user agent
+
+use std::sync::Arc;
+
+use axum::extract::{Extension, Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use common::auth::auth_manager::AuthManager;
+use common::auth::keys::KeyRing;
+use common::auth::passkey::AssertionCredential;
+use common::auth::webauthn::RelyingParty;
+use serde::Deserialize;
+
+use super::authorize::SharedState;
+use super::login::{grant_code, is_pending, LoginQuery};
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct PasskeyLoginForm {
+    request_id: uuid::Uuid,
+    credential: AssertionCredential,
+}
+
+fn error_response(status: StatusCode, message: impl ToString) -> Response {
+    (
+        status,
+        Json(serde_json::json!({"error": message.to_string()})),
+    )
+        .into_response()
+}
+
+fn expired() -> Response {
+    error_response(
+        StatusCode::BAD_REQUEST,
+        "This sign-in has expired, please start over from the app.",
+    )
+}
+
+pub(crate) async fn passkey_login_options(
+    Path(realm): Path<String>,
+    Query(query): Query<LoginQuery>,
+    State(state): State<SharedState>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    Extension(relying_party): Extension<Arc<RelyingParty>>,
+) -> Response {
+    if !is_pending(&state, &realm, query.request_id) {
+        return expired();
+    }
+
+    let db = Arc::clone(&state.read().unwrap().db);
+    match AuthManager::new(db, keys)
+        .passkey_login_options(&relying_party, None)
+        .await
+    {
+        Ok(options) => (StatusCode::OK, Json(options)).into_response(),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e),
+    }
+}
+
+pub(crate) async fn passkey_login(
+    Path(realm): Path<String>,
+    State(state): State<SharedState>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    Extension(relying_party): Extension<Arc<RelyingParty>>,
+    Json(form): Json<PasskeyLoginForm>,
+) -> Response {
+    if !is_pending(&state, &realm, form.request_id) {
+        return expired();
+    }
+
+    let db = Arc::clone(&state.read().unwrap().db);
+    let account = match AuthManager::new(db, keys)
+        .authenticate_with_passkey(&relying_party, &form.credential, None)
+        .await
+    {
+        Ok(account) => account,
+        Err(e) => {
+            tracing::info!("passkey sign-in in realm {} failed: {}", realm, e);
+            return error_response(
+                StatusCode::UNAUTHORIZED,
+                "The passkey could not be verified",
+            );
+        }
+    };
+
+    // The request may have expired while the passkey was checked.
+    let mut state = state.write().unwrap();
+    let Some(redirect) = state
+        .realm_mut(&realm)
+        .and_then(|r| {
+            r.requests
+                .iter_mut()
+                .find(|r| r.id == form.request_id && r.grant.is_none())
+        })
+        .and_then(|request| grant_code(request, account.account_id))
+    else {
+        return expired();
+    };
+
+    (
+        StatusCode::OK,
+        Json(serde_json::json!({"redirect_to": redirect.as_str()})),
+    )
+        .into_response()
+}
@@ -18,20 +18,41 @@
 //! POST /oidc/token — OAuth 2.0 token endpoint (RFC 6749).
 //!
 //! Supported grant types:
+//!   - `authorization_code`                — code from `/oidc/authorize`, verified with PKCE
 //!   - `password`                          — email + password (account login)
 //!   - `urn:photos.network:access_code`    — single access code (customer login)
+//!   - `refresh_token`                     — rotates the refresh token of a session
+//!   - `urn:photos.network:mfa_otp`        — `mfa_token` of a password grant + one-time code
+//!
+//! Confidential clients authenticate with HTTP Basic or `client_secret` in the form body.
+//!
+//! POST /oidc/revoke — token revocation (RFC 7009).
 
+use std::net::SocketAddr;
 use std::sync::Arc;
 
-use axum::{extract::State, http::StatusCode, response::IntoResponse, Form, Json};
-use common::auth::auth_manager::AuthManager;
+use axum::extract::{ConnectInfo, Extension, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::{response::IntoResponse, Form, Json};
+use chrono::Utc;
+use common::auth::account_token::AccountMailSettings;
+use common::auth::auth_manager::{AccountLoginResponse, AuthManager, TokenPair, ACCESS_TOKEN_TTL};
+use common::auth::keys::KeyRing;
+use common::auth::mfa::AccountLogin;
+use common::auth::session::{SessionClient, TrustedProxies};
+use common::config::configuration::Configuration;
 use serde::{Deserialize, Serialize};
 use tracing::error;
 
 use super::authorize::SharedState;
+use crate::client::client_credentials;
+use crate::pkce;
 
+const GRANT_AUTHORIZATION_CODE: &str = "authorization_code";
 const GRANT_PASSWORD: &str = "password";
 const GRANT_ACCESS_CODE: &str = "urn:photos.network:access_code";
+const GRANT_REFRESH_TOKEN: &str = "refresh_token";
+const GRANT_MFA_OTP: &str = "urn:photos.network:mfa_otp";
 
 #[derive(Debug, Deserialize)]
 pub struct TokenRequest {
@@ -39,6 +60,15 @@ pub struct TokenRequest {
     pub username: Option<String>,
     pub password: Option<String>,
     pub access_code: Option<String>,
+    pub refresh_token: Option<String>,
+    pub code: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    pub code_verifier: Option<String>,
+    pub mfa_token: Option<String>,
+    /// Code of the authenticator app or a recovery code.
+    pub otp: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -46,6 +76,47 @@ pub struct TokenResponse {
     pub access_token: String,
     pub token_type: &'static str,
     pub expires_in: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+}
+
+impl From<TokenPair> for TokenResponse {
+    fn from(tokens: TokenPair) -> Self {
+        Self {
+            access_token: tokens.access_token,
+            token_type: "Bearer",
+            expires_in: tokens.expires_in,
+            refresh_token: Some(tokens.refresh_token),
+            id_token: None,
+        }
+    }
+}
+
+/// OpenID Connect ID token, signed with a key of the realm the user signed in to.
+#[derive(Debug, Serialize)]
+struct IdTokenClaims {
+    iss: String,
+    sub: String,
+    aud: String,
+    exp: i64,
+    iat: i64,
+    auth_time: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nonce: Option<String>,
+    /// Session the tokens belong to, needed to end it later.
+    sid: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+}
+
+/// A `token_type_hint` is ignored, refresh and access tokens are both looked up.
+#[derive(Debug, Deserialize)]
+pub struct RevocationRequest {
+    pub token: String,
 }
 
 #[derive(Debug, Serialize)]
@@ -54,103 +125,215 @@ pub struct TokenErrorResponse {
     pub error_description: String,
 }
 
+/// Answer to a password grant of an account with a second factor. The login continues
+/// with the `mfa_otp` grant, or with enrolling a second factor if the policy requires one.
+#[derive(Debug, Serialize)]
+pub struct MfaRequiredResponse {
+    pub error: &'static str,
+    pub error_description: String,
+    pub mfa_token: String,
+    pub enrollment_required: bool,
+    pub expires_in: u64,
+    pub methods: Vec<String>,
+}
+
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn token_endpoint(
     State(state): State<SharedState>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    Extension(account_mail): Extension<Arc<AccountMailSettings>>,
+    Extension(proxies): Extension<Arc<TrustedProxies>>,
+    Extension(config): Extension<Arc<Configuration>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
     Form(req): Form<TokenRequest>,
 ) -> impl IntoResponse {
     let db = Arc::clone(&state.read().unwrap().db);
-    let auth = AuthManager::new(db);
+    let auth = AuthManager::new(db, keys);
+    let client =
+        SessionClient::from_request(&headers, peer.map(|ConnectInfo(addr)| addr), &proxies);
+
+    // Whatever the grant, a client that names itself has to prove it is that client.
+    let client_id = match authenticated_client(&state, &headers, &req) {
+        Ok(client_id) => client_id,
+        Err(e) => return e.into_response(),
+    };
 
     match req.grant_type.as_str() {
-        GRANT_PASSWORD => handle_password_grant(auth, req).await,
-        GRANT_ACCESS_CODE => handle_access_code_grant(auth, req).await,
-        _ => (
+        GRANT_AUTHORIZATION_CODE => {
+            handle_authorization_code_grant(&state, auth, req, client_id, &client).await
+        }
+        GRANT_PASSWORD => {
+            handle_password_grant(
+                auth,
+                &account_mail,
+                config.auth.require_admin_mfa,
+                req,
+                &client,
+            )
+            .await
+        }
+        GRANT_ACCESS_CODE => handle_access_code_grant(auth, req, &client).await,
+        GRANT_REFRESH_TOKEN => handle_refresh_token_grant(auth, req, &client).await,
+        GRANT_MFA_OTP => handle_mfa_otp_grant(auth, req, &client).await,
+        _ => token_error(
             StatusCode::BAD_REQUEST,
-            Json(TokenErrorResponse {
-                error: "unsupported_grant_type",
-                error_description: format!(
-                    "Supported grant types: {GRANT_PASSWORD}, {GRANT_ACCESS_CODE}"
+            "unsupported_grant_type",
+            &format!(
+                    "Supported grant types: {GRANT_AUTHORIZATION_CODE}, {GRANT_PASSWORD}, {GRANT_ACCESS_CODE}, {GRANT_REFRESH_TOKEN}, {GRANT_MFA_OTP}"
                 ),
-            }),
-        )
-            .into_response(),
+        ),
     }
 }
 
-async fn handle_password_grant(auth: AuthManager, req: TokenRequest) -> axum::response::Response {
+/// Revokes the session behind a refresh or access token. Unknown tokens are
+/// answered with 200 as well, so that clients cannot probe for valid ones.
+pub(crate) async fn revocation_endpoint(
+    State(state): State<SharedState>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    Form(req): Form<RevocationRequest>,
+) -> impl IntoResponse {
+    let db = Arc::clone(&state.read().unwrap().db);
+
+    match AuthManager::new(db, keys).revoke_token(&req.token).await {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(e) => {
+            error!("token revocation failed: {}", e);
+            token_error(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "temporarily_unavailable",
+                "The token could not be revoked",
+            )
+        }
+    }
+}
+
+async fn handle_password_grant(
+    auth: AuthManager,
+    account_mail: &AccountMailSettings,
+    require_admin_mfa: bool,
+    req: TokenRequest,
+    client: &SessionClient,
+) -> axum::response::Response {
     let username = match req.username.filter(|s| !s.is_empty()) {
         Some(u) => u,
         None => {
-            return (
+            return token_error(
                 StatusCode::BAD_REQUEST,
-                Json(TokenErrorResponse {
-                    error: "invalid_request",
-                    error_description: "username required for password grant".to_string(),
-                }),
+                "invalid_request",
+                "username required for password grant",
             )
-                .into_response()
         }
     };
     let password = match req.password.filter(|s| !s.is_empty()) {
         Some(p) => p,
         None => {
-            return (
+            return token_error(
                 StatusCode::BAD_REQUEST,
-                Json(TokenErrorResponse {
-                    error: "invalid_request",
-                    error_description: "password required for password grant".to_string(),
-                }),
+                "invalid_request",
+                "password required for password grant",
             )
-                .into_response()
         }
     };
 
-    match auth.verify_account_credentials(username, password).await {
-        Ok(resp) => {
-            let _ = auth.update_last_login_account(resp.account_id).await;
+    match auth
+        .verify_account_credentials(account_mail, require_admin_mfa, username, password, client)
+        .await
+    {
+        Ok(AccountLogin::Complete(resp)) => account_token_response(&auth, resp).await,
+        Ok(AccountLogin::MfaRequired(challenge)) => {
+            let error_description = if challenge.enrollment_required {
+                "Enroll a second factor at /auth/account/mfa/totp with the mfa_token"
+            } else if challenge.methods.iter().any(|m| m == "totp") {
+                "Continue with the urn:photos.network:mfa_otp grant"
+            } else {
+                "Continue with a passkey at /auth/account/login/passkey"
+            };
             (
-                StatusCode::OK,
-                Json(TokenResponse {
-                    access_token: resp.jwt_token.unwrap_or_default(),
-                    token_type: "Bearer",
-                    expires_in: 86400,
+                StatusCode::FORBIDDEN,
+                Json(MfaRequiredResponse {
+                    error: "mfa_required",
+                    error_description: error_description.to_string(),
+                    mfa_token: challenge.mfa_token,
+                    enrollment_required: challenge.enrollment_required,
+                    expires_in: challenge.expires_in,
+                    methods: challenge.methods,
                 }),
             )
                 .into_response()
         }
         Err(e) => {
             error!("password grant failed: {}", e);
-            (
+            token_error(
                 StatusCode::UNAUTHORIZED,
-                Json(TokenErrorResponse {
-                    error: "invalid_client",
-                    error_description: "Invalid email or password".to_string(),
-                }),
+                "invalid_client",
+                "Invalid email or password",
+            )
+        }
+    }
+}
+
+async fn handle_mfa_otp_grant(
+    auth: AuthManager,
+    req: TokenRequest,
+    client: &SessionClient,
+) -> axum::response::Response {
+    let (Some(mfa_token), Some(otp)) = (req.mfa_token, req.otp) else {
+        return token_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_request",
+            "mfa_token and otp required for this grant type",
+        );
+    };
+
+    match auth.complete_mfa_login(&mfa_token, &otp, client).await {
+        Ok(resp) => account_token_response(&auth, resp).await,
+        Err(e) => {
+            error!("mfa_otp grant failed: {}", e);
+            token_error(
+                StatusCode::BAD_REQUEST,
+                "invalid_grant",
+                "Invalid or expired mfa_token or otp",
             )
-                .into_response()
         }
     }
 }
 
+async fn account_token_response(
+    auth: &AuthManager,
+    resp: AccountLoginResponse,
+) -> axum::response::Response {
+    let _ = auth.update_last_login_account(resp.account_id).await;
+    (
+        StatusCode::OK,
+        Json(TokenResponse {
+            access_token: resp.jwt_token.unwrap_or_default(),
+            token_type: "Bearer",
+            expires_in: resp.expires_in.unwrap_or_default(),
+            refresh_token: resp.refresh_token,
+            id_token: None,
+        }),
+    )
+        .into_response()
+}
+
 async fn handle_access_code_grant(
     auth: AuthManager,
     req: TokenRequest,
+    client: &SessionClient,
 ) -> axum::response::Response {
     let code = match req.access_code.filter(|s| !s.is_empty()) {
         Some(c) => c,
         None => {
-            return (
+            return token_error(
                 StatusCode::BAD_REQUEST,
-                Json(TokenErrorResponse {
-                    error: "invalid_request",
-                    error_description: "access_code required for this grant type".to_string(),
-                }),
+                "invalid_request",
+                "access_code required for this grant type",
             )
-                .into_response()
         }
     };
 
-    match auth.verify_access_code(code).await {
+    match auth.verify_access_code(code, client).await {
         Ok(resp) => {
             let _ = auth.update_last_login(resp.customer_id).await;
             (
@@ -158,21 +341,255 @@ async fn handle_access_code_grant(
                 Json(TokenResponse {
                     access_token: resp.jwt_token.unwrap_or_default(),
                     token_type: "Bearer",
-                    expires_in: 86400,
+                    expires_in: resp.expires_in.unwrap_or_default(),
+                    refresh_token: resp.refresh_token,
+                    id_token: None,
                 }),
             )
                 .into_response()
         }
         Err(e) => {
             error!("access_code grant failed: {}", e);
-            (
+            token_error(
                 StatusCode::UNAUTHORIZED,
-                Json(TokenErrorResponse {
-                    error: "invalid_client",
-                    error_description: "Invalid access code".to_string(),
-                }),
+                "invalid_client",
+                "Invalid access code",
             )
-                .into_response()
         }
     }
 }
+
+async fn handle_refresh_token_grant(
+    auth: AuthManager,
+    req: TokenRequest,
+    client: &SessionClient,
+) -> axum::response::Response {
+    let refresh_token = match req.refresh_token.filter(|s| !s.is_empty()) {
+        Some(t) => t,
+        None => {
+            return token_error(
+                StatusCode::BAD_REQUEST,
+                "invalid_request",
+                "refresh_token required for this grant type",
+            )
+        }
+    };
+
+    match auth.refresh_session(&refresh_token, client).await {
+        Ok(tokens) => (StatusCode::OK, Json(TokenResponse::from(tokens))).into_response(),
+        Err(e) => {
+            error!("refresh_token grant failed: {}", e);
+            token_error(
+                StatusCode::BAD_REQUEST,
+                "invalid_grant",
+                "Refresh token is invalid, expired or revoked",
+            )
+        }
+    }
+}
+
+fn token_error(
+    status: StatusCode,
+    error: &'static str,
+    description: &str,
+) -> axum::response::Response {
+    (
+        status,
+        Json(TokenErrorResponse {
+            error,
+            error_description: description.to_string(),
+        }),
+    )
+        .into_response()
+}
+
+/// Why the client of a grant was refused.
+enum ClientError {
+    /// Form and `Authorization` header name different clients.
+    Ambiguous,
+    /// A confidential client without its secret, or a wrong secret.
+    Unauthenticated,
+}
+
+impl IntoResponse for ClientError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            ClientError::Ambiguous => token_error(
+                StatusCode::BAD_REQUEST,
+                "invalid_request",
+                "client_id differs from the authenticated client",
+            ),
+            ClientError::Unauthenticated => (
+                [(header::WWW_AUTHENTICATE, "Basic")],
+                token_error(
+                    StatusCode::UNAUTHORIZED,
+                    "invalid_client",
+                    "Client authentication failed",
+                ),
+            )
+                .into_response(),
+        }
+    }
+}
+
+/// The client a grant is made for. Confidential clients have to authenticate with their
+/// secret, by HTTP Basic or in the form body; public clients only name themselves.
+fn authenticated_client(
+    state: &SharedState,
+    headers: &HeaderMap,
+    req: &TokenRequest,
+) -> Result<Option<String>, ClientError> {
+    let (client_id, client_secret) = client_credentials(
+        headers,
+        req.client_id.as_deref(),
+        req.client_secret.as_deref(),
+    );
+    let Some(client_id) = client_id else {
+        return Ok(None);
+    };
+    if req.client_id.as_ref().is_some_and(|id| *id != client_id) {
+        return Err(ClientError::Ambiguous);
+    }
+
+    let state = state.read().unwrap();
+    let confidential = state
+        .client(&client_id)
+        .is_some_and(|client| client.secret.is_some());
+    let authenticated = match client_secret.filter(|secret| !secret.is_empty()) {
+        Some(secret) => state.authenticate_client(&client_id, &secret),
+        None => !confidential,
+    };
+    if !authenticated {
+        return Err(ClientError::Unauthenticated);
+    }
+    Ok(Some(client_id))
+}
+
+async fn handle_authorization_code_grant(
+    state: &SharedState,
+    auth: AuthManager,
+    req: TokenRequest,
+    client_id: Option<String>,
+    client: &SessionClient,
+) -> axum::response::Response {
+    let (Some(code), Some(redirect_uri), Some(client_id), Some(code_verifier)) =
+        (req.code, req.redirect_uri, client_id, req.code_verifier)
+    else {
+        return token_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_request",
+            "code, redirect_uri, client_id and code_verifier required for this grant type",
+        );
+    };
+
+    // Taken out right away, a code is never accepted twice, not even after a failed attempt.
+    let Some((realm, request)) = state.write().unwrap().take_authorization_code(&code) else {
+        return token_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_grant",
+            "Unknown or already used code",
+        );
+    };
+    let Some(grant) = request.grant.clone() else {
+        return token_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_grant",
+            "Unknown or already used code",
+        );
+    };
+
+    if request.client_id != client_id {
+        return token_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_grant",
+            "Code was issued to another client",
+        );
+    }
+    if request.is_expired(Utc::now().naive_utc()) {
+        return token_error(StatusCode::BAD_REQUEST, "invalid_grant", "Code has expired");
+    }
+    if url::Url::parse(&redirect_uri)
+        .map(String::from)
+        .ok()
+        .as_deref()
+        != Some(request.redirect_uri.as_str())
+    {
+        return token_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_grant",
+            "redirect_uri differs from the authorization request",
+        );
+    }
+    if !pkce::verify_s256(&code_verifier, &request.code_challenge) {
+        return token_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_grant",
+            "code_verifier does not match the code_challenge",
+        );
+    }
+
+    let account = match auth.db.get_account_by_id(&grant.account_id).await {
+        Ok(account) => account,
+        Err(e) => {
+            error!("authorization_code grant for unknown account: {}", e);
+            return token_error(
+                StatusCode::BAD_REQUEST,
+                "invalid_grant",
+                "Account no longer exists",
+            );
+        }
+    };
+    let tokens = match auth
+        .start_session(&account.account_id, "account", account.is_admin, client)
+        .await
+    {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            error!("authorization_code grant failed: {}", e);
+            return token_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "server_error",
+                "Could not start a session",
+            );
+        }
+    };
+    let _ = auth
+        .update_last_login_account(account.account_id.clone())
+        .await;
+
+    let scopes: Vec<&str> = request.scope.split_whitespace().collect();
+    let session_id = tokens.session_id.clone();
+    let mut response = TokenResponse::from(tokens);
+    if scopes.contains(&"openid") {
+        let now = Utc::now().timestamp();
+        let claims = IdTokenClaims {
+            iss: realm.issuer(),
+            sub: account.account_id.clone(),
+            aud: client_id,
+            exp: now + ACCESS_TOKEN_TTL,
+            iat: now,
+            auth_time: grant.issued_at.and_utc().timestamp(),
+            nonce: request.nonce.clone(),
+            sid: session_id,
+            email: scopes.contains(&"email").then(|| account.email.clone()),
+            name: if scopes.contains(&"profile") {
+                account.display_name.clone()
+            } else {
+                None
+            },
+        };
+        match auth.keys.encode_for_realm(&realm.name, &claims) {
+            Ok(id_token) => response.id_token = Some(id_token),
+            Err(e) => {
+                error!("could not sign ID token: {}", e);
+                return token_error(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "server_error",
+                    "Could not sign the ID token",
+                );
+            }
+        }
+    }
+
+    (StatusCode::OK, Json(response)).into_response()
+}
@@ -0,0 +1,114 @@
+/* Photos.network · A privacy first photo storage and sharing service for fediverse.
+ * Copyright (C) 2020 Photos network developers
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of the
+ * License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! GET|POST /oidc/userinfo — OpenID Connect UserInfo endpoint.
+//!
+//! See Section 5.3: https://openid.net/specs/openid-connect-core-1_0.html#UserInfo
+
+use std::sync::Arc;
+
+use axum::extract::{Extension, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use common::auth::auth_manager::AuthManager;
+use common::auth::keys::KeyRing;
+use serde::Serialize;
+use tracing::error;
+
+use super::authorize::SharedState;
+
+/// Standard claims plus the `role` and `is_admin` claims of our access tokens.
+#[derive(Debug, Serialize)]
+pub struct UserInfoResponse {
+    pub sub: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub role: String,
+    pub is_admin: bool,
+}
+
+pub(crate) async fn userinfo_handler(
+    State(state): State<SharedState>,
+    Extension(keys): Extension<Arc<KeyRing>>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(token) = bearer_token(&headers) else {
+        return invalid_token("Bearer access token required");
+    };
+    // Share tokens belong to a share link, not to a user.
+    let access = match AuthManager::decode_access_token(&keys, token) {
+        Ok(access) if access.session_id.is_some() => access,
+        _ => return invalid_token("Access token is invalid or expired"),
+    };
+
+    let db = Arc::clone(&state.read().unwrap().db);
+    let auth = AuthManager::new(Arc::clone(&db), keys);
+    match auth
+        .active_session(access.session_id.as_deref().unwrap_or_default())
+        .await
+    {
+        Ok(Some(_)) => {}
+        Ok(None) => return invalid_token("Session has ended"),
+        Err(e) => {
+            error!("userinfo session lookup failed: {}", e);
+            return StatusCode::SERVICE_UNAVAILABLE.into_response();
+        }
+    }
+
+    let (email, name) = match access.role.as_str() {
+        "account" => match db.get_account_by_id(&access.sub).await {
+            Ok(account) => (Some(account.email), account.display_name),
+            Err(_) => return invalid_token("Account no longer exists"),
+        },
+        _ => match auth.get_customer_by_id(access.sub.clone()).await {
+            Some(customer) => (None, customer.display_name),
+            None => return invalid_token("Customer no longer exists"),
+        },
+    };
+
+    Json(UserInfoResponse {
+        sub: access.sub,
+        email,
+        name,
+        role: access.role,
+        is_admin: access.is_admin,
+    })
+    .into_response()
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+}
+
+/// 401 with the `WWW-Authenticate` challenge of RFC 6750, Section 3.
+fn invalid_token(description: &str) -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        [(
+            header::WWW_AUTHENTICATE,
+            format!("Bearer error=\"invalid_token\", error_description=\"{description}\""),
+        )],
+        Json(serde_json::json!({"error": "invalid_token", "error_description": description})),
+    )
+        .into_response()
+}
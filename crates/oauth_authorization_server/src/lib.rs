@@ -22,9 +22,14 @@ use axum::Router;
 use handler::{
     authorize::authorization_handler,
     discovery::openid_discover_handler,
+    external::{external_callback, external_login},
+    introspect::introspection_endpoint,
     jwks::openid_jwks_handler,
-    login::{get_realm_login_form, post_realm_login},
-    token::token_endpoint,
+    login::{get_realm_login_form, post_realm_login, post_realm_otp},
+    logout::{get_end_session, post_end_session},
+    passkey::{passkey_login, passkey_login_options},
+    token::{revocation_endpoint, token_endpoint},
+    userinfo::userinfo_handler,
 };
 use state::ServerState;
 use std::sync::{Arc, RwLock};
@@ -32,6 +37,7 @@ use std::sync::{Arc, RwLock};
 pub mod client;
 pub mod config;
 pub mod error;
+mod pkce;
 pub mod query;
 pub mod realm;
 pub mod request;
@@ -39,9 +45,14 @@ pub mod state;
 pub mod handler {
     pub mod authorize;
     pub mod discovery;
+    pub mod external;
+    pub mod introspect;
     pub mod jwks;
     pub mod login;
+    pub mod logout;
+    pub mod passkey;
     pub mod token;
+    pub mod userinfo;
 }
 
 pub struct AuthorizationServerManager {}
@@ -58,11 +69,24 @@ impl AuthorizationServerManager {
             )
             .route("/oidc/authorize", get(authorization_handler))
             .route("/oidc/token", post(token_endpoint))
+            .route("/oidc/revoke", post(revocation_endpoint))
+            .route("/oidc/introspect", post(introspection_endpoint))
+            .route("/oidc/userinfo", get(userinfo_handler).post(userinfo_handler))
+            .route("/oidc/logout", get(get_end_session).post(post_end_session))
             .route("/jwk", get(openid_jwks_handler))
+            // Announced as `jwks_uri` by the discovery document
+            .route("/oidc/jwk", get(openid_jwks_handler))
             .route(
                 "/:realm/login",
                 get(get_realm_login_form).post(post_realm_login),
             )
+            .route(
+                "/:realm/login/passkey",
+                get(passkey_login_options).post(passkey_login),
+            )
+            .route("/:realm/login/otp", post(post_realm_otp))
+            .route("/:realm/login/:provider", get(external_login))
+            .route("/oidc/callback", get(external_callback))
             .layer(tower_http::trace::TraceLayer::new_for_http())
             .with_state(Arc::new(RwLock::new(server)))
     }
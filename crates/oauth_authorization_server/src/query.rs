@@ -19,14 +19,14 @@ use serde::Deserialize;
 use url::Url;
 
 #[derive(Debug, Deserialize)]
-#[allow(dead_code)]
 pub(crate) struct AuthorizeQuery {
     pub(crate) response_type: String,
     pub(crate) client_id: String,
-    pub(crate) state: String,
-    pub(crate) code_challenge: String,
-    pub(crate) code_challenge_method: String,
+    pub(crate) state: Option<String>,
+    pub(crate) code_challenge: Option<String>,
+    pub(crate) code_challenge_method: Option<String>,
     pub(crate) redirect_uri: Url,
+    #[serde(default)]
     pub(crate) scope: String,
-    pub(crate) nonce: String,
+    pub(crate) nonce: Option<String>,
 }
@@ -15,29 +15,68 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use common::auth::keys::JwtKey;
 use openidconnect::core::{
-    CoreClaimName, CoreJsonWebKeySet, CoreJwsSigningAlgorithm, CoreProviderMetadata,
-    CoreResponseType, CoreSubjectIdentifierType,
+    CoreAuthDisplay, CoreClaimName, CoreClaimType, CoreClientAuthMethod, CoreGrantType,
+    CoreJsonWebKey, CoreJsonWebKeyType, CoreJsonWebKeyUse, CoreJweContentEncryptionAlgorithm,
+    CoreJweKeyManagementAlgorithm, CoreJwsSigningAlgorithm, CoreResponseMode, CoreResponseType,
+    CoreSubjectIdentifierType,
 };
 use openidconnect::{
-    AuthUrl, EmptyAdditionalProviderMetadata, IssuerUrl, JsonWebKeySetUrl, ResponseTypes, TokenUrl,
+    AdditionalProviderMetadata, AuthUrl, IssuerUrl, JsonWebKeySetUrl, ResponseTypes, TokenUrl,
     UserInfoUrl,
 };
+use p256::pkcs8::{DecodePrivateKey, EncodePrivateKey, LineEnding};
+use rand::rngs::OsRng;
+use rsa::pkcs1::{DecodeRsaPrivateKey, EncodeRsaPrivateKey};
+use rsa::RsaPrivateKey;
+use serde::{Deserialize, Serialize};
+use tracing::info;
 
+use std::fs;
 use std::path::Path;
 
 use crate::client::Client;
 use crate::error::Error;
 use crate::request::AuthRequest;
 
+/// Discovery fields `CoreProviderMetadata` has no setters for.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExtraProviderMetadata {
+    pub end_session_endpoint: String,
+    pub introspection_endpoint: String,
+    pub revocation_endpoint: String,
+    pub code_challenge_methods_supported: Vec<String>,
+}
+
+impl AdditionalProviderMetadata for ExtraProviderMetadata {}
+
+pub type ProviderMetadata = openidconnect::ProviderMetadata<
+    ExtraProviderMetadata,
+    CoreAuthDisplay,
+    CoreClientAuthMethod,
+    CoreClaimName,
+    CoreClaimType,
+    CoreGrantType,
+    CoreJweContentEncryptionAlgorithm,
+    CoreJweKeyManagementAlgorithm,
+    CoreJwsSigningAlgorithm,
+    CoreJsonWebKeyType,
+    CoreJsonWebKeyUse,
+    CoreJsonWebKey,
+    CoreResponseMode,
+    CoreResponseType,
+    CoreSubjectIdentifierType,
+>;
+
 #[derive(Debug, Clone)]
 pub struct Realm {
-    #[allow(dead_code)]
     pub(crate) name: String,
     pub(crate) clients: Vec<Client>,
     pub(crate) domain: String,
-    pub(crate) provider_metadata: CoreProviderMetadata,
-    pub(crate) jwks: CoreJsonWebKeySet,
+    pub(crate) provider_metadata: ProviderMetadata,
+    /// RS256 and ES256 keys, generated on the first start.
+    pub(crate) keys: Vec<JwtKey>,
     pub(crate) requests: Vec<AuthRequest>,
 }
 
@@ -47,38 +86,28 @@ impl Realm {
         domain: &str,
         scheme: &str,
         clients: Vec<Client>,
-        _realm_keys_base_path: P,
+        realm_keys_base_path: P,
     ) -> Result<Self, Error> {
-        /*
-                let mut realm_key_file = File::open(
-                    realm_keys_base_path
-                        .as_ref()
-                        .join(name)
-                        .with_extension("pem"),
-                )
-                .unwrap_or_else(|_| {
-                    error!(
-                        "key ({}) not found in directory ({})!",
-                        name,
-                        realm_keys_base_path.as_ref().display()
-                    );
-                    // TODO: create default key file
-                    let mut default_key_file =
-                        File::create(format!("{}/{}.pem", realm_keys_base_path, name)).unwrap();
-                    let default_key = CoreRsaPrivateSigningKey::into() //CoreJsonWebKey::new_rsa();
-                    default_key_file.write(default_key
-                });
-                let mut realm_key_str = String::new();
-                realm_key_file
-                    .read_to_string(&mut realm_key_str)
-                    .map_err(|_| Error::CouldNotOpenRealmKey(name.to_owned()))?;
-        */
+        let keys = vec![
+            JwtKey::rsa(
+                name,
+                &load_or_create_rsa_key(realm_keys_base_path.as_ref(), name)?,
+            )
+            .map_err(|e| Error::MappedError(e.to_string()))?,
+            JwtKey::ec(
+                name,
+                &load_or_create_ec_key(realm_keys_base_path.as_ref(), name)?,
+            )
+            .map_err(|e| Error::MappedError(e.to_string()))?,
+        ];
+
         Ok(Self {
             name: name.to_owned(),
             domain: domain.to_owned(),
             clients,
+            keys,
             requests: vec![],
-            provider_metadata: CoreProviderMetadata::new(
+            provider_metadata: ProviderMetadata::new(
                 // Parameters required by the OpenID Connect Discovery spec.
                 IssuerUrl::new(format!("{}://{}", scheme, domain))?,
                 AuthUrl::new(format!("{}://{}/oidc/authorize", scheme, domain))?,
@@ -89,19 +118,19 @@ impl Realm {
                     // Recommended: support the code flow.
                     ResponseTypes::new(vec![CoreResponseType::Code]),
                 ],
-                // For user privacy, the Pairwise subject identifier type is preferred. This prevents
-                // distinct relying parties (clients) from knowing whether their users represent the same
-                // real identities. This identifier type is only useful for relying parties that don't
-                // receive the 'email', 'profile' or other personally-identifying scopes.
-                // The Public subject identifier type is also supported.
-                vec![CoreSubjectIdentifierType::Pairwise],
-                // Support the RS256 signature algorithm.
-                vec![CoreJwsSigningAlgorithm::RsaSsaPssSha256],
-                // OpenID Connect Providers may supply custom metadata by providing a struct that
-                // implements the AdditionalProviderMetadata trait. This requires manually using the
-                // generic ProviderMetadata struct rather than the CoreProviderMetadata type alias,
-                // however.
-                EmptyAdditionalProviderMetadata {},
+                // Every client sees the account id as `sub`.
+                vec![CoreSubjectIdentifierType::Public],
+                // Tokens are signed with RS256 or ES256, depending on the configuration.
+                vec![
+                    CoreJwsSigningAlgorithm::RsaSsaPkcs1V15Sha256,
+                    CoreJwsSigningAlgorithm::EcdsaP256Sha256,
+                ],
+                ExtraProviderMetadata {
+                    end_session_endpoint: format!("{}://{}/oidc/logout", scheme, domain),
+                    introspection_endpoint: format!("{}://{}/oidc/introspect", scheme, domain),
+                    revocation_endpoint: format!("{}://{}/oidc/revoke", scheme, domain),
+                    code_challenge_methods_supported: vec![String::from("S256")],
+                },
             )
             // Specify the token endpoint (required for the code flow).
             .set_token_endpoint(Some(TokenUrl::new(format!(
@@ -113,6 +142,21 @@ impl Realm {
                 "{}://{}/oidc/userinfo",
                 scheme, domain
             ))?))
+            // Grant types handled by `token_endpoint`.
+            .set_grant_types_supported(Some(vec![
+                CoreGrantType::AuthorizationCode,
+                CoreGrantType::RefreshToken,
+                CoreGrantType::Password,
+                CoreGrantType::Extension("urn:photos.network:access_code".to_string()),
+                CoreGrantType::Extension("urn:photos.network:mfa_otp".to_string()),
+            ]))
+            // Clients with a secret send it with HTTP Basic or in the form body, public
+            // clients rely on PKCE.
+            .set_token_endpoint_auth_methods_supported(Some(vec![
+                CoreClientAuthMethod::ClientSecretBasic,
+                CoreClientAuthMethod::ClientSecretPost,
+                CoreClientAuthMethod::None,
+            ]))
             // Recommended: specify the supported scopes.
             .set_scopes_supported(Some(vec![
                 openidconnect::Scope::new("openid".to_string()),
@@ -133,27 +177,95 @@ impl Realm {
                 CoreClaimName::new("sub".to_string()),
                 CoreClaimName::new("aud".to_string()),
                 CoreClaimName::new("email".to_string()),
-                CoreClaimName::new("email_verified".to_string()),
                 CoreClaimName::new("exp".to_string()),
                 CoreClaimName::new("iat".to_string()),
                 CoreClaimName::new("iss".to_string()),
+                CoreClaimName::new("auth_time".to_string()),
+                CoreClaimName::new("nonce".to_string()),
+                CoreClaimName::new("sid".to_string()),
                 CoreClaimName::new("name".to_string()),
-                CoreClaimName::new("given_name".to_string()),
-                CoreClaimName::new("family_name".to_string()),
-                CoreClaimName::new("picture".to_string()),
-                CoreClaimName::new("locale".to_string()),
+                CoreClaimName::new("role".to_string()),
+                CoreClaimName::new("is_admin".to_string()),
             ])),
-            jwks: CoreJsonWebKeySet::new(vec![
-            // RSA keys may also be constructed directly using CoreJsonWebKey::new_rsa(). Providers
-            // aiming to support other key types may provide their own implementation of the
-            // JsonWebKey trait or submit a PR to add the desired support to this crate.
-            //    CoreRsaPrivateSigningKey::from_pem(
-            //        &realm_key_str,
-            //        Some(JsonWebKeyId::new(format!("{}_key", name))),
-            //    )
-            //    .expect("Invalid RSA private key")
-            //    .as_verification_key(),
-            ]),
         })
     }
+
+    /// Base URL of this realm, the `iss` of its ID tokens.
+    pub(crate) fn issuer(&self) -> String {
+        self.provider_metadata.issuer().to_string()
+    }
+
+    pub(crate) fn client(&self, client_id: &str) -> Option<&Client> {
+        self.clients.iter().find(|client| client.id == client_id)
+    }
+
+    /// Forgets sign-ins that were abandoned and codes nobody exchanged.
+    pub(crate) fn prune_requests(&mut self, now: chrono::NaiveDateTime) {
+        self.requests.retain(|request| !request.is_expired(now));
+    }
+
+    /// Public keys of this realm, served at `/jwk`.
+    pub(crate) fn jwks(&self) -> serde_json::Value {
+        let keys: Vec<&serde_json::Value> = self.keys.iter().filter_map(JwtKey::jwk).collect();
+        serde_json::json!({ "keys": keys })
+    }
+
+    pub fn keys(&self) -> &[JwtKey] {
+        &self.keys
+    }
+}
+
+/// Reads `<realm>.pem`, creating a 2048 bit RSA key on the first start.
+fn load_or_create_rsa_key(base_path: &Path, realm: &str) -> Result<RsaPrivateKey, Error> {
+    let path = base_path.join(realm).with_extension("pem");
+    if path.exists() {
+        let pem =
+            fs::read_to_string(&path).map_err(|_| Error::CouldNotOpenRealmKey(realm.to_owned()))?;
+        return RsaPrivateKey::from_pkcs1_pem(&pem)
+            .or_else(|_| rsa::pkcs8::DecodePrivateKey::from_pkcs8_pem(&pem))
+            .map_err(|_| Error::CouldNotOpenRealmKey(realm.to_owned()));
+    }
+
+    info!("Creating RSA key of realm {} at {}", realm, path.display());
+    let key = RsaPrivateKey::new(&mut OsRng, 2048)?;
+    write_private_key(&path, key.to_pkcs1_pem(LineEnding::LF)?.as_bytes())?;
+    Ok(key)
+}
+
+/// Reads `<realm>.ec.pem`, creating a P-256 key on the first start.
+fn load_or_create_ec_key(base_path: &Path, realm: &str) -> Result<p256::SecretKey, Error> {
+    let path = base_path.join(format!("{}.ec.pem", realm));
+    if path.exists() {
+        let pem =
+            fs::read_to_string(&path).map_err(|_| Error::CouldNotOpenRealmKey(realm.to_owned()))?;
+        return p256::SecretKey::from_pkcs8_pem(&pem)
+            .map_err(|_| Error::CouldNotOpenRealmKey(realm.to_owned()));
+    }
+
+    info!(
+        "Creating P-256 key of realm {} at {}",
+        realm,
+        path.display()
+    );
+    let key = p256::SecretKey::random(&mut OsRng);
+    let pem = key
+        .to_pkcs8_pem(LineEnding::LF)
+        .map_err(|e| Error::MappedError(e.to_string()))?;
+    write_private_key(&path, pem.as_bytes())?;
+    Ok(key)
+}
+
+/// Writes a private key readable by the owner only.
+fn write_private_key(path: &Path, pem: &[u8]) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    std::io::Write::write_all(&mut options.open(path)?, pem)?;
+    Ok(())
 }
@@ -15,13 +15,44 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use chrono::{Duration, NaiveDateTime};
+use oauth_authentication::PendingLogin;
+
+/// Sign-in pages left open longer than this have to start over at `/oidc/authorize`.
+pub(crate) const REQUEST_TTL: Duration = Duration::minutes(10);
+
+/// Authorization codes have to be exchanged within this time, and only once.
+pub(crate) const CODE_TTL: Duration = Duration::seconds(60);
+
+/// An authorization request between `/oidc/authorize` and the code exchange at `/oidc/token`.
 #[derive(Debug, Clone)]
-#[allow(dead_code)]
 pub(crate) struct AuthRequest {
     pub(crate) id: uuid::Uuid,
-    pub(crate) code: Option<String>,
-    pub(crate) created_at: chrono::NaiveDateTime,
-    pub(crate) state: String,
+    pub(crate) client_id: String,
+    pub(crate) redirect_uri: String,
+    pub(crate) scope: String,
+    pub(crate) created_at: NaiveDateTime,
+    pub(crate) state: Option<String>,
     pub(crate) code_challenge: String,
-    pub(crate) nonce: String,
+    pub(crate) nonce: Option<String>,
+    /// A sign-in with an external identity provider, until it redirects back.
+    pub(crate) external_login: Option<PendingLogin>,
+    /// The code and the account that signed in, once the login form was submitted.
+    pub(crate) grant: Option<AuthGrant>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct AuthGrant {
+    pub(crate) code: String,
+    pub(crate) account_id: String,
+    pub(crate) issued_at: NaiveDateTime,
+}
+
+impl AuthRequest {
+    pub(crate) fn is_expired(&self, now: NaiveDateTime) -> bool {
+        match &self.grant {
+            Some(grant) => grant.issued_at + CODE_TTL <= now,
+            None => self.created_at + REQUEST_TTL <= now,
+        }
+    }
 }
@@ -15,12 +15,18 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use common::auth::keys::JwtKey;
 use common::database::ArcDynDatabase;
+use oauth_authentication::AuthenticationManager;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
 
 use crate::client::Client;
 use crate::config::ServerConfig;
 use crate::error::Error;
 use crate::realm::Realm;
+use crate::request::AuthRequest;
 
 #[derive(Clone)]
 pub struct ServerState {
@@ -28,6 +34,8 @@ pub struct ServerState {
     pub realms: Vec<Realm>,
     pub master_realm: Realm,
     pub db: ArcDynDatabase,
+    /// External OpenID Connect providers offered on the login form.
+    pub identity_providers: Option<Arc<AuthenticationManager>>,
 }
 
 impl ServerState {
@@ -50,6 +58,7 @@ impl ServerState {
             addr: config.listen_addr,
             realms,
             db,
+            identity_providers: None,
             master_realm: Realm::new(
                 "master",
                 &config.domain,
@@ -58,11 +67,93 @@ impl ServerState {
                     id: String::from("master_client"),
                     secret: None,
                     redirect_uri: String::from("photosapp://authenticate"),
+                    post_logout_redirect_uri: None,
                 }],
                 config.realm_keys_base_path.clone(),
             )?,
         })
     }
+
+    pub fn with_identity_providers(mut self, providers: AuthenticationManager) -> Self {
+        self.identity_providers = Some(Arc::new(providers));
+        self
+    }
+
+    /// Names of the identity providers users can sign in with instead of a password.
+    pub(crate) fn identity_provider_names(&self) -> Vec<String> {
+        self.identity_providers
+            .iter()
+            .flat_map(|manager| manager.providers())
+            .map(|provider| provider.name().to_string())
+            .collect()
+    }
+
+    /// Configured realms first, then the master realm.
+    pub(crate) fn realms_mut(&mut self) -> impl Iterator<Item = &mut Realm> {
+        self.realms
+            .iter_mut()
+            .chain(std::iter::once(&mut self.master_realm))
+    }
+
+    pub(crate) fn realm(&self, name: &str) -> Option<&Realm> {
+        self.realms
+            .iter()
+            .chain(std::iter::once(&self.master_realm))
+            .find(|realm| realm.name == name)
+    }
+
+    pub(crate) fn realm_mut(&mut self, name: &str) -> Option<&mut Realm> {
+        self.realms_mut().find(|realm| realm.name == name)
+    }
+
+    /// A client registered in any of the realms.
+    pub(crate) fn client(&self, client_id: &str) -> Option<&Client> {
+        self.realms
+            .iter()
+            .chain(std::iter::once(&self.master_realm))
+            .find_map(|realm| realm.client(client_id))
+    }
+
+    /// Checks the credentials of a confidential client. Public clients have no secret
+    /// and are never authenticated. Digests of equal length are compared in constant time,
+    /// so neither the secret nor its length leaks through the response time.
+    pub(crate) fn authenticate_client(&self, client_id: &str, client_secret: &str) -> bool {
+        self.client(client_id)
+            .and_then(|client| client.secret.as_deref())
+            .is_some_and(|secret| {
+                bool::from(Sha256::digest(secret).ct_eq(&Sha256::digest(client_secret)))
+            })
+    }
+
+    /// Removes the request an authorization code was issued for, so the code works only once.
+    pub(crate) fn take_authorization_code(&mut self, code: &str) -> Option<(Realm, AuthRequest)> {
+        for realm in self.realms_mut() {
+            let position = realm
+                .requests
+                .iter()
+                .position(|r| r.grant.as_ref().is_some_and(|g| g.code == code));
+            if let Some(position) = position {
+                let request = realm.requests.remove(position);
+                return Some((realm.clone(), request));
+            }
+        }
+        None
+    }
+
+    /// Signing keys of all realms, to be added to the `KeyRing`.
+    /// Realms configured under the master realm's name share its key files.
+    pub fn jwt_keys(&self) -> Vec<JwtKey> {
+        let mut keys: Vec<JwtKey> = vec![];
+        for key in std::iter::once(&self.master_realm)
+            .chain(self.realms.iter())
+            .flat_map(|realm| realm.keys())
+        {
+            if !keys.iter().any(|k| k.kid == key.kid) {
+                keys.push(key.clone());
+            }
+        }
+        keys
+    }
 }
 
 fn helper_get_scheme_from_config(use_ssl: bool) -> &'static str {
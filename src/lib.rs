@@ -38,8 +38,15 @@ use anyhow::{Context, Result};
 use axum::extract::DefaultBodyLimit;
 use axum::routing::{get, head};
 use axum::{Json, Router};
+use common::auth::account_token::AccountMailSettings;
+use common::auth::auth_manager::AuthManager;
+use common::auth::keys::KeyRing;
+use common::auth::session::TrustedProxies;
+use common::auth::webauthn::RelyingParty;
 use common::database::ArcDynDatabase;
-use common::zip_cache::{generate_and_write_all_zip, ZipCacheManager};
+use common::image_index::backfill_images;
+use common::jobs::{enqueue_unique, Job, JobWorker};
+use common::zip_cache::{all_zip_options, ZipCacheManager};
 use common::ApplicationState;
 use common::config::database_config::DatabaseDriver;
 use database::postgres::PostgresDatabase;
@@ -107,6 +114,7 @@ pub async fn start_server() -> Result<()> {
     let configuration =
         Arc::new(Configuration::new(CONFIG_PATH).context("Could not parse configuration!")?);
     debug!("Configuration: {}", configuration);
+
     let db: ArcDynDatabase = match configuration.database.clone() {
         Some(database) => {
             match database.driver {
@@ -145,28 +153,100 @@ pub async fn start_server() -> Result<()> {
             .expect("Failed to hash default password");
         let _ = db.create_account(account_id.clone(), default_email.to_string(), password_hash, Some("Admin".to_string())).await;
         let _ = db.set_account_admin(&account_id, true).await;
+        let _ = db.set_email_verified(&account_id, sqlx::types::chrono::Utc::now()).await;
     }
 
-    // init application state
-    let mut app_state = ApplicationState::new(Arc::clone(&configuration), db);
-    let zip_cache = Arc::new(ZipCacheManager::new());
+    let zip_cache = Arc::new(ZipCacheManager::new(configuration.media.zip_cache.clone()));
+    Arc::clone(&zip_cache).spawn_eviction();
 
+    // An `http://` prefix serves plain HTTP issuer URLs, e.g. for a local server.
+    let (use_ssl, external_domain) = match configuration.external_url.strip_prefix("http://") {
+        Some(domain) => (false, domain.to_owned()),
+        None => (
+            true,
+            configuration.external_url.trim_start_matches("https://").to_owned(),
+        ),
+    };
     let cfg = ServerConfig {
         listen_addr: configuration.internal_url.to_owned(),
-        domain: configuration.external_url.to_owned(),
-        use_ssl: true,
+        domain: external_domain.clone(),
+        use_ssl,
         realm_keys_base_path: Path::new("config").to_path_buf(),
         realms: vec![ConfigRealm {
             name: String::from("master"),
-            domain: Some(configuration.external_url.to_owned()),
-            clients: vec![Client {
+            domain: Some(external_domain.clone()),
+            clients: std::iter::once(Client {
                 id: String::from("mobile-app"),
                 secret: None,
                 redirect_uri: String::from("photosapp://authenticate"),
-            }],
+                post_logout_redirect_uri: None,
+            })
+            // Configured clients without an issuer are confidential, e.g. resource servers using
+            // token introspection. The others are identity providers to sign in with.
+            .chain(configuration.clients.iter().filter(|client| client.issuer.is_none()).map(|client| Client {
+                id: client.client_id.clone(),
+                secret: Some(client.client_secret.clone()),
+                redirect_uri: client.redirect_uris.first().cloned().unwrap_or_default(),
+                post_logout_redirect_uri: None,
+            }))
+            .collect(),
         }],
     };
-    let server = ServerState::new(cfg, Arc::clone(&app_state.database))?;
+    let server = ServerState::new(cfg, Arc::clone(&db))?;
+
+    // Sign tokens with the realm keys, refusing the well-known development secret in production.
+    let keys = Arc::new(
+        KeyRing::from_config(&configuration.auth, server.jwt_keys())
+            .context("Could not load JWT signing keys!")?,
+    );
+    let scheme = if use_ssl { "https" } else { "http" };
+    let identity_providers = AuthenticationManager::new(
+        &configuration.clients,
+        &format!("{}://{}/oidc/callback", scheme, external_domain),
+        Arc::clone(&db),
+        Arc::clone(&keys),
+    )
+    .await;
+    let server = server.with_identity_providers(identity_providers);
+
+    // Passkeys are scoped to the external domain, unless configured otherwise.
+    let relying_party = Arc::new(RelyingParty {
+        id: configuration.auth.passkey_rp_id.clone().unwrap_or_else(|| {
+            external_domain.split(':').next().unwrap_or_default().to_string()
+        }),
+        name: String::from("Photos.network"),
+        origins: std::iter::once(format!("{}://{}", scheme, external_domain))
+            .chain(configuration.auth.passkey_origins.iter().cloned())
+            .collect(),
+    });
+
+    // Verification and password reset mails, written to `data/outbox` without an SMTP server.
+    let mailer =
+        common::mail::from_config(&configuration.mail).context("Could not set up the mailer!")?;
+    let account_mail = Arc::new(AccountMailSettings {
+        verify_email_url: configuration.mail.verify_email_url.clone().unwrap_or_else(|| {
+            format!("{}://{}/auth/account/email/verify?token={{token}}", scheme, external_domain)
+        }),
+        password_reset_url: configuration.mail.password_reset_url.clone().unwrap_or_else(|| {
+            format!("{}://{}/reset-password?token={{token}}", scheme, external_domain)
+        }),
+        require_verified_email: configuration.mail.require_verified_email,
+    });
+    let trusted_proxies = Arc::new(
+        TrustedProxies::from_config(&configuration.auth.trusted_proxies)
+            .context("Could not read the trusted proxies!")?,
+    );
+
+    // init application state
+    let mut app_state = ApplicationState::new(
+        Arc::clone(&configuration),
+        db,
+        keys,
+        relying_party,
+        mailer,
+        account_mail,
+        trusted_proxies,
+    );
 
     // TODO: check if `data/credentials.txt` still exists and stop immediately!
     let mut router = Router::new()
@@ -180,9 +260,6 @@ pub async fn start_server() -> Result<()> {
         // Media items
         .nest("/", MediaApi::routes(&app_state).await)
 
-        // OAuth 2.0 Authentication
-        .nest("/", AuthenticationManager::routes())
-
         // OAuth Authorization Server
         .nest("/", AuthorizationServerManager::routes(server))
 
@@ -191,8 +268,18 @@ pub async fn start_server() -> Result<()> {
         .layer(TraceLayer::new_for_http())
         // grant all CORS OPTIONS requests
         .layer(CorsLayer::very_permissive())
+        // configuration, for the admin two-factor policy of the OAuth logins
+        .layer(axum::Extension(Arc::clone(&app_state.config)))
         // make DB available to the User extractor via Extension
         .layer(axum::Extension(Arc::clone(&app_state.database)))
+        // keys that sign and verify tokens, for the User extractor and the OAuth handlers
+        .layer(axum::Extension(Arc::clone(&app_state.keys)))
+        // domain and origins of passkey ceremonies
+        .layer(axum::Extension(Arc::clone(&app_state.relying_party)))
+        // whether the OAuth logins require a verified address
+        .layer(axum::Extension(Arc::clone(&app_state.account_mail)))
+        // proxies whose forwarded client address the OAuth sessions record
+        .layer(axum::Extension(Arc::clone(&app_state.trusted_proxies)))
         // ZIP cache manager shared across media upload/delete and download handlers
         .layer(axum::Extension(Arc::clone(&zip_cache)))
 
@@ -251,6 +338,17 @@ pub async fn start_server() -> Result<()> {
         .unwrap()
         .route("/test", get(|| async { "" }));
 
+    // Execute background jobs, including those interrupted by the last shutdown.
+    JobWorker::new(Arc::clone(&app_state.database)).spawn();
+
+    // Keep refusing access tokens of sessions revoked shortly before a restart.
+    if let Err(e) = AuthManager::new(Arc::clone(&app_state.database), Arc::clone(&app_state.keys))
+        .load_revoked_sessions()
+        .await
+    {
+        tracing::error!("could not load revoked sessions: {}", e);
+    }
+
     // Pre-generate ZIPs for all existing albums in the background so the cache
     // is warm before the first download request arrives.
     {
@@ -258,37 +356,40 @@ pub async fn start_server() -> Result<()> {
         tokio::spawn(async move {
             match db_warmup.list_all_albums().await {
                 Ok(albums) => {
-                    info!("ZIP cache warm-up: {} album(s) to process", albums.len());
+                    let mut queued = 0;
                     for album in albums {
-                        let path = ZipCacheManager::all_zip_path(&album.album_id);
+                        let path = match all_zip_options(&album.album_id, &db_warmup).await {
+                            Ok(options) => ZipCacheManager::variant_path(
+                                ZipCacheManager::all_zip_path(&album.album_id),
+                                &options,
+                            ),
+                            Err(_) => ZipCacheManager::all_zip_path(&album.album_id),
+                        };
                         if path.exists() {
                             continue; // already cached from a previous run
                         }
-                        if let Err(e) = generate_and_write_all_zip(&album.album_id, &db_warmup).await {
-                            warn!("ZIP cache warm-up failed for album {}: {:?}", album.album_id, e);
+                        let key = format!("zip:{}", album.album_id);
+                        let job = Job::BuildAlbumZip { album_id: album.album_id.clone() };
+                        match enqueue_unique(&db_warmup, job, &key).await {
+                            Ok(_) => queued += 1,
+                            Err(e) => warn!("ZIP cache warm-up: could not queue album {}: {:?}", album.album_id, e),
                         }
                     }
-                    info!("ZIP cache warm-up complete");
+                    info!("ZIP cache warm-up: {} album(s) queued", queued);
                 }
                 Err(e) => warn!("ZIP cache warm-up: could not list albums: {:?}", e),
             }
         });
     }
 
-    // task::spawn_blocking(move || {
-    //     tracing::debug!("setup Authentication Manager...");
-    //     let manager = AuthenticationManager::new();
-    //     let nonce = AuthenticationManager::create_authorization_url(
-    //         manager::client,
-    //         manager::pkce_challenge,
-    //     );
-    // }).await?;
+    // Index images uploaded before perceptual hashes and placeholders existed.
+    tokio::spawn(backfill_images(Arc::clone(&app_state.database)));
 
     // start server with all routes
     let addr: SocketAddr = SocketAddr::from(([0, 0, 0, 0], 7777));
     tracing::debug!("listening on {}", addr);
     axum::Server::bind(&addr)
-        .serve(router.into_make_service())
+        .serve(router.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .context("start server")?;
 